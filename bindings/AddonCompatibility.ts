@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AddonKind } from "./AddonKind";
+import type { Compatibility } from "./Compatibility";
+
+export interface AddonCompatibility { file: string, kind: AddonKind, id: string | null, name: string | null, version: string | null, compatibility: Compatibility, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type AlertAction = { type: "Webhook", url: string, } | { type: "Email", to: string, } | { type: "RestartInstance" } | { type: "PauseBackups" };
\ No newline at end of file
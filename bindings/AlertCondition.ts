@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AlertMetric } from "./AlertMetric";
+import type { Comparison } from "./Comparison";
+
+export interface AlertCondition { metric: AlertMetric, comparison: Comparison, threshold: number, for_secs: number, }
\ No newline at end of file
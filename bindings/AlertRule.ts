@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AlertAction } from "./AlertAction";
+import type { AlertCondition } from "./AlertCondition";
+import type { Snowflake } from "./Snowflake";
+
+export interface AlertRule { id: Snowflake, name: string, condition: AlertCondition, actions: Array<AlertAction>, enabled: boolean, }
\ No newline at end of file
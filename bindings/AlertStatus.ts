@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AlertRule } from "./AlertRule";
+
+export interface AlertStatus { rule: AlertRule, firing: boolean, since: bigint | null, last_value: number | null, }
\ No newline at end of file
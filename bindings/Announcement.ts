@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AnnouncementFormat } from "./AnnouncementFormat";
+import type { AnnouncementTiming } from "./AnnouncementTiming";
+import type { Snowflake } from "./Snowflake";
+
+export interface Announcement { id: Snowflake, name: string, message: string, format: AnnouncementFormat, timing: AnnouncementTiming, enabled: boolean, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { GameType } from "./GameType";
+import type { InstanceUuid } from "./InstanceUuid";
+
+export interface ArchivedInstance { uuid: InstanceUuid, name: string, game_type: GameType, dir_name: string, storage_location: string, archive_path: string, size: bigint, archived_at: bigint, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface BedrockStatus { motd: string, version: string, protocol: number, players_online: number, max_players: number, level_name: string | null, game_mode: string | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type Compatibility = { type: "Compatible" } | { type: "Incompatible", requirement: string, } | { type: "Unknown" };
\ No newline at end of file
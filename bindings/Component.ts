@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type Component = "event_loop" | "database" | "migrations" | "instance_creations" | "extensions";
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Component } from "./Component";
+import type { ComponentStatus } from "./ComponentStatus";
+
+export interface ComponentReport { component: Component, status: ComponentStatus, message: string | null, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ServerStatus } from "./ServerStatus";
+import type { SrvCheck } from "./SrvCheck";
+
+export interface ConnectionInfo { address: string | null, public_ip: string | null, lan_ip: string | null, domain: string | null, port: number, srv: SrvCheck | null, status: ServerStatus | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ConsoleColor } from "./ConsoleColor";
+
+export interface ConsoleSpan { text: string, fg: ConsoleColor | null, bg: ConsoleColor | null, bold: boolean, italic: boolean, underline: boolean, }
\ No newline at end of file
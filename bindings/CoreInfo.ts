@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface CoreInfo { version: string, is_setup: boolean, os: string, arch: string, cpu: string, cpu_count: number, total_ram: bigint, total_disk: bigint, host_name: string, uuid: string, core_name: string, up_since: bigint, }
\ No newline at end of file
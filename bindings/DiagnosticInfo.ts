@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DiagnosticKind } from "./DiagnosticKind";
+import type { Snowflake } from "./Snowflake";
+
+export interface DiagnosticInfo { id: Snowflake, kind: DiagnosticKind, method: string, path: string, size: bigint, created_at: bigint, }
\ No newline at end of file
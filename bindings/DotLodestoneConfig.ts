@@ -2,4 +2,4 @@
 import type { GameType } from "./GameType";
 import type { InstanceUuid } from "./InstanceUuid";
 
-export interface DotLodestoneConfig { game_type: GameType, uuid: InstanceUuid, creation_time: bigint, }
\ No newline at end of file
+export interface DotLodestoneConfig { game_type: GameType, uuid: InstanceUuid, creation_time: bigint, storage_location: string | null, }
\ No newline at end of file
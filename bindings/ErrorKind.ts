@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type ErrorKind = "NotFound" | "UnsupportedOperation" | "BadRequest" | "PermissionDenied" | "Unauthorized" | "Internal";
\ No newline at end of file
+export type ErrorKind = "NotFound" | "UnsupportedOperation" | "BadRequest" | "PermissionDenied" | "Unauthorized" | "InsufficientStorage" | "ServiceUnavailable" | "TooManyRequests" | "Internal";
\ No newline at end of file
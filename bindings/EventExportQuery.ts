@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { EventExportFormat } from "./EventExportFormat";
+
+export interface EventExportQuery { filter: string, format: EventExportFormat, }
\ No newline at end of file
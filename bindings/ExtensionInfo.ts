@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ExtensionManifest } from "./ExtensionManifest";
+import type { ExtensionState } from "./ExtensionState";
+
+export interface ExtensionInfo { manifest: ExtensionManifest, state: ExtensionState, }
\ No newline at end of file
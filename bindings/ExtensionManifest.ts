@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ExtensionInstanceType } from "./ExtensionInstanceType";
+
+export interface ExtensionManifest { name: string, version: string, description: string, api_version: number, main: string, instance_types: Array<ExtensionInstanceType>, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ExtensionUser } from "./ExtensionUser";
+
+export interface ExtensionRequest { id: number, method: string, path: string, query: Record<string, string>, body: unknown, user: ExtensionUser, }
\ No newline at end of file
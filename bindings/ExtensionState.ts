@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ExtensionState = { type: "Running" } | { type: "Stopped" } | { type: "Error", message: string, } | { type: "Incompatible", api_version: number, };
\ No newline at end of file
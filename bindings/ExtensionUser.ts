@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UserId } from "./UserId";
+
+export interface ExtensionUser { uid: UserId, username: string, is_owner: boolean, is_admin: boolean, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LineDiff } from "./LineDiff";
+
+export interface FileDiff { path: string, diff: LineDiff, }
\ No newline at end of file
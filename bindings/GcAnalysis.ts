@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PauseDistribution } from "./PauseDistribution";
+
+export interface GcAnalysis { pauses: number, full_pauses: number, pause_time: PauseDistribution, pause_percent: number, allocation_rate_mb_per_sec: number | null, heap_after_gc_mb: number | null, heap_total_mb: number | null, covered_secs: number, }
\ No newline at end of file
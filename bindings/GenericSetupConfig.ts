@@ -1,4 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
-import type { InstanceUuid } from "./InstanceUuid";
+import type { SetupValue } from "./SetupValue";
 
-export interface GenericSetupConfig { game_type: string, uuid: InstanceUuid, name: string, description: string, port: number, auto_start: boolean, restart_on_crash: boolean, started_count: number, }
\ No newline at end of file
+export interface GenericSetupConfig { url: string, setup_value: SetupValue, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+
+export interface GhostInstance { instance_uuid: InstanceUuid, loaded: boolean, }
\ No newline at end of file
@@ -1,3 +1,18 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CommandRole } from "./CommandRole";
+import type { DiskSpaceConfig } from "./DiskSpaceConfig";
+import type { InstanceAccess } from "./InstanceAccess";
+import type { InstanceSettings } from "./InstanceSettings";
+import type { InstanceUuid } from "./InstanceUuid";
+import type { LogShippingSettings } from "./LogShippingSettings";
+import type { MaintenanceWindow } from "./MaintenanceWindow";
+import type { PasswordHashing } from "./PasswordHashing";
+import type { QuotaSettings } from "./QuotaSettings";
+import type { RateLimitConfig } from "./RateLimitConfig";
+import type { RemoteBackupSettings } from "./RemoteBackupSettings";
+import type { SmtpConfig } from "./SmtpConfig";
+import type { StorageLocation } from "./StorageLocation";
+import type { TelemetrySettings } from "./TelemetrySettings";
+import type { WhitelistGroup } from "./WhitelistGroup";
 
-export interface GlobalSettingsData { core_name: string, safe_mode: boolean, domain: string | null, }
\ No newline at end of file
+export interface GlobalSettingsData { core_name: string, safe_mode: boolean, domain: string | null, rate_limit: RateLimitConfig, instance_settings: Record<InstanceUuid, InstanceSettings>, remote_backup: RemoteBackupSettings | null, disk_space: DiskSpaceConfig, quotas: QuotaSettings, instance_access: Record<InstanceUuid, InstanceAccess>, smtp: SmtpConfig | null, password_hashing: PasswordHashing, maintenance: MaintenanceWindow | null, telemetry: TelemetrySettings, storage_locations: Array<StorageLocation>, whitelist_groups: Array<WhitelistGroup>, command_roles: Array<CommandRole>, instance_isolation: boolean, log_shipping: LogShippingSettings | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { HexRow } from "./HexRow";
+
+export interface HexView { offset: bigint, length: bigint, file_size: bigint, rows: Array<HexRow>, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface HostUsage { cpu_percent: number, memory_total: bigint, memory_used: bigint, instances_memory: bigint, }
\ No newline at end of file
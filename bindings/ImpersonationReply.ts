@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ImpersonationSession } from "./ImpersonationSession";
+import type { JwtToken } from "./JwtToken";
+
+export interface ImpersonationReply { token: JwtToken, session: ImpersonationSession, }
\ No newline at end of file
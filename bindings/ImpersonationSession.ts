@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UserId } from "./UserId";
+
+export interface ImpersonationSession { session_id: string, impersonator: UserId, impersonator_name: string, target: UserId, target_name: string, created_at: bigint, expires_at: bigint, }
\ No newline at end of file
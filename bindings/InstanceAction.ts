@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ActionPermission } from "./ActionPermission";
+import type { SettingManifest } from "./SettingManifest";
+
+export interface InstanceAction { name: string, description: string, parameters: Array<SettingManifest>, permission: ActionPermission, }
\ No newline at end of file
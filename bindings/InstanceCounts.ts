@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface InstanceCounts { total: number, starting: number, running: number, stopping: number, stopped: number, error: number, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Drift } from "./Drift";
+import type { InstanceUuid } from "./InstanceUuid";
+
+export interface InstanceDrift { instance_uuid: InstanceUuid, name: string, drift: Array<Drift>, }
\ No newline at end of file
@@ -1,5 +1,11 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ConsoleSpan } from "./ConsoleSpan";
+import type { HealthStatus } from "./HealthStatus";
 import type { InstanceState } from "./InstanceState";
+import type { LogLevel } from "./LogLevel";
 import type { Player } from "./Player";
+import type { PlayerDataEdit } from "./PlayerDataEdit";
+import type { SettingRevert } from "./SettingRevert";
+import type { Snowflake } from "./Snowflake";
 
-export type InstanceEventInner = { type: "StateTransition", to: InstanceState, } | { type: "InstanceWarning", message: string, } | { type: "InstanceError", message: string, } | { type: "InstanceInput", message: string, } | { type: "InstanceOutput", message: string, } | { type: "SystemMessage", message: string, } | { type: "PlayerChange", player_list: Array<Player>, players_joined: Array<Player>, players_left: Array<Player>, } | { type: "PlayerMessage", player: string, player_message: string, };
\ No newline at end of file
+export type InstanceEventInner = { type: "StateTransition", to: InstanceState, } | { type: "InstanceWarning", message: string, } | { type: "InstanceError", message: string, } | { type: "InstanceInput", message: string, } | { type: "InstanceOutput", message: string, spans: Array<ConsoleSpan>, level: LogLevel | null, source: string | null, } | { type: "SystemMessage", message: string, } | { type: "PlayerChange", player_list: Array<Player>, players_joined: Array<Player>, players_left: Array<Player>, } | { type: "PlayerMessage", player: string, player_message: string, } | { type: "PlayerDataChanged", player_uuid: string, edit: PlayerDataEdit, } | { type: "AlertTriggered", rule_id: Snowflake, rule_name: string, value: number, } | { type: "AlertResolved", rule_id: Snowflake, rule_name: string, } | { type: "HealthChanged", health: HealthStatus, reasons: Array<string>, } | { type: "SettingsRolledBack", reverted: Array<SettingRevert>, } | { type: "CommandBlocked", command: string, roles: Array<string>, } | { type: "ChatModerated", rule_id: Snowflake, rule_name: string, player: string, } | { type: "WatchedPlayerJoined", player_id: string, player_name: string, flags: Array<string>, note_count: number, };
\ No newline at end of file
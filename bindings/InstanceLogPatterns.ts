@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LogPattern } from "./LogPattern";
+
+export interface InstanceLogPatterns { builtin: Array<LogPattern>, custom: Array<LogPattern>, }
\ No newline at end of file
@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AlertRule } from "./AlertRule";
+import type { Announcement } from "./Announcement";
+import type { LogShippingSettings } from "./LogShippingSettings";
+import type { ModerationRule } from "./ModerationRule";
+import type { NetworkFilterSettings } from "./NetworkFilterSettings";
+import type { PowerSchedule } from "./PowerSchedule";
+import type { RemoteBackupSettings } from "./RemoteBackupSettings";
+import type { ResourcePack } from "./ResourcePack";
+import type { RestartSchedule } from "./RestartSchedule";
+import type { ShutdownPolicy } from "./ShutdownPolicy";
+import type { WebProxySettings } from "./WebProxySettings";
+
+export interface InstanceSettings { shutdown_policy: ShutdownPolicy | null, remote_backup: RemoteBackupSettings | null, alert_rules: Array<AlertRule>, power_schedule: PowerSchedule | null, network_filter: NetworkFilterSettings | null, web_proxy: WebProxySettings | null, restart_schedule: RestartSchedule | null, announcements: Array<Announcement>, resource_pack: ResourcePack | null, moderation_rules: Array<ModerationRule>, log_shipping: LogShippingSettings | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { NbtTag } from "./NbtTag";
+
+export interface InventoryItem { slot: number, id: string, count: number, extra: NbtTag | null, }
\ No newline at end of file
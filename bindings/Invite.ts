@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UserId } from "./UserId";
+import type { UserPermission } from "./UserPermission";
+
+export interface Invite { code: string, is_admin: boolean, permissions: UserPermission, created_by: UserId, creation_time: bigint, expiry: bigint | null, max_uses: number | null, redeemed_by: Array<UserId>, }
\ No newline at end of file
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CausedBy } from "./CausedBy";
+import type { InstanceUuid } from "./InstanceUuid";
+import type { JobStatus } from "./JobStatus";
+import type { LocalizedMessage } from "./LocalizedMessage";
+import type { Snowflake } from "./Snowflake";
+
+export interface Job { id: Snowflake, name: string, localized_name: LocalizedMessage | null, caused_by: CausedBy, instance_uuid: InstanceUuid | null, status: JobStatus, progress: number, total: number | null, progress_message: string | null, message: string | null, localized_message: LocalizedMessage | null, started_at: bigint, finished_at: bigint | null, }
\ No newline at end of file
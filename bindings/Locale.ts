@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type Locale = "en" | "fr" | "de" | "es" | "zh-CN";
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SyslogTransport } from "./SyslogTransport";
+
+export type LogDestination = { type: "Syslog", address: string, transport: SyslogTransport, } | { type: "Loki", url: string, username: string | null, password: string | null, } | { type: "Elasticsearch", url: string, index: string, api_key: string | null, };
\ No newline at end of file
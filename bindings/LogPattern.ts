@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LogPatternKind } from "./LogPatternKind";
+
+export interface LogPattern { kind: LogPatternKind, regex: string, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LogDestination } from "./LogDestination";
+
+export interface LogShippingSettings { destination: LogDestination, console: boolean, events: boolean, buffer_lines: number, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type LuckPermsChange = { type: "CreateGroup", group: string, } | { type: "DeleteGroup", group: string, } | { type: "SetGroupWeight", group: string, weight: number, } | { type: "SetGroupPermission", group: string, permission: string, value: boolean, } | { type: "UnsetGroupPermission", group: string, permission: string, } | { type: "AddUserGroup", user: string, group: string, } | { type: "RemoveUserGroup", user: string, group: string, } | { type: "SetUserPermission", user: string, permission: string, value: boolean, } | { type: "UnsetUserPermission", user: string, permission: string, };
\ No newline at end of file
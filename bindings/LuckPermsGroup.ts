@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LuckPermsNode } from "./LuckPermsNode";
+
+export interface LuckPermsGroup { name: string, weight: number | null, display_name: string | null, parents: Array<string>, permissions: Array<LuckPermsNode>, }
\ No newline at end of file
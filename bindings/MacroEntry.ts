@@ -1,3 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SettingManifest } from "./SettingManifest";
 
-export interface MacroEntry { name: string, last_run: bigint | null, path: string, }
\ No newline at end of file
+export interface MacroEntry { name: string, last_run: bigint | null, path: string, parameters: Array<SettingManifest>, global_enabled: boolean | null, }
\ No newline at end of file
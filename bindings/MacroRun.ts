@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CausedBy } from "./CausedBy";
+import type { ExitStatus } from "./ExitStatus";
+import type { InstanceUuid } from "./InstanceUuid";
+import type { MacroPID } from "./MacroPID";
+
+export interface MacroRun { id: bigint, instance_uuid: InstanceUuid, macro_pid: MacroPID, macro_name: string, args: Array<string>, caused_by: CausedBy, started_at: bigint, ended_at: bigint | null, exit_status: ExitStatus | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { MacroRun } from "./MacroRun";
+
+export interface MacroRunOutput { run: MacroRun, output: Array<string>, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type MessageId = "Unauthorized" | "CredentialMismatch" | "PermissionDenied" | "TooManyRequests" | "InstanceNotFound" | "InstanceMustBeStopped" | "UserNotFound" | "InvalidSetupKey" | "AlreadySetup" | "InstanceCreationStarted" | "InstanceCreationSucceeded" | "InstanceCreationFailed" | "InstanceDeletionStarted" | "InstanceDeletionSucceeded" | "InstanceDeletionFailed" | "InsufficientDiskSpace" | "InstanceQuotaExceeded" | "DiskQuotaExceeded" | "RamQuotaExceeded" | "InvalidInvite" | "MaintenanceMode";
\ No newline at end of file
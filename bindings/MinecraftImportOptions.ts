@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface MinecraftImportOptions { name: string | null, description: string | null, port: number | null, min_ram: number | null, max_ram: number | null, auto_start: boolean | null, restart_on_crash: boolean | null, }
\ No newline at end of file
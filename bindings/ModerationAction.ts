@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ModerationAction = { type: "Warn", message: string, } | { type: "Mute", command: string, } | { type: "Kick", reason: string, } | { type: "Webhook", url: string, };
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Snowflake } from "./Snowflake";
+import type { TakenAction } from "./TakenAction";
+
+export interface ModerationLogEntry { at: bigint, rule_id: Snowflake, rule_name: string, player: string, message: string, actions: Array<TakenAction>, }
\ No newline at end of file
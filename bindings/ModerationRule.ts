@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ModerationAction } from "./ModerationAction";
+import type { ModerationMatch } from "./ModerationMatch";
+import type { Snowflake } from "./Snowflake";
+
+export interface ModerationRule { id: Snowflake, name: string, matcher: ModerationMatch, actions: Array<ModerationAction>, enabled: boolean, }
\ No newline at end of file
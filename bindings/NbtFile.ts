@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { NbtCompression } from "./NbtCompression";
+import type { NbtTag } from "./NbtTag";
+
+export interface NbtFile { compression: NbtCompression, name: string, root: NbtTag, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type NbtTag = { "type": "Byte", "value": number } | { "type": "Short", "value": number } | { "type": "Int", "value": number } | { "type": "Long", "value": bigint } | { "type": "Float", "value": number } | { "type": "Double", "value": number } | { "type": "ByteArray", "value": Array<number> } | { "type": "String", "value": string } | { "type": "List", "value": Array<NbtTag> } | { "type": "Compound", "value": Record<string, NbtTag> } | { "type": "IntArray", "value": Array<number> } | { "type": "LongArray", "value": Array<bigint> };
\ No newline at end of file
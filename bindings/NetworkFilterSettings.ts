@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface NetworkFilterSettings { enabled: boolean, listen_port: number, listen_address: string | null, allow: Array<string>, deny: Array<string>, udp: boolean, wake_on_connect: boolean, }
\ No newline at end of file
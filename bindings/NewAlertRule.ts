@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AlertAction } from "./AlertAction";
+import type { AlertCondition } from "./AlertCondition";
+
+export interface NewAlertRule { name: string, condition: AlertCondition, actions: Array<AlertAction>, enabled: boolean, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AnnouncementFormat } from "./AnnouncementFormat";
+import type { AnnouncementTiming } from "./AnnouncementTiming";
+
+export interface NewAnnouncement { name: string, message: string, format: AnnouncementFormat, timing: AnnouncementTiming, enabled: boolean, }
\ No newline at end of file
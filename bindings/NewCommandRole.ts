@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { UserId } from "./UserId";
+
+export interface NewCommandRole { name: string, members: Array<UserId>, allowed: Array<string>, blocked: Array<string>, instances: Array<InstanceUuid>, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DiagnosticKind } from "./DiagnosticKind";
+
+export interface NewDiagnostic { kind: DiagnosticKind, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UserPermission } from "./UserPermission";
+
+export interface NewInvite { is_admin: boolean, permissions: UserPermission, expires_in: bigint | null, max_uses: number | null, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ModerationAction } from "./ModerationAction";
+import type { ModerationMatch } from "./ModerationMatch";
+
+export interface NewModerationRule { name: string, matcher: ModerationMatch, actions: Array<ModerationAction>, enabled: boolean, }
\ No newline at end of file
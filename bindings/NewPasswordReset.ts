@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface NewPasswordReset { send_email: boolean, expires_in: bigint | null, }
\ No newline at end of file
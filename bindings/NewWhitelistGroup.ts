@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+
+export interface NewWhitelistGroup { name: string, players: Array<string>, instances: Array<InstanceUuid>, }
\ No newline at end of file
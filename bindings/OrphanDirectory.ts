@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { OrphanReason } from "./OrphanReason";
+
+export interface OrphanDirectory { path: string, storage_location: string, reason: OrphanReason, instance_uuid: InstanceUuid | null, adoptable: boolean, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FileHash } from "./FileHash";
+
+export type PathHash = { type: "File" } & FileHash | { type: "Directory", files: Record<string, FileHash>, };
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface PauseDistribution { p50_ms: number, p90_ms: number, p99_ms: number, max_ms: number, mean_ms: number, }
\ No newline at end of file
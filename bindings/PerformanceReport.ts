@@ -1,4 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { DiskUsage } from "./DiskUsage";
 
-export interface PerformanceReport { memory_usage: bigint | null, disk_usage: DiskUsage | null, cpu_usage: number | null, start_time: bigint | null, }
\ No newline at end of file
+export interface PerformanceReport { memory_usage: bigint | null, disk_usage: DiskUsage | null, cpu_usage: number | null, start_time: bigint | null, disk_read_rate: number | null, disk_write_rate: number | null, open_files: bigint | null, }
\ No newline at end of file
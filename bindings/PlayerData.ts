@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InventoryItem } from "./InventoryItem";
+
+export interface PlayerData { x: number, y: number, z: number, dimension: string | null, health: number, food_level: number, xp_level: number, xp_total: number, xp_progress: number, game_mode: number, inventory: Array<InventoryItem>, ender_items: Array<InventoryItem>, }
\ No newline at end of file
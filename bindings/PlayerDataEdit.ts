@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type PlayerDataEdit = { type: "Teleport", x: number, y: number, z: number, dimension: string | null, } | { type: "ClearItem", slot: number, };
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { UserId } from "./UserId";
+
+export interface PlayerNote { id: bigint, player_id: string, body: string, author_id: UserId, author_name: string, instance_uuid: InstanceUuid | null, created_at: bigint, updated_at: bigint, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface PlayerReputation { player_id: string, player_name: string, watched: boolean, flags: Array<string>, note_count: number, updated_at: bigint, }
\ No newline at end of file
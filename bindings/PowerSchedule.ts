@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PowerWindow } from "./PowerWindow";
+
+export interface PowerSchedule { enabled: boolean, windows: Array<PowerWindow>, warning_minutes: number, }
\ No newline at end of file
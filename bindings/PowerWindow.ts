@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Day } from "./Day";
+
+export interface PowerWindow { days: Array<Day>, start: string, end: string, }
\ No newline at end of file
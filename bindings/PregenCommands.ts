@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface PregenCommands { start: Array<string>, pause: Array<string>, resume: Array<string>, cancel: Array<string>, progress_pattern: string, done_pattern: string, }
\ No newline at end of file
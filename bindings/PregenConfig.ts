@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PregenCommands } from "./PregenCommands";
+
+export interface PregenConfig { radius: number, center_x: number, center_z: number, world: string | null, keep_running_with_players: boolean, commands: PregenCommands | null, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PregenStatus } from "./PregenStatus";
+import type { Snowflake } from "./Snowflake";
+
+export interface PregenInfo { job_id: Snowflake, radius: number, center_x: number, center_z: number, world: string | null, keep_running_with_players: boolean, status: PregenStatus, percent: number, }
\ No newline at end of file
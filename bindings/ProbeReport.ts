@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ComponentReport } from "./ComponentReport";
+
+export interface ProbeReport { ok: boolean, components: Array<ComponentReport>, }
\ No newline at end of file
@@ -1,5 +1,7 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { InstanceInfo } from "./InstanceInfo";
 import type { InstanceUuid } from "./InstanceUuid";
+import type { SnapshotInfo } from "./SnapshotInfo";
+import type { Snowflake } from "./Snowflake";
 
-export type ProgressionEndValue = { type: "InstanceCreation" } & InstanceInfo | { type: "InstanceDelete", instance_uuid: InstanceUuid, } | { type: "FSOperationCompleted", instance_uuid: InstanceUuid, success: boolean, message: string, };
\ No newline at end of file
+export type ProgressionEndValue = { type: "InstanceCreation" } & InstanceInfo | { type: "InstanceDelete", instance_uuid: InstanceUuid, } | { type: "FSOperationCompleted", instance_uuid: InstanceUuid, success: boolean, message: string, } | { type: "SnapshotCreated" } & SnapshotInfo | { type: "SnapshotRestored", instance_uuid: InstanceUuid, snapshot_id: Snowflake, } | { type: "SnapshotVerified" } & SnapshotInfo;
\ No newline at end of file
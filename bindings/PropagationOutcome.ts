@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { SettingsPreview } from "./SettingsPreview";
+
+export interface PropagationOutcome { instance_uuid: InstanceUuid, preview: SettingsPreview | null, error: string | null, }
\ No newline at end of file
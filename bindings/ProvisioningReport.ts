@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceDrift } from "./InstanceDrift";
+
+export interface ProvisioningReport { missing: Array<string>, pending: Array<string>, ambiguous: Array<string>, drifted: Array<InstanceDrift>, }
\ No newline at end of file
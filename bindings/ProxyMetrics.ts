@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ProxyMetrics { active_connections: number, total_connections: bigint, blocked_connections: bigint, udp_sessions: number, bytes_in: bigint, bytes_out: bigint, last_connection_at: bigint | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ProxyRebind { listen_port: number, listen_address: string | null, drain_secs: number | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface QueryStatus { motd: string, version: string, software: string | null, plugins: Array<string>, map: string, players_online: number, max_players: number, players: Array<string>, }
\ No newline at end of file
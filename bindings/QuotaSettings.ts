@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UserId } from "./UserId";
+import type { UserQuota } from "./UserQuota";
+
+export interface QuotaSettings { admin: UserQuota, user: UserQuota, users: Record<UserId, UserQuota>, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RecommendationKind } from "./RecommendationKind";
+
+export interface Recommendation { kind: RecommendationKind, setting: string | null, current: string | null, suggested: string | null, reason: string, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Recommendation } from "./Recommendation";
+
+export interface Recommendations { samples: number, since: bigint | null, recommendations: Array<Recommendation>, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { GhostInstance } from "./GhostInstance";
+import type { OrphanDirectory } from "./OrphanDirectory";
+
+export interface ReconcileReport { orphans: Array<OrphanDirectory>, ghosts: Array<GhostInstance>, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SnapshotInfo } from "./SnapshotInfo";
+import type { VerificationStatus } from "./VerificationStatus";
+
+export interface RemoteBackupInfo { snapshot: SnapshotInfo, archive_size: bigint, upload_time: bigint, verification: VerificationStatus, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RemoteStorage } from "./RemoteStorage";
+import type { RetentionPolicy } from "./RetentionPolicy";
+
+export interface RemoteBackupSettings { storage: RemoteStorage, path_prefix: string, retention: RetentionPolicy, bandwidth_limit: number | null, upload_snapshots: boolean, verify_archives: boolean, }
\ No newline at end of file
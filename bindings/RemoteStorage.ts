@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type RemoteStorage = { type: "S3", endpoint: string, region: string, bucket: string, access_key: string, secret_key: string, path_style: boolean, } | { type: "WebDav", url: string, username: string | null, password: string | null, };
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { MessageId } from "./MessageId";
+
+export interface RenderedMessage { id: MessageId, params: Record<string, string>, text: string, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ResourcePack { id: string, file_name: string, size: bigint, sha1: string, url: string, uploaded_at: bigint, }
\ No newline at end of file
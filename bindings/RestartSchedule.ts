@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { MemoryRestart } from "./MemoryRestart";
+
+export interface RestartSchedule { enabled: boolean, daily_at: Array<string>, memory: MemoryRestart | null, warning_minutes: number, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface RetentionPolicy { keep_last: number | null, max_age_days: number | null, }
\ No newline at end of file
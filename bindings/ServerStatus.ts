@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BedrockStatus } from "./BedrockStatus";
+import type { QueryStatus } from "./QueryStatus";
+
+export interface ServerStatus { checked_at: bigint, query: QueryStatus | null, bedrock: BedrockStatus | null, }
\ No newline at end of file
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CausedBy } from "./CausedBy";
+import type { ConfigurableValue } from "./ConfigurableValue";
+import type { Snowflake } from "./Snowflake";
+
+export interface SettingChangeRecord { id: Snowflake, at: bigint, caused_by: CausedBy, section_id: string, setting_id: string, name: string, old_value: ConfigurableValue | null, new_value: ConfigurableValue | null, is_secret: boolean, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ConfigurableValue } from "./ConfigurableValue";
+
+export interface SettingDiff { section_id: string, setting_id: string, name: string, old_value: ConfigurableValue | null, new_value: ConfigurableValue | null, requires_restart: boolean, }
\ No newline at end of file
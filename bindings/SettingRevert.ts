@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface SettingRevert { file: string, key: string, failed_value: string | null, restored_value: string | null, }
\ No newline at end of file
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FileDiff } from "./FileDiff";
+import type { LineDiff } from "./LineDiff";
+import type { SettingDiff } from "./SettingDiff";
+
+export interface SettingsPreview { settings: Array<SettingDiff>, files: Array<FileDiff>, launch_args: LineDiff | null, requires_restart: boolean, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { SettingKey } from "./SettingKey";
+
+export interface SettingsPropagation { settings: Array<SettingKey>, targets: Array<InstanceUuid>, dry_run: boolean, }
\ No newline at end of file
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { Snowflake } from "./Snowflake";
+import type { UserId } from "./UserId";
+
+export interface ShareLink { id: string, instance_uuid: InstanceUuid, path: string, snapshot: Snowflake | null, created_by: UserId, created_by_name: string, created_at: bigint, expires_at: bigint, downloads: number, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ShareLink } from "./ShareLink";
+
+export interface SharedFile { link: ShareLink, token: string, }
\ No newline at end of file
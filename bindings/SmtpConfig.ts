@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SmtpSecurity } from "./SmtpSecurity";
+
+export interface SmtpConfig { host: string, port: number, security: SmtpSecurity, username: string | null, password: string | null, from: string, }
\ No newline at end of file
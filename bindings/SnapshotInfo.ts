@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { Snowflake } from "./Snowflake";
+import type { VerificationStatus } from "./VerificationStatus";
+
+export interface SnapshotInfo { id: Snowflake, instance_uuid: InstanceUuid, name: string, description: string | null, creation_time: bigint, size: bigint, bytes_copied: bigint, verification: VerificationStatus, }
\ No newline at end of file
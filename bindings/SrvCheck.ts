@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SrvStatus } from "./SrvStatus";
+
+export interface SrvCheck { record: string, status: SrvStatus, target: string | null, port: number | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { StorageLocation } from "./StorageLocation";
+
+export interface StorageLocationInfo { location: StorageLocation, available_space: bigint | null, instance_count: number, }
\ No newline at end of file
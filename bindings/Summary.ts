@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { HostUsage } from "./HostUsage";
+import type { InstanceCounts } from "./InstanceCounts";
+
+export interface Summary { instances: InstanceCounts, players_online: number, host: HostUsage, active_jobs: number, firing_alerts: number, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface TelemetryReport { installation_id: string, core_version: string, os: string, arch: string, instance_count: number, game_types: Record<string, number>, }
\ No newline at end of file
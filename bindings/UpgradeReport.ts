@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AddonCompatibility } from "./AddonCompatibility";
+
+export interface UpgradeReport { current_version: string, target_version: string, addons: Array<AddonCompatibility>, blockers: number, report_id: string, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface UpgradeRequest { version: string, report_id: string, allow_blockers: boolean, skip_snapshot: boolean, }
\ No newline at end of file
@@ -1,4 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UserId } from "./UserId";
 import type { UserPermission } from "./UserPermission";
 
-export type UserEventInner = { type: "UserCreated" } | { type: "UserDeleted" } | { type: "UserLoggedIn" } | { type: "UserLoggedOut" } | { type: "UsernameChanged", new_username: string, } | { type: "PermissionChanged", new_permissions: UserPermission, };
\ No newline at end of file
+export type UserEventInner = { type: "UserCreated" } | { type: "UserDeleted" } | { type: "UserLoggedIn" } | { type: "UserLoggedOut" } | { type: "UsernameChanged", new_username: string, } | { type: "PermissionChanged", new_permissions: UserPermission, } | { type: "PasswordReset" } | { type: "AdminChanged", is_admin: boolean, } | { type: "OwnershipTransferred", previous_owner: UserId, } | { type: "ImpersonationStarted", impersonator: UserId, session_id: string, } | { type: "ImpersonationEnded", session_id: string, };
\ No newline at end of file
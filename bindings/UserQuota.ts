@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface UserQuota { max_instances: number | null, max_disk_usage: bigint | null, max_ram: number | null, }
\ No newline at end of file
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { QuotaUsage } from "./QuotaUsage";
+import type { UserId } from "./UserId";
+import type { UserQuota } from "./UserQuota";
+
+export interface UserQuotaReport { uid: UserId, username: string, quota: UserQuota | null, usage: QuotaUsage, }
\ No newline at end of file
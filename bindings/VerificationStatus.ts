@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type VerificationStatus = { status: "Unverified" } | { status: "Verified", time: bigint, } | { status: "Corrupt", time: bigint, problems: Array<string>, };
\ No newline at end of file
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { Snowflake } from "./Snowflake";
+import type { WhitelistPlayer } from "./WhitelistPlayer";
+
+export interface WhitelistGroup { id: Snowflake, name: string, players: Array<WhitelistPlayer>, instances: Array<InstanceUuid>, }
\ No newline at end of file
@@ -0,0 +1,260 @@
+#![forbid(unsafe_code)]
+
+//! A typed client for the HTTP and WebSocket APIs of Lodestone Core.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), lodestone_client::Error> {
+//! let mut client = lodestone_client::Client::new("http://localhost:16662/api/v1");
+//! client.login("owner", "password").await?;
+//! for instance in client.list_instances().await? {
+//!     println!("{} is {:?}", instance.name, instance.state);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Endpoints without a method here can still be called through [`Client::get`],
+//! [`Client::put`] and [`Client::post`].
+
+use futures_util::{Stream, StreamExt};
+use reqwest::{Method, RequestBuilder};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::Message;
+
+pub mod types;
+
+use types::{ClientEvent, CoreInfo, EventQuery, InstanceInfo, LoginReply, PublicUser, State};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to reach Lodestone Core: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The core answered with an error
+    #[error("Request failed with status {status}: {message}")]
+    Api { status: u16, message: String },
+    #[error("WebSocket error: {0}")]
+    WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error("Failed to parse message from Lodestone Core: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid url: {0}")]
+    Url(#[from] url::ParseError),
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl Client {
+    /// `url` is the base of the API, like `http://localhost:16662/api/v1`
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.into().trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// A request to `path` under the API's base url, authenticated if the client has a token
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let builder = self.http.request(method, format!("{}{}", self.url, path));
+        match &self.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        }
+    }
+
+    pub async fn send<T: DeserializeOwned>(&self, builder: RequestBuilder) -> Result<T, Error> {
+        Ok(Self::check(builder.send().await?).await?.json().await?)
+    }
+
+    /// Turns an error answer into [`Error::Api`]
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response, Error> {
+        let status = response.status();
+        if !status.is_success() {
+            let body: Value = response.json().await.unwrap_or(Value::Null);
+            let message = body
+                .get("causes")
+                .and_then(|causes| causes.as_array())
+                .and_then(|causes| causes.first())
+                .and_then(|cause| cause.as_str())
+                .unwrap_or("Unknown error")
+                .to_string();
+            return Err(Error::Api {
+                status: status.as_u16(),
+                message,
+            });
+        }
+        Ok(response)
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        self.send(self.request(Method::GET, path)).await
+    }
+
+    pub async fn put<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+    ) -> Result<T, Error> {
+        self.send(self.request(Method::PUT, path).json(body)).await
+    }
+
+    pub async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+    ) -> Result<T, Error> {
+        self.send(self.request(Method::POST, path).json(body)).await
+    }
+
+    /// Logs in and keeps the token for the following requests
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<LoginReply, Error> {
+        let reply: LoginReply = self
+            .send(
+                self.http
+                    .post(format!("{}/user/login", self.url))
+                    .basic_auth(username, Some(password)),
+            )
+            .await?;
+        self.token = Some(reply.token.to_string());
+        Ok(reply)
+    }
+
+    pub async fn core_info(&self) -> Result<CoreInfo, Error> {
+        self.get("/info").await
+    }
+
+    pub async fn self_info(&self) -> Result<PublicUser, Error> {
+        self.get("/user/info").await
+    }
+
+    pub async fn create_user(&self, username: &str, password: &str) -> Result<LoginReply, Error> {
+        self.post(
+            "/user",
+            &serde_json::json!({ "username": username, "password": password }),
+        )
+        .await
+    }
+
+    pub async fn list_instances(&self) -> Result<Vec<InstanceInfo>, Error> {
+        self.get("/instance/list").await
+    }
+
+    pub async fn instance_info(&self, uuid: &str) -> Result<InstanceInfo, Error> {
+        self.get(&format!("/instance/{uuid}/info")).await
+    }
+
+    pub async fn instance_state(&self, uuid: &str) -> Result<State, Error> {
+        self.get(&format!("/instance/{uuid}/state")).await
+    }
+
+    pub async fn start_instance(&self, uuid: &str) -> Result<(), Error> {
+        self.send(self.request(Method::PUT, &format!("/instance/{uuid}/start")))
+            .await
+    }
+
+    pub async fn stop_instance(&self, uuid: &str) -> Result<(), Error> {
+        self.send(self.request(Method::PUT, &format!("/instance/{uuid}/stop")))
+            .await
+    }
+
+    pub async fn restart_instance(&self, uuid: &str) -> Result<(), Error> {
+        self.send(self.request(Method::PUT, &format!("/instance/{uuid}/restart")))
+            .await
+    }
+
+    pub async fn kill_instance(&self, uuid: &str) -> Result<(), Error> {
+        self.send(self.request(Method::PUT, &format!("/instance/{uuid}/kill")))
+            .await
+    }
+
+    pub async fn send_command(&self, uuid: &str, command: &str) -> Result<(), Error> {
+        self.post(&format!("/instance/{uuid}/console"), &command)
+            .await
+    }
+
+    /// Snapshots the instance in the background, the outcome is sent as a progression event
+    pub async fn create_snapshot(
+        &self,
+        uuid: &str,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<(), Error> {
+        self.post(
+            &format!("/instance/{uuid}/snapshots"),
+            &serde_json::json!({ "name": name, "description": description }),
+        )
+        .await
+    }
+
+    /// A gzipped tarball of the core's users, settings and instance registry, owner only
+    pub async fn core_backup(&self) -> Result<Vec<u8>, Error> {
+        let response = Self::check(self.request(Method::GET, "/core/backup").send().await?).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// The console lines the core keeps in memory, oldest first
+    pub async fn console_buffer(&self, uuid: &str) -> Result<Vec<ClientEvent>, Error> {
+        self.get(&format!("/instance/{uuid}/console/buffer")).await
+    }
+
+    /// Console events of the instance as they happen, `"all"` for every instance
+    pub async fn console_stream(
+        &self,
+        uuid: &str,
+    ) -> Result<impl Stream<Item = Result<ClientEvent, Error>>, Error> {
+        let mut url = self.websocket_url(&format!("/instance/{uuid}/console/stream"))?;
+        url.query_pairs_mut().append_pair(
+            "token",
+            &format!("Bearer {}", self.token.as_deref().unwrap_or_default()),
+        );
+        self.stream(url).await
+    }
+
+    /// Events matching `query` as they happen, console output excluded
+    pub async fn event_stream(
+        &self,
+        mut query: EventQuery,
+    ) -> Result<impl Stream<Item = Result<ClientEvent, Error>>, Error> {
+        query.bearer_token = self.token.clone();
+        let mut url = self.websocket_url("/events/all/stream")?;
+        url.query_pairs_mut()
+            .append_pair("filter", &serde_json::to_string(&query)?);
+        self.stream(url).await
+    }
+
+    fn websocket_url(&self, path: &str) -> Result<url::Url, Error> {
+        let mut url = url::Url::parse(&format!("{}{}", self.url, path))?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        // only fails for urls that can't have a host, which wouldn't have parsed as http
+        let _ = url.set_scheme(scheme);
+        Ok(url)
+    }
+
+    async fn stream(
+        &self,
+        url: url::Url,
+    ) -> Result<impl Stream<Item = Result<ClientEvent, Error>>, Error> {
+        let (socket, _) = tokio_tungstenite::connect_async(url).await?;
+        Ok(socket.filter_map(|message| async move {
+            match message {
+                Ok(Message::Text(text)) => Some(serde_json::from_str(&text).map_err(Error::from)),
+                Ok(_) => None,
+                Err(e) => Some(Err(e.into())),
+            }
+        }))
+    }
+}
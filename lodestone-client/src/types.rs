@@ -0,0 +1,75 @@
+//! The types of the API, shared with the core through `lodestone-types`.
+//!
+//! Events are the exception, the core's event model isn't part of the shared types, so
+//! [`ClientEvent`] keeps the details of an event as JSON and [`EventQuery`] names event types as
+//! strings.
+
+use serde::{Deserialize, Serialize};
+
+pub use lodestone_types::*;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClientEvent {
+    /// See `EventInner` in the core's bindings
+    pub event_inner: serde_json::Value,
+    pub details: String,
+    pub snowflake: Snowflake,
+    pub level: EventLevel,
+    pub caused_by: CausedBy,
+}
+
+impl ClientEvent {
+    /// The instance's console line, if this is one
+    pub fn console_line(&self) -> Option<ConsoleLine> {
+        let inner = self.event_inner.get("instance_event_inner")?;
+        let message = inner.get("message")?.as_str()?.to_string();
+        match inner.get("type")?.as_str()? {
+            "InstanceOutput" | "SystemMessage" => Some(ConsoleLine::Output(message)),
+            "InstanceInput" => Some(ConsoleLine::Input(message)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConsoleLine {
+    Output(String),
+    /// A command sent to the instance
+    Input(String),
+}
+
+/// Which events an event stream sends, see `EventQuery` in the core
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct EventQuery {
+    pub event_levels: Option<Vec<EventLevel>>,
+    /// Like `InstanceEvent` or `UserEvent`
+    pub event_types: Option<Vec<String>>,
+    pub instance_event_types: Option<Vec<String>>,
+    pub user_event_types: Option<Vec<String>>,
+    pub event_user_ids: Option<Vec<UserId>>,
+    pub event_instance_ids: Option<Vec<InstanceUuid>>,
+    /// Filled in by the client
+    pub bearer_token: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_console_line() {
+        let event: ClientEvent = serde_json::from_str(
+            r#"{
+                "event_inner": {"type": "InstanceEvent", "instance_uuid": "INSTANCE_a",
+                    "instance_name": "Survival",
+                    "instance_event_inner": {"type": "InstanceOutput", "message": "Done!"}},
+                "details": "", "snowflake": "123", "level": "Info", "caused_by": {"type": "System"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            event.console_line(),
+            Some(ConsoleLine::Output("Done!".to_string()))
+        );
+    }
+}
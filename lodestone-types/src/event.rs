@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::id::{InstanceUuid, MacroPID, UserId};
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
+pub enum EventLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[serde(tag = "type")]
+pub enum CausedBy {
+    User {
+        user_id: UserId,
+        user_name: String,
+        /// The admin who was signed in as this user when they acted
+        #[serde(default)]
+        impersonated_by: Option<UserId>,
+    },
+    Instance {
+        instance_uuid: InstanceUuid,
+    },
+    Macro {
+        macro_pid: MacroPID,
+    },
+    System,
+    Unknown,
+}
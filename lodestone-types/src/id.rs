@@ -0,0 +1,229 @@
+use std::fmt::Display;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_aux::prelude::*;
+use ts_rs::TS;
+
+use crate::LODESTONE_EPOCH_MIL;
+
+lazy_static! {
+    static ref SNOWFLAKE_GENERATOR: std::sync::Mutex<snowflake::SnowflakeIdGenerator> =
+        std::sync::Mutex::new(snowflake::SnowflakeIdGenerator::with_epoch(
+            1,
+            1,
+            std::time::UNIX_EPOCH + std::time::Duration::from_millis(LODESTONE_EPOCH_MIL as u64)
+        ));
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, TS, Copy)]
+#[serde(into = "String")]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(transparent))]
+pub struct Snowflake(
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[ts(type = "string")]
+    i64,
+);
+
+impl From<Snowflake> for String {
+    fn from(snowflake: Snowflake) -> Self {
+        snowflake.to_string()
+    }
+}
+
+impl Default for Snowflake {
+    fn default() -> Self {
+        Self(get_snowflake())
+    }
+}
+
+impl Snowflake {
+    pub fn new() -> Self {
+        Self(get_snowflake())
+    }
+
+    /// When the snowflake was generated, in milliseconds since the unix epoch
+    pub fn timestamp_millis(&self) -> i64 {
+        (self.0 >> 22) + LODESTONE_EPOCH_MIL
+    }
+}
+
+impl ToString for Snowflake {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl FromStr for Snowflake {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim().parse().map(Self)
+    }
+}
+
+fn get_snowflake() -> i64 {
+    SNOWFLAKE_GENERATOR.lock().unwrap().real_time_generate()
+}
+
+#[derive(Debug, Clone, Eq, Serialize, Deserialize, TS)]
+#[serde(transparent)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(transparent))]
+pub struct InstanceUuid(String);
+
+impl InstanceUuid {
+    pub fn no_prefix(&self) -> String {
+        self.0.replace("INSTANCE_", "")
+    }
+}
+
+impl From<String> for InstanceUuid {
+    fn from(uuid: String) -> Self {
+        Self(uuid)
+    }
+}
+// new type idiom
+
+impl Default for InstanceUuid {
+    fn default() -> Self {
+        Self(format!("INSTANCE_{}", uuid::Uuid::new_v4()))
+    }
+}
+
+impl AsRef<str> for InstanceUuid {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// implement partial eq for all types that can be converted to stri/ng
+impl<T: AsRef<str>> PartialEq<T> for InstanceUuid {
+    fn eq(&self, other: &T) -> bool {
+        self.0 == other.as_ref()
+    }
+}
+
+impl Hash for InstanceUuid {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl Display for InstanceUuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Eq, Serialize, Deserialize, TS)]
+#[serde(transparent)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(transparent))]
+pub struct UserId(String);
+
+impl From<String> for UserId {
+    fn from(uuid: String) -> Self {
+        Self(uuid)
+    }
+}
+
+impl Default for UserId {
+    fn default() -> Self {
+        Self(format!("USER_{}", uuid::Uuid::new_v4()))
+    }
+}
+
+// implement partial eq for all types that can be converted to string
+impl<T: AsRef<str>> PartialEq<T> for UserId {
+    fn eq(&self, other: &T) -> bool {
+        self.0 == other.as_ref()
+    }
+}
+
+impl AsRef<UserId> for UserId {
+    fn as_ref(&self) -> &UserId {
+        self
+    }
+}
+
+impl AsRef<str> for UserId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Hash for UserId {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, TS)]
+#[serde(transparent)]
+pub struct MacroPID(pub usize); // todo remove pub
+
+impl From<MacroPID> for usize {
+    fn from(uid: MacroPID) -> Self {
+        uid.0
+    }
+}
+
+impl From<&MacroPID> for usize {
+    fn from(uid: &MacroPID) -> Self {
+        uid.0
+    }
+}
+
+impl AsRef<usize> for MacroPID {
+    fn as_ref(&self) -> &usize {
+        &self.0
+    }
+}
+
+impl Display for MacroPID {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MacroPID({})", self.0)
+    }
+}
+
+#[test]
+fn test_snowflake() {
+    let snowflake1 = Snowflake::new();
+    // serializing
+    let snowflake_str = serde_json::to_string(&snowflake1).unwrap();
+    println!("{}", snowflake_str);
+    // deserializing
+    let snowflake2: Snowflake = serde_json::from_str(&snowflake_str).unwrap();
+    assert_eq!(snowflake1, snowflake2);
+    // parsing, like from a Last-Event-ID header
+    assert_eq!(snowflake1.to_string().parse::<Snowflake>(), Ok(snowflake1));
+}
+
+#[test]
+fn test_instance_uuid() {
+    let uuid1 = InstanceUuid::default();
+    // serializing
+    let uuid_str = serde_json::to_string(&uuid1).unwrap();
+    println!("{}", uuid_str);
+    // deserializing
+    let uuid2: InstanceUuid = serde_json::from_str(&uuid_str).unwrap();
+    assert_eq!(uuid1, uuid2);
+}
+
+#[test]
+fn test_user_id() {
+    let user_id1 = UserId::default();
+    // serializing
+    let user_id_str = serde_json::to_string(&user_id1).unwrap();
+    println!("{}", user_id_str);
+    // deserializing
+    let user_id2: UserId = serde_json::from_str(&user_id_str).unwrap();
+    assert_eq!(user_id1, user_id2);
+}
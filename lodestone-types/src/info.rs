@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+pub struct CoreInfo {
+    #[ts(type = "string")]
+    pub version: semver::Version,
+    pub is_setup: bool,
+    pub os: String,
+    pub arch: String,
+    pub cpu: String,
+    pub cpu_count: u32,
+    pub total_ram: u64,
+    pub total_disk: u64,
+    pub host_name: String,
+    pub uuid: String,
+    pub core_name: String,
+    pub up_since: i64,
+}
@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use enum_kinds::EnumKind;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::id::{InstanceUuid, UserId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, Copy)]
+#[serde(rename = "InstanceState")]
+pub enum State {
+    Starting,
+    Running,
+    Stopping,
+    Stopped,
+    Error,
+}
+
+impl ToString for State {
+    fn to_string(&self) -> String {
+        match self {
+            State::Starting => "Starting".to_string(),
+            State::Running => "Running".to_string(),
+            State::Stopping => "Stopping".to_string(),
+            State::Stopped => "Stopped".to_string(),
+            State::Error => "Error".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(tag = "type")]
+pub enum MinecraftVariant {
+    Vanilla,
+    Forge,
+    Fabric,
+    Paper,
+    Spigot,
+    Other { name: String },
+}
+
+/// The type of game this instance is
+///
+/// Meant to be consumed by frontend to display the correct icon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, EnumKind)]
+#[enum_kind(GameType, derive(Serialize, Deserialize, TS))]
+#[serde(tag = "type")]
+pub enum Game {
+    MinecraftJava {
+        variant: MinecraftVariant,
+    },
+    MinecraftBedrock,
+    Generic {
+        game_name: GameType,       //used for identifying the "game" ("Minecraft")
+        game_display_name: String, //displaying to the user what on earth this is ("MinecraftGlowstone")
+    },
+}
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, TS,
+)]
+pub enum HealthStatus {
+    #[default]
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+#[derive(Eq, Debug, Clone, Serialize, Deserialize, TS)]
+pub struct MinecraftPlayer {
+    pub name: String,
+    pub uuid: Option<String>,
+}
+
+impl MinecraftPlayer {
+    pub fn new(name: String, uuid: Option<String>) -> Self {
+        Self { name, uuid }
+    }
+}
+
+impl PartialEq for MinecraftPlayer {
+    fn eq(&self, other: &Self) -> bool {
+        // if uuid is not set, compare by name
+        if self.uuid.is_none() || other.uuid.is_none() {
+            self.name == other.name
+        } else {
+            self.uuid == other.uuid
+        }
+    }
+}
+
+impl Hash for MinecraftPlayer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.uuid.hash(state);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, TS, Clone, Hash)]
+pub struct GenericPlayer {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, TS, Clone)]
+#[serde(tag = "type")]
+pub enum Player {
+    MinecraftPlayer(MinecraftPlayer),
+    GenericPlayer(GenericPlayer),
+}
+
+impl Player {
+    /// Tells players apart, a minecraft player's uuid if known
+    pub fn id(&self) -> String {
+        match self {
+            Player::MinecraftPlayer(player) => {
+                player.uuid.clone().unwrap_or_else(|| player.name.clone())
+            }
+            Player::GenericPlayer(player) => player.id.clone(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Player::MinecraftPlayer(MinecraftPlayer { name, .. })
+            | Player::GenericPlayer(GenericPlayer { name, .. }) => name,
+        }
+    }
+}
+
+impl From<MinecraftPlayer> for Player {
+    fn from(player: MinecraftPlayer) -> Self {
+        Player::MinecraftPlayer(player)
+    }
+}
+
+impl From<GenericPlayer> for Player {
+    fn from(player: GenericPlayer) -> Self {
+        Player::GenericPlayer(player)
+    }
+}
+
+impl PartialEq for Player {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl Hash for Player {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+pub struct InstanceInfo {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    pub game_type: Game,
+    pub description: String,
+    pub version: String,
+    pub port: u32,
+    #[serde(default)]
+    pub bedrock_port: Option<u32>,
+    pub creation_time: i64,
+    pub path: String,
+    pub auto_start: bool,
+    pub restart_on_crash: bool,
+    pub state: State,
+    pub player_count: Option<u32>,
+    pub max_player_count: Option<u32>,
+    pub player_list: Option<HashSet<Player>>,
+    /// Filled in by the core, instances don't know who they belong to
+    #[serde(default)]
+    pub owner: Option<UserId>,
+    /// Also filled in by the core
+    #[serde(default)]
+    pub health: HealthStatus,
+}
+
+#[test]
+fn test_instance_info() {
+    let info: InstanceInfo = serde_json::from_str(
+        r#"{
+            "uuid": "INSTANCE_a", "name": "Survival",
+            "game_type": {"type": "MinecraftJava", "variant": {"type": "Paper"}},
+            "description": "", "version": "1.19.4", "port": 25565, "bedrock_port": null,
+            "creation_time": 1680000000, "path": "/lodestone/instances/Survival",
+            "auto_start": false, "restart_on_crash": true, "state": "Running",
+            "player_count": 1, "max_player_count": 20,
+            "player_list": [{"type": "MinecraftPlayer", "name": "Steve", "uuid": null}],
+            "owner": null, "health": "Healthy"
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(info.state, State::Running);
+    assert_eq!(
+        info.game_type,
+        Game::MinecraftJava {
+            variant: MinecraftVariant::Paper
+        }
+    );
+    let players: Vec<_> = info.player_list.unwrap().into_iter().collect();
+    assert_eq!(players[0].name(), "Steve");
+}
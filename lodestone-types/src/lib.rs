@@ -0,0 +1,25 @@
+#![forbid(unsafe_code)]
+
+//! Types of the Lodestone Core API that both the core and its clients, like `lodestone-client`,
+//! are built from, so the two can't drift apart.
+//!
+//! Their TypeScript bindings are written to the core's `bindings/` by the core, along with the
+//! bindings of its own types.
+
+mod event;
+mod id;
+mod info;
+mod instance;
+mod user;
+
+pub use event::{CausedBy, EventLevel};
+pub use id::{InstanceUuid, MacroPID, Snowflake, UserId};
+pub use info::CoreInfo;
+pub use instance::{
+    Game, GameType, GenericPlayer, HealthStatus, InstanceInfo, MinecraftPlayer, MinecraftVariant,
+    Player, State,
+};
+pub use user::{JwtToken, Locale, LoginReply, PublicUser, UserPermission};
+
+/// Start of the snowflake clock, Nov 4th 2022
+pub const LODESTONE_EPOCH_MIL: i64 = 1667530800000;
@@ -0,0 +1,148 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::id::{InstanceUuid, UserId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, TS)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "en")]
+    En,
+    #[serde(rename = "fr")]
+    Fr,
+    #[serde(rename = "de")]
+    De,
+    #[serde(rename = "es")]
+    Es,
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+}
+
+impl Locale {
+    /// Picks the first supported language out of an `Accept-Language` header
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        header.split(',').find_map(|tag| {
+            let tag = tag.split(';').next()?.trim().to_ascii_lowercase();
+            match tag.split('-').next()? {
+                "en" => Some(Locale::En),
+                "fr" => Some(Locale::Fr),
+                "de" => Some(Locale::De),
+                "es" => Some(Locale::Es),
+                "zh" => Some(Locale::ZhCn),
+                _ => None,
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, TS, Debug)]
+pub struct UserPermission {
+    pub can_view_instance: HashSet<InstanceUuid>,
+    pub can_start_instance: HashSet<InstanceUuid>,
+    pub can_stop_instance: HashSet<InstanceUuid>,
+    // only the console output, without being able to send commands
+    #[serde(default)]
+    pub can_read_instance_console: HashSet<InstanceUuid>,
+    // sending commands, which also lets the user read the console
+    #[serde(alias = "can_access_instance_console")]
+    pub can_write_instance_console: HashSet<InstanceUuid>,
+    // resource usage reported by the monitor
+    #[serde(default)]
+    pub can_monitor_instance: HashSet<InstanceUuid>,
+    pub can_access_instance_setting: HashSet<InstanceUuid>,
+    pub can_read_instance_resource: HashSet<InstanceUuid>,
+    // unsafe permission, owner exclusive unless explicitly granted
+    pub can_write_instance_resource: HashSet<InstanceUuid>,
+    // unsafe permission, owner exclusive unless explicitly granted
+    pub can_access_instance_macro: HashSet<InstanceUuid>,
+    pub can_read_instance_file: HashSet<InstanceUuid>,
+    // unsafe permission, owner exclusive unless explicitly granted
+    pub can_write_instance_file: HashSet<InstanceUuid>,
+
+    pub can_create_instance: bool,
+    pub can_delete_instance: bool,
+    pub can_read_global_file: bool,
+    // unsafe permission, owner exclusive unless explicitly granted
+    pub can_write_global_file: bool,
+    // owner exclusive unless explicitly granted
+    pub can_manage_permission: bool,
+}
+
+impl UserPermission {
+    pub fn new() -> Self {
+        UserPermission {
+            can_view_instance: HashSet::new(),
+            can_start_instance: HashSet::new(),
+            can_stop_instance: HashSet::new(),
+            can_read_instance_console: HashSet::new(),
+            can_write_instance_console: HashSet::new(),
+            can_monitor_instance: HashSet::new(),
+            can_access_instance_setting: HashSet::new(),
+            can_read_instance_resource: HashSet::new(),
+            can_write_instance_resource: HashSet::new(),
+            can_access_instance_macro: HashSet::new(),
+            can_read_instance_file: HashSet::new(),
+            can_write_instance_file: HashSet::new(),
+            can_create_instance: false,
+            can_delete_instance: false,
+            can_read_global_file: false,
+            can_write_global_file: false,
+            can_manage_permission: false,
+        }
+    }
+}
+
+impl Default for UserPermission {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+pub struct PublicUser {
+    pub uid: UserId,
+    pub username: String,
+    pub is_owner: bool,
+    pub is_admin: bool,
+    pub permissions: UserPermission,
+    #[serde(default)]
+    pub locale: Option<Locale>,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(transparent)]
+pub struct JwtToken(String);
+
+impl ToString for JwtToken {
+    fn to_string(&self) -> String {
+        self.0.clone()
+    }
+}
+
+impl From<String> for JwtToken {
+    fn from(token: String) -> Self {
+        JwtToken(token)
+    }
+}
+
+impl From<JwtToken> for String {
+    fn from(jwt_token: JwtToken) -> Self {
+        jwt_token.to_string()
+    }
+}
+
+impl AsRef<str> for JwtToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+pub struct LoginReply {
+    pub token: JwtToken,
+    pub user: PublicUser,
+}
@@ -0,0 +1,22 @@
+-- Standing of a player across instances, keyed by their UUID, XUID or name if neither is known
+CREATE TABLE IF NOT EXISTS PlayerReputation (
+    player_id           TEXT        PRIMARY KEY,
+    player_name         TEXT        NOT NULL,
+    watched             BOOLEAN     NOT NULL    DEFAULT 0,
+    -- JSON array of labels
+    flags               TEXT        NOT NULL    DEFAULT '[]',
+    updated_at          BIGINT      NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS PlayerNotes (
+    id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
+    player_id           TEXT        NOT NULL,
+    body                TEXT        NOT NULL,
+    author_id           TEXT        NOT NULL,
+    author_name         TEXT        NOT NULL,
+    instance_id         TEXT,
+    created_at          BIGINT      NOT NULL,
+    updated_at          BIGINT      NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS PlayerNotesByPlayer ON PlayerNotes (player_id);
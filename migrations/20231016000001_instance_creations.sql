@@ -0,0 +1,10 @@
+-- Instances being set up, removed once they are ready or cleaned up
+CREATE TABLE IF NOT EXISTS InstanceCreations (
+    instance_uuid           TEXT        PRIMARY KEY,
+    setup_path              TEXT        NOT NULL,
+    setup_config            TEXT        NOT NULL,
+    dot_lodestone_config    TEXT        NOT NULL,
+    requester_id            TEXT        NOT NULL,
+    started_at              BIGINT      NOT NULL,
+    attempts                INTEGER     NOT NULL    DEFAULT 1
+);
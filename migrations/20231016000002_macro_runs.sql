@@ -0,0 +1,20 @@
+-- Macro runs started from the API, kept once they are over to debug failed automation
+CREATE TABLE IF NOT EXISTS MacroRuns (
+    id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
+    instance_uuid       TEXT        NOT NULL,
+    -- only unique while the core runs
+    macro_pid           INTEGER     NOT NULL,
+    macro_name          TEXT        NOT NULL,
+    -- JSON array
+    args                TEXT        NOT NULL,
+    -- JSON CausedBy
+    caused_by           TEXT        NOT NULL,
+    started_at          BIGINT      NOT NULL,
+    ended_at            BIGINT,
+    -- JSON ExitStatus, NULL while running
+    exit_status         TEXT,
+    -- JSON array of the last lines the macro printed
+    output              TEXT        NOT NULL    DEFAULT '[]'
+);
+
+CREATE INDEX IF NOT EXISTS MacroRunsByInstance ON MacroRuns (instance_uuid, id);
@@ -0,0 +1,511 @@
+//! User defined alerts on instance metrics.
+//!
+//! Rules are checked every few seconds against a fresh monitor report. A rule fires once its
+//! condition has held for its whole duration, which runs its actions, and resolves as soon as
+//! the condition stops holding. Firing and resolving are also sent out as instance events.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    disk_space,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    mail,
+    prelude::GameInstance,
+    quota,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{MonitorReport, TServer},
+    },
+    types::{InstanceUuid, Snowflake},
+    util::post_webhook,
+    AppState,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// The `tps` command shows up in the console, so it isn't sent on every check
+const TPS_INTERVAL_SECS: i64 = 60;
+pub const MAX_RULES_PER_INSTANCE: usize = 32;
+/// A condition has to hold for at most a day
+const MAX_DURATION_SECS: u32 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, TS)]
+#[ts(export)]
+pub enum AlertMetric {
+    /// Percent of the whole machine
+    CpuPercent,
+    MemoryMb,
+    /// The instance's maximum RAM minus what it uses
+    MemoryHeadroomMb,
+    /// Paper and Spigot only
+    Tps,
+    /// On the disk holding the instance
+    DiskFreeMb,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct AlertCondition {
+    pub metric: AlertMetric,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    /// How long the condition has to hold before the alert fires
+    pub for_secs: u32,
+}
+
+impl AlertCondition {
+    fn holds(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Above => value > self.threshold,
+            Comparison::Below => value < self.threshold,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum AlertAction {
+    /// POSTs a json description of the alert when it fires and when it resolves
+    Webhook {
+        url: String,
+    },
+    /// Needs SMTP to be configured
+    Email {
+        to: String,
+    },
+    RestartInstance,
+    /// Refuses new snapshots of the instance while the alert is firing
+    PauseBackups,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct NewAlertRule {
+    pub name: String,
+    pub condition: AlertCondition,
+    #[serde(default)]
+    pub actions: Vec<AlertAction>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl NewAlertRule {
+    pub fn validate(&self) -> Result<(), Error> {
+        let bad_request = |message: String| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(message),
+        };
+        if self.name.trim().is_empty() {
+            return Err(bad_request("Alert name cannot be empty".to_string()));
+        }
+        if !self.condition.threshold.is_finite() {
+            return Err(bad_request("Alert threshold must be a number".to_string()));
+        }
+        if self.condition.for_secs > MAX_DURATION_SECS {
+            return Err(bad_request(format!(
+                "An alert's condition can hold for at most {MAX_DURATION_SECS} seconds"
+            )));
+        }
+        for action in &self.actions {
+            match action {
+                AlertAction::Webhook { url } => {
+                    let parsed = url::Url::parse(url)
+                        .map_err(|e| bad_request(format!("Invalid webhook URL: {e}")))?;
+                    if !matches!(parsed.scheme(), "http" | "https") {
+                        return Err(bad_request("Webhooks must be http or https".to_string()));
+                    }
+                }
+                AlertAction::Email { to } => mail::validate_address(to)?,
+                AlertAction::RestartInstance | AlertAction::PauseBackups => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_rule(self, id: Snowflake) -> AlertRule {
+        AlertRule {
+            id,
+            name: self.name,
+            condition: self.condition,
+            actions: self.actions,
+            enabled: self.enabled,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct AlertRule {
+    pub id: Snowflake,
+    pub name: String,
+    pub condition: AlertCondition,
+    pub actions: Vec<AlertAction>,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct AlertStatus {
+    pub rule: AlertRule,
+    pub firing: bool,
+    /// When the condition started holding
+    pub since: Option<i64>,
+    pub last_value: Option<f64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Transition {
+    Fired,
+    Resolved,
+}
+
+#[derive(Default)]
+struct RuleState {
+    holding_since: Option<i64>,
+    firing: bool,
+    last_value: Option<f64>,
+}
+
+impl RuleState {
+    /// An unknown value counts as the condition not holding
+    fn observe(
+        &mut self,
+        condition: &AlertCondition,
+        value: Option<f64>,
+        now: i64,
+    ) -> Option<Transition> {
+        self.last_value = value;
+        if !value.map_or(false, |value| condition.holds(value)) {
+            self.holding_since = None;
+            return if std::mem::take(&mut self.firing) {
+                Some(Transition::Resolved)
+            } else {
+                None
+            };
+        }
+        let since = *self.holding_since.get_or_insert(now);
+        if !self.firing && now - since >= condition.for_secs as i64 {
+            self.firing = true;
+            return Some(Transition::Fired);
+        }
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct AlertsManager {
+    states: HashMap<(InstanceUuid, Snowflake), RuleState>,
+    /// Rules currently pausing each instance's backups
+    backups_paused: HashMap<InstanceUuid, HashSet<Snowflake>>,
+    last_tps: HashMap<InstanceUuid, (i64, Option<f64>)>,
+}
+
+impl AlertsManager {
+    pub fn backups_paused(&self, uuid: &InstanceUuid) -> bool {
+        self.backups_paused
+            .get(uuid)
+            .map_or(false, |rules| !rules.is_empty())
+    }
+
+    /// Instances of the alerts currently firing, once per alert
+    pub fn firing(&self) -> impl Iterator<Item = &InstanceUuid> {
+        self.states
+            .iter()
+            .filter(|(_, state)| state.firing)
+            .map(|((uuid, _), _)| uuid)
+    }
+
+    pub fn status(&self, uuid: &InstanceUuid, rules: Vec<AlertRule>) -> Vec<AlertStatus> {
+        rules
+            .into_iter()
+            .map(|rule| {
+                let state = self.states.get(&(uuid.clone(), rule.id));
+                AlertStatus {
+                    firing: state.map_or(false, |state| state.firing),
+                    since: state.and_then(|state| state.holding_since),
+                    last_value: state.and_then(|state| state.last_value),
+                    rule,
+                }
+            })
+            .collect()
+    }
+
+    /// Drops what is known about rules of `uuid` not in `keep`
+    pub fn retain_rules(&mut self, uuid: &InstanceUuid, keep: &[Snowflake]) {
+        self.states
+            .retain(|(instance, rule), _| instance != uuid || keep.contains(rule));
+        if let Some(rules) = self.backups_paused.get_mut(uuid) {
+            rules.retain(|rule| keep.contains(rule));
+        }
+        if keep.is_empty() {
+            self.backups_paused.remove(uuid);
+            self.last_tps.remove(uuid);
+        }
+    }
+}
+
+/// The current value of a metric, `None` if it doesn't apply to the instance right now
+async fn metric_value(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    instance: &mut GameInstance,
+    report: &mut Option<MonitorReport>,
+    metric: AlertMetric,
+    now: i64,
+) -> Option<f64> {
+    const MB: f64 = 1024.0 * 1024.0;
+    if matches!(
+        metric,
+        AlertMetric::CpuPercent | AlertMetric::MemoryMb | AlertMetric::MemoryHeadroomMb
+    ) && report.is_none()
+    {
+        *report = Some(instance.monitor().await);
+    }
+    match metric {
+        AlertMetric::CpuPercent => report.as_ref()?.cpu_usage.map(|cpu| cpu as f64),
+        AlertMetric::MemoryMb => report
+            .as_ref()?
+            .memory_usage
+            .map(|memory| memory as f64 / MB),
+        AlertMetric::MemoryHeadroomMb => {
+            let memory = report.as_ref()?.memory_usage?;
+            let max_ram = quota::max_ram_of(instance).await;
+            (max_ram > 0).then(|| max_ram as f64 - memory as f64 / MB)
+        }
+        AlertMetric::Tps => {
+            let last = state.alerts.lock().await.last_tps.get(uuid).copied();
+            match last {
+                Some((at, tps)) if now - at < TPS_INTERVAL_SECS => tps,
+                _ => {
+                    let tps = match instance {
+                        GameInstance::MinecraftInstance(instance) => instance.query_tps().await,
+                        _ => None,
+                    };
+                    state
+                        .alerts
+                        .lock()
+                        .await
+                        .last_tps
+                        .insert(uuid.clone(), (now, tps));
+                    tps
+                }
+            }
+        }
+        AlertMetric::DiskFreeMb => {
+            let path = instance.path().await;
+            tokio::task::spawn_blocking(move || disk_space::available_space(&path))
+                .await
+                .ok()
+                .flatten()
+                .map(|available| available as f64 / MB)
+        }
+    }
+}
+
+async fn run_actions(
+    state: AppState,
+    uuid: InstanceUuid,
+    mut instance: GameInstance,
+    rule: AlertRule,
+    transition: Transition,
+    value: Option<f64>,
+) {
+    let instance_name = instance.name().await;
+    let fired = transition == Transition::Fired;
+    state.event_broadcaster.send(Event {
+        event_inner: EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid: uuid.clone(),
+            instance_name: instance_name.clone(),
+            instance_event_inner: if fired {
+                InstanceEventInner::AlertTriggered {
+                    rule_id: rule.id,
+                    rule_name: rule.name.clone(),
+                    value: value.unwrap_or_default(),
+                }
+            } else {
+                InstanceEventInner::AlertResolved {
+                    rule_id: rule.id,
+                    rule_name: rule.name.clone(),
+                }
+            },
+        }),
+        details: "".to_string(),
+        snowflake: Snowflake::default(),
+        caused_by: CausedBy::System,
+    });
+    let summary = if fired {
+        format!(
+            "Alert \"{}\" fired for {}: {:?} is {:.1}",
+            rule.name,
+            instance_name,
+            rule.condition.metric,
+            value.unwrap_or_default()
+        )
+    } else {
+        format!("Alert \"{}\" resolved for {}", rule.name, instance_name)
+    };
+    for action in &rule.actions {
+        match action {
+            AlertAction::Webhook { url } => {
+                let body = json!({
+                    "status": if fired { "fired" } else { "resolved" },
+                    "instance_uuid": uuid,
+                    "instance_name": instance_name,
+                    "rule_id": rule.id,
+                    "rule_name": rule.name,
+                    "condition": rule.condition,
+                    "value": value,
+                    "summary": summary,
+                });
+                if let Err(e) = post_webhook(url, &body).await {
+                    warn!("Alert \"{}\" failed to call its webhook: {e}", rule.name);
+                }
+            }
+            AlertAction::Email { to } => {
+                let smtp = state.global_settings.lock().await.smtp();
+                match smtp {
+                    Some(smtp) => {
+                        if let Err(e) = mail::send_mail(&smtp, to, &summary, summary.clone()).await
+                        {
+                            warn!("Alert \"{}\" failed to send its email: {e}", rule.name);
+                        }
+                    }
+                    None => warn!(
+                        "Alert \"{}\" can't send its email, SMTP isn't configured",
+                        rule.name
+                    ),
+                }
+            }
+            AlertAction::RestartInstance if fired => {
+                if let Err(e) = instance.restart(CausedBy::System, false).await {
+                    error!(
+                        "Alert \"{}\" failed to restart {instance_name}: {e}",
+                        rule.name
+                    );
+                }
+            }
+            AlertAction::PauseBackups => {
+                let mut alerts = state.alerts.lock().await;
+                let rules = alerts.backups_paused.entry(uuid.clone()).or_default();
+                if fired {
+                    rules.insert(rule.id);
+                } else {
+                    rules.remove(&rule.id);
+                }
+            }
+            AlertAction::RestartInstance => {}
+        }
+    }
+}
+
+/// Checks every instance's rules until the core shuts down
+pub async fn run_alerts(state: AppState) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let all_rules = state.global_settings.lock().await.all_alert_rules();
+        for (uuid, rules) in all_rules {
+            let instance = state.instances.lock().await.get(&uuid).cloned();
+            let mut instance = match instance {
+                Some(instance) => instance,
+                None => continue,
+            };
+            let now = chrono::Utc::now().timestamp();
+            let mut report = None;
+            let mut values: HashMap<AlertMetric, Option<f64>> = HashMap::new();
+            for rule in rules {
+                let value = if rule.enabled {
+                    match values.get(&rule.condition.metric) {
+                        Some(value) => *value,
+                        None => {
+                            let value = metric_value(
+                                &state,
+                                &uuid,
+                                &mut instance,
+                                &mut report,
+                                rule.condition.metric,
+                                now,
+                            )
+                            .await;
+                            values.insert(rule.condition.metric, value);
+                            value
+                        }
+                    }
+                } else {
+                    // resolves the alert if it was firing when it got disabled
+                    None
+                };
+                let transition = state
+                    .alerts
+                    .lock()
+                    .await
+                    .states
+                    .entry((uuid.clone(), rule.id))
+                    .or_default()
+                    .observe(&rule.condition, value, now);
+                if let Some(transition) = transition {
+                    // slow webhooks or a restart shouldn't hold up other rules
+                    tokio::spawn(run_actions(
+                        state.clone(),
+                        uuid.clone(),
+                        instance.clone(),
+                        rule,
+                        transition,
+                        value,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule_state() {
+        let condition = AlertCondition {
+            metric: AlertMetric::CpuPercent,
+            comparison: Comparison::Above,
+            threshold: 90.0,
+            for_secs: 300,
+        };
+        let mut state = RuleState::default();
+        assert_eq!(state.observe(&condition, Some(95.0), 0), None);
+        assert_eq!(state.observe(&condition, Some(95.0), 299), None);
+        assert_eq!(
+            state.observe(&condition, Some(91.0), 300),
+            Some(Transition::Fired)
+        );
+        assert_eq!(state.observe(&condition, Some(99.0), 400), None);
+        assert_eq!(
+            state.observe(&condition, Some(50.0), 410),
+            Some(Transition::Resolved)
+        );
+        // the timer starts over
+        assert_eq!(state.observe(&condition, Some(95.0), 420), None);
+        assert_eq!(state.observe(&condition, None, 430), None);
+        assert_eq!(state.observe(&condition, Some(95.0), 719), None);
+        assert_eq!(state.holding_since, Some(719));
+    }
+}
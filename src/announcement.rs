@@ -0,0 +1,493 @@
+//! Messages sent to everyone on a running instance on a schedule, like a reminder of the rules
+//! or of an upcoming event.
+//!
+//! An announcement is sent every so many minutes the server has been running, or at the times a
+//! cron expression gives, in the core's local time like [`crate::power_schedule`]. Messages can
+//! use `{instance}`, `{players}`, `{max_players}`, `{uptime}` and `{time}`, filled in when they
+//! are sent.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    power_schedule::local_now,
+    traits::{
+        t_configurable::{Game, TConfigurable},
+        t_player::TPlayerManagement,
+        t_server::{State, TServer},
+    },
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+pub const MAX_ANNOUNCEMENTS_PER_INSTANCE: usize = 32;
+const MAX_MESSAGE_LEN: usize = 1000;
+/// A week
+const MAX_INTERVAL_MINUTES: u32 = 7 * 24 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum AnnouncementFormat {
+    /// Plain text through `say`, on Java and Bedrock
+    Say,
+    /// A JSON text component through `tellraw @a`, Java only
+    Tellraw,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum AnnouncementTiming {
+    /// Every so many minutes while the server runs, counted from when it started or from when
+    /// the announcement was added
+    Interval { minutes: u32 },
+    /// `minute hour day-of-month month day-of-week`, like `0 */2 * * *`
+    Cron { expression: String },
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct NewAnnouncement {
+    pub name: String,
+    pub message: String,
+    pub format: AnnouncementFormat,
+    pub timing: AnnouncementTiming,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl NewAnnouncement {
+    pub fn validate(&self, game: &Game) -> Result<(), Error> {
+        let bad_request = |message: String| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(message),
+        };
+        if self.name.trim().is_empty() {
+            return Err(bad_request("Announcement name cannot be empty".to_string()));
+        }
+        if self.message.trim().is_empty() {
+            return Err(bad_request(
+                "Announcement message cannot be empty".to_string(),
+            ));
+        }
+        if self.message.len() > MAX_MESSAGE_LEN {
+            return Err(bad_request(format!(
+                "An announcement can be at most {MAX_MESSAGE_LEN} characters long"
+            )));
+        }
+        match self.format {
+            AnnouncementFormat::Say => {}
+            AnnouncementFormat::Tellraw => {
+                if !matches!(game, Game::MinecraftJava { .. }) {
+                    return Err(bad_request(
+                        "tellraw announcements are only supported on Minecraft Java".to_string(),
+                    ));
+                }
+                serde_json::from_str::<serde_json::Value>(&self.message).map_err(|e| {
+                    bad_request(format!(
+                        "A tellraw message must be a JSON text component: {e}"
+                    ))
+                })?;
+            }
+        }
+        match &self.timing {
+            AnnouncementTiming::Interval { minutes } => {
+                if *minutes == 0 || *minutes > MAX_INTERVAL_MINUTES {
+                    return Err(bad_request(format!(
+                        "An announcement can be sent every 1 to {MAX_INTERVAL_MINUTES} minutes"
+                    )));
+                }
+            }
+            AnnouncementTiming::Cron { expression } => {
+                if CronSchedule::parse(expression).is_none() {
+                    return Err(bad_request(format!(
+                        "Invalid cron expression {expression}, expected 5 fields like `0 */2 * * *`"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_announcement(self, id: Snowflake) -> Announcement {
+        Announcement {
+            id,
+            name: self.name,
+            message: self.message,
+            format: self.format,
+            timing: self.timing,
+            enabled: self.enabled,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct Announcement {
+    pub id: Snowflake,
+    pub name: String,
+    pub message: String,
+    pub format: AnnouncementFormat,
+    pub timing: AnnouncementTiming,
+    pub enabled: bool,
+}
+
+/// What a message's variables are filled in with
+struct Variables {
+    instance: String,
+    players: u32,
+    max_players: Option<u32>,
+    uptime: chrono::Duration,
+    now: NaiveDateTime,
+}
+
+impl Announcement {
+    fn fill(&self, variables: &Variables) -> String {
+        let uptime = if variables.uptime.num_hours() > 0 {
+            format!(
+                "{}h {}m",
+                variables.uptime.num_hours(),
+                variables.uptime.num_minutes() % 60
+            )
+        } else {
+            format!("{}m", variables.uptime.num_minutes())
+        };
+        let values = [
+            ("{instance}", variables.instance.clone()),
+            ("{players}", variables.players.to_string()),
+            (
+                "{max_players}",
+                variables
+                    .max_players
+                    .map_or_else(|| "?".to_string(), |max| max.to_string()),
+            ),
+            ("{uptime}", uptime),
+            ("{time}", variables.now.format("%H:%M").to_string()),
+        ];
+        let mut message = self.message.clone();
+        for (variable, value) in values {
+            let value = match self.format {
+                AnnouncementFormat::Say => value,
+                // the value lands inside a JSON string
+                AnnouncementFormat::Tellraw => {
+                    let quoted = serde_json::Value::String(value).to_string();
+                    quoted[1..quoted.len() - 1].to_string()
+                }
+            };
+            message = message.replace(variable, &value);
+        }
+        message
+    }
+
+    /// The console command that sends the announcement
+    fn command(&self, variables: &Variables) -> String {
+        let message = self.fill(variables);
+        match self.format {
+            AnnouncementFormat::Say => format!("say {}", message.replace(['\r', '\n'], " ")),
+            AnnouncementFormat::Tellraw => {
+                // commands are a single line
+                let component = serde_json::from_str::<serde_json::Value>(&message)
+                    .map(|component| component.to_string())
+                    .unwrap_or_else(|_| message.replace(['\r', '\n'], " "));
+                format!("tellraw @a {component}")
+            }
+        }
+    }
+}
+
+/// The values a cron field allows, as bits
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut allowed = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)?),
+            None => (item, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse().ok()?, end.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            // `5/15` runs from 5 to the end
+            (value, if step > 1 { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            allowed |= 1 << value;
+        }
+    }
+    Some(allowed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// Whether the day fields were restricted, cron matches either of them when both are
+    restricted_days: (bool, bool),
+}
+
+impl CronSchedule {
+    fn parse(expression: &str) -> Option<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return None;
+        }
+        let mut days_of_week = parse_cron_field(fields[4], 0, 7)?;
+        // both 0 and 7 are Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Some(Self {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days_of_month: parse_cron_field(fields[2], 1, 31)?,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            days_of_week,
+            restricted_days: (fields[2] != "*", fields[4] != "*"),
+        })
+    }
+
+    fn matches(&self, at: NaiveDateTime) -> bool {
+        let has = |bits: u64, value: u32| bits & (1 << value) != 0;
+        let day_of_month = has(self.days_of_month, at.day());
+        let day_of_week = has(self.days_of_week, at.weekday().num_days_from_sunday());
+        let day = match self.restricted_days {
+            (true, true) => day_of_month || day_of_week,
+            (true, false) => day_of_month,
+            (false, true) => day_of_week,
+            (false, false) => true,
+        };
+        day && has(self.minutes, at.minute())
+            && has(self.hours, at.hour())
+            && has(self.months, at.month())
+    }
+
+    /// Whether a minute in `(after, until]` matches, only the last hour of it is looked at
+    fn fires_between(&self, after: NaiveDateTime, until: NaiveDateTime) -> bool {
+        let after = after.max(until - chrono::Duration::hours(1));
+        let mut minute = match after.with_second(0).and_then(|at| at.with_nanosecond(0)) {
+            Some(minute) => minute + chrono::Duration::minutes(1),
+            None => return false,
+        };
+        while minute <= until {
+            if self.matches(minute) {
+                return true;
+            }
+            minute += chrono::Duration::minutes(1);
+        }
+        false
+    }
+}
+
+/// What was last seen of a running instance
+struct Tracked {
+    running_since: NaiveDateTime,
+    last_check: NaiveDateTime,
+    next_interval: HashMap<Snowflake, NaiveDateTime>,
+}
+
+impl Tracked {
+    fn new(now: NaiveDateTime) -> Self {
+        Self {
+            running_since: now,
+            last_check: now,
+            next_interval: HashMap::new(),
+        }
+    }
+
+    fn due<'a>(
+        &mut self,
+        announcements: &'a [Announcement],
+        now: NaiveDateTime,
+    ) -> Vec<&'a Announcement> {
+        let last_check = std::mem::replace(&mut self.last_check, now);
+        self.next_interval.retain(|id, _| {
+            announcements
+                .iter()
+                .any(|announcement| &announcement.id == id)
+        });
+        let next_interval = &mut self.next_interval;
+        announcements
+            .iter()
+            .filter(|announcement| announcement.enabled)
+            .filter(|announcement| match &announcement.timing {
+                AnnouncementTiming::Interval { minutes } => {
+                    let every = chrono::Duration::minutes(*minutes as i64);
+                    let next = next_interval
+                        .entry(announcement.id)
+                        .or_insert_with(|| now + every);
+                    if now < *next {
+                        return false;
+                    }
+                    // a late check sends it once rather than catching up
+                    while *next <= now {
+                        *next += every;
+                    }
+                    true
+                }
+                AnnouncementTiming::Cron { expression } => CronSchedule::parse(expression)
+                    .map_or(false, |cron| cron.fires_between(last_check, now)),
+            })
+            .collect()
+    }
+}
+
+/// Sends every instance's announcements until the core shuts down
+pub async fn run_announcements(state: AppState) {
+    let mut tracked: HashMap<InstanceUuid, Tracked> = HashMap::new();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let all_announcements = state.global_settings.lock().await.all_announcements();
+        tracked.retain(|uuid, _| all_announcements.contains_key(uuid));
+        let now = local_now();
+        for (uuid, announcements) in all_announcements {
+            let instance = state.instances.lock().await.get(&uuid).cloned();
+            let instance = match instance {
+                Some(instance) => instance,
+                None => continue,
+            };
+            if instance.state().await != State::Running {
+                tracked.remove(&uuid);
+                continue;
+            }
+            let seen = tracked.entry(uuid).or_insert_with(|| Tracked::new(now));
+            let due = seen.due(&announcements, now);
+            if due.is_empty() {
+                continue;
+            }
+            let variables = Variables {
+                instance: instance.name().await,
+                players: instance.get_player_count().await.unwrap_or(0),
+                max_players: instance.get_max_player_count().await.ok(),
+                uptime: now - seen.running_since,
+                now,
+            };
+            for announcement in due {
+                if let Err(e) = instance
+                    .send_command(&announcement.command(&variables), CausedBy::System)
+                    .await
+                {
+                    warn!(
+                        "Failed to send announcement {} to {}: {e}",
+                        announcement.name, variables.instance
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date_time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_time, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn announcement(timing: AnnouncementTiming) -> Announcement {
+        Announcement {
+            id: Snowflake::new(),
+            name: "rules".to_string(),
+            message: "Read the rules, {players}/{max_players} online".to_string(),
+            format: AnnouncementFormat::Say,
+            timing,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn test_cron() {
+        let every_two_hours = CronSchedule::parse("0 */2 * * *").unwrap();
+        assert!(every_two_hours.matches(at("2023-05-01 04:00:00")));
+        assert!(!every_two_hours.matches(at("2023-05-01 05:00:00")));
+        assert!(every_two_hours.fires_between(at("2023-05-01 03:59:50"), at("2023-05-01 04:00:05")));
+        assert!(
+            !every_two_hours.fires_between(at("2023-05-01 04:00:05"), at("2023-05-01 04:00:20"))
+        );
+        // 2023-05-01 is a Monday
+        let weekdays = CronSchedule::parse("30 18 * * 1-5").unwrap();
+        assert!(weekdays.matches(at("2023-05-01 18:30:00")));
+        assert!(!weekdays.matches(at("2023-05-06 18:30:00")));
+        let sundays = CronSchedule::parse("0 12 * * 7").unwrap();
+        assert!(sundays.matches(at("2023-05-07 12:00:00")));
+        assert_eq!(CronSchedule::parse("0 24 * * *"), None);
+        assert_eq!(CronSchedule::parse("* * *"), None);
+        assert_eq!(CronSchedule::parse("*/0 * * * *"), None);
+    }
+
+    #[test]
+    fn test_due_on_interval() {
+        let announcements = [announcement(AnnouncementTiming::Interval { minutes: 10 })];
+        let mut tracked = Tracked::new(at("2023-05-01 12:00:00"));
+        assert!(tracked
+            .due(&announcements, at("2023-05-01 12:00:00"))
+            .is_empty());
+        assert!(tracked
+            .due(&announcements, at("2023-05-01 12:09:45"))
+            .is_empty());
+        assert_eq!(
+            tracked.due(&announcements, at("2023-05-01 12:10:00")).len(),
+            1
+        );
+        assert!(tracked
+            .due(&announcements, at("2023-05-01 12:10:15"))
+            .is_empty());
+        // an hour late, it is sent once
+        assert_eq!(
+            tracked.due(&announcements, at("2023-05-01 13:10:00")).len(),
+            1
+        );
+        assert!(tracked
+            .due(&announcements, at("2023-05-01 13:10:15"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_command() {
+        let variables = Variables {
+            instance: "Survival \"SMP\"".to_string(),
+            players: 3,
+            max_players: Some(20),
+            uptime: chrono::Duration::minutes(125),
+            now: at("2023-05-01 12:00:00"),
+        };
+        let mut say = announcement(AnnouncementTiming::Interval { minutes: 10 });
+        assert_eq!(say.command(&variables), "say Read the rules, 3/20 online");
+        say.message = "Up for {uptime}\non {instance}".to_string();
+        assert_eq!(
+            say.command(&variables),
+            "say Up for 2h 5m on Survival \"SMP\""
+        );
+        let tellraw = Announcement {
+            format: AnnouncementFormat::Tellraw,
+            message: "{\n  \"text\": \"Welcome to {instance}\"\n}".to_string(),
+            ..say
+        };
+        assert_eq!(
+            tellraw.command(&variables),
+            r#"tellraw @a {"text":"Welcome to Survival \"SMP\""}"#
+        );
+    }
+}
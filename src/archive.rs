@@ -0,0 +1,457 @@
+//! Archived instances, for seasonal servers that shouldn't take up disk between seasons.
+//!
+//! Archiving compresses the instance directory into a single file, optionally kept in another
+//! storage location, and unloads the instance. Its settings are kept, so restoring the archive
+//! brings the instance back the way it was.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event},
+    i18n::{LocalizedMessage, MessageId},
+    implementations::minecraft::MinecraftInstance,
+    prelude::{path_to_archives, GameInstance},
+    storage::DEFAULT_STORAGE_LOCATION,
+    traits::{
+        t_configurable::{GameType, TConfigurable},
+        t_server::{State, TServer},
+    },
+    types::{DotLodestoneConfig, InstanceUuid},
+    AppState,
+};
+
+const REGISTRY_FILE: &str = "archived_instances.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct ArchivedInstance {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    pub game_type: GameType,
+    /// Name of the instance directory, it is restored under the same name
+    pub dir_name: String,
+    /// Where the instance lived and is restored to
+    pub storage_location: String,
+    #[ts(type = "string")]
+    pub archive_path: PathBuf,
+    /// Compressed size in bytes
+    pub size: u64,
+    pub archived_at: i64,
+}
+
+pub struct ArchiveManager {
+    archived: HashMap<InstanceUuid, ArchivedInstance>,
+    path_to_registry: PathBuf,
+}
+
+impl ArchiveManager {
+    pub async fn load() -> Result<Self, Error> {
+        let path_to_registry = path_to_archives().join(REGISTRY_FILE);
+        let archived: Vec<ArchivedInstance> = match tokio::fs::read(&path_to_registry).await {
+            Ok(content) => serde_json::from_slice(&content)
+                .context("Failed to deserialize archived instances json")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(e)
+                    .context(format!(
+                        "Failed to read archived instances file : {}",
+                        path_to_registry.display()
+                    ))
+                    .map_err(Into::into)
+            }
+        };
+        Ok(Self {
+            archived: archived
+                .into_iter()
+                .map(|archived| (archived.uuid.clone(), archived))
+                .collect(),
+            path_to_registry,
+        })
+    }
+
+    async fn write_to_file(&self) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(&self.path_to_registry)
+            .await
+            .context(format!(
+                "Failed to open/create json file {}",
+                &self.path_to_registry.display()
+            ))?;
+        file.write_all(
+            serde_json::to_string(&self.list())
+                .context("Failed to serialize archived instances json")?
+                .as_bytes(),
+        )
+        .await
+        .context("Failed to write to archived instances json")?;
+        Ok(())
+    }
+
+    /// Most recently archived first
+    pub fn list(&self) -> Vec<ArchivedInstance> {
+        let mut archived: Vec<_> = self.archived.values().cloned().collect();
+        archived.sort_by(|a, b| b.archived_at.cmp(&a.archived_at));
+        archived
+    }
+
+    pub fn get(&self, uuid: &InstanceUuid) -> Option<ArchivedInstance> {
+        self.archived.get(uuid).cloned()
+    }
+
+    pub async fn insert(&mut self, archived: ArchivedInstance) -> Result<(), Error> {
+        let old = self
+            .archived
+            .insert(archived.uuid.clone(), archived.clone());
+        if let Err(e) = self.write_to_file().await {
+            match old {
+                Some(old) => self.archived.insert(archived.uuid, old),
+                None => self.archived.remove(&archived.uuid),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub async fn remove(&mut self, uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(old) = self.archived.remove(uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.archived.insert(uuid.clone(), old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Archives are kept as files next to the instance directories of a storage location,
+/// or in the core's own archive directory for the default location
+pub fn archive_file(dir: &Path, uuid: &InstanceUuid) -> PathBuf {
+    dir.join(format!("{uuid}.lodestone_archive.tar.gz"))
+}
+
+/// Compresses `dir` into `dest`, returning the size of the archive
+pub fn compress(dir: &Path, dest: &Path) -> Result<u64, Error> {
+    let partial = dest.with_extension("part");
+    let result = (|| -> Result<u64, Error> {
+        let file = std::fs::File::create(&partial)
+            .context(format!("Failed to create archive {}", partial.display()))?;
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        builder.follow_symlinks(false);
+        builder
+            .append_dir_all(".", dir)
+            .context(format!("Failed to archive {}", dir.display()))?;
+        builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .context("Failed to finish archive")?;
+        std::fs::rename(&partial, dest)
+            .context(format!("Failed to move archive to {}", dest.display()))?;
+        Ok(std::fs::metadata(dest)
+            .context(format!("Failed to read metadata of {}", dest.display()))?
+            .len())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    result
+}
+
+pub fn extract(archive: &Path, dir: &Path) -> Result<(), Error> {
+    let file = std::fs::File::open(archive)
+        .context(format!("Failed to open archive {}", archive.display()))?;
+    std::fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+    // entries escaping the destination are skipped by unpack
+    tar::Archive::new(GzDecoder::new(file))
+        .unpack(dir)
+        .context(format!("Failed to extract archive {}", archive.display()))?;
+    Ok(())
+}
+
+/// Takes the stopped instance out of the instance list and compresses it in the background
+///
+/// `storage` is the location to keep the archive in, the core's archive directory if `None`.
+pub async fn archive_instance(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    storage: Option<String>,
+    caused_by: CausedBy,
+) -> Result<(), Error> {
+    let dest_dir = match storage.as_deref() {
+        None | Some(DEFAULT_STORAGE_LOCATION) => path_to_archives().clone(),
+        Some(id) => {
+            state
+                .global_settings
+                .lock()
+                .await
+                .storage_location(Some(id))?
+                .path
+        }
+    };
+    let mut instances = state.instances.lock().await;
+    let instance = instances.get(uuid).cloned().ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    if instance.state().await != State::Stopped {
+        return Err(Error::localized(
+            ErrorKind::BadRequest,
+            LocalizedMessage::new(MessageId::InstanceMustBeStopped),
+        ));
+    }
+    let path = instance.path().await;
+    let dot_lodestone_config: DotLodestoneConfig = serde_json::from_slice(
+        &tokio::fs::read(path.join(".lodestone_config"))
+            .await
+            .context("Failed to read .lodestone_config")?,
+    )
+    .context("Failed to parse .lodestone_config")?;
+    let archived = ArchivedInstance {
+        uuid: uuid.clone(),
+        name: instance.name().await,
+        game_type: dot_lodestone_config.game_type().clone(),
+        dir_name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| eyre!("Instance path {} has no name", path.display()))?,
+        storage_location: dot_lodestone_config.storage_location().to_string(),
+        archive_path: archive_file(&dest_dir, uuid),
+        size: 0,
+        archived_at: chrono::Utc::now().timestamp(),
+    };
+    if archived.archive_path.exists() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} already exists", archived.archive_path.display()),
+        });
+    }
+    // nothing can start the instance while it is being archived
+    instances.remove(uuid);
+    drop(instances);
+
+    let (progression_start, event_id) = Event::new_progression_event_start(
+        format!("Archiving instance {}", archived.name),
+        None,
+        None,
+        caused_by,
+    );
+    state.event_broadcaster.send(progression_start);
+    let state = state.clone();
+    tokio::spawn(async move {
+        let result: Result<ArchivedInstance, Error> = async {
+            crate::util::fs::create_dir_all(&dest_dir).await?;
+            let size = tokio::task::spawn_blocking({
+                let (path, dest) = (path.clone(), archived.archive_path.clone());
+                move || compress(&path, &dest)
+            })
+            .await
+            .context("Archive task panicked")??;
+            let archived = ArchivedInstance {
+                size,
+                ..archived.clone()
+            };
+            if let Err(e) = state.archives.lock().await.insert(archived.clone()).await {
+                let _ = tokio::fs::remove_file(&archived.archive_path).await;
+                return Err(e);
+            }
+            Ok(archived)
+        }
+        .await;
+        match result {
+            Ok(archived) => {
+                let mut port_manager = state.port_manager.lock().await;
+                port_manager.deallocate(instance.port().await);
+                if let Some(bedrock_port) = instance.bedrock_port().await {
+                    port_manager.deallocate(bedrock_port);
+                }
+                drop(port_manager);
+                state.health.lock().await.forget(&archived.uuid);
+                state.metrics_history.lock().await.forget(&archived.uuid);
+                state.summary_cache.lock().await.forget(&archived.uuid);
+                drop(instance);
+                if let Err(e) = crate::util::fs::remove_dir_all(&path).await {
+                    warn!("Failed to remove archived instance directory: {e}");
+                }
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some(&format!(
+                            "Instance archived to {}",
+                            archived.archive_path.display()
+                        )),
+                        None,
+                    ));
+            }
+            Err(e) => {
+                state
+                    .instances
+                    .lock()
+                    .await
+                    .insert(archived.uuid.clone(), instance);
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&format!("Failed to archive instance: {e}")),
+                        None,
+                    ));
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Extracts the archive back into the instance's storage location and loads the instance
+pub async fn restore_archived_instance(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    caused_by: CausedBy,
+) -> Result<(), Error> {
+    let archived = state.archives.lock().await.get(uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("No archived instance with this uuid"),
+    })?;
+    let location = state
+        .global_settings
+        .lock()
+        .await
+        .storage_location(Some(&archived.storage_location))
+        .unwrap_or_else(|e| {
+            warn!(
+                "Restoring {} to the default storage location: {e}",
+                archived.name
+            );
+            crate::storage::StorageLocation::default_location()
+        });
+    let path = location.path.join(&archived.dir_name);
+    if path.exists() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} already exists", path.display()),
+        });
+    }
+
+    let (progression_start, event_id) = Event::new_progression_event_start(
+        format!("Restoring archived instance {}", archived.name),
+        None,
+        None,
+        caused_by,
+    );
+    state.event_broadcaster.send(progression_start);
+    let state = state.clone();
+    tokio::spawn(async move {
+        let result: Result<GameInstance, Error> = async {
+            tokio::task::spawn_blocking({
+                let (archive, path) = (archived.archive_path.clone(), path.clone());
+                move || extract(&archive, &path)
+            })
+            .await
+            .context("Extract task panicked")??;
+            let dot_lodestone_config: DotLodestoneConfig = serde_json::from_slice(
+                &tokio::fs::read(path.join(".lodestone_config"))
+                    .await
+                    .context("Failed to read .lodestone_config")?,
+            )
+            .context("Failed to parse .lodestone_config")?;
+            let instance: GameInstance = match dot_lodestone_config.game_type() {
+                GameType::MinecraftJava => MinecraftInstance::restore(
+                    path.clone(),
+                    dot_lodestone_config,
+                    state.event_broadcaster.clone(),
+                    state.macro_executor.clone(),
+                )
+                .await?
+                .into(),
+                other => {
+                    return Err(Error {
+                        kind: ErrorKind::UnsupportedOperation,
+                        source: eyre!("Restoring {other:?} instances is not supported"),
+                    })
+                }
+            };
+            state.archives.lock().await.remove(&archived.uuid).await?;
+            Ok(instance)
+        }
+        .await;
+        match result {
+            Ok(instance) => {
+                let mut port_manager = state.port_manager.lock().await;
+                port_manager.add_port(instance.port().await);
+                if let Some(bedrock_port) = instance.bedrock_port().await {
+                    port_manager.add_port(bedrock_port);
+                }
+                drop(port_manager);
+                state
+                    .instances
+                    .lock()
+                    .await
+                    .insert(archived.uuid.clone(), instance);
+                if let Err(e) = crate::util::fs::remove_file(&archived.archive_path).await {
+                    warn!("Failed to remove restored archive: {e}");
+                }
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some("Archived instance restored"),
+                        None,
+                    ));
+            }
+            Err(e) => {
+                // the archive is still there to try again
+                let _ = crate::util::fs::remove_dir_all(&path).await;
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&format!("Failed to restore archived instance: {e}")),
+                        None,
+                    ));
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_and_extract() {
+        let temp_dir = tempdir::TempDir::new("test_archive").unwrap();
+        let instance_dir = temp_dir.path().join("survival");
+        std::fs::create_dir_all(instance_dir.join("world")).unwrap();
+        std::fs::write(instance_dir.join(".lodestone_config"), "{}").unwrap();
+        std::fs::write(instance_dir.join("world").join("level.dat"), [1, 2, 3]).unwrap();
+
+        let dest = archive_file(temp_dir.path(), &InstanceUuid::default());
+        let size = compress(&instance_dir, &dest).unwrap();
+        assert_eq!(size, std::fs::metadata(&dest).unwrap().len());
+        assert!(!dest.with_extension("part").exists());
+
+        let restored = temp_dir.path().join("restored");
+        extract(&dest, &restored).unwrap();
+        assert_eq!(
+            std::fs::read(restored.join("world").join("level.dat")).unwrap(),
+            vec![1, 2, 3]
+        );
+        assert!(restored.join(".lodestone_config").exists());
+    }
+}
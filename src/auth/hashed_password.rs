@@ -1,18 +1,111 @@
-use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use color_eyre::eyre::eyre;
 use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
+use crate::error::{Error, ErrorKind};
+
+/// Argon2id cost used for new hashes.
+///
+/// Every hash records the parameters it was made with, so changing these only affects
+/// passwords set afterwards, and existing ones as their users log in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PasswordHashing {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+/// Logins shouldn't be able to exhaust the machine's memory
+const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+
+impl Default for PasswordHashing {
+    // OWASP's recommended minimum for Argon2id
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl PasswordHashing {
+    fn params(&self) -> Result<Params, Error> {
+        Params::new(self.memory_kib, self.iterations, self.parallelism, None).map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid password hashing parameters: {e}"),
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.memory_kib > MAX_MEMORY_KIB {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Password hashing can use at most {MAX_MEMORY_KIB} KiB of memory"),
+            });
+        }
+        self.params().map(|_| ())
+    }
+
+    fn hasher(&self) -> Argon2<'static> {
+        Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            self.params().unwrap_or_default(),
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(transparent)]
 #[ts(export)]
 pub struct HashedPassword(String);
 
+impl HashedPassword {
+    /// Whether the hash wasn't made with Argon2id and the current `hashing` parameters
+    pub fn needs_rehash(&self, hashing: &PasswordHashing) -> bool {
+        let hash = match PasswordHash::new(&self.0) {
+            Ok(hash) => hash,
+            Err(_) => return true,
+        };
+        if hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13 as u32)
+        {
+            return true;
+        }
+        match Params::try_from(&hash) {
+            Ok(params) => {
+                params.m_cost() != hashing.memory_kib
+                    || params.t_cost() != hashing.iterations
+                    || params.p_cost() != hashing.parallelism
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+impl HashedPassword {
+    /// Whether `password` matches, false if the stored hash is malformed
+    pub fn verify(&self, password: &str) -> bool {
+        // the algorithm and parameters are read from the hash itself
+        PasswordHash::new(&self.0).map_or(false, |hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+}
+
 impl PartialEq<str> for HashedPassword {
     fn eq(&self, other: &str) -> bool {
-        Argon2::default()
-            .verify_password(other.as_bytes(), &PasswordHash::new(&self.0).unwrap())
-            .is_ok()
+        self.verify(other)
     }
 }
 
@@ -34,9 +127,10 @@ impl AsRef<str> for HashedPassword {
     }
 }
 
-pub fn hash_password(password: impl AsRef<str>) -> HashedPassword {
+pub fn hash_password(password: impl AsRef<str>, hashing: &PasswordHashing) -> HashedPassword {
     HashedPassword(
-        Argon2::default()
+        hashing
+            .hasher()
             .hash_password(
                 password.as_ref().as_bytes(),
                 &SaltString::generate(&mut OsRng),
@@ -45,3 +139,30 @@ pub fn hash_password(password: impl AsRef<str>) -> HashedPassword {
             .to_string(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needs_rehash() {
+        let weak = PasswordHashing {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let hashed = hash_password("12345", &weak);
+        assert!(!hashed.needs_rehash(&weak));
+        assert!(hashed.needs_rehash(&PasswordHashing::default()));
+        // still verifiable after the parameters change
+        assert!(hashed == *"12345");
+
+        let argon2i = HashedPassword(
+            Argon2::new(Algorithm::Argon2i, Version::V0x13, weak.params().unwrap())
+                .hash_password(b"12345", &SaltString::generate(&mut OsRng))
+                .unwrap()
+                .to_string(),
+        );
+        assert!(argon2i.needs_rehash(&weak));
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::util::rand_alphanumeric;
+
+use super::user_id::UserId;
+
+const IMPERSONATION_TTL_SECS: i64 = 60 * 60;
+
+/// An admin signed in as another user, for support and debugging
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ImpersonationSession {
+    pub session_id: String,
+    pub impersonator: UserId,
+    pub impersonator_name: String,
+    pub target: UserId,
+    pub target_name: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+/// Sessions live in memory only, so restarting the core ends all of them
+#[derive(Clone, Default)]
+pub struct Impersonations {
+    sessions: HashMap<String, ImpersonationSession>,
+}
+
+impl Impersonations {
+    pub fn start(
+        &mut self,
+        impersonator: (UserId, String),
+        target: (UserId, String),
+        now: i64,
+    ) -> ImpersonationSession {
+        self.sessions.retain(|_, session| session.expires_at > now);
+        let session = ImpersonationSession {
+            session_id: rand_alphanumeric(32),
+            impersonator: impersonator.0,
+            impersonator_name: impersonator.1,
+            target: target.0,
+            target_name: target.1,
+            created_at: now,
+            expires_at: now + IMPERSONATION_TTL_SECS,
+        };
+        self.sessions
+            .insert(session.session_id.clone(), session.clone());
+        session
+    }
+
+    pub fn is_active(&self, session_id: &str, now: i64) -> bool {
+        self.sessions
+            .get(session_id)
+            .map_or(false, |session| session.expires_at > now)
+    }
+
+    pub fn list(&self, now: i64) -> Vec<ImpersonationSession> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .values()
+            .filter(|session| session.expires_at > now)
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|session| session.created_at);
+        sessions
+    }
+
+    pub fn revoke(&mut self, session_id: &str) -> Option<ImpersonationSession> {
+        self.sessions.remove(session_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_impersonation_expiry_and_revoke() {
+        let mut impersonations = Impersonations::default();
+        let admin = (UserId::default(), "admin".to_string());
+        let user = (UserId::default(), "user".to_string());
+        let session = impersonations.start(admin.clone(), user.clone(), 0);
+        assert!(impersonations.is_active(&session.session_id, 10));
+        assert!(!impersonations.is_active(&session.session_id, IMPERSONATION_TTL_SECS));
+
+        let other = impersonations.start(admin, user, IMPERSONATION_TTL_SECS);
+        // starting a session clears out the expired ones
+        assert_eq!(
+            impersonations.list(IMPERSONATION_TTL_SECS),
+            vec![other.clone()]
+        );
+        assert!(impersonations.revoke(&other.session_id).is_some());
+        assert!(!impersonations.is_active(&other.session_id, IMPERSONATION_TTL_SECS));
+    }
+}
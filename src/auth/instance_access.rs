@@ -0,0 +1,135 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{error::Error, types::InstanceUuid, AppState};
+
+use super::{
+    user::{User, UserAction},
+    user_id::UserId,
+};
+
+/// Who an instance belongs to and who it is shared with.
+///
+/// This is on top of the permissions of each user, it never takes anything away
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct InstanceAccess {
+    pub owner: Option<UserId>,
+    pub access_list: HashSet<UserId>,
+}
+
+impl InstanceAccess {
+    pub fn new(owner: UserId) -> Self {
+        Self {
+            owner: Some(owner),
+            access_list: HashSet::new(),
+        }
+    }
+
+    pub fn is_owned_by(&self, uid: &UserId) -> bool {
+        self.owner.as_ref() == Some(uid)
+    }
+
+    /// Whether owning the instance or being on its access list allows `action`.
+    ///
+    /// Resources and macros stay out of reach, as with regular permissions
+    /// they have to be granted explicitly
+    pub fn grants(&self, uid: &UserId, action: &UserAction) -> bool {
+        if self.is_owned_by(uid) {
+            matches!(
+                action,
+                UserAction::ViewInstance(_)
+                    | UserAction::StartInstance(_)
+                    | UserAction::StopInstance(_)
+                    | UserAction::ReadConsole(_)
+                    | UserAction::WriteConsole(_)
+                    | UserAction::MonitorInstance(_)
+                    | UserAction::AccessSetting(_)
+                    | UserAction::ReadResource(_)
+                    | UserAction::ReadInstanceFile(_)
+                    | UserAction::WriteInstanceFile(_)
+            )
+        } else if self.access_list.contains(uid) {
+            matches!(
+                action,
+                UserAction::ViewInstance(_)
+                    | UserAction::StartInstance(_)
+                    | UserAction::StopInstance(_)
+                    | UserAction::ReadConsole(_)
+                    | UserAction::WriteConsole(_)
+                    | UserAction::MonitorInstance(_)
+                    | UserAction::ReadResource(_)
+                    | UserAction::ReadInstanceFile(_)
+            )
+        } else {
+            false
+        }
+    }
+}
+
+impl AppState {
+    pub async fn instance_access(&self, uuid: &InstanceUuid) -> Option<InstanceAccess> {
+        self.global_settings.lock().await.instance_access(uuid)
+    }
+
+    pub async fn all_instance_access(&self) -> HashMap<InstanceUuid, InstanceAccess> {
+        self.global_settings
+            .lock()
+            .await
+            .as_ref()
+            .instance_access
+            .clone()
+    }
+
+    /// Like `User::can_perform_action`, also taking into account who owns the instance involved
+    pub async fn can_perform_action(&self, user: &User, action: &UserAction) -> bool {
+        user.can_perform_action_with(
+            action,
+            &self.global_settings.lock().await.as_ref().instance_access,
+        )
+    }
+
+    pub async fn try_action(&self, user: &User, action: &UserAction) -> Result<(), Error> {
+        if self.can_perform_action(user, action).await {
+            Ok(())
+        } else {
+            user.try_action(action)
+        }
+    }
+
+    /// Instance owners and admins can change who the instance is shared with
+    pub async fn can_manage_access(&self, user: &User, uuid: &InstanceUuid) -> bool {
+        user.is_owner
+            || user.is_admin
+            || self
+                .instance_access(uuid)
+                .await
+                .map_or(false, |access| access.is_owned_by(&user.uid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grants() {
+        let owner = UserId::default();
+        let friend = UserId::default();
+        let stranger = UserId::default();
+        let uuid = InstanceUuid::default();
+        let mut access = InstanceAccess::new(owner.clone());
+        access.access_list.insert(friend.clone());
+
+        let setting = UserAction::AccessSetting(uuid.clone());
+        let start = UserAction::StartInstance(uuid.clone());
+        let macros = UserAction::AccessMacro(Some(uuid));
+        assert!(access.grants(&owner, &setting));
+        assert!(!access.grants(&owner, &macros));
+        assert!(access.grants(&friend, &start));
+        assert!(!access.grants(&friend, &setting));
+        assert!(!access.grants(&stranger, &start));
+    }
+}
@@ -0,0 +1,184 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    util::rand_alphanumeric,
+};
+
+use super::{permission::UserPermission, user_id::UserId};
+
+/// A code that lets someone create their own account, with the role picked by whoever made the invite
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct Invite {
+    pub code: String,
+    pub is_admin: bool,
+    pub permissions: UserPermission,
+    pub created_by: UserId,
+    pub creation_time: i64,
+    /// Unix timestamp after which the invite can no longer be redeemed, `None` for never
+    pub expiry: Option<i64>,
+    /// `None` for unlimited
+    pub max_uses: Option<u32>,
+    /// The users created with this invite
+    pub redeemed_by: Vec<UserId>,
+}
+
+impl Invite {
+    pub fn new(
+        created_by: UserId,
+        is_admin: bool,
+        permissions: UserPermission,
+        expiry: Option<i64>,
+        max_uses: Option<u32>,
+    ) -> Self {
+        Self {
+            code: rand_alphanumeric(16),
+            is_admin,
+            permissions,
+            created_by,
+            creation_time: chrono::Utc::now().timestamp(),
+            expiry,
+            max_uses,
+            redeemed_by: Vec::new(),
+        }
+    }
+
+    pub fn is_redeemable(&self, now: i64) -> bool {
+        self.expiry.map_or(true, |expiry| now < expiry)
+            && self
+                .max_uses
+                .map_or(true, |max_uses| (self.redeemed_by.len() as u32) < max_uses)
+    }
+}
+
+pub struct InvitesManager {
+    invites: HashMap<String, Invite>,
+    path_to_invites: PathBuf,
+}
+
+impl InvitesManager {
+    pub async fn load(path_to_invites: PathBuf) -> Result<Self, Error> {
+        let invites = match tokio::fs::read(&path_to_invites).await {
+            Ok(content) => {
+                serde_json::from_slice(&content).context("Failed to deserialize invite json")?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                return Err(e)
+                    .context(format!(
+                        "Failed to read invite file : {}",
+                        path_to_invites.display()
+                    ))
+                    .map_err(Into::into)
+            }
+        };
+        Ok(Self {
+            invites,
+            path_to_invites,
+        })
+    }
+
+    async fn write_to_file(&self) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(&self.path_to_invites)
+            .await
+            .context(format!(
+                "Failed to open/create json file {}",
+                &self.path_to_invites.display()
+            ))?;
+        file.write_all(
+            serde_json::to_string(&self.invites)
+                .context("Failed to serialize invite json")?
+                .as_bytes(),
+        )
+        .await
+        .context("Failed to write to invite json")?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<Invite> {
+        let mut invites: Vec<Invite> = self.invites.values().cloned().collect();
+        invites.sort_by_key(|invite| invite.creation_time);
+        invites
+    }
+
+    pub async fn add(&mut self, invite: Invite) -> Result<(), Error> {
+        let code = invite.code.clone();
+        self.invites.insert(code.clone(), invite);
+        if let Err(e) = self.write_to_file().await {
+            self.invites.remove(&code);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub async fn delete(&mut self, code: &str) -> Result<Option<Invite>, Error> {
+        let invite = self.invites.remove(code);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(invite) = invite {
+                self.invites.insert(code.to_string(), invite);
+            }
+            return Err(e);
+        }
+        Ok(invite)
+    }
+
+    /// The invite behind `code`, if it can still be redeemed
+    pub fn get_redeemable(&self, code: &str) -> Result<Invite, Error> {
+        self.invites
+            .get(code)
+            .filter(|invite| invite.is_redeemable(chrono::Utc::now().timestamp()))
+            .cloned()
+            .ok_or_else(|| {
+                Error::localized(
+                    ErrorKind::PermissionDenied,
+                    LocalizedMessage::new(MessageId::InvalidInvite),
+                )
+            })
+    }
+
+    /// Records that `uid` was created with the invite behind `code`
+    pub async fn mark_redeemed(&mut self, code: &str, uid: UserId) -> Result<(), Error> {
+        let invite = match self.invites.get_mut(code) {
+            Some(invite) => invite,
+            None => return Ok(()),
+        };
+        invite.redeemed_by.push(uid);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(invite) = self.invites.get_mut(code) {
+                invite.redeemed_by.pop();
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_redeemable() {
+        let mut invite = Invite::new(
+            UserId::default(),
+            false,
+            UserPermission::default(),
+            Some(1000),
+            Some(1),
+        );
+        assert!(invite.is_redeemable(999));
+        assert!(!invite.is_redeemable(1000));
+        invite.redeemed_by.push(UserId::default());
+        assert!(!invite.is_redeemable(999));
+        invite.max_uses = None;
+        invite.expiry = None;
+        assert!(invite.is_redeemable(i64::MAX));
+    }
+}
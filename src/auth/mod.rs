@@ -1,5 +1,9 @@
 pub mod hashed_password;
+pub mod impersonation;
+pub mod instance_access;
+pub mod invite;
 pub mod jwt_token;
+pub mod password_reset;
 pub mod permission;
 pub mod user;
 pub mod user_id;
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use crate::util::rand_alphanumeric;
+
+use super::user_id::UserId;
+
+/// How long a reset token stays valid unless told otherwise, in seconds
+pub const DEFAULT_RESET_TOKEN_LIFETIME: i64 = 60 * 60;
+
+struct PendingReset {
+    uid: UserId,
+    expiry: i64,
+}
+
+/// One-time tokens that let a user set a new password without knowing the old one.
+///
+/// Only hashes are kept, and only in memory, so a restart invalidates every pending reset
+#[derive(Default)]
+pub struct PasswordResetManager {
+    pending: HashMap<String, PendingReset>,
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl PasswordResetManager {
+    /// Returns a new token for `uid`, replacing any the user already had
+    pub fn issue(&mut self, uid: UserId, expiry: i64) -> String {
+        self.pending.retain(|_, reset| reset.uid != uid);
+        let token = rand_alphanumeric(32);
+        self.pending
+            .insert(hash_token(&token), PendingReset { uid, expiry });
+        token
+    }
+
+    /// Consumes `token`, returning who it was issued for if it is still valid
+    pub fn redeem(&mut self, token: &str, now: i64) -> Option<UserId> {
+        self.pending.retain(|_, reset| now < reset.expiry);
+        self.pending
+            .remove(&hash_token(token))
+            .map(|reset| reset.uid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reset_tokens() {
+        let mut manager = PasswordResetManager::default();
+        let uid = UserId::default();
+        let first = manager.issue(uid.clone(), 100);
+        let second = manager.issue(uid.clone(), 100);
+        // only the latest token is valid, and only once
+        assert_eq!(manager.redeem(&first, 0), None);
+        assert_eq!(manager.redeem(&second, 0), Some(uid.clone()));
+        assert_eq!(manager.redeem(&second, 0), None);
+        let expired = manager.issue(uid, 100);
+        assert_eq!(manager.redeem(&expired, 100), None);
+    }
+}
@@ -1,23 +1,29 @@
 use std::{collections::HashMap, path::PathBuf};
 
 use argon2::{Argon2, PasswordVerifier};
-use color_eyre::eyre::{eyre, Context};
+use color_eyre::{
+    eyre::{eyre, Context},
+    Report,
+};
 use jsonwebtoken::{Algorithm, Validation};
+use lodestone_types::LoginReply;
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncWriteExt;
+use tokio::{io::AsyncWriteExt, sync::RwLock};
 use tracing::warn;
-use ts_rs::TS;
 
 use crate::{
     error::{Error, ErrorKind},
     event_broadcaster::EventBroadcaster,
     events::{CausedBy, Event, EventInner, UserEvent, UserEventInner},
+    i18n::{Locale, LocalizedMessage, MessageId},
     types::{InstanceUuid, Snowflake},
 };
 
 use super::{
-    hashed_password::{hash_password, HashedPassword},
-    jwt_token::JwtToken,
+    hashed_password::{hash_password, HashedPassword, PasswordHashing},
+    impersonation::{ImpersonationSession, Impersonations},
+    instance_access::InstanceAccess,
+    jwt_token::{encode_jwt_token, JwtToken},
     permission::UserPermission,
     user_id::UserId,
     user_secrets::UserSecret,
@@ -27,6 +33,11 @@ use super::{
 pub struct Claim {
     pub uid: UserId,
     pub exp: usize,
+    /// Set on tokens an admin got to act as this user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<UserId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonation_session: Option<String>,
 }
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct User {
@@ -36,7 +47,18 @@ pub struct User {
     pub is_owner: bool,
     pub is_admin: bool,
     pub permissions: UserPermission,
+    /// Missing from core backups, a fresh one is made when they are restored
+    #[serde(default)]
     pub secret: UserSecret,
+    /// Preferred language, `None` to follow the client's `Accept-Language`
+    #[serde(default)]
+    pub locale: Option<Locale>,
+    /// Where password reset links are sent, if the core can send email
+    #[serde(default)]
+    pub email: Option<String>,
+    /// The admin signed in as this user, set on users authenticated with an impersonation token
+    #[serde(skip)]
+    pub impersonated_by: Option<UserId>,
 }
 
 impl User {
@@ -46,17 +68,45 @@ impl User {
         is_owner: bool,
         is_admin: bool,
         permissions: UserPermission,
+        password_hashing: &PasswordHashing,
     ) -> Self {
         User {
             uid: UserId::default(),
             username,
-            hashed_psw: hash_password(password),
+            hashed_psw: hash_password(password, password_hashing),
             is_owner,
             is_admin,
             permissions,
             secret: UserSecret::default(),
+            locale: None,
+            email: None,
+            impersonated_by: None,
         }
     }
+    /// Admins can do anything the owner can, except delete the owner or hand over ownership
+    pub fn has_owner_rights(&self) -> bool {
+        self.is_owner || self.is_admin
+    }
+    /// For routes only owners and admins can use
+    pub fn try_owner_rights(&self) -> Result<(), Error> {
+        if !self.has_owner_rights() {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Only owners and admins can do this"),
+            });
+        }
+        Ok(())
+    }
+    /// Refuses changes to credentials during an impersonation, which would outlast the session
+    pub fn try_not_impersonated(&self) -> Result<(), Error> {
+        if self.impersonated_by.is_some() {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("This can't be done while signed in as another user"),
+            });
+        }
+        Ok(())
+    }
     fn get_permission_level(&self) -> u8 {
         if self.is_owner {
             u8::MAX
@@ -66,6 +116,11 @@ impl User {
             1
         }
     }
+    /// Whether this user may act on `other`'s account, e.g. reset their password
+    pub fn can_manage(&self, other: &User) -> bool {
+        self.has_owner_rights() && self.get_permission_level() > other.get_permission_level()
+    }
+
     pub fn update_permission(
         &self,
         other: &mut User,
@@ -77,7 +132,7 @@ impl User {
                 source: eyre!("You don't have permission to manage other users' permission"),
             });
         }
-        if self.is_owner {
+        if self.has_owner_rights() {
             other.permissions = permissions;
             Ok(())
         } else {
@@ -94,7 +149,7 @@ impl User {
                         "Unsafe and owner exclusive permissions can only be granted by the owner"
                     ),
                 })
-            } else if self.is_admin || self.permissions.can_manage_permission {
+            } else if self.permissions.can_manage_permission {
                 other.permissions = permissions;
                 Ok(())
             } else {
@@ -107,47 +162,49 @@ impl User {
     }
 
     pub fn can_perform_action(&self, action: &UserAction) -> bool {
-        if self.is_owner {
+        if self.has_owner_rights() {
             return true;
         }
         match action {
             UserAction::ViewInstance(instance_id) => {
-                self.is_admin || self.permissions.can_view_instance.contains(instance_id)
+                self.permissions.can_view_instance.contains(instance_id)
             }
             UserAction::StartInstance(instance_id) => {
-                self.is_admin || self.permissions.can_start_instance.contains(instance_id)
+                self.permissions.can_start_instance.contains(instance_id)
             }
             UserAction::StopInstance(instance_id) => {
-                self.is_admin || self.permissions.can_stop_instance.contains(instance_id)
-            }
-            UserAction::AccessConsole(instance_id) => {
-                self.is_admin
-                    || self
-                        .permissions
-                        .can_access_instance_console
-                        .contains(instance_id)
+                self.permissions.can_stop_instance.contains(instance_id)
             }
-            UserAction::AccessSetting(instance_id) => {
-                self.is_admin
+            UserAction::ReadConsole(instance_id) => {
+                self.permissions
+                    .can_read_instance_console
+                    .contains(instance_id)
                     || self
                         .permissions
-                        .can_access_instance_setting
+                        .can_write_instance_console
                         .contains(instance_id)
             }
-            UserAction::ReadResource(instance_id) => {
-                self.is_admin
-                    || self
-                        .permissions
-                        .can_read_instance_resource
-                        .contains(instance_id)
+            UserAction::WriteConsole(instance_id) => self
+                .permissions
+                .can_write_instance_console
+                .contains(instance_id),
+            UserAction::MonitorInstance(instance_id) => {
+                self.permissions.can_monitor_instance.contains(instance_id)
             }
+            UserAction::AccessSetting(instance_id) => self
+                .permissions
+                .can_access_instance_setting
+                .contains(instance_id),
+            UserAction::ReadResource(instance_id) => self
+                .permissions
+                .can_read_instance_resource
+                .contains(instance_id),
             UserAction::WriteResource(instance_id) => self
                 .permissions
                 .can_write_instance_resource
                 .contains(instance_id),
             UserAction::ReadInstanceFile(instance_id) => {
-                self.is_admin
-                    || self.permissions.can_read_global_file
+                self.permissions.can_read_global_file
                     || self
                         .permissions
                         .can_read_instance_file
@@ -166,11 +223,11 @@ impl User {
                 .contains(instance_id),
             // TODO(CheatCod3): check if the macro is global
             UserAction::AccessMacro(None) => false,
-            UserAction::CreateInstance => self.is_admin || self.permissions.can_create_instance,
-            UserAction::DeleteInstance => self.is_admin || self.permissions.can_delete_instance,
+            UserAction::CreateInstance => self.permissions.can_create_instance,
+            UserAction::DeleteInstance => self.permissions.can_delete_instance,
             UserAction::ReadGlobalFile => self.permissions.can_read_global_file,
             UserAction::WriteGlobalFile => self.permissions.can_write_global_file,
-            UserAction::ManageUser => self.is_owner,
+            UserAction::ManageUser => false,
             UserAction::ManagePermission => self.permissions.can_manage_permission,
         }
     }
@@ -181,68 +238,100 @@ impl User {
         } else {
             Err(Error {
                 kind: ErrorKind::PermissionDenied,
-                source: match action {
-                    UserAction::ViewInstance(_) => {
-                        eyre!("You don't have permission to view this instance")
-                    }
-                    UserAction::StartInstance(_) => {
-                        eyre!("You don't have permission to start this instance")
-                    }
-                    UserAction::StopInstance(_) => {
-                        eyre!("You don't have permission to stop this instance")
-                    }
-                    UserAction::AccessConsole(_) => {
-                        eyre!("You don't have permission to access this instance's console")
-                    }
-                    UserAction::AccessSetting(_) => {
-                        eyre!("You don't have permission to access this instance's setting")
-                    }
-                    UserAction::ReadResource(_) => {
-                        eyre!("You don't have permission to read this instance's resource")
-                    }
-                    UserAction::WriteResource(_) => {
-                        eyre!("You don't have permission to write this instance's resource")
-                    }
-                    UserAction::AccessMacro(_) => {
-                        eyre!("You don't have permission to access this instance's macro")
-                    }
-                    UserAction::ReadInstanceFile(_) => {
-                        eyre!("You don't have permission to read this instance's file")
-                    }
-                    UserAction::WriteInstanceFile(_) => {
-                        eyre!("You don't have permission to write this instance's file")
-                    }
-                    UserAction::CreateInstance => {
-                        eyre!("You don't have permission to create instance")
-                    }
-                    UserAction::DeleteInstance => {
-                        eyre!("You don't have permission to delete instance")
-                    }
-                    UserAction::ReadGlobalFile => {
-                        eyre!("You don't have permission to read global file")
-                    }
-                    UserAction::WriteGlobalFile => {
-                        eyre!("You don't have permission to write global file")
-                    }
-                    UserAction::ManageUser => eyre!("You don't have permission to manage user"),
-                    UserAction::ManagePermission => {
-                        eyre!("You don't have permission to manage permission")
-                    }
-                },
+                // the specific reason is kept as context on top of the translatable message
+                source: Report::msg(LocalizedMessage::new(MessageId::PermissionDenied)).wrap_err(
+                    match action {
+                        UserAction::ViewInstance(_) => {
+                            "You don't have permission to view this instance"
+                        }
+                        UserAction::StartInstance(_) => {
+                            "You don't have permission to start this instance"
+                        }
+                        UserAction::StopInstance(_) => {
+                            "You don't have permission to stop this instance"
+                        }
+                        UserAction::ReadConsole(_) => {
+                            "You don't have permission to read this instance's console"
+                        }
+                        UserAction::WriteConsole(_) => {
+                            "You don't have permission to send commands to this instance"
+                        }
+                        UserAction::MonitorInstance(_) => {
+                            "You don't have permission to monitor this instance"
+                        }
+                        UserAction::AccessSetting(_) => {
+                            "You don't have permission to access this instance's setting"
+                        }
+                        UserAction::ReadResource(_) => {
+                            "You don't have permission to read this instance's resource"
+                        }
+                        UserAction::WriteResource(_) => {
+                            "You don't have permission to write this instance's resource"
+                        }
+                        UserAction::AccessMacro(_) => {
+                            "You don't have permission to access this instance's macro"
+                        }
+                        UserAction::ReadInstanceFile(_) => {
+                            "You don't have permission to read this instance's file"
+                        }
+                        UserAction::WriteInstanceFile(_) => {
+                            "You don't have permission to write this instance's file"
+                        }
+                        UserAction::CreateInstance => {
+                            "You don't have permission to create instance"
+                        }
+                        UserAction::DeleteInstance => {
+                            "You don't have permission to delete instance"
+                        }
+                        UserAction::ReadGlobalFile => {
+                            "You don't have permission to read global file"
+                        }
+                        UserAction::WriteGlobalFile => {
+                            "You don't have permission to write global file"
+                        }
+                        UserAction::ManageUser => "You don't have permission to manage user",
+                        UserAction::ManagePermission => {
+                            "You don't have permission to manage permission"
+                        }
+                    },
+                ),
             })
         }
     }
 
-    pub fn can_view_event(&self, event: impl AsRef<Event>) -> bool {
+    /// Like `can_perform_action`, also taking into account who owns and shares the instance involved
+    pub fn can_perform_action_with(
+        &self,
+        action: &UserAction,
+        access: &HashMap<InstanceUuid, InstanceAccess>,
+    ) -> bool {
+        self.can_perform_action(action)
+            || action
+                .instance_uuid()
+                .and_then(|uuid| access.get(uuid))
+                .map_or(false, |access| access.grants(&self.uid, action))
+    }
+
+    pub fn can_view_event(
+        &self,
+        event: impl AsRef<Event>,
+        access: &HashMap<InstanceUuid, InstanceAccess>,
+    ) -> bool {
         match &event.as_ref().event_inner {
-            EventInner::InstanceEvent(event) => {
-                self.can_perform_action(&UserAction::ViewInstance(event.instance_uuid.clone()))
+            EventInner::InstanceEvent(instance_event) => {
+                let uuid = instance_event.instance_uuid.clone();
+                if event.as_ref().is_event_console_message() {
+                    self.can_perform_action_with(&UserAction::ReadConsole(uuid), access)
+                } else {
+                    self.can_perform_action_with(&UserAction::ViewInstance(uuid), access)
+                }
             }
             EventInner::UserEvent(_event) => self.can_perform_action(&UserAction::ManageUser),
             EventInner::FSEvent(_) => self.can_perform_action(&UserAction::ManageUser),
-            EventInner::MacroEvent(macro_event) => {
-                self.can_perform_action(&UserAction::AccessMacro(macro_event.instance_uuid.clone()))
-            }
+            EventInner::MacroEvent(macro_event) => self.can_perform_action_with(
+                &UserAction::AccessMacro(macro_event.instance_uuid.clone()),
+                access,
+            ),
             // TODO!,
             EventInner::ProgressionEvent(_progression_event) => true,
         }
@@ -256,9 +345,11 @@ impl User {
         let claim = Claim {
             uid: self.uid.clone(),
             exp: exp as usize,
+            impersonated_by: None,
+            impersonation_session: None,
         };
 
-        JwtToken::new(claim, self.secret.clone())
+        encode_jwt_token(claim, self.secret.clone())
     }
 }
 
@@ -267,7 +358,9 @@ pub enum UserAction {
     ViewInstance(InstanceUuid),
     StartInstance(InstanceUuid),
     StopInstance(InstanceUuid),
-    AccessConsole(InstanceUuid),
+    ReadConsole(InstanceUuid),
+    WriteConsole(InstanceUuid),
+    MonitorInstance(InstanceUuid),
     AccessSetting(InstanceUuid),
     ReadResource(InstanceUuid),
     WriteResource(InstanceUuid),
@@ -284,16 +377,34 @@ pub enum UserAction {
     ManagePermission,
 }
 
-#[derive(Serialize, Deserialize, Clone, TS)]
-#[ts(export)]
-pub struct PublicUser {
-    pub uid: UserId,
-    pub username: String,
-    pub is_owner: bool,
-    pub is_admin: bool,
-    pub permissions: UserPermission,
+impl UserAction {
+    /// The instance this action is about, if any
+    pub fn instance_uuid(&self) -> Option<&InstanceUuid> {
+        match self {
+            UserAction::ViewInstance(uuid)
+            | UserAction::StartInstance(uuid)
+            | UserAction::StopInstance(uuid)
+            | UserAction::ReadConsole(uuid)
+            | UserAction::WriteConsole(uuid)
+            | UserAction::MonitorInstance(uuid)
+            | UserAction::AccessSetting(uuid)
+            | UserAction::ReadResource(uuid)
+            | UserAction::WriteResource(uuid)
+            | UserAction::ReadInstanceFile(uuid)
+            | UserAction::WriteInstanceFile(uuid) => Some(uuid),
+            UserAction::AccessMacro(uuid) => uuid.as_ref(),
+            UserAction::CreateInstance
+            | UserAction::DeleteInstance
+            | UserAction::ReadGlobalFile
+            | UserAction::WriteGlobalFile
+            | UserAction::ManageUser
+            | UserAction::ManagePermission => None,
+        }
+    }
 }
 
+pub use lodestone_types::PublicUser;
+
 impl From<&User> for PublicUser {
     fn from(user: &User) -> Self {
         PublicUser {
@@ -302,6 +413,8 @@ impl From<&User> for PublicUser {
             is_owner: user.is_owner,
             is_admin: user.is_admin,
             permissions: user.permissions.clone(),
+            locale: user.locale,
+            email: user.email.clone(),
         }
     }
 }
@@ -314,6 +427,8 @@ impl From<User> for PublicUser {
             is_owner: user.is_owner,
             is_admin: user.is_admin,
             permissions: user.permissions,
+            locale: user.locale,
+            email: user.email,
         }
     }
 }
@@ -323,6 +438,9 @@ pub struct UsersManager {
     event_broadcaster: EventBroadcaster,
     users: HashMap<UserId, User>,
     path_to_users: PathBuf,
+    /// Mirrors the core setting, so logins can upgrade outdated hashes
+    password_hashing: PasswordHashing,
+    impersonations: Impersonations,
 }
 
 impl UsersManager {
@@ -335,8 +453,16 @@ impl UsersManager {
             event_broadcaster,
             users,
             path_to_users,
+            password_hashing: PasswordHashing::default(),
+            impersonations: Impersonations::default(),
         }
     }
+    pub fn password_hashing(&self) -> PasswordHashing {
+        self.password_hashing
+    }
+    pub fn set_password_hashing(&mut self, password_hashing: PasswordHashing) {
+        self.password_hashing = password_hashing;
+    }
     pub async fn load_users(&mut self) -> Result<(), Error> {
         if tokio::fs::OpenOptions::new()
             .read(true)
@@ -393,6 +519,15 @@ impl UsersManager {
         .context("Failed to write to user json".to_string())?;
         Ok(())
     }
+    /// Replaces all users at once, when restoring a core backup
+    pub async fn restore_users(&mut self, users: HashMap<UserId, User>) -> Result<(), Error> {
+        let old_users = std::mem::replace(&mut self.users, users);
+        if let Err(e) = self.write_to_file().await {
+            self.users = old_users;
+            return Err(e);
+        }
+        Ok(())
+    }
     pub fn get_user(&self, uid: impl AsRef<UserId>) -> Option<User> {
         self.users.get(uid.as_ref()).cloned()
     }
@@ -541,6 +676,69 @@ impl UsersManager {
         }
     }
 
+    pub async fn set_locale(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        locale: Option<Locale>,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            )
+        })?;
+        let old_locale = std::mem::replace(&mut user.locale, locale);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                user.locale = old_locale;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub async fn set_email(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        email: Option<String>,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            )
+        })?;
+        let old_email = std::mem::replace(&mut user.email, email);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                user.email = old_email;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Sets a new password without checking the old one and logs the user out everywhere
+    pub async fn reset_password(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        password: String,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        self.change_password(uid.as_ref(), None::<&str>, password, caused_by.clone())
+            .await?;
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::UserEvent(UserEvent {
+                user_id: uid.as_ref().to_owned(),
+                user_event_inner: UserEventInner::PasswordReset,
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+        Ok(())
+    }
+
     pub async fn change_password(
         &mut self,
         uid: impl AsRef<UserId>,
@@ -558,18 +756,15 @@ impl UsersManager {
             .hashed_psw
             .clone();
         if let Some(old_password) = old_password {
-            Argon2::default()
-                .verify_password(
-                    old_password.as_ref().as_bytes(),
-                    &argon2::PasswordHash::new(old_data.as_ref()).unwrap(),
-                )
-                .map_err(|_| Error {
+            if !old_data.verify(old_password.as_ref()) {
+                return Err(Error {
                     kind: ErrorKind::Unauthorized,
                     source: eyre!("Credential mismatch"),
-                })?;
+                });
+            }
         }
         if let Some(user) = self.users.get_mut(uid.as_ref()) {
-            user.hashed_psw = hash_password(password);
+            user.hashed_psw = hash_password(password, &self.password_hashing);
         }
         match self.write_to_file().await {
             Ok(_) => {
@@ -642,57 +837,328 @@ impl UsersManager {
         }
     }
 
+    /// Grants or takes away owner rights, the owner themselves can't be demoted this way
+    pub async fn set_admin(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        is_admin: bool,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            )
+        })?;
+        if user.is_owner {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "The owner can't be made or unmade an admin, transfer ownership first"
+                ),
+            });
+        }
+        if user.is_admin == is_admin {
+            return Ok(());
+        }
+        user.is_admin = is_admin;
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                user.is_admin = !is_admin;
+            }
+            return Err(e);
+        }
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::UserEvent(UserEvent {
+                user_id: uid.as_ref().to_owned(),
+                user_event_inner: UserEventInner::AdminChanged { is_admin },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+        Ok(())
+    }
+
+    /// Makes `new_owner` the owner, the previous owner stays on as an admin
+    pub async fn transfer_ownership(
+        &mut self,
+        new_owner: impl AsRef<UserId>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let new_owner = new_owner.as_ref().to_owned();
+        if !self.users.contains_key(&new_owner) {
+            return Err(Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            ));
+        }
+        let previous_owner = self
+            .users
+            .values()
+            .find(|user| user.is_owner)
+            .map(|user| user.uid.clone())
+            .ok_or_else(|| Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("There is no owner to transfer ownership from"),
+            })?;
+        if previous_owner == new_owner {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("This user is already the owner"),
+            });
+        }
+        let set_owner = |users: &mut HashMap<UserId, User>, owner: &UserId, other: &UserId| {
+            if let Some(user) = users.get_mut(owner) {
+                user.is_owner = true;
+                user.is_admin = false;
+            }
+            if let Some(user) = users.get_mut(other) {
+                user.is_owner = false;
+                user.is_admin = true;
+            }
+        };
+        let was_admin = self.users[&new_owner].is_admin;
+        set_owner(&mut self.users, &new_owner, &previous_owner);
+        if let Err(e) = self.write_to_file().await {
+            set_owner(&mut self.users, &previous_owner, &new_owner);
+            if let Some(user) = self.users.get_mut(&new_owner) {
+                user.is_admin = was_admin;
+            }
+            return Err(e);
+        }
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::UserEvent(UserEvent {
+                user_id: new_owner,
+                user_event_inner: UserEventInner::OwnershipTransferred { previous_owner },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+        Ok(())
+    }
+
+    /// For actions that are hard to take back, asks the requester to type their password again
+    pub fn confirm_password(
+        &self,
+        uid: impl AsRef<UserId>,
+        password: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        let user = self.users.get(uid.as_ref()).ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            )
+        })?;
+        let hash = argon2::PasswordHash::new(user.hashed_psw.as_ref()).map_err(|e| Error {
+            kind: ErrorKind::Internal,
+            source: eyre!("Stored password hash of {} is invalid: {e}", user.username),
+        })?;
+        Argon2::default()
+            .verify_password(password.as_ref().as_bytes(), &hash)
+            .map_err(|_| {
+                Error::localized(
+                    ErrorKind::Unauthorized,
+                    LocalizedMessage::new(MessageId::CredentialMismatch),
+                )
+            })
+    }
+
     pub fn try_auth(&self, token: &str) -> Option<User> {
-        let claimed_uid = decode_no_verify(token)?;
+        let claimed_uid = decode_no_verify(token)?.uid;
         let claimed_requester = self.users.get(&claimed_uid)?;
-        let requester_uid = decode_token(token, &claimed_requester.secret)?;
-        if claimed_uid != requester_uid {
+        let claim = decode_token(token, &claimed_requester.secret)?;
+        if claimed_uid != claim.uid {
             return None;
         }
-        Some(claimed_requester.to_owned())
+        if claim.impersonated_by.is_some() {
+            let session = claim.impersonation_session?;
+            if !self
+                .impersonations
+                .is_active(&session, chrono::Utc::now().timestamp())
+            {
+                return None;
+            }
+        }
+        let mut requester = claimed_requester.to_owned();
+        requester.impersonated_by = claim.impersonated_by;
+        Some(requester)
+    }
+
+    /// A short-lived token to act as `target` with, `impersonator` must be able to manage them
+    pub fn impersonate(
+        &mut self,
+        impersonator: &User,
+        target: impl AsRef<UserId>,
+        caused_by: CausedBy,
+    ) -> Result<(JwtToken, ImpersonationSession), Error> {
+        let target = self.get_user(target.as_ref()).ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            )
+        })?;
+        if !impersonator.can_manage(&target) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("You can only sign in as users you manage"),
+            });
+        }
+        let session = self.impersonations.start(
+            (impersonator.uid.clone(), impersonator.username.clone()),
+            (target.uid.clone(), target.username.clone()),
+            chrono::Utc::now().timestamp(),
+        );
+        let token = encode_jwt_token(
+            Claim {
+                uid: target.uid.clone(),
+                exp: session.expires_at as usize,
+                impersonated_by: Some(impersonator.uid.clone()),
+                impersonation_session: Some(session.session_id.clone()),
+            },
+            target.secret,
+        )?;
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::UserEvent(UserEvent {
+                user_id: target.uid,
+                user_event_inner: UserEventInner::ImpersonationStarted {
+                    impersonator: impersonator.uid.clone(),
+                    session_id: session.session_id.clone(),
+                },
+            }),
+            details: format!("{} signed in as {}", impersonator.username, target.username),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+        Ok((token, session))
+    }
+
+    pub fn impersonations(&self) -> Vec<ImpersonationSession> {
+        self.impersonations.list(chrono::Utc::now().timestamp())
+    }
+
+    pub fn end_impersonation(
+        &mut self,
+        session_id: &str,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let session = self
+            .impersonations
+            .revoke(session_id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("No such impersonation session"),
+            })?;
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::UserEvent(UserEvent {
+                user_id: session.target,
+                user_event_inner: UserEventInner::ImpersonationEnded {
+                    session_id: session.session_id,
+                },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+        Ok(())
     }
 
     pub fn try_auth_or_err(&self, token: &str) -> Result<User, Error> {
-        self.try_auth(token).ok_or_else(|| Error {
-            kind: ErrorKind::Unauthorized,
-            source: eyre!("Unauthorized"),
+        self.try_auth(token).ok_or_else(|| {
+            Error::localized(
+                ErrorKind::Unauthorized,
+                LocalizedMessage::new(MessageId::Unauthorized),
+            )
         })
     }
 
-    pub fn login(
-        &self,
+    /// Also rehashes the password if it predates the current hashing parameters
+    ///
+    /// Argon2 is slow on purpose, so the lock is only held to look the user up and to store
+    /// the new hash, not while hashing
+    pub async fn login(
+        users_manager: &RwLock<UsersManager>,
         username: impl AsRef<str>,
         password: impl AsRef<str>,
-    ) -> Result<JwtToken, Error> {
-        let user = self.get_user_by_username(username).ok_or_else(|| Error {
-            kind: ErrorKind::Unauthorized,
-            source: eyre!("Credential mismatch"),
-        })?;
-        Argon2::default()
-            .verify_password(
-                password.as_ref().as_bytes(),
-                &argon2::PasswordHash::new(user.hashed_psw.as_ref()).unwrap(),
-            )
-            .map_err(|_| Error {
-                kind: ErrorKind::Unauthorized,
-                source: eyre!("Credential mismatch"),
-            })?;
-        user.create_jwt()
+    ) -> Result<LoginReply, Error> {
+        let (user, password_hashing) = {
+            let users_manager = users_manager.read().await;
+            let user = users_manager
+                .get_user_by_username(username)
+                .ok_or_else(|| {
+                    Error::localized(
+                        ErrorKind::Unauthorized,
+                        LocalizedMessage::new(MessageId::CredentialMismatch),
+                    )
+                })?;
+            (user, users_manager.password_hashing)
+        };
+        let hashed_psw = user.hashed_psw.clone();
+        let password = password.as_ref().to_owned();
+        let (verified, rehashed) = tokio::task::spawn_blocking(move || {
+            if !hashed_psw.verify(&password) {
+                return (false, None);
+            }
+            let rehashed = hashed_psw
+                .needs_rehash(&password_hashing)
+                .then(|| hash_password(&password, &password_hashing));
+            (true, rehashed)
+        })
+        .await
+        .context("Failed to verify password")?;
+        if !verified {
+            return Err(Error::localized(
+                ErrorKind::Unauthorized,
+                LocalizedMessage::new(MessageId::CredentialMismatch),
+            ));
+        }
+        if let Some(rehashed) = rehashed {
+            users_manager
+                .write()
+                .await
+                .store_rehashed_password(&user, rehashed)
+                .await;
+        }
+        Ok(LoginReply {
+            token: user.create_jwt()?,
+            user: user.into(),
+        })
+    }
+
+    async fn store_rehashed_password(&mut self, user: &User, rehashed: HashedPassword) {
+        match self.users.get_mut(&user.uid) {
+            // the password may have changed while we were hashing
+            Some(stored) if stored.hashed_psw.as_ref() == user.hashed_psw.as_ref() => {
+                stored.hashed_psw = rehashed;
+            }
+            _ => return,
+        }
+        // the old hash still works, so this is retried on the next login
+        if let Err(e) = self.write_to_file().await {
+            warn!(
+                "Failed to save rehashed password for {}: {e}",
+                user.username
+            );
+            if let Some(stored) = self.users.get_mut(&user.uid) {
+                stored.hashed_psw = user.hashed_psw.clone();
+            }
+        }
     }
 }
 
-fn decode_token(token: &str, jwt_secret: &UserSecret) -> Option<UserId> {
+fn decode_token(token: &str, jwt_secret: &UserSecret) -> Option<Claim> {
     match jsonwebtoken::decode::<Claim>(
         token,
         &jsonwebtoken::DecodingKey::from_secret(jwt_secret.as_ref().as_bytes()),
         &Validation::new(Algorithm::HS512),
     ) {
-        Ok(t) => Some(t.claims.uid),
+        Ok(t) => Some(t.claims),
         Err(_) => None,
     }
 }
 
-fn decode_no_verify(token: &str) -> Option<UserId> {
+fn decode_no_verify(token: &str) -> Option<Claim> {
     let mut no_verify = Validation::new(Algorithm::HS512);
     no_verify.insecure_disable_signature_validation();
     match jsonwebtoken::decode::<Claim>(
@@ -700,7 +1166,7 @@ fn decode_no_verify(token: &str) -> Option<UserId> {
         &jsonwebtoken::DecodingKey::from_secret("noverify".as_bytes()),
         &no_verify,
     ) {
-        Ok(t) => Some(t.claims.uid),
+        Ok(t) => Some(t.claims),
         Err(_) => None,
     }
 }
@@ -727,14 +1193,37 @@ mod tests {
             true,
             false,
             UserPermission::default(),
+            // as if hashed before the parameters were raised
+            &PasswordHashing {
+                memory_kib: 1024,
+                iterations: 1,
+                parallelism: 1,
+            },
         );
 
         users_manager
             .add_user(test_user1.clone(), CausedBy::System)
             .await
             .unwrap();
+        let users_manager = RwLock::new(users_manager);
 
-        users_manager.login("test_user1", "12345").unwrap();
+        UsersManager::login(&users_manager, "test_user1", "12345")
+            .await
+            .unwrap();
+        let rehashed = users_manager
+            .read()
+            .await
+            .get_user_by_username("test_user1")
+            .unwrap();
+        assert!(!rehashed
+            .hashed_psw
+            .needs_rehash(&PasswordHashing::default()));
+        UsersManager::login(&users_manager, "test_user1", "12345")
+            .await
+            .unwrap();
+        assert!(UsersManager::login(&users_manager, "test_user1", "54321")
+            .await
+            .is_err());
     }
 
     #[tokio::test]
@@ -751,6 +1240,7 @@ mod tests {
             true,
             false,
             UserPermission::default(),
+            &PasswordHashing::default(),
         );
 
         users_manager
@@ -758,9 +1248,15 @@ mod tests {
             .await
             .unwrap();
 
-        users_manager.login("test_user1", "12345").unwrap();
+        let users_manager = RwLock::new(users_manager);
+
+        UsersManager::login(&users_manager, "test_user1", "12345")
+            .await
+            .unwrap();
 
         users_manager
+            .write()
+            .await
             .change_password(
                 &test_user1.uid,
                 Some("12345"),
@@ -770,7 +1266,121 @@ mod tests {
             .await
             .unwrap();
 
-        users_manager.login("test_user1", "54321").unwrap();
+        UsersManager::login(&users_manager, "test_user1", "54321")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transfer_ownership() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_transfer_ownership")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager = UsersManager::new(tx, HashMap::new(), temp_dir.join("users.json"));
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+            &PasswordHashing::default(),
+        );
+        let user = User::new(
+            "user".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+            &PasswordHashing::default(),
+        );
+        users_manager
+            .add_user(owner.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        users_manager
+            .add_user(user.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        assert!(users_manager
+            .set_admin(&owner.uid, false, CausedBy::System)
+            .await
+            .is_err());
+        users_manager
+            .set_admin(&user.uid, true, CausedBy::System)
+            .await
+            .unwrap();
+        let admin = users_manager.get_user(&user.uid).unwrap();
+        assert!(admin.can_perform_action(&UserAction::WriteGlobalFile));
+        assert!(!admin.can_manage(&users_manager.get_user(&owner.uid).unwrap()));
+
+        users_manager
+            .transfer_ownership(&user.uid, CausedBy::System)
+            .await
+            .unwrap();
+        let (previous, current) = (
+            users_manager.get_user(&owner.uid).unwrap(),
+            users_manager.get_user(&user.uid).unwrap(),
+        );
+        assert!(!previous.is_owner && previous.is_admin);
+        assert!(current.is_owner && !current.is_admin);
+        assert_eq!(
+            users_manager
+                .as_ref()
+                .values()
+                .filter(|user| user.is_owner)
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_impersonation() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_impersonation")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager = UsersManager::new(tx, HashMap::new(), temp_dir.join("users.json"));
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+            &PasswordHashing::default(),
+        );
+        let user = User::new(
+            "user".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+            &PasswordHashing::default(),
+        );
+        users_manager
+            .add_user(owner.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        users_manager
+            .add_user(user.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        let (token, session) = users_manager
+            .impersonate(&owner, &user.uid, CausedBy::System)
+            .unwrap();
+        let impersonated = users_manager.try_auth(token.as_ref()).unwrap();
+        assert_eq!(impersonated.uid, user.uid);
+        assert_eq!(impersonated.impersonated_by, Some(owner.uid.clone()));
+        assert!(impersonated.try_not_impersonated().is_err());
+
+        users_manager
+            .end_impersonation(&session.session_id, CausedBy::System)
+            .unwrap();
+        assert!(users_manager.try_auth(token.as_ref()).is_none());
     }
 
     #[tokio::test]
@@ -787,6 +1397,7 @@ mod tests {
             true,
             false,
             UserPermission::default(),
+            &PasswordHashing::default(),
         );
 
         users_manager
@@ -800,6 +1411,7 @@ mod tests {
             true,
             false,
             UserPermission::default(),
+            &PasswordHashing::default(),
         );
 
         users_manager
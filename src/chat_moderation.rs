@@ -0,0 +1,456 @@
+//! Chat moderation rules run against what players say in chat.
+//!
+//! A rule matches a message by a list of words or by a regex, and then takes its actions on the
+//! player who sent it: warning them, muting them with a command of the server's mute plugin,
+//! kicking them, or notifying staff through a webhook. Vanilla has no mute, so the command is
+//! left to the rule. Each match is kept in a log in the instance's directory and sent out as a
+//! [`InstanceEventInner::ChatModerated`] event.
+
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::SubscriptionFilter,
+    events::{CausedBy, Event, EventInner, EventType, InstanceEvent, InstanceEventInner},
+    traits::{t_configurable::TConfigurable, t_server::TServer},
+    types::{InstanceUuid, Snowflake},
+    util::post_webhook,
+    AppState,
+};
+
+const LOG_FILE: &str = ".lodestone_moderation_log.json";
+/// Older entries are dropped
+const MAX_LOG_ENTRIES: usize = 1000;
+pub const MAX_RULES_PER_INSTANCE: usize = 32;
+const MAX_WORDS: usize = 1024;
+const MAX_PATTERN_LEN: usize = 1024;
+
+lazy_static! {
+    /// Matches are handled in their own tasks, which take turns writing the logs
+    static ref LOG_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum ModerationMatch {
+    /// Any of the words or phrases, ignoring case and punctuation
+    Words {
+        words: Vec<String>,
+    },
+    Regex {
+        pattern: String,
+    },
+}
+
+impl ModerationMatch {
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            ModerationMatch::Words { words: list } => {
+                if list.is_empty() || list.len() > MAX_WORDS {
+                    return Err(format!(
+                        "A rule needs between 1 and {MAX_WORDS} words to match"
+                    ));
+                }
+                if let Some(entry) = list.iter().find(|entry| words(entry).is_empty()) {
+                    return Err(format!("{entry:?} has no words to match"));
+                }
+            }
+            ModerationMatch::Regex { pattern } => {
+                if pattern.len() > MAX_PATTERN_LEN {
+                    return Err(format!(
+                        "Patterns can be at most {MAX_PATTERN_LEN} characters"
+                    ));
+                }
+                Regex::new(pattern).map_err(|e| format!("Invalid pattern: {e}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, message: &str) -> bool {
+        match self {
+            ModerationMatch::Words { words: list } => {
+                let message = words(message);
+                list.iter().any(|entry| {
+                    let entry = words(entry);
+                    !entry.is_empty() && message.windows(entry.len()).any(|window| window == entry)
+                })
+            }
+            ModerationMatch::Regex { pattern } => Regex::new(pattern)
+                .ok()
+                .and_then(|regex| regex.is_match(message).ok())
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// The lowercased words of a message, anything but letters and digits separates them
+fn words(message: &str) -> Vec<String> {
+    message
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum ModerationAction {
+    /// Shown only to the player, in red
+    Warn {
+        message: String,
+    },
+    /// Sent as is after replacing `{player}`, like `mute {player} 10m Language`
+    Mute {
+        command: String,
+    },
+    Kick {
+        reason: String,
+    },
+    /// POSTs a json description of the match
+    Webhook {
+        url: String,
+    },
+}
+
+impl ModerationAction {
+    /// Whether the action sends commands to the console
+    pub fn uses_console(&self) -> bool {
+        !matches!(self, ModerationAction::Webhook { .. })
+    }
+
+    fn command(&self, player: &str) -> Option<String> {
+        match self {
+            ModerationAction::Warn { message } => Some(format!(
+                "tellraw {player} {}",
+                json!({ "text": message, "color": "red" })
+            )),
+            ModerationAction::Mute { command } => Some(command.replace("{player}", player)),
+            ModerationAction::Kick { reason } if reason.trim().is_empty() => {
+                Some(format!("kick {player}"))
+            }
+            ModerationAction::Kick { reason } => Some(format!("kick {player} {}", reason.trim())),
+            ModerationAction::Webhook { .. } => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ModerationAction::Warn { .. } => "Warn",
+            ModerationAction::Mute { .. } => "Mute",
+            ModerationAction::Kick { .. } => "Kick",
+            ModerationAction::Webhook { .. } => "Webhook",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct NewModerationRule {
+    pub name: String,
+    pub matcher: ModerationMatch,
+    #[serde(default)]
+    pub actions: Vec<ModerationAction>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl NewModerationRule {
+    pub fn validate(&self) -> Result<(), Error> {
+        let bad_request = |message: String| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(message),
+        };
+        if self.name.trim().is_empty() {
+            return Err(bad_request("Rule name cannot be empty".to_string()));
+        }
+        self.matcher.validate().map_err(bad_request)?;
+        for action in &self.actions {
+            let text = match action {
+                ModerationAction::Warn { message } => message,
+                ModerationAction::Mute { command } => {
+                    if !command.contains("{player}") {
+                        return Err(bad_request(
+                            "A mute command has to name the player with {player}".to_string(),
+                        ));
+                    }
+                    command
+                }
+                ModerationAction::Kick { reason } => reason,
+                ModerationAction::Webhook { url } => {
+                    let parsed = url::Url::parse(url)
+                        .map_err(|e| bad_request(format!("Invalid webhook URL: {e}")))?;
+                    if !matches!(parsed.scheme(), "http" | "https") {
+                        return Err(bad_request("Webhooks must be http or https".to_string()));
+                    }
+                    continue;
+                }
+            };
+            if text.contains(['\n', '\r']) {
+                return Err(bad_request(format!(
+                    "The {} action has to fit on one line",
+                    action.name()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_rule(self, id: Snowflake) -> ModerationRule {
+        ModerationRule {
+            id,
+            name: self.name.trim().to_string(),
+            matcher: self.matcher,
+            actions: self.actions,
+            enabled: self.enabled,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ModerationRule {
+    pub id: Snowflake,
+    pub name: String,
+    pub matcher: ModerationMatch,
+    pub actions: Vec<ModerationAction>,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct TakenAction {
+    /// `Warn`, `Mute`, `Kick` or `Webhook`
+    pub action: String,
+    /// Why the action failed, if it did
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ModerationLogEntry {
+    pub at: i64,
+    pub rule_id: Snowflake,
+    pub rule_name: String,
+    pub player: String,
+    pub message: String,
+    pub actions: Vec<TakenAction>,
+}
+
+/// Newest first
+pub async fn read_log(instance_path: &Path) -> Vec<ModerationLogEntry> {
+    let path = instance_path.join(LOG_FILE);
+    let content = match tokio::fs::read(&path).await {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+    serde_json::from_slice(&content).unwrap_or_else(|e| {
+        warn!("Ignoring unreadable moderation log {}: {e}", path.display());
+        Vec::new()
+    })
+}
+
+async fn append_log(instance_path: &Path, entry: ModerationLogEntry) -> Result<(), Error> {
+    let _guard = LOG_LOCK.lock().await;
+    let mut log = read_log(instance_path).await;
+    log.insert(0, entry);
+    log.truncate(MAX_LOG_ENTRIES);
+    tokio::fs::write(
+        instance_path.join(LOG_FILE),
+        serde_json::to_vec(&log).context("Failed to serialize moderation log")?,
+    )
+    .await
+    .context("Failed to write moderation log")?;
+    Ok(())
+}
+
+/// Chat names are put in commands as is, so anything that could break out of one isn't acted on
+fn is_commandable_name(player: &str) -> bool {
+    (1..=32).contains(&player.len())
+        && player
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+async fn run_actions(
+    state: AppState,
+    uuid: InstanceUuid,
+    rule: ModerationRule,
+    player: String,
+    message: String,
+) {
+    let instance = match state.instances.lock().await.get(&uuid).cloned() {
+        Some(instance) => instance,
+        None => return,
+    };
+    let instance_name = instance.name().await;
+    let mut taken = Vec::new();
+    for action in &rule.actions {
+        let result = match action.command(&player) {
+            Some(_) if !is_commandable_name(&player) => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{player:?} can't be named in a command"),
+            }),
+            Some(command) => instance.send_command(&command, CausedBy::System).await,
+            None => match action {
+                ModerationAction::Webhook { url } => {
+                    let body = json!({
+                        "instance_uuid": uuid,
+                        "instance_name": instance_name,
+                        "rule_id": rule.id,
+                        "rule_name": rule.name,
+                        "player": player,
+                        "message": message,
+                    });
+                    post_webhook(url, &body).await
+                }
+                _ => Ok(()),
+            },
+        };
+        if let Err(e) = &result {
+            warn!(
+                "Moderation rule \"{}\" failed to {} {player}: {e}",
+                rule.name,
+                action.name().to_lowercase()
+            );
+        }
+        taken.push(TakenAction {
+            action: action.name().to_string(),
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+    state.event_broadcaster.send(Event {
+        event_inner: EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid: uuid.clone(),
+            instance_name: instance_name.clone(),
+            instance_event_inner: InstanceEventInner::ChatModerated {
+                rule_id: rule.id,
+                rule_name: rule.name.clone(),
+                player: player.clone(),
+            },
+        }),
+        details: format!("A message of {player} matched \"{}\"", rule.name),
+        snowflake: Snowflake::default(),
+        caused_by: CausedBy::System,
+    });
+    let entry = ModerationLogEntry {
+        at: chrono::Utc::now().timestamp(),
+        rule_id: rule.id,
+        rule_name: rule.name,
+        player,
+        message,
+        actions: taken,
+    };
+    if let Err(e) = append_log(&instance.path().await, entry).await {
+        warn!("Failed to log a moderation action on {instance_name}: {e}");
+    }
+}
+
+/// Follows chat on every instance until the core shuts down
+pub async fn run_chat_moderation(state: AppState) {
+    let mut events = state
+        .event_broadcaster
+        .subscribe_filtered(SubscriptionFilter {
+            event_types: Some(vec![EventType::InstanceEvent]),
+            ..Default::default()
+        });
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        let (uuid, player, message) = match event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_event_inner:
+                    InstanceEventInner::PlayerMessage {
+                        player,
+                        player_message,
+                    },
+                ..
+            }) => (instance_uuid, player, player_message),
+            _ => continue,
+        };
+        let rules = state
+            .global_settings
+            .lock()
+            .await
+            .instance_moderation_rules(&uuid);
+        for rule in rules {
+            if rule.enabled && rule.matcher.matches(&message) {
+                // a slow webhook shouldn't hold up the chat of other instances
+                tokio::spawn(run_actions(
+                    state.clone(),
+                    uuid.clone(),
+                    rule,
+                    player.clone(),
+                    message.clone(),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let words = ModerationMatch::Words {
+            words: vec!["badword".to_string(), "free diamonds".to_string()],
+        };
+        assert!(words.matches("you BADWORD!"));
+        assert!(words.matches("get FREE   diamonds at example.com"));
+        assert!(!words.matches("notabadword here"));
+        assert!(!words.matches("diamonds free"));
+
+        let regex = ModerationMatch::Regex {
+            pattern: r"(?i)discord\.gg/\w+".to_string(),
+        };
+        assert!(regex.matches("join Discord.gg/abc"));
+        assert!(!regex.matches("discord is down"));
+        assert!(ModerationMatch::Regex {
+            pattern: "(".to_string()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let warn = ModerationAction::Warn {
+            message: "Mind \"your\" language".to_string(),
+        };
+        assert_eq!(
+            warn.command("Steve").unwrap(),
+            r#"tellraw Steve {"text":"Mind \"your\" language","color":"red"}"#
+        );
+        let mute = ModerationAction::Mute {
+            command: "mute {player} 10m".to_string(),
+        };
+        assert_eq!(mute.command("Steve").unwrap(), "mute Steve 10m");
+        let kick = ModerationAction::Kick {
+            reason: " ".to_string(),
+        };
+        assert_eq!(kick.command("Steve").unwrap(), "kick Steve");
+        assert!(is_commandable_name(".Bedrock_Steve"));
+        assert!(!is_commandable_name("Steve @a"));
+    }
+}
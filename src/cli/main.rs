@@ -0,0 +1,180 @@
+#![forbid(unsafe_code)]
+
+//! A small administration client for Lodestone Core, meant for scripting and
+//! environments where only a shell is available.
+//!
+//! Commands talk to the core over its HTTP API through `lodestone-client`, so the core has to be
+//! running. With `--local`, the commands that can be done without it work on the lodestone
+//! directory instead, see [`lodestone_core::local_admin`].
+
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{bail, Context, Result};
+use lodestone_client::{
+    types::{ConsoleLine, Snowflake},
+    Client,
+};
+use lodestone_core::local_admin;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "lodestone-cli",
+    about = "Administer a Lodestone Core from the command line"
+)]
+struct Cli {
+    /// Base url of the core's API
+    #[arg(long, default_value = "http://localhost:16662/api/v1")]
+    url: String,
+    /// Bearer token to authenticate with, falls back to `LODESTONE_TOKEN`
+    #[arg(long)]
+    token: Option<String>,
+    /// Work on the lodestone directory of a stopped core instead of going through the API
+    #[arg(long)]
+    local: bool,
+    /// Lodestone directory for `--local`, found the way the core finds it if not given
+    #[arg(long)]
+    lodestone_path: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Log in and print a token to use with `--token`
+    Login { username: String, password: String },
+    /// List all instances visible to the user
+    List,
+    /// Start an instance
+    Start { uuid: String },
+    /// Stop an instance
+    Stop { uuid: String },
+    /// Restart an instance
+    Restart { uuid: String },
+    /// Kill an instance
+    Kill { uuid: String },
+    /// Send a command to an instance's console
+    Send { uuid: String, command: String },
+    /// Print an instance's console output, optionally following new lines
+    Console {
+        uuid: String,
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Create a new user
+    CreateUser { username: String, password: String },
+    /// Snapshot an instance's files
+    Backup {
+        uuid: String,
+        name: String,
+        #[arg(short, long)]
+        description: Option<String>,
+    },
+    /// Download a backup of the core's users and settings, owner only
+    CoreBackup { output: PathBuf },
+}
+
+async fn run_local(lodestone_path: Option<PathBuf>, command: Command) -> Result<()> {
+    local_admin::init(lodestone_path)?;
+    match command {
+        Command::List => {
+            for instance in local_admin::list_instances().await? {
+                println!(
+                    "{}\t{:?}\t{}",
+                    instance.uuid,
+                    instance.game_type,
+                    instance.path.display(),
+                );
+            }
+        }
+        Command::CreateUser { username, password } => {
+            println!("{}", local_admin::create_user(username, password).await?);
+        }
+        Command::Backup {
+            uuid,
+            name,
+            description,
+        } => {
+            let id = local_admin::create_snapshot(&uuid.into(), name, description).await?;
+            println!("{}", String::from(id));
+        }
+        _ => bail!("This command needs a running core, it can't be used with --local"),
+    }
+    Ok(())
+}
+
+async fn tail_console(client: &Client, uuid: &str, follow: bool) -> Result<()> {
+    // snowflakes grow with time, so lines up to the last one printed have been seen
+    let mut cursor: Option<Snowflake> = None;
+    loop {
+        for event in client.console_buffer(uuid).await? {
+            if cursor.map_or(false, |cursor| event.snowflake <= cursor) {
+                continue;
+            }
+            cursor = Some(event.snowflake);
+            match event.console_line() {
+                Some(ConsoleLine::Output(message)) => println!("{}", message),
+                Some(ConsoleLine::Input(message)) => println!("> {}", message),
+                None => {}
+            }
+        }
+        if !follow {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    let cli = Cli::parse();
+    if cli.local {
+        return run_local(cli.lodestone_path, cli.command).await;
+    }
+    let mut client = Client::new(cli.url);
+    if let Some(token) = cli.token.or_else(|| std::env::var("LODESTONE_TOKEN").ok()) {
+        client = client.with_token(token);
+    }
+    match cli.command {
+        Command::Login { username, password } => {
+            let reply = client.login(&username, &password).await?;
+            println!("{}", String::from(reply.token));
+        }
+        Command::List => {
+            for instance in client.list_instances().await? {
+                println!(
+                    "{}\t{}\t{:?}\t{}",
+                    instance.uuid, instance.name, instance.state, instance.port,
+                );
+            }
+        }
+        Command::Start { uuid } => client.start_instance(&uuid).await?,
+        Command::Stop { uuid } => client.stop_instance(&uuid).await?,
+        Command::Restart { uuid } => client.restart_instance(&uuid).await?,
+        Command::Kill { uuid } => client.kill_instance(&uuid).await?,
+        Command::Send { uuid, command } => client.send_command(&uuid, &command).await?,
+        Command::Console { uuid, follow } => tail_console(&client, &uuid, follow).await?,
+        Command::CreateUser { username, password } => {
+            println!(
+                "{}",
+                client.create_user(&username, &password).await?.user.uid
+            );
+        }
+        Command::Backup {
+            uuid,
+            name,
+            description,
+        } => {
+            client
+                .create_snapshot(&uuid, &name, description.as_deref())
+                .await?
+        }
+        Command::CoreBackup { output } => {
+            tokio::fs::write(&output, client.core_backup().await?)
+                .await
+                .context(format!("Failed to write {}", output.display()))?;
+        }
+    }
+    Ok(())
+}
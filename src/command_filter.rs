@@ -0,0 +1,304 @@
+//! Limits on which console commands users can send, in tiers between no console and full console.
+//!
+//! A command role lists its members and the command patterns they may or may not run, on the
+//! instances it names or on all of them. Users in no role for an instance, and owners, send
+//! anything their permissions let them, so roles only ever narrow down console access. A user in
+//! several roles can run what any of them allows. Refused commands are recorded as
+//! [`InstanceEventInner::CommandBlocked`] events.
+//!
+//! Patterns are matched word by word against the start of the command, so `kick` also covers
+//! `kick Steve griefing`, and `*` stands for any one word, like in `gamemode * Steve`. Namespaces
+//! are ignored, `op` also covers `minecraft:op`. The command an `execute ... run` wraps is
+//! checked as well, so blocking `op` also blocks `execute as @a run op Steve`. A command with a
+//! line break in it is refused, as each line would run as its own command.
+
+use std::collections::HashSet;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::{user::User, user_id::UserId},
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    traits::t_configurable::TConfigurable,
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+pub const MAX_ROLES: usize = 64;
+const MAX_PATTERNS: usize = 256;
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct NewCommandRole {
+    pub name: String,
+    pub members: Vec<UserId>,
+    /// When not empty, only commands matching one of these can be sent
+    pub allowed: Vec<String>,
+    /// Always refused, even when also allowed
+    pub blocked: Vec<String>,
+    /// Every instance when empty
+    pub instances: Vec<InstanceUuid>,
+}
+
+impl NewCommandRole {
+    pub fn validate(&self) -> Result<(), Error> {
+        let bad_request = |message: String| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(message),
+        };
+        if self.name.trim().is_empty() {
+            return Err(bad_request("Command role name cannot be empty".to_string()));
+        }
+        if self.allowed.len() + self.blocked.len() > MAX_PATTERNS {
+            return Err(bad_request(format!(
+                "A command role can have at most {MAX_PATTERNS} patterns"
+            )));
+        }
+        if let Some(pattern) = self
+            .allowed
+            .iter()
+            .chain(self.blocked.iter())
+            .find(|pattern| words(pattern).is_empty())
+        {
+            return Err(bad_request(format!("Invalid command pattern {pattern:?}")));
+        }
+        Ok(())
+    }
+
+    pub fn into_role(self, id: Snowflake) -> CommandRole {
+        CommandRole {
+            id,
+            name: self.name.trim().to_string(),
+            members: self.members,
+            allowed: self.allowed,
+            blocked: self.blocked,
+            instances: self.instances,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct CommandRole {
+    pub id: Snowflake,
+    pub name: String,
+    pub members: Vec<UserId>,
+    pub allowed: Vec<String>,
+    pub blocked: Vec<String>,
+    pub instances: Vec<InstanceUuid>,
+}
+
+impl CommandRole {
+    fn applies_to(&self, uid: &UserId, uuid: &InstanceUuid) -> bool {
+        self.members.contains(uid) && (self.instances.is_empty() || self.instances.contains(uuid))
+    }
+
+    fn allows(&self, command: &str) -> bool {
+        if command.contains(['\r', '\n']) {
+            return false;
+        }
+        commands(command).iter().all(|command| {
+            let matches = |pattern: &String| pattern_matches(&words(pattern), command);
+            !self.blocked.iter().any(matches)
+                && (self.allowed.is_empty() || self.allowed.iter().any(matches))
+        })
+    }
+}
+
+/// The lowercased words of a command or pattern, without the leading `/` and the namespace
+fn words(command: &str) -> Vec<String> {
+    let mut words: Vec<String> = command
+        .trim()
+        .trim_start_matches('/')
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect();
+    strip_namespace(&mut words);
+    words.retain(|word| !word.is_empty());
+    words
+}
+
+fn strip_namespace(words: &mut [String]) {
+    if let Some(first) = words.first_mut() {
+        if let Some((_, name)) = first.rsplit_once(':') {
+            *first = name.to_string();
+        }
+    }
+}
+
+/// The words of a command followed by those of every command it may run through `execute ... run`
+///
+/// `run` can also be an argument, like a score holder named `run`, so what follows every one of
+/// them is checked, not just the first
+fn commands(command: &str) -> Vec<Vec<String>> {
+    let words = words(command);
+    let mut commands = vec![words.clone()];
+    // the wrapped commands are all suffixes of `words`, each only needs checking once
+    let mut starts = HashSet::new();
+    let mut i = 0;
+    while i < commands.len() {
+        if commands[i].first().map(String::as_str) == Some("execute") {
+            let offset = words.len() - commands[i].len();
+            let runs: Vec<usize> = commands[i]
+                .iter()
+                .enumerate()
+                .filter(|(_, word)| *word == "run")
+                .map(|(run, _)| offset + run + 1)
+                .collect();
+            for start in runs {
+                if starts.insert(start) {
+                    let mut wrapped = words[start..].to_vec();
+                    strip_namespace(&mut wrapped);
+                    commands.push(wrapped);
+                }
+            }
+        }
+        i += 1;
+    }
+    commands
+}
+
+fn pattern_matches(pattern: &[String], command: &[String]) -> bool {
+    pattern.len() <= command.len()
+        && pattern
+            .iter()
+            .zip(command)
+            .all(|(pattern, word)| pattern == "*" || pattern == word)
+}
+
+/// The names of the roles that keep `uid` from sending `command`, empty if it may
+fn refusing_roles(
+    roles: &[CommandRole],
+    uid: &UserId,
+    uuid: &InstanceUuid,
+    command: &str,
+) -> Vec<String> {
+    let applying: Vec<&CommandRole> = roles
+        .iter()
+        .filter(|role| role.applies_to(uid, uuid))
+        .collect();
+    if applying.iter().any(|role| role.allows(command)) {
+        return Vec::new();
+    }
+    applying.iter().map(|role| role.name.clone()).collect()
+}
+
+impl AppState {
+    /// Whether the command roles of `user` let them send `command` to the instance, recording
+    /// the attempt if they don't
+    pub async fn check_command(
+        &self,
+        user: &User,
+        uuid: &InstanceUuid,
+        command: &str,
+    ) -> Result<(), Error> {
+        if user.has_owner_rights() {
+            return Ok(());
+        }
+        let roles = refusing_roles(
+            self.global_settings.lock().await.command_roles(),
+            &user.uid,
+            uuid,
+            command,
+        );
+        if roles.is_empty() {
+            return Ok(());
+        }
+        let instance_name = match self.instances.lock().await.get(uuid) {
+            Some(instance) => instance.name().await,
+            None => uuid.to_string(),
+        };
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: uuid.clone(),
+                instance_name,
+                instance_event_inner: InstanceEventInner::CommandBlocked {
+                    command: command.to_string(),
+                    roles: roles.clone(),
+                },
+            }),
+            details: format!("{} was not allowed to run a command", user.username),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::User {
+                user_id: user.uid.clone(),
+                user_name: user.username.clone(),
+                impersonated_by: user.impersonated_by.clone(),
+            },
+        });
+        Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!(
+                "Your command roles ({}) don't allow this command",
+                roles.join(", ")
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn role(allowed: &[&str], blocked: &[&str], members: Vec<UserId>) -> CommandRole {
+        CommandRole {
+            id: Snowflake::new(),
+            name: "moderator".to_string(),
+            members,
+            allowed: allowed.iter().map(|p| p.to_string()).collect(),
+            blocked: blocked.iter().map(|p| p.to_string()).collect(),
+            instances: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_patterns() {
+        let moderator = role(&["kick", "ban", "gamemode *"], &["ban-ip"], Vec::new());
+        assert!(moderator.allows("kick Steve griefing"));
+        assert!(moderator.allows("/minecraft:ban Steve"));
+        assert!(moderator.allows("gamemode creative Steve"));
+        assert!(!moderator.allows("op Steve"));
+        assert!(!moderator.allows("kickall"));
+        assert!(!moderator.allows("stop"));
+
+        let helper = role(&[], &["op", "stop", "deop"], Vec::new());
+        assert!(helper.allows("say hi"));
+        assert!(!helper.allows("  /OP Steve"));
+        assert!(!helper.allows("minecraft:stop"));
+        assert!(!helper.allows("execute as @a run op Steve"));
+        assert!(!helper.allows("execute as @a run execute at @s run minecraft:op Steve"));
+        assert!(helper.allows("execute as @a run say hi"));
+        // a score holder or fake player can be named `run`
+        assert!(!helper.allows("execute if score run obj matches 1 run op Steve"));
+        assert!(!helper.allows("execute as run run execute if entity run run op Steve"));
+
+        assert!(!moderator.allows("kick Steve\nop Mallory"));
+        assert!(!moderator.allows("kick Steve\r"));
+        assert!(!moderator.allows("execute run op Steve"));
+    }
+
+    #[test]
+    fn test_refusing_roles() {
+        let moderator = UserId::default();
+        let admin = UserId::default();
+        let uuid = InstanceUuid::default();
+        let roles = vec![
+            role(&["kick"], &[], vec![moderator.clone()]),
+            role(&["say"], &[], vec![moderator.clone()]),
+        ];
+        assert!(refusing_roles(&roles, &moderator, &uuid, "say hi").is_empty());
+        assert_eq!(
+            refusing_roles(&roles, &moderator, &uuid, "op Steve").len(),
+            2
+        );
+        assert!(refusing_roles(&roles, &admin, &uuid, "op Steve").is_empty());
+
+        let mut elsewhere = roles;
+        for role in elsewhere.iter_mut() {
+            role.instances = vec![InstanceUuid::default()];
+        }
+        assert!(refusing_roles(&elsewhere, &moderator, &uuid, "op Steve").is_empty());
+    }
+}
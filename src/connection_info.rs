@@ -0,0 +1,318 @@
+//! Working out the address players should connect to an instance with.
+//!
+//! The public IP is asked of a STUN server, falling back to an HTTP service, and cached for a
+//! while since it rarely changes. With a domain configured, its `_minecraft._tcp` SRV record is
+//! checked to point at the instance, so the domain can be given out without a port.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tracing::debug;
+use trust_dns_resolver::{error::ResolveErrorKind, TokioAsyncResolver};
+use ts_rs::TS;
+
+use crate::minecraft::query::ServerStatus;
+
+const STUN_SERVER: &str = "stun.l.google.com:19302";
+const HTTP_IP_SERVICE: &str = "https://api.ipify.org";
+const DETECT_TIMEOUT: Duration = Duration::from_secs(3);
+const PUBLIC_IP_TTL: Duration = Duration::from_secs(10 * 60);
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+const DEFAULT_MINECRAFT_PORT: u32 = 25565;
+
+static PUBLIC_IP: Lazy<Mutex<Option<(Instant, Option<IpAddr>)>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum SrvStatus {
+    /// Points at the instance's port, on a host resolving to the public IP when it is known
+    Valid,
+    Missing,
+    /// Points at another port than the instance's
+    PortMismatch,
+    /// The target doesn't resolve to the detected public IP
+    TargetMismatch,
+    LookupFailed,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct SrvCheck {
+    /// The name looked up, like `_minecraft._tcp.example.com`
+    pub record: String,
+    pub status: SrvStatus,
+    pub target: Option<String>,
+    pub port: Option<u32>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ConnectionInfo {
+    /// The best guess at what players should type in, `None` if nothing could be detected
+    pub address: Option<String>,
+    pub public_ip: Option<String>,
+    pub lan_ip: Option<String>,
+    pub domain: Option<String>,
+    pub port: u32,
+    /// Only for Minecraft Java instances with a domain configured
+    pub srv: Option<SrvCheck>,
+    /// What the server said when last asked, only for running Minecraft Java instances
+    pub status: Option<ServerStatus>,
+}
+
+impl ConnectionInfo {
+    fn best_address(&self) -> Option<String> {
+        if let Some(domain) = &self.domain {
+            let srv_valid = matches!(&self.srv, Some(srv) if srv.status == SrvStatus::Valid);
+            return Some(if srv_valid || self.port == DEFAULT_MINECRAFT_PORT {
+                domain.clone()
+            } else {
+                format!("{domain}:{}", self.port)
+            });
+        }
+        self.public_ip
+            .as_ref()
+            .or(self.lan_ip.as_ref())
+            .map(|ip| match ip.parse::<IpAddr>() {
+                Ok(IpAddr::V6(_)) => format!("[{ip}]:{}", self.port),
+                _ => format!("{ip}:{}", self.port),
+            })
+    }
+}
+
+pub async fn connection_info(domain: Option<String>, port: u32, check_srv: bool) -> ConnectionInfo {
+    let public_ip = public_ip().await;
+    let lan_ip = local_ip_address::local_ip().ok();
+    let srv = match &domain {
+        Some(domain) if check_srv => Some(check_srv_record(domain, port, public_ip).await),
+        _ => None,
+    };
+    let mut info = ConnectionInfo {
+        address: None,
+        public_ip: public_ip.map(|ip| ip.to_string()),
+        lan_ip: lan_ip.map(|ip| ip.to_string()),
+        domain,
+        port,
+        srv,
+        status: None,
+    };
+    info.address = info.best_address();
+    info
+}
+
+/// Cached, the lookups are only made again once [`PUBLIC_IP_TTL`] has passed
+pub async fn public_ip() -> Option<IpAddr> {
+    let mut cached = PUBLIC_IP.lock().await;
+    if let Some((at, ip)) = *cached {
+        if at.elapsed() < PUBLIC_IP_TTL {
+            return ip;
+        }
+    }
+    let ip = match tokio::time::timeout(DETECT_TIMEOUT, stun_public_ip()).await {
+        Ok(Some(ip)) => Some(ip),
+        _ => {
+            debug!("STUN lookup of the public IP failed, trying {HTTP_IP_SERVICE}");
+            http_public_ip().await
+        }
+    };
+    *cached = Some((Instant::now(), ip));
+    ip
+}
+
+async fn stun_public_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.ok()?;
+    socket.connect(STUN_SERVER).await.ok()?;
+    let transaction_id: [u8; 12] = rand::random();
+    let mut request = Vec::with_capacity(20);
+    // binding request, no attributes
+    request.extend_from_slice(&0x0001u16.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction_id);
+    socket.send(&request).await.ok()?;
+    let mut buf = [0u8; 512];
+    let len = socket.recv(&mut buf).await.ok()?;
+    parse_stun_response(&buf[..len], &transaction_id)
+}
+
+async fn http_public_ip() -> Option<IpAddr> {
+    reqwest::Client::new()
+        .get(HTTP_IP_SERVICE)
+        .timeout(DETECT_TIMEOUT)
+        .send()
+        .await
+        .ok()?
+        .text()
+        .await
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// The mapped address of a binding success response
+fn parse_stun_response(response: &[u8], transaction_id: &[u8; 12]) -> Option<IpAddr> {
+    if response.len() < 20
+        || response[0..2] != [0x01, 0x01]
+        || response[4..8] != STUN_MAGIC_COOKIE.to_be_bytes()
+        || &response[8..20] != transaction_id
+    {
+        return None;
+    }
+    let length = u16::from_be_bytes([response[2], response[3]]) as usize;
+    let attributes = response.get(20..20 + length)?;
+    let mut mapped = None;
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let kind = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let len = u16::from_be_bytes([attributes[offset + 2], attributes[offset + 3]]) as usize;
+        let value = attributes.get(offset + 4..offset + 4 + len)?;
+        match kind {
+            // XOR-MAPPED-ADDRESS, preferred as some NATs rewrite addresses they find in packets
+            0x0020 => return parse_address(value, Some(transaction_id)),
+            // MAPPED-ADDRESS
+            0x0001 => mapped = parse_address(value, None),
+            _ => {}
+        }
+        // attributes are padded to 4 bytes
+        offset += 4 + (len + 3) / 4 * 4;
+    }
+    mapped
+}
+
+fn parse_address(value: &[u8], xor_with: Option<&[u8; 12]>) -> Option<IpAddr> {
+    let key: Vec<u8> = match xor_with {
+        Some(transaction_id) => STUN_MAGIC_COOKIE
+            .to_be_bytes()
+            .iter()
+            .chain(transaction_id)
+            .copied()
+            .collect(),
+        None => vec![0; 16],
+    };
+    let len = match value.get(1)? {
+        0x01 => 4,
+        0x02 => 16,
+        _ => return None,
+    };
+    let ip: Vec<u8> = value
+        .get(4..4 + len)?
+        .iter()
+        .zip(&key)
+        .map(|(byte, key)| byte ^ key)
+        .collect();
+    match len {
+        4 => <[u8; 4]>::try_from(ip.as_slice())
+            .ok()
+            .map(|ip| IpAddr::V4(Ipv4Addr::from(ip))),
+        _ => <[u8; 16]>::try_from(ip.as_slice())
+            .ok()
+            .map(|ip| IpAddr::V6(Ipv6Addr::from(ip))),
+    }
+}
+
+async fn check_srv_record(domain: &str, port: u32, public_ip: Option<IpAddr>) -> SrvCheck {
+    let record = format!("_minecraft._tcp.{}", domain.trim_end_matches('.'));
+    let mut check = SrvCheck {
+        record: record.clone(),
+        status: SrvStatus::LookupFailed,
+        target: None,
+        port: None,
+    };
+    let resolver = match TokioAsyncResolver::tokio_from_system_conf() {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            debug!("Failed to set up a DNS resolver: {e}");
+            return check;
+        }
+    };
+    let lookup = match resolver.srv_lookup(format!("{record}.")).await {
+        Ok(lookup) => lookup,
+        Err(e) => {
+            if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) {
+                check.status = SrvStatus::Missing;
+            } else {
+                debug!("Failed to look up {record}: {e}");
+            }
+            return check;
+        }
+    };
+    let srv = match lookup
+        .iter()
+        .find(|srv| srv.port() as u32 == port)
+        .or_else(|| lookup.iter().next())
+    {
+        Some(srv) => srv,
+        None => {
+            check.status = SrvStatus::Missing;
+            return check;
+        }
+    };
+    let target = srv.target().to_utf8();
+    check.target = Some(target.trim_end_matches('.').to_string());
+    check.port = Some(srv.port() as u32);
+    if srv.port() as u32 != port {
+        check.status = SrvStatus::PortMismatch;
+        return check;
+    }
+    check.status = match public_ip {
+        Some(public_ip) => match resolver.lookup_ip(target).await {
+            Ok(ips) if ips.iter().any(|ip| ip == public_ip) => SrvStatus::Valid,
+            Ok(_) => SrvStatus::TargetMismatch,
+            Err(_) => SrvStatus::LookupFailed,
+        },
+        None => SrvStatus::Valid,
+    };
+    check
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stun_response() {
+        let transaction_id = [7u8; 12];
+        let ip = [203u8, 0, 113, 5];
+        let cookie = STUN_MAGIC_COOKIE.to_be_bytes();
+        let mut response = vec![0x01, 0x01, 0x00, 0x0c];
+        response.extend_from_slice(&cookie);
+        response.extend_from_slice(&transaction_id);
+        // XOR-MAPPED-ADDRESS, IPv4, port 54321
+        response.extend_from_slice(&[0x00, 0x20, 0x00, 0x08, 0x00, 0x01]);
+        response.extend_from_slice(&(54321u16 ^ 0x2112).to_be_bytes());
+        response.extend(ip.iter().zip(&cookie).map(|(byte, key)| byte ^ key));
+        assert_eq!(
+            parse_stun_response(&response, &transaction_id),
+            Some(IpAddr::from(ip))
+        );
+        assert_eq!(parse_stun_response(&response, &[0u8; 12]), None);
+    }
+
+    #[test]
+    fn test_best_address() {
+        let mut info = ConnectionInfo {
+            address: None,
+            public_ip: Some("203.0.113.5".to_string()),
+            lan_ip: Some("192.168.1.2".to_string()),
+            domain: None,
+            port: 25570,
+            srv: None,
+            status: None,
+        };
+        assert_eq!(info.best_address().as_deref(), Some("203.0.113.5:25570"));
+        info.domain = Some("mc.example.com".to_string());
+        assert_eq!(info.best_address().as_deref(), Some("mc.example.com:25570"));
+        info.srv = Some(SrvCheck {
+            record: "_minecraft._tcp.mc.example.com".to_string(),
+            status: SrvStatus::Valid,
+            target: Some("host.example.com".to_string()),
+            port: Some(25570),
+        });
+        assert_eq!(info.best_address().as_deref(), Some("mc.example.com"));
+    }
+}
@@ -0,0 +1,122 @@
+//! The last lines of an instance's console, kept in memory so a console view
+//! can show recent history without going through the events database.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Weak};
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+
+use crate::event_broadcaster::{EventBroadcaster, SubscriptionFilter};
+use crate::events::{Event, EventType};
+use crate::types::InstanceUuid;
+
+/// Lines kept for an instance that doesn't say otherwise
+pub const DEFAULT_CONSOLE_BUFFER_LINES: u32 = 1000;
+/// Upper bound on the lines an instance can be configured to keep
+pub const MAX_CONSOLE_BUFFER_LINES: u32 = 100_000;
+
+struct Lines {
+    events: VecDeque<Event>,
+    capacity: usize,
+}
+
+#[derive(Clone)]
+pub struct ConsoleBuffer {
+    lines: Arc<Mutex<Lines>>,
+}
+
+impl ConsoleBuffer {
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(Lines {
+                events: VecDeque::new(),
+                capacity: capacity as usize,
+            })),
+        }
+    }
+
+    /// Fills the buffer with the console events of `instance_uuid` until the buffer is dropped
+    pub fn collect_from(&self, instance_uuid: InstanceUuid, event_broadcaster: &EventBroadcaster) {
+        let lines: Weak<Mutex<Lines>> = Arc::downgrade(&self.lines);
+        let mut events = event_broadcaster.subscribe_filtered(SubscriptionFilter {
+            event_types: Some(vec![EventType::InstanceEvent]),
+            instance_uuids: Some(vec![instance_uuid.clone()]),
+            ..Default::default()
+        });
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if !event.is_event_console_message()
+                    || event.get_instance_uuid().as_ref() != Some(&instance_uuid)
+                {
+                    continue;
+                }
+                match lines.upgrade() {
+                    Some(lines) => ConsoleBuffer { lines }.push(event).await,
+                    None => break,
+                }
+            }
+        });
+    }
+
+    pub async fn push(&self, event: Event) {
+        let mut lines = self.lines.lock().await;
+        if lines.capacity == 0 {
+            return;
+        }
+        if lines.events.len() >= lines.capacity {
+            lines.events.pop_front();
+        }
+        lines.events.push_back(event);
+    }
+
+    /// Up to `count` of the most recent lines, oldest first
+    pub async fn last(&self, count: usize) -> Vec<Event> {
+        let lines = self.lines.lock().await;
+        let skip = lines.events.len().saturating_sub(count);
+        lines.events.iter().skip(skip).cloned().collect()
+    }
+
+    /// Drops the oldest lines if the buffer shrinks
+    pub async fn set_capacity(&self, capacity: u32) {
+        let mut lines = self.lines.lock().await;
+        lines.capacity = capacity as usize;
+        let excess = lines.events.len().saturating_sub(lines.capacity);
+        lines.events.drain(..excess);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_console_buffer() {
+        let buffer = ConsoleBuffer::new(3);
+        for i in 0..5 {
+            buffer
+                .push(Event::new_system_message(
+                    InstanceUuid::default(),
+                    "Test".to_string(),
+                    format!("line {i}"),
+                ))
+                .await;
+        }
+        let snowflakes = |events: Vec<Event>| {
+            events
+                .into_iter()
+                .map(|event| event.snowflake)
+                .collect::<Vec<_>>()
+        };
+        let all = snowflakes(buffer.last(10).await);
+        assert_eq!(all.len(), 3);
+        assert_eq!(snowflakes(buffer.last(2).await), all[1..]);
+        buffer.set_capacity(1).await;
+        assert_eq!(snowflakes(buffer.last(10).await), all[2..]);
+    }
+}
@@ -0,0 +1,301 @@
+//! Post-processing of raw console output lines.
+//!
+//! Servers attached to a terminal (or forced to use colors) emit ANSI escape codes.
+//! These are turned into styled [`ConsoleSpan`]s so the frontend does not have to interpret them,
+//! and the log level and source of the line are picked out of the usual log4j style headers.
+
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum ConsoleColor {
+    /// Index into the xterm 256 color palette, 0-7 are the standard colors and 8-15 their bright variants
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default, TS)]
+#[ts(export)]
+pub struct ConsoleSpan {
+    pub text: String,
+    pub fg: Option<ConsoleColor>,
+    pub bg: Option<ConsoleColor>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn from_label(label: &str) -> Option<Self> {
+        match label {
+            "TRACE" | "DEBUG" | "FINE" | "FINER" | "FINEST" => Some(LogLevel::Debug),
+            "INFO" => Some(LogLevel::Info),
+            "WARN" | "WARNING" => Some(LogLevel::Warn),
+            "ERROR" | "SEVERE" | "FATAL" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsoleLine {
+    /// The line with all escape codes removed
+    pub message: String,
+    /// Styled pieces of the line, empty if the line carried no styling
+    pub spans: Vec<ConsoleSpan>,
+    pub level: Option<LogLevel>,
+    /// The thread or plugin the line was logged from
+    pub source: Option<String>,
+}
+
+impl ConsoleLine {
+    pub fn parse(raw: &str) -> Self {
+        let (message, spans) = parse_ansi(raw);
+        let (level, source) = classify(&message);
+        ConsoleLine {
+            message,
+            spans,
+            level,
+            source,
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Eq)]
+struct Style {
+    fg: Option<ConsoleColor>,
+    bg: Option<ConsoleColor>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Style {
+    fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+
+    fn apply_sgr(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(ConsoleColor::Indexed((param - 30) as u8)),
+                38 => self.fg = parse_extended_color(&mut params),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(ConsoleColor::Indexed((param - 40) as u8)),
+                48 => self.bg = parse_extended_color(&mut params),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(ConsoleColor::Indexed((param - 90 + 8) as u8)),
+                100..=107 => self.bg = Some(ConsoleColor::Indexed((param - 100 + 8) as u8)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Parses the `5;n` or `2;r;g;b` tail of a `38`/`48` SGR parameter
+fn parse_extended_color(params: &mut impl Iterator<Item = u16>) -> Option<ConsoleColor> {
+    match params.next()? {
+        5 => Some(ConsoleColor::Indexed(params.next()?.min(255) as u8)),
+        2 => Some(ConsoleColor::Rgb(
+            params.next()?.min(255) as u8,
+            params.next()?.min(255) as u8,
+            params.next()?.min(255) as u8,
+        )),
+        _ => None,
+    }
+}
+
+/// Strips escape sequences out of `raw`, turning SGR sequences into styled spans
+///
+/// Non-SGR CSI sequences (cursor movement, line erasure) and OSC sequences are dropped
+fn parse_ansi(raw: &str) -> (String, Vec<ConsoleSpan>) {
+    let mut message = String::with_capacity(raw.len());
+    let mut spans: Vec<ConsoleSpan> = Vec::new();
+    let mut styled = false;
+    let mut style = Style::default();
+    let mut current = String::new();
+
+    let push_span = |spans: &mut Vec<ConsoleSpan>, text: &mut String, style: &Style| {
+        if !text.is_empty() {
+            spans.push(ConsoleSpan {
+                text: std::mem::take(text),
+                fg: style.fg,
+                bg: style.bg,
+                bold: style.bold,
+                italic: style.italic,
+                underline: style.underline,
+            });
+        }
+    };
+
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            message.push(c);
+            current.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                let mut sequence = String::new();
+                let mut terminator = None;
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        terminator = Some(c);
+                        break;
+                    }
+                    sequence.push(c);
+                }
+                if terminator == Some('m') {
+                    let params: Vec<u16> = sequence
+                        .split(';')
+                        .filter(|param| !param.is_empty())
+                        .filter_map(|param| param.parse().ok())
+                        .collect();
+                    let mut new_style = style.clone();
+                    new_style.apply_sgr(&params);
+                    if new_style != style {
+                        push_span(&mut spans, &mut current, &style);
+                        style = new_style;
+                        styled |= !style.is_plain();
+                    }
+                }
+            }
+            Some(']') => {
+                // OSC, terminated by BEL or ST (ESC \)
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // two character escape sequence, nothing to keep
+            _ => {}
+        }
+    }
+    push_span(&mut spans, &mut current, &style);
+    if !styled {
+        spans.clear();
+    }
+    (message, spans)
+}
+
+lazy_static! {
+    /// `[12:00:00] [Server thread/INFO]: ...`, also matches forge's `[12:00:00] [main/INFO] [forge/]: ...`
+    static ref THREAD_HEADER: Regex =
+        Regex::new(r"^\[[^\]]+\] \[([^\]]+)/([A-Z]+)\](?: \[[^\]]*\])?: (.*)").unwrap();
+    /// `[12:00:00 INFO]: ...` as printed by paper and spigot
+    static ref LEVEL_HEADER: Regex = Regex::new(r"^\[[^\]]+ ([A-Z]+)\]: (.*)").unwrap();
+    /// A `[Name] ` prefix on the message itself, as added by bukkit plugin loggers
+    static ref PLUGIN_PREFIX: Regex = Regex::new(r"^\[([A-Za-z0-9_\-. ]+)\] ").unwrap();
+    /// Lines of a java stack trace, which carry no header of their own
+    static ref STACK_TRACE: Regex =
+        Regex::new(r"^(?:\s+at |\s*\.\.\. \d+ more|Caused by: |[\w.$]+(?:Exception|Error)(?::|$))")
+            .unwrap();
+}
+
+fn classify(message: &str) -> (Option<LogLevel>, Option<String>) {
+    let message = message.trim_end();
+    let plugin_of = |body: &str| {
+        PLUGIN_PREFIX
+            .captures(body)
+            .ok()
+            .flatten()
+            .map(|caps| caps[1].to_string())
+    };
+    if let Ok(Some(caps)) = THREAD_HEADER.captures(message) {
+        let source = plugin_of(&caps[3]).unwrap_or_else(|| caps[1].to_string());
+        return (LogLevel::from_label(&caps[2]), Some(source));
+    }
+    if let Ok(Some(caps)) = LEVEL_HEADER.captures(message) {
+        return (LogLevel::from_label(&caps[1]), plugin_of(&caps[2]));
+    }
+    if STACK_TRACE.is_match(message).unwrap_or(false) {
+        return (Some(LogLevel::Error), None);
+    }
+    (None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ansi() {
+        let line = ConsoleLine::parse("\x1b[33;1mWarning\x1b[0m plain\x1b[K\n");
+        assert_eq!(line.message, "Warning plain\n");
+        assert_eq!(
+            line.spans,
+            vec![
+                ConsoleSpan {
+                    text: "Warning".to_string(),
+                    fg: Some(ConsoleColor::Indexed(3)),
+                    bold: true,
+                    ..Default::default()
+                },
+                ConsoleSpan {
+                    text: " plain\n".to_string(),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let line = ConsoleLine::parse("\x1b[38;2;255;0;10mred");
+        assert_eq!(line.spans[0].fg, Some(ConsoleColor::Rgb(255, 0, 10)));
+
+        // no styling, no spans
+        let line = ConsoleLine::parse("\x1b]0;title\x07hello\x1b[0m");
+        assert_eq!(line.message, "hello");
+        assert!(line.spans.is_empty());
+    }
+
+    #[test]
+    fn test_classify() {
+        let line = ConsoleLine::parse("[12:00:00] [Server thread/INFO]: Done (1.234s)!\n");
+        assert_eq!(line.level, Some(LogLevel::Info));
+        assert_eq!(line.source.as_deref(), Some("Server thread"));
+
+        let line = ConsoleLine::parse("[12:00:00 WARN]: [Essentials] Config outdated");
+        assert_eq!(line.level, Some(LogLevel::Warn));
+        assert_eq!(line.source.as_deref(), Some("Essentials"));
+
+        let line = ConsoleLine::parse("[12:00:00] [main/ERROR] [forge/]: Failed");
+        assert_eq!(line.level, Some(LogLevel::Error));
+        assert_eq!(line.source.as_deref(), Some("main"));
+
+        let line = ConsoleLine::parse("\tat net.minecraft.server.Main.main(Main.java:1)");
+        assert_eq!(line.level, Some(LogLevel::Error));
+
+        let line = ConsoleLine::parse("Loading libraries, please wait...");
+        assert_eq!(line.level, None);
+        assert_eq!(line.source, None);
+    }
+}
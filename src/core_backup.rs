@@ -0,0 +1,395 @@
+//! Backup of the core's own configuration, for moving the panel to a rebuilt host.
+//!
+//! The archive holds users and their permissions, the core settings (which include alert rules
+//! and their webhooks) and the registry of instances, but none of the instances' files. It
+//! carries password hashes and stored credentials, so it should be kept as safe as the host.
+//!
+//! The users' token signing secrets are left out, a restored user gets a new one and has to log
+//! in again.
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Component, Path},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    auth::{user::User, user_id::UserId},
+    error::{Error, ErrorKind},
+    global_settings::GlobalSettingsData,
+    implementations::minecraft::MinecraftInstance,
+    prelude::VERSION,
+    traits::t_configurable::{GameType, TConfigurable},
+    types::DotLodestoneConfig,
+    AppState,
+};
+
+const FORMAT_VERSION: u32 = 1;
+const INFO_FILE: &str = "backup.json";
+const USERS_FILE: &str = "users.json";
+const GLOBAL_SETTINGS_FILE: &str = "global_settings.json";
+const INSTANCES_FILE: &str = "instances.json";
+const DOT_LODESTONE_CONFIG: &str = ".lodestone_config";
+const MINECRAFT_CONFIG: &str = ".lodestone_minecraft_config.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BackupInfo {
+    pub format: u32,
+    pub lodestone_version: String,
+    pub created_at: i64,
+}
+
+/// What is needed to register an instance again, given its files are put back in place
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstanceRecord {
+    pub dir_name: String,
+    pub dot_lodestone_config: DotLodestoneConfig,
+    pub minecraft_config: Option<serde_json::Value>,
+}
+
+#[derive(Clone)]
+pub struct CoreBackup {
+    pub info: BackupInfo,
+    pub users: HashMap<UserId, User>,
+    pub global_settings: GlobalSettingsData,
+    pub instances: Vec<InstanceRecord>,
+}
+
+impl CoreBackup {
+    pub async fn create(state: &AppState) -> Result<Self, Error> {
+        let mut instances = Vec::new();
+        for instance in state.instances.lock().await.values() {
+            let path = instance.path().await;
+            let read_json = |name: &str| {
+                let path = path.join(name);
+                async move {
+                    let content = tokio::fs::read(&path)
+                        .await
+                        .context(format!("Failed to read {}", path.display()))?;
+                    serde_json::from_slice::<serde_json::Value>(&content)
+                        .context(format!("Failed to parse {}", path.display()))
+                }
+            };
+            let dot_lodestone_config =
+                serde_json::from_value(read_json(DOT_LODESTONE_CONFIG).await?)
+                    .context("Failed to parse .lodestone_config")?;
+            let minecraft_config = match read_json(MINECRAFT_CONFIG).await {
+                Ok(config) => Some(config),
+                Err(e) => {
+                    warn!(
+                        "Leaving the game config of {} out of the backup: {e}",
+                        path.display()
+                    );
+                    None
+                }
+            };
+            instances.push(InstanceRecord {
+                dir_name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .ok_or_else(|| eyre!("Instance path {} has no name", path.display()))?,
+                dot_lodestone_config,
+                minecraft_config,
+            });
+        }
+        Ok(Self {
+            info: BackupInfo {
+                format: FORMAT_VERSION,
+                lodestone_version: VERSION.with(|v| v.to_string()),
+                created_at: chrono::Utc::now().timestamp(),
+            },
+            users: state.users_manager.read().await.as_ref().clone(),
+            global_settings: state.global_settings.lock().await.as_ref().clone(),
+            instances,
+        })
+    }
+
+    /// A gzipped tarball of one json file per part
+    pub fn to_archive(&self) -> Result<Vec<u8>, Error> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let mtime = self.info.created_at.max(0) as u64;
+        append_json(&mut builder, INFO_FILE, &self.info, mtime)?;
+        let users = self
+            .users
+            .iter()
+            .map(|(uid, user)| {
+                let mut user = serde_json::to_value(user).context("Failed to serialize a user")?;
+                if let Some(user) = user.as_object_mut() {
+                    user.remove("secret");
+                }
+                Ok((uid, user))
+            })
+            .collect::<Result<HashMap<_, _>, Error>>()?;
+        append_json(&mut builder, USERS_FILE, &users, mtime)?;
+        append_json(
+            &mut builder,
+            GLOBAL_SETTINGS_FILE,
+            &self.global_settings,
+            mtime,
+        )?;
+        append_json(&mut builder, INSTANCES_FILE, &self.instances, mtime)?;
+        Ok(builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .context("Failed to write the backup")?)
+    }
+
+    pub fn from_archive(archive: &[u8]) -> Result<Self, Error> {
+        let mut files = HashMap::new();
+        for entry in tar::Archive::new(GzDecoder::new(archive))
+            .entries()
+            .context("Failed to read the backup")?
+        {
+            let mut entry = entry.context("Failed to read the backup")?;
+            let name = entry
+                .path()
+                .context("Failed to read the backup")?
+                .to_string_lossy()
+                .to_string();
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .context(format!("Failed to read {name} from the backup"))?;
+            files.insert(name, content);
+        }
+        fn part<T: DeserializeOwned>(
+            files: &HashMap<String, Vec<u8>>,
+            name: &str,
+        ) -> Result<T, Error> {
+            let content = files.get(name).ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The backup has no {name}"),
+            })?;
+            serde_json::from_slice(content).map_err(|e| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid {name} in the backup: {e}"),
+            })
+        }
+        let info: BackupInfo = part(&files, INFO_FILE)?;
+        if info.format > FORMAT_VERSION {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "The backup was made by a newer core ({}), update this one first",
+                    info.lodestone_version
+                ),
+            });
+        }
+        let instances: Vec<InstanceRecord> = part(&files, INSTANCES_FILE)?;
+        if let Some(record) = instances
+            .iter()
+            .find(|record| !is_plain_dir_name(&record.dir_name))
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid instance directory name {:?}", record.dir_name),
+            });
+        }
+        Ok(Self {
+            info,
+            // secrets are not in the archive, so each user gets a fresh one
+            users: part(&files, USERS_FILE)?,
+            global_settings: part(&files, GLOBAL_SETTINGS_FILE)?,
+            instances,
+        })
+    }
+
+    /// Only done on a fresh install, with no owner and no instances yet.
+    ///
+    /// Instances are registered again with their config only, their files are expected to be
+    /// copied back into the instance directories separately.
+    pub async fn restore(self, state: &AppState) -> Result<(), Error> {
+        if !state.instances.lock().await.is_empty()
+            || state
+                .users_manager
+                .read()
+                .await
+                .as_ref()
+                .values()
+                .any(|user| user.is_owner)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A backup can only be restored on a fresh install"),
+            });
+        }
+        if !self.users.values().any(|user| user.is_owner) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The backup has no owner account"),
+            });
+        }
+        let mut global_settings = state.global_settings.lock().await;
+        global_settings.restore(self.global_settings).await?;
+        let mut users_manager = state.users_manager.write().await;
+        users_manager.set_password_hashing(global_settings.password_hashing());
+        users_manager.restore_users(self.users).await?;
+        drop(users_manager);
+
+        let mut instances = state.instances.lock().await;
+        for record in self.instances {
+            let location = global_settings
+                .storage_location(Some(record.dot_lodestone_config.storage_location()))
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Restoring {} to the default storage location: {e}",
+                        record.dir_name
+                    );
+                    crate::storage::StorageLocation::default_location()
+                });
+            let path = location.path.join(&record.dir_name);
+            if path.exists() {
+                warn!(
+                    "Not restoring instance {}, it already exists",
+                    path.display()
+                );
+                continue;
+            }
+            let minecraft_config = match record.minecraft_config {
+                Some(config) => config,
+                None => {
+                    warn!(
+                        "Not restoring instance {}, its config was not backed up",
+                        record.dir_name
+                    );
+                    continue;
+                }
+            };
+            crate::util::fs::create_dir_all(&path).await?;
+            for (name, content) in [
+                (
+                    DOT_LODESTONE_CONFIG,
+                    serde_json::to_vec_pretty(&record.dot_lodestone_config),
+                ),
+                (
+                    MINECRAFT_CONFIG,
+                    serde_json::to_vec_pretty(&minecraft_config),
+                ),
+            ] {
+                let content = content.context(format!("Failed to serialize {name}"))?;
+                tokio::fs::write(path.join(name), content)
+                    .await
+                    .context(format!("Failed to write {}", path.join(name).display()))?;
+            }
+            if let GameType::MinecraftJava = record.dot_lodestone_config.game_type() {
+                match MinecraftInstance::restore(
+                    path.clone(),
+                    record.dot_lodestone_config.clone(),
+                    state.event_broadcaster.clone(),
+                    state.macro_executor.clone(),
+                )
+                .await
+                {
+                    Ok(instance) => {
+                        instances
+                            .insert(record.dot_lodestone_config.uuid().clone(), instance.into());
+                    }
+                    Err(e) => warn!(
+                        "Failed to register restored instance {}: {e}",
+                        path.display()
+                    ),
+                }
+            }
+        }
+        state.first_time_setup_key.lock().await.take();
+        Ok(())
+    }
+}
+
+/// A single directory name that can't reach outside of the storage location
+fn is_plain_dir_name(name: &str) -> bool {
+    !name.contains(['/', '\\'])
+        && matches!(
+            Path::new(name).components().collect::<Vec<_>>().as_slice(),
+            [Component::Normal(_)]
+        )
+}
+
+fn append_json(
+    builder: &mut tar::Builder<GzEncoder<Vec<u8>>>,
+    name: &str,
+    value: &impl Serialize,
+    mtime: u64,
+) -> Result<(), Error> {
+    let content =
+        serde_json::to_vec_pretty(value).context(format!("Failed to serialize {name}"))?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, name, content.as_slice())
+        .context(format!("Failed to add {name} to the backup"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{hashed_password::PasswordHashing, permission::UserPermission},
+        types::InstanceUuid,
+    };
+
+    #[test]
+    fn test_archive_round_trip() {
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+            &PasswordHashing {
+                memory_kib: 1024,
+                iterations: 1,
+                parallelism: 1,
+            },
+        );
+        let backup = CoreBackup {
+            info: BackupInfo {
+                format: FORMAT_VERSION,
+                lodestone_version: "0.4.4".to_string(),
+                created_at: 0,
+            },
+            users: HashMap::from([(owner.uid.clone(), owner.clone())]),
+            global_settings: GlobalSettingsData::default(),
+            instances: vec![InstanceRecord {
+                dir_name: "survival".to_string(),
+                dot_lodestone_config: DotLodestoneConfig::new(
+                    InstanceUuid::default(),
+                    GameType::MinecraftJava,
+                ),
+                minecraft_config: Some(serde_json::json!({ "name": "Survival" })),
+            }],
+        };
+        let restored = CoreBackup::from_archive(&backup.to_archive().unwrap()).unwrap();
+        assert_eq!(restored.users[&owner.uid].username, "owner");
+        assert_ne!(restored.users[&owner.uid].secret, owner.secret);
+        assert_eq!(
+            restored.global_settings.core_name,
+            backup.global_settings.core_name
+        );
+        assert_eq!(restored.instances[0].dir_name, "survival");
+        assert_eq!(
+            restored.instances[0].dot_lodestone_config.uuid(),
+            backup.instances[0].dot_lodestone_config.uuid()
+        );
+
+        let mut escaping = backup.clone();
+        escaping.instances[0].dir_name = "../escaped".to_string();
+        assert!(CoreBackup::from_archive(&escaping.to_archive().unwrap()).is_err());
+        escaping.instances[0].dir_name = "..".to_string();
+        assert!(CoreBackup::from_archive(&escaping.to_archive().unwrap()).is_err());
+
+        let mut newer = backup;
+        newer.info.format = FORMAT_VERSION + 1;
+        assert!(CoreBackup::from_archive(&newer.to_archive().unwrap()).is_err());
+        assert!(CoreBackup::from_archive(b"not a backup").is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! Declarative configuration of the core, for headless deployments such as Docker images.
+//!
+//! Read at startup from the file given with `--config` or `LODESTONE_CONFIG`, or else from
+//! `lodestone.toml` in the lodestone directory if there is one. Environment variables override
+//! the file, command line arguments override both:
+//!
+//! ```toml
+//! data_dir = "/data"              # LODESTONE_PATH, only read from a file given explicitly
+//!
+//! [listener]
+//! address = "0.0.0.0"             # LODESTONE_ADDRESS
+//! port = 16662                    # LODESTONE_PORT
+//! tls_cert = "/certs/cert.pem"    # defaults to tls/cert.pem in the lodestone directory
+//! tls_key = "/certs/key.pem"
+//!
+//! [owner]                         # created on first start instead of going through setup
+//! username = "admin"              # LODESTONE_OWNER_USERNAME
+//! password_file = "/run/secrets/owner_password"  # or password, LODESTONE_OWNER_PASSWORD(_FILE)
+//!
+//! [limits]
+//! admin = { max_instances = 10 }
+//! user = { max_instances = 2, max_ram = 8192 }
+//! rate_limit = { enabled = true, requests_per_minute = 120, burst = 30 }
+//!
+//! [telemetry]
+//! enabled = true                  # LODESTONE_TELEMETRY_ENABLED
+//! endpoint = "https://telemetry.example.com"  # LODESTONE_TELEMETRY_ENDPOINT
+//! interval_hours = 24
+//! ```
+//!
+//! Limits and telemetry are written to the global settings on every start, so the file stays
+//! the source of truth for whatever it sets. Everything it leaves out can still be changed
+//! through the API.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    auth::{
+        permission::UserPermission,
+        user::{User, UsersManager},
+    },
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    global_settings::GlobalSettings,
+    quota::{QuotaRole, UserQuota},
+    rate_limiter::RateLimitConfig,
+};
+
+pub const CONFIG_FILE: &str = "lodestone.toml";
+pub const DEFAULT_PORT: u16 = 16_662;
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoreConfig {
+    pub data_dir: Option<PathBuf>,
+    pub listener: ListenerConfig,
+    pub owner: Option<OwnerConfig>,
+    pub limits: LimitsConfig,
+    pub telemetry: TelemetryConfig,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub address: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OwnerConfig {
+    pub username: String,
+    pub password: Option<String>,
+    /// Read instead of `password`, for secrets mounted as files
+    pub password_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub admin: Option<UserQuota>,
+    pub user: Option<UserQuota>,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub enabled: Option<bool>,
+    pub endpoint: Option<String>,
+    pub interval_hours: Option<u32>,
+}
+
+fn invalid(message: String) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!(message),
+    }
+}
+
+impl CoreConfig {
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        toml::from_str(content).map_err(|e| invalid(format!("Invalid {CONFIG_FILE}: {e}")))
+    }
+
+    /// Reads the file if it exists, `required` makes a missing file an error
+    pub fn load(path: &Path, required: bool) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(e) => Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("Failed to read {}: {e}", path.display()),
+            }),
+        }
+    }
+
+    /// Overrides the file with the environment, `var` being `std::env::var` outside of tests
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), Error> {
+        let var = |name: &str| var(name).filter(|value| !value.is_empty());
+        if let Some(path) = var("LODESTONE_PATH") {
+            self.data_dir = Some(PathBuf::from(path));
+        }
+        if let Some(address) = var("LODESTONE_ADDRESS") {
+            self.listener.address = Some(
+                address
+                    .parse()
+                    .map_err(|e| invalid(format!("Invalid LODESTONE_ADDRESS: {e}")))?,
+            );
+        }
+        if let Some(port) = var("LODESTONE_PORT") {
+            self.listener.port = Some(
+                port.parse()
+                    .map_err(|e| invalid(format!("Invalid LODESTONE_PORT: {e}")))?,
+            );
+        }
+        if let Some(username) = var("LODESTONE_OWNER_USERNAME") {
+            self.owner.get_or_insert_with(OwnerConfig::default).username = username;
+        }
+        if let Some(password) = var("LODESTONE_OWNER_PASSWORD") {
+            let owner = self.owner.get_or_insert_with(OwnerConfig::default);
+            owner.password = Some(password);
+            owner.password_file = None;
+        }
+        if let Some(path) = var("LODESTONE_OWNER_PASSWORD_FILE") {
+            let owner = self.owner.get_or_insert_with(OwnerConfig::default);
+            owner.password = None;
+            owner.password_file = Some(PathBuf::from(path));
+        }
+        if let Some(enabled) = var("LODESTONE_TELEMETRY_ENABLED") {
+            self.telemetry.enabled = Some(
+                enabled
+                    .parse()
+                    .map_err(|e| invalid(format!("Invalid LODESTONE_TELEMETRY_ENABLED: {e}")))?,
+            );
+        }
+        if let Some(endpoint) = var("LODESTONE_TELEMETRY_ENDPOINT") {
+            self.telemetry.endpoint = Some(endpoint);
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(owner) = &self.owner {
+            if owner.username.is_empty() {
+                return Err(invalid("The owner needs a username".to_string()));
+            }
+            if owner.password.is_some() == owner.password_file.is_some() {
+                return Err(invalid(
+                    "The owner needs either a password or a password file".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the configuration, and works out the lodestone directory from it unless given one
+    pub fn resolve(
+        config_path: Option<PathBuf>,
+        lodestone_path: Option<PathBuf>,
+        default_lodestone_path: impl FnOnce() -> PathBuf,
+    ) -> Result<(Self, PathBuf), Error> {
+        let env = |name: &str| std::env::var(name).ok();
+        let config_path = config_path.or_else(|| {
+            env("LODESTONE_CONFIG")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+        });
+        let mut config = match &config_path {
+            Some(path) => Self::load(path, true)?,
+            None => Self::default(),
+        };
+        config.apply_env(env)?;
+        let lodestone_path = lodestone_path
+            .or_else(|| config.data_dir.clone())
+            .unwrap_or_else(default_lodestone_path);
+        if config_path.is_none() {
+            config = Self::load(&lodestone_path.join(CONFIG_FILE), false)?;
+            config.apply_env(env)?;
+        }
+        config.validate()?;
+        Ok((config, lodestone_path))
+    }
+
+    pub fn listen_address(&self) -> SocketAddr {
+        SocketAddr::new(
+            self.listener
+                .address
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            self.listener.port.unwrap_or(DEFAULT_PORT),
+        )
+    }
+}
+
+impl OwnerConfig {
+    fn password(&self) -> Result<String, Error> {
+        match (&self.password, &self.password_file) {
+            (Some(password), _) => Ok(password.clone()),
+            (None, Some(path)) => Ok(std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string()),
+            (None, None) => Err(invalid("The owner has no password".to_string())),
+        }
+    }
+}
+
+/// Creates the owner if there is none yet, returns whether it did
+pub async fn bootstrap_owner(
+    users_manager: &mut UsersManager,
+    owner: &OwnerConfig,
+) -> Result<bool, Error> {
+    if users_manager.as_ref().iter().any(|(_, user)| user.is_owner) {
+        return Ok(false);
+    }
+    let user = User::new(
+        owner.username.clone(),
+        owner.password()?,
+        true,
+        false,
+        UserPermission::default(),
+        &users_manager.password_hashing(),
+    );
+    users_manager.add_user(user, CausedBy::System).await?;
+    info!("Created owner {} from the configuration", owner.username);
+    Ok(true)
+}
+
+/// Writes the limits and telemetry of the configuration to the global settings
+pub async fn apply_settings(
+    global_settings: &mut GlobalSettings,
+    config: &CoreConfig,
+) -> Result<(), Error> {
+    let quotas = global_settings.quotas().clone();
+    if let Some(admin) = config.limits.admin {
+        if quotas.admin != admin {
+            global_settings
+                .set_role_quota(QuotaRole::Admin, admin)
+                .await?;
+        }
+    }
+    if let Some(user) = config.limits.user {
+        if quotas.user != user {
+            global_settings
+                .set_role_quota(QuotaRole::User, user)
+                .await?;
+        }
+    }
+    if let Some(rate_limit) = config.limits.rate_limit {
+        if global_settings.rate_limit() != rate_limit {
+            global_settings.set_rate_limit(rate_limit).await?;
+        }
+    }
+    let mut telemetry = global_settings.telemetry();
+    if let Some(enabled) = config.telemetry.enabled {
+        telemetry.enabled = enabled;
+    }
+    if let Some(endpoint) = &config.telemetry.endpoint {
+        telemetry.endpoint = Some(endpoint.clone());
+    }
+    if let Some(interval_hours) = config.telemetry.interval_hours {
+        telemetry.interval_hours = interval_hours;
+    }
+    if telemetry != global_settings.telemetry() {
+        telemetry.validate()?;
+        global_settings.set_telemetry(telemetry).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_core_config() {
+        let mut config = CoreConfig::parse(
+            r#"
+            data_dir = "/data"
+
+            [listener]
+            port = 8080
+
+            [owner]
+            username = "admin"
+            password = "hunter22"
+
+            [limits]
+            user = { max_instances = 2 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.data_dir, Some(PathBuf::from("/data")));
+        assert_eq!(config.limits.user.unwrap().max_instances, Some(2));
+        assert_eq!(config.limits.admin, None);
+        config.validate().unwrap();
+
+        let env: HashMap<&str, &str> = [
+            ("LODESTONE_PORT", "9090"),
+            ("LODESTONE_ADDRESS", "127.0.0.1"),
+            ("LODESTONE_OWNER_PASSWORD_FILE", "/run/secrets/owner"),
+            ("LODESTONE_TELEMETRY_ENDPOINT", ""),
+        ]
+        .into_iter()
+        .collect();
+        config
+            .apply_env(|name| env.get(name).map(|value| value.to_string()))
+            .unwrap();
+        assert_eq!(config.listen_address().to_string(), "127.0.0.1:9090");
+        let owner = config.owner.as_ref().unwrap();
+        assert_eq!(owner.password, None);
+        assert_eq!(
+            owner.password_file,
+            Some(PathBuf::from("/run/secrets/owner"))
+        );
+        assert_eq!(config.telemetry.endpoint, None);
+
+        assert!(CoreConfig::parse("[listener]\nprot = 8080").is_err());
+        assert!(CoreConfig::parse("[owner]\nusername = \"admin\"")
+            .unwrap()
+            .validate()
+            .is_err());
+        assert_eq!(
+            CoreConfig::default().listen_address().to_string(),
+            "0.0.0.0:16662"
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Versioned schema migrations of the SQLite store.
+//!
+//! Migrations are the `<version>_<description>.sql` files in the `migrations` directory, embedded
+//! at compile time and applied in order on startup. Applied versions are recorded by sqlx in the
+//! `_sqlx_migrations` table. A migration must never be edited once released, add a new one instead.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::sqlite::SqlitePool;
+use tracing::info;
+
+use crate::error::{Error, ErrorKind};
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Applies pending migrations, copying the database next to `path_to_db` first
+/// if there is anything in it to lose
+pub async fn run_migrations(pool: &SqlitePool, path_to_db: &Path) -> Result<(), Error> {
+    let pending = pending_migrations(pool).await?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+    if has_data(pool).await? {
+        let backup = backup_path(path_to_db);
+        info!(
+            "Backing up the database to {} before migrating",
+            backup.display()
+        );
+        sqlx::query("VACUUM INTO ?")
+            .bind(backup.to_string_lossy().into_owned())
+            .execute(pool)
+            .await
+            .context("Failed to back up the database, it will not be migrated")?;
+    }
+    info!("Applying database migrations {:?}", pending);
+    MIGRATOR.run(pool).await.map_err(|e| Error {
+        kind: ErrorKind::Internal,
+        source: eyre!("Failed to migrate the database: {e}"),
+    })
+}
+
+async fn pending_migrations(pool: &SqlitePool) -> Result<Vec<i64>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    connection
+        .ensure_migrations_table()
+        .await
+        .context("Failed to create the migrations table")?;
+    let applied = connection
+        .list_applied_migrations()
+        .await
+        .context("Failed to list applied migrations")?;
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.iter().any(|applied| applied.version == *version))
+        .collect())
+}
+
+/// Whether any table other than sqlx's own exists
+async fn has_data(pool: &SqlitePool) -> Result<bool, Error> {
+    let tables: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' \
+         AND name NOT LIKE '\\_sqlx%' ESCAPE '\\' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\'",
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to inspect the database")?;
+    Ok(tables > 0)
+}
+
+fn backup_path(path_to_db: &Path) -> PathBuf {
+    let mut file_name = path_to_db
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_else(|| "data.db".into());
+    file_name.push(format!(
+        ".pre-migration-{}.bak",
+        chrono::Utc::now().timestamp()
+    ));
+    path_to_db.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        let versions: Vec<i64> = MIGRATOR.iter().map(|migration| migration.version).collect();
+        assert!(!versions.is_empty());
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
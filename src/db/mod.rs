@@ -1,3 +1,4 @@
+pub mod migrate;
 pub mod read;
 pub mod types;
 pub mod write;
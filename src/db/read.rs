@@ -1,9 +1,13 @@
 use crate::{
-    error::Error, output_types::ClientEvent,
-    prelude::LODESTONE_EPOCH_MIL, events::EventQuery,
+    error::{Error, ErrorKind},
+    events::EventQuery,
+    output_types::ClientEvent,
+    prelude::LODESTONE_EPOCH_MIL,
+    types::{Snowflake, TimeRange},
 };
 
-use color_eyre::eyre::Context;
+use color_eyre::eyre::{eyre, Context};
+use futures::{Stream, StreamExt};
 use sqlx::sqlite::SqlitePool;
 use tracing::error;
 
@@ -69,6 +73,84 @@ FROM ClientEvents"#
     Ok(filtered)
 }
 
+/// Returns up to `limit` events stored after `cursor`, oldest first
+///
+/// Used to replay the events a client missed while its event stream was disconnected,
+/// a page at a time
+pub async fn events_after_snowflake(
+    pool: &SqlitePool,
+    cursor: Snowflake,
+    limit: i64,
+) -> Result<Vec<ClientEvent>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let rows = sqlx::query!(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE snowflake > ($1)
+ORDER BY snowflake ASC
+LIMIT ($2)"#,
+        cursor,
+        limit
+    )
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch events")?;
+    let mut parsed_client_events: Vec<ClientEvent> = Vec::new();
+    for row in rows {
+        if let Ok(client_event) = serde_json::from_str(&row.event_value) {
+            parsed_client_events.push(client_event);
+        } else {
+            error!("Failed to parse client event: {}", row.event_value);
+        }
+    }
+    Ok(parsed_client_events)
+}
+
+/// Every event stored within `time_range`, oldest first, read from the db as the stream is consumed
+pub fn stream_events(
+    pool: &SqlitePool,
+    time_range: Option<TimeRange>,
+) -> impl Stream<Item = Result<ClientEvent, Error>> + Send + '_ {
+    let (start, end) = match time_range {
+        Some(time_range) => (
+            (time_range.start - LODESTONE_EPOCH_MIL.with(|p| *p)) << 22,
+            (time_range.end + 1 - LODESTONE_EPOCH_MIL.with(|p| *p)) << 22,
+        ),
+        None => (i64::MIN, i64::MAX),
+    };
+    sqlx::query!(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE snowflake >= ($1) AND snowflake <= ($2)
+ORDER BY snowflake ASC"#,
+        start,
+        end
+    )
+    .fetch(pool)
+    .filter_map(|row| async move {
+        match row {
+            Ok(row) => match serde_json::from_str(&row.event_value) {
+                Ok(client_event) => Some(Ok(client_event)),
+                Err(_) => {
+                    error!("Failed to parse client event: {}", row.event_value);
+                    None
+                }
+            },
+            Err(e) => Some(Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("Failed to fetch events: {e}"),
+            })),
+        }
+    })
+}
+
 #[cfg(test)]
 #[allow(unused_imports)]
 mod tests {
@@ -77,7 +159,7 @@ mod tests {
     use sqlx::{sqlite::SqliteConnectOptions, Pool, Sqlite};
 
     use crate::{
-        db::write::init_client_events_table,
+        db::migrate::MIGRATOR,
         events::{CausedBy, EventInner, EventLevel, FSEvent, FSOperation, FSTarget},
         types::Snowflake,
     };
@@ -97,7 +179,11 @@ mod tests {
             .execute(&pool)
             .await;
         assert!(drop_result.is_ok());
-        let init_result = init_client_events_table(&pool).await;
+        let drop_result = sqlx::query(r#"DROP TABLE IF EXISTS _sqlx_migrations"#)
+            .execute(&pool)
+            .await;
+        assert!(drop_result.is_ok());
+        let init_result = MIGRATOR.run(&pool).await;
         assert!(init_result.is_ok());
 
         let snowflake = Snowflake::new();
@@ -1,61 +1,179 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
 use crate::{
     error::Error,
     events::{Event, EventInner, ProgressionEventInner},
     output_types::ClientEvent,
+    types::InstanceUuid,
 };
 
 use color_eyre::eyre::Context;
-use sqlx::sqlite::SqlitePool;
-use tokio::sync::broadcast::{error::RecvError, Receiver};
+use sqlx::sqlite::{SqliteConnection, SqlitePool};
+use tokio::sync::broadcast::{
+    error::{RecvError, TryRecvError},
+    Receiver,
+};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, warn};
 
 use super::types::ClientEventRow;
 
 // TODO clean up all unwraps
 
-pub async fn write_event_to_db_task(mut event_receiver: Receiver<Event>, sqlite_pool: SqlitePool) {
-    let init_result = init_client_events_table(&sqlite_pool).await;
-    if let Err(error) = init_result.as_ref() {
-        warn!("Failed to initialize client events table: {}", error);
-        return;
+/// Queued events are written at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+/// Queued events are written right away once there are this many
+const MAX_BATCH_SIZE: usize = 500;
+/// Console lines saved per instance each second, the rest are dropped
+const CONSOLE_LINES_PER_SECOND: u32 = 200;
+
+/// Caps how many console lines of each instance are saved, so a chatty instance
+/// can't hold up saving everything else
+#[derive(Default)]
+struct ConsoleThrottle {
+    window_start: Option<Instant>,
+    saved: HashMap<InstanceUuid, u32>,
+    dropped: HashMap<InstanceUuid, u64>,
+}
+
+impl ConsoleThrottle {
+    fn admit(&mut self, event: &Event, now: Instant) -> bool {
+        if !event.is_event_console_message() {
+            return true;
+        }
+        let uuid = match event.get_instance_uuid() {
+            Some(uuid) => uuid,
+            None => return true,
+        };
+        match self.window_start {
+            Some(start) if now.duration_since(start) < Duration::from_secs(1) => {}
+            _ => {
+                self.window_start = Some(now);
+                self.saved.clear();
+                for (uuid, dropped) in self.dropped.drain() {
+                    warn!("Dropped {dropped} console lines of instance {uuid} from the event database, it is logging too fast");
+                }
+            }
+        }
+        let saved = self.saved.entry(uuid.clone()).or_default();
+        if *saved < CONSOLE_LINES_PER_SECOND {
+            *saved += 1;
+            true
+        } else {
+            *self.dropped.entry(uuid).or_default() += 1;
+            false
+        }
     }
+}
 
+/// Writes every broadcasted event to the db until `shutdown` is cancelled,
+/// at which point the events still queued in the channel are flushed before returning.
+/// Events are written in batches, each in a single transaction.
+pub async fn write_event_to_db_task(
+    mut event_receiver: Receiver<Event>,
+    sqlite_pool: SqlitePool,
+    shutdown: CancellationToken,
+) {
+    let mut batch = Vec::new();
+    let mut throttle = ConsoleThrottle::default();
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
     loop {
-        let result = event_receiver.recv().await;
-        if let Err(error) = result.as_ref() {
-            match error {
-                RecvError::Lagged(_) => {
-                    warn!("Event buffer lagged");
-                    continue;
+        tokio::select! {
+            result = event_receiver.recv() => match result {
+                Ok(event) => {
+                    if throttle.admit(&event, Instant::now()) {
+                        batch.push(event);
+                    }
+                    if batch.len() >= MAX_BATCH_SIZE {
+                        flush(&sqlite_pool, &mut batch).await;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Event buffer lagged, {skipped} events were not saved");
                 }
-                RecvError::Closed => {
+                Err(RecvError::Closed) => {
                     warn!("Event buffer closed");
+                    flush(&sqlite_pool, &mut batch).await;
                     break;
                 }
+            },
+            _ = interval.tick() => flush(&sqlite_pool, &mut batch).await,
+            _ = shutdown.cancelled() => {
+                flush_remaining_events(&mut event_receiver, &mut batch, &mut throttle);
+                flush(&sqlite_pool, &mut batch).await;
+                break;
             }
         }
+    }
+}
 
-        let client_event: ClientEvent = result.unwrap().into();
+fn flush_remaining_events(
+    event_receiver: &mut Receiver<Event>,
+    batch: &mut Vec<Event>,
+    throttle: &mut ConsoleThrottle,
+) {
+    loop {
+        match event_receiver.try_recv() {
+            Ok(event) => {
+                if throttle.admit(&event, Instant::now()) {
+                    batch.push(event);
+                }
+            }
+            Err(TryRecvError::Lagged(_)) => warn!("Event buffer lagged"),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => return,
+        }
+    }
+}
+
+/// Writes and empties `batch`, the events are dropped if that fails
+async fn flush(sqlite_pool: &SqlitePool, batch: &mut Vec<Event>) {
+    if batch.is_empty() {
+        return;
+    }
+    let events = std::mem::take(batch);
+    let count = events.len();
+    if let Err(e) = write_events(sqlite_pool, events).await {
+        error!("Error inserting {count} events into database: {}", e);
+    }
+}
+
+async fn write_events(sqlite_pool: &SqlitePool, events: Vec<Event>) -> Result<(), Error> {
+    let mut transaction = sqlite_pool
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+    for event in events {
+        let client_event: ClientEvent = event.into();
         if let EventInner::ProgressionEvent(pe) = &client_event.event_inner {
             if let ProgressionEventInner::ProgressionUpdate { .. } = pe.progression_event_inner() {
                 continue;
             }
         }
-        let insertion_result = write_client_event(&sqlite_pool, client_event).await;
-        if let Err(e) = insertion_result.as_ref() {
-            error!("Error inserting into database: {}", e);
-            break;
-        }
+        insert_client_event(&mut transaction, &client_event).await?;
     }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit events")?;
+    Ok(())
 }
 
+#[cfg(test)]
 async fn write_client_event(pool: &SqlitePool, client_event: ClientEvent) -> Result<i64, Error> {
     let mut connection = pool
         .acquire()
         .await
         .context("Failed to aquire db connection")?;
+    insert_client_event(&mut connection, &client_event).await
+}
 
-    let row = ClientEventRow::from(&client_event);
+async fn insert_client_event(
+    connection: &mut SqliteConnection,
+    client_event: &ClientEvent,
+) -> Result<i64, Error> {
+    let row = ClientEventRow::from(client_event);
     let id = sqlx::query!(
         r#"
 INSERT INTO ClientEvents
@@ -70,39 +188,13 @@ VALUES
         row.caused_by_user_id,
         row.instance_id,
     )
-    .execute(&mut connection)
+    .execute(&mut *connection)
     .await
     .context("Failed to write to DB")?
     .last_insert_rowid();
     Ok(id)
 }
 
-pub async fn init_client_events_table(pool: &SqlitePool) -> Result<(), Error> {
-    let mut connection = pool
-        .acquire()
-        .await
-        .context("Failed to aquire db connection")?;
-
-    sqlx::query!(
-        r#"
-        CREATE TABLE IF NOT EXISTS ClientEvents (
-            id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
-            event_value         TEXT        NOT NULL,
-            details             TEXT        NOT NULL,
-            snowflake           BIGINT      NOT NULL,
-            level               VARCHAR(20) NOT NULL,
-            caused_by_user_id   TEXT,
-            instance_id         TEXT
-        );
-        "#
-    )
-    .execute(&mut connection)
-    .await
-    .context("Failed to create table")?;
-
-    Ok(())
-}
-
 #[cfg(test)]
 #[allow(unused_imports)]
 
@@ -112,6 +204,7 @@ mod tests {
     use sqlx::{sqlite::SqliteConnectOptions, Pool};
 
     use crate::{
+        db::migrate::MIGRATOR,
         events::{CausedBy, EventLevel, FSEvent, FSOperation, FSTarget},
         types::Snowflake,
     };
@@ -131,7 +224,11 @@ mod tests {
             .execute(&pool)
             .await;
         assert!(drop_result.is_ok());
-        let init_result = init_client_events_table(&pool).await;
+        let drop_result = sqlx::query(r#"DROP TABLE IF EXISTS _sqlx_migrations"#)
+            .execute(&pool)
+            .await;
+        assert!(drop_result.is_ok());
+        let init_result = MIGRATOR.run(&pool).await;
         assert!(init_result.is_ok());
         let snowflake = Snowflake::new();
         let dummy_event = ClientEvent {
@@ -166,4 +263,20 @@ mod tests {
         assert_eq!(row.caused_by_user_id, None);
         assert_eq!(row.instance_id, None);
     }
+
+    #[test]
+    fn test_console_throttle() {
+        let mut throttle = ConsoleThrottle::default();
+        let uuid = InstanceUuid::default();
+        let line =
+            || Event::new_system_message(uuid.clone(), "Test".to_string(), "line".to_string());
+        let start = Instant::now();
+        for _ in 0..CONSOLE_LINES_PER_SECOND {
+            assert!(throttle.admit(&line(), start));
+        }
+        assert!(!throttle.admit(&line(), start));
+        assert_eq!(throttle.dropped.get(&uuid), Some(&1));
+        assert!(throttle.admit(&line(), start + Duration::from_secs(1)));
+        assert!(throttle.dropped.is_empty());
+    }
 }
@@ -0,0 +1,124 @@
+//! Free space checks done before anything that writes a lot to disk,
+//! so an operation fails up front instead of filling the disk half way through.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sysinfo::{DiskExt, System, SystemExt};
+use tracing::warn;
+use ts_rs::TS;
+use walkdir::WalkDir;
+
+use crate::{
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    prelude::path_to_snapshots,
+    snapshot,
+    types::{InstanceUuid, Snowflake},
+    util::format_byte,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct DiskSpaceConfig {
+    /// Bytes that must be left free once an operation is done
+    pub min_free_space: u64,
+    /// Delete the oldest snapshots of the instance being written for instead of failing
+    pub prune_snapshots: bool,
+}
+
+impl Default for DiskSpaceConfig {
+    fn default() -> Self {
+        Self {
+            min_free_space: 1024 * 1024 * 1024,
+            prune_snapshots: false,
+        }
+    }
+}
+
+/// Mount point and available bytes of the disk holding `path`, which does not have to exist yet
+fn disk_of(path: &Path) -> Option<(PathBuf, u64)> {
+    let path = path
+        .ancestors()
+        .find_map(|ancestor| ancestor.canonicalize().ok())?;
+    let mut system = System::new();
+    system.refresh_disks_list();
+    system
+        .disks()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| (disk.mount_point().to_path_buf(), disk.available_space()))
+}
+
+/// Bytes available on the disk holding `path`
+pub fn available_space(path: &Path) -> Option<u64> {
+    disk_of(path).map(|(_, available)| available)
+}
+
+/// Total size of the files under `path`, to estimate what copying it will take
+pub async fn dir_size(path: PathBuf) -> u64 {
+    tokio::task::spawn_blocking(move || {
+        WalkDir::new(path)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| entry.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    })
+    .await
+    .unwrap_or(0)
+}
+
+/// Fails unless `required` bytes can be written under `path` while keeping the configured reserve free.
+///
+/// If allowed to, the oldest snapshots of `instance`, the one being written for, other than
+/// `protected` are deleted first to make room, as long as they are on the same disk. Snapshots
+/// of other instances are never touched, they may belong to someone else.
+pub async fn ensure_space(
+    config: &DiskSpaceConfig,
+    path: &Path,
+    required: u64,
+    instance: Option<&InstanceUuid>,
+    protected: Option<Snowflake>,
+) -> Result<(), Error> {
+    let (mount_point, mut available) = match disk_of(path) {
+        Some(disk) => disk,
+        // can't tell, better not get in the way
+        None => return Ok(()),
+    };
+    let needed = required.saturating_add(config.min_free_space);
+    if available < needed
+        && config.prune_snapshots
+        && disk_of(path_to_snapshots()).map(|(mount_point, _)| mount_point) == Some(mount_point)
+    {
+        let old_snapshots = match instance {
+            Some(instance) => snapshot::list_snapshots(instance).await?,
+            None => Vec::new(),
+        };
+        for old_snapshot in old_snapshots {
+            if available >= needed {
+                break;
+            }
+            if Some(old_snapshot.id) == protected {
+                continue;
+            }
+            warn!(
+                "Deleting snapshot {} of instance {} to free up disk space",
+                old_snapshot.name, old_snapshot.instance_uuid
+            );
+            snapshot::delete_snapshot(&old_snapshot.instance_uuid, old_snapshot.id).await?;
+            available = disk_of(path).map_or(available, |(_, available)| available);
+        }
+    }
+    if available < needed {
+        return Err(Error::localized(
+            ErrorKind::InsufficientStorage,
+            LocalizedMessage::new(MessageId::InsufficientDiskSpace)
+                .with_param("required", format_byte(needed))
+                .with_param("available", format_byte(available)),
+        ));
+    }
+    Ok(())
+}
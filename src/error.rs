@@ -9,6 +9,8 @@ use serde_json::json;
 use thiserror::Error;
 use ts_rs::TS;
 
+use crate::i18n::{LocalizedMessage, RenderedMessage};
+
 #[derive(Debug, Clone, Deserialize, Serialize, TS)]
 #[ts(export)]
 pub enum ErrorKind {
@@ -17,6 +19,9 @@ pub enum ErrorKind {
     BadRequest,
     PermissionDenied,
     Unauthorized,
+    InsufficientStorage,
+    ServiceUnavailable,
+    TooManyRequests,
     Internal,
 }
 
@@ -27,6 +32,20 @@ pub struct Error {
     pub source: color_eyre::Report,
 }
 
+impl Error {
+    /// An error whose message can be translated by the client, see [`crate::i18n`]
+    pub fn localized(kind: ErrorKind, message: LocalizedMessage) -> Self {
+        Self {
+            kind,
+            source: Report::msg(message),
+        }
+    }
+
+    fn localized_message(&self) -> Option<&LocalizedMessage> {
+        self.source.downcast_ref::<LocalizedMessage>()
+    }
+}
+
 impl Display for ErrorKind {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -35,6 +54,9 @@ impl Display for ErrorKind {
             ErrorKind::BadRequest => write!(f, "Bad Request"),
             ErrorKind::PermissionDenied => write!(f, "Permission Denied"),
             ErrorKind::Unauthorized => write!(f, "Unauthorized"),
+            ErrorKind::InsufficientStorage => write!(f, "Insufficient Storage"),
+            ErrorKind::ServiceUnavailable => write!(f, "Service Unavailable"),
+            ErrorKind::TooManyRequests => write!(f, "Too Many Requests"),
             ErrorKind::Internal => write!(f, "Internal Error"),
         }
     }
@@ -45,10 +67,15 @@ impl Serialize for Error {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("Error", 2)?;
+        let localized_message = self.localized_message();
+        let mut state =
+            serializer.serialize_struct("Error", 2 + localized_message.is_some() as usize)?;
         state.serialize_field("kind", &self.kind)?;
         let vec: Vec<String> = self.source.chain().map(|cause| cause.to_string()).collect();
         state.serialize_field("causes", &vec)?;
+        if let Some(message) = localized_message {
+            state.serialize_field("message", &RenderedMessage::from(message))?;
+        }
         state.end()
     }
 }
@@ -63,6 +90,22 @@ fn test_error_serialization() {
     assert_eq!(json, r#"{"kind":"NotFound","causes":["Test"]}"#);
 }
 
+#[test]
+fn test_localized_error_serialization() {
+    use crate::i18n::MessageId;
+
+    let error = Error {
+        kind: ErrorKind::PermissionDenied,
+        source: Report::msg(LocalizedMessage::new(MessageId::PermissionDenied))
+            .wrap_err("You don't have permission to view this instance"),
+    };
+    let json = serde_json::to_string(&error).unwrap();
+    assert_eq!(
+        json,
+        r#"{"kind":"PermissionDenied","causes":["You don't have permission to view this instance","You don't have permission to perform this action"],"message":{"id":"PermissionDenied","params":{},"text":"You don't have permission to perform this action"}}"#
+    );
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         let status = match self.kind {
@@ -71,6 +114,9 @@ impl IntoResponse for Error {
             ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
             ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
             ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::InsufficientStorage => StatusCode::INSUFFICIENT_STORAGE,
+            ErrorKind::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
             ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
         };
         (status, json!(self).to_string()).into_response()
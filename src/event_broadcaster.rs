@@ -1,20 +1,105 @@
+use std::sync::{Arc, Mutex};
+
 use tokio::sync::broadcast::{Receiver, Sender};
 use tracing::error;
 
-use crate::events::Event;
+use crate::events::{Event, EventInner, EventLevel, EventQuery, EventType, InstanceEventKind};
+use crate::types::InstanceUuid;
+
+/// Which events a subscriber gets, checked before the event is queued for it.
+/// `None` lets everything through.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionFilter {
+    pub levels: Option<Vec<EventLevel>>,
+    pub event_types: Option<Vec<EventType>>,
+    /// Only restricts instance events
+    pub instance_event_types: Option<Vec<InstanceEventKind>>,
+    /// Only restricts instance events
+    pub instance_uuids: Option<Vec<InstanceUuid>>,
+    pub exclude_console: bool,
+}
+
+impl SubscriptionFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        if self.exclude_console && event.is_event_console_message() {
+            return false;
+        }
+        if let Some(levels) = &self.levels {
+            if !levels.contains(&event.level()) {
+                return false;
+            }
+        }
+        if let Some(event_types) = &self.event_types {
+            if !event_types.contains(&(&event.event_inner).into()) {
+                return false;
+            }
+        }
+        if let EventInner::InstanceEvent(instance_event) = &event.event_inner {
+            if let Some(instance_event_types) = &self.instance_event_types {
+                if !instance_event_types.contains(&(&instance_event.instance_event_inner).into()) {
+                    return false;
+                }
+            }
+            if let Some(instance_uuids) = &self.instance_uuids {
+                if !instance_uuids.contains(&instance_event.instance_uuid) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Never stricter than the query, so the query still has to be applied to what comes through
+impl From<&EventQuery> for SubscriptionFilter {
+    fn from(query: &EventQuery) -> Self {
+        Self {
+            levels: query.event_levels.clone(),
+            event_types: query.event_types.clone(),
+            instance_event_types: query.instance_event_types.clone(),
+            instance_uuids: query.event_instance_ids.clone(),
+            exclude_console: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FilteredSubscription {
+    filter: SubscriptionFilter,
+    event_tx: Sender<Event>,
+}
 
 #[derive(Debug, Clone)]
 pub struct EventBroadcaster {
     event_tx: Sender<Event>,
+    capacity: usize,
+    filtered: Arc<Mutex<Vec<FilteredSubscription>>>,
 }
 
 impl EventBroadcaster {
     pub fn new(capacity: usize) -> (Self, Receiver<Event>) {
         let (event_tx, rx) = tokio::sync::broadcast::channel(capacity);
-        (Self { event_tx }, rx)
+        (
+            Self {
+                event_tx,
+                capacity,
+                filtered: Arc::new(Mutex::new(Vec::new())),
+            },
+            rx,
+        )
     }
 
     pub fn send(&self, event: Event) {
+        {
+            let mut filtered = self.filtered.lock().unwrap();
+            filtered.retain(|subscription| subscription.event_tx.receiver_count() > 0);
+            for subscription in filtered.iter() {
+                if subscription.filter.matches(&event) {
+                    // only fails if the receiver was dropped since the retain
+                    let _ = subscription.event_tx.send(event.clone());
+                }
+            }
+        }
         if let Err(e) = self.event_tx.send(event) {
             error!("Failed to send event: {e}");
         }
@@ -23,6 +108,16 @@ impl EventBroadcaster {
     pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Event> {
         self.event_tx.subscribe()
     }
+
+    /// Like [`Self::subscribe`], but events not matching `filter` are never queued for the receiver
+    pub fn subscribe_filtered(&self, filter: SubscriptionFilter) -> Receiver<Event> {
+        let (event_tx, rx) = tokio::sync::broadcast::channel(self.capacity);
+        self.filtered
+            .lock()
+            .unwrap()
+            .push(FilteredSubscription { filter, event_tx });
+        rx
+    }
 }
 
 impl From<EventBroadcaster> for Sender<Event> {
@@ -36,3 +131,31 @@ impl AsRef<Sender<Event>> for EventBroadcaster {
         &self.event_tx
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscribe_filtered() {
+        let (event_broadcaster, _rx) = EventBroadcaster::new(16);
+        let uuid = InstanceUuid::from("INSTANCE_a".to_string());
+        let mut rx = event_broadcaster.subscribe_filtered(SubscriptionFilter {
+            instance_uuids: Some(vec![uuid.clone()]),
+            ..Default::default()
+        });
+        event_broadcaster.send(Event::new_system_message(
+            InstanceUuid::from("INSTANCE_b".to_string()),
+            "b".to_string(),
+            "ignored".to_string(),
+        ));
+        let expected = Event::new_system_message(uuid, "a".to_string(), "kept".to_string());
+        event_broadcaster.send(expected.clone());
+        assert_eq!(rx.recv().await.unwrap(), expected);
+        assert!(rx.try_recv().is_err());
+
+        drop(rx);
+        event_broadcaster.send(expected);
+        assert!(event_broadcaster.filtered.lock().unwrap().is_empty());
+    }
+}
@@ -7,8 +7,14 @@ use ts_rs::TS;
 
 use crate::{
     auth::{permission::UserPermission, user_id::UserId},
+    console_parser::{ConsoleLine, ConsoleSpan, LogLevel},
+    health::HealthStatus,
+    i18n::LocalizedMessage,
     macro_executor::MacroPID,
+    minecraft::player_data::PlayerDataEdit,
     output_types::ClientEvent,
+    snapshot::SnapshotInfo,
+    startup_watchdog::SettingRevert,
     traits::{t_macro::ExitStatus, t_player::Player, t_server::State, InstanceInfo},
     types::{InstanceUuid, Snowflake, TimeRange},
 };
@@ -106,6 +112,12 @@ pub enum InstanceEventInner {
     },
     InstanceOutput {
         message: String,
+        #[serde(default)]
+        spans: Vec<ConsoleSpan>,
+        #[serde(default)]
+        level: Option<LogLevel>,
+        #[serde(default)]
+        source: Option<String>,
     },
     SystemMessage {
         message: String,
@@ -120,6 +132,60 @@ pub enum InstanceEventInner {
         player: String,
         player_message: String,
     },
+    /// The saved data of a player was edited, see [`crate::minecraft::player_data`]
+    PlayerDataChanged {
+        player_uuid: String,
+        edit: PlayerDataEdit,
+    },
+    /// An alert rule's condition has held for long enough, see [`crate::alerts`]
+    AlertTriggered {
+        rule_id: Snowflake,
+        rule_name: String,
+        value: f64,
+    },
+    AlertResolved {
+        rule_id: Snowflake,
+        rule_name: String,
+    },
+    /// See [`crate::health`]
+    HealthChanged {
+        health: HealthStatus,
+        reasons: Vec<String>,
+    },
+    /// Settings were put back after they kept the instance from starting,
+    /// see [`crate::startup_watchdog`]
+    SettingsRolledBack {
+        reverted: Vec<SettingRevert>,
+    },
+    /// A user's command roles refused a command, see [`crate::command_filter`]
+    CommandBlocked {
+        command: String,
+        roles: Vec<String>,
+    },
+    /// A chat message matched a moderation rule, see [`crate::chat_moderation`]
+    ChatModerated {
+        rule_id: Snowflake,
+        rule_name: String,
+        player: String,
+    },
+    /// See [`crate::player_reputation`]
+    WatchedPlayerJoined {
+        player_id: String,
+        player_name: String,
+        flags: Vec<String>,
+        note_count: u32,
+    },
+}
+
+impl From<ConsoleLine> for InstanceEventInner {
+    fn from(line: ConsoleLine) -> Self {
+        InstanceEventInner::InstanceOutput {
+            message: line.message,
+            spans: line.spans,
+            level: line.level,
+            source: line.source,
+        }
+    }
 }
 
 impl AsRef<InstanceEventInner> for InstanceEventInner {
@@ -151,6 +217,22 @@ pub enum UserEventInner {
     PermissionChanged {
         new_permissions: Box<UserPermission>,
     },
+    PasswordReset,
+    AdminChanged {
+        is_admin: bool,
+    },
+    /// Sent for the new owner
+    OwnershipTransferred {
+        previous_owner: UserId,
+    },
+    /// Sent for the impersonated user
+    ImpersonationStarted {
+        impersonator: UserId,
+        session_id: String,
+    },
+    ImpersonationEnded {
+        session_id: String,
+    },
 }
 
 impl AsRef<UserEventInner> for UserEventInner {
@@ -210,6 +292,12 @@ pub enum ProgressionEndValue {
         success: bool,
         message: String,
     },
+    SnapshotCreated(SnapshotInfo),
+    SnapshotRestored {
+        instance_uuid: InstanceUuid,
+        snapshot_id: Snowflake,
+    },
+    SnapshotVerified(SnapshotInfo),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
@@ -226,6 +314,16 @@ pub enum ProgressionStartValue {
     InstanceDelete {
         instance_uuid: InstanceUuid,
     },
+    WorldPregeneration {
+        instance_uuid: InstanceUuid,
+    },
+    InstanceUpgrade {
+        instance_uuid: InstanceUuid,
+        target_version: String,
+    },
+    WorldUpgrade {
+        instance_uuid: InstanceUuid,
+    },
 }
 
 // the backend will keep exactly 1 copy of ProgressionStart, and 1 copy of ProgressionUpdate OR ProgressionEnd
@@ -237,6 +335,8 @@ pub enum ProgressionEventInner {
         progression_name: String,
         total: Option<f64>,
         inner: Option<ProgressionStartValue>,
+        #[serde(default)]
+        localized_name: Option<LocalizedMessage>,
     },
     ProgressionUpdate {
         progress_message: String,
@@ -246,6 +346,8 @@ pub enum ProgressionEventInner {
         success: bool,
         message: Option<String>,
         inner: Option<ProgressionEndValue>,
+        #[serde(default)]
+        localized_message: Option<LocalizedMessage>,
     },
 }
 #[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
@@ -285,6 +387,12 @@ pub fn new_fs_event(operation: FSOperation, target: FSTarget, caused_by: CausedB
 
 pub struct ProgressionEventID(Snowflake);
 
+impl ProgressionEventID {
+    pub fn snowflake(&self) -> Snowflake {
+        self.0
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
 #[ts(export)]
 pub struct ProgressionEvent {
@@ -327,16 +435,7 @@ fn event_type_export() {
     let _ = UserEventKind::export();
     let _ = InstanceEventKind::export();
 }
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
-#[ts(export)]
-#[serde(tag = "type")]
-pub enum CausedBy {
-    User { user_id: UserId, user_name: String },
-    Instance { instance_uuid: InstanceUuid },
-    Macro { macro_pid: MacroPID },
-    System,
-    Unknown,
-}
+pub use lodestone_types::CausedBy;
 
 #[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
 #[serde(into = "ClientEvent")]
@@ -351,14 +450,7 @@ pub trait IntoEvent {
     fn into_event(self, caused_by: CausedBy, details: String) -> Event;
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
-#[ts(export)]
-#[derive(sqlx::Type)]
-pub enum EventLevel {
-    Info,
-    Warning,
-    Error,
-}
+pub use lodestone_types::EventLevel;
 
 // impl From<&EventInner> for EventType {
 //     fn from(event_inner: &EventInner) -> Self {
@@ -438,7 +530,7 @@ impl Event {
             event_inner: EventInner::InstanceEvent(InstanceEvent {
                 instance_uuid,
                 instance_name,
-                instance_event_inner: InstanceEventInner::InstanceOutput { message: output },
+                instance_event_inner: ConsoleLine::parse(&output).into(),
             }),
             caused_by: CausedBy::System,
         }
@@ -518,6 +610,7 @@ impl Event {
                         progression_name: progression_name.as_ref().to_string(),
                         total,
                         inner,
+                        localized_name: None,
                     },
                 }),
                 caused_by,
@@ -560,9 +653,32 @@ impl Event {
                     success,
                     message: message.map(|s| s.as_ref().to_string()),
                     inner,
+                    localized_message: None,
                 },
             }),
             caused_by: CausedBy::System,
         }
     }
+
+    /// Attaches a translatable version of the name of a progression start,
+    /// or the message of a progression end. Other events are returned unchanged
+    #[must_use]
+    pub fn with_localized_message(mut self, localized: LocalizedMessage) -> Self {
+        if let EventInner::ProgressionEvent(ProgressionEvent {
+            progression_event_inner,
+            ..
+        }) = &mut self.event_inner
+        {
+            match progression_event_inner {
+                ProgressionEventInner::ProgressionStart { localized_name, .. } => {
+                    *localized_name = Some(localized)
+                }
+                ProgressionEventInner::ProgressionEnd {
+                    localized_message, ..
+                } => *localized_message = Some(localized),
+                ProgressionEventInner::ProgressionUpdate { .. } => {}
+            }
+        }
+        self
+    }
 }
@@ -0,0 +1,58 @@
+// The extension API, see src/extension/mod.rs.
+// Import it from your extension's entry module, it only talks to the core through the ops below,
+// which stay the same for as long as EXTENSION_API_VERSION does.
+
+// deno-lint-ignore no-explicit-any
+declare const Deno: any;
+const { core } = Deno;
+const { ops } = core;
+import type { ClientEvent } from "../../../bindings/ClientEvent.ts";
+import type { ExtensionRequest } from "../../../bindings/ExtensionRequest.ts";
+
+export const EXTENSION_API_VERSION = 1;
+
+export type ExtensionReply = {
+    status?: number;
+    // deno-lint-ignore no-explicit-any
+    body?: any;
+};
+
+/** The version of the extension API the core implements */
+export function apiVersion(): number {
+    return ops.extension_api_version();
+}
+
+/** Waits for the next event of the core */
+export async function nextEvent(): Promise<ClientEvent> {
+    return await core.opAsync("next_event");
+}
+
+// deno-lint-ignore no-explicit-any
+export function broadcastEvent(event: any) {
+    ops.broadcast_event(event);
+}
+
+/**
+ * Answers the requests to /extension/<name>/... with the handler, forever.
+ * A handler that throws answers with a 500.
+ */
+export async function serve(
+    handler: (request: ExtensionRequest) => Promise<ExtensionReply> | ExtensionReply,
+) {
+    while (true) {
+        const request: ExtensionRequest = await core.opAsync("next_extension_request");
+        (async () => {
+            let reply: ExtensionReply;
+            try {
+                reply = await handler(request);
+            } catch (e) {
+                reply = { status: 500, body: { error: String(e) } };
+            }
+            ops.respond_extension_request({
+                id: request.id,
+                status: reply.status ?? 200,
+                body: reply.body ?? null,
+            });
+        })();
+    }
+}
@@ -0,0 +1,569 @@
+//! Extensions of the core itself, so support for a game or a new feature can be added without
+//! forking it.
+//!
+//! An extension is a directory in `<lodestone>/extensions`, named after it, with an
+//! `extension.json` manifest and a TypeScript or JavaScript entry module. Extensions are run by
+//! the macro executor when the core starts, with the same permissions as macros, so only put
+//! extensions you trust there. Through the API in `js/mod.ts` an extension can:
+//!
+//! - consume and broadcast events, with the event ops every macro has
+//! - serve routes, every request to `/extension/<name>/...` is handed to it with the user making
+//!   it, and answered with whatever it responds
+//! - add instance types, which are generic instances: the manifest points each one at the module
+//!   implementing it, which the dashboard creates instances from with `/instance/create_generic`
+//!
+//! The API is versioned with [`EXTENSION_API_VERSION`], which only goes up when something an
+//! extension relies on changes. Extensions written for another version are listed but not run.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use deno_core::anyhow::{self, anyhow};
+use deno_core::{op, OpState};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::{error, info, warn};
+use ts_rs::TS;
+
+use crate::{
+    auth::user::User,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    macro_executor::{self, MacroPID, SpawnResult, WorkerOptionGenerator},
+    prelude::lodestone_path,
+    traits::t_macro::ExitStatus,
+    types::UserId,
+    AppState,
+};
+
+pub const EXTENSION_API_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "extension.json";
+/// How long a request waits for the extension to respond
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ExtensionManifest {
+    /// Lowercase letters, digits, `-` and `_`, the same as the extension's directory
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    /// The version of the extension API it was written for
+    pub api_version: u32,
+    /// The entry module, relative to the extension's directory
+    #[serde(default = "default_main")]
+    #[ts(type = "string")]
+    pub main: PathBuf,
+    #[serde(default)]
+    pub instance_types: Vec<ExtensionInstanceType>,
+}
+
+fn default_main() -> PathBuf {
+    PathBuf::from("main.ts")
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ExtensionInstanceType {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The generic instance module, as given to `/instance/create_generic`
+    pub url: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum ExtensionState {
+    Running,
+    Stopped,
+    Error {
+        message: String,
+    },
+    /// Written for another version of the extension API
+    Incompatible {
+        api_version: u32,
+    },
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ExtensionInfo {
+    pub manifest: ExtensionManifest,
+    pub state: ExtensionState,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ExtensionUser {
+    pub uid: UserId,
+    pub username: String,
+    pub is_owner: bool,
+    pub is_admin: bool,
+}
+
+impl From<&User> for ExtensionUser {
+    fn from(user: &User) -> Self {
+        Self {
+            uid: user.uid.clone(),
+            username: user.username.clone(),
+            is_owner: user.is_owner,
+            is_admin: user.is_admin,
+        }
+    }
+}
+
+/// Sent to the extension for a request to one of its routes
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ExtensionRequest {
+    #[ts(type = "number")]
+    pub id: u64,
+    pub method: String,
+    /// Relative to `/extension/<name>`, starting with `/`
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// Only JSON bodies are passed on
+    #[ts(type = "unknown")]
+    pub body: Option<Value>,
+    /// Who is making the request, the extension decides what they are allowed to do
+    pub user: ExtensionUser,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ExtensionResponse {
+    #[ts(type = "number")]
+    pub id: u64,
+    pub status: u16,
+    #[ts(type = "unknown")]
+    pub body: Value,
+}
+
+/// Hands requests to the extension's worker and its responses back
+#[derive(Clone)]
+pub struct ExtensionBridge {
+    next_id: Arc<AtomicU64>,
+    request_tx: mpsc::UnboundedSender<ExtensionRequest>,
+    request_rx: Arc<Mutex<mpsc::UnboundedReceiver<ExtensionRequest>>>,
+    pending: Arc<std::sync::Mutex<HashMap<u64, oneshot::Sender<ExtensionResponse>>>>,
+}
+
+impl ExtensionBridge {
+    fn new() -> Self {
+        let (request_tx, request_rx) = mpsc::unbounded_channel();
+        Self {
+            next_id: Arc::new(AtomicU64::new(0)),
+            request_tx,
+            request_rx: Arc::new(Mutex::new(request_rx)),
+            pending: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub async fn call(&self, request: ExtensionRequest) -> Result<ExtensionResponse, Error> {
+        let id = request.id;
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        if self.request_tx.send(request).is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(eyre!("The extension is not running").into());
+        }
+        match tokio::time::timeout(REQUEST_TIMEOUT, rx).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(eyre!("The extension dropped the request").into()),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!(
+                        "The extension did not respond within {} seconds",
+                        REQUEST_TIMEOUT.as_secs()
+                    ),
+                })
+            }
+        }
+    }
+}
+
+#[op]
+async fn next_extension_request(
+    state: Rc<RefCell<OpState>>,
+) -> Result<ExtensionRequest, anyhow::Error> {
+    let bridge = state.borrow().borrow::<ExtensionBridge>().clone();
+    let mut rx = bridge.request_rx.lock().await;
+    rx.recv()
+        .await
+        .ok_or_else(|| anyhow!("The core is shutting down"))
+}
+
+#[op]
+fn respond_extension_request(
+    state: Rc<RefCell<OpState>>,
+    response: ExtensionResponse,
+) -> Result<(), anyhow::Error> {
+    let bridge = state.borrow().borrow::<ExtensionBridge>().clone();
+    let tx = bridge
+        .pending
+        .lock()
+        .unwrap()
+        .remove(&response.id)
+        .ok_or_else(|| anyhow!("No request {} waiting for a response", response.id))?;
+    // the request may have timed out in the meantime
+    let _ = tx.send(response);
+    Ok(())
+}
+
+#[op]
+fn extension_api_version() -> u32 {
+    EXTENSION_API_VERSION
+}
+
+struct ExtensionWorkerGenerator {
+    bridge: ExtensionBridge,
+}
+
+impl WorkerOptionGenerator for ExtensionWorkerGenerator {
+    fn generate(&self) -> deno_runtime::worker::WorkerOptions {
+        let ext = deno_core::Extension::builder("extension_ops")
+            .ops(vec![
+                next_extension_request::decl(),
+                respond_extension_request::decl(),
+                extension_api_version::decl(),
+            ])
+            .state({
+                let bridge = self.bridge.clone();
+                move |state| {
+                    state.put(bridge);
+                }
+            })
+            .force_op_registration()
+            .build();
+        deno_runtime::worker::WorkerOptions {
+            extensions: vec![ext],
+            module_loader: Rc::new(macro_executor::TypescriptModuleLoader::default()),
+            ..Default::default()
+        }
+    }
+}
+
+struct LoadedExtension {
+    manifest: ExtensionManifest,
+    state: ExtensionState,
+    bridge: ExtensionBridge,
+    pid: Option<MacroPID>,
+}
+
+#[derive(Default)]
+pub struct ExtensionManager {
+    extensions: HashMap<String, LoadedExtension>,
+}
+
+impl ExtensionManager {
+    pub fn list(&self) -> Vec<ExtensionInfo> {
+        let mut ret: Vec<ExtensionInfo> = self
+            .extensions
+            .values()
+            .map(|extension| ExtensionInfo {
+                manifest: extension.manifest.clone(),
+                state: extension.state.clone(),
+            })
+            .collect();
+        ret.sort_by(|a, b| a.manifest.name.cmp(&b.manifest.name));
+        ret
+    }
+
+    /// The instance types of the extensions that can run
+    pub fn instance_types(&self) -> Vec<ExtensionInstanceType> {
+        let mut extensions: Vec<&LoadedExtension> = self
+            .extensions
+            .values()
+            .filter(|extension| !matches!(extension.state, ExtensionState::Incompatible { .. }))
+            .collect();
+        extensions.sort_by(|a, b| a.manifest.name.cmp(&b.manifest.name));
+        extensions
+            .into_iter()
+            .flat_map(|extension| extension.manifest.instance_types.clone())
+            .collect()
+    }
+
+    /// Builds the request for a route of the extension, returning the bridge to send it through
+    pub fn request(
+        &self,
+        name: &str,
+        method: String,
+        path: String,
+        query: HashMap<String, String>,
+        body: Option<Value>,
+        user: &User,
+    ) -> Result<(ExtensionBridge, ExtensionRequest), Error> {
+        let extension = self.extensions.get(name).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Extension {} not found", name),
+        })?;
+        if extension.state != ExtensionState::Running {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Extension {} is not running", name),
+            });
+        }
+        let request = ExtensionRequest {
+            id: extension.bridge.next_id(),
+            method,
+            path: format!("/{}", path.trim_start_matches('/')),
+            query,
+            body,
+            user: user.into(),
+        };
+        Ok((extension.bridge.clone(), request))
+    }
+
+    fn set_state(&mut self, name: &str, state: ExtensionState) {
+        if let Some(extension) = self.extensions.get_mut(name) {
+            extension.state = state;
+        }
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+fn parse_manifest(content: &str, dir_name: &str) -> Result<ExtensionManifest, Error> {
+    let manifest: ExtensionManifest =
+        serde_json::from_str(content).context(format!("Invalid {MANIFEST_FILE}"))?;
+    if !is_valid_name(&manifest.name) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Extension name {} may only contain lowercase letters, digits, - and _",
+                manifest.name
+            ),
+        });
+    }
+    if manifest.name != dir_name {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Extension {} must be in a directory of the same name, not {}",
+                manifest.name,
+                dir_name
+            ),
+        });
+    }
+    if manifest.main.is_absolute()
+        || manifest
+            .main
+            .components()
+            .any(|c| matches!(c, std::path::Component::ParentDir))
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The entry module must be inside the extension's directory"),
+        });
+    }
+    Ok(manifest)
+}
+
+async fn read_manifest(dir: &Path) -> Result<ExtensionManifest, Error> {
+    let content = tokio::fs::read_to_string(dir.join(MANIFEST_FILE))
+        .await
+        .context(format!("Failed to read {MANIFEST_FILE}"))?;
+    let dir_name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    parse_manifest(&content, &dir_name)
+}
+
+pub fn path_to_extensions() -> PathBuf {
+    lodestone_path().join("extensions")
+}
+
+async fn start_extension(
+    state: &AppState,
+    dir: &Path,
+    manifest: &ExtensionManifest,
+    bridge: ExtensionBridge,
+) -> Result<SpawnResult, Error> {
+    state
+        .macro_executor
+        .spawn(
+            dir.join(&manifest.main),
+            Vec::new(),
+            CausedBy::System,
+            Box::new(ExtensionWorkerGenerator { bridge }),
+            None,
+            None,
+            None,
+        )
+        .await
+}
+
+/// Runs every compatible extension found in the extensions directory
+pub async fn load_extensions(state: AppState) {
+    let dir = path_to_extensions();
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        error!("Failed to create the extensions directory: {e}");
+        return;
+    }
+    let mut entries = match tokio::fs::read_dir(&dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to read the extensions directory: {e}");
+            return;
+        }
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let manifest = match read_manifest(&path).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!("Skipping extension at {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let name = manifest.name.clone();
+        let bridge = ExtensionBridge::new();
+        if manifest.api_version != EXTENSION_API_VERSION {
+            warn!(
+                "Extension {} was written for API version {}, this core has version {}",
+                name, manifest.api_version, EXTENSION_API_VERSION
+            );
+            state.extensions.lock().await.extensions.insert(
+                name,
+                LoadedExtension {
+                    state: ExtensionState::Incompatible {
+                        api_version: manifest.api_version,
+                    },
+                    manifest,
+                    bridge,
+                    pid: None,
+                },
+            );
+            continue;
+        }
+        let (extension_state, pid, exit_future) =
+            match start_extension(&state, &path, &manifest, bridge.clone()).await {
+                Ok(SpawnResult {
+                    macro_pid,
+                    exit_future,
+                    ..
+                }) => {
+                    info!("Started extension {} {}", name, manifest.version);
+                    (ExtensionState::Running, Some(macro_pid), Some(exit_future))
+                }
+                Err(e) => {
+                    error!("Failed to start extension {}: {}", name, e);
+                    (
+                        ExtensionState::Error {
+                            message: e.to_string(),
+                        },
+                        None,
+                        None,
+                    )
+                }
+            };
+        state.extensions.lock().await.extensions.insert(
+            name.clone(),
+            LoadedExtension {
+                manifest,
+                state: extension_state,
+                bridge,
+                pid,
+            },
+        );
+        if let Some(exit_future) = exit_future {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let extension_state = match exit_future.await {
+                    Ok(ExitStatus::Error { error_msg, .. }) => {
+                        error!("Extension {} failed: {}", name, error_msg);
+                        ExtensionState::Error { message: error_msg }
+                    }
+                    Ok(_) => ExtensionState::Stopped,
+                    Err(e) => ExtensionState::Error {
+                        message: e.to_string(),
+                    },
+                };
+                state
+                    .extensions
+                    .lock()
+                    .await
+                    .set_state(&name, extension_state);
+            });
+        }
+    }
+}
+
+/// Stops the extension's worker, it stays listed as stopped
+pub async fn stop_extension(state: &AppState, name: &str) -> Result<(), Error> {
+    let pid = state
+        .extensions
+        .lock()
+        .await
+        .extensions
+        .get(name)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Extension {} not found", name),
+        })?
+        .pid;
+    match pid {
+        Some(pid) => state.macro_executor.abort_macro(pid),
+        None => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Extension {} is not running", name),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse_manifest(
+            r#"{
+                "name": "terraria",
+                "version": "0.1.0",
+                "api_version": 1,
+                "instance_types": [
+                    { "name": "Terraria", "url": "https://example.com/terraria/mod.ts" }
+                ]
+            }"#,
+            "terraria",
+        )
+        .unwrap();
+        assert_eq!(manifest.main, PathBuf::from("main.ts"));
+        assert_eq!(manifest.instance_types[0].name, "Terraria");
+
+        let manifest = |name: &str, main: &str| {
+            format!(r#"{{ "name": "{name}", "version": "1", "api_version": 1, "main": "{main}" }}"#)
+        };
+        assert!(parse_manifest(&manifest("terraria", "main.ts"), "other").is_err());
+        assert!(parse_manifest(&manifest("Terraria", "main.ts"), "Terraria").is_err());
+        assert!(parse_manifest(&manifest("terraria", "../main.ts"), "terraria").is_err());
+        assert!(parse_manifest(&manifest("terraria", "src/main.js"), "terraria").is_ok());
+    }
+}
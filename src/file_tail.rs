@@ -0,0 +1,217 @@
+//! Following a file as it grows, like `tail -F`.
+//!
+//! The file is polled rather than watched, which works the same on every platform and file
+//! system. A file that was replaced, like `logs/latest.log` when a server starts, or truncated
+//! is followed again from its start.
+
+use std::fs::Metadata;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+use color_eyre::eyre::Context;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use ts_rs::TS;
+
+use crate::error::Error;
+
+/// How much of the end of the file is searched for the lines sent first
+const BACKLOG_BYTES: u64 = 64 * 1024;
+/// Read per poll, so a file growing very fast doesn't hold everything up
+const MAX_READ_BYTES: u64 = 1024 * 1024;
+/// A line longer than this is sent in pieces
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum FileTailMessage {
+    Lines {
+        lines: Vec<String>,
+    },
+    /// The file was replaced or truncated, the lines that follow are from its start
+    Rotated,
+    /// The file is gone, for instance between being rotated away and created again
+    Missing,
+}
+
+/// What tells one file apart from another one later created at the same path
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    let created = metadata
+        .created()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    Some((created.as_secs(), created.subsec_nanos() as u64))
+}
+
+fn decode_line(line: &[u8]) -> String {
+    String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)).into_owned()
+}
+
+pub struct FileTail {
+    path: PathBuf,
+    identity: Option<(u64, u64)>,
+    position: u64,
+    /// Read after the last complete line
+    partial: Vec<u8>,
+    missing: bool,
+}
+
+impl FileTail {
+    /// Starts at the end of the file, returning up to `backlog` of its last lines
+    pub async fn open(path: PathBuf, backlog: usize) -> Result<(Self, Vec<String>), Error> {
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .context(format!("Failed to read {}", path.display()))?;
+        let len = metadata.len();
+        let start = len.saturating_sub(BACKLOG_BYTES);
+        let mut file = tokio::fs::File::open(&path)
+            .await
+            .context(format!("Failed to open {}", path.display()))?;
+        file.seek(SeekFrom::Start(start))
+            .await
+            .context("Failed to seek")?;
+        let mut content = Vec::new();
+        file.take(len - start)
+            .read_to_end(&mut content)
+            .await
+            .context(format!("Failed to read {}", path.display()))?;
+        let mut lines: Vec<&[u8]> = content.split(|byte| *byte == b'\n').collect();
+        // an incomplete line at the end is sent once it is complete
+        let partial = lines.pop().unwrap_or_default().to_vec();
+        if start > 0 && !lines.is_empty() {
+            // most likely cut off
+            lines.remove(0);
+        }
+        let skip = lines.len().saturating_sub(backlog);
+        let backlog = lines[skip..].iter().map(|line| decode_line(line)).collect();
+        Ok((
+            Self {
+                path,
+                identity: identity(&metadata),
+                position: len,
+                partial,
+                missing: false,
+            },
+            backlog,
+        ))
+    }
+
+    /// What happened to the file since the last poll
+    pub async fn poll(&mut self) -> Result<Vec<FileTailMessage>, Error> {
+        let mut ret = Vec::new();
+        let metadata = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                if !std::mem::replace(&mut self.missing, true) {
+                    ret.push(FileTailMessage::Missing);
+                }
+                return Ok(ret);
+            }
+        };
+        let identity = identity(&metadata);
+        if std::mem::take(&mut self.missing)
+            || identity != self.identity
+            || metadata.len() < self.position
+        {
+            self.identity = identity;
+            self.position = 0;
+            self.partial.clear();
+            ret.push(FileTailMessage::Rotated);
+        }
+        if metadata.len() == self.position {
+            return Ok(ret);
+        }
+        let mut file = tokio::fs::File::open(&self.path)
+            .await
+            .context(format!("Failed to open {}", self.path.display()))?;
+        file.seek(SeekFrom::Start(self.position))
+            .await
+            .context("Failed to seek")?;
+        let mut content = Vec::new();
+        let read = file
+            .take(MAX_READ_BYTES)
+            .read_to_end(&mut content)
+            .await
+            .context(format!("Failed to read {}", self.path.display()))?;
+        self.position += read as u64;
+        let mut lines = Vec::new();
+        for byte in content {
+            if byte == b'\n' {
+                lines.push(decode_line(&std::mem::take(&mut self.partial)));
+            } else {
+                self.partial.push(byte);
+                if self.partial.len() >= MAX_LINE_BYTES {
+                    lines.push(decode_line(&std::mem::take(&mut self.partial)));
+                }
+            }
+        }
+        if !lines.is_empty() {
+            ret.push(FileTailMessage::Lines { lines });
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn append(path: &std::path::Path, content: &str) {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+    }
+
+    fn lines(lines: &[&str]) -> FileTailMessage {
+        FileTailMessage::Lines {
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follow_rotation() {
+        let temp_dir = tempdir::TempDir::new("test_file_tail").unwrap();
+        let path = temp_dir.path().join("latest.log");
+        append(&path, "one\ntwo\nthree\npart");
+
+        let (mut tail, backlog) = FileTail::open(path.clone(), 2).await.unwrap();
+        assert_eq!(backlog, vec!["two", "three"]);
+        assert!(tail.poll().await.unwrap().is_empty());
+
+        append(&path, "ial\r\nfour\n");
+        assert_eq!(
+            tail.poll().await.unwrap(),
+            vec![lines(&["partial", "four"])]
+        );
+
+        std::fs::rename(&path, temp_dir.path().join("old.log")).unwrap();
+        assert_eq!(tail.poll().await.unwrap(), vec![FileTailMessage::Missing]);
+        assert!(tail.poll().await.unwrap().is_empty());
+        append(&path, "fresh\n");
+        assert_eq!(
+            tail.poll().await.unwrap(),
+            vec![FileTailMessage::Rotated, lines(&["fresh"])]
+        );
+
+        std::fs::write(&path, "").unwrap();
+        append(&path, "x\n");
+        assert_eq!(
+            tail.poll().await.unwrap(),
+            vec![FileTailMessage::Rotated, lines(&["x"])]
+        );
+    }
+}
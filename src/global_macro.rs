@@ -0,0 +1,149 @@
+//! Macros in the core's `macros` directory, which every instance can run without a copy in its
+//! own macros directory.
+//!
+//! An instance's own macro wins over a global one of the same name. `games.json` in the directory
+//! can limit macros to some games, as in `{ "restart-warning.ts": ["MinecraftJava"] }`, macros it
+//! doesn't mention are available to every game. Each instance can turn global macros off.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use tracing::warn;
+
+use crate::{
+    error::{Error, ErrorKind},
+    implementations::minecraft::r#macro::{read_macro_parameters, resolve_macro_invocation},
+    prelude::path_to_global_macros,
+    traits::{t_configurable::GameType, t_macro::MacroEntry},
+};
+
+const GAMES_FILE: &str = "games.json";
+
+fn read_game_filter(dir: &Path) -> HashMap<String, Vec<GameType>> {
+    let path = dir.join(GAMES_FILE);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => return HashMap::new(),
+    };
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        warn!("Ignoring {}: {}", path.display(), e);
+        HashMap::new()
+    })
+}
+
+fn is_available(filter: &HashMap<String, Vec<GameType>>, name: &str, game_type: GameType) -> bool {
+    filter
+        .get(name)
+        .map_or(true, |game_types| game_types.contains(&game_type))
+}
+
+/// The name a macro is listed under: its file, or its directory
+fn entry_name(dir: &Path, path_to_macro: &Path) -> Option<String> {
+    let entry = if path_to_macro.parent() == Some(dir) {
+        path_to_macro
+    } else {
+        path_to_macro.parent()?
+    };
+    Some(entry.file_name()?.to_string_lossy().to_string())
+}
+
+/// The global macros available to instances of the game, with whether the instance runs them
+pub fn list_global_macros(game_type: GameType, disabled: &[String]) -> Vec<MacroEntry> {
+    let dir = path_to_global_macros();
+    let filter = read_game_filter(dir);
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to read the global macros directory: {e}");
+            return Vec::new();
+        }
+    };
+    let mut ret = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !is_available(&filter, &name, game_type) {
+            continue;
+        }
+        if let Some(path_to_macro) = resolve_macro_invocation(dir, &name) {
+            ret.push(MacroEntry {
+                parameters: read_macro_parameters(dir, &path_to_macro).unwrap_or_else(|e| {
+                    warn!("Ignoring the parameters of global macro {name}: {e}");
+                    Vec::new()
+                }),
+                global_enabled: Some(!disabled.contains(&name)),
+                last_run: None,
+                path: entry.path(),
+                name,
+            });
+        }
+    }
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    ret
+}
+
+/// Resolves a global macro for an instance of the game, `None` if there is no such macro
+pub fn resolve_global_macro(
+    name: &str,
+    game_type: GameType,
+    disabled: &[String],
+) -> Result<Option<PathBuf>, Error> {
+    let dir = path_to_global_macros();
+    let path_to_macro = match resolve_macro_invocation(dir, name) {
+        Some(path_to_macro) => path_to_macro,
+        None => return Ok(None),
+    };
+    let entry_name = match entry_name(dir, &path_to_macro) {
+        Some(entry_name) => entry_name,
+        None => return Ok(None),
+    };
+    if !is_available(&read_game_filter(dir), &entry_name, game_type) {
+        return Ok(None);
+    }
+    if disabled.contains(&entry_name) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Global macro {} is turned off for this instance",
+                entry_name
+            ),
+        });
+    }
+    Ok(Some(path_to_macro))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_game_filter() {
+        let filter: HashMap<String, Vec<GameType>> =
+            serde_json::from_str(r#"{ "restart-warning.ts": ["MinecraftJava"] }"#).unwrap();
+        assert!(is_available(
+            &filter,
+            "restart-warning.ts",
+            GameType::MinecraftJava
+        ));
+        assert!(!is_available(
+            &filter,
+            "restart-warning.ts",
+            GameType::MinecraftBedrock
+        ));
+        assert!(is_available(
+            &filter,
+            "backup.ts",
+            GameType::MinecraftBedrock
+        ));
+
+        let dir = Path::new("/lodestone/macros");
+        assert_eq!(
+            entry_name(dir, &dir.join("backup.ts")).as_deref(),
+            Some("backup.ts")
+        );
+        assert_eq!(
+            entry_name(dir, &dir.join("backup").join("index.ts")).as_deref(),
+            Some("backup")
+        );
+    }
+}
@@ -1,11 +1,157 @@
-use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 use color_eyre::eyre::Context;
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 use ts_rs::TS;
 
-use crate::{error::Error, event_broadcaster::EventBroadcaster};
+use crate::{
+    alerts::{AlertAction, AlertRule},
+    announcement::Announcement,
+    auth::{hashed_password::PasswordHashing, instance_access::InstanceAccess, user_id::UserId},
+    chat_moderation::{ModerationAction, ModerationRule},
+    command_filter::CommandRole,
+    disk_space::DiskSpaceConfig,
+    error::Error,
+    event_broadcaster::EventBroadcaster,
+    log_shipping::LogShippingSettings,
+    mail::SmtpConfig,
+    maintenance::MaintenanceWindow,
+    network_filter::NetworkFilterSettings,
+    power_schedule::PowerSchedule,
+    quota::{QuotaRole, QuotaSettings, UserQuota},
+    rate_limiter::RateLimitConfig,
+    remote_backup::{RemoteBackupSettings, SECRET_PLACEHOLDER},
+    resource_pack::ResourcePack,
+    restart_schedule::RestartSchedule,
+    service::ShutdownPolicy,
+    storage::{self, StorageLocation},
+    telemetry::TelemetrySettings,
+    types::InstanceUuid,
+    web_proxy::WebProxySettings,
+    whitelist_group::WhitelistGroup,
+};
+
+/// Everything configured for a single instance, dropped along with it by
+/// [`GlobalSettings::forget`]
+#[derive(Serialize, Deserialize, Clone, Default, TS)]
+#[ts(export)]
+pub struct InstanceSettings {
+    /// Overrides the core-wide shutdown policy
+    #[serde(default)]
+    pub shutdown_policy: Option<ShutdownPolicy>,
+    /// Overrides the core-wide remote backup settings
+    #[serde(default)]
+    pub remote_backup: Option<RemoteBackupSettings>,
+    #[serde(default)]
+    pub alert_rules: Vec<AlertRule>,
+    /// When the instance is allowed to run
+    #[serde(default)]
+    pub power_schedule: Option<PowerSchedule>,
+    #[serde(default)]
+    pub network_filter: Option<NetworkFilterSettings>,
+    /// Which local port `/instance/:uuid/web` forwards to
+    #[serde(default)]
+    pub web_proxy: Option<WebProxySettings>,
+    #[serde(default)]
+    pub restart_schedule: Option<RestartSchedule>,
+    #[serde(default)]
+    pub announcements: Vec<Announcement>,
+    /// Hosted by the core, see [`crate::resource_pack`]
+    #[serde(default)]
+    pub resource_pack: Option<ResourcePack>,
+    /// See [`crate::chat_moderation`]
+    #[serde(default)]
+    pub moderation_rules: Vec<ModerationRule>,
+    /// Overrides the core-wide log shipping settings
+    #[serde(default)]
+    pub log_shipping: Option<LogShippingSettings>,
+}
+
+impl InstanceSettings {
+    pub fn is_empty(&self) -> bool {
+        self.shutdown_policy.is_none()
+            && self.remote_backup.is_none()
+            && self.alert_rules.is_empty()
+            && self.power_schedule.is_none()
+            && self.network_filter.is_none()
+            && self.web_proxy.is_none()
+            && self.restart_schedule.is_none()
+            && self.announcements.is_empty()
+            && self.resource_pack.is_none()
+            && self.moderation_rules.is_empty()
+            && self.log_shipping.is_none()
+    }
+
+    fn redact(&mut self) {
+        self.remote_backup = self
+            .remote_backup
+            .take()
+            .map(|settings| settings.redacted());
+        self.log_shipping = self.log_shipping.take().map(|settings| settings.redacted());
+        for rule in self.alert_rules.iter_mut() {
+            for action in rule.actions.iter_mut() {
+                if let AlertAction::Webhook { url } = action {
+                    *url = SECRET_PLACEHOLDER.to_string();
+                }
+            }
+        }
+        for rule in self.moderation_rules.iter_mut() {
+            for action in rule.actions.iter_mut() {
+                if let ModerationAction::Webhook { url } = action {
+                    *url = SECRET_PLACEHOLDER.to_string();
+                }
+            }
+        }
+    }
+}
+
+/// The top-level maps per-instance settings were kept in before [`InstanceSettings`], and the
+/// field each moved to
+const LEGACY_INSTANCE_MAPS: [(&str, &str); 11] = [
+    ("instance_shutdown_policies", "shutdown_policy"),
+    ("instance_remote_backups", "remote_backup"),
+    ("instance_alert_rules", "alert_rules"),
+    ("instance_power_schedules", "power_schedule"),
+    ("instance_network_filters", "network_filter"),
+    ("instance_web_proxies", "web_proxy"),
+    ("instance_restart_schedules", "restart_schedule"),
+    ("instance_announcements", "announcements"),
+    ("instance_resource_packs", "resource_pack"),
+    ("instance_moderation_rules", "moderation_rules"),
+    ("instance_log_shipping", "log_shipping"),
+];
+
+/// Moves settings written by older versions into `instance_settings`
+fn migrate_legacy_instance_maps(data: &mut serde_json::Value) {
+    let object = match data.as_object_mut() {
+        Some(object) => object,
+        None => return,
+    };
+    for (legacy_key, field) in LEGACY_INSTANCE_MAPS {
+        let legacy_map = match object.remove(legacy_key) {
+            Some(serde_json::Value::Object(legacy_map)) => legacy_map,
+            _ => continue,
+        };
+        let instance_settings = object
+            .entry("instance_settings")
+            .or_insert_with(|| serde_json::Value::Object(Default::default()));
+        if let Some(instance_settings) = instance_settings.as_object_mut() {
+            for (uuid, value) in legacy_map {
+                if let Some(settings) = instance_settings
+                    .entry(uuid)
+                    .or_insert_with(|| serde_json::Value::Object(Default::default()))
+                    .as_object_mut()
+                {
+                    settings.insert(field.to_string(), value);
+                }
+            }
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, TS)]
 #[ts(export)]
@@ -13,6 +159,77 @@ pub struct GlobalSettingsData {
     pub core_name: String,
     pub safe_mode: bool,
     pub domain: Option<String>,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Everything configured for individual instances
+    #[serde(default)]
+    pub instance_settings: HashMap<InstanceUuid, InstanceSettings>,
+    /// Where snapshots are uploaded to, unless overridden for an instance
+    #[serde(default)]
+    pub remote_backup: Option<RemoteBackupSettings>,
+    #[serde(default)]
+    pub disk_space: DiskSpaceConfig,
+    #[serde(default)]
+    pub quotas: QuotaSettings,
+    /// Who each instance belongs to and is shared with
+    #[serde(default)]
+    pub instance_access: HashMap<InstanceUuid, InstanceAccess>,
+    /// Used to email password reset links, none are sent without it
+    #[serde(default)]
+    pub smtp: Option<SmtpConfig>,
+    #[serde(default)]
+    pub password_hashing: PasswordHashing,
+    /// Current or upcoming maintenance, during which only admins can make changes
+    #[serde(default)]
+    pub maintenance: Option<MaintenanceWindow>,
+    /// Off unless turned on by an owner
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    /// Besides the default one, see [`crate::storage`]
+    #[serde(default)]
+    pub storage_locations: Vec<StorageLocation>,
+    /// Players whitelisted on several instances at once, see [`crate::whitelist_group`]
+    #[serde(default)]
+    pub whitelist_groups: Vec<WhitelistGroup>,
+    /// Limits on console commands, see [`crate::command_filter`]
+    #[serde(default)]
+    pub command_roles: Vec<CommandRole>,
+    /// Run each instance as its own system user, see [`crate::isolation`]
+    #[serde(default)]
+    pub instance_isolation: bool,
+    /// Where console output and events are shipped, unless overridden for an instance,
+    /// see [`crate::log_shipping`]
+    #[serde(default)]
+    pub log_shipping: Option<LogShippingSettings>,
+}
+
+impl GlobalSettingsData {
+    /// Replaces stored credentials with a placeholder, for sending to clients
+    pub fn redacted(&self) -> Self {
+        let mut ret = self.clone();
+        ret.remote_backup = ret.remote_backup.map(|settings| settings.redacted());
+        ret.smtp = ret.smtp.map(|smtp| smtp.redacted());
+        ret.log_shipping = ret.log_shipping.map(|settings| settings.redacted());
+        for settings in ret.instance_settings.values_mut() {
+            settings.redact();
+        }
+        ret
+    }
+
+    /// What users without owner rights get, the core-wide settings without anything about
+    /// individual instances or users, or where data is sent
+    pub fn public(&self) -> Self {
+        Self {
+            core_name: self.core_name.clone(),
+            safe_mode: self.safe_mode,
+            domain: self.domain.clone(),
+            rate_limit: self.rate_limit,
+            disk_space: self.disk_space,
+            maintenance: self.maintenance.clone(),
+            instance_isolation: self.instance_isolation,
+            ..Self::default()
+        }
+    }
 }
 
 impl Default for GlobalSettingsData {
@@ -21,6 +238,21 @@ impl Default for GlobalSettingsData {
             core_name: format!("{}'s Lodestone Core", whoami::realname()),
             safe_mode: true,
             domain: None,
+            rate_limit: RateLimitConfig::default(),
+            instance_settings: HashMap::new(),
+            remote_backup: None,
+            disk_space: DiskSpaceConfig::default(),
+            quotas: QuotaSettings::default(),
+            instance_access: HashMap::new(),
+            smtp: None,
+            password_hashing: PasswordHashing::default(),
+            maintenance: None,
+            telemetry: TelemetrySettings::default(),
+            storage_locations: Vec::new(),
+            whitelist_groups: Vec::new(),
+            command_roles: Vec::new(),
+            instance_isolation: false,
+            log_shipping: None,
         }
     }
 }
@@ -65,7 +297,7 @@ impl GlobalSettings {
         {
             self.global_settings_data = GlobalSettingsData::default();
         } else {
-            self.global_settings_data = serde_json::from_slice(
+            let mut data: serde_json::Value = serde_json::from_slice(
                 &tokio::fs::read(&self.path_to_global_settings)
                     .await
                     .context(format!(
@@ -77,6 +309,11 @@ impl GlobalSettings {
                 "Failed to parse global settings file at {}",
                 self.path_to_global_settings.display()
             ))?;
+            migrate_legacy_instance_maps(&mut data);
+            self.global_settings_data = serde_json::from_value(data).context(format!(
+                "Failed to parse global settings file at {}",
+                self.path_to_global_settings.display()
+            ))?;
         }
         Ok(())
     }
@@ -99,6 +336,15 @@ impl GlobalSettings {
         ))?;
         Ok(())
     }
+    /// Replaces all settings at once, when restoring a core backup
+    pub async fn restore(&mut self, data: GlobalSettingsData) -> Result<(), Error> {
+        let old_data = std::mem::replace(&mut self.global_settings_data, data);
+        if let Err(e) = self.write_to_file().await {
+            self.global_settings_data = old_data;
+            return Err(e);
+        }
+        Ok(())
+    }
     pub async fn set_core_name(&mut self, name: String) -> Result<(), Error> {
         let old_name = self.global_settings_data.core_name.clone();
         self.global_settings_data.core_name = name;
@@ -131,6 +377,22 @@ impl GlobalSettings {
         self.global_settings_data.safe_mode
     }
 
+    pub async fn set_instance_isolation(&mut self, instance_isolation: bool) -> Result<(), Error> {
+        let old_instance_isolation = self.global_settings_data.instance_isolation;
+        self.global_settings_data.instance_isolation = instance_isolation;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.instance_isolation = old_instance_isolation;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn instance_isolation(&self) -> bool {
+        self.global_settings_data.instance_isolation
+    }
+
     pub async fn set_domain(&mut self, domain: Option<String>) -> Result<(), Error> {
         let old_domain = self.global_settings_data.domain.clone();
         self.global_settings_data.domain = domain;
@@ -146,6 +408,603 @@ impl GlobalSettings {
     pub fn domain(&self) -> Option<String> {
         self.global_settings_data.domain.clone()
     }
+
+    pub async fn set_rate_limit(&mut self, rate_limit: RateLimitConfig) -> Result<(), Error> {
+        let old_rate_limit = self.global_settings_data.rate_limit;
+        self.global_settings_data.rate_limit = rate_limit;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.rate_limit = old_rate_limit;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn rate_limit(&self) -> RateLimitConfig {
+        self.global_settings_data.rate_limit
+    }
+
+    pub async fn set_disk_space(&mut self, disk_space: DiskSpaceConfig) -> Result<(), Error> {
+        let old_disk_space = self.global_settings_data.disk_space;
+        self.global_settings_data.disk_space = disk_space;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.disk_space = old_disk_space;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn disk_space(&self) -> DiskSpaceConfig {
+        self.global_settings_data.disk_space
+    }
+
+    pub async fn set_storage_locations(
+        &mut self,
+        storage_locations: Vec<StorageLocation>,
+    ) -> Result<(), Error> {
+        let old_storage_locations = std::mem::replace(
+            &mut self.global_settings_data.storage_locations,
+            storage_locations,
+        );
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.storage_locations = old_storage_locations;
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn set_whitelist_groups(
+        &mut self,
+        whitelist_groups: Vec<WhitelistGroup>,
+    ) -> Result<(), Error> {
+        let old_whitelist_groups = std::mem::replace(
+            &mut self.global_settings_data.whitelist_groups,
+            whitelist_groups,
+        );
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.whitelist_groups = old_whitelist_groups;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn whitelist_groups(&self) -> &[WhitelistGroup] {
+        &self.global_settings_data.whitelist_groups
+    }
+
+    pub async fn set_command_roles(
+        &mut self,
+        command_roles: Vec<CommandRole>,
+    ) -> Result<(), Error> {
+        let old_command_roles =
+            std::mem::replace(&mut self.global_settings_data.command_roles, command_roles);
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.command_roles = old_command_roles;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn command_roles(&self) -> &[CommandRole] {
+        &self.global_settings_data.command_roles
+    }
+
+    /// Every instance something is configured for
+    pub fn referenced_instances(&self) -> HashSet<InstanceUuid> {
+        let data = &self.global_settings_data;
+        data.instance_settings
+            .keys()
+            .chain(data.instance_access.keys())
+            .chain(
+                data.whitelist_groups
+                    .iter()
+                    .flat_map(|group| group.instances.iter()),
+            )
+            .chain(
+                data.command_roles
+                    .iter()
+                    .flat_map(|role| role.instances.iter()),
+            )
+            .cloned()
+            .collect()
+    }
+
+    /// The configured storage locations, without the default one
+    pub fn storage_locations(&self) -> &[StorageLocation] {
+        &self.global_settings_data.storage_locations
+    }
+
+    /// The storage location `id` refers to, the default one if `None`
+    pub fn storage_location(&self, id: Option<&str>) -> Result<StorageLocation, Error> {
+        storage::resolve(&self.global_settings_data.storage_locations, id)
+    }
+
+    /// The installation id can't be changed, the current one is kept
+    pub async fn set_telemetry(&mut self, mut telemetry: TelemetrySettings) -> Result<(), Error> {
+        telemetry.installation_id = self.global_settings_data.telemetry.installation_id.clone();
+        let old_telemetry = std::mem::replace(&mut self.global_settings_data.telemetry, telemetry);
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.telemetry = old_telemetry;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn telemetry(&self) -> TelemetrySettings {
+        self.global_settings_data.telemetry.clone()
+    }
+
+    pub async fn set_smtp(&mut self, mut smtp: Option<SmtpConfig>) -> Result<(), Error> {
+        if let (Some(smtp), Some(old_smtp)) = (&mut smtp, &self.global_settings_data.smtp) {
+            smtp.keep_secrets_from(old_smtp);
+        }
+        let old_smtp = std::mem::replace(&mut self.global_settings_data.smtp, smtp);
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.smtp = old_smtp;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn smtp(&self) -> Option<SmtpConfig> {
+        self.global_settings_data.smtp.clone()
+    }
+
+    pub async fn set_password_hashing(
+        &mut self,
+        password_hashing: PasswordHashing,
+    ) -> Result<(), Error> {
+        let old_password_hashing = self.global_settings_data.password_hashing;
+        self.global_settings_data.password_hashing = password_hashing;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.password_hashing = old_password_hashing;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn password_hashing(&self) -> PasswordHashing {
+        self.global_settings_data.password_hashing
+    }
+
+    pub async fn set_maintenance(
+        &mut self,
+        maintenance: Option<MaintenanceWindow>,
+    ) -> Result<(), Error> {
+        let old_maintenance =
+            std::mem::replace(&mut self.global_settings_data.maintenance, maintenance);
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.maintenance = old_maintenance;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn maintenance(&self) -> Option<MaintenanceWindow> {
+        self.global_settings_data.maintenance.clone()
+    }
+
+    async fn update_quotas(&mut self, f: impl FnOnce(&mut QuotaSettings)) -> Result<(), Error> {
+        let old_quotas = self.global_settings_data.quotas.clone();
+        f(&mut self.global_settings_data.quotas);
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.quotas = old_quotas;
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn set_role_quota(&mut self, role: QuotaRole, quota: UserQuota) -> Result<(), Error> {
+        self.update_quotas(|quotas| match role {
+            QuotaRole::Admin => quotas.admin = quota,
+            QuotaRole::User => quotas.user = quota,
+        })
+        .await
+    }
+
+    /// `None` makes the user fall back to the quota of their role
+    pub async fn set_user_quota(
+        &mut self,
+        uid: UserId,
+        quota: Option<UserQuota>,
+    ) -> Result<(), Error> {
+        self.update_quotas(|quotas| match quota {
+            Some(quota) => {
+                quotas.users.insert(uid, quota);
+            }
+            None => {
+                quotas.users.remove(&uid);
+            }
+        })
+        .await
+    }
+
+    pub fn quotas(&self) -> &QuotaSettings {
+        &self.global_settings_data.quotas
+    }
+
+    /// `None` forgets about the instance, leaving it to admins and explicit permissions
+    pub async fn set_instance_access(
+        &mut self,
+        uuid: InstanceUuid,
+        access: Option<InstanceAccess>,
+    ) -> Result<(), Error> {
+        let old_access = match access {
+            Some(access) => self
+                .global_settings_data
+                .instance_access
+                .insert(uuid.clone(), access),
+            None => self.global_settings_data.instance_access.remove(&uuid),
+        };
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                match old_access {
+                    Some(old_access) => self
+                        .global_settings_data
+                        .instance_access
+                        .insert(uuid, old_access),
+                    None => self.global_settings_data.instance_access.remove(&uuid),
+                };
+                Err(e)
+            }
+        }
+    }
+
+    pub fn instance_access(&self, uuid: &InstanceUuid) -> Option<InstanceAccess> {
+        self.global_settings_data.instance_access.get(uuid).cloned()
+    }
+
+    pub fn instance_settings(&self, uuid: &InstanceUuid) -> Option<&InstanceSettings> {
+        self.global_settings_data.instance_settings.get(uuid)
+    }
+
+    /// Changes an instance's settings with `f`, nothing is changed if they can't be written
+    async fn update_instance_settings(
+        &mut self,
+        uuid: InstanceUuid,
+        f: impl FnOnce(&mut InstanceSettings),
+    ) -> Result<(), Error> {
+        let old_settings = self.global_settings_data.instance_settings.remove(&uuid);
+        let mut settings = old_settings.clone().unwrap_or_default();
+        f(&mut settings);
+        if !settings.is_empty() {
+            self.global_settings_data
+                .instance_settings
+                .insert(uuid.clone(), settings);
+        }
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                match old_settings {
+                    Some(old_settings) => self
+                        .global_settings_data
+                        .instance_settings
+                        .insert(uuid, old_settings),
+                    None => self.global_settings_data.instance_settings.remove(&uuid),
+                };
+                Err(e)
+            }
+        }
+    }
+
+    /// One setting of every instance that has it
+    fn all_instance_settings<T>(
+        &self,
+        f: impl Fn(&InstanceSettings) -> Option<T>,
+    ) -> HashMap<InstanceUuid, T> {
+        self.global_settings_data
+            .instance_settings
+            .iter()
+            .filter_map(|(uuid, settings)| Some((uuid.clone(), f(settings)?)))
+            .collect()
+    }
+
+    /// Drops everything kept about an instance, returning its settings.
+    ///
+    /// Nothing is changed if the result can't be written
+    pub async fn forget(&mut self, uuid: &InstanceUuid) -> Result<InstanceSettings, Error> {
+        let old_data = self.global_settings_data.clone();
+        let data = &mut self.global_settings_data;
+        let settings = data.instance_settings.remove(uuid).unwrap_or_default();
+        data.instance_access.remove(uuid);
+        for group in data.whitelist_groups.iter_mut() {
+            group.instances.retain(|instance| instance != uuid);
+        }
+        // a role left with no instances would apply to all of them
+        data.command_roles.retain(|role| {
+            role.instances.is_empty() || role.instances.iter().any(|instance| instance != uuid)
+        });
+        for role in data.command_roles.iter_mut() {
+            role.instances.retain(|instance| instance != uuid);
+        }
+        match self.write_to_file().await {
+            Ok(_) => Ok(settings),
+            Err(e) => {
+                self.global_settings_data = old_data;
+                Err(e)
+            }
+        }
+    }
+
+    /// `None` resets the instance to the core-wide policy
+    pub async fn set_instance_shutdown_policy(
+        &mut self,
+        uuid: InstanceUuid,
+        policy: Option<ShutdownPolicy>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| settings.shutdown_policy = policy)
+            .await
+    }
+
+    pub fn instance_shutdown_policy(&self, uuid: &InstanceUuid) -> Option<ShutdownPolicy> {
+        self.instance_settings(uuid)
+            .and_then(|settings| settings.shutdown_policy)
+    }
+
+    /// `None` removes the instance's schedule
+    pub async fn set_instance_power_schedule(
+        &mut self,
+        uuid: InstanceUuid,
+        schedule: Option<PowerSchedule>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| settings.power_schedule = schedule)
+            .await
+    }
+
+    pub fn instance_power_schedule(&self, uuid: &InstanceUuid) -> Option<PowerSchedule> {
+        self.instance_settings(uuid)
+            .and_then(|settings| settings.power_schedule.clone())
+    }
+
+    pub fn all_power_schedules(&self) -> HashMap<InstanceUuid, PowerSchedule> {
+        self.all_instance_settings(|settings| settings.power_schedule.clone())
+    }
+
+    /// `None` removes the instance's schedule
+    pub async fn set_instance_restart_schedule(
+        &mut self,
+        uuid: InstanceUuid,
+        schedule: Option<RestartSchedule>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| settings.restart_schedule = schedule)
+            .await
+    }
+
+    pub fn instance_restart_schedule(&self, uuid: &InstanceUuid) -> Option<RestartSchedule> {
+        self.instance_settings(uuid)
+            .and_then(|settings| settings.restart_schedule.clone())
+    }
+
+    pub fn all_restart_schedules(&self) -> HashMap<InstanceUuid, RestartSchedule> {
+        self.all_instance_settings(|settings| settings.restart_schedule.clone())
+    }
+
+    /// `None` removes the instance's filter
+    pub async fn set_instance_network_filter(
+        &mut self,
+        uuid: InstanceUuid,
+        filter: Option<NetworkFilterSettings>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| settings.network_filter = filter)
+            .await
+    }
+
+    pub fn instance_network_filter(&self, uuid: &InstanceUuid) -> Option<NetworkFilterSettings> {
+        self.instance_settings(uuid)
+            .and_then(|settings| settings.network_filter.clone())
+    }
+
+    pub fn all_network_filters(&self) -> HashMap<InstanceUuid, NetworkFilterSettings> {
+        self.all_instance_settings(|settings| settings.network_filter.clone())
+    }
+
+    /// `None` removes the instance's resource pack
+    pub async fn set_instance_resource_pack(
+        &mut self,
+        uuid: InstanceUuid,
+        pack: Option<ResourcePack>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| settings.resource_pack = pack)
+            .await
+    }
+
+    pub fn instance_resource_pack(&self, uuid: &InstanceUuid) -> Option<ResourcePack> {
+        self.instance_settings(uuid)
+            .and_then(|settings| settings.resource_pack.clone())
+    }
+
+    /// The pack served at `/resource_packs/:id`
+    pub fn resource_pack_by_id(&self, id: &str) -> Option<ResourcePack> {
+        self.global_settings_data
+            .instance_settings
+            .values()
+            .filter_map(|settings| settings.resource_pack.as_ref())
+            .find(|pack| pack.id == id)
+            .cloned()
+    }
+
+    /// `None` removes the instance's web proxy
+    pub async fn set_instance_web_proxy(
+        &mut self,
+        uuid: InstanceUuid,
+        proxy: Option<WebProxySettings>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| settings.web_proxy = proxy)
+            .await
+    }
+
+    pub fn instance_web_proxy(&self, uuid: &InstanceUuid) -> Option<WebProxySettings> {
+        self.instance_settings(uuid)
+            .and_then(|settings| settings.web_proxy)
+    }
+
+    /// An empty list removes the instance's rules
+    pub async fn set_instance_alert_rules(
+        &mut self,
+        uuid: InstanceUuid,
+        rules: Vec<AlertRule>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| settings.alert_rules = rules)
+            .await
+    }
+
+    pub fn instance_alert_rules(&self, uuid: &InstanceUuid) -> Vec<AlertRule> {
+        self.instance_settings(uuid)
+            .map(|settings| settings.alert_rules.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn all_alert_rules(&self) -> HashMap<InstanceUuid, Vec<AlertRule>> {
+        self.all_instance_settings(|settings| {
+            Some(settings.alert_rules.clone()).filter(|rules| !rules.is_empty())
+        })
+    }
+
+    /// An empty list removes the instance's rules
+    pub async fn set_instance_moderation_rules(
+        &mut self,
+        uuid: InstanceUuid,
+        rules: Vec<ModerationRule>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| settings.moderation_rules = rules)
+            .await
+    }
+
+    pub fn instance_moderation_rules(&self, uuid: &InstanceUuid) -> Vec<ModerationRule> {
+        self.instance_settings(uuid)
+            .map(|settings| settings.moderation_rules.clone())
+            .unwrap_or_default()
+    }
+
+    pub async fn set_instance_announcements(
+        &mut self,
+        uuid: InstanceUuid,
+        announcements: Vec<Announcement>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| settings.announcements = announcements)
+            .await
+    }
+
+    pub fn instance_announcements(&self, uuid: &InstanceUuid) -> Vec<Announcement> {
+        self.instance_settings(uuid)
+            .map(|settings| settings.announcements.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn all_announcements(&self) -> HashMap<InstanceUuid, Vec<Announcement>> {
+        self.all_instance_settings(|settings| {
+            Some(settings.announcements.clone()).filter(|announcements| !announcements.is_empty())
+        })
+    }
+
+    pub async fn set_remote_backup(
+        &mut self,
+        mut settings: Option<RemoteBackupSettings>,
+    ) -> Result<(), Error> {
+        if let (Some(settings), Some(old_settings)) =
+            (&mut settings, &self.global_settings_data.remote_backup)
+        {
+            settings.keep_secrets_from(old_settings);
+        }
+        let old_settings =
+            std::mem::replace(&mut self.global_settings_data.remote_backup, settings);
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.remote_backup = old_settings;
+                Err(e)
+            }
+        }
+    }
+
+    /// `None` makes the instance use the core-wide remote backup settings
+    pub async fn set_instance_remote_backup(
+        &mut self,
+        uuid: InstanceUuid,
+        mut remote_backup: Option<RemoteBackupSettings>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| {
+            if let (Some(remote_backup), Some(old_remote_backup)) =
+                (&mut remote_backup, &settings.remote_backup)
+            {
+                remote_backup.keep_secrets_from(old_remote_backup);
+            }
+            settings.remote_backup = remote_backup;
+        })
+        .await
+    }
+
+    /// The settings that apply to an instance, its own if it has any
+    pub fn remote_backup(&self, uuid: &InstanceUuid) -> Option<RemoteBackupSettings> {
+        self.instance_settings(uuid)
+            .and_then(|settings| settings.remote_backup.as_ref())
+            .or(self.global_settings_data.remote_backup.as_ref())
+            .cloned()
+    }
+
+    pub async fn set_log_shipping(
+        &mut self,
+        mut settings: Option<LogShippingSettings>,
+    ) -> Result<(), Error> {
+        if let (Some(settings), Some(old_settings)) =
+            (&mut settings, &self.global_settings_data.log_shipping)
+        {
+            settings.keep_secrets_from(old_settings);
+        }
+        let old_settings = std::mem::replace(&mut self.global_settings_data.log_shipping, settings);
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.log_shipping = old_settings;
+                Err(e)
+            }
+        }
+    }
+
+    /// `None` makes the instance use the core-wide log shipping settings
+    pub async fn set_instance_log_shipping(
+        &mut self,
+        uuid: InstanceUuid,
+        mut log_shipping: Option<LogShippingSettings>,
+    ) -> Result<(), Error> {
+        self.update_instance_settings(uuid, |settings| {
+            if let (Some(log_shipping), Some(old_log_shipping)) =
+                (&mut log_shipping, &settings.log_shipping)
+            {
+                log_shipping.keep_secrets_from(old_log_shipping);
+            }
+            settings.log_shipping = log_shipping;
+        })
+        .await
+    }
+
+    /// The log shipping settings that apply to an instance, its own if it has any
+    pub fn log_shipping(&self, uuid: &InstanceUuid) -> Option<LogShippingSettings> {
+        self.instance_settings(uuid)
+            .and_then(|settings| settings.log_shipping.as_ref())
+            .or(self.global_settings_data.log_shipping.as_ref())
+            .cloned()
+    }
 }
 
 impl AsRef<GlobalSettingsData> for GlobalSettings {
@@ -159,7 +1018,7 @@ mod tests {
     #[tokio::test]
     async fn test_global_settings() {
         use super::*;
-        use std::path::PathBuf;
+        use std::{collections::HashMap, path::PathBuf};
 
         // create a temporary directory
         let temp_dir = tempdir::TempDir::new("test_global_settings").unwrap();
@@ -213,4 +1072,74 @@ mod tests {
 
         assert_eq!(global_settings.core_name(), "test_core_name");
     }
+
+    #[test]
+    fn test_public() {
+        use super::*;
+
+        let mut data = GlobalSettingsData {
+            core_name: "test_core_name".to_string(),
+            ..Default::default()
+        };
+        data.instance_settings.insert(
+            InstanceUuid::default(),
+            InstanceSettings {
+                web_proxy: Some(WebProxySettings { port: 8123 }),
+                ..Default::default()
+            },
+        );
+        let public = data.public();
+        assert_eq!(public.core_name, "test_core_name");
+        assert!(public.instance_settings.is_empty());
+        assert_eq!(data.redacted().instance_settings.len(), 1);
+    }
+
+    #[test]
+    fn test_legacy_instance_maps() {
+        use super::*;
+
+        let uuid = InstanceUuid::default();
+        let mut data = serde_json::json!({
+            "core_name": "test_core_name",
+            "safe_mode": true,
+            "domain": null,
+            "instance_web_proxies": { uuid.to_string(): { "port": 8123 } },
+            "instance_shutdown_policies": {},
+        });
+        migrate_legacy_instance_maps(&mut data);
+        let data: GlobalSettingsData = serde_json::from_value(data).unwrap();
+        assert_eq!(data.instance_settings.len(), 1);
+        assert_eq!(
+            data.instance_settings[&uuid].web_proxy,
+            Some(WebProxySettings { port: 8123 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forget() {
+        use super::*;
+        use std::path::PathBuf;
+
+        let temp_dir = tempdir::TempDir::new("test_forget").unwrap();
+        let (event_broadcaster, _) = EventBroadcaster::new(10);
+        let mut global_settings = GlobalSettings::new(
+            PathBuf::from(temp_dir.path()).join("global_settings.json"),
+            event_broadcaster,
+            GlobalSettingsData::default(),
+        );
+        let uuid = InstanceUuid::default();
+        global_settings
+            .set_instance_web_proxy(uuid.clone(), Some(WebProxySettings { port: 8123 }))
+            .await
+            .unwrap();
+        global_settings
+            .set_instance_shutdown_policy(uuid.clone(), Some(ShutdownPolicy::default()))
+            .await
+            .unwrap();
+        assert!(global_settings.referenced_instances().contains(&uuid));
+
+        let settings = global_settings.forget(&uuid).await.unwrap();
+        assert_eq!(settings.web_proxy, Some(WebProxySettings { port: 8123 }));
+        assert!(global_settings.referenced_instances().is_empty());
+    }
 }
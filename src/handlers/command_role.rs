@@ -0,0 +1,106 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    command_filter::{CommandRole, NewCommandRole, MAX_ROLES},
+    error::{Error, ErrorKind},
+    types::Snowflake,
+    AppState,
+};
+
+fn role_not_found() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Command role not found"),
+    }
+}
+
+pub async fn list_command_roles(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<CommandRole>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    Ok(Json(
+        state.global_settings.lock().await.command_roles().to_vec(),
+    ))
+}
+
+pub async fn create_command_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(new_role): Json<NewCommandRole>,
+) -> Result<Json<CommandRole>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    new_role.validate()?;
+    let mut global_settings = state.global_settings.lock().await;
+    let mut roles = global_settings.command_roles().to_vec();
+    if roles.len() >= MAX_ROLES {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("There can be at most {MAX_ROLES} command roles"),
+        });
+    }
+    let role = new_role.into_role(Snowflake::new());
+    roles.push(role.clone());
+    global_settings.set_command_roles(roles).await?;
+    Ok(Json(role))
+}
+
+pub async fn update_command_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(role_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+    Json(new_role): Json<NewCommandRole>,
+) -> Result<Json<CommandRole>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    new_role.validate()?;
+    let mut global_settings = state.global_settings.lock().await;
+    let mut roles = global_settings.command_roles().to_vec();
+    let role = roles
+        .iter_mut()
+        .find(|role| role.id == role_id)
+        .ok_or_else(role_not_found)?;
+    *role = new_role.into_role(role_id);
+    let role = role.clone();
+    global_settings.set_command_roles(roles).await?;
+    Ok(Json(role))
+}
+
+pub async fn delete_command_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(role_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    let mut global_settings = state.global_settings.lock().await;
+    let mut roles = global_settings.command_roles().to_vec();
+    let count = roles.len();
+    roles.retain(|role| role.id != role_id);
+    if roles.len() == count {
+        return Err(role_not_found());
+    }
+    global_settings.set_command_roles(roles).await?;
+    Ok(Json(()))
+}
+
+pub fn get_command_role_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/command_roles",
+            get(list_command_roles).post(create_command_role),
+        )
+        .route(
+            "/command_roles/:role_id",
+            put(update_command_role).delete(delete_command_role),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,89 @@
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::header,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    core_backup::CoreBackup,
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    AppState,
+};
+
+/// Everything but the instances' files, see [`crate::core_backup`].
+///
+/// Owner only, the archive holds every account's password hash.
+pub async fn get_core_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Response, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to back up the core"),
+        });
+    }
+    let backup = CoreBackup::create(&state).await?;
+    let file_name = format!("lodestone_core_backup_{}.tar.gz", backup.info.created_at);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/gzip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        backup.to_archive()?,
+    )
+        .into_response())
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct RestoreQuery {
+    /// There is no owner to log in as on a fresh install, so the first time setup key is used
+    pub setup_key: String,
+}
+
+pub async fn restore_core_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Query(query): Query<RestoreQuery>,
+    body: Bytes,
+) -> Result<Json<()>, Error> {
+    match &*state.first_time_setup_key.lock().await {
+        Some(key) if *key == query.setup_key => {}
+        Some(_) => {
+            return Err(Error::localized(
+                ErrorKind::PermissionDenied,
+                LocalizedMessage::new(MessageId::InvalidSetupKey),
+            ))
+        }
+        None => {
+            return Err(Error::localized(
+                ErrorKind::PermissionDenied,
+                LocalizedMessage::new(MessageId::AlreadySetup),
+            ))
+        }
+    }
+    let backup = tokio::task::spawn_blocking(move || CoreBackup::from_archive(&body))
+        .await
+        .map_err(|e| eyre!("Backup parsing task panicked: {e}"))??;
+    backup.restore(&state).await?;
+    Ok(Json(()))
+}
+
+pub fn get_core_backup_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/core/backup", get(get_core_backup))
+        .route("/core/restore", post(restore_core_backup))
+        .with_state(state)
+}
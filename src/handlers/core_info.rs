@@ -2,24 +2,9 @@ use std::env;
 
 use crate::{prelude::VERSION, AppState};
 use axum::{routing::get, Json, Router};
-use serde::{Deserialize, Serialize};
 use sysinfo::{CpuExt, DiskExt, System, SystemExt};
 
-#[derive(Serialize, Deserialize)]
-pub struct CoreInfo {
-    version: semver::Version,
-    is_setup: bool,
-    os: String,
-    arch: String,
-    cpu: String,
-    cpu_count: u32,
-    total_ram: u64,
-    total_disk: u64,
-    host_name: String,
-    uuid: String,
-    core_name: String,
-    up_since: i64,
-}
+pub use lodestone_types::CoreInfo;
 
 pub async fn get_core_info(
     axum::extract::State(state): axum::extract::State<AppState>,
@@ -1,25 +1,37 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
+    sync::Arc,
+};
 
 use axum::{
+    body::StreamBody,
     extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
-    response::Response,
+    http::{header, HeaderMap},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::get,
     Json, Router,
 };
 use axum_auth::AuthBearer;
 
 use color_eyre::eyre::eyre;
-use futures::{SinkExt, StreamExt};
+use futures::{SinkExt, Stream, StreamExt};
 use ringbuffer::{AllocRingBuffer, RingBufferExt};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
-use crate::output_types::ClientEvent;
+use crate::output_types::{ClientEvent, CLIENT_EVENT_CSV_HEADER};
 use crate::types::InstanceUuid;
 use crate::{
     auth::{user::UsersManager, user_id::UserId},
-    db::read::search_events,
+    db::read::{events_after_snowflake, search_events, stream_events},
     error::{Error, ErrorKind},
-    events::EventQuery,
+    event_broadcaster::SubscriptionFilter,
+    events::{EventQuery, EventType},
+    global_settings::GlobalSettings,
+    types::Snowflake,
 };
 
 use crate::{
@@ -27,16 +39,33 @@ use crate::{
     AppState,
 };
 use serde::Deserialize;
-use tokio::sync::{broadcast::Receiver, RwLock};
+use tokio::sync::{
+    broadcast::{
+        error::{RecvError, TryRecvError},
+        Receiver,
+    },
+    Mutex, RwLock,
+};
+use tokio_stream::wrappers::ReceiverStream;
 use ts_rs::TS;
 
 use super::util::parse_bearer_token;
 
 #[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
 pub struct EventQueryWrapper {
     filter: String,
 }
 
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct EventStreamQuery {
+    filter: String,
+    /// Snowflake of the last event the client has seen.
+    /// If present, events after it are replayed from the database before going live
+    cursor: Option<Snowflake>,
+}
+
 pub async fn get_event_buffer(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
@@ -59,6 +88,7 @@ pub async fn get_event_buffer(
             kind: ErrorKind::Unauthorized,
             source: eyre!("Token error"),
         })?;
+    let access = state.all_instance_access().await;
     Ok(Json(
         state
             .events_buffer
@@ -66,7 +96,7 @@ pub async fn get_event_buffer(
             .await
             .iter()
             .filter(|event| {
-                query.filter(ClientEvent::from(*event)) && requester.can_view_event(*event)
+                query.filter(ClientEvent::from(*event)) && requester.can_view_event(*event, &access)
             })
             .cloned()
             .collect(),
@@ -99,6 +129,97 @@ pub async fn get_event_search(
     search_events(&state.sqlite_pool, query).await.map(Json)
 }
 
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum EventExportFormat {
+    #[default]
+    Jsonl,
+    Csv,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct EventExportQuery {
+    filter: String,
+    #[serde(default)]
+    format: EventExportFormat,
+}
+
+/// Streams every stored event matching the filter that the requester can see, oldest first
+pub async fn export_events(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(export_query): Query<EventExportQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let query: EventQuery = serde_json::from_str(&export_query.filter).map_err(|e| {
+        error!("Error deserializing event query: {}", e);
+        Error {
+            kind: ErrorKind::BadRequest,
+            source: e.into(),
+        }
+    })?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let access = state.all_instance_access().await;
+    let format = export_query.format;
+    let pool = state.sqlite_pool.clone();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, std::io::Error>>(64);
+    tokio::spawn(async move {
+        if format == EventExportFormat::Csv
+            && tx
+                .send(Ok(CLIENT_EVENT_CSV_HEADER.to_string()))
+                .await
+                .is_err()
+        {
+            return;
+        }
+        let mut events = stream_events(&pool, query.time_range.clone());
+        while let Some(client_event) = events.next().await {
+            let line = match client_event {
+                Ok(client_event) => {
+                    if !query.filter(&client_event)
+                        || !requester.can_view_event(&Event::from(&client_event), &access)
+                    {
+                        continue;
+                    }
+                    match format {
+                        EventExportFormat::Jsonl => {
+                            let mut line = serde_json::to_string(&client_event).unwrap();
+                            line.push('\n');
+                            Ok(line)
+                        }
+                        EventExportFormat::Csv => Ok(client_event.to_csv_row()),
+                    }
+                }
+                // fails the response instead of ending it early as if complete
+                Err(e) => Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    e.to_string(),
+                )),
+            };
+            let failed = line.is_err();
+            // the client went away
+            if tx.send(line).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    let (content_type, file_name) = match format {
+        EventExportFormat::Jsonl => ("application/x-ndjson", "events.jsonl"),
+        EventExportFormat::Csv => ("text/csv", "events.csv"),
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        StreamBody::new(ReceiverStream::new(rx)),
+    ))
+}
+
 pub async fn get_console_buffer(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
@@ -113,6 +234,7 @@ pub async fn get_console_buffer(
             kind: ErrorKind::Unauthorized,
             source: eyre!("Token error"),
         })?;
+    let access = state.all_instance_access().await;
     Ok(Json(
         state
             .console_out_buffer
@@ -124,7 +246,7 @@ pub async fn get_console_buffer(
             .filter(|event| match &event.event_inner {
                 EventInner::InstanceEvent(instance_event) => {
                     (instance_event.instance_uuid == uuid || uuid == "all")
-                        && requester.can_view_event(event)
+                        && requester.can_view_event(event, &access)
                 }
                 _ => false,
             })
@@ -133,16 +255,21 @@ pub async fn get_console_buffer(
     ))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS)]
+#[ts(export)]
 pub struct WebsocketQuery {
-    token: String,
+    pub token: String,
 }
 
+/// Events replayed per database query
+const REPLAY_PAGE_SIZE: i64 = 256;
+
 pub async fn event_stream(
     ws: WebSocketUpgrade,
     axum::extract::State(state): axum::extract::State<AppState>,
-    query: Query<EventQueryWrapper>,
+    query: Query<EventStreamQuery>,
 ) -> Result<Response, Error> {
+    let cursor = query.cursor;
     let query: EventQuery = serde_json::from_str(query.filter.as_str()).map_err(|e| {
         error!("Error deserializing event query: {}", e);
         Error {
@@ -164,38 +291,40 @@ pub async fn event_stream(
             kind: ErrorKind::Unauthorized,
             source: eyre!("Token error"),
         })?;
-    let event_receiver = state.event_broadcaster.subscribe();
+    // subscribe before reading the db so no event can fall between the replay and the live stream
+    let event_receiver = state
+        .event_broadcaster
+        .subscribe_filtered(SubscriptionFilter {
+            exclude_console: true,
+            ..SubscriptionFilter::from(&query)
+        });
 
     Ok(ws.on_upgrade(move |socket| {
-        event_stream_ws(socket, event_receiver, query, user.uid, state.users_manager)
+        event_stream_ws(socket, event_receiver, cursor, query, user.uid, state)
     }))
 }
 
 async fn event_stream_ws(
     stream: WebSocket,
-    mut event_receiver: Receiver<Event>,
+    event_receiver: Receiver<Event>,
+    cursor: Option<Snowflake>,
     query: EventQuery,
     uid: UserId,
-    users_manager: Arc<RwLock<UsersManager>>,
+    state: AppState,
 ) {
     let (mut sender, mut receiver) = stream.split();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(64);
+    tokio::spawn(follow_events(tx, event_receiver, cursor, query, uid, state));
     loop {
         tokio::select! {
-            Ok(event) = event_receiver.recv() => {
-                if event.is_event_console_message() {
-                    continue;
-                }
-                let user = match users_manager.read().await.get_user(&uid) {
-                    Some(user) => user,
-                    None => {
-                        break;
-                    }
+            client_event = rx.recv() => {
+                let client_event = match client_event {
+                    Some(client_event) => client_event,
+                    None => break,
                 };
-                if query.filter(ClientEvent::from(event.clone())) && user.can_view_event(&event) {
-                    if let Err(e) = sender.send(axum::extract::ws::Message::Text(serde_json::to_string(&event).unwrap())).await {
-                        error!("Error sending event to websocket: {}", e);
-                        break;
-                    }
+                if let Err(e) = sender.send(axum::extract::ws::Message::Text(serde_json::to_string(&client_event).unwrap())).await {
+                    error!("Error sending event to websocket: {}", e);
+                    break;
                 }
             }
             Some(Ok(ws_msg)) = receiver.next() => {
@@ -208,6 +337,180 @@ async fn event_stream_ws(
     }
 }
 
+/// Same events as [`event_stream`], for clients behind proxies that break WebSockets.
+///
+/// `EventSource` reconnects on its own and sends the id of the last event it got as
+/// `Last-Event-ID`, which takes precedence over `cursor` so nothing is missed in between.
+pub async fn event_stream_sse(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    query: Query<EventStreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, Error> {
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok())
+        .map(|id| {
+            id.parse::<Snowflake>().map_err(|e| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid Last-Event-ID: {}", e),
+            })
+        })
+        .transpose()?;
+    let cursor = last_event_id.or(query.cursor);
+    let query: EventQuery = serde_json::from_str(query.filter.as_str()).map_err(|e| {
+        error!("Error deserializing event query: {}", e);
+        Error {
+            kind: ErrorKind::BadRequest,
+            source: e.into(),
+        }
+    })?;
+    let token = query.bearer_token.clone().ok_or(Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Missing token"),
+    })?;
+    let user = state
+        .users_manager
+        .read()
+        .await
+        .try_auth(&token)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    let event_receiver = state
+        .event_broadcaster
+        .subscribe_filtered(SubscriptionFilter {
+            exclude_console: true,
+            ..SubscriptionFilter::from(&query)
+        });
+    let (tx, rx) = tokio::sync::mpsc::channel(64);
+    tokio::spawn(follow_events(
+        tx,
+        event_receiver,
+        cursor,
+        query,
+        user.uid,
+        state,
+    ));
+    Ok(
+        Sse::new(ReceiverStream::new(rx).map(|client_event| to_sse_event(&client_event)))
+            .keep_alive(KeepAlive::default()),
+    )
+}
+
+fn to_sse_event(client_event: &ClientEvent) -> Result<SseEvent, Infallible> {
+    Ok(SseEvent::default()
+        .id(client_event.snowflake.to_string())
+        .data(serde_json::to_string(client_event).unwrap()))
+}
+
+/// Sends the events the user may see to `tx`, those stored after `cursor` first, then live ones
+///
+/// Live events that arrive during the replay are held back until it is done, and each event
+/// is sent once even if it was both replayed and broadcast. Ends when `tx` is closed.
+async fn follow_events(
+    tx: tokio::sync::mpsc::Sender<ClientEvent>,
+    mut event_receiver: Receiver<Event>,
+    cursor: Option<Snowflake>,
+    query: EventQuery,
+    uid: UserId,
+    state: AppState,
+) {
+    let mut held_back = VecDeque::new();
+    if let Some(mut cursor) = cursor {
+        let user = match state.users_manager.read().await.get_user(&uid) {
+            Some(user) => user,
+            None => return,
+        };
+        let instance_access = state
+            .global_settings
+            .lock()
+            .await
+            .as_ref()
+            .instance_access
+            .clone();
+        let mut replayed = HashSet::new();
+        loop {
+            let page =
+                match events_after_snowflake(&state.sqlite_pool, cursor, REPLAY_PAGE_SIZE).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        // the client reconnects with the last event it got, so nothing is lost
+                        error!("Failed to replay events: {}", e);
+                        return;
+                    }
+                };
+            let is_last_page = (page.len() as i64) < REPLAY_PAGE_SIZE;
+            for client_event in page {
+                cursor = client_event.snowflake;
+                replayed.insert(client_event.snowflake);
+                let event = Event::from(&client_event);
+                if !event.is_event_console_message()
+                    && query.filter(&client_event)
+                    && user.can_view_event(&event, &instance_access)
+                    && tx.send(client_event).await.is_err()
+                {
+                    return;
+                }
+                hold_back_live_events(&mut event_receiver, &mut held_back);
+            }
+            if is_last_page {
+                break;
+            }
+        }
+        // anything stored by now was broadcast before this point
+        hold_back_live_events(&mut event_receiver, &mut held_back);
+        held_back.retain(|event| !replayed.contains(&event.snowflake));
+    }
+    loop {
+        let event = match held_back.pop_front() {
+            Some(event) => event,
+            None => tokio::select! {
+                event = event_receiver.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Event stream fell behind, skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                // the client went away
+                _ = tx.closed() => break,
+            },
+        };
+        if event.is_event_console_message() {
+            continue;
+        }
+        let user = match state.users_manager.read().await.get_user(&uid) {
+            Some(user) => user,
+            None => break,
+        };
+        let client_event = ClientEvent::from(&event);
+        if query.filter(&client_event)
+            && user.can_view_event(
+                &event,
+                &state.global_settings.lock().await.as_ref().instance_access,
+            )
+            && tx.send(client_event).await.is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Moves the live events received so far into `held_back`, so a long replay doesn't lag the receiver
+fn hold_back_live_events(event_receiver: &mut Receiver<Event>, held_back: &mut VecDeque<Event>) {
+    loop {
+        match event_receiver.try_recv() {
+            Ok(event) => held_back.push_back(event),
+            Err(TryRecvError::Lagged(skipped)) => {
+                warn!("Event stream fell behind, skipped {} events", skipped);
+            }
+            Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+        }
+    }
+}
+
 pub async fn console_stream(
     ws: WebSocketUpgrade,
     axum::extract::State(state): axum::extract::State<AppState>,
@@ -223,10 +526,24 @@ pub async fn console_stream(
             source: eyre!("Token error"),
         })?;
     drop(users_manager);
-    let event_receiver = state.event_broadcaster.subscribe();
+    // user events are let through to notice the user logging out
+    let event_receiver = state
+        .event_broadcaster
+        .subscribe_filtered(SubscriptionFilter {
+            event_types: Some(vec![EventType::InstanceEvent, EventType::UserEvent]),
+            instance_uuids: (uuid != "all").then(|| vec![uuid.clone()]),
+            ..Default::default()
+        });
 
     Ok(ws.on_upgrade(move |socket| {
-        console_stream_ws(socket, event_receiver, user.uid, uuid, state.users_manager)
+        console_stream_ws(
+            socket,
+            event_receiver,
+            user.uid,
+            uuid,
+            state.users_manager,
+            state.global_settings,
+        )
     }))
 }
 
@@ -236,6 +553,7 @@ async fn console_stream_ws(
     uid: UserId,
     uuid: InstanceUuid,
     users_manager: Arc<RwLock<UsersManager>>,
+    global_settings: Arc<Mutex<GlobalSettings>>,
 ) {
     let (mut sender, mut receiver) = stream.split();
     loop {
@@ -248,7 +566,7 @@ async fn console_stream_ws(
                             None => break,
                         };
                         if event.is_event_console_message() && (instance_event.instance_uuid == uuid || uuid == "all")
-                            && user.can_view_event(&event)
+                            && user.can_view_event(&event, &global_settings.lock().await.as_ref().instance_access)
                         {
                             if let Err(e) = sender
                                 .send(axum::extract::ws::Message::Text(
@@ -289,8 +607,17 @@ async fn console_stream_ws(
 pub fn get_events_routes(state: AppState) -> Router {
     Router::new()
         .route("/events/:uuid/stream", get(event_stream))
+        .route("/events/:uuid/sse", get(event_stream_sse))
         .route("/events/:uuid/buffer", get(get_event_buffer))
-        .route("/events/search", get(get_event_search))
+        .merge(
+            Router::new()
+                .route("/events/search", get(get_event_search))
+                .route("/events/export", get(export_events))
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::rate_limiter::rate_limit,
+                )),
+        )
         .route("/instance/:uuid/console/stream", get(console_stream))
         .route("/instance/:uuid/console/buffer", get(get_console_buffer))
         .with_state(state)
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query},
+    http::{Method, StatusCode},
+    routing::{any, get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde_json::Value;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    extension::{self, ExtensionInfo, ExtensionInstanceType},
+    AppState,
+};
+
+pub async fn get_extensions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ExtensionInfo>>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.extensions.lock().await.list()))
+}
+
+pub async fn get_extension_instance_types(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ExtensionInstanceType>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::CreateInstance)
+        .await?;
+    Ok(Json(state.extensions.lock().await.instance_types()))
+}
+
+pub async fn stop_extension(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(name): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner can stop extensions"),
+        });
+    }
+    extension::stop_extension(&state, &name).await?;
+    Ok(Json(()))
+}
+
+/// Hands the request to the extension, which answers with any status and JSON body
+pub async fn forward_to_extension(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    method: Method,
+    Path((name, path)): Path<(String, String)>,
+    Query(query): Query<HashMap<String, String>>,
+    AuthBearer(token): AuthBearer,
+    body: Option<Json<Value>>,
+) -> Result<(StatusCode, Json<Value>), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let (bridge, request) = state.extensions.lock().await.request(
+        &name,
+        method.to_string(),
+        path,
+        query,
+        body.map(|Json(body)| body),
+        &requester,
+    )?;
+    let response = bridge.call(request).await?;
+    let status = StatusCode::from_u16(response.status).map_err(|_| Error {
+        kind: ErrorKind::Internal,
+        source: eyre!(
+            "Extension {} responded with invalid status {}",
+            name,
+            response.status
+        ),
+    })?;
+    Ok((status, Json(response.body)))
+}
+
+pub fn get_extension_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/extensions", get(get_extensions))
+        .route(
+            "/extensions/instance_types",
+            get(get_extension_instance_types),
+        )
+        .route("/extensions/:name/stop", post(stop_extension))
+        .route("/extension/:name/*path", any(forward_to_extension))
+        .with_state(state)
+}
@@ -22,12 +22,7 @@ pub async fn open_port(
             kind: ErrorKind::Unauthorized,
             source: eyre!("Token error"),
         })?;
-    if !requester.is_owner {
-        return Err(Error {
-            kind: ErrorKind::Unauthorized,
-            source: eyre!("Only owners can open ports"),
-        });
-    }
+    requester.try_owner_rights()?;
 
     Ok(Json(state.port_manager.lock().await.open_port(port).await?))
 }
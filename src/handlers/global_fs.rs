@@ -117,6 +117,7 @@ async fn list_files(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     let ret: Vec<FileEntry> = list_dir(&path, None)
         .await?
@@ -161,6 +162,7 @@ async fn read_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Read,
@@ -198,6 +200,7 @@ async fn write_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Write,
@@ -236,6 +239,7 @@ async fn make_directory(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Create,
@@ -270,6 +274,7 @@ async fn move_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
 
     state.event_broadcaster.send(new_fs_event(
@@ -309,6 +314,7 @@ async fn remove_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Delete,
@@ -344,6 +350,7 @@ async fn remove_dir(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Delete,
@@ -380,6 +387,7 @@ async fn new_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Create,
@@ -417,6 +425,7 @@ async fn download_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Download,
@@ -467,6 +476,7 @@ async fn upload_file(
         CausedBy::User {
             user_id: requester.uid.clone(),
             user_name: requester.username.clone(),
+            impersonated_by: requester.impersonated_by.clone(),
         },
     );
     state.event_broadcaster.send(progression_start_event);
@@ -538,6 +548,7 @@ async fn upload_file(
         let caused_by = CausedBy::User {
             user_id: requester.uid.clone(),
             user_name: requester.username.clone(),
+            impersonated_by: requester.impersonated_by.clone(),
         };
         state.event_broadcaster.send(new_fs_event(
             FSOperation::Upload,
@@ -557,6 +568,48 @@ async fn upload_file(
     Ok(Json(()))
 }
 
+/// Streams the file at `path` as an attachment
+pub(crate) async fn file_attachment(
+    path: &std::path::Path,
+) -> Result<
+    (
+        [(HeaderName, String); 3],
+        StreamBody<ReaderStream<tokio::fs::File>>,
+    ),
+    Error,
+> {
+    let file = tokio::fs::File::open(&path)
+        .await
+        .context(format!("Failed to open file {}", path.display()))?;
+
+    let headers = [
+        (
+            http::header::CONTENT_DISPOSITION,
+            "application/octet-stream".to_string(),
+        ),
+        (
+            http::header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"{}\"",
+                path.file_name()
+                    .and_then(|s| s.to_str().map(|s| s.to_string()))
+                    .unwrap_or_else(|| "unknown".to_string())
+            ),
+        ),
+        if let Ok(metadata) = file.metadata().await {
+            (http::header::CONTENT_LENGTH, metadata.len().to_string())
+        } else {
+            // if we can't get the file size, we just don't set the header
+            // but the rust compiler enforces array length to be known at compile time
+            // so we just set a dummy header
+            (http::header::ACCEPT_LANGUAGE, "*".to_string())
+        },
+    ];
+    let stream = ReaderStream::new(file);
+    let body = StreamBody::new(stream);
+    Ok((headers, body))
+}
+
 async fn download(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(key): Path<String>,
@@ -567,37 +620,9 @@ async fn download(
     ),
     Error,
 > {
-    if let Some(path) = state.download_urls.lock().await.get(&key) {
-        let file = tokio::fs::File::open(&path)
-            .await
-            .context(format!("Failed to open file {}", path.display()))?;
-
-        let headers = [
-            (
-                http::header::CONTENT_DISPOSITION,
-                "application/octet-stream".to_string(),
-            ),
-            (
-                http::header::CONTENT_DISPOSITION,
-                format!(
-                    "attachment; filename=\"{}\"",
-                    path.file_name()
-                        .and_then(|s| s.to_str().map(|s| s.to_string()))
-                        .unwrap_or_else(|| "unknown".to_string())
-                ),
-            ),
-            if let Ok(metadata) = file.metadata().await {
-                (http::header::CONTENT_LENGTH, metadata.len().to_string())
-            } else {
-                // if we can't get the file size, we just don't set the header
-                // but the rust compiler enforces array length to be known at compile time
-                // so we just set a dummy header
-                (http::header::ACCEPT_LANGUAGE, "*".to_string())
-            },
-        ];
-        let stream = ReaderStream::new(file);
-        let body = StreamBody::new(stream);
-        Ok((headers, body))
+    let path = state.download_urls.lock().await.get(&key).cloned();
+    if let Some(path) = path {
+        file_attachment(&path).await
     } else {
         Err(Error {
             kind: ErrorKind::NotFound,
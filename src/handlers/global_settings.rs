@@ -4,14 +4,38 @@ use axum::{
 };
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
 
-use crate::{error::ErrorKind, AppState, Error, GlobalSettingsData};
+use crate::{
+    auth::hashed_password::PasswordHashing,
+    disk_space::DiskSpaceConfig,
+    error::ErrorKind,
+    isolation,
+    log_shipping::LogShippingSettings,
+    mail::SmtpConfig,
+    maintenance::MaintenanceWindow,
+    rate_limiter::RateLimitConfig,
+    remote_backup::RemoteBackupSettings,
+    telemetry::{self, TelemetryReport, TelemetrySettings},
+    AppState, Error, GlobalSettingsData,
+};
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewMaintenance {
+    /// Unix timestamp to enter maintenance at, right away if `None`
+    pub start: Option<i64>,
+    /// Minutes until maintenance ends by itself, `None` to stay in it until turned off
+    pub duration: Option<u32>,
+    pub message: Option<String>,
+}
 
 pub async fn get_core_settings(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<GlobalSettingsData>, Error> {
-    state
+    let requester = state
         .users_manager
         .read()
         .await
@@ -21,7 +45,12 @@ pub async fn get_core_settings(
             source: eyre!("Token error"),
         })?;
 
-    Ok(Json(state.global_settings.lock().await.as_ref().clone()))
+    let global_settings = state.global_settings.lock().await;
+    if requester.has_owner_rights() {
+        Ok(Json(global_settings.as_ref().redacted()))
+    } else {
+        Ok(Json(global_settings.as_ref().public()))
+    }
 }
 
 pub async fn change_core_name(
@@ -31,12 +60,7 @@ pub async fn change_core_name(
 ) -> Result<(), Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
 
-    if !requester.is_owner {
-        return Err(Error {
-            kind: ErrorKind::PermissionDenied,
-            source: eyre!("Not authorized to change core name"),
-        });
-    }
+    requester.try_owner_rights()?;
     if new_name.len() > 32 {
         return Err(Error {
             kind: ErrorKind::BadRequest,
@@ -65,12 +89,7 @@ pub async fn change_core_safe_mode(
 ) -> Result<(), Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
 
-    if !requester.is_owner {
-        return Err(Error {
-            kind: ErrorKind::PermissionDenied,
-            source: eyre!("Not authorized to change core safe mode"),
-        });
-    }
+    requester.try_owner_rights()?;
     state
         .global_settings
         .lock()
@@ -86,12 +105,7 @@ pub async fn change_domain(
     Json(new_domain): Json<String>,
 ) -> Result<(), Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    if !requester.is_owner {
-        return Err(Error {
-            kind: ErrorKind::PermissionDenied,
-            source: eyre!("Not authorized to change core domain"),
-        });
-    }
+    requester.try_owner_rights()?;
     if new_domain.len() > 253 {
         return Err(Error {
             kind: ErrorKind::BadRequest,
@@ -111,11 +125,224 @@ pub async fn change_domain(
     Ok(())
 }
 
+pub async fn change_rate_limit(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(rate_limit): Json<RateLimitConfig>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    if rate_limit.requests_per_minute == 0 || rate_limit.burst == 0 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Rate limit must allow at least one request"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_rate_limit(rate_limit)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_disk_space(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(disk_space): Json<DiskSpaceConfig>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_disk_space(disk_space)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_remote_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(settings): Json<Option<RemoteBackupSettings>>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    if let Some(settings) = &settings {
+        settings.validate()?;
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_remote_backup(settings)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_log_shipping(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(settings): Json<Option<LogShippingSettings>>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    if let Some(settings) = &settings {
+        settings.validate()?;
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_log_shipping(settings)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_smtp(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(smtp): Json<Option<SmtpConfig>>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    if let Some(smtp) = &smtp {
+        smtp.validate()?;
+    }
+    state.global_settings.lock().await.set_smtp(smtp).await?;
+    Ok(())
+}
+
+pub async fn change_password_hashing(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(password_hashing): Json<PasswordHashing>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    password_hashing.validate()?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_password_hashing(password_hashing)
+        .await?;
+    state
+        .users_manager
+        .write()
+        .await
+        .set_password_hashing(password_hashing);
+    Ok(())
+}
+
+/// Takes effect the next time each instance starts
+pub async fn change_instance_isolation(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(instance_isolation): Json<bool>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    if instance_isolation && !isolation::is_supported() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Instances can only be isolated on Linux, with the core running as root or with the capabilities to manage users"
+            ),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_isolation(instance_isolation)
+        .await?;
+    isolation::set_enabled(instance_isolation);
+    Ok(())
+}
+
+/// Schedules or starts maintenance, or ends it when given `null`
+pub async fn change_maintenance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(maintenance): Json<Option<NewMaintenance>>,
+) -> Result<Json<Option<MaintenanceWindow>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    let window = maintenance.map(|maintenance| {
+        let start = maintenance
+            .start
+            .unwrap_or_else(|| chrono::Utc::now().timestamp());
+        MaintenanceWindow {
+            start,
+            end: maintenance
+                .duration
+                .map(|duration| start.saturating_add(duration as i64 * 60)),
+            message: maintenance.message.filter(|message| !message.is_empty()),
+        }
+    });
+    if let Some(window) = &window {
+        window.validate()?;
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_maintenance(window.clone())
+        .await?;
+    Ok(Json(window))
+}
+
+pub async fn change_telemetry(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(telemetry): Json<TelemetrySettings>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    telemetry.validate()?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_telemetry(telemetry)
+        .await?;
+    Ok(())
+}
+
+/// Exactly what would be sent if telemetry were enabled
+pub async fn preview_telemetry(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TelemetryReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    Ok(Json(telemetry::build_report(&state).await))
+}
+
 pub fn get_global_settings_routes(state: AppState) -> Router {
     Router::new()
         .route("/global_settings", get(get_core_settings))
         .route("/global_settings/name", put(change_core_name))
         .route("/global_settings/safe_mode", put(change_core_safe_mode))
         .route("/global_settings/domain", put(change_domain))
+        .route("/global_settings/rate_limit", put(change_rate_limit))
+        .route("/global_settings/remote_backup", put(change_remote_backup))
+        .route("/global_settings/disk_space", put(change_disk_space))
+        .route("/global_settings/smtp", put(change_smtp))
+        .route("/global_settings/log_shipping", put(change_log_shipping))
+        .route(
+            "/global_settings/password_hashing",
+            put(change_password_hashing),
+        )
+        .route(
+            "/global_settings/instance_isolation",
+            put(change_instance_isolation),
+        )
+        .route("/global_settings/maintenance", put(change_maintenance))
+        .route("/global_settings/telemetry", put(change_telemetry))
+        .route("/global_settings/telemetry/preview", get(preview_telemetry))
         .with_state(state)
 }
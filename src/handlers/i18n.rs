@@ -0,0 +1,24 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::Path, routing::get, Json, Router};
+
+use crate::{
+    i18n::{Locale, MessageId},
+    AppState,
+};
+
+/// Every message template in `locale`, for clients that render message ids themselves
+pub async fn get_catalog(Path(locale): Path<Locale>) -> Json<BTreeMap<MessageId, &'static str>> {
+    Json(
+        MessageId::ALL
+            .iter()
+            .map(|id| (*id, id.template(locale)))
+            .collect(),
+    )
+}
+
+pub fn get_i18n_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/i18n/:locale", get(get_catalog))
+        .with_state(state)
+}
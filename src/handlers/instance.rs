@@ -1,29 +1,39 @@
 use axum::routing::{delete, get, post};
 use axum::Router;
-use axum::{extract::Path, Json};
+use axum::{
+    extract::{Path, Query},
+    Json,
+};
 use axum_auth::AuthBearer;
 
-use color_eyre::eyre::{eyre, Context};
+use color_eyre::eyre::Context;
 use serde::Deserialize;
-use tracing::error;
+use tracing::{error, warn};
+use ts_rs::TS;
 
+use crate::auth::instance_access::InstanceAccess;
 use crate::auth::user::UserAction;
+use crate::disk_space;
 use crate::error::{Error, ErrorKind};
-use crate::events::{CausedBy, Event, ProgressionEndValue, ProgressionStartValue};
+use crate::events::{CausedBy, Event, ProgressionEndValue};
+use crate::i18n::{LocalizedMessage, MessageId};
+use crate::instance_creation;
+use crate::quota;
+use crate::world_map;
 
 use crate::implementations::generic;
 use crate::traits::t_configurable::GameType;
 
-
 use crate::implementations::minecraft::MinecraftInstance;
-use crate::prelude::{path_to_instances, GameInstance};
+use crate::prelude::GameInstance;
 use crate::traits::t_configurable::manifest::SetupValue;
 use crate::traits::{t_configurable::TConfigurable, t_server::TServer, InstanceInfo, TInstance};
 
 use crate::types::{DotLodestoneConfig, InstanceUuid};
-use crate::{implementations::minecraft, traits::t_server::State, AppState};
+use crate::{traits::t_server::State, AppState};
 
 use super::instance_setup_configs::HandlerGameType;
+use super::storage::StorageQuery;
 
 pub async fn get_instance_list(
     axum::extract::State(state): axum::extract::State<AppState>,
@@ -33,9 +43,14 @@ pub async fn get_instance_list(
     let mut list_of_configs: Vec<InstanceInfo> = Vec::new();
 
     let instances = state.instances.lock().await;
+    let access = state.all_instance_access().await;
     for instance in instances.values() {
-        if requester.can_perform_action(&UserAction::ViewInstance(instance.uuid().await)) {
-            list_of_configs.push(instance.get_instance_info().await);
+        let uuid = instance.uuid().await;
+        if requester.can_perform_action_with(&UserAction::ViewInstance(uuid.clone()), &access) {
+            let mut info = instance.get_instance_info().await;
+            info.owner = access.get(&uuid).and_then(|access| access.owner.clone());
+            info.health = state.health.lock().await.status(&uuid);
+            list_of_configs.push(info);
         }
     }
 
@@ -53,150 +68,78 @@ pub async fn get_instance_info(
 
     let instances = state.instances.lock().await;
 
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
 
-    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
-    Ok(Json(instance.get_instance_info().await))
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    let mut info = instance.get_instance_info().await;
+    info.owner = state
+        .instance_access(&uuid)
+        .await
+        .and_then(|access| access.owner);
+    info.health = state.health.lock().await.status(&uuid);
+    Ok(Json(info))
 }
 
 pub async fn create_minecraft_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
     Path(game_type): Path<HandlerGameType>,
+    Query(StorageQuery { storage }): Query<StorageQuery>,
     Json(manifest_value): Json<SetupValue>,
 ) -> Result<Json<InstanceUuid>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::CreateInstance)?;
-    let mut perm = requester.permissions;
-
-    let mut instance_uuid = InstanceUuid::default();
-
-    for uuid in state.instances.lock().await.keys() {
-        if let Some(uuid) = uuid.as_ref().get(0..8) {
-            if uuid == &instance_uuid.no_prefix()[0..8] {
-                instance_uuid = InstanceUuid::default();
-            }
-        }
-    }
-
-    let instance_uuid = instance_uuid;
+    let (disk_space, location) = {
+        let global_settings = state.global_settings.lock().await;
+        (
+            global_settings.disk_space(),
+            global_settings.storage_location(storage.as_deref())?,
+        )
+    };
+    disk_space::ensure_space(&disk_space, &location.path, 0, None, None).await?;
+    let max_ram = manifest_value
+        .get_unique_setting("max_ram")
+        .and_then(|setting| setting.get_value())
+        .and_then(|value| value.try_as_unsigned_integer().ok())
+        .unwrap_or(0);
+    quota::check_new_instance(&state, &requester, max_ram).await?;
 
     let flavour = game_type.try_into()?;
 
     let setup_config = MinecraftInstance::construct_setup_config(manifest_value, flavour).await?;
 
-    let setup_path = path_to_instances().join(format!(
-        "{}-{}",
-        setup_config.name,
-        &instance_uuid.no_prefix()[0..8]
-    ));
-
-    tokio::fs::create_dir_all(&setup_path)
-        .await
-        .context("Failed to create instance directory")?;
-
-    let dot_lodestone_config = DotLodestoneConfig::new(instance_uuid.clone(), game_type.into());
-
-    // write dot lodestone config
-
-    tokio::fs::write(
-        setup_path.join(".lodestone_config"),
-        serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+    let creation = instance_creation::prepare_creation(
+        &state,
+        setup_config,
+        game_type.into(),
+        &location,
+        requester.uid.clone(),
     )
-    .await
-    .context("Failed to write .lodestone_config file")?;
+    .await?;
+    let instance_uuid = creation.instance_uuid.clone();
 
-    tokio::task::spawn({
-        let uuid = instance_uuid.clone();
-        let instance_name = setup_config.name.clone();
-        let event_broadcaster = state.event_broadcaster.clone();
-        let port = setup_config.port;
-        let flavour = setup_config.flavour.clone();
-        let caused_by = CausedBy::User {
-            user_id: requester.uid.clone(),
-            user_name: requester.username.clone(),
-        };
-        async move {
-            let (progression_start_event, event_id) = Event::new_progression_event_start(
-                format!("Setting up Minecraft server {instance_name}"),
-                Some(10.0),
-                Some(ProgressionStartValue::InstanceCreation {
-                    instance_uuid: uuid.clone(),
-                    instance_name: instance_name.clone(),
-                    port,
-                    flavour: flavour.to_string(),
-                    game_type: "minecraft".to_string(),
-                }),
-                caused_by,
-            );
-            event_broadcaster.send(progression_start_event);
-            let minecraft_instance = match minecraft::MinecraftInstance::new(
-                setup_config.clone(),
-                dot_lodestone_config,
-                setup_path.clone(),
-                &event_id,
-                state.event_broadcaster.clone(),
-                state.macro_executor.clone(),
-            )
-            .await
-            {
-                Ok(v) => {
-                    event_broadcaster.send(Event::new_progression_event_end(
-                        event_id,
-                        true,
-                        Some("Instance created successfully"),
-                        Some(ProgressionEndValue::InstanceCreation(
-                            v.get_instance_info().await,
-                        )),
-                    ));
-                    v
-                }
-                Err(e) => {
-                    event_broadcaster.send(Event::new_progression_event_end(
-                        event_id,
-                        false,
-                        Some(&format!("Instance creation failed: {e}")),
-                        None,
-                    ));
-                    crate::util::fs::remove_dir_all(setup_path)
-                        .await
-                        .context("Failed to remove directory after instance creation failed")
-                        .unwrap();
-                    return;
-                }
-            };
-            let mut port_manager = state.port_manager.lock().await;
-            port_manager.add_port(setup_config.port);
-            perm.can_start_instance.insert(uuid.clone());
-            perm.can_stop_instance.insert(uuid.clone());
-            perm.can_view_instance.insert(uuid.clone());
-            perm.can_read_instance_file.insert(uuid.clone());
-            perm.can_write_instance_file.insert(uuid.clone());
-            // ignore errors since we don't care if the permissions update fails
-            let _ = state
-                .users_manager
-                .write()
-                .await
-                .update_permissions(&requester.uid, perm, CausedBy::System)
-                .await
-                .map_err(|e| {
-                    error!("Failed to update permissions: {:?}", e);
-                    e
-                });
-            state
-                .instances
-                .lock()
-                .await
-                .insert(uuid.clone(), minecraft_instance.into());
-        }
-    });
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    tokio::task::spawn(instance_creation::create_minecraft_instance(
+        state.clone(),
+        creation,
+        caused_by,
+    ));
     Ok(Json(instance_uuid))
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
 pub struct GenericSetupConfig {
     url: String,
     setup_value: SetupValue,
@@ -205,10 +148,20 @@ pub struct GenericSetupConfig {
 pub async fn create_generic_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
+    Query(StorageQuery { storage }): Query<StorageQuery>,
     Json(setup_config): Json<GenericSetupConfig>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::CreateInstance)?;
+    let (disk_space, location) = {
+        let global_settings = state.global_settings.lock().await;
+        (
+            global_settings.disk_space(),
+            global_settings.storage_location(storage.as_deref())?,
+        )
+    };
+    disk_space::ensure_space(&disk_space, &location.path, 0, None, None).await?;
+    quota::check_new_instance(&state, &requester, 0).await?;
     let mut instance_uuid = InstanceUuid::default();
     for uuid in state.instances.lock().await.keys() {
         if let Some(uuid) = uuid.as_ref().get(0..8) {
@@ -220,7 +173,7 @@ pub async fn create_generic_instance(
 
     let instance_uuid = instance_uuid;
 
-    let setup_path = path_to_instances().join(format!(
+    let setup_path = location.path.join(format!(
         "{}-{}",
         setup_config.setup_value.name,
         &instance_uuid.no_prefix()[0..8]
@@ -230,7 +183,8 @@ pub async fn create_generic_instance(
         .await
         .context("Failed to create instance directory")?;
 
-    let dot_lodestone_config = DotLodestoneConfig::new(instance_uuid.clone(), GameType::Generic);
+    let dot_lodestone_config = DotLodestoneConfig::new(instance_uuid.clone(), GameType::Generic)
+        .with_storage_location(&location.id);
 
     // write dot lodestone config
 
@@ -251,33 +205,82 @@ pub async fn create_generic_instance(
     )
     .await?;
 
-    state
-        .instances
+    let mut instances = state.instances.lock().await;
+    if let Err(e) = state
+        .global_settings
         .lock()
         .await
-        .insert(instance_uuid.clone(), instance.into());
+        .set_instance_access(
+            instance_uuid.clone(),
+            Some(InstanceAccess::new(requester.uid.clone())),
+        )
+        .await
+    {
+        error!("Failed to record the owner of instance {instance_uuid}: {e}");
+    }
+    instances.insert(instance_uuid.clone(), instance.into());
     Ok(Json(()))
 }
 
+/// Clears everything kept about an instance outside of its directory, for when it goes away.
+///
+/// Failures are only logged, the instance is gone either way
+pub async fn forget_instance(state: &AppState, uuid: &InstanceUuid) {
+    // remote backups are left in place, they are meant to outlive the instance
+    let forgotten = state.global_settings.lock().await.forget(uuid).await;
+    match forgotten {
+        Ok(settings) => {
+            if let Some(pack) = settings.resource_pack {
+                if let Err(e) = crate::resource_pack::remove_pack(&pack.id).await {
+                    warn!("Failed to remove resource pack of deleted instance: {e}");
+                }
+            }
+        }
+        Err(e) => warn!("Failed to clear settings of deleted instance: {e}"),
+    }
+    if let Err(e) = state.share_links.lock().await.forget_instance(uuid).await {
+        warn!("Failed to clear share links of deleted instance: {e}");
+    }
+    let _ = tokio::fs::remove_file(world_map::map_path(uuid)).await;
+    state.network_filters.lock().await.forget(state, uuid).await;
+    state.alerts.lock().await.retain_rules(uuid, &[]);
+    state.health.lock().await.forget(uuid);
+    state.metrics_history.lock().await.forget(uuid);
+    state.summary_cache.lock().await.forget(uuid);
+    state.startup_watchdog.lock().await.forget(uuid);
+    if let Err(e) = crate::snapshot::delete_all_snapshots(uuid).await {
+        warn!("Failed to delete snapshots of deleted instance: {e}");
+    }
+    crate::isolation::remove_user(uuid).await;
+}
+
 pub async fn delete_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::DeleteInstance)?;
+    // owners can delete their own instances without the global permission
+    if !state
+        .instance_access(&uuid)
+        .await
+        .map_or(false, |access| access.is_owned_by(&requester.uid))
+    {
+        requester.try_action(&UserAction::DeleteInstance)?;
+    }
     let mut instances = state.instances.lock().await;
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     if let Some(instance) = instances.remove(&uuid) {
         if !(instance.state().await == State::Stopped) {
             instances.insert(uuid.clone(), instance);
-            Err(Error {
-                kind: ErrorKind::BadRequest,
-                source: eyre!("Instance must be stopped before deletion"),
-            })
+            Err(Error::localized(
+                ErrorKind::BadRequest,
+                LocalizedMessage::new(MessageId::InstanceMustBeStopped),
+            ))
         } else {
             let (progression_event_start, event_id) = Event::new_progression_event_start(
                 format!("Deleting instance {}", instance.name().await),
@@ -286,7 +289,12 @@ pub async fn delete_instance(
                 caused_by,
             );
             let event_broadcaster = state.event_broadcaster.clone();
-            event_broadcaster.send(progression_event_start);
+            event_broadcaster.send(
+                progression_event_start.with_localized_message(
+                    LocalizedMessage::new(MessageId::InstanceDeletionStarted)
+                        .with_param("instance_name", instance.name().await),
+                ),
+            );
             if let Err(e) =
                 tokio::fs::remove_file(instance.path().await.join(".lodestone_config")).await
             {
@@ -302,11 +310,13 @@ pub async fn delete_instance(
                     .map_err(Into::into);
             }
 
-            state
-                .port_manager
-                .lock()
-                .await
-                .deallocate(instance.port().await);
+            let mut port_manager = state.port_manager.lock().await;
+            port_manager.deallocate(instance.port().await);
+            if let Some(bedrock_port) = instance.bedrock_port().await {
+                port_manager.deallocate(bedrock_port);
+            }
+            drop(port_manager);
+            forget_instance(&state, &uuid).await;
             let instance_path = instance.path().await;
             // if instance is generic
             if let GameInstance::GenericInstance(i) = instance {
@@ -315,32 +325,43 @@ pub async fn delete_instance(
             drop(instances);
             let res = crate::util::fs::remove_dir_all(instance_path).await;
             match &res {
-                Ok(_) => event_broadcaster.send(Event::new_progression_event_end(
-                    event_id,
-                    true,
-                    Some("Instance deleted successfully"),
-                    Some(ProgressionEndValue::InstanceDelete {
-                        instance_uuid: uuid.clone(),
-                    }),
-                )),
-                Err(e) => {
-                    event_broadcaster.send(Event::new_progression_event_end(
+                Ok(_) => event_broadcaster.send(
+                    Event::new_progression_event_end(
                         event_id,
-                        false,
-                        Some(&format!(
-                            "Failed to delete some of all of instance's files : {e}"
-                        )),
-                        None,
-                    ));
+                        true,
+                        Some("Instance deleted successfully"),
+                        Some(ProgressionEndValue::InstanceDelete {
+                            instance_uuid: uuid.clone(),
+                        }),
+                    )
+                    .with_localized_message(LocalizedMessage::new(
+                        MessageId::InstanceDeletionSucceeded,
+                    )),
+                ),
+                Err(e) => {
+                    event_broadcaster.send(
+                        Event::new_progression_event_end(
+                            event_id,
+                            false,
+                            Some(&format!(
+                                "Failed to delete some of all of instance's files : {e}"
+                            )),
+                            None,
+                        )
+                        .with_localized_message(
+                            LocalizedMessage::new(MessageId::InstanceDeletionFailed)
+                                .with_param("error", e),
+                        ),
+                    );
                 }
             }
             res.map(|_| Json(()))
         }
     } else {
-        Err(Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
-        })
+        Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ))
     }
 }
 
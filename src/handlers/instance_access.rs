@@ -0,0 +1,129 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::{instance_access::InstanceAccess, user::UserAction, user_id::UserId},
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    types::InstanceUuid,
+    AppState,
+};
+
+async fn ensure_instance_exists(state: &AppState, uuid: &InstanceUuid) -> Result<(), Error> {
+    if state.instances.lock().await.contains_key(uuid) {
+        Ok(())
+    } else {
+        Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ))
+    }
+}
+
+pub async fn get_instance_access(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceAccess>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    ensure_instance_exists(&state, &uuid).await?;
+    Ok(Json(state.instance_access(&uuid).await.unwrap_or_default()))
+}
+
+pub async fn set_instance_owner(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(owner): Json<Option<UserId>>,
+) -> Result<Json<()>, Error> {
+    {
+        let users_manager = state.users_manager.read().await;
+        let requester = users_manager.try_auth_or_err(&token)?;
+        requester.try_owner_rights()?;
+        if let Some(owner) = &owner {
+            if users_manager.get_user(owner).is_none() {
+                return Err(Error::localized(
+                    ErrorKind::NotFound,
+                    LocalizedMessage::new(MessageId::UserNotFound),
+                ));
+            }
+        }
+    }
+    ensure_instance_exists(&state, &uuid).await?;
+    let mut access = state.instance_access(&uuid).await.unwrap_or_default();
+    if let Some(owner) = &owner {
+        access.access_list.remove(owner);
+    }
+    access.owner = owner;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_access(uuid, Some(access))
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_access_list(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(access_list): Json<HashSet<UserId>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !state.can_manage_access(&requester, &uuid).await {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!(
+                "Only the owner of this instance or an admin can change who it is shared with"
+            ),
+        });
+    }
+    ensure_instance_exists(&state, &uuid).await?;
+    let unknown_user = {
+        let users_manager = state.users_manager.read().await;
+        access_list
+            .iter()
+            .find(|uid| users_manager.get_user(*uid).is_none())
+            .cloned()
+    };
+    if let Some(unknown) = unknown_user {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User {unknown} does not exist"),
+        });
+    }
+    let mut access = state.instance_access(&uuid).await.unwrap_or_default();
+    access.access_list = access_list;
+    if let Some(owner) = &access.owner {
+        access.access_list.remove(owner);
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_access(uuid, Some(access))
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_access_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/access", get(get_instance_access))
+        .route("/instance/:uuid/access/owner", put(set_instance_owner))
+        .route(
+            "/instance/:uuid/access/access_list",
+            put(set_instance_access_list),
+        )
+        .with_state(state)
+}
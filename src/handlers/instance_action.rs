@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde_json::Value;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    i18n::{LocalizedMessage, MessageId},
+    traits::t_action::{parse_action_args, InstanceAction, TAction},
+    types::InstanceUuid,
+    AppState,
+};
+
+pub async fn get_instance_actions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<InstanceAction>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    Ok(Json(instance.list_actions().await))
+}
+
+/// Arguments are given by parameter id, in the string form of their values
+pub async fn run_instance_action(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(args): Json<HashMap<String, String>>,
+) -> Result<Json<Value>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    // actions can take a while, don't hold up every other instance meanwhile
+    let mut instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .cloned()
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })?;
+    let action = instance
+        .list_actions()
+        .await
+        .into_iter()
+        .find(|action| action.name == name)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("This instance has no action {}", name),
+        })?;
+    state
+        .try_action(&requester, &action.permission.user_action(uuid.clone()))
+        .await?;
+    let args = parse_action_args(&action.parameters, args)?;
+    if let Some(command) = instance.action_command(&name, &args).await? {
+        state.check_command(&requester, &uuid, &command).await?;
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    Ok(Json(instance.run_action(&name, args, caused_by).await?))
+}
+
+pub fn get_instance_action_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/action", get(get_instance_actions))
+        .route("/instance/:uuid/action/:name", post(run_instance_action))
+        .with_state(state)
+}
@@ -0,0 +1,180 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    alerts::{AlertAction, AlertRule, AlertStatus, NewAlertRule, MAX_RULES_PER_INSTANCE},
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+async fn check_instance_exists(state: &AppState, uuid: &InstanceUuid) -> Result<(), Error> {
+    if state.instances.lock().await.contains_key(uuid) {
+        Ok(())
+    } else {
+        Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ))
+    }
+}
+
+/// A rule that restarts the instance can only be set up by someone allowed to restart it
+async fn check_actions(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+    rule: &NewAlertRule,
+) -> Result<(), Error> {
+    rule.validate()?;
+    if rule.actions.contains(&AlertAction::RestartInstance) {
+        state
+            .try_action(requester, &UserAction::StopInstance(uuid.clone()))
+            .await?;
+        state
+            .try_action(requester, &UserAction::StartInstance(uuid.clone()))
+            .await?;
+    }
+    Ok(())
+}
+
+async fn save_rules(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    rules: Vec<AlertRule>,
+) -> Result<(), Error> {
+    let ids: Vec<Snowflake> = rules.iter().map(|rule| rule.id).collect();
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_alert_rules(uuid.clone(), rules)
+        .await?;
+    state.alerts.lock().await.retain_rules(uuid, &ids);
+    Ok(())
+}
+
+fn rule_not_found() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Alert rule not found"),
+    }
+}
+
+pub async fn list_alerts(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<AlertStatus>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    check_instance_exists(&state, &uuid).await?;
+    let rules = state
+        .global_settings
+        .lock()
+        .await
+        .instance_alert_rules(&uuid);
+    Ok(Json(state.alerts.lock().await.status(&uuid, rules)))
+}
+
+pub async fn create_alert(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(new_rule): Json<NewAlertRule>,
+) -> Result<Json<AlertRule>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    check_instance_exists(&state, &uuid).await?;
+    check_actions(&state, &requester, &uuid, &new_rule).await?;
+    let mut rules = state
+        .global_settings
+        .lock()
+        .await
+        .instance_alert_rules(&uuid);
+    if rules.len() >= MAX_RULES_PER_INSTANCE {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("An instance can have at most {MAX_RULES_PER_INSTANCE} alert rules"),
+        });
+    }
+    let rule = new_rule.into_rule(Snowflake::new());
+    rules.push(rule.clone());
+    save_rules(&state, &uuid, rules).await?;
+    Ok(Json(rule))
+}
+
+/// Replaces the rule, it stays firing if it was
+pub async fn update_alert(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, rule_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+    Json(new_rule): Json<NewAlertRule>,
+) -> Result<Json<AlertRule>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    check_instance_exists(&state, &uuid).await?;
+    check_actions(&state, &requester, &uuid, &new_rule).await?;
+    let mut rules = state
+        .global_settings
+        .lock()
+        .await
+        .instance_alert_rules(&uuid);
+    let rule = rules
+        .iter_mut()
+        .find(|rule| rule.id == rule_id)
+        .ok_or_else(rule_not_found)?;
+    *rule = new_rule.into_rule(rule_id);
+    let rule = rule.clone();
+    save_rules(&state, &uuid, rules).await?;
+    Ok(Json(rule))
+}
+
+pub async fn delete_alert(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, rule_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let mut rules = state
+        .global_settings
+        .lock()
+        .await
+        .instance_alert_rules(&uuid);
+    let count = rules.len();
+    rules.retain(|rule| rule.id != rule_id);
+    if rules.len() == count {
+        return Err(rule_not_found());
+    }
+    save_rules(&state, &uuid, rules).await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_alerts_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/alerts",
+            get(list_alerts).post(create_alert),
+        )
+        .route(
+            "/instance/:uuid/alerts/:rule_id",
+            put(update_alert).delete(delete_alert),
+        )
+        .with_state(state)
+}
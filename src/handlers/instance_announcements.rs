@@ -0,0 +1,154 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    announcement::{Announcement, NewAnnouncement, MAX_ANNOUNCEMENTS_PER_INSTANCE},
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    traits::t_configurable::TConfigurable,
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+/// Announcements are sent through the console, so setting one up takes being allowed to write
+/// to it
+async fn check_announcement(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+    announcement: &NewAnnouncement,
+) -> Result<(), Error> {
+    state
+        .try_action(requester, &UserAction::WriteConsole(uuid.clone()))
+        .await?;
+    let game = match state.instances.lock().await.get(uuid) {
+        Some(instance) => instance.game_type().await,
+        None => {
+            return Err(Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            ))
+        }
+    };
+    announcement.validate(&game)
+}
+
+fn announcement_not_found() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Announcement not found"),
+    }
+}
+
+pub async fn list_announcements(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<Announcement>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    Ok(Json(
+        state
+            .global_settings
+            .lock()
+            .await
+            .instance_announcements(&uuid),
+    ))
+}
+
+pub async fn create_announcement(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(new_announcement): Json<NewAnnouncement>,
+) -> Result<Json<Announcement>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    check_announcement(&state, &requester, &uuid, &new_announcement).await?;
+    let mut global_settings = state.global_settings.lock().await;
+    let mut announcements = global_settings.instance_announcements(&uuid);
+    if announcements.len() >= MAX_ANNOUNCEMENTS_PER_INSTANCE {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "An instance can have at most {MAX_ANNOUNCEMENTS_PER_INSTANCE} announcements"
+            ),
+        });
+    }
+    let announcement = new_announcement.into_announcement(Snowflake::new());
+    announcements.push(announcement.clone());
+    global_settings
+        .set_instance_announcements(uuid, announcements)
+        .await?;
+    Ok(Json(announcement))
+}
+
+pub async fn update_announcement(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, announcement_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+    Json(new_announcement): Json<NewAnnouncement>,
+) -> Result<Json<Announcement>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    check_announcement(&state, &requester, &uuid, &new_announcement).await?;
+    let mut global_settings = state.global_settings.lock().await;
+    let mut announcements = global_settings.instance_announcements(&uuid);
+    let announcement = announcements
+        .iter_mut()
+        .find(|announcement| announcement.id == announcement_id)
+        .ok_or_else(announcement_not_found)?;
+    *announcement = new_announcement.into_announcement(announcement_id);
+    let announcement = announcement.clone();
+    global_settings
+        .set_instance_announcements(uuid, announcements)
+        .await?;
+    Ok(Json(announcement))
+}
+
+pub async fn delete_announcement(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, announcement_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let mut global_settings = state.global_settings.lock().await;
+    let mut announcements = global_settings.instance_announcements(&uuid);
+    let count = announcements.len();
+    announcements.retain(|announcement| announcement.id != announcement_id);
+    if announcements.len() == count {
+        return Err(announcement_not_found());
+    }
+    global_settings
+        .set_instance_announcements(uuid, announcements)
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_announcements_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/announcements",
+            get(list_announcements).post(create_announcement),
+        )
+        .route(
+            "/instance/:uuid/announcements/:announcement_id",
+            put(update_announcement).delete(delete_announcement),
+        )
+        .with_state(state)
+}
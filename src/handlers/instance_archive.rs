@@ -0,0 +1,110 @@
+use axum::{
+    extract::{Path, Query},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+
+use crate::{
+    archive::{self, ArchivedInstance},
+    auth::user::{User, UserAction},
+    error::Error,
+    events::CausedBy,
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::storage::StorageQuery;
+
+/// Archiving takes the instance away like deleting it does, so the same users may do it
+async fn ensure_can_archive(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+) -> Result<(), Error> {
+    if !state
+        .instance_access(uuid)
+        .await
+        .map_or(false, |access| access.is_owned_by(&requester.uid))
+    {
+        requester.try_action(&UserAction::DeleteInstance)?;
+    }
+    Ok(())
+}
+
+pub async fn get_archived_instances(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ArchivedInstance>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let access = state.all_instance_access().await;
+    Ok(Json(
+        state
+            .archives
+            .lock()
+            .await
+            .list()
+            .into_iter()
+            .filter(|archived| {
+                requester.can_perform_action_with(
+                    &UserAction::ViewInstance(archived.uuid.clone()),
+                    &access,
+                )
+            })
+            .collect(),
+    ))
+}
+
+pub async fn archive_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    // the storage location to keep the archive in, e.g. one on slower disks
+    Query(StorageQuery { storage }): Query<StorageQuery>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    ensure_can_archive(&state, &requester, &uuid).await?;
+    archive::archive_instance(
+        &state,
+        &uuid,
+        storage,
+        CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+            impersonated_by: requester.impersonated_by,
+        },
+    )
+    .await?;
+    Ok(Json(()))
+}
+
+pub async fn restore_archived_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    ensure_can_archive(&state, &requester, &uuid).await?;
+    archive::restore_archived_instance(
+        &state,
+        &uuid,
+        CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+            impersonated_by: requester.impersonated_by,
+        },
+    )
+    .await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_archive_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/archived", get(get_archived_instances))
+        .route("/instance/:uuid/archive", post(archive_instance))
+        .route(
+            "/instance/archived/:uuid/restore",
+            post(restore_archived_instance),
+        )
+        .with_state(state)
+}
@@ -1,19 +1,33 @@
+use std::collections::HashMap;
+
 use axum::{
-    extract::Path,
-    routing::{get, put},
+    extract::{Path, Query},
+    routing::{get, post, put},
     Json, Router,
 };
 use axum_auth::AuthBearer;
-use color_eyre::eyre::eyre;
+use tracing::warn;
 
 use crate::{
     auth::user::UserAction,
     error::{Error, ErrorKind},
+    events::CausedBy,
+    i18n::{LocalizedMessage, MessageId},
+    log_shipping::LogShippingSettings,
+    power_schedule::PowerSchedule,
+    prelude::GameInstance,
+    quota,
+    remote_backup::RemoteBackupSettings,
+    restart_schedule::RestartSchedule,
+    service::ShutdownPolicy,
+    settings_history::{SettingChangeRecord, SettingHistoryDiff, SettingsHistory},
     traits::t_configurable::{
         manifest::{ConfigurableManifest, ConfigurableValue},
+        preview::{SettingChange, SettingsPreview},
+        propagation::{changes_from, PropagationOutcome, SettingsPropagation},
         TConfigurable,
     },
-    types::InstanceUuid,
+    types::{InstanceUuid, TimeRange},
     AppState,
 };
 
@@ -23,11 +37,15 @@ pub async fn get_instance_configurable_manifest(
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<ConfigurableManifest>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
     let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get_mut(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     Ok(Json(instance.configurable_manifest().await))
 }
@@ -38,15 +56,95 @@ pub async fn get_instance_settings(
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<ConfigurableManifest>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
     let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get_mut(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     Ok(Json(instance.configurable_manifest().await))
 }
 
+/// Applies setting changes in order and records them in the instance's settings history
+///
+/// Stops at the first change that fails, the ones before it stay applied
+async fn apply_settings(
+    state: &AppState,
+    instances: &mut HashMap<InstanceUuid, GameInstance>,
+    uuid: &InstanceUuid,
+    changes: Vec<SettingChange>,
+    caused_by: CausedBy,
+) -> Result<(), Error> {
+    let path = instance_path(instances, uuid).await?;
+    let mut history = SettingsHistory::load(&path).await;
+    let mut result = Ok(());
+    for change in changes {
+        match apply_setting(state, instances, uuid, change, caused_by.clone()).await {
+            Ok(Some(record)) => history.push(record),
+            Ok(None) => {}
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    // what went through stays applied, a history that failed to save shouldn't hide that
+    if let Err(e) = history.save(&path).await {
+        warn!("Failed to save the settings history of {uuid}: {e}");
+    }
+    result
+}
+
+async fn apply_setting(
+    state: &AppState,
+    instances: &mut HashMap<InstanceUuid, GameInstance>,
+    uuid: &InstanceUuid,
+    change: SettingChange,
+    caused_by: CausedBy,
+) -> Result<Option<SettingChangeRecord>, Error> {
+    if change.setting_id == "max_ram" {
+        let max_ram = change.value.try_as_unsigned_integer()?;
+        quota::check_ram_change(state, instances, uuid, max_ram).await?;
+    }
+    let instance = instances.get_mut(uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    let record = SettingChangeRecord::new(
+        &instance.configurable_manifest().await,
+        &change.section_id,
+        &change.setting_id,
+        &change.value,
+        caused_by,
+    );
+    instance
+        .update_configurable(&change.section_id, &change.setting_id, change.value)
+        .await?;
+    Ok(record)
+}
+
+async fn instance_path(
+    instances: &HashMap<InstanceUuid, GameInstance>,
+    uuid: &InstanceUuid,
+) -> Result<std::path::PathBuf, Error> {
+    Ok(instances
+        .get(uuid)
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })?
+        .path()
+        .await)
+}
+
 pub async fn set_instance_setting(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, section_id, setting_id)): Path<(InstanceUuid, String, String)>,
@@ -54,18 +152,231 @@ pub async fn set_instance_setting(
     Json(value): Json<ConfigurableValue>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    apply_settings(
+        &state,
+        &mut *state.instances.lock().await,
+        &uuid,
+        vec![SettingChange {
+            section_id,
+            setting_id,
+            value,
+        }],
+        caused_by,
+    )
+    .await?;
+
+    Ok(Json(()))
+}
+
+/// Every recorded setting change, oldest first
+pub async fn get_instance_settings_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<SettingChangeRecord>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let path = instance_path(&*state.instances.lock().await, &uuid).await?;
+    Ok(Json(SettingsHistory::load(&path).await.changes().to_vec()))
+}
+
+/// The settings that differ between two points in time
+pub async fn diff_instance_settings_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(range): Query<TimeRange>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<SettingHistoryDiff>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let path = instance_path(&*state.instances.lock().await, &uuid).await?;
+    Ok(Json(
+        SettingsHistory::load(&path)
+            .await
+            .diff(range.start, range.end),
+    ))
+}
+
+/// Puts the settings back to what they were at the given time, returns what was changed
+///
+/// Secret settings are left as they are, their values aren't kept in the history
+pub async fn rollback_instance_settings(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(at): Json<i64>,
+) -> Result<Json<Vec<SettingHistoryDiff>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
     let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or(Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get_mut(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
+    let manifest = instance.configurable_manifest().await;
+    let reverts: Vec<_> = SettingsHistory::load(&instance.path().await)
+        .await
+        .snapshot_at(at)
+        .into_iter()
+        .filter(|(_, setting)| !setting.is_secret)
+        .filter_map(|((section_id, setting_id), setting)| {
+            let to = setting.value?;
+            let from = manifest
+                .get_setting(&section_id, &setting_id)?
+                .get_value()
+                .cloned();
+            (from.as_ref() != Some(&to)).then(|| SettingHistoryDiff {
+                section_id,
+                setting_id,
+                name: setting.name,
+                from,
+                to: Some(to),
+            })
+        })
+        .collect();
+    let changes = reverts
+        .iter()
+        .filter_map(|revert| {
+            Some(SettingChange {
+                section_id: revert.section_id.clone(),
+                setting_id: revert.setting_id.clone(),
+                value: revert.to.clone()?,
+            })
+        })
+        .collect();
+    apply_settings(&state, &mut instances, &uuid, changes, caused_by).await?;
+    Ok(Json(reverts))
+}
 
-    instance
-        .update_configurable(&section_id, &setting_id, value)
+/// Shows what a batch of setting changes would do, without applying any of them
+pub async fn preview_instance_settings(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(changes): Json<Vec<SettingChange>>,
+) -> Result<Json<SettingsPreview>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
         .await?;
+    let mut instances = state.instances.lock().await;
+    // fail the same way applying the changes would
+    if let Some(change) = changes
+        .iter()
+        .rev()
+        .find(|change| change.setting_id == "max_ram")
+    {
+        let max_ram = change.value.try_as_unsigned_integer()?;
+        quota::check_ram_change(&state, &mut instances, &uuid, max_ram).await?;
+    }
+    let instance = instances.get_mut(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    Ok(Json(instance.preview_configurable(changes).await?))
+}
 
-    Ok(Json(()))
+/// Gives the chosen settings of an instance the same values on other instances
+///
+/// A target that fails doesn't stop the others, its outcome carries the error instead
+pub async fn propagate_instance_settings(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(propagation): Json<SettingsPropagation>,
+) -> Result<Json<Vec<PropagationOutcome>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    for target in &propagation.targets {
+        state
+            .try_action(&requester, &UserAction::AccessSetting(target.clone()))
+            .await?;
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    let mut instances = state.instances.lock().await;
+    let source = instances
+        .get_mut(&uuid)
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })?
+        .configurable_manifest()
+        .await;
+    let changes = changes_from(&source, &propagation.settings)?;
+    let mut outcomes = Vec::new();
+    for target in propagation.targets {
+        if target == uuid {
+            continue;
+        }
+        let result: Result<SettingsPreview, Error> = async {
+            let preview = instances
+                .get_mut(&target)
+                .ok_or_else(|| {
+                    Error::localized(
+                        ErrorKind::NotFound,
+                        LocalizedMessage::new(MessageId::InstanceNotFound),
+                    )
+                })?
+                .preview_configurable(changes.clone())
+                .await?;
+            if !propagation.dry_run {
+                apply_settings(
+                    &state,
+                    &mut instances,
+                    &target,
+                    changes.clone(),
+                    caused_by.clone(),
+                )
+                .await?;
+            }
+            Ok(preview)
+        }
+        .await;
+        outcomes.push(match result {
+            Ok(preview) => PropagationOutcome {
+                instance_uuid: target,
+                preview: Some(preview),
+                error: None,
+            },
+            Err(e) => PropagationOutcome {
+                instance_uuid: target,
+                preview: None,
+                error: Some(e.to_string()),
+            },
+        });
+    }
+    Ok(Json(outcomes))
 }
 
 pub async fn set_instance_name(
@@ -75,15 +386,19 @@ pub async fn set_instance_name(
     Json(new_name): Json<String>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
     state
         .instances
         .lock()
         .await
         .get_mut(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .set_name(new_name)
         .await?;
@@ -97,42 +412,281 @@ pub async fn set_instance_description(
     Json(new_description): Json<String>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
     state
         .instances
         .lock()
         .await
         .get_mut(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .set_description(new_description)
         .await?;
     Ok(Json(()))
 }
 
+pub async fn set_instance_console_buffer_lines(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(lines): Json<u32>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })?
+        .set_console_buffer_lines(lines)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_config_files(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config_files): Json<Vec<String>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    // the files become editable by anyone with access to settings
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })?
+        .set_config_files(config_files)
+        .await?;
+    Ok(Json(()))
+}
+
 pub async fn change_version(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, new_version)): Path<(InstanceUuid, String)>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
     state
         .instances
         .lock()
         .await
         .get_mut(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .change_version(new_version)
         .await?;
     Ok(Json(()))
 }
 
+pub async fn set_instance_shutdown_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(policy): Json<Option<ShutdownPolicy>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    if !state.instances.lock().await.contains_key(&uuid) {
+        return Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ));
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_shutdown_policy(uuid, policy)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_power_schedule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<PowerSchedule>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    Ok(Json(
+        state
+            .global_settings
+            .lock()
+            .await
+            .instance_power_schedule(&uuid),
+    ))
+}
+
+/// `null` removes the schedule
+pub async fn set_instance_power_schedule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(schedule): Json<Option<PowerSchedule>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    if !state.instances.lock().await.contains_key(&uuid) {
+        return Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ));
+    }
+    if let Some(schedule) = &schedule {
+        schedule.validate()?;
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_power_schedule(uuid, schedule)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_restart_schedule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<RestartSchedule>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    Ok(Json(
+        state
+            .global_settings
+            .lock()
+            .await
+            .instance_restart_schedule(&uuid),
+    ))
+}
+
+/// `null` removes the schedule
+pub async fn set_instance_restart_schedule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(schedule): Json<Option<RestartSchedule>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    if !state.instances.lock().await.contains_key(&uuid) {
+        return Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ));
+    }
+    if let Some(schedule) = &schedule {
+        schedule.validate()?;
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_restart_schedule(uuid, schedule)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_remote_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(settings): Json<Option<RemoteBackupSettings>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    if !state.instances.lock().await.contains_key(&uuid) {
+        return Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ));
+    }
+    if let Some(settings) = &settings {
+        settings.validate()?;
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_remote_backup(uuid, settings)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_log_shipping(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(settings): Json<Option<LogShippingSettings>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    if !state.instances.lock().await.contains_key(&uuid) {
+        return Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ));
+    }
+    if let Some(settings) = &settings {
+        settings.validate()?;
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_log_shipping(uuid, settings)
+        .await?;
+    Ok(Json(()))
+}
+
 pub fn get_instance_config_routes(state: AppState) -> Router {
     Router::new()
         .route(
@@ -145,7 +699,55 @@ pub fn get_instance_config_routes(state: AppState) -> Router {
             "/instance/:uuid/settings/:section_id/:setting_id",
             put(set_instance_setting),
         )
+        .route(
+            "/instance/:uuid/settings/history",
+            get(get_instance_settings_history),
+        )
+        .route(
+            "/instance/:uuid/settings/history/diff",
+            get(diff_instance_settings_history),
+        )
+        .route(
+            "/instance/:uuid/settings/history/rollback",
+            post(rollback_instance_settings),
+        )
+        .route(
+            "/instance/:uuid/settings/preview",
+            post(preview_instance_settings),
+        )
+        .route(
+            "/instance/:uuid/settings/propagate",
+            post(propagate_instance_settings),
+        )
         .route("/instance/:uuid/name", put(set_instance_name))
         .route("/instance/:uuid/description", put(set_instance_description))
+        .route(
+            "/instance/:uuid/config_files",
+            put(set_instance_config_files),
+        )
+        .route(
+            "/instance/:uuid/console/buffer_lines",
+            put(set_instance_console_buffer_lines),
+        )
+        .route(
+            "/instance/:uuid/shutdown_policy",
+            put(set_instance_shutdown_policy),
+        )
+        .route(
+            "/instance/:uuid/power_schedule",
+            get(get_instance_power_schedule).put(set_instance_power_schedule),
+        )
+        .route(
+            "/instance/:uuid/restart_schedule",
+            get(get_instance_restart_schedule).put(set_instance_restart_schedule),
+        )
+        .route(
+            "/instance/:uuid/remote_backup",
+            put(set_instance_remote_backup),
+        )
+        .route(
+            "/instance/:uuid/log_shipping",
+            put(set_instance_log_shipping),
+        )
         .with_state(state)
 }
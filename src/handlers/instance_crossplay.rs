@@ -0,0 +1,105 @@
+use axum::{extract::Path, routing::put, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    implementations::minecraft::crossplay::DEFAULT_BEDROCK_PORT,
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::util::get_minecraft_instance;
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct CrossplaySettings {
+    /// A free port from 19132 up is picked if left out
+    pub bedrock_port: Option<u32>,
+}
+
+/// Installs Geyser and Floodgate, returning the port Bedrock players can join on
+pub async fn enable_crossplay(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(settings): Json<CrossplaySettings>,
+) -> Result<Json<u32>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    let previous = instance.bedrock_port().await;
+    let bedrock_port = {
+        let mut port_manager = state.port_manager.lock().await;
+        match settings.bedrock_port {
+            Some(port) if Some(port) == previous => port,
+            Some(port) => {
+                if port == 0 || port > u16::MAX as u32 {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Invalid port {}", port),
+                    });
+                }
+                let status = port_manager.port_status(port);
+                if status.is_allocated || status.is_in_use {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Port {} is in use", port),
+                    });
+                }
+                port_manager.add_port(port);
+                port
+            }
+            None => previous.unwrap_or_else(|| port_manager.allocate(DEFAULT_BEDROCK_PORT)),
+        }
+    };
+    if let Err(e) = instance.enable_crossplay(bedrock_port).await {
+        if Some(bedrock_port) != previous {
+            state.port_manager.lock().await.deallocate(bedrock_port);
+        }
+        return Err(e);
+    }
+    if let Some(previous) = previous.filter(|previous| *previous != bedrock_port) {
+        state.port_manager.lock().await.deallocate(previous);
+    }
+    Ok(Json(bedrock_port))
+}
+
+pub async fn disable_crossplay(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    let previous = instance.bedrock_port().await;
+    instance.disable_crossplay().await?;
+    if let Some(previous) = previous {
+        state.port_manager.lock().await.deallocate(previous);
+    }
+    Ok(Json(()))
+}
+
+pub fn get_instance_crossplay_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/crossplay",
+            put(enable_crossplay).delete(disable_crossplay),
+        )
+        .with_state(state)
+}
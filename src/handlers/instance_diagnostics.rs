@@ -0,0 +1,133 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    implementations::minecraft::{
+        diagnostics::{DiagnosticInfo, DiagnosticKind},
+        gc_log::GcAnalysis,
+    },
+    traits::t_configurable::TConfigurable,
+    types::{InstanceUuid, Snowflake},
+    util::rand_alphanumeric,
+    AppState,
+};
+
+use super::util::get_minecraft_instance;
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewDiagnostic {
+    pub kind: DiagnosticKind,
+}
+
+pub async fn list_diagnostics(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<DiagnosticInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.list_diagnostics().await?))
+}
+
+pub async fn take_diagnostic(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(new_diagnostic): Json<NewDiagnostic>,
+) -> Result<Json<DiagnosticInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    // heap dumps go through the console on Paper
+    state
+        .try_action(&requester, &UserAction::WriteConsole(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.take_diagnostic(new_diagnostic.kind).await?))
+}
+
+/// Returns a key to download the artifact from `/file/:key`
+pub async fn download_diagnostic(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<String, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    let info = instance
+        .list_diagnostics()
+        .await?
+        .into_iter()
+        .find(|info| info.id == id)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Diagnostic not found"),
+        })?;
+    let key = rand_alphanumeric(32);
+    state
+        .download_urls
+        .lock()
+        .await
+        .insert(key.clone(), instance.path().await.join(info.path));
+    Ok(key)
+}
+
+pub async fn delete_diagnostic(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    instance.delete_diagnostic(id).await?;
+    Ok(Json(()))
+}
+
+/// Pause times and allocation rate from the instance's recent GC logs, see the `gc_logging` setting
+pub async fn analyze_gc_logs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<GcAnalysis>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.analyze_gc_logs().await?))
+}
+
+pub fn get_instance_diagnostics_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/diagnostics",
+            get(list_diagnostics).post(take_diagnostic),
+        )
+        .route("/instance/:uuid/diagnostics/:id", delete(delete_diagnostic))
+        .route(
+            "/instance/:uuid/diagnostics/:id/download",
+            get(download_diagnostic),
+        )
+        .route("/instance/:uuid/gc_analysis", get(analyze_gc_logs))
+        .with_state(state)
+}
@@ -2,7 +2,8 @@ use std::path::PathBuf;
 
 use axum::{
     body::Bytes,
-    extract::{DefaultBodyLimit, Multipart, Path},
+    extract::{DefaultBodyLimit, Multipart, Path, Query},
+    http::HeaderName,
     routing::{delete, get, put},
     Json, Router,
 };
@@ -11,7 +12,7 @@ use color_eyre::eyre::{eyre, Context};
 use fs_extra::TransitProcess;
 use headers::HeaderMap;
 use reqwest::header::CONTENT_LENGTH;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 use tracing::error;
 use ts_rs::TS;
@@ -21,7 +22,11 @@ use crate::{
     auth::user::UserAction,
     error::{Error, ErrorKind},
     events::{new_fs_event, CausedBy, Event, FSOperation, FSTarget, ProgressionEndValue},
+    hex_view::{self, HexView},
+    i18n::{LocalizedMessage, MessageId},
     prelude::path_to_tmp,
+    snapshot::{self, FileHash, Manifest},
+    text_encoding,
     traits::t_configurable::TConfigurable,
     types::InstanceUuid,
     util::{
@@ -47,7 +52,7 @@ static PROTECTED_EXTENSIONS: [&str; 10] = [
 
 static PROTECTED_DIR_NAME: [&str; 1] = ["mods"];
 
-fn is_path_protected(path: impl AsRef<std::path::Path>) -> bool {
+pub(super) fn is_path_protected(path: impl AsRef<std::path::Path>) -> bool {
     let path = path.as_ref();
     if path.is_dir() {
         path.file_name()
@@ -72,11 +77,15 @@ async fn list_instance_files(
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
 
-    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -95,6 +104,7 @@ async fn list_instance_files(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Read,
@@ -108,47 +118,195 @@ async fn read_instance_file(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
     AuthBearer(token): AuthBearer,
-) -> Result<String, Error> {
+) -> Result<([(HeaderName, &'static str); 1], String), Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
     let path = scoped_join_win_safe(root, relative_path)?;
 
-    let ret = tokio::fs::read_to_string(&path)
-        .await
-        .context("Failed to read file")?;
+    let (ret, encoding) = text_encoding::decode(
+        &tokio::fs::read(&path)
+            .await
+            .context("Failed to read file")?,
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Read,
+        FSTarget::File(path),
+        caused_by,
+    ));
+    Ok((
+        [(
+            HeaderName::from_static(text_encoding::FILE_ENCODING_HEADER),
+            encoding.name(),
+        )],
+        ret,
+    ))
+}
+
+#[derive(Serialize, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum PathHash {
+    File(FileHash),
+    /// Every file under the directory, keyed by their `/` separated path relative to it
+    Directory {
+        files: Manifest,
+    },
+}
+
+/// SHA-256 of a file, or of every file under a directory so clients can tell what changed
+/// without downloading anything. `.` hashes the whole instance
+async fn hash_instance_path(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<PathHash>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    let root = instance.path().await;
+    drop(instances);
+    let path = scoped_join_win_safe(root, relative_path.trim_start_matches('/'))?;
+    let metadata = tokio::fs::metadata(&path).await.map_err(|_| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("{} does not exist", relative_path),
+    })?;
+    let is_dir = metadata.is_dir();
+    let ret = tokio::task::spawn_blocking({
+        let path = path.clone();
+        move || -> Result<PathHash, Error> {
+            Ok(if is_dir {
+                PathHash::Directory {
+                    files: snapshot::hash_dir(&path)?,
+                }
+            } else {
+                PathHash::File(snapshot::hash_file(&path)?)
+            })
+        }
+    })
+    .await
+    .context("Hash task panicked")??;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Read,
+        if is_dir {
+            FSTarget::Directory(path)
+        } else {
+            FSTarget::File(path)
+        },
+        caused_by,
+    ));
+    Ok(Json(ret))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct HexViewQuery {
+    #[serde(default)]
+    offset: u64,
+    length: Option<u64>,
+}
+
+/// A window of any file as hex and ASCII, see [`hex_view::MAX_LENGTH`] for how much at most
+async fn hex_view_instance_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Query(HexViewQuery { offset, length }): Query<HexViewQuery>,
+) -> Result<Json<HexView>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    let root = instance.path().await;
+    drop(instances);
+    let path = scoped_join_win_safe(root, relative_path)?;
+    if !path.is_file() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Not a file"),
+        });
+    }
+    let ret = tokio::task::spawn_blocking({
+        let path = path.clone();
+        move || hex_view::read_window(&path, offset, length.unwrap_or(hex_view::DEFAULT_LENGTH))
+    })
+    .await
+    .context("Read task panicked")??;
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Read,
         FSTarget::File(path),
         caused_by,
     ));
-    Ok(ret)
+    Ok(Json(ret))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct WriteFileQuery {
+    /// Encoding to convert the UTF-8 body to, like the one the file was read as
+    encoding: Option<String>,
 }
 
 async fn write_instance_file(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
     AuthBearer(token): AuthBearer,
+    Query(WriteFileQuery { encoding }): Query<WriteFileQuery>,
     body: Bytes,
 ) -> Result<Json<()>, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -160,6 +318,16 @@ async fn write_instance_file(
             source: eyre!("You don't have permission to write to this file"),
         });
     }
+    let body = match encoding {
+        Some(encoding) => {
+            let text = std::str::from_utf8(&body).map_err(|_| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Text to convert must be UTF-8"),
+            })?;
+            Bytes::from(text_encoding::encode(text, &encoding)?)
+        }
+        None => body,
+    };
     let mut file = tokio::fs::File::create(&path)
         .await
         .context("Failed to create file")?;
@@ -170,6 +338,7 @@ async fn write_instance_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Write,
@@ -186,11 +355,15 @@ async fn make_instance_directory(
 ) -> Result<Json<()>, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -201,6 +374,7 @@ async fn make_instance_directory(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Create,
@@ -227,11 +401,15 @@ async fn copy_instance_files(
     }): Json<CopyInstanceFileRequest>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -279,6 +457,7 @@ async fn copy_instance_files(
                         CausedBy::User {
                             user_id: requester.uid.clone(),
                             user_name: requester.username.clone(),
+                            impersonated_by: requester.impersonated_by.clone(),
                         },
                     );
                 event_broadcaster.send(progression_event_start);
@@ -371,11 +550,15 @@ async fn move_instance_file(
     let relative_path_source = decode_base64(&base64_relative_path_source)?;
     let relative_path_dest = decode_base64(&base64_relative_path_dest)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -389,7 +572,9 @@ async fn move_instance_file(
         .strip_prefix(&root)
         .context("Error stripping prefix")?;
 
-    if !requester.can_perform_action(&UserAction::WriteInstanceFile(uuid.clone()))
+    if !state
+        .can_perform_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await
         && (is_path_protected(&path_source) || is_path_protected(&path_dest))
     {
         return Err(Error {
@@ -419,6 +604,7 @@ async fn move_instance_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
 
     state.event_broadcaster.send(new_fs_event(
@@ -439,11 +625,15 @@ async fn remove_instance_file(
 ) -> Result<Json<()>, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -461,6 +651,7 @@ async fn remove_instance_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Delete,
@@ -477,11 +668,15 @@ async fn remove_instance_dir(
 ) -> Result<Json<()>, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -524,6 +719,7 @@ async fn remove_instance_dir(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Delete,
@@ -540,11 +736,15 @@ async fn new_instance_file(
 ) -> Result<Json<()>, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -562,6 +762,7 @@ async fn new_instance_file(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Create,
@@ -578,11 +779,15 @@ async fn get_instance_file_url(
 ) -> Result<String, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -600,6 +805,7 @@ async fn get_instance_file_url(
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
     };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Download,
@@ -618,15 +824,20 @@ async fn upload_instance_file(
 ) -> Result<Json<()>, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -755,11 +966,15 @@ pub async fn unzip_instance_file(
 ) -> Result<Json<()>, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -782,6 +997,7 @@ pub async fn unzip_instance_file(
             CausedBy::User {
                 user_id: requester.uid.clone(),
                 user_name: requester.username.clone(),
+                impersonated_by: requester.impersonated_by.clone(),
             },
         );
 
@@ -829,11 +1045,15 @@ async fn zip_instance_files(
     Json(zip_request): Json<ZipRequest>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let root = instance.path().await;
     drop(instances);
@@ -879,6 +1099,7 @@ async fn zip_instance_files(
             CausedBy::User {
                 user_id: requester.uid.clone(),
                 user_name: requester.username.clone(),
+                impersonated_by: requester.impersonated_by.clone(),
             },
         );
         event_broadcaster.send(progression_start_event);
@@ -923,6 +1144,10 @@ pub fn get_instance_fs_routes(state: AppState) -> Router {
             "/instance/:uuid/fs/:base64_relative_path/read",
             get(read_instance_file),
         )
+        .route(
+            "/instance/:uuid/fs/:base64_relative_path/hex",
+            get(hex_view_instance_file),
+        )
         .route(
             "/instance/:uuid/fs/:base64_relative_path/write",
             put(write_instance_file),
@@ -932,6 +1157,7 @@ pub fn get_instance_fs_routes(state: AppState) -> Router {
             put(make_instance_directory),
         )
         .route("/instance/:uuid/fs/cpr", put(copy_instance_files))
+        .route("/instance/:uuid/fs/hash/*path", get(hash_instance_path))
         .route(
             "/instance/:uuid/fs/:base64_relative_path/move/:base64_relative_path_dest",
             put(move_instance_file),
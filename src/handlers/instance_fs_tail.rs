@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Path, Query, WebSocketUpgrade,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use color_eyre::eyre::eyre;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::error;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    event_broadcaster::SubscriptionFilter,
+    events::{
+        new_fs_event, CausedBy, Event, EventInner, EventType, FSOperation, FSTarget, UserEventInner,
+    },
+    file_tail::{FileTail, FileTailMessage},
+    i18n::{LocalizedMessage, MessageId},
+    traits::t_configurable::TConfigurable,
+    types::{InstanceUuid, UserId},
+    util::scoped_join_win_safe,
+    AppState,
+};
+
+use super::util::parse_bearer_token;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_BACKLOG_LINES: usize = 1000;
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct FileTailQuery {
+    /// Browsers can't set headers on WebSockets
+    pub token: String,
+    /// How many of the file's last lines are sent first
+    #[serde(default = "default_backlog_lines")]
+    pub lines: usize,
+}
+
+fn default_backlog_lines() -> usize {
+    100
+}
+
+/// Streams the lines appended to a file as [`FileTailMessage`]s, starting with its last ones
+pub async fn tail_instance_file(
+    ws: WebSocketUpgrade,
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, relative_path)): Path<(InstanceUuid, String)>,
+    Query(query): Query<FileTailQuery>,
+) -> Result<Response, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = parse_bearer_token(query.token.as_str())
+        .and_then(|token| users_manager.try_auth(&token))
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    drop(users_manager);
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    let root = instance.path().await;
+    drop(instances);
+    let path = scoped_join_win_safe(root, relative_path.trim_start_matches('/'))?;
+    if !path.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("{} is not a file", relative_path),
+        });
+    }
+    let (tail, backlog) = FileTail::open(path.clone(), query.lines.min(MAX_BACKLOG_LINES)).await?;
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Read,
+        FSTarget::File(path),
+        CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username,
+            impersonated_by: requester.impersonated_by.clone(),
+        },
+    ));
+    // to stop following once the user logs out
+    let user_events = state
+        .event_broadcaster
+        .subscribe_filtered(SubscriptionFilter {
+            event_types: Some(vec![EventType::UserEvent]),
+            ..Default::default()
+        });
+    Ok(
+        ws.on_upgrade(move |socket| {
+            tail_file_ws(socket, tail, backlog, requester.uid, user_events)
+        }),
+    )
+}
+
+async fn send(
+    sender: &mut futures::stream::SplitSink<WebSocket, Message>,
+    message: &FileTailMessage,
+) -> bool {
+    sender
+        .send(Message::Text(serde_json::to_string(message).unwrap()))
+        .await
+        .is_ok()
+}
+
+async fn tail_file_ws(
+    stream: WebSocket,
+    mut tail: FileTail,
+    backlog: Vec<String>,
+    uid: UserId,
+    mut user_events: Receiver<Event>,
+) {
+    let (mut sender, mut receiver) = stream.split();
+    if !send(&mut sender, &FileTailMessage::Lines { lines: backlog }).await {
+        return;
+    }
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let messages = match tail.poll().await {
+                    Ok(messages) => messages,
+                    Err(e) => {
+                        let _ = sender.send(Message::Close(None)).await;
+                        error!("Failed to follow file: {}", e);
+                        break;
+                    }
+                };
+                for message in &messages {
+                    if !send(&mut sender, message).await {
+                        return;
+                    }
+                }
+            }
+            event = user_events.recv() => match event {
+                Ok(Event { event_inner: EventInner::UserEvent(user_event), .. }) => {
+                    if user_event.user_id == uid
+                        && matches!(
+                            user_event.user_event_inner,
+                            UserEventInner::UserLoggedOut | UserEventInner::UserDeleted
+                        )
+                    {
+                        break;
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            },
+            ws_msg = receiver.next() => match ws_msg {
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                // pings are answered by axum, the client has nothing else to say
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+pub fn get_instance_fs_tail_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/fs/tail/*path", get(tail_instance_file))
+        .with_state(state)
+}
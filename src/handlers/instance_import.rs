@@ -0,0 +1,308 @@
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Query},
+    routing::post,
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+
+use crate::{
+    auth::{instance_access::InstanceAccess, user::UserAction},
+    disk_space,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, ProgressionEndValue, ProgressionStartValue},
+    i18n::{LocalizedMessage, MessageId},
+    implementations::minecraft::{
+        import::{detect_server, extract_server_archive, extracted_size, MinecraftImportOptions},
+        MinecraftInstance,
+    },
+    prelude::path_to_tmp,
+    quota,
+    traits::{t_configurable::GameType, TInstance},
+    types::{DotLodestoneConfig, InstanceUuid},
+    AppState,
+};
+
+use super::storage::StorageQuery;
+
+/// Sets up a Minecraft instance from a zip of an existing server.
+///
+/// The multipart body holds the archive as `file`, and optionally [`MinecraftImportOptions`] as
+/// JSON in `options`. Detection problems are reported right away, the rest of the setup is a
+/// progression like creating an instance.
+pub async fn import_minecraft_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(StorageQuery { storage }): Query<StorageQuery>,
+    mut multipart: Multipart,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let (disk_space, location) = {
+        let global_settings = state.global_settings.lock().await;
+        (
+            global_settings.disk_space(),
+            global_settings.storage_location(storage.as_deref())?,
+        )
+    };
+    disk_space::ensure_space(&disk_space, &location.path, 0, None, None).await?;
+
+    crate::util::fs::create_dir_all(path_to_tmp()).await?;
+    let archive = tempfile::Builder::new()
+        .suffix(".zip")
+        .tempfile_in(path_to_tmp())
+        .context("Failed to create temporary file")?;
+    let mut options = MinecraftImportOptions::default();
+    let mut archive_name = None;
+    while let Some(mut field) = multipart.next_field().await.map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Failed to read upload: {e}"),
+    })? {
+        match field.name() {
+            Some("options") => {
+                let text = field.text().await.map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Failed to read options: {e}"),
+                })?;
+                options = serde_json::from_str(&text).map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid options: {e}"),
+                })?;
+            }
+            Some("file") => {
+                archive_name = field
+                    .file_name()
+                    .and_then(|name| name.strip_suffix(".zip"))
+                    .map(str::to_string);
+                let mut file = tokio::fs::File::create(archive.path())
+                    .await
+                    .context("Failed to open temporary file")?;
+                while let Some(chunk) = field.chunk().await.map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Failed to read upload: {e}"),
+                })? {
+                    file.write_all(&chunk)
+                        .await
+                        .context("Failed to write temporary file")?;
+                }
+            }
+            _ => continue,
+        }
+    }
+
+    let archive_path = archive.path().to_owned();
+    let size = tokio::task::spawn_blocking(move || extracted_size(&archive_path))
+        .await
+        .context("Archive task panicked")??;
+    if size == 0 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("No server archive was uploaded"),
+        });
+    }
+    disk_space::ensure_space(&disk_space, &location.path, size, None, None).await?;
+
+    let mut instance_uuid = InstanceUuid::default();
+    for uuid in state.instances.lock().await.keys() {
+        if let Some(uuid) = uuid.as_ref().get(0..8) {
+            if uuid == &instance_uuid.no_prefix()[0..8] {
+                instance_uuid = InstanceUuid::default();
+            }
+        }
+    }
+    let instance_uuid = instance_uuid;
+
+    let name = sanitize_filename::sanitize(
+        options
+            .name
+            .clone()
+            .or(archive_name)
+            .unwrap_or_else(|| "Imported Server".to_string()),
+    );
+    let setup_path = location
+        .path
+        .join(format!("{}-{}", name, &instance_uuid.no_prefix()[0..8]));
+    crate::util::fs::create_dir_all(&setup_path).await?;
+
+    let detected = tokio::task::spawn_blocking({
+        let setup_path = setup_path.clone();
+        move || {
+            extract_server_archive(archive.path(), &setup_path, size)?;
+            detect_server(&setup_path)
+        }
+    })
+    .await
+    .context("Extract task panicked")?;
+    let checked = match detected {
+        Ok(detected) => {
+            let setup_config = detected.setup_config(name, options);
+            let port_in_use = state
+                .port_manager
+                .lock()
+                .await
+                .port_status(setup_config.port)
+                .is_allocated;
+            if port_in_use {
+                Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Port {} is used by another instance, choose another one",
+                        setup_config.port
+                    ),
+                })
+            } else {
+                quota::check_new_instance(
+                    &state,
+                    &requester,
+                    setup_config.max_ram.unwrap_or_default(),
+                )
+                .await
+                .map(|_| (detected, setup_config))
+            }
+        }
+        Err(e) => Err(e),
+    };
+    let (detected, setup_config) = match checked {
+        Ok(v) => v,
+        Err(e) => {
+            let _ = crate::util::fs::remove_dir_all(&setup_path).await;
+            return Err(e);
+        }
+    };
+
+    let dot_lodestone_config =
+        DotLodestoneConfig::new(instance_uuid.clone(), GameType::MinecraftJava)
+            .with_storage_location(&location.id);
+    // written after extracting, so the one of a server exported from another core is replaced
+    tokio::fs::write(
+        setup_path.join(".lodestone_config"),
+        serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+    )
+    .await
+    .context("Failed to write .lodestone_config file")?;
+
+    let mut perm = requester.permissions.clone();
+    tokio::task::spawn({
+        let uuid = instance_uuid.clone();
+        let instance_name = setup_config.name.clone();
+        let event_broadcaster = state.event_broadcaster.clone();
+        let port = setup_config.port;
+        let flavour = setup_config.flavour.clone();
+        let caused_by = CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+            impersonated_by: requester.impersonated_by.clone(),
+        };
+        async move {
+            let (progression_start_event, event_id) = Event::new_progression_event_start(
+                format!("Importing Minecraft server {instance_name}"),
+                Some(10.0),
+                Some(ProgressionStartValue::InstanceCreation {
+                    instance_uuid: uuid.clone(),
+                    instance_name: instance_name.clone(),
+                    port,
+                    flavour: flavour.to_string(),
+                    game_type: "minecraft".to_string(),
+                }),
+                caused_by,
+            );
+            event_broadcaster.send(
+                progression_start_event.with_localized_message(
+                    LocalizedMessage::new(MessageId::InstanceCreationStarted)
+                        .with_param("game", "Minecraft")
+                        .with_param("instance_name", &instance_name),
+                ),
+            );
+            let minecraft_instance = match MinecraftInstance::import(
+                setup_config,
+                detected,
+                dot_lodestone_config,
+                setup_path.clone(),
+                &event_id,
+                state.event_broadcaster.clone(),
+                state.macro_executor.clone(),
+            )
+            .await
+            {
+                Ok(v) => {
+                    event_broadcaster.send(
+                        Event::new_progression_event_end(
+                            event_id,
+                            true,
+                            Some("Instance imported successfully"),
+                            Some(ProgressionEndValue::InstanceCreation(
+                                v.get_instance_info().await,
+                            )),
+                        )
+                        .with_localized_message(LocalizedMessage::new(
+                            MessageId::InstanceCreationSucceeded,
+                        )),
+                    );
+                    v
+                }
+                Err(e) => {
+                    event_broadcaster.send(
+                        Event::new_progression_event_end(
+                            event_id,
+                            false,
+                            Some(&format!("Instance import failed: {e}")),
+                            None,
+                        )
+                        .with_localized_message(
+                            LocalizedMessage::new(MessageId::InstanceCreationFailed)
+                                .with_param("error", &e),
+                        ),
+                    );
+                    if let Err(e) = crate::util::fs::remove_dir_all(setup_path).await {
+                        error!("Failed to remove directory after instance import failed: {e}");
+                    }
+                    return;
+                }
+            };
+            state.port_manager.lock().await.add_port(port);
+            perm.can_start_instance.insert(uuid.clone());
+            perm.can_stop_instance.insert(uuid.clone());
+            perm.can_view_instance.insert(uuid.clone());
+            perm.can_read_instance_file.insert(uuid.clone());
+            perm.can_write_instance_file.insert(uuid.clone());
+            // ignore errors since we don't care if the permissions update fails
+            let _ = state
+                .users_manager
+                .write()
+                .await
+                .update_permissions(&requester.uid, perm, CausedBy::System)
+                .await
+                .map_err(|e| {
+                    error!("Failed to update permissions: {:?}", e);
+                    e
+                });
+            let mut instances = state.instances.lock().await;
+            if let Err(e) = state
+                .global_settings
+                .lock()
+                .await
+                .set_instance_access(
+                    uuid.clone(),
+                    Some(InstanceAccess::new(requester.uid.clone())),
+                )
+                .await
+            {
+                error!("Failed to record the owner of instance {uuid}: {e}");
+            }
+            instances.insert(uuid.clone(), minecraft_instance.into());
+        }
+    });
+    Ok(Json(instance_uuid))
+}
+
+pub fn get_instance_import_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/import/minecraft",
+            post(import_minecraft_instance),
+        )
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state)
+}
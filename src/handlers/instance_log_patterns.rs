@@ -0,0 +1,101 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    log_parser::{self, LogPattern},
+    prelude::GameInstance,
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    AppState,
+};
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct InstanceLogPatterns {
+    /// What the game recognizes out of the box
+    pub builtin: Vec<LogPattern>,
+    /// Set for the instance, tried first
+    pub custom: Vec<LogPattern>,
+}
+
+async fn get_instance(state: &AppState, uuid: &InstanceUuid) -> Result<GameInstance, Error> {
+    state
+        .instances
+        .lock()
+        .await
+        .get(uuid)
+        .cloned()
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })
+}
+
+fn unsupported() -> Error {
+    Error {
+        kind: ErrorKind::UnsupportedOperation,
+        source: eyre!("This instance does not support log patterns"),
+    }
+}
+
+pub async fn get_log_patterns(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceLogPatterns>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let instance = get_instance(&state, &uuid).await?;
+    let custom = match &instance {
+        GameInstance::MinecraftInstance(minecraft) => minecraft.log_patterns().await,
+        GameInstance::GenericInstance(generic) => generic.log_patterns().await,
+        #[allow(unreachable_patterns)]
+        _ => return Err(unsupported()),
+    };
+    Ok(Json(InstanceLogPatterns {
+        builtin: log_parser::builtin_patterns(&instance.game_type().await),
+        custom,
+    }))
+}
+
+/// Replaces the custom patterns. Minecraft instances use them from the next start of the
+/// server, generic instances only use Ready patterns, and right away
+pub async fn set_log_patterns(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(log_patterns): Json<Vec<LogPattern>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    match get_instance(&state, &uuid).await? {
+        GameInstance::MinecraftInstance(minecraft) => {
+            minecraft.set_log_patterns(log_patterns).await?
+        }
+        GameInstance::GenericInstance(generic) => generic.set_log_patterns(log_patterns).await?,
+        #[allow(unreachable_patterns)]
+        _ => return Err(unsupported()),
+    }
+    Ok(Json(()))
+}
+
+pub fn get_instance_log_patterns_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/log_patterns",
+            get(get_log_patterns).put(set_log_patterns),
+        )
+        .with_state(state)
+}
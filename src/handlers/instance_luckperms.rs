@@ -0,0 +1,99 @@
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    events::CausedBy,
+    minecraft::luckperms::{LuckPermsChange, LuckPermsGroup, LuckPermsStatus, LuckPermsUser},
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::util::get_minecraft_instance;
+
+pub async fn get_luckperms_status(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LuckPermsStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let minecraft = get_minecraft_instance(&state, &uuid).await?;
+    Ok(Json(minecraft.luckperms_status().await))
+}
+
+pub async fn get_luckperms_groups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<LuckPermsGroup>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let minecraft = get_minecraft_instance(&state, &uuid).await?;
+    Ok(Json(minecraft.luckperms_groups().await?))
+}
+
+pub async fn get_luckperms_users(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<LuckPermsUser>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let minecraft = get_minecraft_instance(&state, &uuid).await?;
+    Ok(Json(minecraft.luckperms_users().await?))
+}
+
+/// Changes are made with console commands, so they need the instance running and being allowed
+/// to write to its console
+pub async fn apply_luckperms_change(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(change): Json<LuckPermsChange>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    state
+        .try_action(&requester, &UserAction::WriteConsole(uuid.clone()))
+        .await?;
+    state
+        .check_command(&requester, &uuid, &change.command()?)
+        .await?;
+    let minecraft = get_minecraft_instance(&state, &uuid).await?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
+    };
+    minecraft.apply_luckperms_change(&change, caused_by).await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_luckperms_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/luckperms", get(get_luckperms_status))
+        .route(
+            "/instance/:uuid/luckperms/groups",
+            get(get_luckperms_groups),
+        )
+        .route("/instance/:uuid/luckperms/users", get(get_luckperms_users))
+        .route(
+            "/instance/:uuid/luckperms/changes",
+            post(apply_luckperms_change),
+        )
+        .with_state(state)
+}
@@ -1,17 +1,22 @@
 use axum::{
-    extract::Path,
+    extract::{Path, Query},
     routing::{get, put},
     Json, Router,
 };
 
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use tracing::warn;
+use ts_rs::TS;
 
 use crate::{
     auth::user::UserAction,
     error::{Error, ErrorKind},
     events::CausedBy,
+    i18n::{LocalizedMessage, MessageId},
     macro_executor::MacroPID,
+    macro_history::{self, MacroRun, MacroRunOutput},
     traits::t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry},
     types::InstanceUuid,
     AppState,
@@ -23,11 +28,15 @@ pub async fn get_instance_task_list(
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<Vec<TaskEntry>>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    state
+        .try_action(&requester, &UserAction::AccessMacro(Some(uuid.clone())))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let tasks = instance.get_task_list().await?;
     Ok(Json(tasks))
@@ -39,11 +48,15 @@ pub async fn get_instance_macro_list(
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<Vec<MacroEntry>>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    state
+        .try_action(&requester, &UserAction::AccessMacro(Some(uuid.clone())))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let macros = instance.get_macro_list().await?;
     Ok(Json(macros))
@@ -55,11 +68,15 @@ pub async fn get_instance_history_list(
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<Vec<HistoryEntry>>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    state
+        .try_action(&requester, &UserAction::AccessMacro(Some(uuid.clone())))
+        .await?;
     let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let history = instance.get_history_list().await?;
     Ok(Json(history))
@@ -72,50 +89,143 @@ pub async fn run_macro(
     Json(args): Json<Vec<String>>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    state
+        .try_action(&requester, &UserAction::AccessMacro(Some(uuid.clone())))
+        .await?;
     let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
-    })?;
-    instance
-        .run_macro(
-            &macro_name,
-            args,
-            CausedBy::User {
-                user_id: requester.uid,
-                user_name: requester.username,
-            },
+    let instance = instances.get_mut(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
         )
+    })?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
+    };
+    let task = instance
+        .run_macro(&macro_name, args.clone(), caused_by.clone())
         .await?;
+    drop(instances);
+    if let Err(e) = macro_history::record_start(&state, &uuid, &task, &args, &caused_by).await {
+        warn!("Failed to record the run of macro {macro_name}: {e}");
+    }
     Ok(Json(()))
 }
 
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct MacroRunsQuery {
+    /// Only runs older than this one, to page through them
+    pub before: Option<i64>,
+    #[serde(default = "default_macro_runs_limit")]
+    pub limit: u32,
+}
+
+fn default_macro_runs_limit() -> u32 {
+    macro_history::DEFAULT_LIMIT
+}
+
+/// Runs kept in the database, latest first, including those from before the core restarted
+pub async fn get_instance_macro_runs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<MacroRunsQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<MacroRun>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessMacro(Some(uuid.clone())))
+        .await?;
+    Ok(Json(
+        macro_history::list_runs(&state.sqlite_pool, &uuid, query.before, query.limit).await?,
+    ))
+}
+
+pub async fn get_instance_macro_run_output(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, i64)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<MacroRunOutput>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessMacro(Some(uuid.clone())))
+        .await?;
+    macro_history::get_run_output(&state.sqlite_pool, &uuid, id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Macro run {} not found", id),
+        })
+}
+
 pub async fn kill_macro(
     Path((uuid, pid)): Path<(InstanceUuid, MacroPID)>,
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    state
+        .try_action(&requester, &UserAction::AccessMacro(Some(uuid.clone())))
+        .await?;
     let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instances.get_mut(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     instance.kill_macro(pid).await?;
     Ok(Json(()))
 }
 
+/// Turns a macro of the core's macros directory on or off for the instance
+pub async fn set_global_macro_enabled(
+    Path((uuid, macro_name)): Path<(InstanceUuid, String)>,
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(enabled): Json<bool>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessMacro(Some(uuid.clone())))
+        .await?;
+    let mut instances = state.instances.lock().await;
+    let instance = instances.get_mut(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    instance
+        .set_global_macro_enabled(&macro_name, enabled)
+        .await?;
+    Ok(Json(()))
+}
+
 pub fn get_instance_macro_routes(state: AppState) -> Router {
     Router::new()
         .route("/instance/:uuid/macro/run/:macro_name", put(run_macro))
         .route("/instance/:uuid/macro/kill/:pid", put(kill_macro))
         .route("/instance/:uuid/macro/list", get(get_instance_macro_list))
+        .route(
+            "/instance/:uuid/macro/global/:macro_name/enabled",
+            put(set_global_macro_enabled),
+        )
         .route("/instance/:uuid/task/list", get(get_instance_task_list))
         .route(
             "/instance/:uuid/history/list",
             get(get_instance_history_list),
         )
+        .route(
+            "/instance/:uuid/tasks/history",
+            get(get_instance_macro_runs),
+        )
+        .route(
+            "/instance/:uuid/tasks/history/:id",
+            get(get_instance_macro_run_output),
+        )
         .with_state(state)
 }
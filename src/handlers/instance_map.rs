@@ -0,0 +1,47 @@
+use axum::{
+    extract::Path,
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::Context;
+
+use crate::{auth::user::UserAction, error::Error, types::InstanceUuid, world_map, AppState};
+
+use super::util::get_minecraft_instance;
+
+/// The last rendered map of the instance's world, rendered now if there isn't one yet
+pub async fn get_world_map(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Response, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    let path = world_map::map_path(&uuid);
+    if !path.exists() {
+        world_map::render_instance(&instance).await?;
+    }
+    let png = tokio::fs::read(&path)
+        .await
+        .context(format!("Failed to read {}", path.display()))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, "private, max-age=60"),
+        ],
+        png,
+    )
+        .into_response())
+}
+
+pub fn get_instance_map_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/map.png", get(get_world_map))
+        .with_state(state)
+}
@@ -0,0 +1,206 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::{User, UserAction},
+    chat_moderation::{
+        self, ModerationAction, ModerationLogEntry, ModerationRule, NewModerationRule,
+        MAX_RULES_PER_INSTANCE,
+    },
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    traits::t_configurable::TConfigurable,
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+async fn check_instance_exists(state: &AppState, uuid: &InstanceUuid) -> Result<(), Error> {
+    if state.instances.lock().await.contains_key(uuid) {
+        Ok(())
+    } else {
+        Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ))
+    }
+}
+
+/// Actions run as console commands, so setting them up needs the same rights as sending them
+async fn check_actions(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+    rule: &NewModerationRule,
+) -> Result<(), Error> {
+    rule.validate()?;
+    if rule.actions.iter().any(ModerationAction::uses_console) {
+        state
+            .try_action(requester, &UserAction::WriteConsole(uuid.clone()))
+            .await?;
+    }
+    for action in &rule.actions {
+        if let ModerationAction::Mute { command } = action {
+            state.check_command(requester, uuid, command).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn save_rules(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    rules: Vec<ModerationRule>,
+) -> Result<(), Error> {
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_moderation_rules(uuid.clone(), rules)
+        .await
+}
+
+fn rule_not_found() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Moderation rule not found"),
+    }
+}
+
+pub async fn list_moderation_rules(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ModerationRule>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    check_instance_exists(&state, &uuid).await?;
+    Ok(Json(
+        state
+            .global_settings
+            .lock()
+            .await
+            .instance_moderation_rules(&uuid),
+    ))
+}
+
+pub async fn create_moderation_rule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(new_rule): Json<NewModerationRule>,
+) -> Result<Json<ModerationRule>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    check_instance_exists(&state, &uuid).await?;
+    check_actions(&state, &requester, &uuid, &new_rule).await?;
+    let mut rules = state
+        .global_settings
+        .lock()
+        .await
+        .instance_moderation_rules(&uuid);
+    if rules.len() >= MAX_RULES_PER_INSTANCE {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("An instance can have at most {MAX_RULES_PER_INSTANCE} moderation rules"),
+        });
+    }
+    let rule = new_rule.into_rule(Snowflake::new());
+    rules.push(rule.clone());
+    save_rules(&state, &uuid, rules).await?;
+    Ok(Json(rule))
+}
+
+pub async fn update_moderation_rule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, rule_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+    Json(new_rule): Json<NewModerationRule>,
+) -> Result<Json<ModerationRule>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    check_instance_exists(&state, &uuid).await?;
+    check_actions(&state, &requester, &uuid, &new_rule).await?;
+    let mut rules = state
+        .global_settings
+        .lock()
+        .await
+        .instance_moderation_rules(&uuid);
+    let rule = rules
+        .iter_mut()
+        .find(|rule| rule.id == rule_id)
+        .ok_or_else(rule_not_found)?;
+    *rule = new_rule.into_rule(rule_id);
+    let rule = rule.clone();
+    save_rules(&state, &uuid, rules).await?;
+    Ok(Json(rule))
+}
+
+pub async fn delete_moderation_rule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, rule_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let mut rules = state
+        .global_settings
+        .lock()
+        .await
+        .instance_moderation_rules(&uuid);
+    let count = rules.len();
+    rules.retain(|rule| rule.id != rule_id);
+    if rules.len() == count {
+        return Err(rule_not_found());
+    }
+    save_rules(&state, &uuid, rules).await?;
+    Ok(Json(()))
+}
+
+/// Newest first, the log outlives the rules that wrote it
+pub async fn get_moderation_log(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ModerationLogEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let path = match state.instances.lock().await.get(&uuid) {
+        Some(instance) => instance.path().await,
+        None => {
+            return Err(Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            ))
+        }
+    };
+    Ok(Json(chat_moderation::read_log(&path).await))
+}
+
+pub fn get_instance_moderation_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/moderation/rules",
+            get(list_moderation_rules).post(create_moderation_rule),
+        )
+        .route(
+            "/instance/:uuid/moderation/rules/:rule_id",
+            put(update_moderation_rule).delete(delete_moderation_rule),
+        )
+        .route("/instance/:uuid/moderation/log", get(get_moderation_log))
+        .with_state(state)
+}
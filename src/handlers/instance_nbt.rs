@@ -0,0 +1,120 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::{new_fs_event, CausedBy, FSOperation, FSTarget},
+    i18n::{LocalizedMessage, MessageId},
+    implementations::minecraft::nbt::{self, NbtFile},
+    traits::{t_configurable::TConfigurable, t_server::State, t_server::TServer},
+    types::InstanceUuid,
+    util::scoped_join_win_safe,
+    AppState,
+};
+
+use super::util::decode_base64;
+
+async fn read_nbt_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<NbtFile>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    let root = instance.path().await;
+    drop(instances);
+    let path = scoped_join_win_safe(root, relative_path)?;
+    let bytes = tokio::fs::read(&path)
+        .await
+        .context(format!("Failed to read {}", path.display()))?;
+    let ret = tokio::task::spawn_blocking(move || NbtFile::parse(&bytes))
+        .await
+        .context("Parse task panicked")??;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Read,
+        FSTarget::File(path),
+        caused_by,
+    ));
+    Ok(Json(ret))
+}
+
+/// Writes edited tags back, keeping the original next to it as `<file>.<timestamp>.bak`.
+/// Returns the name of the backup
+async fn write_nbt_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(file): Json<NbtFile>,
+) -> Result<Json<String>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    // a running server would overwrite the edit with what it has in memory
+    if instance.state().await != State::Stopped {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance must be stopped before editing NBT files"),
+        });
+    }
+    let root = instance.path().await;
+    drop(instances);
+    let path = scoped_join_win_safe(root, relative_path)?;
+    if !path.is_file() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Not a file"),
+        });
+    }
+    let bytes = tokio::task::spawn_blocking(move || file.to_bytes())
+        .await
+        .context("Serialize task panicked")??;
+
+    let backup_name = nbt::write_with_backup(&path, &bytes).await?;
+
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+        impersonated_by: requester.impersonated_by,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Write,
+        FSTarget::File(path),
+        caused_by,
+    ));
+    Ok(Json(backup_name))
+}
+
+pub fn get_instance_nbt_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/nbt/:base64_relative_path",
+            get(read_nbt_file).put(write_nbt_file),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,299 @@
+use std::time::Duration;
+
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    connection_info::{self, ConnectionInfo},
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    minecraft::query::ServerStatus,
+    network_filter::{
+        BlockedAttempt, NetworkFilterSettings, ProxyMetrics, DEFAULT_DRAIN, MAX_DRAIN,
+    },
+    prelude::GameInstance,
+    traits::{
+        t_configurable::{Game, TConfigurable},
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::util::get_minecraft_instance;
+
+pub async fn get_network_filter(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<NetworkFilterSettings>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    Ok(Json(
+        state
+            .global_settings
+            .lock()
+            .await
+            .instance_network_filter(&uuid),
+    ))
+}
+
+/// Validates the settings, and that the proxy can take their port
+async fn check_network_filter(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    settings: &NetworkFilterSettings,
+) -> Result<(), Error> {
+    let instance_port = match state.instances.lock().await.get(uuid) {
+        Some(instance) => instance.port().await,
+        None => {
+            return Err(Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            ))
+        }
+    };
+    settings.validate()?;
+    let current_port = state
+        .global_settings
+        .lock()
+        .await
+        .instance_network_filter(uuid)
+        .filter(|current| current.enabled)
+        .map(|current| current.listen_port);
+    if settings.listen_port == instance_port
+        || (current_port != Some(settings.listen_port)
+            && state
+                .port_manager
+                .lock()
+                .await
+                .port_status(settings.listen_port)
+                .is_allocated)
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Port {} is already used", settings.listen_port),
+        });
+    }
+    Ok(())
+}
+
+/// `null` removes the filter, the proxy is started or stopped right away
+pub async fn set_network_filter(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(settings): Json<Option<NetworkFilterSettings>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    if let Some(settings) = &settings {
+        check_network_filter(&state, &uuid, settings).await?;
+    }
+    // the proxy is set up first, so nothing is saved if its port can't be listened on
+    state
+        .network_filters
+        .lock()
+        .await
+        .apply(&state, &uuid, settings.as_ref())
+        .await?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_network_filter(uuid, settings)
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct ProxyRebind {
+    pub listen_port: u32,
+    /// All addresses when not set
+    pub listen_address: Option<String>,
+    /// How long connections through the old port are kept open, 5 minutes when not set and at
+    /// most an hour
+    pub drain_secs: Option<u32>,
+}
+
+/// Moves the proxy to another port or address while the instance keeps running. Players can
+/// join through the new one right away, and the ones connected through the old one are
+/// disconnected once the drain time is over.
+pub async fn rebind_network_filter(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(rebind): Json<ProxyRebind>,
+) -> Result<Json<NetworkFilterSettings>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let mut settings = state
+        .global_settings
+        .lock()
+        .await
+        .instance_network_filter(&uuid)
+        .filter(|settings| settings.enabled)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The network filter proxy isn't enabled for this instance"),
+        })?;
+    let drain = rebind
+        .drain_secs
+        .map_or(DEFAULT_DRAIN, |secs| Duration::from_secs(secs as u64));
+    if drain > MAX_DRAIN {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Connections can be drained for at most {} minutes",
+                MAX_DRAIN.as_secs() / 60
+            ),
+        });
+    }
+    settings.listen_port = rebind.listen_port;
+    settings.listen_address = rebind.listen_address;
+    check_network_filter(&state, &uuid, &settings).await?;
+    state
+        .network_filters
+        .lock()
+        .await
+        .apply_draining(&state, &uuid, Some(&settings), drain)
+        .await?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_network_filter(uuid, Some(settings.clone()))
+        .await?;
+    Ok(Json(settings))
+}
+
+/// Most recent first
+pub async fn get_blocked_attempts(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BlockedAttempt>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    Ok(Json(
+        state.network_filters.lock().await.blocked_attempts(&uuid),
+    ))
+}
+
+pub async fn get_proxy_metrics(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ProxyMetrics>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::MonitorInstance(uuid.clone()))
+        .await?;
+    Ok(Json(state.network_filters.lock().await.metrics(&uuid)))
+}
+
+/// The address players should use, with what went into guessing it
+pub async fn get_connection_info(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ConnectionInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    let (game, instance_port, minecraft) = match state.instances.lock().await.get(&uuid) {
+        Some(instance) => (
+            instance.game_type().await,
+            instance.port().await,
+            match instance {
+                GameInstance::MinecraftInstance(minecraft) => Some(minecraft.clone()),
+                _ => None,
+            },
+        ),
+        None => {
+            return Err(Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            ))
+        }
+    };
+    // players connect through the filter's proxy when there is one
+    let port = state
+        .global_settings
+        .lock()
+        .await
+        .instance_network_filter(&uuid)
+        .filter(|settings| settings.enabled)
+        .map_or(instance_port, |settings| settings.listen_port);
+    let domain = state.global_settings.lock().await.domain();
+    // Bedrock clients don't look up SRV records
+    let check_srv = matches!(game, Game::MinecraftJava { .. });
+    let mut info = connection_info::connection_info(domain, port, check_srv).await;
+    if let Some(minecraft) = minecraft {
+        if minecraft.state().await == State::Running {
+            info.status = Some(minecraft.server_status().await);
+        }
+    }
+    Ok(Json(info))
+}
+
+/// Asks the server over Query, and Geyser over Bedrock's ping, right away
+pub async fn ping_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ServerStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    let minecraft = get_minecraft_instance(&state, &uuid).await?;
+    if minecraft.state().await != State::Running {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Only a running instance can be pinged"),
+        });
+    }
+    Ok(Json(minecraft.refresh_server_status().await))
+}
+
+pub fn get_instance_network_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/network_filter",
+            get(get_network_filter).put(set_network_filter),
+        )
+        .route(
+            "/instance/:uuid/network_filter/blocked",
+            get(get_blocked_attempts),
+        )
+        .route(
+            "/instance/:uuid/network_filter/rebind",
+            post(rebind_network_filter),
+        )
+        .route(
+            "/instance/:uuid/network_filter/metrics",
+            get(get_proxy_metrics),
+        )
+        .route("/instance/:uuid/connection_info", get(get_connection_info))
+        .route("/instance/:uuid/ping", get(ping_instance))
+        .with_state(state)
+}
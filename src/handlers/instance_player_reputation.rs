@@ -0,0 +1,201 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    player_reputation::{
+        self, NewPlayerNote, PlayerNote, PlayerNoteEdit, PlayerReputation, PlayerReputationUpdate,
+    },
+    traits::t_player::{TPlayer, TPlayerManagement},
+    types::InstanceUuid,
+    AppState,
+};
+
+/// Notes are about players of every instance, but are looked at and written from one of them
+async fn check_instance(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+    write: bool,
+) -> Result<(), Error> {
+    let action = if write {
+        UserAction::WriteConsole(uuid.clone())
+    } else {
+        UserAction::ReadConsole(uuid.clone())
+    };
+    state.try_action(requester, &action).await?;
+    if state.instances.lock().await.contains_key(uuid) {
+        Ok(())
+    } else {
+        Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ))
+    }
+}
+
+/// Everyone watched or flagged, for owners
+pub async fn list_marked_players(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PlayerReputation>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    Ok(Json(
+        player_reputation::list_marked(&state.sqlite_pool).await?,
+    ))
+}
+
+/// What is known about the players currently on the instance, those nothing is known about are
+/// left out
+pub async fn get_online_reputations(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PlayerReputation>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_instance(&state, &requester, &uuid, false).await?;
+    let players = match state.instances.lock().await.get(&uuid) {
+        Some(instance) => instance.get_player_list().await?,
+        None => return Ok(Json(Vec::new())),
+    };
+    let mut reputations = Vec::new();
+    for player in players {
+        if let Some(reputation) =
+            player_reputation::get_reputation(&state.sqlite_pool, &player.get_id()).await?
+        {
+            reputations.push(reputation);
+        }
+    }
+    Ok(Json(reputations))
+}
+
+pub async fn get_player_reputation(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<PlayerReputation>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_instance(&state, &requester, &uuid, false).await?;
+    Ok(Json(
+        player_reputation::get_reputation(&state.sqlite_pool, &player_id).await?,
+    ))
+}
+
+pub async fn set_player_reputation(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(update): Json<PlayerReputationUpdate>,
+) -> Result<Json<PlayerReputation>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_instance(&state, &requester, &uuid, true).await?;
+    Ok(Json(
+        player_reputation::set_reputation(&state.sqlite_pool, &player_id, update).await?,
+    ))
+}
+
+pub async fn list_player_notes(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PlayerNote>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_instance(&state, &requester, &uuid, false).await?;
+    Ok(Json(
+        player_reputation::list_notes(&state.sqlite_pool, &player_id).await?,
+    ))
+}
+
+pub async fn add_player_note(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(note): Json<NewPlayerNote>,
+) -> Result<Json<PlayerNote>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_instance(&state, &requester, &uuid, true).await?;
+    Ok(Json(
+        player_reputation::add_note(
+            &state.sqlite_pool,
+            &player_id,
+            note,
+            &requester.uid,
+            &requester.username,
+            &uuid,
+        )
+        .await?,
+    ))
+}
+
+/// Notes can only be changed by whoever wrote them, or by an owner
+async fn check_author(
+    state: &AppState,
+    requester: &User,
+    player_id: &str,
+    note_id: i64,
+) -> Result<(), Error> {
+    let note = player_reputation::get_note(&state.sqlite_pool, player_id, note_id).await?;
+    if note.author_id != requester.uid && !requester.has_owner_rights() {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the author of a note can change it"),
+        });
+    }
+    Ok(())
+}
+
+pub async fn edit_player_note(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_id, note_id)): Path<(InstanceUuid, String, i64)>,
+    AuthBearer(token): AuthBearer,
+    Json(edit): Json<PlayerNoteEdit>,
+) -> Result<Json<PlayerNote>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_instance(&state, &requester, &uuid, true).await?;
+    check_author(&state, &requester, &player_id, note_id).await?;
+    Ok(Json(
+        player_reputation::edit_note(&state.sqlite_pool, &player_id, note_id, &edit.body).await?,
+    ))
+}
+
+pub async fn delete_player_note(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_id, note_id)): Path<(InstanceUuid, String, i64)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_instance(&state, &requester, &uuid, true).await?;
+    check_author(&state, &requester, &player_id, note_id).await?;
+    player_reputation::delete_note(&state.sqlite_pool, &player_id, note_id).await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_player_reputation_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/players/reputation", get(list_marked_players))
+        .route(
+            "/instance/:uuid/players/reputation",
+            get(get_online_reputations),
+        )
+        .route(
+            "/instance/:uuid/players/:player_id/reputation",
+            get(get_player_reputation).put(set_player_reputation),
+        )
+        .route(
+            "/instance/:uuid/players/:player_id/notes",
+            get(list_player_notes).post(add_player_note),
+        )
+        .route(
+            "/instance/:uuid/players/:player_id/notes/:note_id",
+            put(edit_player_note).delete(delete_player_note),
+        )
+        .with_state(state)
+}
@@ -1,15 +1,21 @@
 use std::collections::HashSet;
 
 use axum::{extract::Path, routing::get, Json, Router};
-use color_eyre::eyre::eyre;
+use axum_auth::AuthBearer;
 
 use crate::{
+    auth::user::UserAction,
     error::{Error, ErrorKind},
+    events::CausedBy,
+    i18n::{LocalizedMessage, MessageId},
+    implementations::minecraft::player_data::{PlayerData, PlayerDataEdit},
     traits::t_player::{Player, TPlayerManagement},
     types::InstanceUuid,
     AppState,
 };
 
+use super::util::get_minecraft_instance;
+
 pub async fn get_player_count(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -19,9 +25,11 @@ pub async fn get_player_count(
         .lock()
         .await
         .get(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .get_player_count()
         .await
@@ -37,9 +45,11 @@ pub async fn get_max_player_count(
         .lock()
         .await
         .get(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .get_max_player_count()
         .await
@@ -56,9 +66,11 @@ pub async fn set_max_player_count(
         .lock()
         .await
         .get_mut(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .set_max_player_count(count)
         .await
@@ -74,15 +86,53 @@ pub async fn get_player_list(
         .lock()
         .await
         .get_mut(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .get_player_list()
         .await
         .map(Json)
 }
 
+pub async fn get_player_data(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_uuid)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<PlayerData>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.player_data(&player_uuid).await?))
+}
+
+pub async fn edit_player_data(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_uuid)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(edit): Json<PlayerDataEdit>,
+) -> Result<Json<PlayerData>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    Ok(Json(
+        instance
+            .edit_player_data(&player_uuid, edit, caused_by)
+            .await?,
+    ))
+}
+
 pub fn get_instance_players_routes(state: AppState) -> Router {
     Router::new()
         .route("/instance/:uuid/players/count", get(get_player_count))
@@ -91,5 +141,9 @@ pub fn get_instance_players_routes(state: AppState) -> Router {
             get(get_max_player_count).put(set_max_player_count),
         )
         .route("/instance/:uuid/players", get(get_player_list))
+        .route(
+            "/instance/:uuid/players/:player_uuid/data",
+            get(get_player_data).put(edit_player_data),
+        )
         .with_state(state)
 }
@@ -0,0 +1,103 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    events::CausedBy,
+    implementations::minecraft::pregen::{PregenConfig, PregenInfo},
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::util::get_minecraft_instance;
+
+pub async fn get_pregen(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<PregenInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.pregen_info().await))
+}
+
+pub async fn start_pregen(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<PregenConfig>,
+) -> Result<Json<PregenInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    // the task is driven through console commands
+    state
+        .try_action(&requester, &UserAction::WriteConsole(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    Ok(Json(instance.start_pregen(config, caused_by).await?))
+}
+
+pub async fn pause_pregen(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteConsole(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    instance.set_pregen_paused(true).await?;
+    Ok(Json(()))
+}
+
+pub async fn resume_pregen(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteConsole(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    instance.set_pregen_paused(false).await?;
+    Ok(Json(()))
+}
+
+pub async fn cancel_pregen(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteConsole(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    instance.cancel_pregen().await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_pregen_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/pregen",
+            get(get_pregen).post(start_pregen).delete(cancel_pregen),
+        )
+        .route("/instance/:uuid/pregen/pause", put(pause_pregen))
+        .route("/instance/:uuid/pregen/resume", put(resume_pregen))
+        .with_state(state)
+}
@@ -0,0 +1,48 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    recommendations::{self, Recommendations},
+    types::InstanceUuid,
+    AppState,
+};
+
+pub async fn get_recommendations(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Recommendations>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::MonitorInstance(uuid.clone()))
+        .await?;
+    let mut instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .cloned()
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })?;
+    let view_distance = recommendations::view_distance(&mut instance).await;
+    Ok(Json(
+        state
+            .metrics_history
+            .lock()
+            .await
+            .recommendations(&uuid, view_distance),
+    ))
+}
+
+pub fn get_instance_recommendations_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/recommendations", get(get_recommendations))
+        .with_state(state)
+}
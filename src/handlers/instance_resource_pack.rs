@@ -0,0 +1,227 @@
+use axum::{
+    body::StreamBody,
+    extract::{DefaultBodyLimit, Multipart, Path},
+    routing::get,
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use headers::HeaderName;
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    minecraft::{configurable::ServerPropertySetting, MinecraftInstance},
+    resource_pack::{self, PackWriter, ResourcePack},
+    traits::t_configurable::{manifest::ConfigurableValue, TConfigurable},
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::{global_fs::file_attachment, util::get_minecraft_instance};
+
+fn read_error(e: axum::extract::multipart::MultipartError) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Failed to read upload: {e}"),
+    }
+}
+
+/// Points `server.properties` at the pack, or at nothing
+async fn write_properties(
+    minecraft: &mut MinecraftInstance,
+    url: &str,
+    sha1: &str,
+) -> Result<(), Error> {
+    for (setting_id, value) in [("resource-pack", url), ("resource-pack-sha1", sha1)] {
+        minecraft
+            .update_configurable(
+                ServerPropertySetting::get_section_id(),
+                setting_id,
+                ConfigurableValue::String(value.to_string()),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn get_resource_pack(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<ResourcePack>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    Ok(Json(
+        state
+            .global_settings
+            .lock()
+            .await
+            .instance_resource_pack(&uuid),
+    ))
+}
+
+/// Replaces the instance's pack and points `server.properties` at it.
+///
+/// The multipart body holds the pack as `file`, and as `base_url` the address players reach the
+/// core at, like `https://core.example.com:16662`. Players get the new pack when they next join,
+/// once the server has been restarted to read the properties.
+pub async fn upload_resource_pack(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    mut multipart: Multipart,
+) -> Result<Json<ResourcePack>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let mut minecraft = get_minecraft_instance(&state, &uuid).await?;
+    let id = state
+        .global_settings
+        .lock()
+        .await
+        .instance_resource_pack(&uuid)
+        .map(|pack| pack.id)
+        .unwrap_or_else(ResourcePack::new_id);
+    let mut base_url = None;
+    let mut upload = None;
+    while let Some(mut field) = multipart.next_field().await.map_err(read_error)? {
+        match field.name() {
+            Some("base_url") => base_url = Some(field.text().await.map_err(read_error)?),
+            Some("file") => {
+                let file_name = field.file_name().unwrap_or("pack.zip").to_string();
+                let mut writer = PackWriter::create(&id).await?;
+                loop {
+                    let written = match field.chunk().await {
+                        Ok(Some(chunk)) => writer.write(&chunk).await,
+                        Ok(None) => break,
+                        Err(e) => Err(read_error(e)),
+                    };
+                    if let Err(e) = written {
+                        writer.discard().await;
+                        return Err(e);
+                    }
+                }
+                upload = Some((file_name, writer));
+            }
+            _ => continue,
+        }
+    }
+    let (file_name, writer) = upload.ok_or_else(|| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("No resource pack was uploaded"),
+    })?;
+    let url = match base_url {
+        Some(base_url) => resource_pack::pack_url(&base_url, &id),
+        None => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The address of the core is needed to link to the pack"),
+        }),
+    };
+    let url = match url {
+        Ok(url) => url,
+        Err(e) => {
+            writer.discard().await;
+            return Err(e);
+        }
+    };
+    let (size, sha1) = writer.finish(&id).await?;
+    let pack = ResourcePack {
+        id,
+        file_name,
+        size,
+        sha1,
+        url,
+        uploaded_at: chrono::Utc::now().timestamp(),
+    };
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_resource_pack(uuid, Some(pack.clone()))
+        .await?;
+    write_properties(&mut minecraft, &pack.url, &pack.sha1).await?;
+    Ok(Json(pack))
+}
+
+/// Removes the pack, `server.properties` is cleared if it still points at it
+pub async fn delete_resource_pack(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let mut minecraft = get_minecraft_instance(&state, &uuid).await?;
+    let mut global_settings = state.global_settings.lock().await;
+    let pack = global_settings
+        .instance_resource_pack(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("This instance has no resource pack"),
+        })?;
+    global_settings
+        .set_instance_resource_pack(uuid, None)
+        .await?;
+    drop(global_settings);
+    if let Err(e) = resource_pack::remove_pack(&pack.id).await {
+        warn!("Failed to remove resource pack {}: {e}", pack.id);
+    }
+    let current_url = minecraft
+        .configurable_manifest()
+        .await
+        .get_unique_setting_key("resource-pack")
+        .and_then(|setting| setting.get_value())
+        .and_then(|value| value.try_as_string().ok().cloned());
+    if current_url.as_deref() == Some(pack.url.as_str()) {
+        write_properties(&mut minecraft, "", "").await?;
+    }
+    Ok(Json(()))
+}
+
+/// Needs no sign in, Minecraft clients download the pack from here
+pub async fn download_resource_pack(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+) -> Result<
+    (
+        [(HeaderName, String); 3],
+        StreamBody<ReaderStream<tokio::fs::File>>,
+    ),
+    Error,
+> {
+    let not_found = || Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Resource pack not found"),
+    };
+    if !resource_pack::is_valid_id(&id) {
+        return Err(not_found());
+    }
+    let pack = state
+        .global_settings
+        .lock()
+        .await
+        .resource_pack_by_id(&id)
+        .ok_or_else(not_found)?;
+    file_attachment(&pack.path()).await
+}
+
+pub fn get_instance_resource_pack_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/resource_pack",
+            get(get_resource_pack)
+                .put(upload_resource_pack)
+                .delete(delete_resource_pack),
+        )
+        .layer(DefaultBodyLimit::disable())
+        .route("/resource_packs/:id", get(download_resource_pack))
+        .with_state(state)
+}
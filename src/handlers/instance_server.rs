@@ -1,5 +1,5 @@
 use axum::{
-    extract::Path,
+    extract::{Path, Query},
     routing::{get, post, put},
     Router,
 };
@@ -8,12 +8,16 @@ use axum::Json;
 use axum_auth::AuthBearer;
 
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
 use serde_json::{json, Value};
+use ts_rs::TS;
 
 use crate::{
     auth::user::UserAction,
     error::{Error, ErrorKind},
-    events::CausedBy,
+    events::{CausedBy, Event},
+    i18n::{LocalizedMessage, MessageId},
+    power_schedule::local_now,
     types::InstanceUuid,
 };
 
@@ -22,21 +26,54 @@ use crate::{
     AppState,
 };
 
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct StartQuery {
+    /// Start even though the instance's power schedule doesn't allow it right now
+    #[serde(default)]
+    pub override_schedule: bool,
+}
+
 pub async fn start_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<StartQuery>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::StartInstance(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::StartInstance(uuid.clone()))
+        .await?;
+    let schedule = state
+        .global_settings
+        .lock()
+        .await
+        .instance_power_schedule(&uuid);
+    if let Some(schedule) = schedule {
+        if !schedule.allows_start(local_now()) {
+            if !query.override_schedule {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("The instance's power schedule doesn't allow it to run now"),
+                });
+            }
+            // only those who could change the schedule anyway can get around it
+            state
+                .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+                .await?;
+        }
+    }
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     let mut instance_list = state.instances.lock().await;
-    let instance = instance_list.get_mut(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instance_list.get_mut(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
     let port = instance.port().await;
 
@@ -57,19 +94,24 @@ pub async fn stop_instance(
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::StopInstance(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::StopInstance(uuid.clone()))
+        .await?;
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     state
         .instances
         .lock()
         .await
         .get_mut(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .stop(caused_by, false)
         .await?;
@@ -82,17 +124,23 @@ pub async fn restart_instance(
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester
-        .try_action(&UserAction::StopInstance(uuid.clone()))
-        .and_then(|_x| requester.try_action(&UserAction::StartInstance(uuid.clone())))?;
+    state
+        .try_action(&requester, &UserAction::StopInstance(uuid.clone()))
+        .await?;
+    state
+        .try_action(&requester, &UserAction::StartInstance(uuid.clone()))
+        .await?;
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     let mut instance_list = state.instances.lock().await;
-    let instance = instance_list.get_mut(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
+    let instance = instance_list.get_mut(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
     })?;
 
     instance.restart(caused_by, false).await?;
@@ -105,19 +153,25 @@ pub async fn kill_instance(
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<Value>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::StopInstance(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::StopInstance(uuid.clone()))
+        .await?;
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
+    state.health.lock().await.expect_kill(&uuid);
     state
         .instances
         .lock()
         .await
         .get_mut(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .kill(caused_by)
         .await?;
@@ -131,32 +185,85 @@ pub async fn send_command(
     Json(command): Json<String>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    state
+        .try_action(&requester, &UserAction::WriteConsole(uuid.clone()))
+        .await?;
+    state.check_command(&requester, &uuid, &command).await?;
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     state
         .instances
         .lock()
         .await
         .get_mut(&uuid)
-        .ok_or_else(|| Error {
-            kind: ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .send_command(&command, caused_by)
         .await
         .map(|_| Json(()))
 }
 
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct ConsoleQuery {
+    #[serde(default = "default_console_lines")]
+    pub lines: usize,
+}
+
+fn default_console_lines() -> usize {
+    500
+}
+
+/// Recent console lines from memory, for filling a console view as it opens
+pub async fn get_console_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<ConsoleQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<Event>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    let history = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })?
+        .console_history(query.lines)
+        .await;
+    let access = state.all_instance_access().await;
+    Ok(Json(
+        history
+            .into_iter()
+            .filter(|event| requester.can_view_event(event, &access))
+            .collect(),
+    ))
+}
+
 pub async fn get_instance_state(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<Value>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    if !requester.can_perform_action(&UserAction::ViewInstance(uuid.clone())) {
+    if !state
+        .can_perform_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await
+    {
         return Err(Error {
             kind: ErrorKind::PermissionDenied,
             source: eyre!("You don't have permission to view this instance"),
@@ -168,10 +275,10 @@ pub async fn get_instance_state(
             .lock()
             .await
             .get(&uuid)
-            .ok_or_else(|| Error {
-                kind: ErrorKind::NotFound,
-                source: eyre!("Instance not found"),
-            })?
+            .ok_or_else(|| Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound)
+            ))?
             .state()
             .await
     )))
@@ -183,7 +290,10 @@ pub fn get_instance_server_routes(state: AppState) -> Router {
         .route("/instance/:uuid/stop", put(stop_instance))
         .route("/instance/:uuid/restart", put(restart_instance))
         .route("/instance/:uuid/kill", put(kill_instance))
-        .route("/instance/:uuid/console", post(send_command))
+        .route(
+            "/instance/:uuid/console",
+            get(get_console_history).post(send_command),
+        )
         .route("/instance/:uuid/state", get(get_instance_state))
         .with_state(state)
 }
@@ -1,5 +1,6 @@
 use crate::error::Error;
 use crate::error::ErrorKind;
+use crate::handlers::util::etag_cache;
 use crate::implementations::generic;
 use crate::implementations::minecraft;
 use crate::minecraft::FlavourKind;
@@ -75,7 +76,8 @@ pub async fn get_setup_manifest(
         .map(Json)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS)]
+#[ts(export)]
 pub struct GenericSetupManifestBody {
     pub url: String,
 }
@@ -93,6 +95,7 @@ pub fn get_instance_setup_config_routes(appstate: AppState) -> Router {
     Router::new()
         .route("/games", get(get_available_games))
         .route("/setup_manifest/:game_type", get(get_setup_manifest))
+        .route_layer(axum::middleware::from_fn(etag_cache))
         .route("/generic_setup_manifest", put(get_generic_setup_manifest))
         .with_state(appstate)
 }
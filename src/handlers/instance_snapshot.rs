@@ -0,0 +1,562 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use std::path::PathBuf;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use tracing::error;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    disk_space::{self, DiskSpaceConfig},
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, ProgressionEndValue},
+    i18n::{LocalizedMessage, MessageId},
+    implementations::minecraft::MinecraftInstance,
+    prelude::{path_to_snapshots, GameInstance},
+    remote_backup::{self, RemoteBackupInfo, RemoteBackupSettings},
+    snapshot::{self, SnapshotInfo, VerificationStatus},
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    types::{DotLodestoneConfig, InstanceUuid, Snowflake},
+    AppState,
+};
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewSnapshot {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+pub async fn list_snapshots(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<SnapshotInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    if !state.instances.lock().await.contains_key(&uuid) {
+        return Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ));
+    }
+    Ok(Json(snapshot::list_snapshots(&uuid).await?))
+}
+
+pub async fn create_snapshot(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(new_snapshot): Json<NewSnapshot>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    if new_snapshot.name.trim().is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Snapshot name cannot be empty"),
+        });
+    }
+    if state.alerts.lock().await.backups_paused(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Backups of this instance are paused by a firing alert"),
+        });
+    }
+    let instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })?
+        .clone();
+    let (remote_backup, disk_space) = {
+        let global_settings = state.global_settings.lock().await;
+        (
+            global_settings
+                .remote_backup(&uuid)
+                .filter(|settings| settings.upload_snapshots),
+            global_settings.disk_space(),
+        )
+    };
+    let event_broadcaster = state.event_broadcaster.clone();
+    tokio::spawn(async move {
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!("Creating snapshot {}", new_snapshot.name),
+            None,
+            None,
+            CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+                impersonated_by: requester.impersonated_by.clone(),
+            },
+        );
+        event_broadcaster.send(progression_start_event);
+
+        let instance_path = instance.path().await;
+        // a plain copy is assumed, the snapshot takes less room if files can be cloned
+        let required = disk_space::dir_size(instance_path.clone()).await;
+        if let Err(e) = disk_space::ensure_space(
+            &disk_space,
+            path_to_snapshots(),
+            required,
+            Some(&uuid),
+            None,
+        )
+        .await
+        {
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Failed to create snapshot: {e}")),
+                None,
+            ));
+            return;
+        }
+
+        // a running minecraft server keeps writing to its world, hold that off while copying
+        let held_minecraft = match &instance {
+            GameInstance::MinecraftInstance(minecraft)
+                if instance.state().await != State::Stopped =>
+            {
+                match minecraft.suspend_saves().await {
+                    Ok(()) => Some(minecraft.clone()),
+                    Err(e) => {
+                        // copying while the server writes chunks would likely give a corrupt world
+                        event_broadcaster.send(Event::new_progression_event_end(
+                            event_id,
+                            false,
+                            Some(&format!("Failed to create snapshot: {e}")),
+                            None,
+                        ));
+                        return;
+                    }
+                }
+            }
+            _ => None,
+        };
+        let result = snapshot::create_snapshot(
+            instance_path,
+            uuid.clone(),
+            new_snapshot.name.clone(),
+            new_snapshot.description,
+        )
+        .await;
+        if let Some(minecraft) = held_minecraft {
+            if let Err(e) = minecraft.resume_saves().await {
+                error!("Failed to resume saves after snapshot, run save-on manually: {e}");
+            }
+        }
+        match result {
+            Ok(info) => {
+                let snapshot_id = info.id;
+                event_broadcaster.send(Event::new_progression_event_end(
+                    event_id,
+                    true,
+                    Some(&format!("Created snapshot {}", new_snapshot.name)),
+                    Some(ProgressionEndValue::SnapshotCreated(info)),
+                ));
+                if let Some(settings) = remote_backup {
+                    upload(
+                        &event_broadcaster,
+                        &settings,
+                        &disk_space,
+                        uuid,
+                        snapshot_id,
+                        new_snapshot.name,
+                        CausedBy::User {
+                            user_id: requester.uid.clone(),
+                            user_name: requester.username.clone(),
+                            impersonated_by: requester.impersonated_by.clone(),
+                        },
+                    )
+                    .await;
+                }
+            }
+            Err(e) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Failed to create snapshot: {e}")),
+                None,
+            )),
+        }
+    });
+    Ok(Json(()))
+}
+
+async fn upload(
+    event_broadcaster: &EventBroadcaster,
+    settings: &RemoteBackupSettings,
+    disk_space: &DiskSpaceConfig,
+    uuid: InstanceUuid,
+    snapshot_id: Snowflake,
+    snapshot_name: String,
+    caused_by: CausedBy,
+) {
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        format!("Uploading snapshot {snapshot_name}"),
+        None,
+        None,
+        caused_by,
+    );
+    event_broadcaster.send(progression_start_event);
+    match remote_backup::upload_snapshot(settings, disk_space, &uuid, snapshot_id).await {
+        Ok(_) => event_broadcaster.send(Event::new_progression_event_end(
+            event_id,
+            true,
+            Some(&format!("Uploaded snapshot {snapshot_name}")),
+            None,
+        )),
+        Err(e) => event_broadcaster.send(Event::new_progression_event_end(
+            event_id,
+            false,
+            Some(&format!("Failed to upload snapshot: {e}")),
+            None,
+        )),
+    }
+}
+
+async fn remote_backup_settings(
+    state: &AppState,
+    uuid: &InstanceUuid,
+) -> Result<RemoteBackupSettings, Error> {
+    if !state.instances.lock().await.contains_key(uuid) {
+        return Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        ));
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .remote_backup(uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("No remote backup storage is configured for this instance"),
+        })
+}
+
+pub async fn upload_snapshot(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, snapshot_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let settings = remote_backup_settings(&state, &uuid).await?;
+    let snapshot = snapshot::get_snapshot(&uuid, snapshot_id).await?;
+    let disk_space = state.global_settings.lock().await.disk_space();
+    tokio::spawn(async move {
+        upload(
+            &state.event_broadcaster,
+            &settings,
+            &disk_space,
+            uuid,
+            snapshot_id,
+            snapshot.name,
+            CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+                impersonated_by: requester.impersonated_by.clone(),
+            },
+        )
+        .await;
+    });
+    Ok(Json(()))
+}
+
+pub async fn list_remote_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<RemoteBackupInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let settings = remote_backup_settings(&state, &uuid).await?;
+    Ok(Json(
+        remote_backup::list_remote_backups(&settings, &uuid).await?,
+    ))
+}
+
+/// Fetches a remote backup back as a local snapshot, to be restored like any other
+pub async fn download_remote_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, snapshot_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    let settings = remote_backup_settings(&state, &uuid).await?;
+    let disk_space = state.global_settings.lock().await.disk_space();
+    let event_broadcaster = state.event_broadcaster.clone();
+    tokio::spawn(async move {
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            "Downloading remote backup",
+            None,
+            None,
+            CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+                impersonated_by: requester.impersonated_by.clone(),
+            },
+        );
+        event_broadcaster.send(progression_start_event);
+        match remote_backup::download_backup(&settings, &disk_space, &uuid, snapshot_id).await {
+            Ok(info) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some(&format!("Downloaded snapshot {}", info.name)),
+                Some(ProgressionEndValue::SnapshotCreated(info)),
+            )),
+            Err(e) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Failed to download remote backup: {e}")),
+                None,
+            )),
+        }
+    });
+    Ok(Json(()))
+}
+
+pub async fn delete_remote_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, snapshot_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    let settings = remote_backup_settings(&state, &uuid).await?;
+    remote_backup::delete_remote_backup(&settings, &uuid, snapshot_id).await?;
+    Ok(Json(()))
+}
+
+pub async fn restore_snapshot(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, snapshot_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    let snapshot = snapshot::get_snapshot(&uuid, snapshot_id).await?;
+    let mut instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    if instance.state().await != State::Stopped {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance must be stopped before restoring a snapshot"),
+        });
+    }
+    // taken out of the list so it cannot be started while its files are being swapped
+    let instance = instances.remove(&uuid).unwrap();
+    drop(instances);
+
+    let disk_space = state.global_settings.lock().await.disk_space();
+    let event_broadcaster = state.event_broadcaster.clone();
+    tokio::spawn(async move {
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!("Restoring snapshot {}", snapshot.name),
+            None,
+            None,
+            CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+                impersonated_by: requester.impersonated_by.clone(),
+            },
+        );
+        event_broadcaster.send(progression_start_event);
+        let path = instance.path().await;
+        let result = match disk_space::ensure_space(
+            &disk_space,
+            &path,
+            snapshot.size,
+            Some(&uuid),
+            Some(snapshot_id),
+        )
+        .await
+        {
+            Ok(()) => snapshot::restore_snapshot(path.clone(), &uuid, snapshot_id).await,
+            Err(e) => Err(e),
+        };
+        // the instance keeps its configuration in memory, reload it from the restored files
+        let instance = match (&result, &instance) {
+            (Ok(()), GameInstance::MinecraftInstance(_)) => {
+                match reload_minecraft_instance(path, &state).await {
+                    Ok(restored) => restored.into(),
+                    Err(e) => {
+                        error!("Failed to reload instance after restoring snapshot: {e}");
+                        instance
+                    }
+                }
+            }
+            _ => instance,
+        };
+        state.instances.lock().await.insert(uuid.clone(), instance);
+        match result {
+            Ok(()) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some(&format!("Restored snapshot {}", snapshot.name)),
+                Some(ProgressionEndValue::SnapshotRestored {
+                    instance_uuid: uuid,
+                    snapshot_id,
+                }),
+            )),
+            Err(e) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Failed to restore snapshot: {e}")),
+                None,
+            )),
+        }
+    });
+    Ok(Json(()))
+}
+
+pub async fn verify_snapshot(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, snapshot_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let snapshot = snapshot::get_snapshot(&uuid, snapshot_id).await?;
+    let event_broadcaster = state.event_broadcaster.clone();
+    tokio::spawn(async move {
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!("Verifying snapshot {}", snapshot.name),
+            None,
+            None,
+            CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+                impersonated_by: requester.impersonated_by.clone(),
+            },
+        );
+        event_broadcaster.send(progression_start_event);
+        match snapshot::verify_snapshot(&uuid, snapshot_id).await {
+            Ok(info) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some(&match &info.verification {
+                    VerificationStatus::Corrupt { problems, .. } => {
+                        format!("Snapshot {} is corrupt: {}", info.name, problems.join(", "))
+                    }
+                    _ => format!("Snapshot {} is intact", info.name),
+                }),
+                Some(ProgressionEndValue::SnapshotVerified(info)),
+            )),
+            Err(e) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Failed to verify snapshot: {e}")),
+                None,
+            )),
+        }
+    });
+    Ok(Json(()))
+}
+
+/// Builds the instance again from its files, for after they changed on disk
+pub(crate) async fn reload_minecraft_instance(
+    path: PathBuf,
+    state: &AppState,
+) -> Result<MinecraftInstance, Error> {
+    let dot_lodestone_config: DotLodestoneConfig = serde_json::from_slice(
+        &tokio::fs::read(path.join(".lodestone_config"))
+            .await
+            .context("Failed to read .lodestone_config file")?,
+    )
+    .context("Failed to parse .lodestone_config file")?;
+    MinecraftInstance::restore(
+        path,
+        dot_lodestone_config,
+        state.event_broadcaster.clone(),
+        state.macro_executor.clone(),
+    )
+    .await
+}
+
+pub async fn delete_snapshot(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, snapshot_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+        .await?;
+    snapshot::delete_snapshot(&uuid, snapshot_id).await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_snapshot_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/snapshots",
+            get(list_snapshots).post(create_snapshot),
+        )
+        .route(
+            "/instance/:uuid/snapshots/:snapshot_id",
+            delete(delete_snapshot),
+        )
+        .route(
+            "/instance/:uuid/snapshots/:snapshot_id/restore",
+            put(restore_snapshot),
+        )
+        .route(
+            "/instance/:uuid/snapshots/:snapshot_id/verify",
+            put(verify_snapshot),
+        )
+        .route(
+            "/instance/:uuid/snapshots/:snapshot_id/upload",
+            put(upload_snapshot),
+        )
+        .route("/instance/:uuid/remote_backups", get(list_remote_backups))
+        .route(
+            "/instance/:uuid/remote_backups/:snapshot_id",
+            delete(delete_remote_backup),
+        )
+        .route(
+            "/instance/:uuid/remote_backups/:snapshot_id/download",
+            put(download_remote_backup),
+        )
+        .with_state(state)
+}
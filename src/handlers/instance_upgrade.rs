@@ -0,0 +1,198 @@
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, ProgressionStartValue},
+    implementations::minecraft::upgrade::UpgradeReport,
+    snapshot,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+use super::util::get_minecraft_instance;
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct UpgradeRequest {
+    pub version: String,
+    /// The `report_id` of the report the user agreed to
+    pub report_id: String,
+    /// Upgrade even though some addons are incompatible with the new version
+    #[serde(default)]
+    pub allow_blockers: bool,
+    /// Don't take a snapshot of the instance first
+    #[serde(default)]
+    pub skip_snapshot: bool,
+}
+
+pub async fn get_upgrade_report(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, version)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<UpgradeReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let instance = get_minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.upgrade_report(&version).await?))
+}
+
+/// Starts the upgrade, returning the id of its job
+pub async fn upgrade_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<UpgradeRequest>,
+) -> Result<Json<Snowflake>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let mut instance = get_minecraft_instance(&state, &uuid).await?;
+    if instance.state().await != State::Stopped {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Stop the server before upgrading it"),
+        });
+    }
+    // the addons may have changed since the user looked at the report
+    let report = instance.upgrade_report(&request.version).await?;
+    if report.report_id != request.report_id {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The compatibility report is out of date, review it again"),
+        });
+    }
+    if report.blockers > 0 && !request.allow_blockers {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "{} addon(s) are incompatible with {}",
+                report.blockers,
+                request.version
+            ),
+        });
+    }
+
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        format!(
+            "Upgrading {} from {} to {}",
+            instance.name().await,
+            report.current_version,
+            request.version
+        ),
+        Some(2.0),
+        Some(ProgressionStartValue::InstanceUpgrade {
+            instance_uuid: uuid.clone(),
+            target_version: request.version.clone(),
+        }),
+        CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+            impersonated_by: requester.impersonated_by.clone(),
+        },
+    );
+    let job_id = event_id.snowflake();
+    let event_broadcaster = state.event_broadcaster.clone();
+    event_broadcaster.send(progression_start_event);
+    tokio::spawn(async move {
+        if !request.skip_snapshot {
+            event_broadcaster.send(Event::new_progression_event_update(
+                &event_id,
+                "Taking a snapshot",
+                0.0,
+            ));
+            if let Err(e) = snapshot::create_snapshot(
+                instance.path().await,
+                uuid.clone(),
+                format!("Before upgrading to {}", request.version),
+                Some(format!("Automatic snapshot of {}", report.current_version)),
+            )
+            .await
+            {
+                event_broadcaster.send(Event::new_progression_event_end(
+                    event_id,
+                    false,
+                    Some(&format!("Failed to take a snapshot: {e}")),
+                    None,
+                ));
+                return;
+            }
+        }
+        event_broadcaster.send(Event::new_progression_event_update(
+            &event_id,
+            "Downloading the new server jar",
+            1.0,
+        ));
+        match instance.change_version(request.version.clone()).await {
+            Ok(()) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some(&format!("Upgraded to {}", request.version)),
+                None,
+            )),
+            Err(e) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Failed to upgrade: {e}")),
+                None,
+            )),
+        }
+    });
+    Ok(Json(job_id))
+}
+
+/// Starts the server with its world being converted to the server's version, returning the id
+/// of the job following the conversion
+pub async fn upgrade_world(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Snowflake>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::StartInstance(uuid.clone()))
+        .await?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    let mut instance = get_minecraft_instance(&state, &uuid).await?;
+    if instance.state().await != State::Stopped {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Stop the server before upgrading its world"),
+        });
+    }
+    // an upgrade that fails has nothing to do with the settings, and shouldn't run again
+    state.startup_watchdog.lock().await.leave_next_start(&uuid);
+    let job_id = instance
+        .start_world_upgrade(CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+            impersonated_by: requester.impersonated_by.clone(),
+        })
+        .await?;
+    Ok(Json(job_id))
+}
+
+pub fn get_instance_upgrade_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/upgrade", post(upgrade_instance))
+        .route("/instance/:uuid/upgrade_world", post(upgrade_world))
+        .route("/instance/:uuid/upgrade/:version", get(get_upgrade_report))
+        .with_state(state)
+}
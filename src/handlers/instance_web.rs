@@ -0,0 +1,216 @@
+use axum::{
+    body::Bytes,
+    extract::{OriginalUri, Path},
+    http::{header, HeaderMap, Method},
+    response::{Redirect, Response},
+    routing::{any, get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    web_proxy::{self, WebProxySettings, WebSessionInfo},
+    AppState,
+};
+
+pub async fn get_web_proxy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<WebProxySettings>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::AccessSetting(uuid.clone()))
+        .await?;
+    Ok(Json(
+        state.global_settings.lock().await.instance_web_proxy(&uuid),
+    ))
+}
+
+/// `null` removes the proxy.
+///
+/// Owners and admins only, as the proxy reaches any local port, including the core's own API and
+/// other services on the host.
+pub async fn set_web_proxy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(settings): Json<Option<WebProxySettings>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    let instance_port = match state.instances.lock().await.get(&uuid) {
+        Some(instance) => instance.port().await,
+        None => {
+            return Err(Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            ))
+        }
+    };
+    if let Some(settings) = &settings {
+        settings.validate()?;
+        if settings.port == instance_port {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Port {} is the game port of the instance", settings.port),
+            });
+        }
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_web_proxy(uuid, settings)
+        .await?;
+    Ok(Json(()))
+}
+
+/// A key to open the instance's web pages with in a browser, as `web/?session=<key>`
+pub async fn create_web_session(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<WebSessionInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+    Ok(Json(
+        state.web_sessions.lock().await.create(requester.uid, uuid),
+    ))
+}
+
+/// The page's relative links only resolve under a trailing slash
+pub async fn redirect_web_root(OriginalUri(uri): OriginalUri) -> Redirect {
+    match uri.query() {
+        Some(query) => Redirect::permanent(&format!("{}/?{query}", uri.path())),
+        None => Redirect::permanent(&format!("{}/", uri.path())),
+    }
+}
+
+pub async fn proxy_web_root(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Error> {
+    proxy_web(state, uuid, "", uri, method, headers, body).await
+}
+
+pub async fn proxy_web_path(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, path)): Path<(InstanceUuid, String)>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Error> {
+    proxy_web(state, uuid, &path, uri, method, headers, body).await
+}
+
+async fn proxy_web(
+    state: AppState,
+    uuid: InstanceUuid,
+    path: &str,
+    uri: axum::http::Uri,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Error> {
+    let unauthorized = || {
+        Error::localized(
+            ErrorKind::Unauthorized,
+            LocalizedMessage::new(MessageId::Unauthorized),
+        )
+    };
+    let (query_session, query) = web_proxy::take_session_query(uri.query());
+    let (cookie_session, cookies) = web_proxy::take_session_cookie(&headers);
+
+    if let Some(session) = query_session {
+        // the session moves into a cookie, so it doesn't stay in the address bar
+        state
+            .web_sessions
+            .lock()
+            .await
+            .resolve(&session, &uuid)
+            .ok_or_else(unauthorized)?;
+        let marker = format!("/instance/{uuid}/web");
+        let cookie_path = match uri.path().find(&marker) {
+            Some(i) => &uri.path()[..i + marker.len()],
+            None => uri.path(),
+        };
+        let location = match &query {
+            Some(query) => format!("{}?{query}", uri.path()),
+            None => uri.path().to_string(),
+        };
+        return web_proxy::redirect_with_session(
+            &location,
+            &web_proxy::session_cookie(&session, cookie_path),
+        );
+    }
+
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let requester = if let Some(token) = bearer {
+        state.users_manager.read().await.try_auth_or_err(token)?
+    } else {
+        let uid = match cookie_session {
+            Some(session) => state.web_sessions.lock().await.resolve(&session, &uuid),
+            None => None,
+        }
+        .ok_or_else(unauthorized)?;
+        state
+            .users_manager
+            .read()
+            .await
+            .get_user(&uid)
+            .ok_or_else(unauthorized)?
+    };
+    state
+        .try_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+        .await?;
+
+    let settings = state
+        .global_settings
+        .lock()
+        .await
+        .instance_web_proxy(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("No web proxy is set up for this instance"),
+        })?;
+    web_proxy::forward(
+        settings.port,
+        method,
+        path,
+        query.as_deref(),
+        headers,
+        cookies,
+        body,
+    )
+    .await
+}
+
+pub fn get_instance_web_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/web_proxy",
+            get(get_web_proxy).put(set_web_proxy),
+        )
+        .route("/instance/:uuid/web_session", post(create_web_session))
+        .route("/instance/:uuid/web", get(redirect_web_root))
+        .route("/instance/:uuid/web/", any(proxy_web_root))
+        .route("/instance/:uuid/web/*path", any(proxy_web_path))
+        .with_state(state)
+}
@@ -0,0 +1,324 @@
+use std::path::PathBuf;
+
+use axum::{
+    body::{boxed, Body},
+    extract::{OriginalUri, Path},
+    http::{header, HeaderValue, Request, Uri},
+    response::{IntoResponse, Response},
+    routing::any,
+    Router,
+};
+use dav_server::{
+    davpath::DavPath,
+    fakels::FakeLs,
+    fs::{
+        DavDirEntry, DavFile, DavFileSystem, DavMetaData, FsError, FsFuture, FsStream, OpenOptions,
+        ReadDirMeta,
+    },
+    localfs::LocalFs,
+    DavHandler,
+};
+use futures::{future, FutureExt};
+use headers::{
+    authorization::{Basic, Bearer},
+    Authorization, HeaderMapExt,
+};
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{new_fs_event, CausedBy, FSOperation, FSTarget},
+    i18n::{LocalizedMessage, MessageId},
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    util::scoped_join_win_safe,
+    AppState,
+};
+
+use super::instance_fs::is_path_protected;
+
+/// The instance directory as seen over WebDAV, refusing writes the REST fs routes would refuse
+#[derive(Clone)]
+struct InstanceDavFs {
+    inner: Box<LocalFs>,
+    root: PathBuf,
+    can_write: bool,
+    can_write_protected: bool,
+    event_broadcaster: EventBroadcaster,
+    caused_by: CausedBy,
+}
+
+impl InstanceDavFs {
+    /// Where `path` is on disk, refusing anything reached through a symlink
+    ///
+    /// `LocalFs` follows symlinks wherever they point, unlike the REST fs routes which keep
+    /// them inside the instance directory
+    fn resolve(&self, path: &DavPath) -> Result<PathBuf, FsError> {
+        let joined = self.root.join(path.as_rel_ospath());
+        match scoped_join_win_safe(&self.root, path.as_rel_ospath()) {
+            Ok(resolved) if resolved == joined => Ok(joined),
+            _ => Err(FsError::Forbidden),
+        }
+    }
+
+    fn check_write(&self, path: &DavPath) -> Result<PathBuf, FsError> {
+        let resolved = self.resolve(path)?;
+        if !self.can_write || (!self.can_write_protected && is_path_protected(&resolved)) {
+            return Err(FsError::Forbidden);
+        }
+        Ok(resolved)
+    }
+
+    /// Sends the same event the REST fs routes would once `future` succeeds
+    fn notify<'a, T: Send + 'a>(
+        &'a self,
+        future: FsFuture<'a, T>,
+        operation: FSOperation,
+        target: FSTarget,
+    ) -> FsFuture<'a, T> {
+        future
+            .map(move |result| {
+                if result.is_ok() {
+                    self.event_broadcaster.send(new_fs_event(
+                        operation,
+                        target,
+                        self.caused_by.clone(),
+                    ));
+                }
+                result
+            })
+            .boxed()
+    }
+}
+
+impl DavFileSystem for InstanceDavFs {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        if options.write
+            || options.append
+            || options.truncate
+            || options.create
+            || options.create_new
+        {
+            return match self.check_write(path) {
+                Ok(resolved) => self.notify(
+                    self.inner.open(path, options),
+                    FSOperation::Write,
+                    FSTarget::File(resolved),
+                ),
+                Err(e) => future::ready(Err(e)).boxed(),
+            };
+        }
+        if let Err(e) = self.resolve(path) {
+            return future::ready(Err(e)).boxed();
+        }
+        self.inner.open(path, options)
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        if let Err(e) = self.resolve(path) {
+            return future::ready(Err(e)).boxed();
+        }
+        self.inner.read_dir(path, meta)
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        if let Err(e) = self.resolve(path) {
+            return future::ready(Err(e)).boxed();
+        }
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        if let Err(e) = self.resolve(path) {
+            return future::ready(Err(e)).boxed();
+        }
+        self.inner.symlink_metadata(path)
+    }
+
+    // like the mkdir route, creating directories only needs write access
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        let resolved = match self.resolve(path) {
+            Ok(resolved) if self.can_write => resolved,
+            Ok(_) => return future::ready(Err(FsError::Forbidden)).boxed(),
+            Err(e) => return future::ready(Err(e)).boxed(),
+        };
+        self.notify(
+            self.inner.create_dir(path),
+            FSOperation::Create,
+            FSTarget::Directory(resolved),
+        )
+    }
+
+    // the files in it are removed one by one first, each going through `remove_file`
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        let resolved = match self.resolve(path) {
+            Ok(resolved) if self.can_write => resolved,
+            Ok(_) => return future::ready(Err(FsError::Forbidden)).boxed(),
+            Err(e) => return future::ready(Err(e)).boxed(),
+        };
+        self.notify(
+            self.inner.remove_dir(path),
+            FSOperation::Delete,
+            FSTarget::Directory(resolved),
+        )
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        match self.check_write(path) {
+            Ok(resolved) => self.notify(
+                self.inner.remove_file(path),
+                FSOperation::Delete,
+                FSTarget::File(resolved),
+            ),
+            Err(e) => future::ready(Err(e)).boxed(),
+        }
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        match self
+            .check_write(from)
+            .and_then(|from| Ok((from, self.check_write(to)?)))
+        {
+            Ok((from_resolved, to_resolved)) => self.notify(
+                self.inner.rename(from, to),
+                FSOperation::Move {
+                    source: from_resolved,
+                },
+                FSTarget::File(to_resolved),
+            ),
+            Err(e) => future::ready(Err(e)).boxed(),
+        }
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        match self.resolve(from).and_then(|_| self.check_write(to)) {
+            Ok(resolved) => self.notify(
+                self.inner.copy(from, to),
+                FSOperation::Create,
+                FSTarget::File(resolved),
+            ),
+            Err(e) => future::ready(Err(e)).boxed(),
+        }
+    }
+
+    fn get_quota(&self) -> FsFuture<'_, (u64, Option<u64>)> {
+        self.inner.get_quota()
+    }
+}
+
+pub async fn webdav_root(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    OriginalUri(uri): OriginalUri,
+    request: Request<Body>,
+) -> Result<Response, Error> {
+    webdav(state, uuid, uri, request).await
+}
+
+pub async fn webdav_path(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, _)): Path<(InstanceUuid, String)>,
+    OriginalUri(uri): OriginalUri,
+    request: Request<Body>,
+) -> Result<Response, Error> {
+    webdav(state, uuid, uri, request).await
+}
+
+/// Serves the instance directory over WebDAV, to be mounted as a network drive.
+///
+/// Clients authenticate with HTTP basic auth using a Lodestone token as the password, the
+/// username is ignored. Windows only sends basic auth over HTTPS unless told otherwise
+async fn webdav(
+    state: AppState,
+    uuid: InstanceUuid,
+    uri: Uri,
+    request: Request<Body>,
+) -> Result<Response, Error> {
+    let token = match request.headers().typed_get::<Authorization<Basic>>() {
+        Some(Authorization(basic)) => Some(basic.password().to_string()),
+        None => request
+            .headers()
+            .typed_get::<Authorization<Bearer>>()
+            .map(|Authorization(bearer)| bearer.token().to_string()),
+    };
+    let requester = match token {
+        Some(token) => state.users_manager.read().await.try_auth(&token),
+        None => None,
+    };
+    let requester = match requester {
+        Some(requester) => requester,
+        // prompts the client for credentials
+        None => {
+            let mut response = Error::localized(
+                ErrorKind::Unauthorized,
+                LocalizedMessage::new(MessageId::Unauthorized),
+            )
+            .into_response();
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"Lodestone\""),
+            );
+            return Ok(response);
+        }
+    };
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let root = match state.instances.lock().await.get(&uuid) {
+        Some(instance) => instance.path().await,
+        None => {
+            return Err(Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            ))
+        }
+    };
+    let fs = InstanceDavFs {
+        inner: LocalFs::new(&root, false, false, false),
+        root,
+        can_write: state
+            .can_perform_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+            .await,
+        can_write_protected: requester.can_perform_action(&UserAction::WriteGlobalFile),
+        event_broadcaster: state.event_broadcaster.clone(),
+        caused_by: CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+            impersonated_by: requester.impersonated_by.clone(),
+        },
+    };
+
+    // links in the responses have to point at what the client asked for, not the nested path
+    let marker = format!("/instance/{uuid}/dav");
+    let prefix = match uri.path().find(&marker) {
+        Some(i) => &uri.path()[..i + marker.len()],
+        None => marker.as_str(),
+    };
+    let handler = DavHandler::builder()
+        .filesystem(Box::new(fs))
+        .locksystem(FakeLs::new())
+        .strip_prefix(prefix)
+        .build_handler();
+    let (mut parts, body) = request.into_parts();
+    parts.uri = uri;
+    Ok(handler
+        .handle(Request::from_parts(parts, body))
+        .await
+        .map(boxed))
+}
+
+pub fn get_instance_webdav_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/dav", any(webdav_root))
+        .route("/instance/:uuid/dav/*path", any(webdav_path))
+        .with_state(state)
+}
@@ -0,0 +1,168 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    auth::{
+        hashed_password::PasswordHashing, invite::Invite, permission::UserPermission, user::User,
+    },
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    AppState,
+};
+
+use super::users::{LoginReply, NewUser};
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewInvite {
+    pub is_admin: bool,
+    pub permissions: UserPermission,
+    /// Seconds until the invite expires, `None` for never
+    pub expires_in: Option<i64>,
+    pub max_uses: Option<u32>,
+}
+
+fn ensure_can_manage_invites(requester: &User) -> Result<(), Error> {
+    if requester.is_owner || requester.is_admin {
+        Ok(())
+    } else {
+        Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to manage invites"),
+        })
+    }
+}
+
+pub async fn create_invite(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(new_invite): Json<NewInvite>,
+) -> Result<Json<Invite>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_not_impersonated()?;
+    ensure_can_manage_invites(&requester)?;
+    // an invite can't hand out more than its creator could grant directly
+    let mut invitee = User::new(
+        String::new(),
+        "",
+        false,
+        new_invite.is_admin,
+        UserPermission::default(),
+        &PasswordHashing::default(),
+    );
+    requester.update_permission(&mut invitee, new_invite.permissions.clone())?;
+    if matches!(new_invite.expires_in, Some(expires_in) if expires_in <= 0) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invites must expire in the future"),
+        });
+    }
+    let invite = Invite::new(
+        requester.uid,
+        new_invite.is_admin,
+        new_invite.permissions,
+        new_invite
+            .expires_in
+            .map(|expires_in| chrono::Utc::now().timestamp().saturating_add(expires_in)),
+        new_invite.max_uses,
+    );
+    state
+        .invites_manager
+        .lock()
+        .await
+        .add(invite.clone())
+        .await?;
+    Ok(Json(invite))
+}
+
+pub async fn get_invites(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<Invite>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    ensure_can_manage_invites(&requester)?;
+    Ok(Json(state.invites_manager.lock().await.list()))
+}
+
+pub async fn delete_invite(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(code): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    ensure_can_manage_invites(&requester)?;
+    state
+        .invites_manager
+        .lock()
+        .await
+        .delete(&code)
+        .await?
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Invite not found"),
+        })?;
+    Ok(Json(()))
+}
+
+/// Lets a client check the code before asking for a username and password
+pub async fn verify_invite(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(code): Path<String>,
+) -> Result<Json<()>, Error> {
+    state.invites_manager.lock().await.get_redeemable(&code)?;
+    Ok(Json(()))
+}
+
+pub async fn redeem_invite(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(code): Path<String>,
+    Json(config): Json<NewUser>,
+) -> Result<Json<LoginReply>, Error> {
+    // held until the use is recorded so the invite can't be redeemed past its limit
+    let mut invites_manager = state.invites_manager.lock().await;
+    let invite = invites_manager.get_redeemable(&code)?;
+    let mut users_manager = state.users_manager.write().await;
+    let user = User::new(
+        config.username,
+        config.password,
+        false,
+        invite.is_admin,
+        invite.permissions,
+        &users_manager.password_hashing(),
+    );
+    let caused_by = CausedBy::User {
+        user_id: user.uid.clone(),
+        user_name: user.username.clone(),
+        impersonated_by: None,
+    };
+    users_manager.add_user(user.clone(), caused_by).await?;
+    drop(users_manager);
+    if let Err(e) = invites_manager.mark_redeemed(&code, user.uid.clone()).await {
+        warn!(
+            "Failed to record that {} redeemed invite: {e}",
+            user.username
+        );
+    }
+    Ok(Json(LoginReply {
+        token: user.create_jwt()?,
+        user: user.into(),
+    }))
+}
+
+pub fn get_invite_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/invite", post(create_invite))
+        .route("/invite/list", get(get_invites))
+        .route("/invite/:code", delete(delete_invite))
+        .route("/invite/:code/verify", get(verify_invite))
+        .route("/invite/:code/redeem", post(redeem_invite))
+        .with_state(state)
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::{
+        instance_access::InstanceAccess,
+        user::{User, UserAction},
+    },
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    jobs::Job,
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+/// Users see the jobs they started and those of instances they can view
+fn can_view_job(
+    requester: &User,
+    job: &Job,
+    access: &HashMap<InstanceUuid, InstanceAccess>,
+) -> bool {
+    requester.is_owner
+        || requester.is_admin
+        || matches!(&job.caused_by, CausedBy::User { user_id, .. } if *user_id == requester.uid)
+        || job.instance_uuid.as_ref().map_or(false, |uuid| {
+            requester.can_perform_action_with(&UserAction::ViewInstance(uuid.clone()), access)
+        })
+}
+
+pub async fn get_jobs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<Job>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let access = state.all_instance_access().await;
+    Ok(Json(
+        state
+            .jobs
+            .lock()
+            .await
+            .list()
+            .into_iter()
+            .filter(|job| can_view_job(&requester, job, &access))
+            .collect(),
+    ))
+}
+
+pub async fn get_job(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Job>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let access = state.all_instance_access().await;
+    state
+        .jobs
+        .lock()
+        .await
+        .get(&id)
+        .filter(|job| can_view_job(&requester, job, &access))
+        .map(Json)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Job not found"),
+        })
+}
+
+pub fn get_jobs_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/jobs", get(get_jobs))
+        .route("/jobs/:id", get(get_job))
+        .with_state(state)
+}
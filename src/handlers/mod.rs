@@ -2,20 +2,57 @@
 // pub mod instance;
 // pub mod users;
 pub mod checks;
+pub mod command_role;
+pub mod core_backup;
 pub mod core_info;
 pub mod events;
+pub mod extension;
 pub mod gateway;
 pub mod global_fs;
 pub mod global_settings;
+pub mod i18n;
 pub mod instance;
+pub mod instance_access;
+pub mod instance_action;
+pub mod instance_alerts;
+pub mod instance_announcements;
+pub mod instance_archive;
 pub mod instance_config;
+pub mod instance_crossplay;
+pub mod instance_diagnostics;
 pub mod instance_fs;
+pub mod instance_fs_tail;
+pub mod instance_import;
+pub mod instance_log_patterns;
+pub mod instance_luckperms;
 pub mod instance_macro;
+pub mod instance_map;
+pub mod instance_moderation;
+pub mod instance_nbt;
+pub mod instance_network;
+pub mod instance_player_reputation;
 pub mod instance_players;
+pub mod instance_pregen;
+pub mod instance_recommendations;
+pub mod instance_resource_pack;
 pub mod instance_server;
 pub mod instance_setup_configs;
+pub mod instance_snapshot;
+pub mod instance_upgrade;
+pub mod instance_web;
+pub mod instance_webdav;
+pub mod invite;
+pub mod jobs;
 pub mod monitor;
+pub mod password_reset;
+pub mod probes;
+pub mod quota;
+pub mod reconcile;
 pub mod setup;
+pub mod share_link;
+pub mod storage;
+pub mod summary;
 pub mod system;
 pub mod users;
 mod util;
+pub mod whitelist_group;
@@ -1,7 +1,7 @@
 use std::{collections::HashMap, sync::Arc};
 
 use axum::{
-    extract::{ws::WebSocket, Path, WebSocketUpgrade},
+    extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
     response::Response,
     routing::get,
     Router,
@@ -13,26 +13,44 @@ use tokio::sync::Mutex;
 use tracing::error;
 
 use crate::{
-    error::Error,
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
     prelude::GameInstance,
     traits::{t_server::MonitorReport, t_server::TServer},
     types::InstanceUuid,
     AppState,
 };
 
+use super::{events::WebsocketQuery, util::parse_bearer_token};
+
 pub async fn monitor(
     ws: WebSocketUpgrade,
     axum::extract::State(state): axum::extract::State<AppState>,
+    query: Query<WebsocketQuery>,
     Path(uuid): Path<InstanceUuid>,
 ) -> Result<Response, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = parse_bearer_token(query.token.as_str())
+        .and_then(|token| users_manager.try_auth(&token))
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    drop(users_manager);
+    state
+        .try_action(&requester, &UserAction::MonitorInstance(uuid.clone()))
+        .await?;
     let instance = state
         .instances
         .lock()
         .await
         .get(&uuid)
-        .ok_or_else(|| Error {
-            kind: crate::error::ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
         })?
         .to_owned();
     Ok(ws.on_upgrade(move |stream| {
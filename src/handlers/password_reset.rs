@@ -0,0 +1,172 @@
+use axum::{extract::Path, routing::post, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::{password_reset::DEFAULT_RESET_TOKEN_LIFETIME, user_id::UserId},
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    i18n::{LocalizedMessage, MessageId},
+    mail, AppState,
+};
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewPasswordReset {
+    /// Email the token to the user instead of handing it back, requires SMTP to be configured
+    pub send_email: bool,
+    /// Seconds until the token expires, an hour by default
+    pub expires_in: Option<i64>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct PasswordResetIssued {
+    /// `None` when it was emailed instead
+    pub token: Option<String>,
+    pub expiry: i64,
+    pub emailed_to: Option<String>,
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct PasswordResetRedeem {
+    pub new_password: String,
+}
+
+pub async fn issue_password_reset(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<NewPasswordReset>,
+) -> Result<Json<PasswordResetIssued>, Error> {
+    let (requester, user) = {
+        let users_manager = state.users_manager.read().await;
+        let requester = users_manager.try_auth_or_err(&token)?;
+        requester.try_not_impersonated()?;
+        let user = users_manager.get_user(&uid).ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            )
+        })?;
+        (requester, user)
+    };
+    if !requester.can_manage(&user) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You are not authorized to reset this user's password"),
+        });
+    }
+    let expires_in = config.expires_in.unwrap_or(DEFAULT_RESET_TOKEN_LIFETIME);
+    if expires_in <= 0 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Reset tokens must expire in the future"),
+        });
+    }
+    let expiry = chrono::Utc::now().timestamp().saturating_add(expires_in);
+
+    let mail_target = if config.send_email {
+        let (smtp, core_name) = {
+            let global_settings = state.global_settings.lock().await;
+            (
+                global_settings.smtp(),
+                global_settings.as_ref().core_name.clone(),
+            )
+        };
+        let smtp = smtp.ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("SMTP is not configured"),
+        })?;
+        let email = user.email.clone().ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} has no email address", user.username),
+        })?;
+        Some((smtp, core_name, email))
+    } else {
+        None
+    };
+
+    let reset_token = state
+        .password_resets
+        .lock()
+        .await
+        .issue(uid.clone(), expiry);
+
+    match mail_target {
+        Some((smtp, core_name, email)) => {
+            let body = format!(
+                "A password reset was requested for your account {} on {core_name}.\n\n\
+                 Use this code to choose a new password: {reset_token}\n\n\
+                 It expires on {}. If you didn't expect this, let an admin know.",
+                user.username,
+                chrono::NaiveDateTime::from_timestamp_opt(expiry, 0)
+                    .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+                    .unwrap_or_default(),
+            );
+            if let Err(e) =
+                mail::send_mail(&smtp, &email, &format!("{core_name} password reset"), body).await
+            {
+                // the token would be unusable anyway
+                state
+                    .password_resets
+                    .lock()
+                    .await
+                    .redeem(&reset_token, chrono::Utc::now().timestamp());
+                return Err(e);
+            }
+            Ok(Json(PasswordResetIssued {
+                token: None,
+                expiry,
+                emailed_to: Some(email),
+            }))
+        }
+        None => Ok(Json(PasswordResetIssued {
+            token: Some(reset_token),
+            expiry,
+            emailed_to: None,
+        })),
+    }
+}
+
+pub async fn redeem_password_reset(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(reset_token): Path<String>,
+    Json(config): Json<PasswordResetRedeem>,
+) -> Result<Json<()>, Error> {
+    let uid = state
+        .password_resets
+        .lock()
+        .await
+        .redeem(&reset_token, chrono::Utc::now().timestamp())
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Invalid or expired reset token"),
+        })?;
+    let mut users_manager = state.users_manager.write().await;
+    let user = users_manager.get_user(&uid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::UserNotFound),
+        )
+    })?;
+    let caused_by = CausedBy::User {
+        user_id: user.uid.clone(),
+        user_name: user.username.clone(),
+        impersonated_by: None,
+    };
+    users_manager
+        .reset_password(&uid, config.new_password, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_password_reset_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/user/:uid/password_reset", post(issue_password_reset))
+        .route("/user/password_reset/:token", post(redeem_password_reset))
+        .with_state(state)
+}
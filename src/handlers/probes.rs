@@ -0,0 +1,31 @@
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+
+use crate::{
+    probes::{self, ProbeReport},
+    AppState,
+};
+
+fn respond(report: ProbeReport) -> (StatusCode, Json<ProbeReport>) {
+    let status = if report.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+pub async fn get_liveness(State(state): State<AppState>) -> (StatusCode, Json<ProbeReport>) {
+    respond(probes::liveness(&state).await)
+}
+
+pub async fn get_readiness(State(state): State<AppState>) -> (StatusCode, Json<ProbeReport>) {
+    respond(probes::readiness(&state).await)
+}
+
+/// Not under the API prefix nor behind authentication, so orchestrators can probe them as is
+pub fn get_probe_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/healthz", get(get_liveness))
+        .route("/readyz", get(get_readiness))
+        .with_state(state)
+}
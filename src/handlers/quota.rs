@@ -0,0 +1,119 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+
+use crate::{
+    auth::{
+        user::{User, UserAction},
+        user_id::UserId,
+    },
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    quota::{self, QuotaRole, UserQuota, UserQuotaReport},
+    AppState,
+};
+
+async fn report_of(state: &AppState, user: &User) -> UserQuotaReport {
+    let quota = state.global_settings.lock().await.quotas().quota_of(user);
+    let access = state.all_instance_access().await;
+    let usage = quota::usage_of(&access, &mut *state.instances.lock().await, &user.uid).await;
+    UserQuotaReport {
+        uid: user.uid.clone(),
+        username: user.username.clone(),
+        quota,
+        usage,
+    }
+}
+
+pub async fn get_all_quotas(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<UserQuotaReport>>, Error> {
+    let users: Vec<User> = {
+        let users_manager = state.users_manager.read().await;
+        let requester = users_manager.try_auth_or_err(&token)?;
+        requester.try_action(&UserAction::ManageUser)?;
+        users_manager.as_ref().values().cloned().collect()
+    };
+    let mut reports = Vec::with_capacity(users.len());
+    for user in users.iter() {
+        reports.push(report_of(&state, user).await);
+    }
+    Ok(Json(reports))
+}
+
+pub async fn get_user_quota(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<UserQuotaReport>, Error> {
+    let user = {
+        let users_manager = state.users_manager.read().await;
+        let requester = users_manager.try_auth_or_err(&token)?;
+        if requester.uid != uid {
+            requester.try_action(&UserAction::ManageUser)?;
+        }
+        users_manager.get_user(&uid).ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            )
+        })?
+    };
+    Ok(Json(report_of(&state, &user).await))
+}
+
+pub async fn set_role_quota(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(role): Path<QuotaRole>,
+    AuthBearer(token): AuthBearer,
+    Json(quota): Json<UserQuota>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_role_quota(role, quota)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_user_quota(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(quota): Json<Option<UserQuota>>,
+) -> Result<Json<()>, Error> {
+    {
+        let users_manager = state.users_manager.read().await;
+        let requester = users_manager.try_auth_or_err(&token)?;
+        requester.try_owner_rights()?;
+        if users_manager.get_user(&uid).is_none() {
+            return Err(Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            ));
+        }
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_user_quota(uid, quota)
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_quota_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/quota", get(get_all_quotas))
+        .route("/quota/user/:uid", get(get_user_quota))
+        .route("/quota/user/:uid", put(set_user_quota))
+        .route("/quota/role/:role", put(set_role_quota))
+        .with_state(state)
+}
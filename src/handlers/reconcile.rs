@@ -0,0 +1,189 @@
+use std::path::Path;
+
+use axum::{routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+
+use crate::{
+    auth::{instance_access::InstanceAccess, user::User},
+    error::{Error, ErrorKind},
+    prelude::GameInstance,
+    provisioning::{self, ProvisioningFile, ProvisioningReport},
+    reconcile::{self, OrphanDirectory, OrphanReason, ReconcileAction, ReconcileReport},
+    traits::{
+        t_configurable::{GameType, TConfigurable},
+        t_server::{State, TServer},
+    },
+    types::{DotLodestoneConfig, InstanceUuid},
+    AppState,
+};
+
+use super::{instance::forget_instance, instance_snapshot::reload_minecraft_instance};
+
+/// Only what is in the report right now can be acted on
+async fn find_orphan(state: &AppState, path: &Path) -> Result<OrphanDirectory, Error> {
+    reconcile::report(state)
+        .await?
+        .orphans
+        .into_iter()
+        .find(|orphan| orphan.path == path)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("{} is not an orphaned directory", path.display()),
+        })
+}
+
+/// Loads the orphan as an instance. Unless its `.lodestone_config` was only failing to load,
+/// it is given a new one with a new uuid
+async fn adopt_orphan(state: &AppState, requester: &User, path: &Path) -> Result<(), Error> {
+    let orphan = find_orphan(state, path).await?;
+    if !orphan.adoptable {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "{} does not hold a Minecraft server Lodestone can load",
+                path.display()
+            ),
+        });
+    }
+    let dot_lodestone_config = match (orphan.reason, orphan.instance_uuid) {
+        (OrphanReason::NotLoaded, Some(_)) => {
+            let mut config: DotLodestoneConfig = serde_json::from_slice(
+                &tokio::fs::read(path.join(".lodestone_config"))
+                    .await
+                    .context("Failed to read .lodestone_config file")?,
+            )
+            .context("Failed to parse .lodestone_config file")?;
+            // the directory may have been moved between locations by hand
+            config.set_storage_location(&orphan.storage_location);
+            config
+        }
+        _ => DotLodestoneConfig::new(InstanceUuid::default(), GameType::MinecraftJava)
+            .with_storage_location(&orphan.storage_location),
+    };
+    tokio::fs::write(
+        path.join(".lodestone_config"),
+        serde_json::to_string_pretty(&dot_lodestone_config)
+            .context("Failed to serialize .lodestone_config")?,
+    )
+    .await
+    .context("Failed to write .lodestone_config file")?;
+    let instance: GameInstance = reload_minecraft_instance(path.to_owned(), state)
+        .await?
+        .into();
+    let uuid = dot_lodestone_config.uuid().clone();
+
+    let mut instances = state.instances.lock().await;
+    if instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance {uuid} was loaded in the meantime"),
+        });
+    }
+    let mut port_manager = state.port_manager.lock().await;
+    port_manager.add_port(instance.port().await);
+    if let Some(bedrock_port) = instance.bedrock_port().await {
+        port_manager.add_port(bedrock_port);
+    }
+    drop(port_manager);
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_access(
+            uuid.clone(),
+            Some(InstanceAccess::new(requester.uid.clone())),
+        )
+        .await?;
+    instances.insert(uuid, instance);
+    Ok(())
+}
+
+async fn remove_orphan(state: &AppState, path: &Path) -> Result<(), Error> {
+    find_orphan(state, path).await?;
+    crate::util::fs::remove_dir_all(path).await
+}
+
+/// Unloads the instance if its directory disappeared under it, then clears its settings
+async fn remove_ghost(state: &AppState, uuid: &InstanceUuid) -> Result<(), Error> {
+    let ghost = reconcile::report(state)
+        .await?
+        .ghosts
+        .into_iter()
+        .find(|ghost| &ghost.instance_uuid == uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance {uuid} is not a ghost"),
+        })?;
+    if ghost.loaded {
+        let mut instances = state.instances.lock().await;
+        if let Some(instance) = instances.get(uuid) {
+            if instance.state().await != State::Stopped {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Instance {uuid} must be stopped before it is removed"),
+                });
+            }
+            let mut port_manager = state.port_manager.lock().await;
+            port_manager.deallocate(instance.port().await);
+            if let Some(bedrock_port) = instance.bedrock_port().await {
+                port_manager.deallocate(bedrock_port);
+            }
+            drop(port_manager);
+            instances.remove(uuid);
+        }
+    }
+    forget_instance(state, uuid).await;
+    Ok(())
+}
+
+pub async fn get_reconcile_report(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ReconcileReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    Ok(Json(reconcile::report(&state).await?))
+}
+
+/// Returns the report as it is after the action
+pub async fn apply_reconcile_action(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(action): Json<ReconcileAction>,
+) -> Result<Json<ReconcileReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    match action {
+        ReconcileAction::AdoptOrphan { path } => adopt_orphan(&state, &requester, &path).await?,
+        ReconcileAction::RemoveOrphan { path } => remove_orphan(&state, &path).await?,
+        ReconcileAction::RemoveGhost { instance_uuid } => {
+            remove_ghost(&state, &instance_uuid).await?
+        }
+    }
+    Ok(Json(reconcile::report(&state).await?))
+}
+
+/// How the instances compare to `provisioning.toml`
+pub async fn get_provisioning_report(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ProvisioningReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    let file = ProvisioningFile::load().await?.ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("There is no {}", provisioning::PROVISIONING_FILE),
+    })?;
+    Ok(Json(provisioning::report(&state, &file).await?))
+}
+
+pub fn get_reconcile_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instances/reconcile",
+            get(get_reconcile_report).post(apply_reconcile_action),
+        )
+        .route("/instances/provisioning", get(get_provisioning_report))
+        .with_state(state)
+}
@@ -1,61 +1,117 @@
 use axum::{extract::Path, Json, Router};
 use color_eyre::eyre::eyre;
+use serde::Serialize;
+use ts_rs::TS;
 
 use crate::{
     auth::{permission::UserPermission, user::User},
     error::{Error, ErrorKind},
     events::CausedBy,
+    i18n::{LocalizedMessage, MessageId},
     AppState,
 };
 
 use super::users::LoginReply;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, TS)]
+#[ts(export)]
 pub struct OwnerSetup {
     username: String,
     password: String,
 }
 
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct SetupStatus {
+    pub is_setup: bool,
+    pub owner_present: bool,
+}
+
+pub async fn get_setup_status(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Json<SetupStatus> {
+    Json(SetupStatus {
+        is_setup: state.first_time_setup_key.lock().await.is_none(),
+        owner_present: state
+            .users_manager
+            .read()
+            .await
+            .as_ref()
+            .iter()
+            .any(|(_, user)| user.is_owner),
+    })
+}
+
+fn check_setup_key(setup_key: &Option<String>, key: &str) -> Result<(), Error> {
+    match setup_key {
+        Some(k) if k == key => Ok(()),
+        None => Err(Error::localized(
+            ErrorKind::PermissionDenied,
+            LocalizedMessage::new(MessageId::AlreadySetup),
+        )),
+        Some(_) => Err(Error::localized(
+            ErrorKind::PermissionDenied,
+            LocalizedMessage::new(MessageId::InvalidSetupKey),
+        )),
+    }
+}
+
+/// Lets a headless client validate the key before asking for the owner's credentials
+pub async fn verify_setup_key(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(key): Path<String>,
+) -> Result<Json<()>, Error> {
+    check_setup_key(&*state.first_time_setup_key.lock().await, &key)?;
+    Ok(Json(()))
+}
+
 pub async fn setup_owner(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(key): Path<String>,
     Json(owner_setup): Json<OwnerSetup>,
 ) -> Result<Json<LoginReply>, Error> {
     let mut setup_key_lock = state.first_time_setup_key.lock().await;
-    match setup_key_lock.clone() {
-        Some(k) if k == key => {
-            setup_key_lock.take();
-            let owner = User::new(
-                owner_setup.username,
-                &owner_setup.password,
-                true,
-                false,
-                UserPermission::default(),
-            );
-            state
-                .users_manager
-                .write()
-                .await
-                .add_user(owner.clone(), CausedBy::System)
-                .await?;
-            Ok(Json(LoginReply {
-                token: owner.create_jwt()?,
-                user: owner.into(),
-            }))
-        }
-        None => Err(Error {
-            kind: ErrorKind::PermissionDenied,
-            source: eyre!("Setup key already used."),
-        }),
-        Some(_) => Err(Error {
-            kind: ErrorKind::PermissionDenied,
-            source: eyre!("Invalid setup key."),
-        }),
+    check_setup_key(&setup_key_lock, &key)?;
+    if state
+        .users_manager
+        .read()
+        .await
+        .as_ref()
+        .iter()
+        .any(|(_, user)| user.is_owner)
+    {
+        setup_key_lock.take();
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Owner account already present"),
+        });
     }
+    let mut users_manager = state.users_manager.write().await;
+    let owner = User::new(
+        owner_setup.username,
+        &owner_setup.password,
+        true,
+        false,
+        UserPermission::default(),
+        &users_manager.password_hashing(),
+    );
+    users_manager
+        .add_user(owner.clone(), CausedBy::System)
+        .await?;
+    drop(users_manager);
+    setup_key_lock.take();
+    Ok(Json(LoginReply {
+        token: owner.create_jwt()?,
+        user: owner.into(),
+    }))
 }
 
 pub fn get_setup_route(state: AppState) -> Router {
     Router::new()
-        .route("/setup/:key", axum::routing::post(setup_owner))
+        .route("/setup/status", axum::routing::get(get_setup_status))
+        .route(
+            "/setup/:key",
+            axum::routing::get(verify_setup_key).post(setup_owner),
+        )
         .with_state(state)
 }
@@ -0,0 +1,273 @@
+use axum::{
+    body::StreamBody,
+    extract::Path,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use headers::HeaderName;
+use serde::{Deserialize, Serialize};
+use tokio_util::io::ReaderStream;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    disk_space,
+    error::{Error, ErrorKind},
+    events::{new_fs_event, CausedBy, FSOperation, FSTarget},
+    i18n::{LocalizedMessage, MessageId},
+    share_link::{ShareLink, DEFAULT_TTL_SECS, MAX_TTL_SECS},
+    snapshot,
+    traits::t_configurable::TConfigurable,
+    types::{InstanceUuid, Snowflake},
+    util::scoped_join_win_safe,
+    AppState,
+};
+
+use super::{global_fs::file_attachment, util::decode_base64};
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewShareLink {
+    /// Defaults to a day, at most a week
+    pub expires_in_secs: Option<u32>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct SharedFile {
+    pub link: ShareLink,
+    /// Downloads the file from `/share/:token`, without signing in
+    pub token: String,
+}
+
+async fn instance_root(state: &AppState, uuid: &InstanceUuid) -> Result<std::path::PathBuf, Error> {
+    Ok(state
+        .instances
+        .lock()
+        .await
+        .get(uuid)
+        .ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::InstanceNotFound),
+            )
+        })?
+        .path()
+        .await)
+}
+
+fn ttl_secs(new_link: &NewShareLink) -> Result<u32, Error> {
+    let ttl_secs = new_link.expires_in_secs.unwrap_or(DEFAULT_TTL_SECS);
+    if ttl_secs == 0 || ttl_secs > MAX_TTL_SECS {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("A share link can last between 1 and {MAX_TTL_SECS} seconds"),
+        });
+    }
+    Ok(ttl_secs)
+}
+
+pub async fn share_instance_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(new_link): Json<NewShareLink>,
+) -> Result<Json<SharedFile>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_not_impersonated()?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let ttl_secs = ttl_secs(&new_link)?;
+    let path = scoped_join_win_safe(instance_root(&state, &uuid).await?, &relative_path)?;
+    if !path.is_file() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Only files can be shared"),
+        });
+    }
+    let link = ShareLink::new(uuid, relative_path, &requester, ttl_secs);
+    let token = state.share_links.lock().await.create(link.clone()).await?;
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Download,
+        FSTarget::File(path),
+        CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+            impersonated_by: requester.impersonated_by,
+        },
+    ));
+    Ok(Json(SharedFile { link, token }))
+}
+
+/// Packs the snapshot into an archive that lasts as long as the link
+pub async fn share_snapshot(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, snapshot_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+    Json(new_link): Json<NewShareLink>,
+) -> Result<Json<SharedFile>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_not_impersonated()?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let ttl_secs = ttl_secs(&new_link)?;
+    let snapshot = snapshot::get_snapshot(&uuid, snapshot_id).await?;
+    let link = ShareLink::new_snapshot(&snapshot, &requester, ttl_secs);
+    let archive = link
+        .archive_path()
+        .ok_or_else(|| eyre!("Snapshot link has no archive"))?;
+    if let Some(dir) = archive.parent() {
+        crate::util::fs::create_dir_all(dir).await?;
+    }
+    let disk_space = state.global_settings.lock().await.disk_space();
+    // the archive is compressed, it will not be larger than the snapshot
+    disk_space::ensure_space(
+        &disk_space,
+        &archive,
+        snapshot.size,
+        Some(&uuid),
+        Some(snapshot_id),
+    )
+    .await?;
+    snapshot::export_snapshot(&uuid, snapshot_id, archive.clone()).await?;
+    let token = match state.share_links.lock().await.create(link.clone()).await {
+        Ok(token) => token,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&archive).await;
+            return Err(e);
+        }
+    };
+    Ok(Json(SharedFile { link, token }))
+}
+
+pub async fn list_share_links(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ShareLink>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    Ok(Json(state.share_links.lock().await.list(&uuid)))
+}
+
+pub async fn revoke_share_link(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .try_action(&requester, &UserAction::ReadInstanceFile(uuid.clone()))
+        .await?;
+    let not_found = || Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Share link not found"),
+    };
+    let link = state
+        .share_links
+        .lock()
+        .await
+        .get(&uuid, &id)
+        .ok_or_else(not_found)?;
+    // other users' links can only be revoked by those who could change the files anyway
+    if link.created_by != requester.uid {
+        state
+            .try_action(&requester, &UserAction::WriteInstanceFile(uuid.clone()))
+            .await?;
+    }
+    state
+        .share_links
+        .lock()
+        .await
+        .revoke(&uuid, &id)
+        .await?
+        .ok_or_else(not_found)?;
+    Ok(Json(()))
+}
+
+/// The only route that needs no sign in, the token is all the access there is
+pub async fn download_shared_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(token): Path<String>,
+) -> Result<
+    (
+        [(HeaderName, String); 3],
+        StreamBody<ReaderStream<tokio::fs::File>>,
+    ),
+    Error,
+> {
+    let link = state
+        .share_links
+        .lock()
+        .await
+        .resolve(&token)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("This link is invalid or has expired"),
+        })?;
+    let not_found = || Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("This link is invalid or has expired"),
+    };
+    // the link gives no more access than its creator still has
+    let creator = state
+        .users_manager
+        .read()
+        .await
+        .get_user(&link.created_by)
+        .ok_or_else(not_found)?;
+    state
+        .try_action(
+            &creator,
+            &UserAction::ReadInstanceFile(link.instance_uuid.clone()),
+        )
+        .await
+        .map_err(|_| not_found())?;
+    let path = match link.archive_path() {
+        Some(archive) => archive,
+        // joined again, the instance may have moved or the file been swapped for a symlink since
+        None => scoped_join_win_safe(
+            instance_root(&state, &link.instance_uuid).await?,
+            &link.path,
+        )?,
+    };
+    let attachment = file_attachment(&path).await?;
+    state
+        .share_links
+        .lock()
+        .await
+        .record_download(&link.id)
+        .await;
+    Ok(attachment)
+}
+
+pub fn get_share_link_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/fs/:base64_relative_path/share",
+            post(share_instance_file),
+        )
+        .route(
+            "/instance/:uuid/snapshots/:snapshot_id/share",
+            post(share_snapshot),
+        )
+        .route("/instance/:uuid/share_links", get(list_share_links))
+        .route("/instance/:uuid/share_links/:id", delete(revoke_share_link))
+        // the only route here anyone can call
+        .merge(
+            Router::new()
+                .route("/share/:token", get(download_shared_file))
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::rate_limiter::rate_limit,
+                )),
+        )
+        .with_state(state)
+}
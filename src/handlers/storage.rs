@@ -0,0 +1,241 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use tracing::error;
+use ts_rs::TS;
+
+use crate::{
+    disk_space,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event},
+    i18n::{LocalizedMessage, MessageId},
+    prelude::GameInstance,
+    storage::{self, StorageLocation, StorageLocationInfo},
+    traits::{t_configurable::TConfigurable, t_server::State, t_server::TServer},
+    types::{DotLodestoneConfig, InstanceUuid},
+    AppState,
+};
+
+use super::instance_snapshot::reload_minecraft_instance;
+
+/// Picks the storage location of a new instance, the default one if not given
+#[derive(Deserialize, Debug, Default, TS)]
+#[ts(export)]
+pub struct StorageQuery {
+    pub storage: Option<String>,
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct MoveInstance {
+    /// Id of the storage location to move the instance to
+    pub location: String,
+}
+
+pub async fn get_storage_locations(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<StorageLocationInfo>>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    let locations: Vec<StorageLocation> = std::iter::once(StorageLocation::default_location())
+        .chain(
+            state
+                .global_settings
+                .lock()
+                .await
+                .storage_locations()
+                .iter()
+                .cloned(),
+        )
+        .collect();
+    let mut instance_paths = Vec::new();
+    for instance in state.instances.lock().await.values() {
+        instance_paths.push(instance.path().await);
+    }
+    Ok(Json(
+        locations
+            .into_iter()
+            .map(|location| StorageLocationInfo {
+                available_space: disk_space::available_space(&location.path),
+                instance_count: instance_paths
+                    .iter()
+                    .filter(|path| location.holds(path))
+                    .count() as u32,
+                location,
+            })
+            .collect(),
+    ))
+}
+
+/// Replaces the configured storage locations.
+/// A location can't be removed or pointed elsewhere while it still holds instances
+pub async fn set_storage_locations(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(locations): Json<Vec<StorageLocation>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    storage::validate_locations(&locations)?;
+    let mut instance_paths = Vec::new();
+    for instance in state.instances.lock().await.values() {
+        instance_paths.push(instance.path().await);
+    }
+    let mut global_settings = state.global_settings.lock().await;
+    for old_location in global_settings.storage_locations() {
+        if locations.contains(old_location) {
+            continue;
+        }
+        if instance_paths.iter().any(|path| old_location.holds(path)) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Storage location {} still holds instances, move them elsewhere first",
+                    old_location.name
+                ),
+            });
+        }
+    }
+    for location in &locations {
+        crate::util::fs::create_dir_all(&location.path).await?;
+    }
+    global_settings.set_storage_locations(locations).await?;
+    Ok(Json(()))
+}
+
+/// Moves a stopped instance to another storage location, in the background
+pub async fn move_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(MoveInstance { location }): Json<MoveInstance>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    let (disk_space, location) = {
+        let global_settings = state.global_settings.lock().await;
+        (
+            global_settings.disk_space(),
+            global_settings.storage_location(Some(&location))?,
+        )
+    };
+    let mut instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )
+    })?;
+    // only minecraft instances can be reloaded from their new path
+    if !matches!(instance, GameInstance::MinecraftInstance(_)) {
+        return Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Only Minecraft instances can be moved"),
+        });
+    }
+    if instance.state().await != State::Stopped {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance must be stopped before moving it"),
+        });
+    }
+    let path = instance.path().await;
+    if location.holds(&path) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance is already in {}", location.name),
+        });
+    }
+    let size = disk_space::dir_size(path.clone()).await;
+    disk_space::ensure_space(&disk_space, &location.path, size, Some(&uuid), None).await?;
+    // taken out of the list so it cannot be started while its files are being moved
+    let instance = instances.remove(&uuid).unwrap();
+    drop(instances);
+
+    let event_broadcaster = state.event_broadcaster.clone();
+    tokio::spawn(async move {
+        let instance_name = instance.name().await;
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!("Moving {} to {}", instance_name, location.name),
+            None,
+            None,
+            CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+                impersonated_by: requester.impersonated_by.clone(),
+            },
+        );
+        event_broadcaster.send(progression_start_event);
+        let result = match storage::move_instance_dir(&path, &location.path).await {
+            Ok(new_path) => {
+                let moved = match reload_minecraft_instance(new_path.clone(), &state).await {
+                    Ok(moved) => record_storage_location(&new_path, &location.id)
+                        .await
+                        .map(|_| moved),
+                    Err(e) => Err(e),
+                };
+                if moved.is_err() {
+                    // put the files back where the old instance expects them
+                    if let Some(old_location) = path.parent() {
+                        if let Err(e) = storage::move_instance_dir(&new_path, old_location).await {
+                            error!("Failed to move instance {instance_name} back: {e}");
+                        }
+                    }
+                }
+                moved
+            }
+            Err(e) => Err(e),
+        };
+        let (instance, result) = match result {
+            Ok(moved) => (moved.into(), Ok(())),
+            Err(e) => {
+                error!("Failed to move instance {instance_name}: {e}");
+                (instance, Err(e))
+            }
+        };
+        state.instances.lock().await.insert(uuid, instance);
+        event_broadcaster.send(match result {
+            Ok(()) => Event::new_progression_event_end(
+                event_id,
+                true,
+                Some(&format!("Moved {} to {}", instance_name, location.name)),
+                None,
+            ),
+            Err(e) => Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Failed to move {instance_name}: {e}")),
+                None,
+            ),
+        });
+    });
+    Ok(Json(()))
+}
+
+async fn record_storage_location(instance_path: &std::path::Path, id: &str) -> Result<(), Error> {
+    let config_path = instance_path.join(".lodestone_config");
+    let mut dot_lodestone_config: DotLodestoneConfig =
+        serde_json::from_str(&crate::util::fs::read_to_string(&config_path).await?)
+            .context("Failed to parse .lodestone_config file")?;
+    dot_lodestone_config.set_storage_location(id);
+    crate::util::fs::write_all(
+        &config_path,
+        serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+    )
+    .await
+}
+
+pub fn get_storage_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/storage/locations",
+            get(get_storage_locations).put(set_storage_locations),
+        )
+        .route("/instance/:uuid/storage", put(move_instance))
+        .with_state(state)
+}
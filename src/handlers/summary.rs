@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+
+use axum::{routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use ringbuffer::RingBufferExt;
+use sysinfo::{CpuExt, SystemExt};
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    summary::{HostUsage, Summary},
+    types::InstanceUuid,
+    AppState,
+};
+
+/// Counts over the instances the requester can see, for the home screen
+pub async fn get_summary(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Summary>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let uuids: Vec<InstanceUuid> = state.instances.lock().await.keys().cloned().collect();
+    let mut visible = HashSet::new();
+    for uuid in uuids {
+        if state
+            .can_perform_action(&requester, &UserAction::ViewInstance(uuid.clone()))
+            .await
+        {
+            visible.insert(uuid);
+        }
+    }
+
+    let (instances, players_online) = state.summary_cache.lock().await.counts(&visible);
+    let active_jobs = state
+        .jobs
+        .lock()
+        .await
+        .running()
+        .filter(|job| {
+            job.instance_uuid
+                .as_ref()
+                .map_or(requester.has_owner_rights(), |uuid| visible.contains(uuid))
+        })
+        .count() as u32;
+    let firing_alerts = state
+        .alerts
+        .lock()
+        .await
+        .firing()
+        .filter(|uuid| visible.contains(*uuid))
+        .count() as u32;
+    let instances_memory = state
+        .monitor_buffer
+        .lock()
+        .await
+        .iter()
+        .filter(|(uuid, _)| visible.contains(*uuid))
+        .filter_map(|(_, reports)| reports.back()?.memory_usage)
+        .sum();
+    let mut sys = state.system.lock().await;
+    sys.refresh_memory();
+    // usage since the last refresh, which is fine for a glance
+    sys.refresh_cpu();
+    let host = HostUsage {
+        cpu_percent: sys.global_cpu_info().cpu_usage(),
+        memory_total: sys.total_memory(),
+        memory_used: sys.used_memory(),
+        instances_memory,
+    };
+    drop(sys);
+
+    Ok(Json(Summary {
+        instances,
+        players_online,
+        host,
+        active_jobs,
+        firing_alerts,
+    }))
+}
+
+pub fn get_summary_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/summary", get(get_summary))
+        .with_state(state)
+}
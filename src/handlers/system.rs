@@ -1,13 +1,15 @@
 use axum::{routing::get, Json, Router};
 use serde::{Deserialize, Serialize};
 use sysinfo::{CpuExt, CpuRefreshKind, DiskExt, SystemExt};
+use ts_rs::TS;
 
 use tokio::time::sleep;
 
 use crate::AppState;
 
 // Since MemInfo is not serializable, we need to create a new struct that is serializable.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
 pub struct MemInfo {
     total: u64,
     free: u64,
@@ -23,7 +25,8 @@ pub async fn get_ram(axum::extract::State(state): axum::extract::State<AppState>
 }
 
 // Since DiskInfo is not serializable, we need to create a new struct that is serializable.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
 pub struct DiskInfo {
     total: u64,
     free: u64,
@@ -41,7 +44,8 @@ pub async fn get_disk(
     })
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
 pub struct CPUInfo {
     pub cpu_speed: u64,
     pub cpu_load: f32,
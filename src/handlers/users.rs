@@ -1,12 +1,14 @@
 use crate::{
     auth::{
+        impersonation::ImpersonationSession,
         jwt_token::JwtToken,
         permission::UserPermission,
-        user::{PublicUser, User, UserAction},
+        user::{PublicUser, User, UserAction, UsersManager},
         user_id::UserId,
     },
     error::{Error, ErrorKind},
     events::CausedBy,
+    i18n::{Locale, LocalizedMessage, MessageId},
     AppState,
 };
 
@@ -20,9 +22,11 @@ use axum_auth::{AuthBasic, AuthBearer};
 use color_eyre::eyre::eyre;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use tracing::warn;
 use ts_rs::TS;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
 pub struct NewUser {
     pub username: String,
     pub password: String,
@@ -42,10 +46,12 @@ pub async fn new_user(
         false,
         false,
         UserPermission::default(),
+        &users_manager.password_hashing(),
     );
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     users_manager
         .add_user(user.clone(), caused_by.clone())
@@ -71,14 +77,33 @@ pub async fn delete_user(
             source: eyre!("You cannot delete yourself"),
         });
     }
+    if users_manager
+        .get_user(&uid)
+        .map_or(false, |user| user.is_owner)
+    {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("The owner can't be deleted, transfer ownership first"),
+        });
+    }
 
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     users_manager
         .delete_user(uid.clone(), caused_by.clone())
         .await?;
+    if let Err(e) = state
+        .global_settings
+        .lock()
+        .await
+        .set_user_quota(uid, None)
+        .await
+    {
+        warn!("Failed to clear quota of deleted user: {e}");
+    }
     Ok(Json(json!("ok")))
 }
 
@@ -100,6 +125,7 @@ pub async fn logout(
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by.clone(),
     };
     users_manager
         .logout_user(uid.clone(), caused_by.clone())
@@ -120,6 +146,7 @@ pub async fn update_permissions(
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     users_manager
         .update_permissions(uid, new_permissions, caused_by)
@@ -127,6 +154,149 @@ pub async fn update_permissions(
     Ok(Json(()))
 }
 
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct AdminChange {
+    pub is_admin: bool,
+    /// The requester's own password, to confirm the change
+    pub password: String,
+}
+
+pub async fn set_admin(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(change): Json<AdminChange>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManageUser)?;
+    if uid == requester.uid {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("You cannot change your own admin status"),
+        });
+    }
+    let target = users_manager.get_user(&uid).ok_or_else(|| {
+        Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::UserNotFound),
+        )
+    })?;
+    // admins can promote users, but only the owner can demote an admin
+    if !requester.can_manage(&target) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You are not authorized to change this user's admin status"),
+        });
+    }
+    users_manager.confirm_password(&requester.uid, &change.password)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    users_manager
+        .set_admin(uid, change.is_admin, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct OwnershipTransfer {
+    /// The current owner's password, to confirm the transfer
+    pub password: String,
+}
+
+pub async fn transfer_ownership(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(transfer): Json<OwnershipTransfer>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner can transfer ownership"),
+        });
+    }
+    users_manager.confirm_password(&requester.uid, &transfer.password)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    users_manager.transfer_ownership(uid, caused_by).await?;
+    Ok(Json(()))
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct ImpersonationReply {
+    pub token: JwtToken,
+    pub session: ImpersonationSession,
+}
+
+pub async fn impersonate(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ImpersonationReply>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_not_impersonated()?;
+    requester.try_action(&UserAction::ManageUser)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    let (token, session) = users_manager.impersonate(&requester, uid, caused_by)?;
+    Ok(Json(ImpersonationReply { token, session }))
+}
+
+pub async fn get_impersonations(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ImpersonationSession>>, Error> {
+    let users_manager = state.users_manager.read().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManageUser)?;
+    Ok(Json(users_manager.impersonations()))
+}
+
+/// Admins can end any session, users can end the ones made for them
+pub async fn end_impersonation(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(session_id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    let is_own = users_manager
+        .impersonations()
+        .iter()
+        .any(|session| session.session_id == session_id && session.target == requester.uid);
+    if !is_own {
+        requester.try_action(&UserAction::ManageUser)?;
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
+    };
+    users_manager.end_impersonation(&session_id, caused_by)?;
+    Ok(Json(()))
+}
+
 pub async fn get_self_info(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
@@ -158,9 +328,11 @@ pub async fn get_user_info(
     Ok(Json(
         users_manager
             .get_user(&uid)
-            .ok_or(Error {
-                kind: ErrorKind::NotFound,
-                source: eyre!("User not found"),
+            .ok_or_else(|| {
+                Error::localized(
+                    ErrorKind::NotFound,
+                    LocalizedMessage::new(MessageId::UserNotFound),
+                )
             })?
             .into(),
     ))
@@ -186,12 +358,86 @@ pub async fn rename_user(
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
+        impersonated_by: requester.impersonated_by.clone(),
     };
     users_manager.rename_user(uid, new_name, caused_by).await?;
     Ok(Json(()))
 }
 
-#[derive(Deserialize)]
+pub async fn set_locale(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(locale): Json<Option<Locale>>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+
+    if requester.uid != uid {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You can only change your own language"),
+        });
+    }
+
+    users_manager.set_locale(uid, locale).await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct EmailChange {
+    pub email: Option<String>,
+    /// The requester's own password, required when changing their own email
+    pub confirm_password: Option<String>,
+}
+
+pub async fn set_email(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(EmailChange {
+        email,
+        confirm_password,
+    }): Json<EmailChange>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_not_impersonated()?;
+
+    if requester.uid == uid {
+        // the email is where reset links go, so a stolen session shouldn't be enough to move it
+        let password = confirm_password.ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Confirm your password to change your email"),
+        })?;
+        users_manager.confirm_password(&requester.uid, password)?;
+    } else {
+        let target = users_manager.get_user(&uid).ok_or_else(|| {
+            Error::localized(
+                ErrorKind::NotFound,
+                LocalizedMessage::new(MessageId::UserNotFound),
+            )
+        })?;
+        if !requester.can_manage(&target) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("You are not authorized to change other users email"),
+            });
+        }
+    }
+    if let Some(email) = &email {
+        crate::mail::validate_address(email)?;
+    }
+
+    users_manager.set_email(uid, email).await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
 pub struct ChangePasswordConfig {
     uid: UserId,
     old_password: Option<String>,
@@ -206,6 +452,7 @@ pub async fn change_password(
     let mut users_manager = state.users_manager.write().await;
 
     let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_not_impersonated()?;
 
     if requester.uid != config.uid || !requester.can_perform_action(&UserAction::ManageUser) {
         return Err(Error {
@@ -217,6 +464,7 @@ pub async fn change_password(
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username,
+        impersonated_by: requester.impersonated_by.clone(),
     };
     users_manager
         .change_password(
@@ -237,30 +485,16 @@ pub async fn change_password(
     Ok(Json(()))
 }
 
-#[derive(Serialize, TS)]
-#[ts(export)]
-pub struct LoginReply {
-    pub token: JwtToken,
-    pub user: PublicUser,
-}
+pub use lodestone_types::LoginReply;
 
 pub async fn login(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBasic((username, password)): AuthBasic,
 ) -> Result<Json<LoginReply>, Error> {
     if let Some(password) = password {
-        let users_manager = state.users_manager.read().await;
-
-        Ok(Json(LoginReply {
-            token: users_manager.login(&username, &password)?,
-            user: users_manager
-                .get_user_by_username(&username)
-                .ok_or_else(|| Error {
-                    kind: ErrorKind::NotFound,
-                    source: eyre!("User not found"),
-                })?
-                .into(),
-        }))
+        Ok(Json(
+            UsersManager::login(&state.users_manager, &username, &password).await?,
+        ))
     } else {
         Err(Error {
             kind: ErrorKind::BadRequest,
@@ -296,10 +530,23 @@ pub fn get_user_routes(state: AppState) -> Router {
         .route("/user/:uid", get(get_user_info))
         .route("/user/:uid", delete(delete_user))
         .route("/user/:uid/update_perm", put(update_permissions))
+        .route("/user/:uid/admin", put(set_admin))
+        .route("/user/:uid/transfer_ownership", post(transfer_ownership))
+        .route("/user/:uid/impersonate", post(impersonate))
+        .route("/user/impersonations", get(get_impersonations))
+        .route(
+            "/user/impersonations/:session_id",
+            delete(end_impersonation),
+        )
         .route("/user/info", get(get_self_info))
         .route("/user/:uid/rename", put(rename_user))
         .route("/user/:uid/password", put(change_password))
-        .route("/user/login", post(login))
+        .route("/user/:uid/locale", put(set_locale))
+        .route("/user/:uid/email", put(set_email))
         .route("/user/logout/:uid", post(logout))
+        // every attempt costs a password hash, and it's what brute forcing goes for
+        .merge(Router::new().route("/user/login", post(login)).route_layer(
+            axum::middleware::from_fn_with_state(state.clone(), crate::rate_limiter::rate_limit),
+        ))
         .with_state(state)
 }
@@ -1,6 +1,24 @@
-use color_eyre::eyre::Context;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
 
-use crate::error::Error;
+use axum::{
+    body::{boxed, Full},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::{eyre, Context};
+
+use crate::{
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    implementations::minecraft::MinecraftInstance,
+    prelude::GameInstance,
+    types::InstanceUuid,
+    AppState,
+};
 
 pub fn parse_bearer_token(token: &str) -> Option<String> {
     let mut split = token.split_ascii_whitespace();
@@ -23,3 +41,53 @@ pub fn decode_base64(input: &str) -> Result<String, Error> {
     )
     .context("Invalid UTF-8")?)
 }
+
+/// Middleware for rarely changing GET endpoints (game lists, setup manifests)
+///
+/// Tags successful responses with an ETag and a short Cache-Control,
+/// and answers with 304 Not Modified if the client already has the same body
+pub async fn etag_cache<B>(request: Request<B>, next: Next<B>) -> Response {
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH).cloned();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    let etag = HeaderValue::from_str(&format!("W/\"{:x}\"", hasher.finish()))
+        .expect("hex digest is always a valid header value");
+    parts.headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, max-age=300"),
+    );
+    parts.headers.insert(header::ETAG, etag.clone());
+    if if_none_match.as_ref() == Some(&etag) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        return Response::from_parts(parts, boxed(Full::default()));
+    }
+    Response::from_parts(parts, boxed(Full::from(bytes)))
+}
+
+/// The instance behind `uuid`, as long as it is a Minecraft Java one
+pub async fn get_minecraft_instance(
+    state: &AppState,
+    uuid: &InstanceUuid,
+) -> Result<MinecraftInstance, Error> {
+    match state.instances.lock().await.get(uuid) {
+        Some(GameInstance::MinecraftInstance(minecraft)) => Ok(minecraft.clone()),
+        Some(_) => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Only Minecraft Java instances support this"),
+        }),
+        None => Err(Error::localized(
+            ErrorKind::NotFound,
+            LocalizedMessage::new(MessageId::InstanceNotFound),
+        )),
+    }
+}
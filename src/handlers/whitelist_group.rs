@@ -0,0 +1,160 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    error::{Error, ErrorKind},
+    types::{InstanceUuid, Snowflake},
+    whitelist_group::{
+        resolve_players, sync_instances, NewWhitelistGroup, WhitelistGroup, WhitelistPlayer,
+        MAX_GROUPS,
+    },
+    AppState,
+};
+
+fn group_not_found() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Whitelist group not found"),
+    }
+}
+
+/// Validates the group, and looks up the players no group has yet
+async fn build_group(
+    state: &AppState,
+    id: Snowflake,
+    new_group: NewWhitelistGroup,
+) -> Result<WhitelistGroup, Error> {
+    new_group.validate()?;
+    let instances = state.instances.lock().await;
+    if let Some(uuid) = new_group
+        .instances
+        .iter()
+        .find(|uuid| !instances.contains_key(*uuid))
+    {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance {uuid} not found"),
+        });
+    }
+    drop(instances);
+    let known: Vec<WhitelistPlayer> = state
+        .global_settings
+        .lock()
+        .await
+        .whitelist_groups()
+        .iter()
+        .flat_map(|group| group.players.iter().cloned())
+        .collect();
+    Ok(WhitelistGroup {
+        id,
+        name: new_group.name,
+        players: resolve_players(&new_group.players, &known).await,
+        instances: new_group.instances,
+    })
+}
+
+pub async fn list_whitelist_groups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<WhitelistGroup>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    Ok(Json(
+        state
+            .global_settings
+            .lock()
+            .await
+            .whitelist_groups()
+            .to_vec(),
+    ))
+}
+
+pub async fn create_whitelist_group(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(new_group): Json<NewWhitelistGroup>,
+) -> Result<Json<WhitelistGroup>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    let group = build_group(&state, Snowflake::new(), new_group).await?;
+    let mut global_settings = state.global_settings.lock().await;
+    let mut groups = global_settings.whitelist_groups().to_vec();
+    if groups.len() >= MAX_GROUPS {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("There can be at most {MAX_GROUPS} whitelist groups"),
+        });
+    }
+    groups.push(group.clone());
+    global_settings.set_whitelist_groups(groups).await?;
+    drop(global_settings);
+    sync_instances(&state, &group.instances).await;
+    Ok(Json(group))
+}
+
+/// Replaces the group, the instances it no longer applies to keep their whitelist
+pub async fn update_whitelist_group(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(group_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+    Json(new_group): Json<NewWhitelistGroup>,
+) -> Result<Json<WhitelistGroup>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    let group = build_group(&state, group_id, new_group).await?;
+    let mut global_settings = state.global_settings.lock().await;
+    let mut groups = global_settings.whitelist_groups().to_vec();
+    let old_group = groups
+        .iter_mut()
+        .find(|group| group.id == group_id)
+        .ok_or_else(group_not_found)?;
+    let old_instances = std::mem::replace(old_group, group.clone()).instances;
+    global_settings.set_whitelist_groups(groups).await?;
+    drop(global_settings);
+    // the instances it left may be in other groups, which decide their whitelist now
+    let instances: Vec<InstanceUuid> = old_instances
+        .into_iter()
+        .filter(|uuid| !group.instances.contains(uuid))
+        .chain(group.instances.iter().cloned())
+        .collect();
+    sync_instances(&state, &instances).await;
+    Ok(Json(group))
+}
+
+pub async fn delete_whitelist_group(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(group_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner_rights()?;
+    let mut global_settings = state.global_settings.lock().await;
+    let mut groups = global_settings.whitelist_groups().to_vec();
+    let index = groups
+        .iter()
+        .position(|group| group.id == group_id)
+        .ok_or_else(group_not_found)?;
+    let group = groups.remove(index);
+    global_settings.set_whitelist_groups(groups).await?;
+    drop(global_settings);
+    sync_instances(&state, &group.instances).await;
+    Ok(Json(()))
+}
+
+pub fn get_whitelist_group_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/whitelist_groups",
+            get(list_whitelist_groups).post(create_whitelist_group),
+        )
+        .route(
+            "/whitelist_groups/:group_id",
+            put(update_whitelist_group).delete(delete_whitelist_group),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,273 @@
+//! A rough health rating of each instance, derived from its recent events.
+//!
+//! Crashes, errors in the console and low TPS readings are counted over a sliding window.
+//! Whenever the resulting status changes a [`InstanceEventInner::HealthChanged`] event is sent.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    console_parser::LogLevel,
+    event_broadcaster::SubscriptionFilter,
+    events::{CausedBy, Event, EventInner, EventType, InstanceEvent, InstanceEventInner},
+    implementations::minecraft::line_parser::parse_tps,
+    traits::t_server::State,
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+const EVALUATE_INTERVAL: Duration = Duration::from_secs(15);
+const CRASH_WINDOW_SECS: i64 = 60 * 60;
+const ERROR_WINDOW_SECS: i64 = 5 * 60;
+/// TPS is only known when someone runs `tps`, old readings are ignored
+const TPS_MAX_AGE_SECS: i64 = 5 * 60;
+const DEGRADED_CRASHES: usize = 1;
+const UNHEALTHY_CRASHES: usize = 3;
+const DEGRADED_ERRORS: usize = 20;
+const UNHEALTHY_ERRORS: usize = 100;
+const DEGRADED_TPS: f64 = 18.0;
+const UNHEALTHY_TPS: f64 = 12.0;
+
+pub use lodestone_types::HealthStatus;
+
+#[derive(Default)]
+struct InstanceHealth {
+    name: String,
+    last_state: Option<State>,
+    /// Set when the process is killed, so its exit isn't taken for a crash
+    kill_requested: bool,
+    crashes: VecDeque<i64>,
+    errors: VecDeque<i64>,
+    tps: Option<(i64, f64)>,
+    status: HealthStatus,
+}
+
+impl InstanceHealth {
+    fn observe(&mut self, inner: &InstanceEventInner, now: i64) {
+        match inner {
+            InstanceEventInner::StateTransition { to } => {
+                let unexpected_exit = *to == State::Stopped
+                    && matches!(self.last_state, Some(State::Starting | State::Running))
+                    && !self.kill_requested;
+                if unexpected_exit {
+                    self.crashes.push_back(now);
+                }
+                if *to == State::Stopped {
+                    self.kill_requested = false;
+                    self.tps = None;
+                }
+                self.last_state = Some(*to);
+            }
+            InstanceEventInner::InstanceError { .. }
+            | InstanceEventInner::InstanceOutput {
+                level: Some(LogLevel::Error),
+                ..
+            } => self.errors.push_back(now),
+            InstanceEventInner::InstanceOutput { message, .. }
+            | InstanceEventInner::SystemMessage { message } => {
+                if let Some(tps) = parse_tps(message) {
+                    self.tps = Some((now, tps));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The status along with what lowered it
+    fn evaluate(&mut self, now: i64) -> (HealthStatus, Vec<String>) {
+        while self
+            .crashes
+            .front()
+            .map_or(false, |at| now - at > CRASH_WINDOW_SECS)
+        {
+            self.crashes.pop_front();
+        }
+        while self
+            .errors
+            .front()
+            .map_or(false, |at| now - at > ERROR_WINDOW_SECS)
+        {
+            self.errors.pop_front();
+        }
+
+        let mut status = HealthStatus::Healthy;
+        let mut reasons = Vec::new();
+        let mut lower = |to: HealthStatus, reason: String| {
+            status = status.max(to);
+            reasons.push(reason);
+        };
+        let crashes = self.crashes.len();
+        if crashes >= UNHEALTHY_CRASHES {
+            lower(
+                HealthStatus::Unhealthy,
+                format!("{crashes} crashes in the last hour"),
+            );
+        } else if crashes >= DEGRADED_CRASHES {
+            lower(
+                HealthStatus::Degraded,
+                format!("{crashes} crash(es) in the last hour"),
+            );
+        }
+        let errors = self.errors.len();
+        if errors >= UNHEALTHY_ERRORS {
+            lower(
+                HealthStatus::Unhealthy,
+                format!("{errors} errors logged in the last 5 minutes"),
+            );
+        } else if errors >= DEGRADED_ERRORS {
+            lower(
+                HealthStatus::Degraded,
+                format!("{errors} errors logged in the last 5 minutes"),
+            );
+        }
+        if let Some((at, tps)) = self.tps {
+            if now - at <= TPS_MAX_AGE_SECS {
+                if tps < UNHEALTHY_TPS {
+                    lower(HealthStatus::Unhealthy, format!("TPS at {tps:.1}"));
+                } else if tps < DEGRADED_TPS {
+                    lower(HealthStatus::Degraded, format!("TPS at {tps:.1}"));
+                }
+            }
+        }
+        (status, reasons)
+    }
+}
+
+#[derive(Default)]
+pub struct HealthTracker {
+    instances: HashMap<InstanceUuid, InstanceHealth>,
+}
+
+impl HealthTracker {
+    /// Healthy for instances nothing is known about
+    pub fn status(&self, uuid: &InstanceUuid) -> HealthStatus {
+        self.instances
+            .get(uuid)
+            .map(|health| health.status)
+            .unwrap_or_default()
+    }
+
+    /// The last TPS reading seen in the console, if it isn't too old
+    pub fn tps(&self, uuid: &InstanceUuid, now: i64) -> Option<f64> {
+        match self.instances.get(uuid)?.tps {
+            Some((at, tps)) if now - at <= TPS_MAX_AGE_SECS => Some(tps),
+            _ => None,
+        }
+    }
+
+    pub fn expect_kill(&mut self, uuid: &InstanceUuid) {
+        if let Some(health) = self.instances.get_mut(uuid) {
+            health.kill_requested = true;
+        }
+    }
+
+    pub fn forget(&mut self, uuid: &InstanceUuid) {
+        self.instances.remove(uuid);
+    }
+
+    fn observe(&mut self, event: &Event) {
+        if let EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid,
+            instance_name,
+            instance_event_inner,
+        }) = &event.event_inner
+        {
+            let health = self.instances.entry(instance_uuid.clone()).or_default();
+            health.name = instance_name.clone();
+            health.observe(instance_event_inner, chrono::Utc::now().timestamp());
+        }
+    }
+
+    /// Events for the instances whose status changed
+    fn evaluate(&mut self) -> Vec<Event> {
+        let now = chrono::Utc::now().timestamp();
+        let mut ret = Vec::new();
+        for (uuid, health) in self.instances.iter_mut() {
+            let (status, reasons) = health.evaluate(now);
+            if status == health.status {
+                continue;
+            }
+            health.status = status;
+            ret.push(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: uuid.clone(),
+                    instance_name: health.name.clone(),
+                    instance_event_inner: InstanceEventInner::HealthChanged {
+                        health: status,
+                        reasons,
+                    },
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: CausedBy::System,
+            });
+        }
+        ret
+    }
+}
+
+/// Follows instance events and re-rates instances until the core shuts down
+pub async fn run_health(state: AppState) {
+    let mut events = state
+        .event_broadcaster
+        .subscribe_filtered(SubscriptionFilter {
+            event_types: Some(vec![EventType::InstanceEvent]),
+            ..Default::default()
+        });
+    let mut interval = tokio::time::interval(EVALUATE_INTERVAL);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => state.health.lock().await.observe(&event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            _ = interval.tick() => {
+                let changes = state.health.lock().await.evaluate();
+                for event in changes {
+                    state.event_broadcaster.send(event);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_health() {
+        let mut health = InstanceHealth::default();
+        let transition = |to| InstanceEventInner::StateTransition { to };
+        health.observe(&transition(State::Starting), 0);
+        health.observe(&transition(State::Running), 10);
+        health.observe(&transition(State::Stopped), 20);
+        assert_eq!(health.evaluate(30).0, HealthStatus::Degraded);
+
+        // a requested stop is not a crash
+        health.observe(&transition(State::Starting), 40);
+        health.observe(&transition(State::Running), 50);
+        health.observe(&transition(State::Stopping), 60);
+        health.observe(&transition(State::Stopped), 70);
+        assert_eq!(health.crashes.len(), 1);
+
+        health.observe(
+            &InstanceEventInner::SystemMessage {
+                message: "TPS from last 1m, 5m, 15m: 9.5, 15.0, 19.0".to_string(),
+            },
+            80,
+        );
+        let (status, reasons) = health.evaluate(90);
+        assert_eq!(status, HealthStatus::Unhealthy);
+        assert_eq!(reasons.len(), 2);
+
+        // both fall out of their windows
+        assert_eq!(
+            health.evaluate(20 + CRASH_WINDOW_SECS + 1).0,
+            HealthStatus::Healthy
+        );
+    }
+}
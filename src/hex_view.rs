@@ -0,0 +1,122 @@
+//! A window into a file shown as hex and ASCII, like `xxd`,
+//! to look at binary files without downloading them.
+
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::Error;
+
+pub const BYTES_PER_ROW: usize = 16;
+pub const DEFAULT_LENGTH: u64 = 4096;
+/// Most bytes shown at once, more has to be paged through
+pub const MAX_LENGTH: u64 = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct HexRow {
+    pub offset: u64,
+    /// Bytes as space separated pairs of hex digits
+    pub hex: String,
+    /// Bytes as ASCII, with `.` for anything not printable
+    pub ascii: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct HexView {
+    pub offset: u64,
+    /// Bytes actually shown, less than asked for at the end of the file
+    pub length: u64,
+    pub file_size: u64,
+    pub rows: Vec<HexRow>,
+}
+
+pub fn format_rows(offset: u64, bytes: &[u8]) -> Vec<HexRow> {
+    bytes
+        .chunks(BYTES_PER_ROW)
+        .enumerate()
+        .map(|(i, chunk)| HexRow {
+            offset: offset + (i * BYTES_PER_ROW) as u64,
+            hex: chunk
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            ascii: chunk
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Reads up to `length` bytes from `offset`, capped to [`MAX_LENGTH`]
+pub fn read_window(path: &Path, offset: u64, length: u64) -> Result<HexView, Error> {
+    let mut file =
+        std::fs::File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let file_size = file
+        .metadata()
+        .context(format!("Failed to read metadata of {}", path.display()))?
+        .len();
+    let offset = offset.min(file_size);
+    file.seek(SeekFrom::Start(offset))
+        .context(format!("Failed to seek in {}", path.display()))?;
+    let mut bytes = Vec::new();
+    file.take(length.min(MAX_LENGTH))
+        .read_to_end(&mut bytes)
+        .context(format!("Failed to read {}", path.display()))?;
+    Ok(HexView {
+        offset,
+        length: bytes.len() as u64,
+        file_size,
+        rows: format_rows(offset, &bytes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_rows() {
+        let bytes: Vec<u8> = b"Hello, region!\x00\x01\xff\x0aend".to_vec();
+        let rows = format_rows(32, &bytes);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].offset, 32);
+        assert_eq!(
+            rows[0].hex,
+            "48 65 6c 6c 6f 2c 20 72 65 67 69 6f 6e 21 00 01"
+        );
+        assert_eq!(rows[0].ascii, "Hello, region!..");
+        assert_eq!(rows[1].offset, 48);
+        assert_eq!(rows[1].hex, "ff 0a 65 6e 64");
+        assert_eq!(rows[1].ascii, "..end");
+    }
+
+    #[test]
+    fn test_read_window() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("level.dat");
+        std::fs::write(&path, (0..=255u8).collect::<Vec<_>>()).unwrap();
+
+        let view = read_window(&path, 250, 100).unwrap();
+        assert_eq!((view.offset, view.length, view.file_size), (250, 6, 256));
+        assert_eq!(view.rows[0].hex, "fa fb fc fd fe ff");
+
+        let view = read_window(&path, 1000, 16).unwrap();
+        assert_eq!((view.offset, view.length), (256, 0));
+        assert!(view.rows.is_empty());
+    }
+}
@@ -0,0 +1,401 @@
+//! Message catalog for user facing strings.
+//!
+//! Errors and progression events can carry a [`LocalizedMessage`], a message id plus its parameters,
+//! next to their English text. Frontends can translate the id themselves with the catalog served at
+//! `/i18n/:locale`, and errors are additionally rendered in the locale of the requesting user.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use axum::{
+    http::{header, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::AppState;
+
+pub use lodestone_types::Locale;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum MessageId {
+    Unauthorized,
+    CredentialMismatch,
+    PermissionDenied,
+    TooManyRequests,
+    InstanceNotFound,
+    InstanceMustBeStopped,
+    UserNotFound,
+    InvalidSetupKey,
+    AlreadySetup,
+    InstanceCreationStarted,
+    InstanceCreationSucceeded,
+    InstanceCreationFailed,
+    InstanceDeletionStarted,
+    InstanceDeletionSucceeded,
+    InstanceDeletionFailed,
+    InsufficientDiskSpace,
+    InstanceQuotaExceeded,
+    DiskQuotaExceeded,
+    RamQuotaExceeded,
+    InvalidInvite,
+    MaintenanceMode,
+}
+
+impl MessageId {
+    pub const ALL: &'static [MessageId] = &[
+        MessageId::Unauthorized,
+        MessageId::CredentialMismatch,
+        MessageId::PermissionDenied,
+        MessageId::TooManyRequests,
+        MessageId::InstanceNotFound,
+        MessageId::InstanceMustBeStopped,
+        MessageId::UserNotFound,
+        MessageId::InvalidSetupKey,
+        MessageId::AlreadySetup,
+        MessageId::InstanceCreationStarted,
+        MessageId::InstanceCreationSucceeded,
+        MessageId::InstanceCreationFailed,
+        MessageId::InstanceDeletionStarted,
+        MessageId::InstanceDeletionSucceeded,
+        MessageId::InstanceDeletionFailed,
+        MessageId::InsufficientDiskSpace,
+        MessageId::InstanceQuotaExceeded,
+        MessageId::DiskQuotaExceeded,
+        MessageId::RamQuotaExceeded,
+        MessageId::InvalidInvite,
+        MessageId::MaintenanceMode,
+    ];
+
+    /// The template for this message in `locale`, with `{param}` placeholders
+    pub fn template(self, locale: Locale) -> &'static str {
+        use Locale::*;
+        use MessageId::*;
+        match (self, locale) {
+            (Unauthorized, En) => "Unauthorized",
+            (Unauthorized, Fr) => "Non autorisé",
+            (Unauthorized, De) => "Nicht autorisiert",
+            (Unauthorized, Es) => "No autorizado",
+            (Unauthorized, ZhCn) => "未授权",
+
+            (CredentialMismatch, En) => "Credential mismatch",
+            (CredentialMismatch, Fr) => "Identifiants incorrects",
+            (CredentialMismatch, De) => "Ungültige Anmeldedaten",
+            (CredentialMismatch, Es) => "Credenciales incorrectas",
+            (CredentialMismatch, ZhCn) => "用户名或密码错误",
+
+            (PermissionDenied, En) => "You don't have permission to perform this action",
+            (PermissionDenied, Fr) => "Vous n'avez pas la permission d'effectuer cette action",
+            (PermissionDenied, De) => "Sie haben keine Berechtigung für diese Aktion",
+            (PermissionDenied, Es) => "No tiene permiso para realizar esta acción",
+            (PermissionDenied, ZhCn) => "你没有执行此操作的权限",
+
+            (TooManyRequests, En) => "Too many requests, slow down",
+            (TooManyRequests, Fr) => "Trop de requêtes, veuillez ralentir",
+            (TooManyRequests, De) => "Zu viele Anfragen, bitte langsamer",
+            (TooManyRequests, Es) => "Demasiadas solicitudes, vaya más despacio",
+            (TooManyRequests, ZhCn) => "请求过于频繁，请稍后再试",
+
+            (InstanceNotFound, En) => "Instance not found",
+            (InstanceNotFound, Fr) => "Instance introuvable",
+            (InstanceNotFound, De) => "Instanz nicht gefunden",
+            (InstanceNotFound, Es) => "Instancia no encontrada",
+            (InstanceNotFound, ZhCn) => "找不到实例",
+
+            (InstanceMustBeStopped, En) => "Instance must be stopped before deletion",
+            (InstanceMustBeStopped, Fr) => "L'instance doit être arrêtée avant d'être supprimée",
+            (InstanceMustBeStopped, De) => "Die Instanz muss vor dem Löschen gestoppt werden",
+            (InstanceMustBeStopped, Es) => "La instancia debe detenerse antes de eliminarla",
+            (InstanceMustBeStopped, ZhCn) => "删除前必须先停止实例",
+
+            (UserNotFound, En) => "User not found",
+            (UserNotFound, Fr) => "Utilisateur introuvable",
+            (UserNotFound, De) => "Benutzer nicht gefunden",
+            (UserNotFound, Es) => "Usuario no encontrado",
+            (UserNotFound, ZhCn) => "找不到用户",
+
+            (InvalidSetupKey, En) => "Invalid setup key",
+            (InvalidSetupKey, Fr) => "Clé de configuration invalide",
+            (InvalidSetupKey, De) => "Ungültiger Einrichtungsschlüssel",
+            (InvalidSetupKey, Es) => "Clave de configuración no válida",
+            (InvalidSetupKey, ZhCn) => "设置密钥无效",
+
+            (AlreadySetup, En) => "Core is already setup",
+            (AlreadySetup, Fr) => "Le serveur est déjà configuré",
+            (AlreadySetup, De) => "Der Core ist bereits eingerichtet",
+            (AlreadySetup, Es) => "El núcleo ya está configurado",
+            (AlreadySetup, ZhCn) => "核心已完成设置",
+
+            (InstanceCreationStarted, En) => "Setting up {game} server {instance_name}",
+            (InstanceCreationStarted, Fr) => "Installation du serveur {game} {instance_name}",
+            (InstanceCreationStarted, De) => "{game}-Server {instance_name} wird eingerichtet",
+            (InstanceCreationStarted, Es) => "Configurando el servidor de {game} {instance_name}",
+            (InstanceCreationStarted, ZhCn) => "正在设置 {game} 服务器 {instance_name}",
+
+            (InstanceCreationSucceeded, En) => "Instance created successfully",
+            (InstanceCreationSucceeded, Fr) => "Instance créée avec succès",
+            (InstanceCreationSucceeded, De) => "Instanz erfolgreich erstellt",
+            (InstanceCreationSucceeded, Es) => "Instancia creada correctamente",
+            (InstanceCreationSucceeded, ZhCn) => "实例创建成功",
+
+            (InstanceCreationFailed, En) => "Instance creation failed: {error}",
+            (InstanceCreationFailed, Fr) => "Échec de la création de l'instance : {error}",
+            (InstanceCreationFailed, De) => "Erstellen der Instanz fehlgeschlagen: {error}",
+            (InstanceCreationFailed, Es) => "Error al crear la instancia: {error}",
+            (InstanceCreationFailed, ZhCn) => "实例创建失败：{error}",
+
+            (InstanceDeletionStarted, En) => "Deleting instance {instance_name}",
+            (InstanceDeletionStarted, Fr) => "Suppression de l'instance {instance_name}",
+            (InstanceDeletionStarted, De) => "Instanz {instance_name} wird gelöscht",
+            (InstanceDeletionStarted, Es) => "Eliminando la instancia {instance_name}",
+            (InstanceDeletionStarted, ZhCn) => "正在删除实例 {instance_name}",
+
+            (InstanceDeletionSucceeded, En) => "Instance deleted successfully",
+            (InstanceDeletionSucceeded, Fr) => "Instance supprimée avec succès",
+            (InstanceDeletionSucceeded, De) => "Instanz erfolgreich gelöscht",
+            (InstanceDeletionSucceeded, Es) => "Instancia eliminada correctamente",
+            (InstanceDeletionSucceeded, ZhCn) => "实例删除成功",
+
+            (InstanceDeletionFailed, En) => {
+                "Failed to delete some or all of the instance's files: {error}"
+            }
+            (InstanceDeletionFailed, Fr) => {
+                "Impossible de supprimer tout ou partie des fichiers de l'instance : {error}"
+            }
+            (InstanceDeletionFailed, De) => {
+                "Einige oder alle Dateien der Instanz konnten nicht gelöscht werden: {error}"
+            }
+            (InstanceDeletionFailed, Es) => {
+                "No se pudieron eliminar algunos o todos los archivos de la instancia: {error}"
+            }
+            (InstanceDeletionFailed, ZhCn) => "无法删除实例的部分或全部文件：{error}",
+
+            (InsufficientDiskSpace, En) => {
+                "Not enough disk space: {required} needed, {available} available"
+            }
+            (InsufficientDiskSpace, Fr) => {
+                "Espace disque insuffisant : {required} nécessaires, {available} disponibles"
+            }
+            (InsufficientDiskSpace, De) => {
+                "Nicht genügend Speicherplatz: {required} benötigt, {available} verfügbar"
+            }
+            (InsufficientDiskSpace, Es) => {
+                "Espacio en disco insuficiente: se necesitan {required}, hay {available} disponibles"
+            }
+            (InsufficientDiskSpace, ZhCn) => "磁盘空间不足：需要 {required}，可用 {available}",
+
+            (InstanceQuotaExceeded, En) => "Instance quota exceeded: you may own at most {limit}",
+            (InstanceQuotaExceeded, Fr) => {
+                "Quota d'instances dépassé : vous pouvez en posséder au plus {limit}"
+            }
+            (InstanceQuotaExceeded, De) => {
+                "Instanzkontingent überschritten: Sie dürfen höchstens {limit} besitzen"
+            }
+            (InstanceQuotaExceeded, Es) => {
+                "Cuota de instancias superada: puede tener como máximo {limit}"
+            }
+            (InstanceQuotaExceeded, ZhCn) => "超出实例配额：最多可拥有 {limit} 个",
+
+            (DiskQuotaExceeded, En) => "Disk quota exceeded: your instances may use at most {limit}",
+            (DiskQuotaExceeded, Fr) => {
+                "Quota disque dépassé : vos instances peuvent utiliser au plus {limit}"
+            }
+            (DiskQuotaExceeded, De) => {
+                "Speicherkontingent überschritten: Ihre Instanzen dürfen höchstens {limit} belegen"
+            }
+            (DiskQuotaExceeded, Es) => {
+                "Cuota de disco superada: sus instancias pueden usar como máximo {limit}"
+            }
+            (DiskQuotaExceeded, ZhCn) => "超出磁盘配额：您的实例最多可使用 {limit}",
+
+            (RamQuotaExceeded, En) => "RAM quota exceeded: your instances may use at most {limit}",
+            (RamQuotaExceeded, Fr) => {
+                "Quota de RAM dépassé : vos instances peuvent utiliser au plus {limit}"
+            }
+            (RamQuotaExceeded, De) => {
+                "RAM-Kontingent überschritten: Ihre Instanzen dürfen höchstens {limit} belegen"
+            }
+            (RamQuotaExceeded, Es) => {
+                "Cuota de RAM superada: sus instancias pueden usar como máximo {limit}"
+            }
+            (RamQuotaExceeded, ZhCn) => "超出内存配额：您的实例最多可使用 {limit}",
+
+            (InvalidInvite, En) => "This invite is invalid, expired or used up",
+            (InvalidInvite, Fr) => "Cette invitation est invalide, expirée ou épuisée",
+            (InvalidInvite, De) => "Diese Einladung ist ungültig, abgelaufen oder aufgebraucht",
+            (InvalidInvite, Es) => "Esta invitación no es válida, ha caducado o se ha agotado",
+            (InvalidInvite, ZhCn) => "此邀请无效、已过期或已用完",
+            (MaintenanceMode, En) => {
+                "The core is under maintenance, only admins can make changes right now"
+            }
+            (MaintenanceMode, Fr) => {
+                "Le core est en maintenance, seuls les administrateurs peuvent effectuer des modifications pour le moment"
+            }
+            (MaintenanceMode, De) => {
+                "Der Core wird gerade gewartet, nur Administratoren können Änderungen vornehmen"
+            }
+            (MaintenanceMode, Es) => {
+                "El núcleo está en mantenimiento, solo los administradores pueden hacer cambios por ahora"
+            }
+            (MaintenanceMode, ZhCn) => "核心正在维护中，目前只有管理员可以进行更改",
+        }
+    }
+}
+
+/// A message id and the parameters to substitute into its template
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LocalizedMessage {
+    pub id: MessageId,
+    pub params: BTreeMap<String, String>,
+}
+
+impl LocalizedMessage {
+    pub fn new(id: MessageId) -> Self {
+        Self {
+            id,
+            params: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_param(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        self.params.insert(key.into(), value.to_string());
+        self
+    }
+
+    /// Placeholders are filled in a single pass, so ones that happen to be in a value are kept
+    pub fn render(&self, locale: Locale) -> String {
+        let mut rest = self.id.template(locale);
+        let mut rendered = String::with_capacity(rest.len());
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            match after
+                .find('}')
+                .and_then(|end| Some((end, self.params.get(&after[..end])?)))
+            {
+                Some((end, value)) => {
+                    rendered.push_str(value);
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = after;
+                }
+            }
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+}
+
+/// A [`LocalizedMessage`] along with its rendering in the locale of the request
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct RenderedMessage {
+    pub id: MessageId,
+    pub params: BTreeMap<String, String>,
+    pub text: String,
+}
+
+impl From<&LocalizedMessage> for RenderedMessage {
+    fn from(message: &LocalizedMessage) -> Self {
+        Self {
+            id: message.id,
+            params: message.params.clone(),
+            text: message.render(current_locale()),
+        }
+    }
+}
+
+/// Renders in English, so a localized message can be used as the source of an [`crate::error::Error`]
+impl Display for LocalizedMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(Locale::En))
+    }
+}
+
+tokio::task_local! {
+    static REQUEST_LOCALE: Locale;
+}
+
+/// The locale of the request being handled, English outside of a request
+pub fn current_locale() -> Locale {
+    REQUEST_LOCALE
+        .try_with(|locale| *locale)
+        .unwrap_or_default()
+}
+
+/// Resolves the locale of a request from the user's preference,
+/// falling back to the `Accept-Language` header
+pub async fn negotiate_locale<B>(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let user_locale = match request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(token) => state
+            .users_manager
+            .read()
+            .await
+            .try_auth(token)
+            .and_then(|user| user.locale),
+        None => None,
+    };
+    let locale = user_locale
+        .or_else(|| {
+            request
+                .headers()
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(Locale::from_accept_language)
+        })
+        .unwrap_or_default();
+    REQUEST_LOCALE.scope(locale, next.run(request)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let message = LocalizedMessage::new(MessageId::InstanceDeletionStarted)
+            .with_param("instance_name", "survival");
+        assert_eq!(message.render(Locale::En), "Deleting instance survival");
+        assert_eq!(message.render(Locale::De), "Instanz survival wird gelöscht");
+        assert_eq!(message.to_string(), "Deleting instance survival");
+    }
+
+    #[test]
+    fn test_render_placeholder_in_param() {
+        let message = LocalizedMessage::new(MessageId::InstanceCreationStarted)
+            .with_param("game", "{instance_name}")
+            .with_param("instance_name", "{error}");
+        assert_eq!(
+            message.render(Locale::En),
+            "Setting up {instance_name} server {error}"
+        );
+    }
+
+    #[test]
+    fn test_accept_language() {
+        assert_eq!(
+            Locale::from_accept_language("fr-CH, fr;q=0.9, en;q=0.8"),
+            Some(Locale::Fr)
+        );
+        assert_eq!(
+            Locale::from_accept_language("ja, zh-CN;q=0.5"),
+            Some(Locale::ZhCn)
+        );
+        assert_eq!(Locale::from_accept_language("ja"), None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+
+use crate::{
+    error::Error,
+    traits::t_configurable::{
+        manifest::{ConfigurableManifest, ConfigurableValue},
+        Game, GameType, TConfigurable,
+    },
+    types::InstanceUuid,
+};
+
+use super::FakeInstance;
+
+#[async_trait]
+impl TConfigurable for FakeInstance {
+    async fn uuid(&self) -> InstanceUuid {
+        self.uuid.clone()
+    }
+
+    async fn name(&self) -> String {
+        self.config.lock().await.name.clone()
+    }
+
+    async fn game_type(&self) -> Game {
+        Game::Generic {
+            game_name: GameType::Generic,
+            game_display_name: "Fake Server".to_string(),
+        }
+    }
+
+    async fn version(&self) -> String {
+        "fake".to_string()
+    }
+
+    async fn description(&self) -> String {
+        self.config.lock().await.description.clone()
+    }
+
+    async fn port(&self) -> u32 {
+        self.config.lock().await.port
+    }
+
+    async fn creation_time(&self) -> i64 {
+        self.creation_time
+    }
+
+    async fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    async fn auto_start(&self) -> bool {
+        self.config.lock().await.auto_start
+    }
+
+    async fn restart_on_crash(&self) -> bool {
+        self.config.lock().await.restart_on_crash
+    }
+
+    async fn set_name(&mut self, name: String) -> Result<(), Error> {
+        self.config.lock().await.name = name;
+        Ok(())
+    }
+
+    async fn set_description(&mut self, description: String) -> Result<(), Error> {
+        self.config.lock().await.description = description;
+        Ok(())
+    }
+
+    async fn set_port(&mut self, port: u32) -> Result<(), Error> {
+        self.config.lock().await.port = port;
+        Ok(())
+    }
+
+    async fn set_auto_start(&mut self, auto_start: bool) -> Result<(), Error> {
+        self.config.lock().await.auto_start = auto_start;
+        Ok(())
+    }
+
+    async fn set_restart_on_crash(&mut self, restart_on_crash: bool) -> Result<(), Error> {
+        self.config.lock().await.restart_on_crash = restart_on_crash;
+        Ok(())
+    }
+
+    async fn set_console_buffer_lines(&mut self, lines: u32) -> Result<(), Error> {
+        self.console_buffer.set_capacity(lines).await;
+        Ok(())
+    }
+
+    async fn configurable_manifest(&mut self) -> ConfigurableManifest {
+        let config = self.config.lock().await.clone();
+        ConfigurableManifest::new(
+            config.auto_start,
+            config.restart_on_crash,
+            self.configurable_manifest.lock().await.get_all_sections(),
+        )
+    }
+
+    async fn update_configurable(
+        &mut self,
+        section_id: &str,
+        setting_id: &str,
+        value: ConfigurableValue,
+    ) -> Result<(), Error> {
+        self.configurable_manifest
+            .lock()
+            .await
+            .update_setting_value(section_id, setting_id, value)
+    }
+}
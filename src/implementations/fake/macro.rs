@@ -0,0 +1,150 @@
+use std::{cell::RefCell, rc::Rc};
+
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Context};
+use deno_core::{anyhow, op, OpState};
+
+use crate::{
+    error::Error,
+    events::CausedBy,
+    implementations::minecraft::r#macro::{read_macro_parameters, resolve_macro_invocation},
+    macro_executor::{self, MacroPID, SpawnResult, WorkerOptionGenerator},
+    traits::{
+        t_macro::{parse_macro_args, HistoryEntry, MacroEntry, TMacro, TaskEntry},
+        t_server::TServer,
+    },
+};
+
+use super::FakeInstance;
+
+#[op]
+async fn send_stdin(state: Rc<RefCell<OpState>>, cmd: String) -> Result<(), anyhow::Error> {
+    let instance = state.borrow().borrow::<FakeInstance>().clone();
+    instance.send_command(&cmd, CausedBy::Unknown).await?;
+    Ok(())
+}
+
+/// Macros can only write to the console of a fake server
+struct FakeMainWorkerGenerator {
+    instance: FakeInstance,
+}
+
+impl WorkerOptionGenerator for FakeMainWorkerGenerator {
+    fn generate(&self) -> deno_runtime::worker::WorkerOptions {
+        let ext = deno_core::Extension::builder("fake_deno_extension_builder")
+            .ops(vec![send_stdin::decl()])
+            .state({
+                let instance = self.instance.clone();
+                move |state| {
+                    state.put(instance);
+                }
+            })
+            .force_op_registration()
+            .build();
+        deno_runtime::worker::WorkerOptions {
+            extensions: vec![ext],
+            module_loader: Rc::new(macro_executor::TypescriptModuleLoader::default()),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl TMacro for FakeInstance {
+    async fn get_macro_list(&self) -> Result<Vec<MacroEntry>, Error> {
+        let mut ret = Vec::new();
+        for entry in
+            (std::fs::read_dir(&self.path_to_macros).context("Failed to read macro dir")?).flatten()
+        {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(path_to_macro) = resolve_macro_invocation(&self.path_to_macros, &name) {
+                ret.push(MacroEntry {
+                    parameters: read_macro_parameters(&self.path_to_macros, &path_to_macro)
+                        .unwrap_or_default(),
+                    global_enabled: None,
+                    name,
+                    last_run: None,
+                    path: entry.path(),
+                });
+            }
+        }
+        ret.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ret)
+    }
+
+    async fn get_task_list(&self) -> Result<Vec<TaskEntry>, Error> {
+        let mut ret = Vec::new();
+        for (pid, task_entry) in self.pid_to_task_entry.lock().await.iter() {
+            if self.macro_executor.get_macro_status(*pid).await.is_none() {
+                ret.push(task_entry.clone());
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn get_history_list(&self) -> Result<Vec<HistoryEntry>, Error> {
+        let mut ret = Vec::new();
+        for (pid, task_entry) in self.pid_to_task_entry.lock().await.iter() {
+            if let Some(exit_status) = self.macro_executor.get_macro_status(*pid).await {
+                ret.push(HistoryEntry {
+                    task: task_entry.clone(),
+                    exit_status,
+                });
+            }
+        }
+        ret.sort_by(|a, b| b.exit_status.time().cmp(&a.exit_status.time()));
+        Ok(ret)
+    }
+
+    async fn delete_macro(&mut self, name: &str) -> Result<(), Error> {
+        crate::util::fs::remove_file(self.path_to_macros.join(name)).await
+    }
+
+    async fn create_macro(&mut self, name: &str, content: &str) -> Result<(), Error> {
+        crate::util::fs::write_all(self.path_to_macros.join(name), content.as_bytes().to_vec())
+            .await
+    }
+
+    async fn run_macro(
+        &mut self,
+        name: &str,
+        args: Vec<String>,
+        caused_by: CausedBy,
+    ) -> Result<TaskEntry, Error> {
+        let path_to_macro = resolve_macro_invocation(&self.path_to_macros, name)
+            .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
+        let args = parse_macro_args(
+            &read_macro_parameters(&self.path_to_macros, &path_to_macro)?,
+            args,
+        )?;
+        let SpawnResult { macro_pid: pid, .. } = self
+            .macro_executor
+            .spawn(
+                path_to_macro,
+                args,
+                caused_by,
+                Box::new(FakeMainWorkerGenerator {
+                    instance: self.clone(),
+                }),
+                None,
+                Some(self.uuid.clone()),
+                None,
+            )
+            .await?;
+        let entry = TaskEntry {
+            pid,
+            name: name.to_string(),
+            creation_time: chrono::Utc::now().timestamp(),
+        };
+        self.pid_to_task_entry
+            .lock()
+            .await
+            .insert(pid, entry.clone());
+        Ok(entry)
+    }
+
+    async fn kill_macro(&mut self, pid: MacroPID) -> Result<(), Error> {
+        self.macro_executor.abort_macro(pid)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+//! An in-memory stand-in for a game server, so handlers, events, backups and macros can be tested
+//! without downloading and running a real one.
+//!
+//! Only built with the `fake-instance` feature. Nothing is spawned: starting waits out a delay,
+//! the console plays back a script and players can be made to join and leave from the test. The
+//! instance's directory is only used for macros and whatever the test puts there.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use color_eyre::eyre::Context;
+use indexmap::IndexMap;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::{
+    console_buffer::{ConsoleBuffer, DEFAULT_CONSOLE_BUFFER_LINES},
+    error::Error,
+    event_broadcaster::EventBroadcaster,
+    events::Event,
+    macro_executor::{MacroExecutor, MacroPID},
+    traits::{
+        t_action::TAction,
+        t_configurable::manifest::{
+            ConfigurableManifest, ConfigurableValue, ConfigurableValueType, SectionManifest,
+            SettingManifest,
+        },
+        t_macro::TaskEntry,
+        t_resource::TResourceManagement,
+        t_server::State,
+        TInstance,
+    },
+    types::InstanceUuid,
+};
+
+use self::player::FakePlayers;
+
+pub mod configurable;
+mod r#macro;
+pub mod player;
+pub mod server;
+
+/// How the fake server behaves
+#[derive(Clone, Debug)]
+pub struct FakeInstanceOptions {
+    pub name: String,
+    pub port: u32,
+    /// How long the server stays `Starting` before it is `Running`
+    pub startup_delay: Duration,
+    /// How long it stays `Stopping` before it is `Stopped`
+    pub shutdown_delay: Duration,
+    /// Printed to the console once the server is running, one line every `line_interval`
+    pub console_script: Vec<String>,
+    pub line_interval: Duration,
+    /// Players that join as the server comes up
+    pub players: Vec<String>,
+    pub max_players: u32,
+    /// Whether the server crashes once the script has played
+    pub crash_after_script: bool,
+}
+
+impl Default for FakeInstanceOptions {
+    fn default() -> Self {
+        Self {
+            name: "Fake Server".to_string(),
+            port: 25565,
+            startup_delay: Duration::from_millis(100),
+            shutdown_delay: Duration::from_millis(100),
+            console_script: vec!["Done! For help, type \"help\"".to_string()],
+            line_interval: Duration::from_millis(10),
+            players: Vec::new(),
+            max_players: 20,
+            crash_after_script: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct FakeConfig {
+    name: String,
+    description: String,
+    port: u32,
+    auto_start: bool,
+    restart_on_crash: bool,
+    max_players: u32,
+}
+
+#[derive(Clone)]
+pub struct FakeInstance {
+    uuid: InstanceUuid,
+    creation_time: i64,
+    path: PathBuf,
+    path_to_macros: PathBuf,
+    options: FakeInstanceOptions,
+    config: Arc<Mutex<FakeConfig>>,
+    configurable_manifest: Arc<Mutex<ConfigurableManifest>>,
+    state: Arc<Mutex<State>>,
+    started_at: Arc<Mutex<Option<u64>>>,
+    players: Arc<Mutex<FakePlayers>>,
+    /// Everything sent to the console, oldest first
+    commands: Arc<Mutex<Vec<String>>>,
+    /// Plays the script while the server runs
+    run_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    console_buffer: ConsoleBuffer,
+    event_broadcaster: EventBroadcaster,
+    macro_executor: MacroExecutor,
+    pid_to_task_entry: Arc<Mutex<IndexMap<MacroPID, TaskEntry>>>,
+}
+
+impl FakeInstance {
+    pub async fn new(
+        path: PathBuf,
+        options: FakeInstanceOptions,
+        event_broadcaster: EventBroadcaster,
+        macro_executor: MacroExecutor,
+    ) -> Result<FakeInstance, Error> {
+        let path_to_macros = path.join("macros");
+        tokio::fs::create_dir_all(&path_to_macros)
+            .await
+            .context("Failed to create macros directory for fake instance")?;
+        let uuid = InstanceUuid::default();
+        let config = FakeConfig {
+            name: options.name.clone(),
+            description: String::new(),
+            port: options.port,
+            auto_start: false,
+            restart_on_crash: false,
+            max_players: options.max_players,
+        };
+        let console_buffer = ConsoleBuffer::new(DEFAULT_CONSOLE_BUFFER_LINES);
+        console_buffer.collect_from(uuid.clone(), &event_broadcaster);
+        Ok(FakeInstance {
+            players: Arc::new(Mutex::new(FakePlayers::new(
+                uuid.clone(),
+                event_broadcaster.clone(),
+            ))),
+            uuid,
+            creation_time: chrono::Utc::now().timestamp(),
+            path,
+            path_to_macros,
+            options,
+            configurable_manifest: Arc::new(Mutex::new(fake_manifest(&config))),
+            config: Arc::new(Mutex::new(config)),
+            state: Arc::new(Mutex::new(State::Stopped)),
+            started_at: Arc::new(Mutex::new(None)),
+            commands: Arc::new(Mutex::new(Vec::new())),
+            run_task: Arc::new(Mutex::new(None)),
+            console_buffer,
+            event_broadcaster,
+            macro_executor,
+            pid_to_task_entry: Arc::new(Mutex::new(IndexMap::new())),
+        })
+    }
+
+    /// Prints a line to the console, as if the server wrote it
+    pub async fn emit_output(&self, line: impl Into<String>) {
+        let name = self.config.lock().await.name.clone();
+        self.event_broadcaster.send(Event::new_instance_output(
+            self.uuid.clone(),
+            name,
+            line.into(),
+        ));
+    }
+
+    /// Commands received through [`crate::traits::t_server::TServer::send_command`], oldest first
+    pub async fn received_commands(&self) -> Vec<String> {
+        self.commands.lock().await.clone()
+    }
+
+    pub async fn join_player(&self, name: &str) {
+        let instance_name = self.config.lock().await.name.clone();
+        self.players.lock().await.join(name, instance_name);
+    }
+
+    pub async fn leave_player(&self, name: &str) {
+        let instance_name = self.config.lock().await.name.clone();
+        self.players.lock().await.leave(name, instance_name);
+    }
+}
+
+fn fake_manifest(config: &FakeConfig) -> ConfigurableManifest {
+    let mut settings = IndexMap::new();
+    settings.insert(
+        "motd".to_string(),
+        SettingManifest::new_value_with_type(
+            "motd".to_string(),
+            "Message of the day".to_string(),
+            "Does nothing, it is only there to be changed".to_string(),
+            Some(ConfigurableValue::String("A fake server".to_string())),
+            ConfigurableValueType::String { regex: None },
+            None,
+            false,
+            true,
+        ),
+    );
+    let mut sections = IndexMap::new();
+    sections.insert(
+        "fake".to_string(),
+        SectionManifest::new(
+            "fake".to_string(),
+            "Fake Server".to_string(),
+            "Settings of the fake server".to_string(),
+            settings,
+        ),
+    );
+    ConfigurableManifest::new(config.auto_start, config.restart_on_crash, sections)
+}
+
+#[async_trait]
+impl TResourceManagement for FakeInstance {}
+
+#[async_trait]
+impl TAction for FakeInstance {}
+
+impl TInstance for FakeInstance {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{CausedBy, EventInner, InstanceEventInner};
+    use crate::traits::{t_player::TPlayerManagement, t_server::TServer};
+
+    #[tokio::test]
+    async fn test_fake_instance() {
+        let temp_dir = tempdir::TempDir::new("test_fake_instance").unwrap();
+        let (event_broadcaster, _) = EventBroadcaster::new(64);
+        let macro_executor = MacroExecutor::new(event_broadcaster.clone());
+        let mut rx = event_broadcaster.subscribe();
+        let mut instance = FakeInstance::new(
+            temp_dir.path().to_owned(),
+            FakeInstanceOptions {
+                players: vec!["Steve".to_string()],
+                ..Default::default()
+            },
+            event_broadcaster,
+            macro_executor,
+        )
+        .await
+        .unwrap();
+
+        instance.start(CausedBy::System, true).await.unwrap();
+        assert_eq!(instance.state().await, State::Running);
+        assert_eq!(instance.get_player_count().await.unwrap(), 1);
+        instance.join_player("Alex").await;
+        assert_eq!(instance.get_player_count().await.unwrap(), 2);
+        instance
+            .send_command("say hi", CausedBy::System)
+            .await
+            .unwrap();
+        assert_eq!(instance.received_commands().await, vec!["say hi"]);
+        instance.stop(CausedBy::System, true).await.unwrap();
+        assert_eq!(instance.state().await, State::Stopped);
+        assert_eq!(instance.get_player_count().await.unwrap(), 0);
+
+        let mut transitions = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            if let EventInner::InstanceEvent(event) = event.event_inner {
+                if let InstanceEventInner::StateTransition { to } = event.instance_event_inner {
+                    transitions.push(to);
+                }
+            }
+        }
+        assert_eq!(
+            transitions,
+            vec![
+                State::Starting,
+                State::Running,
+                State::Stopping,
+                State::Stopped
+            ]
+        );
+    }
+}
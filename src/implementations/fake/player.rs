@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+
+use crate::{
+    error::Error,
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    implementations::generic::player::GenericPlayer,
+    traits::t_player::{Player, TPlayerManagement},
+    types::{InstanceUuid, Snowflake},
+};
+
+use super::FakeInstance;
+
+/// Fake players show up as generic ones, named and identified by their name
+pub struct FakePlayers {
+    players: HashSet<GenericPlayer>,
+    instance_uuid: InstanceUuid,
+    event_broadcaster: EventBroadcaster,
+}
+
+impl FakePlayers {
+    pub fn new(instance_uuid: InstanceUuid, event_broadcaster: EventBroadcaster) -> Self {
+        Self {
+            players: HashSet::new(),
+            instance_uuid,
+            event_broadcaster,
+        }
+    }
+
+    pub fn join(&mut self, name: &str, instance_name: String) {
+        let player = GenericPlayer {
+            id: name.to_string(),
+            name: name.to_string(),
+        };
+        if self.players.insert(player.clone()) {
+            self.send_change(HashSet::from([player]), HashSet::new(), instance_name);
+        }
+    }
+
+    pub fn leave(&mut self, name: &str, instance_name: String) {
+        if let Some(player) = self.players.iter().find(|p| p.name == name).cloned() {
+            self.players.remove(&player);
+            self.send_change(HashSet::new(), HashSet::from([player]), instance_name);
+        }
+    }
+
+    pub fn clear(&mut self, instance_name: String) {
+        if self.players.is_empty() {
+            return;
+        }
+        let left = std::mem::take(&mut self.players);
+        self.send_change(HashSet::new(), left, instance_name);
+    }
+
+    pub fn list(&self) -> HashSet<Player> {
+        self.players.iter().cloned().map(Player::from).collect()
+    }
+
+    fn send_change(
+        &self,
+        joined: HashSet<GenericPlayer>,
+        left: HashSet<GenericPlayer>,
+        instance_name: String,
+    ) {
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: self.instance_uuid.clone(),
+                instance_name,
+                instance_event_inner: InstanceEventInner::PlayerChange {
+                    player_list: self.list(),
+                    players_joined: joined.into_iter().map(Player::from).collect(),
+                    players_left: left.into_iter().map(Player::from).collect(),
+                },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::Instance {
+                instance_uuid: self.instance_uuid.clone(),
+            },
+        });
+    }
+}
+
+#[async_trait]
+impl TPlayerManagement for FakeInstance {
+    async fn get_player_count(&self) -> Result<u32, Error> {
+        Ok(self.players.lock().await.list().len() as u32)
+    }
+
+    async fn get_max_player_count(&self) -> Result<u32, Error> {
+        Ok(self.config.lock().await.max_players)
+    }
+
+    async fn get_player_list(&self) -> Result<HashSet<Player>, Error> {
+        Ok(self.players.lock().await.list())
+    }
+
+    async fn set_max_player_count(&mut self, max_player_count: u32) -> Result<(), Error> {
+        self.config.lock().await.max_players = max_player_count;
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use color_eyre::eyre::{eyre, Context};
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    traits::t_server::{MonitorReport, State, StateAction, StateTransition, TServer},
+    types::Snowflake,
+};
+
+use super::FakeInstance;
+
+/// What the fake server reports using while it runs
+const FAKE_MEMORY_USAGE: u64 = 512 * 1024 * 1024;
+const FAKE_CPU_USAGE: f32 = 1.5;
+
+impl FakeInstance {
+    async fn transition(
+        &self,
+        action: StateAction,
+        caused_by: &CausedBy,
+        details: &str,
+    ) -> Result<(), Error> {
+        let name = self.config.lock().await.name.clone();
+        self.state.lock().await.try_transition(
+            action,
+            Some(&|state| {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_name: name.clone(),
+                        instance_uuid: self.uuid.clone(),
+                        instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                    }),
+                    snowflake: Snowflake::default(),
+                    details: details.to_string(),
+                    caused_by: caused_by.clone(),
+                });
+            }),
+        )
+    }
+
+    async fn went_down(&self, caused_by: &CausedBy, details: &str) {
+        let name = self.config.lock().await.name.clone();
+        self.players.lock().await.clear(name);
+        *self.started_at.lock().await = None;
+        let _ = self
+            .transition(StateAction::InstanceStop, caused_by, details)
+            .await;
+    }
+
+    async fn crashed(&self) {
+        self.went_down(&CausedBy::System, "Server crashed").await;
+        if self.config.lock().await.restart_on_crash {
+            let mut instance = self.clone();
+            tokio::spawn(async move {
+                let _ = instance.start(CausedBy::System, false).await;
+            });
+        }
+    }
+
+    /// Takes the server down as if its process died, restarting it if it is set to
+    pub async fn crash(&self) {
+        if *self.state.lock().await == State::Stopped {
+            return;
+        }
+        if let Some(task) = self.run_task.lock().await.take() {
+            task.abort();
+        }
+        self.crashed().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl TServer for FakeInstance {
+    async fn start(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
+        self.transition(StateAction::UserStart, &caused_by, "Starting server")
+            .await?;
+        let (ready_tx, ready_rx) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn({
+            let instance = self.clone();
+            async move {
+                tokio::time::sleep(instance.options.startup_delay).await;
+                for player in &instance.options.players {
+                    instance.join_player(player).await;
+                }
+                *instance.started_at.lock().await = Some(chrono::Utc::now().timestamp() as u64);
+                let _ = instance
+                    .transition(StateAction::InstanceStart, &caused_by, "Server started")
+                    .await;
+                let _ = ready_tx.send(());
+                for line in &instance.options.console_script {
+                    tokio::time::sleep(instance.options.line_interval).await;
+                    instance.emit_output(line.clone()).await;
+                }
+                if instance.options.crash_after_script {
+                    instance.crashed().await;
+                }
+            }
+        });
+        *self.run_task.lock().await = Some(task);
+        if block {
+            ready_rx
+                .await
+                .map_err(|_| eyre!("Instance went down before it finished starting"))?;
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
+        self.transition(StateAction::UserStop, &caused_by, "Stopping server")
+            .await?;
+        if let Some(task) = self.run_task.lock().await.take() {
+            task.abort();
+        }
+        let task = tokio::spawn({
+            let instance = self.clone();
+            async move {
+                tokio::time::sleep(instance.options.shutdown_delay).await;
+                instance.went_down(&caused_by, "Server stopped").await;
+            }
+        });
+        if block {
+            task.await.context("Stop task panicked")?;
+        }
+        Ok(())
+    }
+
+    async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
+        if block {
+            self.stop(caused_by.clone(), true).await?;
+            self.start(caused_by, true).await
+        } else {
+            self.state
+                .lock()
+                .await
+                .try_new_state(StateAction::UserStop, None)?;
+            let mut instance = self.clone();
+            tokio::spawn(async move {
+                if instance.stop(caused_by.clone(), true).await.is_ok() {
+                    let _ = instance.start(caused_by, false).await;
+                }
+            });
+            Ok(())
+        }
+    }
+
+    async fn kill(&mut self, caused_by: CausedBy) -> Result<(), Error> {
+        if self.state().await == State::Stopped {
+            return Err(eyre!("Instance is already stopped").into());
+        }
+        if let Some(task) = self.run_task.lock().await.take() {
+            task.abort();
+        }
+        self.went_down(&caused_by, "Server killed").await;
+        Ok(())
+    }
+
+    async fn state(&self) -> State {
+        *self.state.lock().await
+    }
+
+    /// Commands are only recorded, except for `stop` which stops the server
+    async fn send_command(&self, command: &str, caused_by: CausedBy) -> Result<(), Error> {
+        if self.state().await != State::Running {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Instance is not running"),
+            });
+        }
+        self.commands.lock().await.push(command.to_string());
+        if command.trim() == "stop" {
+            self.clone().stop(caused_by, false).await?;
+        }
+        Ok(())
+    }
+
+    async fn monitor(&self) -> MonitorReport {
+        match *self.started_at.lock().await {
+            Some(start_time) => MonitorReport {
+                memory_usage: Some(FAKE_MEMORY_USAGE),
+                cpu_usage: Some(FAKE_CPU_USAGE),
+                start_time: Some(start_time),
+                ..Default::default()
+            },
+            None => MonitorReport::default(),
+        }
+    }
+
+    async fn console_history(&self, lines: usize) -> Vec<Event> {
+        self.console_buffer.last(lines).await
+    }
+}
@@ -8,13 +8,18 @@ use url::Url;
 use self::{
     bridge::procedure_call::{emit_result, next_procedure, proc_bridge_ready, ProcedureCallInner},
     r#macro::GenericMainWorkerGenerator,
+    readiness::Readiness,
 };
 use crate::{
+    console_buffer::{ConsoleBuffer, DEFAULT_CONSOLE_BUFFER_LINES},
     error::Error,
     event_broadcaster::EventBroadcaster,
     events::CausedBy,
+    health::HealthStatus,
+    log_parser::LogPattern,
     macro_executor::{self, MacroExecutor, MacroPID, SpawnResult, WorkerOptionGenerator},
     traits::{
+        t_action::TAction,
         t_configurable::{
             manifest::{SetupManifest, SetupValue},
             TConfigurable,
@@ -31,6 +36,7 @@ mod bridge;
 pub mod configurable;
 mod r#macro;
 pub mod player;
+pub mod readiness;
 pub mod resource;
 pub mod server;
 
@@ -42,6 +48,8 @@ pub struct GenericInstance {
     core_macro_executor: MacroExecutor,
     path: PathBuf,
     core_macro_pid: MacroPID,
+    console_buffer: ConsoleBuffer,
+    readiness: Readiness,
 }
 
 struct InitWorkerGenerator {
@@ -140,6 +148,10 @@ impl GenericInstance {
                 path: path.clone(),
             })
             .await?;
+        let console_buffer = ConsoleBuffer::new(DEFAULT_CONSOLE_BUFFER_LINES);
+        console_buffer.collect_from(dot_lodestone_config.uuid().clone(), &event_broadcaster);
+        let readiness = Readiness::load(&path).await;
+        readiness.watch(dot_lodestone_config.uuid().clone(), &event_broadcaster);
         Ok(GenericInstance {
             dot_lodestone_config,
             procedure_bridge,
@@ -147,6 +159,8 @@ impl GenericInstance {
             core_macro_executor,
             path,
             core_macro_pid,
+            console_buffer,
+            readiness,
         })
     }
 
@@ -181,6 +195,10 @@ impl GenericInstance {
                 path: path_to_instance.clone(),
             })
             .await?;
+        let console_buffer = ConsoleBuffer::new(DEFAULT_CONSOLE_BUFFER_LINES);
+        console_buffer.collect_from(dot_lodestone_config.uuid().clone(), &event_broadcaster);
+        let readiness = Readiness::load(&path_to_instance).await;
+        readiness.watch(dot_lodestone_config.uuid().clone(), &event_broadcaster);
         Ok(GenericInstance {
             dot_lodestone_config,
             procedure_bridge,
@@ -188,6 +206,8 @@ impl GenericInstance {
             core_macro_executor,
             path: path_to_instance,
             core_macro_pid,
+            console_buffer,
+            readiness,
         })
     }
 
@@ -234,6 +254,15 @@ impl GenericInstance {
             .try_into()
     }
 
+    pub async fn log_patterns(&self) -> Vec<LogPattern> {
+        self.readiness.patterns().await
+    }
+
+    /// Only Ready patterns are used for generic instances
+    pub async fn set_log_patterns(&self, log_patterns: Vec<LogPattern>) -> Result<(), Error> {
+        self.readiness.set_patterns(log_patterns).await
+    }
+
     /// Will notify the typescript side that the instance is being destructed
     pub async fn destruct(self) {
         let _ = self
@@ -260,6 +289,9 @@ impl Drop for GenericInstance {
     }
 }
 
+#[async_trait]
+impl TAction for GenericInstance {}
+
 #[async_trait]
 impl TInstance for GenericInstance {
     async fn get_instance_info(&self) -> InstanceInfo {
@@ -270,6 +302,7 @@ impl TInstance for GenericInstance {
             description: self.description().await,
             version: self.version().await,
             port: self.port().await,
+            bedrock_port: self.bedrock_port().await,
             creation_time: self.creation_time().await,
             path: self.path().await.display().to_string(),
             auto_start: self.auto_start().await,
@@ -278,6 +311,8 @@ impl TInstance for GenericInstance {
             player_count: self.get_player_count().await.ok(),
             max_player_count: self.get_max_player_count().await.ok(),
             player_list: self.get_player_list().await.ok(),
+            owner: None,
+            health: HealthStatus::default(),
         }
     }
 }
@@ -1,8 +1,6 @@
 use std::collections::HashSet;
 
 use async_trait::async_trait;
-use serde::{Deserialize, Serialize};
-use ts_rs::TS;
 
 use crate::{
     error::Error,
@@ -11,12 +9,7 @@ use crate::{
 
 use super::{bridge::procedure_call::ProcedureCallInner, GenericInstance};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, TS, Clone, Hash)]
-#[ts(export)]
-pub struct GenericPlayer {
-    pub id: String,
-    pub name: String,
-}
+pub use lodestone_types::GenericPlayer;
 
 impl TPlayer for GenericPlayer {
     fn get_id(&self) -> String {
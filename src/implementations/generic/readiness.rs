@@ -0,0 +1,155 @@
+//! Holding a generic instance in Starting until its server says it is ready.
+//!
+//! Implementations often report Running as soon as they have spawned the server. When the
+//! instance has a Ready pattern, see [`crate::log_parser`], the core reports it as Starting
+//! until a line of output matches, and sends the Running transition itself once one does.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+
+use color_eyre::eyre::Context;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::{
+    error::Error,
+    event_broadcaster::{EventBroadcaster, SubscriptionFilter},
+    events::{CausedBy, Event, EventInner, EventType, InstanceEvent, InstanceEventInner},
+    log_parser::{self, LineParser, LogEvent, LogPattern},
+    traits::t_server::State,
+    types::{InstanceUuid, Snowflake},
+};
+
+const LOG_PATTERNS_FILE: &str = ".lodestone_log_patterns.json";
+
+struct Inner {
+    path: PathBuf,
+    patterns: Mutex<(Vec<LogPattern>, LineParser)>,
+    ready: AtomicBool,
+}
+
+#[derive(Clone)]
+pub struct Readiness {
+    inner: Arc<Inner>,
+}
+
+impl Readiness {
+    /// With the patterns saved in the instance directory, if any
+    pub async fn load(path_to_instance: &Path) -> Self {
+        let path = path_to_instance.join(LOG_PATTERNS_FILE);
+        let patterns: Vec<LogPattern> = match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!("Failed to parse {}: {e}", path.display());
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        let parser = LineParser::custom(&patterns);
+        Self {
+            inner: Arc::new(Inner {
+                path,
+                patterns: Mutex::new((patterns, parser)),
+                ready: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub async fn patterns(&self) -> Vec<LogPattern> {
+        self.inner.patterns.lock().await.0.clone()
+    }
+
+    pub async fn set_patterns(&self, patterns: Vec<LogPattern>) -> Result<(), Error> {
+        log_parser::validate(&patterns)?;
+        tokio::fs::write(
+            &self.inner.path,
+            serde_json::to_string_pretty(&patterns).context("Failed to serialize log patterns")?,
+        )
+        .await
+        .context(format!("Failed to write {}", self.inner.path.display()))?;
+        let parser = LineParser::custom(&patterns);
+        *self.inner.patterns.lock().await = (patterns, parser);
+        Ok(())
+    }
+
+    /// `reported` unless the server has yet to print its ready line
+    pub async fn adjust(&self, reported: State) -> State {
+        if reported == State::Running
+            && !self.inner.ready.load(Ordering::SeqCst)
+            && self.inner.patterns.lock().await.1.has_ready_pattern()
+        {
+            State::Starting
+        } else {
+            reported
+        }
+    }
+
+    /// Follows the instance's events until the instance is dropped
+    pub fn watch(&self, instance_uuid: InstanceUuid, event_broadcaster: &EventBroadcaster) {
+        let inner: Weak<Inner> = Arc::downgrade(&self.inner);
+        let mut events = event_broadcaster.subscribe_filtered(SubscriptionFilter {
+            event_types: Some(vec![EventType::InstanceEvent]),
+            instance_uuids: Some(vec![instance_uuid]),
+            ..Default::default()
+        });
+        let event_broadcaster = event_broadcaster.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let inner = match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => break,
+                };
+                if let EventInner::InstanceEvent(instance_event) = event.event_inner {
+                    Readiness { inner }
+                        .handle(instance_event, &event_broadcaster)
+                        .await;
+                }
+            }
+        });
+    }
+
+    async fn handle(&self, event: InstanceEvent, event_broadcaster: &EventBroadcaster) {
+        let transition = |to: State, details: &str| Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: event.instance_name.clone(),
+                instance_uuid: event.instance_uuid.clone(),
+                instance_event_inner: InstanceEventInner::StateTransition { to },
+            }),
+            snowflake: Snowflake::default(),
+            details: details.to_string(),
+            caused_by: CausedBy::System,
+        };
+        let ready = &self.inner.ready;
+        match &event.instance_event_inner {
+            InstanceEventInner::StateTransition { to: State::Running } => {
+                if !ready.load(Ordering::SeqCst)
+                    && self.inner.patterns.lock().await.1.has_ready_pattern()
+                {
+                    event_broadcaster.send(transition(
+                        State::Starting,
+                        "Waiting for the server to be ready",
+                    ));
+                }
+            }
+            // not on Starting, the one sent above can arrive after the ready line
+            InstanceEventInner::StateTransition {
+                to: State::Stopping | State::Stopped | State::Error,
+            } => ready.store(false, Ordering::SeqCst),
+            InstanceEventInner::InstanceOutput { message, .. } if !ready.load(Ordering::SeqCst) => {
+                let is_ready =
+                    self.inner.patterns.lock().await.1.parse(message) == Some(LogEvent::Ready);
+                if is_ready {
+                    ready.store(true, Ordering::SeqCst);
+                    event_broadcaster.send(transition(State::Running, "Server is ready"));
+                }
+            }
+            _ => {}
+        }
+    }
+}
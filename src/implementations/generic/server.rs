@@ -1,6 +1,8 @@
+use tracing::instrument;
+
 use crate::{
     error::Error,
-    events::CausedBy,
+    events::{CausedBy, Event},
     traits::t_server::{MonitorReport, State, TServer},
 };
 
@@ -8,24 +10,28 @@ use super::{bridge::procedure_call::ProcedureCallInner, GenericInstance};
 
 #[async_trait::async_trait]
 impl TServer for GenericInstance {
+    #[instrument(skip_all, fields(instance_uuid = %self.dot_lodestone_config.uuid()))]
     async fn start(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
         self.procedure_bridge
             .call(ProcedureCallInner::StartInstance { caused_by, block })
             .await?;
         Ok(())
     }
+    #[instrument(skip_all, fields(instance_uuid = %self.dot_lodestone_config.uuid()))]
     async fn stop(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
         self.procedure_bridge
             .call(ProcedureCallInner::StopInstance { caused_by, block })
             .await?;
         Ok(())
     }
+    #[instrument(skip_all, fields(instance_uuid = %self.dot_lodestone_config.uuid()))]
     async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
         self.procedure_bridge
             .call(ProcedureCallInner::RestartInstance { caused_by, block })
             .await?;
         Ok(())
     }
+    #[instrument(skip_all, fields(instance_uuid = %self.dot_lodestone_config.uuid()))]
     async fn kill(&mut self, caused_by: CausedBy) -> Result<(), Error> {
         self.procedure_bridge
             .call(ProcedureCallInner::KillInstance { caused_by })
@@ -33,10 +39,12 @@ impl TServer for GenericInstance {
         Ok(())
     }
     async fn state(&self) -> State {
-        self.procedure_bridge
+        let reported = self
+            .procedure_bridge
             .call(ProcedureCallInner::GetState)
             .await
-            .map_or(State::Stopped, |r| r.try_into().unwrap_or(State::Stopped))
+            .map_or(State::Stopped, |r| r.try_into().unwrap_or(State::Stopped));
+        self.readiness.adjust(reported).await
     }
     async fn send_command(&self, command: &str, caused_by: CausedBy) -> Result<(), Error> {
         self.procedure_bridge
@@ -55,4 +63,8 @@ impl TServer for GenericInstance {
                 r.try_into().unwrap_or_default()
             })
     }
+
+    async fn console_history(&self, lines: usize) -> Vec<Event> {
+        self.console_buffer.last(lines).await
+    }
 }
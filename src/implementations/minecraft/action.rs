@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::{
+        t_action::{ActionPermission, InstanceAction, TAction},
+        t_configurable::manifest::{ConfigurableValue, ConfigurableValueType, SettingManifest},
+        t_server::TServer,
+    },
+};
+
+use super::{Flavour, MinecraftInstance};
+
+fn action(name: &str, description: &str, permission: ActionPermission) -> InstanceAction {
+    InstanceAction {
+        name: name.to_string(),
+        description: description.to_string(),
+        parameters: Vec::new(),
+        permission,
+    }
+}
+
+fn actions_of(flavour: &Flavour) -> Vec<InstanceAction> {
+    let mut actions = vec![
+        action(
+            "save_world",
+            "Writes the world to disk",
+            ActionPermission::WriteConsole,
+        ),
+        action(
+            "reload_datapacks",
+            "Reloads data packs, loot tables and functions",
+            ActionPermission::WriteResource,
+        ),
+        action(
+            "reload_whitelist",
+            "Reloads the whitelist from whitelist.json",
+            ActionPermission::AccessSetting,
+        ),
+        InstanceAction {
+            parameters: vec![SettingManifest::new_required_unset(
+                "message".to_string(),
+                "Message".to_string(),
+                "What to say to every player".to_string(),
+                // a line break would end the command and start another one
+                ConfigurableValueType::String {
+                    regex: Some(r"^[^\r\n]+$".to_string()),
+                },
+                false,
+                true,
+            )],
+            ..action(
+                "broadcast",
+                "Says a message to every player",
+                ActionPermission::WriteConsole,
+            )
+        },
+    ];
+    if matches!(flavour, Flavour::Paper { .. } | Flavour::Spigot) {
+        actions.push(action(
+            "reload_plugins",
+            "Reloads every plugin, which some plugins don't handle well",
+            ActionPermission::WriteResource,
+        ));
+    }
+    actions
+}
+
+#[async_trait]
+impl TAction for MinecraftInstance {
+    async fn list_actions(&self) -> Vec<InstanceAction> {
+        actions_of(&self.config.lock().await.flavour)
+    }
+
+    async fn action_command(
+        &self,
+        name: &str,
+        args: &HashMap<String, ConfigurableValue>,
+    ) -> Result<Option<String>, Error> {
+        if !self
+            .list_actions()
+            .await
+            .iter()
+            .any(|action| action.name == name)
+        {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("This instance has no action {}", name),
+            });
+        }
+        let command = match name {
+            "save_world" => "save-all flush".to_string(),
+            "reload_datapacks" => "minecraft:reload".to_string(),
+            "reload_whitelist" => "whitelist reload".to_string(),
+            "broadcast" => format!(
+                "say {}",
+                args.get("message")
+                    .map(|message| message.to_string())
+                    .unwrap_or_default()
+            ),
+            "reload_plugins" => "reload confirm".to_string(),
+            _ => {
+                return Err(Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!("Action {} is listed but not implemented", name),
+                })
+            }
+        };
+        Ok(Some(command))
+    }
+
+    async fn run_action(
+        &mut self,
+        name: &str,
+        args: HashMap<String, ConfigurableValue>,
+        caused_by: CausedBy,
+    ) -> Result<serde_json::Value, Error> {
+        if let Some(command) = self.action_command(name, &args).await? {
+            self.send_command(&command, caused_by).await?;
+        }
+        Ok(serde_json::Value::Null)
+    }
+}
@@ -0,0 +1,238 @@
+//! Taking back control of a server process that outlived the core that started it.
+//!
+//! When a server is started, its pid is recorded in the instance directory.
+//! If the core restarts while the server is still running, the process is adopted instead of
+//! being reported as stopped: console output is followed through `logs/latest.log`,
+//! commands go through RCON, and stopping falls back to SIGTERM when RCON is unavailable.
+
+use std::{io::SeekFrom, time::Duration};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, PidExt, ProcessExt, Signal, SystemExt};
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader};
+use tracing::{info, warn};
+
+use crate::{
+    console_parser::ConsoleLine,
+    error::Error,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    log_parser::{LineParser, LogEvent},
+    process_tree::ProcessTree,
+    traits::t_server::{StateAction, StateTransition},
+    types::Snowflake,
+};
+
+use super::{player::MinecraftPlayer, util::name_to_uuid, MinecraftInstance};
+
+const PROCESS_RECORD_FILE: &str = ".lodestone_process.json";
+
+#[derive(Serialize, Deserialize)]
+struct ProcessRecord {
+    pid: u32,
+    /// Guards against the pid having been reused by an unrelated process
+    start_time: u64,
+}
+
+impl MinecraftInstance {
+    pub(super) async fn write_process_record(&self, pid: u32) -> Result<(), Error> {
+        let start_time = {
+            let mut sys = self.system.lock().await;
+            sys.refresh_process(Pid::from_u32(pid));
+            sys.process(Pid::from_u32(pid))
+                .map(|proc| proc.start_time())
+                .unwrap_or_default()
+        };
+        tokio::fs::write(
+            self.path_to_instance.join(PROCESS_RECORD_FILE),
+            serde_json::to_string(&ProcessRecord { pid, start_time })
+                .context("Failed to serialize process record")?,
+        )
+        .await
+        .context("Failed to write process record")?;
+        Ok(())
+    }
+
+    pub(super) async fn remove_process_record(&self) {
+        let path = self.path_to_instance.join(PROCESS_RECORD_FILE);
+        if path.exists() {
+            if let Err(e) = tokio::fs::remove_file(path).await {
+                warn!("Failed to remove process record: {}", e);
+            }
+        }
+    }
+
+    async fn is_process_alive(&self, pid: u32, start_time: u64) -> bool {
+        let mut sys = self.system.lock().await;
+        sys.refresh_process(Pid::from_u32(pid));
+        sys.process(Pid::from_u32(pid))
+            .map_or(false, |proc| proc.start_time() == start_time)
+    }
+
+    /// Reattaches to the server process left behind by a previous run of the core, if it is still alive
+    pub(super) async fn try_adopt_process(&mut self) {
+        let record: ProcessRecord =
+            match tokio::fs::read(self.path_to_instance.join(PROCESS_RECORD_FILE))
+                .await
+                .ok()
+                .and_then(|content| serde_json::from_slice(&content).ok())
+            {
+                Some(record) => record,
+                None => return,
+            };
+        if !self.is_process_alive(record.pid, record.start_time).await {
+            self.remove_process_record().await;
+            return;
+        }
+        let name = self.config.lock().await.name.clone();
+        info!(
+            "[{}] Reattaching to running server process {}",
+            name, record.pid
+        );
+        *self.adopted_pid.lock().await = Some(record.pid);
+        *self.process_tree.lock().await = Some(ProcessTree::new(record.pid));
+        let _ = self.state.lock().await.try_transition(
+            StateAction::InstanceStart,
+            Some(&|state| {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_name: name.clone(),
+                        instance_uuid: self.uuid.clone(),
+                        instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                    }),
+                    snowflake: Snowflake::default(),
+                    details: "Reattached to running server process".to_string(),
+                    caused_by: CausedBy::System,
+                });
+            }),
+        );
+        self.connect_rcon().await;
+        tokio::spawn({
+            let instance = self.clone();
+            async move { instance.follow_adopted_process(record).await }
+        });
+    }
+
+    /// Streams new lines of `logs/latest.log` as console output until the adopted process exits
+    async fn follow_adopted_process(self, record: ProcessRecord) {
+        let (name, line_parser) = {
+            let config = self.config.lock().await;
+            (config.name.clone(), self.line_parser(&config))
+        };
+        let mut log_reader = match tokio::fs::File::open(
+            self.path_to_instance.join("logs").join("latest.log"),
+        )
+        .await
+        {
+            Ok(mut file) => {
+                let _ = file.seek(SeekFrom::End(0)).await;
+                Some(BufReader::new(file))
+            }
+            Err(e) => {
+                warn!(
+                    "[{}] Failed to open server log, console output will be unavailable: {}",
+                    name, e
+                );
+                None
+            }
+        };
+        let mut interval = tokio::time::interval(Duration::from_millis(500));
+        // kept across ticks so a line the server is still writing is completed on the next read
+        let mut line = String::new();
+        while self.is_process_alive(record.pid, record.start_time).await {
+            interval.tick().await;
+            let log_reader = match log_reader.as_mut() {
+                Some(log_reader) => log_reader,
+                None => continue,
+            };
+            while let Ok(n) = log_reader.read_line(&mut line).await {
+                if n == 0 || !line.ends_with('\n') {
+                    break;
+                }
+                self.handle_adopted_output(&name, &line_parser, std::mem::take(&mut line))
+                    .await;
+            }
+        }
+        info!("Instance {} process shutdown", name);
+        self.adopted_pid.lock().await.take();
+        self.rcon_conn.lock().await.take();
+        self.reap_process_tree().await;
+        self.remove_process_record().await;
+        let _ = self.state.lock().await.try_transition(
+            StateAction::InstanceStop,
+            Some(&|state| {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_name: name.clone(),
+                        instance_uuid: self.uuid.clone(),
+                        instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                    }),
+                    snowflake: Snowflake::default(),
+                    details: "Instance stopping as server process exited".to_string(),
+                    caused_by: CausedBy::System,
+                });
+            }),
+        );
+        self.players_manager.lock().await.clear(name);
+    }
+
+    async fn handle_adopted_output(&self, name: &str, line_parser: &LineParser, line: String) {
+        let console_line = ConsoleLine::parse(&line);
+        let line = console_line.message.clone();
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: console_line.into(),
+                instance_name: name.to_string(),
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        });
+        match line_parser.parse(&line) {
+            Some(LogEvent::PlayerJoined(player_name)) => {
+                self.players_manager.lock().await.add_player(
+                    MinecraftPlayer {
+                        name: player_name.clone(),
+                        uuid: name_to_uuid(&player_name).await,
+                    },
+                    name.to_string(),
+                );
+            }
+            Some(LogEvent::PlayerLeft(player_name)) => {
+                self.players_manager
+                    .lock()
+                    .await
+                    .remove_by_name(&player_name, name.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    /// Asks the adopted process to shut down, through RCON if possible, and SIGTERM otherwise
+    pub(super) async fn stop_adopted_process(&self, pid: u32) -> Result<(), Error> {
+        if self.send_rcon("stop").await.is_ok() {
+            return Ok(());
+        }
+        let mut sys = self.system.lock().await;
+        sys.refresh_process(Pid::from_u32(pid));
+        let proc = sys
+            .process(Pid::from_u32(pid))
+            .ok_or_else(|| color_eyre::eyre::eyre!("Adopted server process not found"))?;
+        // the server's shutdown hook saves the world on SIGTERM
+        match proc.kill_with(Signal::Term) {
+            Some(true) => Ok(()),
+            _ if proc.kill() => Ok(()),
+            _ => Err(color_eyre::eyre::eyre!("Failed to signal adopted server process").into()),
+        }
+    }
+
+    pub(super) async fn kill_adopted_process(&self, pid: u32) -> Result<(), Error> {
+        let mut sys = self.system.lock().await;
+        sys.refresh_process(Pid::from_u32(pid));
+        match sys.process(Pid::from_u32(pid)) {
+            Some(proc) if proc.kill() => Ok(()),
+            _ => Err(color_eyre::eyre::eyre!("Failed to kill adopted server process").into()),
+        }
+    }
+}
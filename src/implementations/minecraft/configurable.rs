@@ -3,17 +3,28 @@ use std::sync::atomic;
 
 use async_trait::async_trait;
 use color_eyre::eyre::{eyre, Context, ContextCompat};
+use tracing::warn;
 
+use crate::console_buffer::MAX_CONSOLE_BUFFER_LINES;
 use crate::error::{Error, ErrorKind};
 use crate::prelude::path_to_tmp;
+use crate::process_priority::ProcessPriority;
+use crate::remote_backup::SECRET_PLACEHOLDER;
+use crate::sandbox::SandboxLevel;
+use crate::startup_watchdog::StartupTimeoutAction;
+use crate::traits::t_configurable::config_file::ConfigFileAdapter;
 use crate::traits::t_configurable::manifest::{
     ConfigurableManifest, ConfigurableValue, ConfigurableValueType, SettingManifest,
 };
+use crate::traits::t_configurable::preview::{
+    diff_manifests, FileDiff, LineDiff, SettingChange, SettingsPreview,
+};
 use crate::traits::t_configurable::{Game, TConfigurable};
 use crate::traits::t_server::State;
 
 use crate::types::InstanceUuid;
 use crate::util::download_file;
+use crate::wasm_macro::{DEFAULT_WASM_MACRO_MEMORY, DEFAULT_WASM_MACRO_TIMEOUT};
 
 use super::util::{get_fabric_jar_url, get_paper_jar_url, get_vanilla_jar_url};
 use super::MinecraftInstance;
@@ -52,6 +63,10 @@ impl TConfigurable for MinecraftInstance {
         self.path_to_instance.clone()
     }
 
+    async fn bedrock_port(&self) -> Option<u32> {
+        self.config.lock().await.bedrock_port
+    }
+
     async fn auto_start(&self) -> bool {
         self.config.lock().await.auto_start
     }
@@ -119,7 +134,7 @@ impl TConfigurable for MinecraftInstance {
         if version == self.config.lock().await.version {
             return Ok(());
         }
-        let (url, _) = match self.config.lock().await.flavour {
+        let (url, flavour) = match self.config.lock().await.flavour {
             super::Flavour::Vanilla => get_vanilla_jar_url(&version).await.ok_or_else(|| {
                 let error_msg =
                     format!("Cannot get the vanilla jar version for version {}", version);
@@ -168,7 +183,40 @@ impl TConfigurable for MinecraftInstance {
         .await?;
         let jar_path = temp_dir.path().join("server.jar");
         crate::util::fs::rename(jar_path, self.path().await.join("server.jar")).await?;
-        self.config.lock().await.version = version;
+        let mut config = self.config.lock().await;
+        config.version = version;
+        // the loader or build that goes with the new version
+        config.flavour = flavour;
+        drop(config);
+        self.write_config_to_file().await
+    }
+
+    async fn set_console_buffer_lines(&mut self, lines: u32) -> Result<(), Error> {
+        if lines > MAX_CONSOLE_BUFFER_LINES {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "The console buffer can keep at most {} lines",
+                    MAX_CONSOLE_BUFFER_LINES
+                ),
+            });
+        }
+        self.console_buffer.set_capacity(lines).await;
+        self.config.lock().await.console_buffer_lines = lines;
+        self.write_config_to_file().await
+    }
+
+    async fn set_config_files(&mut self, config_files: Vec<String>) -> Result<(), Error> {
+        for relative_path in &config_files {
+            let adapter = ConfigFileAdapter::new(&self.path_to_instance, relative_path)?;
+            if !adapter.path().is_file() {
+                return Err(Error {
+                    kind: ErrorKind::NotFound,
+                    source: eyre!("Config file {} does not exist", relative_path),
+                });
+            }
+        }
+        self.config.lock().await.config_files = config_files;
         self.write_config_to_file().await
     }
 
@@ -178,7 +226,19 @@ impl TConfigurable for MinecraftInstance {
             .await
             .clear_section(ServerPropertySetting::get_section_id());
         let _ = self.read_properties().await;
-        self.configurable_manifest.lock().await.clone()
+        let mut manifest = self.configurable_manifest.lock().await.clone();
+        // config files are read fresh every time, they are edited outside of lodestone too
+        for adapter in self.config_file_adapters().await {
+            match adapter.read_section().await {
+                Ok(section) => manifest.insert_section(section),
+                Err(e) => warn!(
+                    "Failed to read config file {}: {}",
+                    adapter.path().display(),
+                    e
+                ),
+            }
+        }
+        manifest
     }
 
     async fn update_configurable(
@@ -187,6 +247,16 @@ impl TConfigurable for MinecraftInstance {
         setting_id: &str,
         value: ConfigurableValue,
     ) -> Result<(), Error> {
+        if let Some(adapter) = self
+            .config_file_adapters()
+            .await
+            .into_iter()
+            .find(|adapter| adapter.section_id() == section_id)
+        {
+            let mut section = adapter.read_section().await?;
+            section.update_setting(setting_id, value)?;
+            return adapter.write_section(&section).await;
+        }
         let _ = self.read_properties().await;
         self.configurable_manifest
             .lock()
@@ -196,6 +266,106 @@ impl TConfigurable for MinecraftInstance {
         self.write_config_to_file().await?;
         self.write_properties_to_file().await
     }
+
+    async fn preview_configurable(
+        &mut self,
+        changes: Vec<SettingChange>,
+    ) -> Result<SettingsPreview, Error> {
+        let current = self.configurable_manifest().await;
+        let mut updated = current.clone();
+        for change in changes {
+            updated.update_setting_value(&change.section_id, &change.setting_id, change.value)?;
+        }
+        // both server.properties and the launch arguments are only read when the server starts
+        let running = *self.state.lock().await != State::Stopped;
+        let settings = diff_manifests(&current, &updated, |_, _| running);
+        let properties = LineDiff::between(
+            &properties_lines(&current, true),
+            &properties_lines(&updated, true),
+        );
+        let files = if properties.is_empty() {
+            Vec::new()
+        } else {
+            vec![FileDiff {
+                path: "server.properties".to_string(),
+                diff: properties,
+            }]
+        };
+        Ok(SettingsPreview {
+            requires_restart: settings.iter().any(|setting| setting.requires_restart),
+            settings,
+            files,
+            launch_args: Some(LineDiff::between(
+                &launch_args(&current),
+                &launch_args(&updated),
+            )),
+        })
+    }
+}
+
+impl MinecraftInstance {
+    async fn config_file_adapters(&self) -> Vec<ConfigFileAdapter> {
+        self.config
+            .lock()
+            .await
+            .config_files
+            .iter()
+            .filter_map(|relative_path| {
+                ConfigFileAdapter::new(&self.path_to_instance, relative_path)
+                    .map_err(|e| warn!("Ignoring config file {}: {}", relative_path, e))
+                    .ok()
+            })
+            .collect()
+    }
+}
+
+/// The lines of server.properties for `manifest`, secret values are replaced
+/// with [`SECRET_PLACEHOLDER`] if `redact_secrets` is set
+pub(super) fn properties_lines(
+    manifest: &ConfigurableManifest,
+    redact_secrets: bool,
+) -> Vec<String> {
+    let section = match manifest.get_section(ServerPropertySetting::get_section_id()) {
+        Some(section) => section,
+        None => return Vec::new(),
+    };
+    section
+        .all_settings()
+        .iter()
+        .map(|(key, setting)| {
+            let value = if redact_secrets && setting.is_secret() {
+                SECRET_PLACEHOLDER.to_string()
+            } else {
+                setting
+                    .get_value()
+                    .expect("Programming error, value is not set")
+                    .to_string()
+            };
+            format!("{}={}", key, value)
+        })
+        .collect()
+}
+
+/// The JVM arguments the server is started with, minus the jar, see `server.rs`
+fn launch_args(manifest: &ConfigurableManifest) -> Vec<String> {
+    let value = |setting: CmdArgSetting| {
+        manifest
+            .get_setting(CmdArgSetting::get_section_id(), setting.get_identifier())
+            .and_then(|setting| setting.get_value())
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+    };
+    let mut args = vec![
+        format!("-Xmx{}M", value(CmdArgSetting::MaxRam(Default::default()))),
+        format!("-Xms{}M", value(CmdArgSetting::MinRam(Default::default()))),
+    ];
+    args.extend(
+        value(CmdArgSetting::Args(Default::default()))
+            .split(' ')
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.to_string()),
+    );
+    args
 }
 
 pub(super) enum InstanceSetting {
@@ -276,6 +446,14 @@ pub(super) enum CmdArgSetting {
     MaxRam(u32),
     JavaCmd(String),
     Args(Vec<String>),
+    UsePty(bool),
+    GcLogging(bool),
+    Sandbox(SandboxLevel),
+    Priority(ProcessPriority),
+    StartupTimeout(u32),
+    OnStartupTimeout(StartupTimeoutAction),
+    WasmMacroMemory(u32),
+    WasmMacroTimeout(u32),
 }
 
 impl CmdArgSetting {
@@ -288,6 +466,14 @@ impl CmdArgSetting {
             CmdArgSetting::MaxRam(_) => "max_ram",
             CmdArgSetting::JavaCmd(_) => "java_cmd",
             CmdArgSetting::Args(_) => "cmd_args",
+            CmdArgSetting::UsePty(_) => "use_pty",
+            CmdArgSetting::GcLogging(_) => "gc_logging",
+            CmdArgSetting::Sandbox(_) => "sandbox",
+            CmdArgSetting::Priority(_) => "priority",
+            CmdArgSetting::StartupTimeout(_) => "startup_timeout",
+            CmdArgSetting::OnStartupTimeout(_) => "on_startup_timeout",
+            CmdArgSetting::WasmMacroMemory(_) => "wasm_macro_memory",
+            CmdArgSetting::WasmMacroTimeout(_) => "wasm_macro_timeout",
         }
     }
     pub fn get_name(&self) -> &'static str {
@@ -296,6 +482,14 @@ impl CmdArgSetting {
             CmdArgSetting::MaxRam(_) => "Maximum RAM",
             CmdArgSetting::JavaCmd(_) => "Java command",
             CmdArgSetting::Args(_) => "Command line arguments",
+            CmdArgSetting::UsePty(_) => "Run in a pseudo terminal",
+            CmdArgSetting::GcLogging(_) => "GC logging",
+            CmdArgSetting::Sandbox(_) => "Sandbox",
+            CmdArgSetting::Priority(_) => "Process priority",
+            CmdArgSetting::StartupTimeout(_) => "Startup timeout",
+            CmdArgSetting::OnStartupTimeout(_) => "On startup timeout",
+            CmdArgSetting::WasmMacroMemory(_) => "WebAssembly macro memory",
+            CmdArgSetting::WasmMacroTimeout(_) => "WebAssembly macro time limit",
         }
     }
     pub fn get_description(&self) -> &'static str {
@@ -308,6 +502,30 @@ impl CmdArgSetting {
             }
             CmdArgSetting::JavaCmd(_) => "The command to use to run the java executable",
             CmdArgSetting::Args(_) => "The command line arguments to pass to the server",
+            CmdArgSetting::UsePty(_) => {
+                "Attach the server to a pseudo terminal instead of pipes. Some servers and wrapper scripts need this to print prompts or colored output"
+            }
+            CmdArgSetting::GcLogging(_) => {
+                "Log garbage collections to rotated files in logs/gc, which can be analyzed to tune RAM and Java flags"
+            }
+            CmdArgSetting::Sandbox(_) => {
+                "Restrict the server on Linux. \"filesystem\" keeps it out of everything but its own directory and the Java runtime, \"strict\" also only lets it listen on the server and RCON ports and blocks syscalls a server doesn't need. Plugins serving web maps need \"filesystem\". Not available with a pseudo terminal"
+            }
+            CmdArgSetting::Priority(_) => {
+                "CPU and disk priority of the server, applied when it starts. Lower it for background servers sharing the machine with busier ones. Raising it needs the core to run as root or administrator"
+            }
+            CmdArgSetting::StartupTimeout(_) => {
+                "Minutes the server may take to finish starting before it is reported as stuck, with the last lines of its output. 0 to wait indefinitely"
+            }
+            CmdArgSetting::OnStartupTimeout(_) => {
+                "What to do with a server stuck starting. \"leave\" keeps it running for inspection, \"retry\" kills it and starts it once more"
+            }
+            CmdArgSetting::WasmMacroMemory(_) => {
+                "Megabytes of memory each WebAssembly macro may use"
+            }
+            CmdArgSetting::WasmMacroTimeout(_) => {
+                "Minutes a WebAssembly macro may run before it is stopped. 0 to let it run until it returns"
+            }
         }
     }
     pub fn from_key_val(key: &str, val: &str) -> Result<Self, Error> {
@@ -322,6 +540,24 @@ impl CmdArgSetting {
             "cmd_args" => Ok(CmdArgSetting::Args(
                 val.split(' ').map(|s| s.to_string()).collect(),
             )),
+            "use_pty" => Ok(CmdArgSetting::UsePty(
+                val.parse().context("Invalid value. Expected a bool")?,
+            )),
+            "gc_logging" => Ok(CmdArgSetting::GcLogging(
+                val.parse().context("Invalid value. Expected a bool")?,
+            )),
+            "sandbox" => Ok(CmdArgSetting::Sandbox(val.parse()?)),
+            "priority" => Ok(CmdArgSetting::Priority(val.parse()?)),
+            "startup_timeout" => Ok(CmdArgSetting::StartupTimeout(
+                val.parse().context("Invalid value. Expected a u32")?,
+            )),
+            "on_startup_timeout" => Ok(CmdArgSetting::OnStartupTimeout(val.parse()?)),
+            "wasm_macro_memory" => Ok(CmdArgSetting::WasmMacroMemory(
+                val.parse().context("Invalid value. Expected a u32")?,
+            )),
+            "wasm_macro_timeout" => Ok(CmdArgSetting::WasmMacroTimeout(
+                val.parse().context("Invalid value. Expected a u32")?,
+            )),
             _ => Err(Error {
                 kind: ErrorKind::BadRequest,
                 source: eyre!("Invalid key"),
@@ -329,7 +565,21 @@ impl CmdArgSetting {
         }
     }
     pub fn is_key_valid(key: &str) -> bool {
-        matches!(key, "min_ram" | "max_ram" | "java_cmd" | "cmd_args")
+        matches!(
+            key,
+            "min_ram"
+                | "max_ram"
+                | "java_cmd"
+                | "cmd_args"
+                | "use_pty"
+                | "gc_logging"
+                | "sandbox"
+                | "priority"
+                | "startup_timeout"
+                | "on_startup_timeout"
+                | "wasm_macro_memory"
+                | "wasm_macro_timeout"
+        )
     }
 }
 
@@ -382,6 +632,117 @@ impl From<CmdArgSetting> for SettingManifest {
                 false,
                 true,
             ),
+            CmdArgSetting::UsePty(use_pty) => SettingManifest::new_optional_value(
+                value.get_identifier().to_owned(),
+                value.get_name().to_owned(),
+                value.get_description().to_owned(),
+                Some(ConfigurableValue::Boolean(use_pty)),
+                ConfigurableValueType::Boolean,
+                Some(ConfigurableValue::Boolean(false)),
+                false,
+                true,
+            ),
+            CmdArgSetting::GcLogging(gc_logging) => SettingManifest::new_optional_value(
+                value.get_identifier().to_owned(),
+                value.get_name().to_owned(),
+                value.get_description().to_owned(),
+                Some(ConfigurableValue::Boolean(gc_logging)),
+                ConfigurableValueType::Boolean,
+                Some(ConfigurableValue::Boolean(false)),
+                false,
+                true,
+            ),
+            CmdArgSetting::Sandbox(sandbox) => SettingManifest::new_optional_value(
+                value.get_identifier().to_owned(),
+                value.get_name().to_owned(),
+                value.get_description().to_owned(),
+                Some(ConfigurableValue::Enum(sandbox.to_string())),
+                ConfigurableValueType::Enum {
+                    options: vec![
+                        "off".to_string(),
+                        "filesystem".to_string(),
+                        "strict".to_string(),
+                    ],
+                },
+                Some(ConfigurableValue::Enum(SandboxLevel::Off.to_string())),
+                false,
+                true,
+            ),
+            CmdArgSetting::Priority(priority) => SettingManifest::new_optional_value(
+                value.get_identifier().to_owned(),
+                value.get_name().to_owned(),
+                value.get_description().to_owned(),
+                Some(ConfigurableValue::Enum(priority.to_string())),
+                ConfigurableValueType::Enum {
+                    options: vec![
+                        "low".to_string(),
+                        "below_normal".to_string(),
+                        "normal".to_string(),
+                        "above_normal".to_string(),
+                        "high".to_string(),
+                    ],
+                },
+                Some(ConfigurableValue::Enum(ProcessPriority::Normal.to_string())),
+                false,
+                true,
+            ),
+            CmdArgSetting::StartupTimeout(startup_timeout) => SettingManifest::new_optional_value(
+                value.get_identifier().to_owned(),
+                value.get_name().to_owned(),
+                value.get_description().to_owned(),
+                Some(ConfigurableValue::UnsignedInteger(startup_timeout)),
+                ConfigurableValueType::UnsignedInteger {
+                    min: Some(0),
+                    max: None,
+                },
+                Some(ConfigurableValue::UnsignedInteger(0)),
+                false,
+                true,
+            ),
+            CmdArgSetting::OnStartupTimeout(action) => SettingManifest::new_optional_value(
+                value.get_identifier().to_owned(),
+                value.get_name().to_owned(),
+                value.get_description().to_owned(),
+                Some(ConfigurableValue::Enum(action.to_string())),
+                ConfigurableValueType::Enum {
+                    options: vec!["leave".to_string(), "retry".to_string()],
+                },
+                Some(ConfigurableValue::Enum(
+                    StartupTimeoutAction::Leave.to_string(),
+                )),
+                false,
+                true,
+            ),
+            CmdArgSetting::WasmMacroMemory(memory) => SettingManifest::new_optional_value(
+                value.get_identifier().to_owned(),
+                value.get_name().to_owned(),
+                value.get_description().to_owned(),
+                Some(ConfigurableValue::UnsignedInteger(memory)),
+                ConfigurableValueType::UnsignedInteger {
+                    min: Some(1),
+                    max: None,
+                },
+                Some(ConfigurableValue::UnsignedInteger(
+                    DEFAULT_WASM_MACRO_MEMORY,
+                )),
+                false,
+                true,
+            ),
+            CmdArgSetting::WasmMacroTimeout(timeout) => SettingManifest::new_optional_value(
+                value.get_identifier().to_owned(),
+                value.get_name().to_owned(),
+                value.get_description().to_owned(),
+                Some(ConfigurableValue::UnsignedInteger(timeout)),
+                ConfigurableValueType::UnsignedInteger {
+                    min: Some(0),
+                    max: None,
+                },
+                Some(ConfigurableValue::UnsignedInteger(
+                    DEFAULT_WASM_MACRO_TIMEOUT,
+                )),
+                false,
+                true,
+            ),
         }
     }
 }
@@ -419,6 +780,57 @@ impl TryFrom<SettingManifest> for CmdArgSetting {
                     .map(|s| s.to_string())
                     .collect(),
             )),
+            "use_pty" => Ok(CmdArgSetting::UsePty(
+                value
+                    .get_value()
+                    .context("Expected a value")?
+                    .try_as_boolean()?,
+            )),
+            "gc_logging" => Ok(CmdArgSetting::GcLogging(
+                value
+                    .get_value()
+                    .context("Expected a value")?
+                    .try_as_boolean()?,
+            )),
+            "sandbox" => Ok(CmdArgSetting::Sandbox(
+                value
+                    .get_value()
+                    .context("Expected a value")?
+                    .try_as_enum()?
+                    .parse()?,
+            )),
+            "priority" => Ok(CmdArgSetting::Priority(
+                value
+                    .get_value()
+                    .context("Expected a value")?
+                    .try_as_enum()?
+                    .parse()?,
+            )),
+            "startup_timeout" => Ok(CmdArgSetting::StartupTimeout(
+                value
+                    .get_value()
+                    .context("Expected a value")?
+                    .try_as_integer()? as u32,
+            )),
+            "on_startup_timeout" => Ok(CmdArgSetting::OnStartupTimeout(
+                value
+                    .get_value()
+                    .context("Expected a value")?
+                    .try_as_enum()?
+                    .parse()?,
+            )),
+            "wasm_macro_memory" => Ok(CmdArgSetting::WasmMacroMemory(
+                value
+                    .get_value()
+                    .context("Expected a value")?
+                    .try_as_integer()? as u32,
+            )),
+            "wasm_macro_timeout" => Ok(CmdArgSetting::WasmMacroTimeout(
+                value
+                    .get_value()
+                    .context("Expected a value")?
+                    .try_as_integer()? as u32,
+            )),
             _ => Err(Error {
                 kind: ErrorKind::BadRequest,
                 source: eyre!("Invalid key"),
@@ -0,0 +1,217 @@
+//! Crossplay with Bedrock Edition through Geyser and Floodgate.
+//!
+//! Both are downloaded from GeyserMC for the instance's flavour, and Geyser is pointed at
+//! the Bedrock port with Floodgate handling authentication.
+
+use std::path::PathBuf;
+
+use color_eyre::eyre::{eyre, Context};
+use tracing::warn;
+
+use crate::error::{Error, ErrorKind};
+use crate::traits::t_configurable::config_file::ConfigFormat;
+use crate::traits::t_configurable::manifest::{ConfigurableValue, SectionManifest};
+use crate::traits::t_server::{State, TServer};
+use crate::util::download_file;
+
+use super::{Flavour, MinecraftInstance};
+
+/// Port Bedrock clients connect to unless told otherwise
+pub const DEFAULT_BEDROCK_PORT: u32 = 19132;
+
+/// Where a flavour wants its GeyserMC downloads
+struct CrossplayPlatform {
+    /// Platform name in the GeyserMC download API
+    platform: &'static str,
+    /// Directory the jars go in
+    jar_dir: &'static str,
+    /// Geyser's config, relative to the instance's directory
+    geyser_config: &'static str,
+}
+
+fn crossplay_platform(flavour: &Flavour) -> Result<CrossplayPlatform, Error> {
+    match flavour {
+        Flavour::Paper { .. } | Flavour::Spigot => Ok(CrossplayPlatform {
+            platform: "spigot",
+            jar_dir: "plugins",
+            geyser_config: "plugins/Geyser-Spigot/config.yml",
+        }),
+        Flavour::Fabric { .. } => Ok(CrossplayPlatform {
+            platform: "fabric",
+            jar_dir: "mods",
+            geyser_config: "config/Geyser-Fabric/config.yml",
+        }),
+        Flavour::Vanilla | Flavour::Forge { .. } => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Crossplay needs a Paper, Spigot or Fabric server"),
+        }),
+    }
+}
+
+fn download_url(project: &str, platform: &str) -> String {
+    format!(
+        "https://download.geysermc.org/v2/projects/{project}/versions/latest/builds/latest/downloads/{platform}"
+    )
+}
+
+fn jar_name(project: &str, platform: &str) -> String {
+    let capitalize = |s: &str| {
+        let mut chars = s.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+            .unwrap_or_default()
+    };
+    format!("{}-{}.jar", capitalize(project), capitalize(platform))
+}
+
+/// Geyser's config with the Bedrock port and Floodgate authentication filled in
+fn geyser_config(existing: Option<&str>, bedrock_port: u32) -> Result<String, Error> {
+    let existing = match existing {
+        Some(existing) => existing,
+        // Geyser fills in everything else on first start
+        None => {
+            return Ok(format!(
+                "bedrock:\n  port: {bedrock_port}\nremote:\n  auth-type: floodgate\n"
+            ))
+        }
+    };
+    let mut section = SectionManifest::new(
+        String::new(),
+        String::new(),
+        String::new(),
+        ConfigFormat::Yaml.parse_settings(existing)?,
+    );
+    for (setting_id, value) in [
+        (
+            "bedrock.port",
+            ConfigurableValue::Integer(bedrock_port as i32),
+        ),
+        (
+            "remote.auth-type",
+            ConfigurableValue::String("floodgate".to_string()),
+        ),
+    ] {
+        section
+            .update_setting(setting_id, value)
+            .map_err(|_| Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("Geyser's config has no {} setting", setting_id),
+            })?;
+    }
+    ConfigFormat::Yaml.apply_section(existing, &section)
+}
+
+impl MinecraftInstance {
+    /// Installs Geyser and Floodgate and has Geyser listen on `bedrock_port`
+    pub async fn enable_crossplay(&self, bedrock_port: u32) -> Result<(), Error> {
+        if self.state().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Stop the server before setting up crossplay"),
+            });
+        }
+        let platform = crossplay_platform(&self.config.lock().await.flavour)?;
+        let jar_dir = self.path_to_instance.join(platform.jar_dir);
+        tokio::fs::create_dir_all(&jar_dir)
+            .await
+            .context(format!("Failed to create {}", jar_dir.display()))?;
+        for project in ["geyser", "floodgate"] {
+            download_file(
+                &download_url(project, platform.platform),
+                &jar_dir,
+                Some(&jar_name(project, platform.platform)),
+                &|_| {},
+                true,
+            )
+            .await?;
+        }
+
+        let config_path = self.path_to_instance.join(platform.geyser_config);
+        let existing = match tokio::fs::read_to_string(&config_path).await {
+            Ok(existing) => Some(existing),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e)
+                    .context(format!("Failed to read {}", config_path.display()))
+                    .map_err(Into::into)
+            }
+        };
+        let config = geyser_config(existing.as_deref(), bedrock_port)?;
+        if let Some(parent) = config_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context(format!("Failed to create {}", parent.display()))?;
+        }
+        tokio::fs::write(&config_path, config)
+            .await
+            .context(format!("Failed to write {}", config_path.display()))?;
+
+        let mut config = self.config.lock().await;
+        config.bedrock_port = Some(bedrock_port);
+        // so the rest of Geyser's config can be changed from the settings page
+        if !config
+            .config_files
+            .iter()
+            .any(|file| file == platform.geyser_config)
+        {
+            config.config_files.push(platform.geyser_config.to_string());
+        }
+        drop(config);
+        self.write_config_to_file().await
+    }
+
+    /// Removes the Geyser and Floodgate jars, leaving their configs in place
+    pub async fn disable_crossplay(&self) -> Result<(), Error> {
+        if self.state().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Stop the server before turning off crossplay"),
+            });
+        }
+        let platform = crossplay_platform(&self.config.lock().await.flavour)?;
+        for project in ["geyser", "floodgate"] {
+            let jar: PathBuf = self
+                .path_to_instance
+                .join(platform.jar_dir)
+                .join(jar_name(project, platform.platform));
+            if let Err(e) = tokio::fs::remove_file(&jar).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to remove {}: {}", jar.display(), e);
+                }
+            }
+        }
+        let mut config = self.config.lock().await;
+        config.bedrock_port = None;
+        config
+            .config_files
+            .retain(|file| file != platform.geyser_config);
+        drop(config);
+        self.write_config_to_file().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geyser_config() {
+        assert_eq!(jar_name("floodgate", "spigot"), "Floodgate-Spigot.jar");
+        let existing = "bedrock:\n  address: 0.0.0.0\n  port: 19132\nremote:\n  address: auto\n  auth-type: online\n";
+        let updated = geyser_config(Some(existing), 19133).unwrap();
+        let settings = ConfigFormat::Yaml.parse_settings(&updated).unwrap();
+        assert_eq!(
+            settings["bedrock.port"].get_value(),
+            Some(&ConfigurableValue::Integer(19133))
+        );
+        assert_eq!(
+            settings["remote.auth-type"].get_value(),
+            Some(&ConfigurableValue::String("floodgate".to_string()))
+        );
+        assert_eq!(
+            settings["remote.address"].get_value(),
+            Some(&ConfigurableValue::String("auto".to_string()))
+        );
+    }
+}
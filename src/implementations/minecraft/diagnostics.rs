@@ -0,0 +1,339 @@
+//! Thread dumps and heap information taken from a running server.
+//!
+//! The JDK's `jcmd` (or `jstack`) is used when one can be found next to the server's java
+//! or on the `PATH`, the bundled runtimes are JREs without them. Paper servers can
+//! still dump their heap through `/paper dumpheap`. Every result is kept as an artifact
+//! in the instance's `diagnostics` directory, next to a json file describing it.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::CausedBy;
+use crate::traits::t_server::{State, TServer};
+use crate::types::Snowflake;
+
+use super::{Flavour, MinecraftInstance};
+
+const DIAGNOSTICS_DIR: &str = "diagnostics";
+/// Heap dumps of large servers take a while to write
+const HEAP_DUMP_TIMEOUT: Duration = Duration::from_secs(600);
+const TOOL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum DiagnosticKind {
+    ThreadDump,
+    HeapInfo,
+    HeapDump,
+}
+
+impl DiagnosticKind {
+    fn file_name(&self, id: Snowflake) -> String {
+        match self {
+            DiagnosticKind::ThreadDump => format!("{}-thread-dump.txt", id.to_string()),
+            DiagnosticKind::HeapInfo => format!("{}-heap-info.txt", id.to_string()),
+            DiagnosticKind::HeapDump => format!("{}-heap-dump.hprof", id.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct DiagnosticInfo {
+    pub id: Snowflake,
+    pub kind: DiagnosticKind,
+    /// How the artifact was produced, like `jcmd Thread.print`
+    pub method: String,
+    /// The artifact, relative to the instance's directory
+    pub path: String,
+    pub size: u64,
+    pub created_at: i64,
+}
+
+/// A JDK tool, next to `java` or on the `PATH`
+fn find_jdk_tool(java: &Path, tool: &str) -> Option<PathBuf> {
+    let file_name = if cfg!(windows) {
+        format!("{tool}.exe")
+    } else {
+        tool.to_string()
+    };
+    let beside_java = java
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .map(|parent| parent.join(&file_name));
+    beside_java
+        .into_iter()
+        .chain(
+            std::env::var_os("PATH")
+                .map(|paths| {
+                    std::env::split_paths(&paths)
+                        .map(|path| path.join(&file_name))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default(),
+        )
+        .find(|path| path.is_file())
+}
+
+/// The heap dump file Paper reports having written
+fn parse_paper_heap_dump(message: &str) -> Option<String> {
+    lazy_static! {
+        static ref HEAP_DUMP_RE: Regex = Regex::new(r#"([^\s"']+\.hprof)"#).unwrap();
+    }
+    HEAP_DUMP_RE
+        .captures(message)
+        .ok()
+        .flatten()
+        .and_then(|captures| captures.get(1))
+        .map(|path| path.as_str().to_string())
+}
+
+async fn run_tool(mut command: Command, timeout: Duration) -> Result<String, Error> {
+    let output = tokio::time::timeout(timeout, command.kill_on_drop(true).output())
+        .await
+        .map_err(|_| Error {
+            kind: ErrorKind::Internal,
+            source: eyre!("Timed out waiting for the diagnostic tool"),
+        })?
+        .context("Failed to run the diagnostic tool")?;
+    if !output.status.success() {
+        return Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "The diagnostic tool failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl MinecraftInstance {
+    fn diagnostics_dir(&self) -> PathBuf {
+        self.path_to_instance.join(DIAGNOSTICS_DIR)
+    }
+
+    /// Newest first
+    pub async fn list_diagnostics(&self) -> Result<Vec<DiagnosticInfo>, Error> {
+        let mut entries = match tokio::fs::read_dir(self.diagnostics_dir()).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e)
+                    .context("Failed to read diagnostics directory")
+                    .map_err(Into::into)
+            }
+        };
+        let mut ret = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context("Failed to read diagnostics directory")?
+        {
+            let path = entry.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            match tokio::fs::read(&path)
+                .await
+                .ok()
+                .and_then(|content| serde_json::from_slice::<DiagnosticInfo>(&content).ok())
+            {
+                Some(info) => ret.push(info),
+                None => tracing::warn!("Ignoring unreadable diagnostic {}", path.display()),
+            }
+        }
+        ret.sort_by(|a, b| b.id.cmp(&a.id));
+        Ok(ret)
+    }
+
+    pub async fn delete_diagnostic(&self, id: Snowflake) -> Result<(), Error> {
+        let info = self
+            .list_diagnostics()
+            .await?
+            .into_iter()
+            .find(|info| info.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Diagnostic not found"),
+            })?;
+        let artifact = self.path_to_instance.join(&info.path);
+        if let Err(e) = tokio::fs::remove_file(&artifact).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e)
+                    .context(format!("Failed to remove {}", artifact.display()))
+                    .map_err(Into::into);
+            }
+        }
+        let metadata = self
+            .diagnostics_dir()
+            .join(format!("{}.json", id.to_string()));
+        tokio::fs::remove_file(&metadata)
+            .await
+            .context(format!("Failed to remove {}", metadata.display()))?;
+        Ok(())
+    }
+
+    /// Takes a thread dump, heap summary or heap dump of the running server
+    pub async fn take_diagnostic(&self, kind: DiagnosticKind) -> Result<DiagnosticInfo, Error> {
+        if self.state().await != State::Running {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The server has to be running to be diagnosed"),
+            });
+        }
+        let pid = self.server_pid().await.ok_or_else(|| Error {
+            kind: ErrorKind::Internal,
+            source: eyre!("The server's process id is unknown"),
+        })?;
+        let (java, flavour) = {
+            let config = self.config.lock().await;
+            (self.java_binary(&config), config.flavour.clone())
+        };
+        let diagnostics_dir = self.diagnostics_dir();
+        tokio::fs::create_dir_all(&diagnostics_dir)
+            .await
+            .context(format!("Failed to create {}", diagnostics_dir.display()))?;
+        let id = Snowflake::new();
+        let artifact = diagnostics_dir.join(kind.file_name(id));
+
+        let jcmd = find_jdk_tool(&java, "jcmd");
+        let method = match (kind, jcmd) {
+            (DiagnosticKind::ThreadDump, Some(jcmd)) => {
+                let mut command = Command::new(jcmd);
+                command.arg(pid.to_string()).args(["Thread.print", "-l"]);
+                self.write_artifact(&artifact, run_tool(command, TOOL_TIMEOUT).await?)
+                    .await?;
+                "jcmd Thread.print"
+            }
+            (DiagnosticKind::ThreadDump, None) => match find_jdk_tool(&java, "jstack") {
+                Some(jstack) => {
+                    let mut command = Command::new(jstack);
+                    command.arg("-l").arg(pid.to_string());
+                    self.write_artifact(&artifact, run_tool(command, TOOL_TIMEOUT).await?)
+                        .await?;
+                    "jstack"
+                }
+                None => return Err(no_jdk_error()),
+            },
+            (DiagnosticKind::HeapInfo, Some(jcmd)) => {
+                let mut command = Command::new(jcmd);
+                command.arg(pid.to_string()).arg("GC.heap_info");
+                self.write_artifact(&artifact, run_tool(command, TOOL_TIMEOUT).await?)
+                    .await?;
+                "jcmd GC.heap_info"
+            }
+            (DiagnosticKind::HeapInfo, None) => return Err(no_jdk_error()),
+            (DiagnosticKind::HeapDump, Some(jcmd)) => {
+                let mut command = Command::new(jcmd);
+                command
+                    .arg(pid.to_string())
+                    .arg("GC.heap_dump")
+                    .arg(&artifact);
+                run_tool(command, HEAP_DUMP_TIMEOUT).await?;
+                "jcmd GC.heap_dump"
+            }
+            (DiagnosticKind::HeapDump, None) => match flavour {
+                Flavour::Paper { .. } => {
+                    self.paper_dump_heap(&artifact).await?;
+                    "paper dumpheap"
+                }
+                _ => return Err(no_jdk_error()),
+            },
+        };
+
+        let size = tokio::fs::metadata(&artifact)
+            .await
+            .context(format!("Failed to read {}", artifact.display()))?
+            .len();
+        let info = DiagnosticInfo {
+            id,
+            kind,
+            method: method.to_string(),
+            path: format!("{}/{}", DIAGNOSTICS_DIR, kind.file_name(id)),
+            size,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        let metadata = diagnostics_dir.join(format!("{}.json", id.to_string()));
+        tokio::fs::write(
+            &metadata,
+            serde_json::to_string_pretty(&info).context("Failed to serialize diagnostic")?,
+        )
+        .await
+        .context(format!("Failed to write {}", metadata.display()))?;
+        Ok(info)
+    }
+
+    async fn write_artifact(&self, artifact: &Path, content: String) -> Result<(), Error> {
+        tokio::fs::write(artifact, content)
+            .await
+            .context(format!("Failed to write {}", artifact.display()))?;
+        Ok(())
+    }
+
+    /// Has Paper dump its heap and moves the dump to `artifact`
+    async fn paper_dump_heap(&self, artifact: &Path) -> Result<(), Error> {
+        let mut events = self.event_broadcaster.subscribe();
+        self.send_command("paper dumpheap", CausedBy::System)
+            .await?;
+        let dump = std::sync::Mutex::new(None);
+        let reported = self
+            .wait_for_output(&mut events, HEAP_DUMP_TIMEOUT, |message| {
+                if message.contains("Failed to write heap dump") {
+                    return true;
+                }
+                match parse_paper_heap_dump(message) {
+                    Some(path) => {
+                        *dump.lock().unwrap() = Some(path);
+                        true
+                    }
+                    None => false,
+                }
+            })
+            .await;
+        let dump = match dump.into_inner().unwrap() {
+            Some(dump) if reported => self.path_to_instance.join(dump),
+            _ => {
+                return Err(Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!("Paper did not dump its heap"),
+                })
+            }
+        };
+        crate::util::fs::rename(dump, artifact.to_path_buf()).await
+    }
+}
+
+fn no_jdk_error() -> Error {
+    Error {
+        kind: ErrorKind::UnsupportedOperation,
+        source: eyre!("This needs a JDK with jcmd, point the instance's java at one to use it"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_paper_heap_dump() {
+        assert_eq!(
+            parse_paper_heap_dump(
+                "Successfully dumped heap. Check dumps/heap-dump-2023-05-01_12.00.00-server.hprof"
+            ),
+            Some("dumps/heap-dump-2023-05-01_12.00.00-server.hprof".to_string())
+        );
+        assert_eq!(
+            parse_paper_heap_dump("Dumping heap, this may take a while..."),
+            None
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Garbage collector logs, written to rotated files when turned on for an instance, and a
+//! summary of them to help pick the RAM and flags of a server.
+//!
+//! Java 9 and later use the unified logging format, one line per collection:
+//! `[12.345s][info][gc] GC(7) Pause Young (Normal) (G1 Evacuation Pause) 512M->128M(1024M) 5.123ms`.
+//! Java 8 writes `12.345: [GC pause (G1 Evacuation Pause) (young) 512M->128M(1024M), 0.0051230 secs]`,
+//! which only stays on one line because the detailed flags aren't passed.
+
+use std::path::PathBuf;
+
+use color_eyre::eyre::Context;
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::Error;
+
+use super::MinecraftInstance;
+
+/// Relative to the instance's directory, which the server runs in
+const GC_LOG_DIR: &str = "logs/gc";
+const GC_LOG_FILE: &str = "gc.log";
+const GC_LOG_FILE_COUNT: u32 = 5;
+const GC_LOG_FILE_SIZE_MB: u32 = 10;
+/// Only the newest logs are read, older ones say little about the current settings
+const MAX_ANALYZED_BYTES: u64 = 32 * 1024 * 1024;
+
+/// The JVM flags that write rotated GC logs
+pub(super) fn gc_log_args(jre_major_version: u64) -> Vec<String> {
+    let file = format!("{GC_LOG_DIR}/{GC_LOG_FILE}");
+    if jre_major_version >= 9 {
+        vec![format!(
+            "-Xlog:gc:file={file}:uptime,level,tags:filecount={GC_LOG_FILE_COUNT},filesize={GC_LOG_FILE_SIZE_MB}M"
+        )]
+    } else {
+        vec![
+            format!("-Xloggc:{file}"),
+            "-XX:+UseGCLogFileRotation".to_string(),
+            format!("-XX:NumberOfGCLogFiles={GC_LOG_FILE_COUNT}"),
+            format!("-XX:GCLogFileSize={GC_LOG_FILE_SIZE_MB}M"),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct GcPause {
+    /// Seconds since the JVM started
+    uptime: f64,
+    full: bool,
+    heap_before_mb: f64,
+    heap_after_mb: f64,
+    heap_total_mb: f64,
+    pause_ms: f64,
+}
+
+fn to_mb(size: &str, unit: &str) -> Option<f64> {
+    let size: f64 = size.parse().ok()?;
+    Some(match unit {
+        "K" => size / 1024.0,
+        "M" => size,
+        "G" => size * 1024.0,
+        _ => return None,
+    })
+}
+
+fn parse_line(line: &str) -> Option<GcPause> {
+    lazy_static! {
+        static ref UNIFIED_RE: Regex = Regex::new(
+            r"\[([\d.]+)s\].*GC\(\d+\) Pause (\S+).* (\d+)([KMG])->(\d+)([KMG])\((\d+)([KMG])\) ([\d.]+)ms"
+        )
+        .unwrap();
+        static ref LEGACY_RE: Regex = Regex::new(
+            r"([\d.]+): \[(Full GC|GC)[^\[\]]*?(\d+)([KMG])->(\d+)([KMG])\((\d+)([KMG])\), ([\d.]+) secs\]"
+        )
+        .unwrap();
+    }
+    let (captures, unified) = match UNIFIED_RE.captures(line).ok().flatten() {
+        Some(captures) => (captures, true),
+        None => (LEGACY_RE.captures(line).ok().flatten()?, false),
+    };
+    let get = |i: usize| captures.get(i).map(|m| m.as_str());
+    let pause: f64 = get(9)?.parse().ok()?;
+    Some(GcPause {
+        uptime: get(1)?.parse().ok()?,
+        full: if unified {
+            get(2)? == "Full"
+        } else {
+            get(2)? == "Full GC"
+        },
+        heap_before_mb: to_mb(get(3)?, get(4)?)?,
+        heap_after_mb: to_mb(get(5)?, get(6)?)?,
+        heap_total_mb: to_mb(get(7)?, get(8)?)?,
+        pause_ms: if unified { pause } else { pause * 1000.0 },
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct PauseDistribution {
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+    pub mean_ms: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct GcAnalysis {
+    pub pauses: u32,
+    pub full_pauses: u32,
+    pub pause_time: PauseDistribution,
+    /// Share of the logged time spent paused
+    pub pause_percent: f64,
+    /// Heap filled between collections, per second
+    pub allocation_rate_mb_per_sec: Option<f64>,
+    /// Heap still in use after the latest collection
+    pub heap_after_gc_mb: Option<f64>,
+    pub heap_total_mb: Option<f64>,
+    /// Seconds of server uptime the logs cover
+    pub covered_secs: f64,
+}
+
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let index = ((sorted.len() - 1) as f64 * percent / 100.0).round() as usize;
+    sorted[index]
+}
+
+/// `pauses` in the order they were logged, possibly over several runs of the server
+fn analyze(pauses: &[GcPause]) -> GcAnalysis {
+    let mut durations: Vec<f64> = pauses.iter().map(|pause| pause.pause_ms).collect();
+    durations.sort_by(|a, b| a.total_cmp(b));
+    let total_pause_ms: f64 = durations.iter().sum();
+    let (mut allocated_mb, mut covered_secs) = (0.0, 0.0);
+    for pair in pauses.windows(2) {
+        let (previous, next) = (&pair[0], &pair[1]);
+        // uptime going back means the server was restarted in between
+        if next.uptime <= previous.uptime {
+            continue;
+        }
+        covered_secs += next.uptime - previous.uptime;
+        allocated_mb += (next.heap_before_mb - previous.heap_after_mb).max(0.0);
+    }
+    GcAnalysis {
+        pauses: pauses.len() as u32,
+        full_pauses: pauses.iter().filter(|pause| pause.full).count() as u32,
+        pause_time: PauseDistribution {
+            p50_ms: percentile(&durations, 50.0),
+            p90_ms: percentile(&durations, 90.0),
+            p99_ms: percentile(&durations, 99.0),
+            max_ms: durations.last().copied().unwrap_or_default(),
+            mean_ms: if durations.is_empty() {
+                0.0
+            } else {
+                total_pause_ms / durations.len() as f64
+            },
+        },
+        pause_percent: if covered_secs > 0.0 {
+            total_pause_ms / 10.0 / covered_secs
+        } else {
+            0.0
+        },
+        allocation_rate_mb_per_sec: (covered_secs > 0.0).then(|| allocated_mb / covered_secs),
+        heap_after_gc_mb: pauses.last().map(|pause| pause.heap_after_mb),
+        heap_total_mb: pauses.last().map(|pause| pause.heap_total_mb),
+        covered_secs,
+    }
+}
+
+impl MinecraftInstance {
+    /// Oldest first, up to [`MAX_ANALYZED_BYTES`] of the newest ones
+    async fn gc_log_files(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut entries = match tokio::fs::read_dir(self.path_to_instance.join(GC_LOG_DIR)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e)
+                    .context("Failed to read GC log directory")
+                    .map_err(Into::into)
+            }
+        };
+        let mut files = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context("Failed to read GC log directory")?
+        {
+            let metadata = match entry.metadata().await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            if let Ok(modified) = metadata.modified() {
+                files.push((modified, metadata.len(), entry.path()));
+            }
+        }
+        files.sort_by(|a, b| b.0.cmp(&a.0));
+        let mut total = 0;
+        let mut ret: Vec<PathBuf> = files
+            .into_iter()
+            .take_while(|(_, len, _)| {
+                let fits = total == 0 || total + len <= MAX_ANALYZED_BYTES;
+                total += len;
+                fits
+            })
+            .map(|(_, _, path)| path)
+            .collect();
+        ret.reverse();
+        Ok(ret)
+    }
+
+    pub async fn analyze_gc_logs(&self) -> Result<GcAnalysis, Error> {
+        let mut pauses = Vec::new();
+        for path in self.gc_log_files().await? {
+            let content = tokio::fs::read(&path)
+                .await
+                .context(format!("Failed to read GC log {}", path.display()))?;
+            pauses.extend(
+                String::from_utf8_lossy(&content)
+                    .lines()
+                    .filter_map(parse_line),
+            );
+        }
+        Ok(analyze(&pauses))
+    }
+
+    /// The directory has to exist before the JVM can log into it
+    pub(super) async fn prepare_gc_log_dir(&self) -> Result<(), Error> {
+        crate::util::fs::create_dir_all(self.path_to_instance.join(GC_LOG_DIR)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gc_lines() {
+        assert_eq!(
+            parse_line("[12.345s][info][gc] GC(7) Pause Young (Normal) (G1 Evacuation Pause) 512M->128M(1024M) 5.123ms"),
+            Some(GcPause {
+                uptime: 12.345,
+                full: false,
+                heap_before_mb: 512.0,
+                heap_after_mb: 128.0,
+                heap_total_mb: 1024.0,
+                pause_ms: 5.123,
+            })
+        );
+        assert!(
+            parse_line("[20.000s][info][gc] GC(8) Pause Full (System.gc()) 1G->200M(2G) 150.000ms")
+                .unwrap()
+                .full
+        );
+        assert_eq!(
+            parse_line(
+                "3.500: [Full GC (Allocation Failure)  65536K->1024K(251392K), 0.0500000 secs]"
+            ),
+            Some(GcPause {
+                uptime: 3.5,
+                full: true,
+                heap_before_mb: 64.0,
+                heap_after_mb: 1.0,
+                heap_total_mb: 245.5,
+                pause_ms: 50.0,
+            })
+        );
+        assert_eq!(parse_line("[0.010s][info][gc] Using G1"), None);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let pause = |uptime: f64, before: f64, after: f64, pause_ms: f64| GcPause {
+            uptime,
+            full: false,
+            heap_before_mb: before,
+            heap_after_mb: after,
+            heap_total_mb: 1024.0,
+            pause_ms,
+        };
+        let analysis = analyze(&[
+            pause(10.0, 500.0, 100.0, 10.0),
+            pause(20.0, 600.0, 100.0, 20.0),
+            // restarted
+            pause(5.0, 300.0, 50.0, 30.0),
+            pause(15.0, 350.0, 50.0, 40.0),
+        ]);
+        assert_eq!(analysis.pauses, 4);
+        assert_eq!(analysis.covered_secs, 20.0);
+        assert_eq!(analysis.allocation_rate_mb_per_sec, Some(40.0));
+        assert_eq!(analysis.pause_time.max_ms, 40.0);
+        assert_eq!(analysis.pause_time.mean_ms, 25.0);
+        assert_eq!(analysis.heap_after_gc_mb, Some(50.0));
+        // 100ms paused over 20s
+        assert_eq!(analysis.pause_percent, 0.5);
+        assert_eq!(analyze(&[]).allocation_rate_mb_per_sec, None);
+    }
+}
@@ -0,0 +1,719 @@
+//! Setting up an instance from the files of an existing server, for users moving from another panel.
+//!
+//! The server's archive is extracted into the new instance's directory, and its flavour and
+//! version are told from the server jar and the files around it. `server.properties` is kept as
+//! is, so the instance starts out with the settings the server already had.
+
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use serde_json::to_string_pretty;
+use ts_rs::TS;
+
+use crate::{
+    console_buffer::DEFAULT_CONSOLE_BUFFER_LINES,
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{Event, ProgressionEventID},
+    macro_executor::MacroExecutor,
+    process_priority::ProcessPriority,
+    sandbox::SandboxLevel,
+    startup_watchdog::StartupTimeoutAction,
+    traits::t_configurable::TConfigurable,
+    types::DotLodestoneConfig,
+    wasm_macro::{DEFAULT_WASM_MACRO_MEMORY, DEFAULT_WASM_MACRO_TIMEOUT},
+};
+
+use super::{
+    FabricLoaderVersion, Flavour, ForgeBuildVersion, MinecraftInstance, PaperBuildVersion,
+    RestoreConfig, SetupConfig,
+};
+
+/// Left behind by a core that ran the server, it must not be adopted by this one
+const PROCESS_RECORD_FILE: &str = ".lodestone_process.json";
+/// Where the vanilla jar goes when an old Fabric launcher takes the place of `server.jar`
+const FABRIC_VANILLA_JAR: &str = "vanilla-server.jar";
+/// Scripts other panels and hosts start the server with, read for the memory flags
+const START_SCRIPTS: [&str; 6] = [
+    "start.sh",
+    "start.bat",
+    "start.cmd",
+    "run.sh",
+    "run.bat",
+    "user_jvm_args.txt",
+];
+/// Jars in the server's directory that aren't the server
+const IGNORED_JARS: [&str; 2] = ["forge-installer.jar", FABRIC_VANILLA_JAR];
+
+/// Overrides for what is detected from the server's files
+#[derive(Debug, Clone, Default, Deserialize, TS)]
+#[ts(export)]
+pub struct MinecraftImportOptions {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Defaults to the `server-port` of the server
+    pub port: Option<u32>,
+    /// Defaults to the `-Xms` flag of the server's start script
+    pub min_ram: Option<u32>,
+    /// Defaults to the `-Xmx` flag of the server's start script
+    pub max_ram: Option<u32>,
+    pub auto_start: Option<bool>,
+    pub restart_on_crash: Option<bool>,
+}
+
+/// What could be told about a server from its files
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedServer {
+    pub version: String,
+    pub flavour: Flavour,
+    /// The jar the server is started with, `None` for Forge which is started through its own files
+    pub server_jar: Option<String>,
+    pub port: Option<u32>,
+    pub min_ram: Option<u32>,
+    pub max_ram: Option<u32>,
+}
+
+impl DetectedServer {
+    pub fn setup_config(&self, name: String, options: MinecraftImportOptions) -> SetupConfig {
+        SetupConfig {
+            name,
+            version: self.version.clone(),
+            flavour: self.flavour.clone(),
+            port: options.port.or(self.port).unwrap_or(25565),
+            cmd_args: Vec::new(),
+            description: options.description,
+            min_ram: options.min_ram.or(self.min_ram),
+            max_ram: options.max_ram.or(self.max_ram),
+            auto_start: options.auto_start,
+            restart_on_crash: options.restart_on_crash,
+            backup_period: None,
+        }
+    }
+}
+
+fn not_a_server(reason: &str) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("The archive doesn't look like a Minecraft server: {reason}"),
+    }
+}
+
+fn invalid_archive(e: zip::result::ZipError) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Not a valid zip archive: {e}"),
+    }
+}
+
+/// Total size of the files in a zip archive once extracted, as declared by the archive.
+/// [`extract_server_archive`] holds the archive to it
+pub fn extracted_size(archive: &Path) -> Result<u64, Error> {
+    let file = std::fs::File::open(archive)
+        .context(format!("Failed to open archive {}", archive.display()))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(invalid_archive)?;
+    let mut size = 0_u64;
+    for i in 0..archive.len() {
+        if let Ok(entry) = archive.by_index_raw(i) {
+            size = size.saturating_add(entry.size());
+        }
+    }
+    Ok(size)
+}
+
+/// The folder everything in the archive is in, if there is one
+fn archive_root(entries: &[(PathBuf, bool)]) -> Option<PathBuf> {
+    let mut root = None;
+    for (name, is_dir) in entries {
+        let mut components = name.components();
+        let first = match components.next() {
+            Some(Component::Normal(first)) => Path::new(first),
+            _ => return None,
+        };
+        if !is_dir && components.next().is_none() {
+            return None;
+        }
+        match root {
+            None => root = Some(first),
+            Some(root) if root != first => return None,
+            Some(_) => {}
+        }
+    }
+    root.map(Path::to_path_buf)
+}
+
+/// Extracts the server's archive into `dest`, which should be empty.
+/// Servers are often zipped along with the folder they are in, that folder is left out.
+///
+/// The sizes an archive declares can't be trusted, so this fails as soon as more than
+/// `max_size` bytes have been written
+pub fn extract_server_archive(archive: &Path, dest: &Path, max_size: u64) -> Result<(), Error> {
+    let file = std::fs::File::open(archive)
+        .context(format!("Failed to open archive {}", archive.display()))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(invalid_archive)?;
+    let mut entries = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i).map_err(invalid_archive)?;
+        let name = entry
+            .enclosed_name()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The archive contains an invalid path: {}", entry.name()),
+            })?;
+        entries.push((name, entry.is_dir()));
+    }
+    let root = archive_root(&entries);
+    let mut written = 0_u64;
+    for (i, (name, is_dir)) in entries.iter().enumerate() {
+        let path = match &root {
+            Some(root) => dest.join(name.strip_prefix(root).unwrap_or(name)),
+            None => dest.join(name),
+        };
+        if *is_dir {
+            std::fs::create_dir_all(&path)
+                .context(format!("Failed to create directory {}", path.display()))?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .context(format!("Failed to create directory {}", parent.display()))?;
+        }
+        let mut entry = archive.by_index(i).map_err(invalid_archive)?;
+        let mut file = std::fs::File::create(&path)
+            .context(format!("Failed to create file {}", path.display()))?;
+        written += std::io::copy(
+            &mut (&mut entry).take((max_size - written).saturating_add(1)),
+            &mut file,
+        )
+        .context(format!("Failed to extract {}", path.display()))?;
+        if written > max_size {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The archive extracts to more than it declares"),
+            });
+        }
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode & 0o777))
+                .context(format!("Failed to set permissions of {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn read_properties(content: &str) -> impl Iterator<Item = (&str, &str)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+fn property(content: &str, key: &str) -> Option<String> {
+    read_properties(content)
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| value.to_string())
+}
+
+/// `4G` or `4096M` as MB, the way the JVM reads `-Xmx`
+fn parse_memory_arg(arg: &str) -> Option<u32> {
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let amount: u64 = arg[..split].parse().ok()?;
+    let mb = match &arg[split..] {
+        "g" | "G" => amount * 1024,
+        "m" | "M" => amount,
+        "k" | "K" => amount / 1024,
+        "" => amount / (1024 * 1024),
+        _ => return None,
+    };
+    u32::try_from(mb).ok().filter(|mb| *mb > 0)
+}
+
+/// The `-Xms` and `-Xmx` flags found in `script`
+fn parse_memory_flags(script: &str) -> (Option<u32>, Option<u32>) {
+    let mut min_ram = None;
+    let mut max_ram = None;
+    for token in script
+        .lines()
+        .filter(|line| !line.trim_start().starts_with(['#', ':']))
+        .flat_map(str::split_whitespace)
+    {
+        if let Some(arg) = token.strip_prefix("-Xms") {
+            min_ram = parse_memory_arg(arg).or(min_ram);
+        } else if let Some(arg) = token.strip_prefix("-Xmx") {
+            max_ram = parse_memory_arg(arg).or(max_ram);
+        }
+    }
+    (min_ram, max_ram)
+}
+
+fn is_minecraft_version(s: &str) -> bool {
+    let parts: Vec<&str> = s.split('.').collect();
+    (2..=3).contains(&parts.len())
+        && parts[0] == "1"
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The Minecraft version in a name like `paper-1.20.1-196.jar`
+fn version_in_name(name: &str) -> Option<String> {
+    name.trim_end_matches(".jar")
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .map(|part| part.trim_matches('.'))
+        .find(|part| is_minecraft_version(part))
+        .map(str::to_string)
+}
+
+/// The build in a name like `paper-1.20.1-196.jar`
+fn paper_build_in_name(name: &str) -> Option<i64> {
+    let mut parts = name.trim_end_matches(".jar").split('-');
+    parts.find(|part| is_minecraft_version(part))?;
+    parts.next()?.parse().ok()
+}
+
+/// Paper records `git-Paper-196 (MC: 1.20.1)` in `version_history.json`
+fn version_in_paper_history(content: &str) -> Option<String> {
+    let history: serde_json::Value = serde_json::from_str(content).ok()?;
+    let current = history.get("currentVersion")?.as_str()?;
+    let (_, version) = current.split_once("(MC: ")?;
+    Some(version.trim_end_matches(')').trim().to_string())
+}
+
+fn read_zip_entry(archive: &mut zip::ZipArchive<std::fs::File>, name: &str) -> Option<String> {
+    let mut entry = archive.by_name(name).ok()?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+/// The id in the `version.json` bundled with the server jar since 1.14
+fn version_in_jar(archive: &mut zip::ZipArchive<std::fs::File>) -> Option<String> {
+    let version: serde_json::Value =
+        serde_json::from_str(&read_zip_entry(archive, "version.json")?).ok()?;
+    version.get("id")?.as_str().map(str::to_string)
+}
+
+fn detect_forge(path: &Path) -> Option<(String, Flavour)> {
+    let builds = path
+        .join("libraries")
+        .join("net")
+        .join("minecraftforge")
+        .join("forge");
+    // the installer puts the libraries of the build it installed under its `<version>-<build>`
+    let mut builds = std::fs::read_dir(builds)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    builds.sort();
+    let build = builds.pop()?;
+    let version = build
+        .split('-')
+        .next()
+        .filter(|v| is_minecraft_version(v))?;
+    Some((
+        version.to_string(),
+        Flavour::Forge {
+            build_version: Some(ForgeBuildVersion(build.clone())),
+        },
+    ))
+}
+
+/// The jar the server is most likely started with
+fn find_server_jar(path: &Path) -> Result<String, Error> {
+    let jars = std::fs::read_dir(path)
+        .context(format!("Failed to read directory {}", path.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".jar") && !IGNORED_JARS.contains(&name.as_str()))
+        .collect::<Vec<_>>();
+    const KNOWN_PREFIXES: [&str; 7] = [
+        "fabric-server",
+        "paper",
+        "purpur",
+        "spigot",
+        "craftbukkit",
+        "minecraft_server",
+        "server",
+    ];
+    for prefix in KNOWN_PREFIXES {
+        if let Some(jar) = jars.iter().find(|jar| jar.starts_with(prefix)) {
+            return Ok(jar.clone());
+        }
+    }
+    match jars.as_slice() {
+        [jar] => Ok(jar.clone()),
+        [] => Err(not_a_server("no server jar found")),
+        _ => Err(not_a_server(
+            "there are several jars and none of them is named like a server jar",
+        )),
+    }
+}
+
+/// Tells the flavour and version of the server in `path`, along with the settings it was run with
+pub fn detect_server(path: &Path) -> Result<DetectedServer, Error> {
+    let properties = std::fs::read_to_string(path.join("server.properties")).unwrap_or_default();
+    let port = property(&properties, "server-port").and_then(|port| port.parse().ok());
+    let (mut min_ram, mut max_ram) = (None, None);
+    for script in START_SCRIPTS {
+        if let Ok(content) = std::fs::read_to_string(path.join(script)) {
+            let (min, max) = parse_memory_flags(&content);
+            min_ram = min_ram.or(min);
+            max_ram = max_ram.or(max);
+        }
+    }
+
+    if let Some((version, flavour)) = detect_forge(path) {
+        return Ok(DetectedServer {
+            version,
+            flavour,
+            server_jar: None,
+            port,
+            min_ram,
+            max_ram,
+        });
+    }
+
+    // servers installed with an old Fabric installer start through a launcher next to the vanilla jar
+    let fabric_launcher = path.join("fabric-server-launch.jar");
+    let server_jar = if fabric_launcher.is_file() {
+        let properties = std::fs::read_to_string(path.join("fabric-server-launch.properties"))
+            .unwrap_or_default();
+        property(&properties, "serverJar").unwrap_or_else(|| "server.jar".to_string())
+    } else {
+        find_server_jar(path)?
+    };
+    let mut archive = std::fs::File::open(path.join(&server_jar))
+        .ok()
+        .and_then(|file| zip::ZipArchive::new(file).ok())
+        .ok_or_else(|| not_a_server(&format!("{server_jar} isn't a valid jar")))?;
+    let lower_name = server_jar.to_lowercase();
+    let is_paper = lower_name.starts_with("paper")
+        || lower_name.starts_with("purpur")
+        || archive
+            .file_names()
+            .any(|name| name.starts_with("io/papermc/paperclip/"));
+    let is_spigot = lower_name.starts_with("spigot")
+        || lower_name.starts_with("craftbukkit")
+        || archive
+            .file_names()
+            .any(|name| name.starts_with("org/spigotmc/") || name.starts_with("org/bukkit/"));
+    // the launcher from the Fabric website says what it was made for
+    let fabric_install = read_zip_entry(&mut archive, "install.properties");
+
+    let (flavour, version) = if fabric_launcher.is_file() {
+        (
+            Flavour::Fabric {
+                loader_version: None,
+                installer_version: None,
+            },
+            version_in_jar(&mut archive),
+        )
+    } else if let Some(install) = fabric_install {
+        (
+            Flavour::Fabric {
+                loader_version: property(&install, "fabric-loader-version")
+                    .map(FabricLoaderVersion),
+                installer_version: None,
+            },
+            property(&install, "game-version"),
+        )
+    } else if is_paper {
+        (
+            Flavour::Paper {
+                build_version: paper_build_in_name(&server_jar).map(PaperBuildVersion),
+            },
+            std::fs::read_to_string(path.join("version_history.json"))
+                .ok()
+                .and_then(|content| version_in_paper_history(&content))
+                .or_else(|| version_in_jar(&mut archive)),
+        )
+    } else if is_spigot {
+        (Flavour::Spigot, version_in_jar(&mut archive))
+    } else {
+        (Flavour::Vanilla, version_in_jar(&mut archive))
+    };
+    let version = version
+        .or_else(|| version_in_name(&server_jar))
+        .ok_or_else(|| not_a_server("the Minecraft version of the server couldn't be told"))?;
+
+    Ok(DetectedServer {
+        version,
+        flavour,
+        server_jar: Some(if fabric_launcher.is_file() {
+            "fabric-server-launch.jar".to_string()
+        } else {
+            server_jar
+        }),
+        port,
+        min_ram,
+        max_ram,
+    })
+}
+
+/// Puts the server jar where the instance starts it from, `server.jar`
+async fn prepare_server_jar(path_to_instance: &Path, server_jar: &str) -> Result<(), Error> {
+    if server_jar == "server.jar" {
+        return Ok(());
+    }
+    let path_to_server_jar = path_to_instance.join("server.jar");
+    if server_jar == "fabric-server-launch.jar" {
+        // the launcher reads which jar is vanilla from its properties
+        let path_to_launcher_properties = path_to_instance.join("fabric-server-launch.properties");
+        let properties = tokio::fs::read_to_string(&path_to_launcher_properties)
+            .await
+            .unwrap_or_default();
+        let vanilla_jar =
+            property(&properties, "serverJar").unwrap_or_else(|| "server.jar".to_string());
+        tokio::fs::rename(
+            path_to_instance.join(&vanilla_jar),
+            path_to_instance.join(FABRIC_VANILLA_JAR),
+        )
+        .await
+        .context(format!("Failed to move {vanilla_jar}"))?;
+        let mut content = read_properties(&properties)
+            .filter(|(key, _)| *key != "serverJar")
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect::<String>();
+        content.push_str(&format!("serverJar={FABRIC_VANILLA_JAR}\n"));
+        tokio::fs::write(&path_to_launcher_properties, content)
+            .await
+            .context("Failed to write fabric-server-launch.properties")?;
+    } else if path_to_server_jar.exists() {
+        return Err(eyre!("Another server.jar is in the way of {server_jar}").into());
+    }
+    tokio::fs::rename(path_to_instance.join(server_jar), &path_to_server_jar)
+        .await
+        .context(format!("Failed to rename {server_jar} to server.jar"))?;
+    Ok(())
+}
+
+impl MinecraftInstance {
+    /// Turns the extracted server in `path_to_instance` into an instance,
+    /// `config` should come from [`DetectedServer::setup_config`]
+    pub async fn import(
+        config: SetupConfig,
+        detected: DetectedServer,
+        dot_lodestone_config: DotLodestoneConfig,
+        path_to_instance: PathBuf,
+        progression_event_id: &ProgressionEventID,
+        event_broadcaster: EventBroadcaster,
+        macro_executor: MacroExecutor,
+    ) -> Result<MinecraftInstance, Error> {
+        let path_to_config = path_to_instance.join(".lodestone_minecraft_config.json");
+        let path_to_eula = path_to_instance.join("eula.txt");
+        let path_to_resources = path_to_instance.join("resources");
+
+        // Step 1: Create Directories
+        event_broadcaster.send(Event::new_progression_event_update(
+            progression_event_id,
+            "1/4: Creating directories",
+            1.0,
+        ));
+        tokio::fs::create_dir_all(path_to_instance.join("macros"))
+            .await
+            .and(tokio::fs::create_dir_all(&path_to_resources.join("mods")).await)
+            .and(tokio::fs::create_dir_all(&path_to_resources.join("worlds")).await)
+            .and(tokio::fs::create_dir_all(&path_to_resources.join("defaults")).await)
+            .context("Could not create some directories for instance")?;
+        if !path_to_eula.exists() {
+            tokio::fs::write(&path_to_eula, "#generated by Lodestone\neula=true")
+                .await
+                .context("Could not create eula.txt")?;
+        }
+        let path_to_process_record = path_to_instance.join(PROCESS_RECORD_FILE);
+        if path_to_process_record.exists() {
+            tokio::fs::remove_file(&path_to_process_record)
+                .await
+                .context("Could not remove the process record of the old server")?;
+        }
+
+        // Step 2: Download JRE
+        let (jre, jre_major_version) =
+            Self::install_jre(&config.version, progression_event_id, &event_broadcaster).await?;
+
+        // Step 3: Set up the server jar
+        event_broadcaster.send(Event::new_progression_event_update(
+            progression_event_id,
+            "3/4: Setting up the server jar",
+            1.0,
+        ));
+        if let Some(server_jar) = detected.server_jar {
+            prepare_server_jar(&path_to_instance, &server_jar).await?;
+        }
+
+        // Step 4: Finishing Up
+        event_broadcaster.send(Event::new_progression_event_update(
+            progression_event_id,
+            "4/4: Finishing up",
+            1.0,
+        ));
+        let port = config.port;
+        let restore_config = RestoreConfig {
+            name: config.name,
+            version: config.version,
+            flavour: config.flavour,
+            description: config.description.unwrap_or_default(),
+            cmd_args: config.cmd_args,
+            port,
+            min_ram: config.min_ram.unwrap_or(2048),
+            max_ram: config.max_ram.unwrap_or(4096),
+            auto_start: config.auto_start.unwrap_or(false),
+            restart_on_crash: config.restart_on_crash.unwrap_or(false),
+            backup_period: config.backup_period,
+            jre_major_version,
+            // the server has been run before, just not by us
+            has_started: true,
+            use_pty: false,
+            config_files: Vec::new(),
+            bedrock_port: None,
+            console_buffer_lines: DEFAULT_CONSOLE_BUFFER_LINES,
+            gc_logging: false,
+            sandbox: SandboxLevel::Off,
+            priority: ProcessPriority::Normal,
+            log_patterns: Vec::new(),
+            startup_timeout: 0,
+            on_startup_timeout: StartupTimeoutAction::Leave,
+            wasm_macro_memory: DEFAULT_WASM_MACRO_MEMORY,
+            wasm_macro_timeout: DEFAULT_WASM_MACRO_TIMEOUT,
+            disabled_global_macros: Vec::new(),
+            java_cmd: Some(jre.to_string_lossy().to_string()),
+        };
+        tokio::fs::write(
+            &path_to_config,
+            to_string_pretty(&restore_config).context(
+                "Failed to serialize config to string. This is a bug, please report it.",
+            )?,
+        )
+        .await
+        .context(format!(
+            "Failed to write config file at {}",
+            &path_to_config.display()
+        ))?;
+        let mut instance = MinecraftInstance::restore(
+            path_to_instance,
+            dot_lodestone_config,
+            event_broadcaster,
+            macro_executor,
+        )
+        .await?;
+        // server.properties is only rewritten if it has to be, it may hold what we can't parse
+        if detected.port != Some(port) {
+            instance.set_port(port).await?;
+        }
+        Ok(instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory_flags() {
+        assert_eq!(
+            parse_memory_flags("#!/bin/sh\njava -Xms1G -Xmx4096M -jar server.jar nogui\n"),
+            (Some(1024), Some(4096))
+        );
+        assert_eq!(
+            parse_memory_flags("# -Xmx8G\njava -jar server.jar"),
+            (None, None)
+        );
+        assert_eq!(parse_memory_arg("512m"), Some(512));
+        assert_eq!(parse_memory_arg("4T"), None);
+    }
+
+    #[test]
+    fn test_versions_in_names() {
+        assert_eq!(
+            version_in_name("paper-1.20.1-196.jar"),
+            Some("1.20.1".to_string())
+        );
+        assert_eq!(
+            version_in_name("minecraft_server.1.8.9.jar"),
+            Some("1.8.9".to_string())
+        );
+        assert_eq!(version_in_name("server.jar"), None);
+        assert_eq!(paper_build_in_name("paper-1.20.1-196.jar"), Some(196));
+        assert_eq!(
+            version_in_paper_history(r#"{"currentVersion":"git-Paper-196 (MC: 1.20.1)"}"#),
+            Some("1.20.1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_detect_server() {
+        let temp_dir = tempdir::TempDir::new("test_import").unwrap();
+        let path = temp_dir.path();
+        std::fs::write(
+            path.join("server.properties"),
+            "motd=hi\nserver-port=25570\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path.join("start.sh"),
+            "java -Xmx6G -jar paper-1.19.4-550.jar",
+        )
+        .unwrap();
+        let mut jar =
+            zip::ZipWriter::new(std::fs::File::create(path.join("paper-1.19.4-550.jar")).unwrap());
+        jar.start_file("io/papermc/paperclip/Main.class", Default::default())
+            .unwrap();
+        jar.finish().unwrap();
+
+        assert_eq!(
+            detect_server(path).unwrap(),
+            DetectedServer {
+                version: "1.19.4".to_string(),
+                flavour: Flavour::Paper {
+                    build_version: Some(PaperBuildVersion(550)),
+                },
+                server_jar: Some("paper-1.19.4-550.jar".to_string()),
+                port: Some(25570),
+                min_ram: None,
+                max_ram: Some(6144),
+            }
+        );
+
+        std::fs::create_dir_all(path.join("libraries/net/minecraftforge/forge/1.20.1-47.1.0"))
+            .unwrap();
+        assert_eq!(
+            detect_server(path).unwrap().flavour,
+            Flavour::Forge {
+                build_version: Some(ForgeBuildVersion("1.20.1-47.1.0".to_string())),
+            }
+        );
+    }
+
+    #[test]
+    fn test_extract_server_archive() {
+        use std::io::Write;
+
+        let temp_dir = tempdir::TempDir::new("test_extract").unwrap();
+        let archive = temp_dir.path().join("server.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+        zip.start_file("server/eula.txt", Default::default())
+            .unwrap();
+        zip.write_all(b"eula=true").unwrap();
+        // a folder with the same name as the one the server is in
+        zip.start_file("server/server/server.properties", Default::default())
+            .unwrap();
+        zip.write_all(b"server-port=25570").unwrap();
+        zip.finish().unwrap();
+
+        let dest = temp_dir.path().join("dest");
+        extract_server_archive(&archive, &dest, 1024).unwrap();
+        assert!(dest.join("eula.txt").is_file());
+        assert!(dest.join("server/server.properties").is_file());
+
+        let dest = temp_dir.path().join("too_small");
+        assert!(extract_server_archive(&archive, &dest, 10).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 use fancy_regex::Regex;
 use lazy_static::lazy_static;
 
+use crate::log_parser::{LogPattern, LogPatternKind};
+
 pub struct PlayerMessage {
     pub player: String,
     pub message: String,
@@ -37,39 +39,44 @@ pub fn parse_player_msg(msg: &str) -> Option<PlayerMessage> {
     }
 }
 
-pub fn parse_player_joined(system_msg: &str) -> Option<String> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"(.+) joined the game").unwrap();
-    }
-    if RE.is_match(system_msg).unwrap() {
-        if let Some(cap) = RE.captures(system_msg).ok()? {
-            Some(cap.get(1)?.as_str().to_string())
-        } else {
-            None
-        }
-    } else {
-        None
-    }
+/// What [`crate::log_parser`] recognizes in the logs of vanilla, Forge, Fabric, Paper and
+/// Spigot servers. The name has to follow the logger's `]: ` right away, so chat messages
+/// quoting a join can't fake one
+pub fn log_patterns() -> Vec<LogPattern> {
+    vec![
+        LogPattern::new(
+            LogPatternKind::PlayerJoined,
+            r"\]: (?P<player>[^\s<\[]\S*) joined the game",
+        ),
+        LogPattern::new(
+            LogPatternKind::PlayerLeft,
+            r"\]: (?P<player>[^\s<\[]\S*) left the game",
+        ),
+        LogPattern::new(LogPatternKind::Ready, r"\]: Done \(.+\)!"),
+        LogPattern::new(
+            LogPatternKind::Error,
+            r"[/ ](?:ERROR|FATAL)\](?: \[[^\]]*\])?: (?P<message>.+)",
+        ),
+    ]
 }
 
-pub fn parse_player_left(system_msg: &str) -> Option<String> {
+/// The one minute average of Paper and Spigot's `tps` command
+pub fn parse_tps(system_msg: &str) -> Option<f64> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"(.+) left the game").unwrap();
-    }
-    if RE.is_match(system_msg).unwrap() {
-        if let Some(cap) = RE.captures(system_msg).ok()? {
-            Some(cap.get(1)?.as_str().to_string())
-        } else {
-            None
-        }
-    } else {
-        None
+        static ref RE: Regex = Regex::new(r"TPS from last 1m, 5m, 15m: \*?([\d.]+)").unwrap();
     }
+    RE.captures(system_msg).ok()??.get(1)?.as_str().parse().ok()
 }
 
-pub fn parse_server_started(system_msg: &str) -> bool {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r#"Done \(.+\)!"#).unwrap();
-    }
-    RE.is_match(system_msg).unwrap()
+#[test]
+fn test_parse_tps() {
+    assert_eq!(
+        parse_tps("[12:00:00 INFO]: TPS from last 1m, 5m, 15m: *20.0, 19.98, 19.97"),
+        Some(20.0)
+    );
+    assert_eq!(
+        parse_tps("[12:00:00 INFO]: TPS from last 1m, 5m, 15m: 14.2, 18.1, 19.5"),
+        Some(14.2)
+    );
+    assert_eq!(parse_tps("[12:00:00 INFO]: Done (3.2s)!"), None);
 }
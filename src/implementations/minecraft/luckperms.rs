@@ -0,0 +1,556 @@
+//! Basic rank management for instances running LuckPerms.
+//!
+//! Groups and users are read straight from LuckPerms' storage when it is flat file YAML or JSON,
+//! the databases it otherwise uses aren't read. Changes are always made through `lp` console
+//! commands, so LuckPerms stays the one writing its storage and running servers pick them up
+//! right away, which also means the instance has to be running to change anything.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::t_server::{State, TServer},
+};
+
+use super::MinecraftInstance;
+
+/// Where LuckPerms keeps its data, on Bukkit derived servers and on modded ones
+const DATA_DIRS: [&str; 2] = ["plugins/LuckPerms", "config/luckperms"];
+const CONFIG_FILES: [&str; 2] = ["config.yml", "luckperms.conf"];
+const MAX_USERS: usize = 5000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct LuckPermsStatus {
+    pub installed: bool,
+    /// `storage-method` from LuckPerms' config, `h2` when it isn't set
+    pub storage_method: Option<String>,
+    /// Whether groups and users can be read, only flat file YAML and JSON storage can be
+    pub readable: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct LuckPermsNode {
+    pub key: String,
+    pub value: bool,
+    /// Like `server` and `world`, the node only applies where they match
+    pub context: BTreeMap<String, String>,
+    /// Seconds since the epoch, for temporary nodes
+    pub expiry: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct LuckPermsGroup {
+    pub name: String,
+    pub weight: Option<i32>,
+    pub display_name: Option<String>,
+    pub parents: Vec<String>,
+    pub permissions: Vec<LuckPermsNode>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct LuckPermsUser {
+    pub uuid: String,
+    pub name: Option<String>,
+    pub primary_group: Option<String>,
+    pub parents: Vec<String>,
+    pub permissions: Vec<LuckPermsNode>,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum LuckPermsChange {
+    CreateGroup {
+        group: String,
+    },
+    DeleteGroup {
+        group: String,
+    },
+    SetGroupWeight {
+        group: String,
+        weight: i32,
+    },
+    SetGroupPermission {
+        group: String,
+        permission: String,
+        value: bool,
+    },
+    UnsetGroupPermission {
+        group: String,
+        permission: String,
+    },
+    /// `user` is a player name or UUID
+    AddUserGroup {
+        user: String,
+        group: String,
+    },
+    RemoveUserGroup {
+        user: String,
+        group: String,
+    },
+    SetUserPermission {
+        user: String,
+        permission: String,
+        value: bool,
+    },
+    UnsetUserPermission {
+        user: String,
+        permission: String,
+    },
+}
+
+impl LuckPermsChange {
+    /// The `lp` command making the change, refusing arguments that would change what it does
+    pub fn command(&self) -> Result<String, Error> {
+        lazy_static! {
+            static ref GROUP_RE: Regex = Regex::new(r"^[a-z0-9_\-]{1,36}$").unwrap();
+            static ref USER_RE: Regex = Regex::new(r"^[A-Za-z0-9_\-]{1,36}$").unwrap();
+            static ref PERMISSION_RE: Regex = Regex::new(r"^[A-Za-z0-9_\-.*]{1,128}$").unwrap();
+        }
+        let check = |re: &Regex, what: &str, value: &str| -> Result<(), Error> {
+            if re.is_match(value).unwrap_or(false) {
+                Ok(())
+            } else {
+                Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid {what} {value}"),
+                })
+            }
+        };
+        let group = |name: &str| check(&GROUP_RE, "group name", name);
+        let user = |name: &str| check(&USER_RE, "user", name);
+        let permission = |node: &str| check(&PERMISSION_RE, "permission", node);
+        Ok(match self {
+            Self::CreateGroup { group: name } => {
+                group(name)?;
+                format!("lp creategroup {name}")
+            }
+            Self::DeleteGroup { group: name } => {
+                group(name)?;
+                format!("lp deletegroup {name}")
+            }
+            Self::SetGroupWeight {
+                group: name,
+                weight,
+            } => {
+                group(name)?;
+                format!("lp group {name} setweight {weight}")
+            }
+            Self::SetGroupPermission {
+                group: name,
+                permission: node,
+                value,
+            } => {
+                group(name)?;
+                permission(node)?;
+                format!("lp group {name} permission set {node} {value}")
+            }
+            Self::UnsetGroupPermission {
+                group: name,
+                permission: node,
+            } => {
+                group(name)?;
+                permission(node)?;
+                format!("lp group {name} permission unset {node}")
+            }
+            Self::AddUserGroup {
+                user: name,
+                group: parent,
+            } => {
+                user(name)?;
+                group(parent)?;
+                format!("lp user {name} parent add {parent}")
+            }
+            Self::RemoveUserGroup {
+                user: name,
+                group: parent,
+            } => {
+                user(name)?;
+                group(parent)?;
+                format!("lp user {name} parent remove {parent}")
+            }
+            Self::SetUserPermission {
+                user: name,
+                permission: node,
+                value,
+            } => {
+                user(name)?;
+                permission(node)?;
+                format!("lp user {name} permission set {node} {value}")
+            }
+            Self::UnsetUserPermission {
+                user: name,
+                permission: node,
+            } => {
+                user(name)?;
+                permission(node)?;
+                format!("lp user {name} permission unset {node}")
+            }
+        })
+    }
+}
+
+fn storage_method(config: &str) -> Option<String> {
+    lazy_static! {
+        static ref STORAGE_METHOD_RE: Regex =
+            Regex::new(r#"(?m)^\s*storage-method\s*[:=]\s*"?([A-Za-z0-9_\-]+)"?"#).unwrap();
+    }
+    STORAGE_METHOD_RE
+        .captures(config)
+        .ok()
+        .flatten()
+        .and_then(|captures| captures.get(1))
+        .map(|method| method.as_str().to_lowercase())
+}
+
+/// The key and attributes of a node in a section. YAML storage writes `- key: {attributes}`,
+/// JSON writes the key next to the attributes under `key_field`, and both write nodes without
+/// attributes as just the key.
+fn parse_entry(entry: &Value, key_field: &str) -> Option<(String, Option<Value>)> {
+    match entry {
+        Value::String(key) => Some((key.clone(), None)),
+        Value::Mapping(map) => match map.get(key_field).and_then(Value::as_str) {
+            Some(key) => Some((key.to_string(), Some(entry.clone()))),
+            None if map.len() == 1 => {
+                let (key, attributes) = map.iter().next()?;
+                Some((key.as_str()?.to_string(), Some(attributes.clone())))
+            }
+            None => None,
+        },
+        _ => None,
+    }
+}
+
+fn parse_node(entry: &Value, key_field: &str) -> Option<LuckPermsNode> {
+    let (key, attributes) = parse_entry(entry, key_field)?;
+    let mut node = LuckPermsNode {
+        key,
+        value: true,
+        context: BTreeMap::new(),
+        expiry: None,
+    };
+    let attributes = match attributes {
+        Some(Value::Mapping(attributes)) => attributes,
+        _ => return Some(node),
+    };
+    for (name, value) in attributes.iter() {
+        match name.as_str() {
+            Some("value") => node.value = value.as_bool().unwrap_or(true),
+            Some("expiry") => node.expiry = value.as_i64(),
+            Some("server") | Some("world") => {
+                if let (Some(name), Some(value)) = (name.as_str(), value.as_str()) {
+                    node.context.insert(name.to_string(), value.to_string());
+                }
+            }
+            Some("context") => {
+                if let Value::Mapping(context) = value {
+                    for (name, value) in context.iter() {
+                        let value = match value {
+                            Value::Sequence(values) => values
+                                .iter()
+                                .filter_map(Value::as_str)
+                                .collect::<Vec<_>>()
+                                .join(","),
+                            value => value.as_str().unwrap_or_default().to_string(),
+                        };
+                        if let Some(name) = name.as_str() {
+                            node.context.insert(name.to_string(), value);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(node)
+}
+
+fn nodes(holder: &Value, section: &str, key_field: &str) -> Vec<LuckPermsNode> {
+    holder
+        .get(section)
+        .and_then(Value::as_sequence)
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| parse_node(entry, key_field))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Inheritance used to be stored as `group.<name>` permissions, newer versions have a section
+fn parents(holder: &Value, permissions: &[LuckPermsNode]) -> Vec<String> {
+    let mut parents: Vec<String> = nodes(holder, "parents", "group")
+        .into_iter()
+        .map(|node| node.key)
+        .chain(
+            permissions
+                .iter()
+                .filter_map(|node| node.key.strip_prefix("group.").map(str::to_string)),
+        )
+        .collect();
+    let mut seen = HashSet::new();
+    parents.retain(|parent| seen.insert(parent.clone()));
+    parents
+}
+
+fn parse_group(content: &str) -> Option<LuckPermsGroup> {
+    let holder: Value = serde_yaml::from_str(content).ok()?;
+    let permissions = nodes(&holder, "permissions", "permission");
+    let find = |prefix: &str| {
+        permissions
+            .iter()
+            .find_map(|node| node.key.strip_prefix(prefix).map(str::to_string))
+    };
+    Some(LuckPermsGroup {
+        name: holder.get("name")?.as_str()?.to_string(),
+        weight: find("weight.").and_then(|weight| weight.parse().ok()),
+        display_name: find("displayname."),
+        parents: parents(&holder, &permissions),
+        permissions: permissions
+            .iter()
+            .filter(|node| {
+                !["group.", "weight.", "displayname."]
+                    .iter()
+                    .any(|prefix| node.key.starts_with(prefix))
+            })
+            .cloned()
+            .collect(),
+    })
+}
+
+fn parse_user(content: &str) -> Option<LuckPermsUser> {
+    let holder: Value = serde_yaml::from_str(content).ok()?;
+    let permissions = nodes(&holder, "permissions", "permission");
+    let text = |key: &str| holder.get(key).and_then(Value::as_str).map(str::to_string);
+    Some(LuckPermsUser {
+        uuid: text("uuid")?,
+        name: text("name"),
+        primary_group: text("primary-group").or_else(|| text("primaryGroup")),
+        parents: parents(&holder, &permissions),
+        permissions: permissions
+            .into_iter()
+            .filter(|node| !node.key.starts_with("group."))
+            .collect(),
+    })
+}
+
+impl MinecraftInstance {
+    /// LuckPerms' data directory and storage method, if it has been run on the instance
+    async fn luckperms_storage(&self) -> Option<(PathBuf, String)> {
+        for dir in DATA_DIRS {
+            let dir = self.path_to_instance.join(dir);
+            for config in CONFIG_FILES {
+                if let Ok(config) = tokio::fs::read_to_string(dir.join(config)).await {
+                    let method = storage_method(&config).unwrap_or_else(|| "h2".to_string());
+                    return Some((dir, method));
+                }
+            }
+        }
+        None
+    }
+
+    pub async fn luckperms_status(&self) -> LuckPermsStatus {
+        match self.luckperms_storage().await {
+            Some((_, method)) => LuckPermsStatus {
+                installed: true,
+                readable: matches!(method.as_str(), "yaml" | "json"),
+                storage_method: Some(method),
+            },
+            None => LuckPermsStatus {
+                installed: false,
+                storage_method: None,
+                readable: false,
+            },
+        }
+    }
+
+    /// The files in a directory of LuckPerms' flat file storage
+    async fn luckperms_files(&self, kind: &str) -> Result<Vec<PathBuf>, Error> {
+        let unreadable = |message: &str| Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("{message}"),
+        };
+        let (dir, method) = self
+            .luckperms_storage()
+            .await
+            .ok_or_else(|| unreadable("LuckPerms is not installed on this instance"))?;
+        if !matches!(method.as_str(), "yaml" | "json") {
+            return Err(unreadable(&format!(
+                "LuckPerms stores its data with {method}, only yaml and json storage can be read"
+            )));
+        }
+        let dir = dir.join(format!("{method}-storage")).join(kind);
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e)
+                    .context(format!("Failed to read {}", dir.display()))
+                    .map_err(Into::into)
+            }
+        };
+        let mut files = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("Failed to read {}", dir.display()))?
+        {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) == Some(method.as_str()) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    async fn read_luckperms<T>(
+        &self,
+        kind: &str,
+        limit: usize,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<Vec<T>, Error> {
+        let mut ret = Vec::new();
+        for path in self.luckperms_files(kind).await?.into_iter().take(limit) {
+            if let Some(parsed) = read_to_string(&path).await?.as_deref().and_then(&parse) {
+                ret.push(parsed);
+            }
+        }
+        Ok(ret)
+    }
+
+    pub async fn luckperms_groups(&self) -> Result<Vec<LuckPermsGroup>, Error> {
+        self.read_luckperms("groups", usize::MAX, parse_group).await
+    }
+
+    /// At most [`MAX_USERS`], by UUID
+    pub async fn luckperms_users(&self) -> Result<Vec<LuckPermsUser>, Error> {
+        self.read_luckperms("users", MAX_USERS, parse_user).await
+    }
+
+    pub async fn apply_luckperms_change(
+        &self,
+        change: &LuckPermsChange,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let command = change.command()?;
+        if self.state().await != State::Running {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("LuckPerms is changed through the console, start the instance first"),
+            });
+        }
+        self.send_command(&command, caused_by).await
+    }
+}
+
+/// `None` if the file went away while being read
+async fn read_to_string(path: &Path) -> Result<Option<String>, Error> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)
+            .context(format!("Failed to read {}", path.display()))
+            .map_err(Into::into),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_yaml_group() {
+        let group = parse_group(
+            r#"
+name: admin
+permissions:
+- weight.100
+- displayname.Admin
+- essentials.fly
+- minecraft.command.gamemode:
+    value: false
+    server: survival
+parents:
+- mod
+"#,
+        )
+        .unwrap();
+        assert_eq!(group.name, "admin");
+        assert_eq!(group.weight, Some(100));
+        assert_eq!(group.display_name.as_deref(), Some("Admin"));
+        assert_eq!(group.parents, vec!["mod".to_string()]);
+        assert_eq!(group.permissions.len(), 2);
+        assert!(!group.permissions[1].value);
+        assert_eq!(
+            group.permissions[1]
+                .context
+                .get("server")
+                .map(String::as_str),
+            Some("survival")
+        );
+    }
+
+    #[test]
+    fn test_parse_json_user() {
+        let user = parse_user(
+            r#"{
+  "uuid": "8667ba71-b85a-4004-af54-457a9734eed7",
+  "name": "Steve",
+  "primaryGroup": "admin",
+  "parents": [{ "group": "admin", "expiry": 1700000000 }],
+  "permissions": [{ "permission": "essentials.home", "value": true, "context": { "world": ["world", "world_nether"] } }]
+}"#,
+        )
+        .unwrap();
+        assert_eq!(user.name.as_deref(), Some("Steve"));
+        assert_eq!(user.primary_group.as_deref(), Some("admin"));
+        assert_eq!(user.parents, vec!["admin".to_string()]);
+        assert_eq!(
+            user.permissions[0].context.get("world").map(String::as_str),
+            Some("world,world_nether")
+        );
+    }
+
+    #[test]
+    fn test_change_command() {
+        assert_eq!(
+            LuckPermsChange::SetGroupPermission {
+                group: "admin".to_string(),
+                permission: "essentials.*".to_string(),
+                value: false,
+            }
+            .command()
+            .unwrap(),
+            "lp group admin permission set essentials.* false"
+        );
+        assert!(LuckPermsChange::AddUserGroup {
+            user: "Steve; op Steve".to_string(),
+            group: "admin".to_string(),
+        }
+        .command()
+        .is_err());
+        assert_eq!(
+            storage_method("storage-method: YAML\n").as_deref(),
+            Some("yaml")
+        );
+        assert_eq!(
+            storage_method("storage-method = \"json\"\n").as_deref(),
+            Some("json")
+        );
+    }
+}
@@ -2,20 +2,30 @@ use std::{
     cell::RefCell,
     path::{Path, PathBuf},
     rc::Rc,
+    sync::Arc,
 };
 
 use async_trait::async_trait;
 use color_eyre::eyre::{eyre, Context};
 use deno_core::{anyhow, op, OpState};
+use serde::Deserialize;
+use tracing::warn;
 
 use crate::{
-    error::Error,
+    error::{Error, ErrorKind},
     events::{CausedBy, EventInner},
+    global_macro,
     macro_executor::{self, MacroPID, SpawnResult, WorkerOptionGenerator},
+    prelude::path_to_global_macros,
     traits::{
-        t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry},
+        t_configurable::{
+            manifest::{ConfigurableValueType, SettingManifest},
+            GameType,
+        },
+        t_macro::{parse_macro_args, HistoryEntry, MacroEntry, TMacro, TaskEntry},
         t_server::TServer,
     },
+    wasm_macro::WasmMacroHost,
 };
 
 use super::MinecraftInstance;
@@ -40,6 +50,14 @@ async fn on_event(
     event: String,
 ) -> Result<Option<String>, anyhow::Error> {
     let instance = state.borrow().borrow::<MinecraftInstance>().clone();
+    Ok(next_macro_event(&instance, &event).await?)
+}
+
+/// Waits for the next event of a kind macros can listen to, as JSON
+async fn next_macro_event(
+    instance: &MinecraftInstance,
+    event: &str,
+) -> Result<Option<String>, Error> {
     let mut event_rx = instance.event_broadcaster.subscribe();
     if event == "playerMessage" {
         while let Ok(event) = event_rx.recv().await {
@@ -114,13 +132,36 @@ async fn on_event(
                 }
             }
         }
+    } else {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Unknown event {}", event),
+        });
+    }
+    Ok(None)
+}
+
+struct MinecraftWasmHost {
+    instance: MinecraftInstance,
+}
+
+#[async_trait]
+impl WasmMacroHost for MinecraftWasmHost {
+    async fn send_stdin(&self, cmd: &str) -> Result<(), Error> {
+        self.instance.send_command(cmd, CausedBy::Unknown).await
+    }
+    async fn send_rcon(&self, cmd: &str) -> Result<String, Error> {
+        self.instance.send_rcon(cmd).await
+    }
+    async fn on_event(&self, event: &str) -> Result<Option<String>, Error> {
+        next_macro_event(&self.instance, event).await
     }
-    todo!()
 }
 
 pub fn resolve_macro_invocation(path_to_macro: &Path, macro_name: &str) -> Option<PathBuf> {
     let ts_macro = path_to_macro.join(macro_name).with_extension("ts");
     let js_macro = path_to_macro.join(macro_name).with_extension("js");
+    let wasm_macro = path_to_macro.join(macro_name).with_extension("wasm");
 
     let macro_folder = path_to_macro.join(macro_name);
 
@@ -128,19 +169,115 @@ pub fn resolve_macro_invocation(path_to_macro: &Path, macro_name: &str) -> Optio
         return Some(ts_macro);
     } else if js_macro.is_file() {
         return Some(js_macro);
+    } else if wasm_macro.is_file() {
+        return Some(wasm_macro);
     } else if macro_folder.is_dir() {
         // check if index.ts exists
         let index_ts = macro_folder.join("index.ts");
         let index_js = macro_folder.join("index.js");
+        let index_wasm = macro_folder.join("index.wasm");
         if index_ts.exists() {
             return Some(index_ts);
         } else if index_js.exists() {
             return Some(index_js);
+        } else if index_wasm.exists() {
+            return Some(index_wasm);
         }
     }
     None
 }
 
+/// A parameter as declared in a macro's parameter file
+#[derive(Deserialize)]
+struct MacroParameterDeclaration {
+    id: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+    value_type: ConfigurableValueType,
+    /// In the same form as the argument would be given
+    default: Option<String>,
+    #[serde(default)]
+    optional: bool,
+}
+
+impl MacroParameterDeclaration {
+    fn into_setting(self) -> Result<SettingManifest, Error> {
+        Ok(match (self.default, self.optional) {
+            (Some(default), _) => {
+                let default = self.value_type.parse_value(&default)?;
+                SettingManifest::new_value_with_type(
+                    self.id,
+                    self.name,
+                    self.description,
+                    Some(default.clone()),
+                    self.value_type,
+                    Some(default),
+                    false,
+                    true,
+                )
+            }
+            (None, true) => SettingManifest::new_optional_value(
+                self.id,
+                self.name,
+                self.description,
+                None,
+                self.value_type,
+                None,
+                false,
+                true,
+            ),
+            (None, false) => SettingManifest::new_required_unset(
+                self.id,
+                self.name,
+                self.description,
+                self.value_type,
+                false,
+                true,
+            ),
+        })
+    }
+}
+
+/// Reads the parameters a macro declares, given the module [`resolve_macro_invocation`] resolved
+/// it to: `<name>.params.json` next to a single file macro, or `params.json` in a macro
+/// directory. Macros without one take any arguments.
+pub fn read_macro_parameters(
+    path_to_macros: &Path,
+    path_to_macro: &Path,
+) -> Result<Vec<SettingManifest>, Error> {
+    let path = if path_to_macro.parent() == Some(path_to_macros) {
+        path_to_macro.with_extension("params.json")
+    } else {
+        path_to_macro.with_file_name("params.json")
+    };
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content =
+        std::fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+    let declarations: Vec<MacroParameterDeclaration> = serde_json::from_str(&content)
+        .context(format!("Invalid macro parameters in {}", path.display()))?;
+    let mut parameters: Vec<SettingManifest> = Vec::new();
+    for declaration in declarations {
+        if parameters
+            .iter()
+            .any(|parameter| parameter.get_identifier() == &declaration.id)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Macro parameter {} is declared twice in {}",
+                    declaration.id,
+                    path.display()
+                ),
+            });
+        }
+        parameters.push(declaration.into_setting()?);
+    }
+    Ok(parameters)
+}
+
 pub struct MinecraftMainWorkerGenerator {
     instance: MinecraftInstance,
 }
@@ -182,33 +319,48 @@ impl TMacro for MinecraftInstance {
         for entry in
             (std::fs::read_dir(&self.path_to_macros).context("Failed to read macro dir")?).flatten()
         {
-            // if the entry is a file, check if it has the .ts or .js extension
+            // if the entry is a file, check if it has the .ts, .js or .wasm extension
             let path = entry.path();
             let name = entry.file_name().to_string_lossy().to_string();
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if ext == "ts" || ext == "js" {
-                        ret.push(MacroEntry {
-                            last_run: self.macro_name_to_last_run.lock().await.get(&name).cloned(),
-                            name,
-                            path,
-                        })
-                    }
-                }
+            let is_macro = if path.is_file() {
+                path.extension()
+                    .map_or(false, |ext| ext == "ts" || ext == "js" || ext == "wasm")
             } else if path.is_dir() {
-                // check if index.ts or index.js exists
-                let index_ts = path.join("index.ts");
-                let index_js = path.join("index.js");
-                if index_ts.exists() || index_js.exists() {
-                    ret.push(MacroEntry {
-                        last_run: self.macro_name_to_last_run.lock().await.get(&name).cloned(),
-                        name,
-                        path,
-                    })
-                }
+                // check if index.ts, index.js or index.wasm exists
+                path.join("index.ts").exists()
+                    || path.join("index.js").exists()
+                    || path.join("index.wasm").exists()
+            } else {
+                false
+            };
+            if !is_macro {
+                continue;
             }
+            let parameters = match resolve_macro_invocation(&self.path_to_macros, &name) {
+                Some(path_to_macro) => read_macro_parameters(&self.path_to_macros, &path_to_macro)
+                    .unwrap_or_else(|e| {
+                        warn!("Ignoring the parameters of macro {name}: {e}");
+                        Vec::new()
+                    }),
+                None => Vec::new(),
+            };
+            ret.push(MacroEntry {
+                last_run: self.macro_name_to_last_run.lock().await.get(&name).cloned(),
+                name,
+                path,
+                parameters,
+                global_enabled: None,
+            })
         }
         ret.sort_by(|a, b| a.name.cmp(&b.name));
+        // the instance's own macros take the place of global ones of the same name
+        let disabled = self.config.lock().await.disabled_global_macros.clone();
+        let global_macros: Vec<MacroEntry> =
+            global_macro::list_global_macros(GameType::MinecraftJava, &disabled)
+                .into_iter()
+                .filter(|global| !ret.iter().any(|local| local.name == global.name))
+                .collect();
+        ret.extend(global_macros);
         Ok(ret)
     }
 
@@ -253,22 +405,54 @@ impl TMacro for MinecraftInstance {
         args: Vec<String>,
         caused_by: CausedBy,
     ) -> Result<TaskEntry, Error> {
-        let path_to_macro = resolve_macro_invocation(&self.path_to_macros, name)
-            .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
+        let (path_to_macros, path_to_macro) =
+            match resolve_macro_invocation(&self.path_to_macros, name) {
+                Some(path_to_macro) => (self.path_to_macros.clone(), path_to_macro),
+                None => {
+                    let disabled = self.config.lock().await.disabled_global_macros.clone();
+                    let path_to_macro = global_macro::resolve_global_macro(
+                        name,
+                        GameType::MinecraftJava,
+                        &disabled,
+                    )?
+                    .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
+                    (path_to_global_macros().clone(), path_to_macro)
+                }
+            };
+        let args = parse_macro_args(
+            &read_macro_parameters(&path_to_macros, &path_to_macro)?,
+            args,
+        )?;
 
-        let main_worker_generator = MinecraftMainWorkerGenerator::new(self.clone());
-        let SpawnResult { macro_pid: pid, .. } = self
-            .macro_executor
-            .spawn(
-                path_to_macro,
-                args,
-                caused_by,
-                Box::new(main_worker_generator),
-                None,
-                Some(self.uuid.clone()),
-                None,
-            )
-            .await?;
+        let pid = if path_to_macro.extension().map_or(false, |ext| ext == "wasm") {
+            self.macro_executor
+                .spawn_wasm(
+                    path_to_macro,
+                    args,
+                    caused_by,
+                    Arc::new(MinecraftWasmHost {
+                        instance: self.clone(),
+                    }),
+                    self.wasm_macro_limits().await,
+                    Some(self.uuid.clone()),
+                )
+                .await?
+        } else {
+            let main_worker_generator = MinecraftMainWorkerGenerator::new(self.clone());
+            let SpawnResult { macro_pid, .. } = self
+                .macro_executor
+                .spawn(
+                    path_to_macro,
+                    args,
+                    caused_by,
+                    Box::new(main_worker_generator),
+                    None,
+                    Some(self.uuid.clone()),
+                    None,
+                )
+                .await?;
+            macro_pid
+        };
         let entry = TaskEntry {
             pid,
             name: name.to_string(),
@@ -290,4 +474,25 @@ impl TMacro for MinecraftInstance {
         self.macro_executor.abort_macro(pid)?;
         Ok(())
     }
+
+    async fn set_global_macro_enabled(&mut self, name: &str, enabled: bool) -> Result<(), Error> {
+        if !global_macro::list_global_macros(GameType::MinecraftJava, &[])
+            .iter()
+            .any(|global| global.name == name)
+        {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Global macro {} not found", name),
+            });
+        }
+        let mut config = self.config.lock().await;
+        config
+            .disabled_global_macros
+            .retain(|disabled| disabled != name);
+        if !enabled {
+            config.disabled_global_macros.push(name.to_string());
+        }
+        drop(config);
+        self.write_config_to_file().await
+    }
 }
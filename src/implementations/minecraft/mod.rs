@@ -1,68 +1,102 @@
+mod action;
+mod adopt;
 pub mod configurable;
+pub mod crossplay;
+pub mod diagnostics;
 pub mod fabric;
 mod forge;
-mod line_parser;
+pub mod gc_log;
+pub mod import;
+pub mod line_parser;
+pub mod luckperms;
 pub mod r#macro;
+pub mod nbt;
 mod paper;
 pub mod player;
+pub mod player_data;
 mod players_manager;
+pub mod pregen;
+mod process;
+pub mod query;
 pub mod resource;
 pub mod server;
+pub mod upgrade;
 pub mod util;
 mod vanilla;
 pub mod versions;
+pub mod world_upgrade;
 
 use color_eyre::eyre::{eyre, Context, ContextCompat};
 use enum_kinds::EnumKind;
 use indexmap::IndexMap;
 
 use std::collections::HashMap;
+use std::path::Path;
 use std::process::Stdio;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Duration;
 use sysinfo::SystemExt;
 use tokio::io::AsyncWriteExt;
-use tokio::process::{Child, Command};
+use tokio::process::Command;
 
 use tokio::sync::Mutex;
 
 use ::serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
 
-use tracing::error;
+use tracing::{error, info, warn};
 
 use tokio;
 use ts_rs::TS;
 
+use crate::console_buffer::{ConsoleBuffer, DEFAULT_CONSOLE_BUFFER_LINES};
 use crate::error::Error;
 use crate::event_broadcaster::EventBroadcaster;
-use crate::events::{Event, ProgressionEventID};
+use crate::events::{
+    CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, ProgressionEventID,
+};
 use crate::macro_executor::{MacroExecutor, MacroPID};
 use crate::prelude::path_to_binaries;
 use crate::traits::t_configurable::PathBuf;
+use crate::traits::t_configurable::TConfigurable;
 
 use crate::traits::t_configurable::manifest::{
     ConfigurableManifest, ConfigurableValue, ConfigurableValueType, SectionManifest,
     SettingManifest, SetupManifest, SetupValue,
 };
 
+use crate::log_parser::{self, LineParser, LogPattern};
+use crate::process_priority::ProcessPriority;
+use crate::process_tree::ProcessTree;
+use crate::sandbox::{SandboxLevel, SandboxPolicy};
+use crate::startup_watchdog::StartupTimeoutAction;
 use crate::traits::t_macro::TaskEntry;
-use crate::traits::t_server::State;
+use crate::traits::t_server::{DiskSample, State, TServer};
 use crate::traits::TInstance;
-use crate::types::{DotLodestoneConfig, InstanceUuid};
+use crate::types::{DotLodestoneConfig, InstanceUuid, Snowflake};
 use crate::util::{
-    dont_spawn_terminal, download_file, format_byte, format_byte_download, unzip_file_async,
-    UnzipOption,
+    dont_spawn_terminal, download_file, format_byte, format_byte_download, scoped_join_win_safe,
+    unzip_file_async, UnzipOption,
 };
+use crate::wasm_macro::{WasmLimits, DEFAULT_WASM_MACRO_MEMORY, DEFAULT_WASM_MACRO_TIMEOUT};
 
 use self::configurable::{CmdArgSetting, ServerPropertySetting};
 use self::fabric::get_fabric_minecraft_versions;
 use self::forge::get_forge_minecraft_versions;
 use self::paper::get_paper_minecraft_versions;
 use self::players_manager::PlayersManager;
+use self::process::{ServerProcess, ServerStdin};
 use self::util::{get_jre_url, get_server_jar_url, read_properties_from_path};
 use self::vanilla::get_vanilla_minecraft_versions;
 
+/// How long the server gets to acknowledge `save-off` and `save-on`
+const SAVE_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a lagging server gets to answer the `tps` command
+const TPS_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the server gets to write the whole world to disk on `save-all flush`
+const SAVE_FLUSH_TIMEOUT: Duration = Duration::from_secs(120);
+
 #[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
 #[ts(export)]
 pub struct FabricLoaderVersion(String);
@@ -168,6 +202,56 @@ pub struct RestoreConfig {
     pub backup_period: Option<u32>,
     pub jre_major_version: u64,
     pub has_started: bool,
+    /// Attach the server to a pseudo terminal instead of plain pipes
+    #[serde(default)]
+    pub use_pty: bool,
+    /// Config files, relative to the instance's directory, editable as setting sections
+    #[serde(default)]
+    pub config_files: Vec<String>,
+    /// Set once Geyser and Floodgate are installed, see [`crossplay`]
+    #[serde(default)]
+    pub bedrock_port: Option<u32>,
+    /// How many console lines are kept in memory for the console view
+    #[serde(default = "default_console_buffer_lines")]
+    pub console_buffer_lines: u32,
+    /// Write rotated GC logs, see [`gc_log`]
+    #[serde(default)]
+    pub gc_logging: bool,
+    /// How tightly the server process is confined, see [`crate::sandbox`]
+    #[serde(default)]
+    pub sandbox: SandboxLevel,
+    /// CPU and IO priority the server is started with
+    #[serde(default)]
+    pub priority: ProcessPriority,
+    /// Tried before the built-in ones, see [`crate::log_parser`]
+    #[serde(default)]
+    pub log_patterns: Vec<LogPattern>,
+    /// Minutes the server may take to start, 0 for no limit, see [`crate::startup_watchdog`]
+    #[serde(default)]
+    pub startup_timeout: u32,
+    #[serde(default)]
+    pub on_startup_timeout: StartupTimeoutAction,
+    /// Megabytes each WebAssembly macro may use, see [`crate::wasm_macro`]
+    #[serde(default = "default_wasm_macro_memory")]
+    pub wasm_macro_memory: u32,
+    /// Minutes each WebAssembly macro may run, 0 for no limit
+    #[serde(default = "default_wasm_macro_timeout")]
+    pub wasm_macro_timeout: u32,
+    /// Macros of the core's macros directory this instance doesn't run, see [`crate::global_macro`]
+    #[serde(default)]
+    pub disabled_global_macros: Vec<String>,
+}
+
+fn default_console_buffer_lines() -> u32 {
+    DEFAULT_CONSOLE_BUFFER_LINES
+}
+
+fn default_wasm_macro_memory() -> u32 {
+    DEFAULT_WASM_MACRO_MEMORY
+}
+
+fn default_wasm_macro_timeout() -> u32 {
+    DEFAULT_WASM_MACRO_TIMEOUT
 }
 
 #[derive(Clone)]
@@ -191,8 +275,12 @@ pub struct MinecraftInstance {
     auto_start: Arc<AtomicBool>,
     restart_on_crash: Arc<AtomicBool>,
     backup_period: Option<u32>,
-    process: Arc<Mutex<Option<Child>>>,
-    stdin: Arc<Mutex<Option<tokio::process::ChildStdin>>>,
+    process: Arc<Mutex<Option<ServerProcess>>>,
+    /// pid of a server process left running by a previous core, see [`adopt`]
+    adopted_pid: Arc<Mutex<Option<u32>>>,
+    /// The server process and whatever it started
+    process_tree: Arc<Mutex<Option<ProcessTree>>>,
+    stdin: Arc<Mutex<Option<ServerStdin>>>,
     system: Arc<Mutex<sysinfo::System>>,
     players_manager: Arc<Mutex<PlayersManager>>,
     configurable_manifest: Arc<Mutex<ConfigurableManifest>>,
@@ -200,6 +288,12 @@ pub struct MinecraftInstance {
     rcon_conn: Arc<Mutex<Option<rcon::Connection<tokio::net::TcpStream>>>>,
     macro_name_to_last_run: Arc<Mutex<HashMap<String, i64>>>,
     pid_to_task_entry: Arc<Mutex<IndexMap<MacroPID, TaskEntry>>>,
+    pregen: Arc<Mutex<Option<pregen::PregenTask>>>,
+    console_buffer: ConsoleBuffer,
+    last_disk_sample: Arc<Mutex<Option<DiskSample>>>,
+    server_status: Arc<Mutex<Option<query::ServerStatus>>>,
+    /// Job id of the upgrade run going on, see [`world_upgrade`]
+    world_upgrade: Arc<Mutex<Option<Snowflake>>>,
 }
 
 #[tokio::test]
@@ -398,6 +492,34 @@ impl MinecraftInstance {
         cmd_args_config_map.insert(max_ram.get_identifier().to_owned(), max_ram.into());
         let java_cmd = CmdArgSetting::JavaCmd(java_cmd);
         cmd_args_config_map.insert(java_cmd.get_identifier().to_owned(), java_cmd.into());
+        let use_pty = CmdArgSetting::UsePty(restore_config.use_pty);
+        cmd_args_config_map.insert(use_pty.get_identifier().to_owned(), use_pty.into());
+        let gc_logging = CmdArgSetting::GcLogging(restore_config.gc_logging);
+        cmd_args_config_map.insert(gc_logging.get_identifier().to_owned(), gc_logging.into());
+        let sandbox = CmdArgSetting::Sandbox(restore_config.sandbox);
+        cmd_args_config_map.insert(sandbox.get_identifier().to_owned(), sandbox.into());
+        let priority = CmdArgSetting::Priority(restore_config.priority);
+        cmd_args_config_map.insert(priority.get_identifier().to_owned(), priority.into());
+        let startup_timeout = CmdArgSetting::StartupTimeout(restore_config.startup_timeout);
+        cmd_args_config_map.insert(
+            startup_timeout.get_identifier().to_owned(),
+            startup_timeout.into(),
+        );
+        let on_startup_timeout = CmdArgSetting::OnStartupTimeout(restore_config.on_startup_timeout);
+        cmd_args_config_map.insert(
+            on_startup_timeout.get_identifier().to_owned(),
+            on_startup_timeout.into(),
+        );
+        let wasm_macro_memory = CmdArgSetting::WasmMacroMemory(restore_config.wasm_macro_memory);
+        cmd_args_config_map.insert(
+            wasm_macro_memory.get_identifier().to_owned(),
+            wasm_macro_memory.into(),
+        );
+        let wasm_macro_timeout = CmdArgSetting::WasmMacroTimeout(restore_config.wasm_macro_timeout);
+        cmd_args_config_map.insert(
+            wasm_macro_timeout.get_identifier().to_owned(),
+            wasm_macro_timeout.into(),
+        );
 
         let cmd_line_section_manifest = SectionManifest::new(
             CmdArgSetting::get_section_id().to_string(),
@@ -428,47 +550,15 @@ impl MinecraftInstance {
         ConfigurableManifest::new(false, false, setting_sections)
     }
 
-    pub async fn new(
-        config: SetupConfig,
-        dot_lodestone_config: DotLodestoneConfig,
-        path_to_instance: PathBuf,
+    /// Downloads the JRE `version` runs on unless it already is,
+    /// returning the path to its java binary and its major version
+    pub(super) async fn install_jre(
+        version: &str,
         progression_event_id: &ProgressionEventID,
-        event_broadcaster: EventBroadcaster,
-        macro_executor: MacroExecutor,
-    ) -> Result<MinecraftInstance, Error> {
-        let path_to_config = path_to_instance.join(".lodestone_minecraft_config.json");
-        let path_to_eula = path_to_instance.join("eula.txt");
-        let path_to_macros = path_to_instance.join("macros");
-        let path_to_resources = path_to_instance.join("resources");
-        let path_to_properties = path_to_instance.join("server.properties");
+        event_broadcaster: &EventBroadcaster,
+    ) -> Result<(PathBuf, u64), Error> {
         let path_to_runtimes = path_to_binaries().to_owned();
-
-        let uuid = dot_lodestone_config.uuid().to_owned();
-
-        // Step 1: Create Directories
-        event_broadcaster.send(Event::new_progression_event_update(
-            progression_event_id,
-            "1/4: Creating directories",
-            1.0,
-        ));
-        tokio::fs::create_dir_all(&path_to_instance)
-            .await
-            .and(tokio::fs::create_dir_all(&path_to_macros).await)
-            .and(tokio::fs::create_dir_all(&path_to_resources.join("mods")).await)
-            .and(tokio::fs::create_dir_all(&path_to_resources.join("worlds")).await)
-            .and(tokio::fs::create_dir_all(&path_to_resources.join("defaults")).await)
-            .and(tokio::fs::write(&path_to_eula, "#generated by Lodestone\neula=true").await)
-            .and(
-                tokio::fs::write(&path_to_properties, format!("server-port={}", config.port)).await,
-            )
-            .context("Could not create some files or directories for instance")
-            .map_err(|e| {
-                error!("{e}");
-                e
-            })?;
-
-        // Step 2: Download JRE
-        let (url, jre_major_version) = get_jre_url(config.version.as_str())
+        let (url, jre_major_version) = get_jre_url(version)
             .await
             .context("Could not get JRE URL")?;
         if !path_to_runtimes
@@ -535,6 +625,59 @@ impl MinecraftInstance {
                 4.0,
             ));
         }
+        let jre = path_to_runtimes
+            .join("java")
+            .join(format!("jre{}", jre_major_version))
+            .join(if std::env::consts::OS == "macos" {
+                "Contents/Home/bin"
+            } else {
+                "bin"
+            })
+            .join("java");
+        Ok((jre, jre_major_version))
+    }
+
+    pub async fn new(
+        config: SetupConfig,
+        dot_lodestone_config: DotLodestoneConfig,
+        path_to_instance: PathBuf,
+        progression_event_id: &ProgressionEventID,
+        event_broadcaster: EventBroadcaster,
+        macro_executor: MacroExecutor,
+    ) -> Result<MinecraftInstance, Error> {
+        let path_to_config = path_to_instance.join(".lodestone_minecraft_config.json");
+        let path_to_eula = path_to_instance.join("eula.txt");
+        let path_to_macros = path_to_instance.join("macros");
+        let path_to_resources = path_to_instance.join("resources");
+        let path_to_properties = path_to_instance.join("server.properties");
+
+        let uuid = dot_lodestone_config.uuid().to_owned();
+
+        // Step 1: Create Directories
+        event_broadcaster.send(Event::new_progression_event_update(
+            progression_event_id,
+            "1/4: Creating directories",
+            1.0,
+        ));
+        tokio::fs::create_dir_all(&path_to_instance)
+            .await
+            .and(tokio::fs::create_dir_all(&path_to_macros).await)
+            .and(tokio::fs::create_dir_all(&path_to_resources.join("mods")).await)
+            .and(tokio::fs::create_dir_all(&path_to_resources.join("worlds")).await)
+            .and(tokio::fs::create_dir_all(&path_to_resources.join("defaults")).await)
+            .and(tokio::fs::write(&path_to_eula, "#generated by Lodestone\neula=true").await)
+            .and(
+                tokio::fs::write(&path_to_properties, format!("server-port={}", config.port)).await,
+            )
+            .context("Could not create some files or directories for instance")
+            .map_err(|e| {
+                error!("{e}");
+                e
+            })?;
+
+        // Step 2: Download JRE
+        let (jre, jre_major_version) =
+            Self::install_jre(&config.version, progression_event_id, &event_broadcaster).await?;
 
         // Step 3: Download server.jar
         let flavour_name = config.flavour.to_string();
@@ -589,15 +732,6 @@ impl MinecraftInstance {
             true,
         )
         .await?;
-        let jre = path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", jre_major_version))
-            .join(if std::env::consts::OS == "macos" {
-                "Contents/Home/bin"
-            } else {
-                "bin"
-            })
-            .join("java");
         // Step 3 (part 2): Forge Setup
         if let Flavour::Forge { .. } = flavour.clone() {
             event_broadcaster.send(Event::new_progression_event_update(
@@ -656,6 +790,19 @@ impl MinecraftInstance {
             backup_period: config.backup_period,
             jre_major_version,
             has_started: false,
+            use_pty: false,
+            config_files: Vec::new(),
+            bedrock_port: None,
+            console_buffer_lines: DEFAULT_CONSOLE_BUFFER_LINES,
+            gc_logging: false,
+            sandbox: SandboxLevel::Off,
+            priority: ProcessPriority::Normal,
+            log_patterns: Vec::new(),
+            startup_timeout: 0,
+            on_startup_timeout: StartupTimeoutAction::Leave,
+            wasm_macro_memory: DEFAULT_WASM_MACRO_MEMORY,
+            wasm_macro_timeout: DEFAULT_WASM_MACRO_TIMEOUT,
+            disabled_global_macros: Vec::new(),
             java_cmd: Some(jre.to_string_lossy().to_string()),
         };
         // create config file
@@ -722,6 +869,9 @@ impl MinecraftInstance {
             java_path.to_string_lossy().to_string(),
         )));
 
+        let console_buffer = ConsoleBuffer::new(restore_config.console_buffer_lines);
+        console_buffer.collect_from(dot_lodestone_config.uuid().clone(), &event_broadcaster);
+
         let mut instance = MinecraftInstance {
             state: Arc::new(Mutex::new(State::Stopped)),
             uuid: dot_lodestone_config.uuid().clone(),
@@ -743,20 +893,129 @@ impl MinecraftInstance {
             event_broadcaster,
             path_to_runtimes,
             process: Arc::new(Mutex::new(None)),
+            adopted_pid: Arc::new(Mutex::new(None)),
+            process_tree: Arc::new(Mutex::new(None)),
             system: Arc::new(Mutex::new(sysinfo::System::new_all())),
             stdin: Arc::new(Mutex::new(None)),
             rcon_conn: Arc::new(Mutex::new(None)),
             configurable_manifest,
             macro_name_to_last_run: Arc::new(Mutex::new(HashMap::new())),
             pid_to_task_entry: Arc::new(Mutex::new(IndexMap::new())),
+            pregen: Arc::new(Mutex::new(None)),
+            console_buffer,
+            last_disk_sample: Arc::new(Mutex::new(None)),
+            server_status: Arc::new(Mutex::new(None)),
+            world_upgrade: Arc::new(Mutex::new(None)),
         };
         instance
             .read_properties()
             .await
             .context("Failed to read properties")?;
+        instance.try_adopt_process().await;
         Ok(instance)
     }
 
+    /// Of the process we started or adopted, if there is one
+    /// Kills what is left of the server's process tree once the server itself is gone
+    async fn reap_process_tree(&self) {
+        let tree = self.process_tree.lock().await.take();
+        if let Some(tree) = tree {
+            tree.kill_descendants(&mut self.system.lock().await);
+        }
+    }
+
+    async fn server_pid(&self) -> Option<u32> {
+        match self.process.lock().await.as_ref().and_then(|p| p.id()) {
+            Some(pid) => Some(pid),
+            None => *self.adopted_pid.lock().await,
+        }
+    }
+
+    /// The java the server is started with
+    fn java_binary(&self, config: &RestoreConfig) -> PathBuf {
+        if let Some(jre) = &config.java_cmd {
+            PathBuf::from(jre)
+        } else {
+            self.path_to_runtimes
+                .join("java")
+                .join(format!("jre{}", config.jre_major_version))
+                .join(if std::env::consts::OS == "macos" {
+                    "Contents/Home/bin"
+                } else {
+                    "bin"
+                })
+                .join("java")
+        }
+    }
+
+    /// What the server may access when sandboxed, the ports are read from the manifest so
+    /// changes made while the server was stopped are picked up
+    async fn sandbox_policy(&self, config: &RestoreConfig, jre: &Path) -> SandboxPolicy {
+        let mut runtime_dirs = vec![self.path_to_runtimes.clone()];
+        // a custom java is usually <runtime>/bin/java
+        if let Some(runtime) = jre.ancestors().nth(2).filter(|p| p.is_absolute()) {
+            runtime_dirs.push(runtime.to_owned());
+        }
+        let mut ports = vec![config.port as u16];
+        let lock = self.configurable_manifest.lock().await;
+        let rcon_enabled = lock
+            .get_unique_setting_key("enable-rcon")
+            .and_then(|v| v.get_value().map(|v| v.try_as_boolean().ok()))
+            .flatten()
+            .unwrap_or(false);
+        let rcon_port = lock
+            .get_unique_setting_key("rcon.port")
+            .and_then(|v| v.get_value().map(|v| v.try_as_unsigned_integer().ok()))
+            .flatten();
+        if let (true, Some(rcon_port)) = (rcon_enabled, rcon_port) {
+            ports.push(rcon_port as u16);
+        }
+        SandboxPolicy {
+            level: config.sandbox,
+            instance_dir: self.path_to_instance.clone(),
+            runtime_dirs,
+            ports,
+        }
+    }
+
+    fn line_parser(&self, config: &RestoreConfig) -> LineParser {
+        LineParser::new(&config.flavour.clone().into(), &config.log_patterns)
+    }
+
+    pub async fn log_patterns(&self) -> Vec<LogPattern> {
+        self.config.lock().await.log_patterns.clone()
+    }
+
+    /// Takes effect the next time the server is started
+    pub async fn set_log_patterns(&self, log_patterns: Vec<LogPattern>) -> Result<(), Error> {
+        log_parser::validate(&log_patterns)?;
+        self.config.lock().await.log_patterns = log_patterns;
+        self.write_config_to_file().await
+    }
+
+    /// `None` if the server may take as long as it needs to start, which upgrade runs always may
+    pub async fn startup_timeout(&self) -> Option<(Duration, StartupTimeoutAction)> {
+        if self.world_upgrade.lock().await.is_some() {
+            return None;
+        }
+        let config = self.config.lock().await;
+        (config.startup_timeout > 0).then(|| {
+            (
+                Duration::from_secs(config.startup_timeout as u64 * 60),
+                config.on_startup_timeout,
+            )
+        })
+    }
+
+    pub async fn wasm_macro_limits(&self) -> WasmLimits {
+        let config = self.config.lock().await;
+        WasmLimits {
+            max_memory_bytes: config.wasm_macro_memory as usize * 1024 * 1024,
+            timeout: (config.wasm_macro_timeout > 0)
+                .then(|| Duration::from_secs(config.wasm_macro_timeout as u64 * 60)),
+        }
+    }
+
     async fn write_config_to_file(&self) -> Result<(), Error> {
         tokio::fs::write(
             &self.path_to_config,
@@ -805,25 +1064,10 @@ impl MinecraftInstance {
                 &self.path_to_properties.display()
             ))?;
         let mut setting_str = "".to_string();
-        for (key, value) in self
-            .configurable_manifest
-            .lock()
-            .await
-            .get_section(ServerPropertySetting::get_section_id())
-            .unwrap()
-            .all_settings()
-            .iter()
+        for line in configurable::properties_lines(&*self.configurable_manifest.lock().await, false)
         {
-            // print the key and value separated by a =
-            // println!("{}={}", key, value);
-            setting_str.push_str(&format!(
-                "{}={}\n",
-                key,
-                value
-                    .get_value()
-                    .expect("Programming error, value is not set")
-                    .to_string()
-            ));
+            setting_str.push_str(&line);
+            setting_str.push('\n');
         }
         file.write_all(setting_str.as_bytes())
             .await
@@ -890,6 +1134,218 @@ impl MinecraftInstance {
                 .expect("Programming error, value is not a string")
                 .to_owned(),
         );
+
+        config_lock.use_pty = configurable_map
+            .get(CmdArgSetting::UsePty(Default::default()).get_identifier())
+            .expect("Programming error, value is not set")
+            .get_value()
+            .expect("Programming error, value is not set")
+            .try_as_boolean()
+            .expect("Programming error, value is not a boolean");
+
+        config_lock.gc_logging = configurable_map
+            .get(CmdArgSetting::GcLogging(Default::default()).get_identifier())
+            .expect("Programming error, value is not set")
+            .get_value()
+            .expect("Programming error, value is not set")
+            .try_as_boolean()
+            .expect("Programming error, value is not a boolean");
+
+        config_lock.sandbox = configurable_map
+            .get(CmdArgSetting::Sandbox(Default::default()).get_identifier())
+            .expect("Programming error, value is not set")
+            .get_value()
+            .expect("Programming error, value is not set")
+            .try_as_enum()
+            .expect("Programming error, value is not an enum")
+            .parse()
+            .expect("Programming error, value is not a sandbox level");
+
+        config_lock.priority = configurable_map
+            .get(CmdArgSetting::Priority(Default::default()).get_identifier())
+            .expect("Programming error, value is not set")
+            .get_value()
+            .expect("Programming error, value is not set")
+            .try_as_enum()
+            .expect("Programming error, value is not an enum")
+            .parse()
+            .expect("Programming error, value is not a priority");
+
+        config_lock.startup_timeout = configurable_map
+            .get(CmdArgSetting::StartupTimeout(Default::default()).get_identifier())
+            .expect("Programming error, value is not set")
+            .get_value()
+            .expect("Programming error, value is not set")
+            .clone()
+            .try_as_unsigned_integer()
+            .expect("Programming error, value is not an unsigned integer");
+
+        config_lock.on_startup_timeout = configurable_map
+            .get(CmdArgSetting::OnStartupTimeout(Default::default()).get_identifier())
+            .expect("Programming error, value is not set")
+            .get_value()
+            .expect("Programming error, value is not set")
+            .try_as_enum()
+            .expect("Programming error, value is not an enum")
+            .parse()
+            .expect("Programming error, value is not a startup timeout action");
+
+        config_lock.wasm_macro_memory = configurable_map
+            .get(CmdArgSetting::WasmMacroMemory(Default::default()).get_identifier())
+            .expect("Programming error, value is not set")
+            .get_value()
+            .expect("Programming error, value is not set")
+            .clone()
+            .try_as_unsigned_integer()
+            .expect("Programming error, value is not an unsigned integer");
+
+        config_lock.wasm_macro_timeout = configurable_map
+            .get(CmdArgSetting::WasmMacroTimeout(Default::default()).get_identifier())
+            .expect("Programming error, value is not set")
+            .get_value()
+            .expect("Programming error, value is not set")
+            .clone()
+            .try_as_unsigned_integer()
+            .expect("Programming error, value is not an unsigned integer");
+    }
+
+    /// Waits for the server to print a line matching `predicate`
+    async fn wait_for_output(
+        &self,
+        events: &mut tokio::sync::broadcast::Receiver<Event>,
+        timeout: Duration,
+        predicate: impl Fn(&str) -> bool,
+    ) -> bool {
+        tokio::time::timeout(timeout, async {
+            while let Ok(event) = events.recv().await {
+                if let EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid,
+                    instance_event_inner: InstanceEventInner::InstanceOutput { message, .. },
+                    ..
+                }) = event.event_inner
+                {
+                    if instance_uuid == self.uuid && predicate(&message) {
+                        return true;
+                    }
+                }
+            }
+            false
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    /// Directory of the world the server loads, from `level-name` in `server.properties`
+    pub async fn world_path(&self) -> Result<PathBuf, Error> {
+        let level_name = read_properties_from_path(&self.path_to_properties)
+            .await
+            .ok()
+            .and_then(|properties| properties.get("level-name").cloned())
+            .unwrap_or_else(|| "world".to_string());
+        scoped_join_win_safe(&self.path_to_instance, level_name)
+    }
+
+    /// Ticks per second over the last minute, for Paper and Spigot servers.
+    ///
+    /// This goes through the `tps` command, so the answer shows up in the console
+    pub async fn query_tps(&self) -> Option<f64> {
+        if !matches!(
+            self.config.lock().await.flavour,
+            Flavour::Paper { .. } | Flavour::Spigot
+        ) || self.state().await != State::Running
+        {
+            return None;
+        }
+        let mut events = self.event_broadcaster.subscribe();
+        self.send_command("tps", CausedBy::System).await.ok()?;
+        let tps = std::sync::Mutex::new(None);
+        self.wait_for_output(
+            &mut events,
+            TPS_TIMEOUT,
+            |message| match line_parser::parse_tps(message) {
+                Some(value) => {
+                    *tps.lock().unwrap() = Some(value);
+                    true
+                }
+                None => false,
+            },
+        )
+        .await;
+        tps.into_inner().unwrap()
+    }
+
+    /// Flushes the world to disk and stops the server from writing to it,
+    /// so its files can be copied consistently while it keeps running.
+    ///
+    /// Fails if the server does not acknowledge either step in time, in which case
+    /// automatic saving is turned back on. Otherwise it must be followed by [`Self::resume_saves`]
+    pub async fn suspend_saves(&self) -> Result<(), Error> {
+        let mut events = self.event_broadcaster.subscribe();
+        self.send_command("save-off", CausedBy::System).await?;
+        let result = if !self
+            .wait_for_output(&mut events, SAVE_ACK_TIMEOUT, |message| {
+                message.contains("Automatic saving is now disabled")
+                    || message.contains("Saving is already turned off")
+            })
+            .await
+        {
+            Err(eyre!(
+                "Server did not acknowledge save-off within {} seconds",
+                SAVE_ACK_TIMEOUT.as_secs()
+            ))
+        } else if let Err(e) = self.send_command("save-all flush", CausedBy::System).await {
+            Err(e.source)
+        } else if !self
+            .wait_for_output(&mut events, SAVE_FLUSH_TIMEOUT, |message| {
+                message.contains("Saved the game") || message.contains("Saved the world")
+            })
+            .await
+        {
+            // the flush may still be running, a copy made now could be inconsistent
+            Err(eyre!(
+                "Server did not finish saving the world within {} seconds",
+                SAVE_FLUSH_TIMEOUT.as_secs()
+            ))
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            self.event_broadcaster.send(Event::new_system_message(
+                self.uuid.clone(),
+                self.name().await,
+                format!("Failed to pause world saving for a backup: {e}"),
+            ));
+            if let Err(e) = self.send_command("save-on", CausedBy::System).await {
+                error!("Failed to turn automatic saving back on: {e}");
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    pub async fn resume_saves(&self) -> Result<(), Error> {
+        let mut events = self.event_broadcaster.subscribe();
+        self.send_command("save-on", CausedBy::System).await?;
+        if !self
+            .wait_for_output(&mut events, SAVE_ACK_TIMEOUT, |message| {
+                message.contains("Automatic saving is now enabled")
+                    || message.contains("Saving is already turned on")
+            })
+            .await
+        {
+            self.event_broadcaster.send(Event::new_system_message(
+                self.uuid.clone(),
+                self.name().await,
+                "Server did not confirm that automatic saving is back on, run save-on manually"
+                    .to_string(),
+            ));
+            return Err(eyre!(
+                "Server did not acknowledge save-on within {} seconds",
+                SAVE_ACK_TIMEOUT.as_secs()
+            )
+            .into());
+        }
+        Ok(())
     }
 
     pub async fn send_rcon(&self, cmd: &str) -> Result<String, Error> {
@@ -907,6 +1363,54 @@ impl MinecraftInstance {
             .context("Failed to send rcon command")?;
         Ok(a)
     }
+
+    /// Connects to the server's RCON if it is enabled in server.properties
+    async fn connect_rcon(&self) {
+        if let (Some(true), Some(rcon_psw), Some(rcon_port)) = {
+            let lock = self.configurable_manifest.lock().await;
+
+            let a = lock
+                .get_unique_setting_key("enable-rcon")
+                .and_then(|v| v.get_value().map(|v| v.try_as_boolean().ok()))
+                .flatten();
+
+            let b = lock
+                .get_unique_setting_key("rcon.password")
+                .and_then(|v| v.get_value().map(|v| v.try_as_string().ok()))
+                .flatten()
+                .cloned();
+
+            let c = lock
+                .get_unique_setting_key("rcon.port")
+                .and_then(|v| v.get_value().map(|v| v.try_as_unsigned_integer().ok()))
+                .flatten();
+            (a, b, c)
+        } {
+            let max_retry = 3;
+            for i in 0..max_retry {
+                let rcon = <rcon::Connection<tokio::net::TcpStream>>::builder()
+                    .enable_minecraft_quirks(true)
+                    .connect(&format!("localhost:{}", rcon_port), &rcon_psw)
+                    .await
+                    .map_err(|e| {
+                        warn!(
+                            "Failed to connect to RCON: {}, retry {}/{}",
+                            e, i, max_retry
+                        );
+                        e
+                    });
+                if let Ok(rcon) = rcon {
+                    info!("Connected to RCON");
+                    self.rcon_conn.lock().await.replace(rcon);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_secs(2_u64.pow(i))).await;
+            }
+        } else {
+            warn!("RCON is not enabled or misconfigured, skipping");
+            self.rcon_conn.lock().await.take();
+        }
+    }
 }
 
 impl TInstance for MinecraftInstance {}
@@ -0,0 +1,441 @@
+//! Reading and writing NBT files like `level.dat` and `playerdata/<uuid>.dat`.
+//!
+//! Tags keep their type in JSON (`{"type": "Int", "value": 64}`) so an edited file is written
+//! back with the exact types Minecraft expects, and compounds keep their order.
+
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use flate2::{
+    read::{GzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+/// Same limit as Minecraft, deeper files are refused instead of overflowing the stack
+const MAX_DEPTH: usize = 512;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[serde(tag = "type", content = "value")]
+#[ts(export)]
+pub enum NbtTag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    /// Every element must have the same type
+    List(Vec<NbtTag>),
+    Compound(IndexMap<String, NbtTag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum NbtCompression {
+    Gzip,
+    Zlib,
+    None,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct NbtFile {
+    /// Kept as found, to write the file back the same way
+    pub compression: NbtCompression,
+    /// Name of the root tag, usually empty
+    pub name: String,
+    /// Always a compound
+    pub root: NbtTag,
+}
+
+fn invalid(message: impl std::fmt::Display) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid NBT: {}", message),
+    }
+}
+
+impl NbtTag {
+    fn type_id(&self) -> u8 {
+        match self {
+            NbtTag::Byte(_) => TAG_BYTE,
+            NbtTag::Short(_) => TAG_SHORT,
+            NbtTag::Int(_) => TAG_INT,
+            NbtTag::Long(_) => TAG_LONG,
+            NbtTag::Float(_) => TAG_FLOAT,
+            NbtTag::Double(_) => TAG_DOUBLE,
+            NbtTag::ByteArray(_) => TAG_BYTE_ARRAY,
+            NbtTag::String(_) => TAG_STRING,
+            NbtTag::List(_) => TAG_LIST,
+            NbtTag::Compound(_) => TAG_COMPOUND,
+            NbtTag::IntArray(_) => TAG_INT_ARRAY,
+            NbtTag::LongArray(_) => TAG_LONG_ARRAY,
+        }
+    }
+
+    /// Checks what the JSON can express but NBT can't, like lists of mixed types
+    pub fn validate(&self) -> Result<(), Error> {
+        self.validate_at(0)
+    }
+
+    fn validate_at(&self, depth: usize) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("nested too deeply"));
+        }
+        match self {
+            NbtTag::String(s) => {
+                if cesu8::to_java_cesu8(s).len() > u16::MAX as usize {
+                    return Err(invalid("string too long"));
+                }
+            }
+            NbtTag::List(list) => {
+                if let Some(first) = list.first() {
+                    if list.iter().any(|tag| tag.type_id() != first.type_id()) {
+                        return Err(invalid("list elements must all have the same type"));
+                    }
+                }
+                for tag in list {
+                    tag.validate_at(depth + 1)?;
+                }
+            }
+            NbtTag::Compound(compound) => {
+                for (name, tag) in compound {
+                    if cesu8::to_java_cesu8(name).len() > u16::MAX as usize {
+                        return Err(invalid("name too long"));
+                    }
+                    tag.validate_at(depth + 1)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(invalid("unexpected end of file"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Length of an array or list, checked against what is left to read
+    fn length(&mut self, element_size: usize) -> Result<usize, Error> {
+        let len = i32::from_be_bytes(self.array()?).max(0) as usize;
+        if len.saturating_mul(element_size) > self.bytes.len() {
+            return Err(invalid("length goes past the end of the file"));
+        }
+        Ok(len)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = u16::from_be_bytes(self.array()?) as usize;
+        let bytes = self.take(len)?;
+        Ok(cesu8::from_java_cesu8(bytes)
+            .map_err(|_| invalid("string is not valid modified UTF-8"))?
+            .into_owned())
+    }
+
+    fn payload(&mut self, type_id: u8, depth: usize) -> Result<NbtTag, Error> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("nested too deeply"));
+        }
+        Ok(match type_id {
+            TAG_BYTE => NbtTag::Byte(self.u8()? as i8),
+            TAG_SHORT => NbtTag::Short(i16::from_be_bytes(self.array()?)),
+            TAG_INT => NbtTag::Int(i32::from_be_bytes(self.array()?)),
+            TAG_LONG => NbtTag::Long(i64::from_be_bytes(self.array()?)),
+            TAG_FLOAT => NbtTag::Float(f32::from_be_bytes(self.array()?)),
+            TAG_DOUBLE => NbtTag::Double(f64::from_be_bytes(self.array()?)),
+            TAG_BYTE_ARRAY => {
+                let len = self.length(1)?;
+                NbtTag::ByteArray(self.take(len)?.iter().map(|&b| b as i8).collect())
+            }
+            TAG_STRING => NbtTag::String(self.string()?),
+            TAG_LIST => {
+                let element_type = self.u8()?;
+                // every element takes at least a byte, except for lists of `TAG_End`
+                let len = self.length(if element_type == TAG_END { 0 } else { 1 })?;
+                if element_type == TAG_END {
+                    return Ok(NbtTag::List(Vec::new()));
+                }
+                let mut list = Vec::with_capacity(len);
+                for _ in 0..len {
+                    list.push(self.payload(element_type, depth + 1)?);
+                }
+                NbtTag::List(list)
+            }
+            TAG_COMPOUND => {
+                let mut compound = IndexMap::new();
+                loop {
+                    let type_id = self.u8()?;
+                    if type_id == TAG_END {
+                        break;
+                    }
+                    let name = self.string()?;
+                    let tag = self.payload(type_id, depth + 1)?;
+                    compound.insert(name, tag);
+                }
+                NbtTag::Compound(compound)
+            }
+            TAG_INT_ARRAY => {
+                let len = self.length(4)?;
+                let mut array = Vec::with_capacity(len);
+                for _ in 0..len {
+                    array.push(i32::from_be_bytes(self.array()?));
+                }
+                NbtTag::IntArray(array)
+            }
+            TAG_LONG_ARRAY => {
+                let len = self.length(8)?;
+                let mut array = Vec::with_capacity(len);
+                for _ in 0..len {
+                    array.push(i64::from_be_bytes(self.array()?));
+                }
+                NbtTag::LongArray(array)
+            }
+            other => return Err(invalid(format!("unknown tag type {other}"))),
+        })
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    let bytes = cesu8::to_java_cesu8(s);
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(&bytes);
+}
+
+fn write_payload(out: &mut Vec<u8>, tag: &NbtTag) {
+    match tag {
+        NbtTag::Byte(v) => out.push(*v as u8),
+        NbtTag::Short(v) => out.extend_from_slice(&v.to_be_bytes()),
+        NbtTag::Int(v) => out.extend_from_slice(&v.to_be_bytes()),
+        NbtTag::Long(v) => out.extend_from_slice(&v.to_be_bytes()),
+        NbtTag::Float(v) => out.extend_from_slice(&v.to_be_bytes()),
+        NbtTag::Double(v) => out.extend_from_slice(&v.to_be_bytes()),
+        NbtTag::ByteArray(array) => {
+            out.extend_from_slice(&(array.len() as i32).to_be_bytes());
+            out.extend(array.iter().map(|&b| b as u8));
+        }
+        NbtTag::String(s) => write_string(out, s),
+        NbtTag::List(list) => {
+            out.push(list.first().map_or(TAG_END, NbtTag::type_id));
+            out.extend_from_slice(&(list.len() as i32).to_be_bytes());
+            for tag in list {
+                write_payload(out, tag);
+            }
+        }
+        NbtTag::Compound(compound) => {
+            for (name, tag) in compound {
+                out.push(tag.type_id());
+                write_string(out, name);
+                write_payload(out, tag);
+            }
+            out.push(TAG_END);
+        }
+        NbtTag::IntArray(array) => {
+            out.extend_from_slice(&(array.len() as i32).to_be_bytes());
+            for v in array {
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        NbtTag::LongArray(array) => {
+            out.extend_from_slice(&(array.len() as i32).to_be_bytes());
+            for v in array {
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+    }
+}
+
+impl NbtFile {
+    /// Parses the content of an NBT file, compressed or not
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let (compression, decompressed) = match bytes {
+            [0x1f, 0x8b, ..] => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(bytes)
+                    .read_to_end(&mut decompressed)
+                    .map_err(|e| invalid(format!("failed to decompress: {e}")))?;
+                (NbtCompression::Gzip, decompressed)
+            }
+            [0x78, ..] => {
+                let mut decompressed = Vec::new();
+                ZlibDecoder::new(bytes)
+                    .read_to_end(&mut decompressed)
+                    .map_err(|e| invalid(format!("failed to decompress: {e}")))?;
+                (NbtCompression::Zlib, decompressed)
+            }
+            _ => (NbtCompression::None, bytes.to_vec()),
+        };
+        let mut reader = Reader {
+            bytes: &decompressed,
+        };
+        if reader.u8()? != TAG_COMPOUND {
+            return Err(invalid("root tag is not a compound"));
+        }
+        let name = reader.string()?;
+        let root = reader.payload(TAG_COMPOUND, 0)?;
+        Ok(Self {
+            compression,
+            name,
+            root,
+        })
+    }
+
+    /// The content of the file, after checking the tags can be written as NBT
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        if !matches!(self.root, NbtTag::Compound(_)) {
+            return Err(invalid("root tag must be a compound"));
+        }
+        self.root.validate()?;
+        let mut out = vec![TAG_COMPOUND];
+        write_string(&mut out, &self.name);
+        write_payload(&mut out, &self.root);
+        Ok(match self.compression {
+            NbtCompression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(&out)
+                    .and_then(|_| encoder.finish())
+                    .context("Failed to compress NBT")?
+            }
+            NbtCompression::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(&out)
+                    .and_then(|_| encoder.finish())
+                    .context("Failed to compress NBT")?
+            }
+            NbtCompression::None => out,
+        })
+    }
+}
+
+/// Replaces the file at `path` with `bytes`, keeping the original next to it as
+/// `<file>.<timestamp>.bak`. Returns the name of the backup
+pub async fn write_with_backup(path: &Path, bytes: &[u8]) -> Result<String, Error> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| eyre!("{} has no file name", path.display()))?
+        .to_string_lossy()
+        .to_string();
+    let backup_name = format!("{}.{}.bak", file_name, chrono::Utc::now().timestamp());
+    tokio::fs::copy(path, path.with_file_name(&backup_name))
+        .await
+        .context(format!("Failed to back up {}", path.display()))?;
+    crate::util::fs::write_all(path, bytes).await?;
+    Ok(backup_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level_dat() -> NbtFile {
+        let mut data = IndexMap::new();
+        data.insert("SpawnX".to_string(), NbtTag::Int(-120));
+        data.insert("SpawnY".to_string(), NbtTag::Int(64));
+        data.insert("RandomSeed".to_string(), NbtTag::Long(-4172144997902289642));
+        data.insert("LevelName".to_string(), NbtTag::String("Wörld".to_string()));
+        data.insert(
+            "Pos".to_string(),
+            NbtTag::List(vec![NbtTag::Double(0.5), NbtTag::Double(-3.25)]),
+        );
+        data.insert("Empty".to_string(), NbtTag::List(Vec::new()));
+        data.insert("Biomes".to_string(), NbtTag::IntArray(vec![1, 2, 3]));
+        data.insert("Flags".to_string(), NbtTag::ByteArray(vec![-1, 0, 1]));
+        let mut root = IndexMap::new();
+        root.insert("Data".to_string(), NbtTag::Compound(data));
+        NbtFile {
+            compression: NbtCompression::Gzip,
+            name: String::new(),
+            root: NbtTag::Compound(root),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for compression in [
+            NbtCompression::Gzip,
+            NbtCompression::Zlib,
+            NbtCompression::None,
+        ] {
+            let file = NbtFile {
+                compression,
+                ..level_dat()
+            };
+            assert_eq!(NbtFile::parse(&file.to_bytes().unwrap()).unwrap(), file);
+        }
+    }
+
+    #[test]
+    fn test_json_keeps_types() {
+        let file = level_dat();
+        let json = serde_json::to_string(&file).unwrap();
+        assert!(json.contains(r#""SpawnY":{"type":"Int","value":64}"#));
+        let parsed: NbtFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, file);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut file = level_dat();
+        file.root = NbtTag::Compound(IndexMap::from([(
+            "Mixed".to_string(),
+            NbtTag::List(vec![NbtTag::Int(1), NbtTag::Short(2)]),
+        )]));
+        assert!(file.to_bytes().is_err());
+
+        let bytes = level_dat().to_bytes().unwrap();
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&bytes[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(NbtFile::parse(&decompressed[..decompressed.len() - 5]).is_err());
+        // a list claiming more elements than the file could hold
+        assert!(NbtFile::parse(&[10, 0, 0, 9, 0, 1, b'L', 3, 0x7f, 0xff, 0xff, 0xff]).is_err());
+    }
+}
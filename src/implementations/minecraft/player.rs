@@ -1,7 +1,6 @@
-use async_trait::async_trait;
+use std::collections::HashSet;
 
-use serde::{Deserialize, Serialize};
-use ts_rs::TS;
+use async_trait::async_trait;
 
 use crate::traits::t_player::Player;
 use crate::traits::t_player::{TPlayer, TPlayerManagement};
@@ -10,36 +9,7 @@ use crate::Error;
 use super::configurable::ServerPropertySetting;
 use super::MinecraftInstance;
 
-#[derive(Eq, Debug, Clone, Serialize, Deserialize, TS)]
-#[ts(export)]
-pub struct MinecraftPlayer {
-    pub name: String,
-    pub uuid: Option<String>,
-}
-
-impl MinecraftPlayer {
-    pub fn new(name: String, uuid: Option<String>) -> Self {
-        Self { name, uuid }
-    }
-}
-
-impl PartialEq for MinecraftPlayer {
-    fn eq(&self, other: &Self) -> bool {
-        // if uuid is not set, compare by name
-        if self.uuid.is_none() || other.uuid.is_none() {
-            self.name == other.name
-        } else {
-            self.uuid == other.uuid
-        }
-    }
-}
-use std::collections::HashSet;
-use std::hash::{Hash, Hasher};
-impl Hash for MinecraftPlayer {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.uuid.hash(state);
-    }
-}
+pub use lodestone_types::MinecraftPlayer;
 
 impl TPlayer for MinecraftPlayer {
     fn get_id(&self) -> String {
@@ -0,0 +1,390 @@
+//! Player data saved by the server in `<world>/playerdata/<uuid>.dat`, decoded from NBT into
+//! what the dashboard shows, with a few edits that can't leave the file in a broken state.
+
+use std::path::PathBuf;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_server::{State, TServer};
+use crate::types::Snowflake;
+
+use super::nbt::{self, NbtFile, NbtTag};
+use super::MinecraftInstance;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct InventoryItem {
+    pub slot: i8,
+    /// Like `minecraft:diamond_sword`
+    pub id: String,
+    pub count: i32,
+    /// Enchantments, names and the like, as stored by the game version that saved it
+    pub extra: Option<NbtTag>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct PlayerData {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    /// Like `minecraft:overworld`
+    pub dimension: Option<String>,
+    pub health: f32,
+    pub food_level: i32,
+    pub xp_level: i32,
+    pub xp_total: i32,
+    /// Towards the next level, from 0 to 1
+    pub xp_progress: f32,
+    pub game_mode: i32,
+    pub inventory: Vec<InventoryItem>,
+    pub ender_items: Vec<InventoryItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum PlayerDataEdit {
+    Teleport {
+        x: f64,
+        y: f64,
+        z: f64,
+        /// Stays in the same dimension if left out
+        dimension: Option<String>,
+    },
+    ClearItem {
+        slot: i8,
+    },
+}
+
+fn invalid(message: impl std::fmt::Display) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Unexpected player data: {}", message),
+    }
+}
+
+/// Dimensions were numbers before 1.16
+const LEGACY_DIMENSIONS: [(i32, &str); 3] = [
+    (0, "minecraft:overworld"),
+    (-1, "minecraft:the_nether"),
+    (1, "minecraft:the_end"),
+];
+
+fn compound(tag: &NbtTag) -> Result<&indexmap::IndexMap<String, NbtTag>, Error> {
+    match tag {
+        NbtTag::Compound(compound) => Ok(compound),
+        _ => Err(invalid("root is not a compound")),
+    }
+}
+
+fn items(root: &indexmap::IndexMap<String, NbtTag>, key: &str) -> Vec<InventoryItem> {
+    let list = match root.get(key) {
+        Some(NbtTag::List(list)) => list,
+        _ => return Vec::new(),
+    };
+    list.iter()
+        .filter_map(|item| {
+            let item = match item {
+                NbtTag::Compound(item) => item,
+                _ => return None,
+            };
+            let slot = match item.get("Slot") {
+                Some(NbtTag::Byte(slot)) => *slot,
+                _ => return None,
+            };
+            let id = match item.get("id") {
+                Some(NbtTag::String(id)) => id.clone(),
+                _ => return None,
+            };
+            // `Count` was a byte until 1.20.5 renamed it
+            let count = match item.get("count").or_else(|| item.get("Count")) {
+                Some(NbtTag::Byte(count)) => *count as i32,
+                Some(NbtTag::Int(count)) => *count,
+                _ => 1,
+            };
+            Some(InventoryItem {
+                slot,
+                id,
+                count,
+                extra: item.get("components").or_else(|| item.get("tag")).cloned(),
+            })
+        })
+        .collect()
+}
+
+impl PlayerData {
+    pub fn from_nbt(root: &NbtTag) -> Result<Self, Error> {
+        let root = compound(root)?;
+        let (x, y, z) = match root.get("Pos") {
+            Some(NbtTag::List(pos)) => match pos.as_slice() {
+                [NbtTag::Double(x), NbtTag::Double(y), NbtTag::Double(z)] => (*x, *y, *z),
+                _ => return Err(invalid("Pos is not 3 doubles")),
+            },
+            _ => return Err(invalid("no Pos")),
+        };
+        let dimension = match root.get("Dimension") {
+            Some(NbtTag::String(dimension)) => Some(dimension.clone()),
+            Some(NbtTag::Int(legacy)) => LEGACY_DIMENSIONS
+                .iter()
+                .find(|(id, _)| id == legacy)
+                .map(|(_, name)| name.to_string()),
+            _ => None,
+        };
+        let int = |key: &str| match root.get(key) {
+            Some(NbtTag::Int(v)) => *v,
+            _ => 0,
+        };
+        let float = |key: &str| match root.get(key) {
+            Some(NbtTag::Float(v)) => *v,
+            _ => 0.0,
+        };
+        Ok(Self {
+            x,
+            y,
+            z,
+            dimension,
+            health: float("Health"),
+            food_level: int("foodLevel"),
+            xp_level: int("XpLevel"),
+            xp_total: int("XpTotal"),
+            xp_progress: float("XpP"),
+            game_mode: int("playerGameType"),
+            inventory: items(root, "Inventory"),
+            ender_items: items(root, "EnderItems"),
+        })
+    }
+}
+
+impl PlayerDataEdit {
+    pub fn apply(&self, root: &mut NbtTag) -> Result<(), Error> {
+        let root = match root {
+            NbtTag::Compound(compound) => compound,
+            _ => return Err(invalid("root is not a compound")),
+        };
+        match self {
+            PlayerDataEdit::Teleport { x, y, z, dimension } => {
+                if !(x.is_finite() && y.is_finite() && z.is_finite()) {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Coordinates must be finite"),
+                    });
+                }
+                if let Some(dimension) = dimension {
+                    let tag = match root.get("Dimension") {
+                        Some(NbtTag::Int(_)) => LEGACY_DIMENSIONS
+                            .iter()
+                            .find(|(_, name)| name == dimension)
+                            .map(|(id, _)| NbtTag::Int(*id))
+                            .ok_or_else(|| Error {
+                                kind: ErrorKind::BadRequest,
+                                source: eyre!("{} doesn't exist in this game version", dimension),
+                            })?,
+                        _ => NbtTag::String(dimension.clone()),
+                    };
+                    root.insert("Dimension".to_string(), tag);
+                }
+                root.insert(
+                    "Pos".to_string(),
+                    NbtTag::List(vec![
+                        NbtTag::Double(*x),
+                        NbtTag::Double(*y),
+                        NbtTag::Double(*z),
+                    ]),
+                );
+                // so the player doesn't arrive with the speed they logged out with
+                root.insert(
+                    "Motion".to_string(),
+                    NbtTag::List(vec![NbtTag::Double(0.0); 3]),
+                );
+                root.insert("FallDistance".to_string(), NbtTag::Float(0.0));
+            }
+            PlayerDataEdit::ClearItem { slot } => {
+                let inventory = match root.get_mut("Inventory") {
+                    Some(NbtTag::List(inventory)) => inventory,
+                    _ => return Err(invalid("no Inventory")),
+                };
+                let before = inventory.len();
+                inventory.retain(|item| {
+                    !matches!(item, NbtTag::Compound(item)
+                        if item.get("Slot") == Some(&NbtTag::Byte(*slot)))
+                });
+                if inventory.len() == before {
+                    return Err(Error {
+                        kind: ErrorKind::NotFound,
+                        source: eyre!("No item in slot {}", slot),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MinecraftInstance {
+    async fn player_data_path(&self, player_uuid: &str) -> Result<PathBuf, Error> {
+        let player_uuid = uuid::Uuid::parse_str(player_uuid).map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid player uuid {}", player_uuid),
+        })?;
+        let path = self
+            .world_path()
+            .await?
+            .join("playerdata")
+            .join(format!("{}.dat", player_uuid.hyphenated()));
+        if !path.is_file() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("No saved data for player {}", player_uuid),
+            });
+        }
+        Ok(path)
+    }
+
+    async fn read_player_nbt(&self, path: PathBuf) -> Result<NbtFile, Error> {
+        let bytes = tokio::fs::read(&path)
+            .await
+            .context(format!("Failed to read {}", path.display()))?;
+        tokio::task::spawn_blocking(move || NbtFile::parse(&bytes))
+            .await
+            .context("Parse task panicked")?
+    }
+
+    pub async fn player_data(&self, player_uuid: &str) -> Result<PlayerData, Error> {
+        let path = self.player_data_path(player_uuid).await?;
+        PlayerData::from_nbt(&self.read_player_nbt(path).await?.root)
+    }
+
+    /// Edits the saved data of a player, which the server only reads when it starts or
+    /// the player joins, so it has to be stopped
+    pub async fn edit_player_data(
+        &self,
+        player_uuid: &str,
+        edit: PlayerDataEdit,
+        caused_by: CausedBy,
+    ) -> Result<PlayerData, Error> {
+        if self.state().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Instance must be stopped before editing player data"),
+            });
+        }
+        let path = self.player_data_path(player_uuid).await?;
+        let mut file = self.read_player_nbt(path.clone()).await?;
+        edit.apply(&mut file.root)?;
+        let ret = PlayerData::from_nbt(&file.root)?;
+        let bytes = tokio::task::spawn_blocking(move || file.to_bytes())
+            .await
+            .context("Serialize task panicked")??;
+        nbt::write_with_backup(&path, &bytes).await?;
+
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: self.uuid.clone(),
+                instance_name: self.name().await,
+                instance_event_inner: InstanceEventInner::PlayerDataChanged {
+                    player_uuid: player_uuid.to_string(),
+                    edit,
+                },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::*;
+
+    fn item(slot: i8, id: &str, count: i8) -> NbtTag {
+        NbtTag::Compound(IndexMap::from([
+            ("Slot".to_string(), NbtTag::Byte(slot)),
+            ("id".to_string(), NbtTag::String(id.to_string())),
+            ("Count".to_string(), NbtTag::Byte(count)),
+        ]))
+    }
+
+    fn player() -> NbtTag {
+        NbtTag::Compound(IndexMap::from([
+            (
+                "Pos".to_string(),
+                NbtTag::List(vec![
+                    NbtTag::Double(10.5),
+                    NbtTag::Double(64.0),
+                    NbtTag::Double(-3.5),
+                ]),
+            ),
+            ("Dimension".to_string(), NbtTag::Int(-1)),
+            ("Health".to_string(), NbtTag::Float(18.0)),
+            ("XpLevel".to_string(), NbtTag::Int(30)),
+            (
+                "Inventory".to_string(),
+                NbtTag::List(vec![
+                    item(0, "minecraft:diamond_sword", 1),
+                    item(8, "minecraft:torch", 64),
+                ]),
+            ),
+        ]))
+    }
+
+    #[test]
+    fn test_from_nbt() {
+        let data = PlayerData::from_nbt(&player()).unwrap();
+        assert_eq!((data.x, data.y, data.z), (10.5, 64.0, -3.5));
+        assert_eq!(data.dimension.as_deref(), Some("minecraft:the_nether"));
+        assert_eq!((data.health, data.xp_level), (18.0, 30));
+        assert_eq!(data.inventory.len(), 2);
+        assert_eq!(data.inventory[1].count, 64);
+        assert!(data.ender_items.is_empty());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut root = player();
+        PlayerDataEdit::Teleport {
+            x: 0.5,
+            y: 70.0,
+            z: 0.5,
+            dimension: Some("minecraft:overworld".to_string()),
+        }
+        .apply(&mut root)
+        .unwrap();
+        PlayerDataEdit::ClearItem { slot: 8 }
+            .apply(&mut root)
+            .unwrap();
+        let data = PlayerData::from_nbt(&root).unwrap();
+        assert_eq!((data.x, data.y, data.z), (0.5, 70.0, 0.5));
+        assert_eq!(data.dimension.as_deref(), Some("minecraft:overworld"));
+        assert_eq!(data.inventory.len(), 1);
+
+        assert!(PlayerDataEdit::ClearItem { slot: 8 }
+            .apply(&mut root)
+            .is_err());
+        assert!(PlayerDataEdit::Teleport {
+            x: f64::NAN,
+            y: 0.0,
+            z: 0.0,
+            dimension: None,
+        }
+        .apply(&mut root)
+        .is_err());
+        assert!(PlayerDataEdit::Teleport {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            dimension: Some("modded:mining".to_string()),
+        }
+        .apply(&mut root)
+        .is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! World pregeneration through a plugin or mod like Chunky, driven over the console.
+//!
+//! Progress is read back from the console and reported as a progression event.
+//! The task can be paused while players are online so it doesn't lag the server for them.
+
+use color_eyre::eyre::eyre;
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{
+    CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, ProgressionStartValue,
+};
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_server::{State, TServer};
+use crate::types::Snowflake;
+
+use super::line_parser::parse_system_msg;
+use super::MinecraftInstance;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PregenCommands {
+    /// Sent in order to start the task, with `{world}`, `{radius}`, `{x}` and `{z}` filled in.
+    /// Commands mentioning `{world}` are skipped when no world is given
+    pub start: Vec<String>,
+    pub pause: Vec<String>,
+    pub resume: Vec<String>,
+    pub cancel: Vec<String>,
+    /// Matched against console output, its `percent` group is the overall progress
+    pub progress_pattern: String,
+    /// Matched against console output, the task is over once it matches
+    pub done_pattern: String,
+}
+
+impl Default for PregenCommands {
+    /// Chunky's commands
+    fn default() -> Self {
+        Self {
+            start: vec![
+                "chunky world {world}".to_string(),
+                "chunky center {x} {z}".to_string(),
+                "chunky radius {radius}".to_string(),
+                "chunky start".to_string(),
+            ],
+            pause: vec!["chunky pause".to_string()],
+            resume: vec!["chunky continue".to_string()],
+            cancel: vec!["chunky cancel".to_string(), "chunky confirm".to_string()],
+            progress_pattern: r"Processed: \d+ chunks \((?P<percent>[\d.]+)%\)".to_string(),
+            done_pattern: r"Task finished for".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct PregenConfig {
+    /// In blocks
+    pub radius: u32,
+    #[serde(default)]
+    pub center_x: i32,
+    #[serde(default)]
+    pub center_z: i32,
+    /// The main world if left out
+    pub world: Option<String>,
+    /// Keep generating while players are online instead of pausing until they leave
+    #[serde(default)]
+    pub keep_running_with_players: bool,
+    /// Chunky's commands if left out
+    pub commands: Option<PregenCommands>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum PregenStatus {
+    Running,
+    /// Paused by a user
+    Paused,
+    /// Paused until every player has left
+    WaitingForPlayersToLeave,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct PregenInfo {
+    /// Id of the progression event, and of the job, tracking the task
+    pub job_id: Snowflake,
+    pub radius: u32,
+    pub center_x: i32,
+    pub center_z: i32,
+    pub world: Option<String>,
+    pub keep_running_with_players: bool,
+    pub status: PregenStatus,
+    pub percent: f64,
+}
+
+pub(super) struct PregenTask {
+    info: PregenInfo,
+    commands: PregenCommands,
+    cancel: CancellationToken,
+}
+
+fn fill_commands(config: &PregenConfig, commands: &PregenCommands) -> Vec<String> {
+    commands
+        .start
+        .iter()
+        .filter_map(|command| {
+            let command = match &config.world {
+                Some(world) => command.replace("{world}", world),
+                None if command.contains("{world}") => return None,
+                None => command.clone(),
+            };
+            Some(
+                command
+                    .replace("{radius}", &config.radius.to_string())
+                    .replace("{x}", &config.center_x.to_string())
+                    .replace("{z}", &config.center_z.to_string()),
+            )
+        })
+        .collect()
+}
+
+fn compile_pattern(pattern: &str) -> Result<Regex, Error> {
+    Regex::new(pattern).map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid pattern {}: {}", pattern, e),
+    })
+}
+
+fn parse_percent(progress_pattern: &Regex, message: &str) -> Option<f64> {
+    progress_pattern
+        .captures(message)
+        .ok()??
+        .name("percent")?
+        .as_str()
+        .parse()
+        .ok()
+}
+
+impl MinecraftInstance {
+    pub async fn pregen_info(&self) -> Option<PregenInfo> {
+        self.pregen
+            .lock()
+            .await
+            .as_ref()
+            .map(|task| task.info.clone())
+    }
+
+    async fn send_commands(&self, commands: &[String]) -> Result<(), Error> {
+        for command in commands {
+            self.send_command(command, CausedBy::System).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn start_pregen(
+        &self,
+        config: PregenConfig,
+        caused_by: CausedBy,
+    ) -> Result<PregenInfo, Error> {
+        if self.state().await != State::Running {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The server must be running to pregenerate its world"),
+            });
+        }
+        if config.radius == 0 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Radius must be greater than 0"),
+            });
+        }
+        let commands = config.commands.clone().unwrap_or_default();
+        let progress_pattern = compile_pattern(&commands.progress_pattern)?;
+        let done_pattern = compile_pattern(&commands.done_pattern)?;
+        if !progress_pattern
+            .capture_names()
+            .any(|name| name == Some("percent"))
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Progress pattern needs a `percent` group"),
+            });
+        }
+
+        let mut pregen = self.pregen.lock().await;
+        if pregen.is_some() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The world is already being pregenerated"),
+            });
+        }
+        let mut events = self.event_broadcaster.subscribe();
+        self.send_commands(&fill_commands(&config, &commands))
+            .await?;
+
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!(
+                "Pregenerating {} to a radius of {}",
+                self.name().await,
+                config.radius
+            ),
+            Some(100.0),
+            Some(ProgressionStartValue::WorldPregeneration {
+                instance_uuid: self.uuid.clone(),
+            }),
+            caused_by,
+        );
+        let info = PregenInfo {
+            job_id: event_id.snowflake(),
+            radius: config.radius,
+            center_x: config.center_x,
+            center_z: config.center_z,
+            world: config.world.clone(),
+            keep_running_with_players: config.keep_running_with_players,
+            status: PregenStatus::Running,
+            percent: 0.0,
+        };
+        let cancel = CancellationToken::new();
+        *pregen = Some(PregenTask {
+            info: info.clone(),
+            commands,
+            cancel: cancel.clone(),
+        });
+        drop(pregen);
+        self.event_broadcaster.send(progression_start_event);
+
+        let __self = self.clone();
+        tokio::spawn(async move {
+            let (success, message) = loop {
+                let event = tokio::select! {
+                    _ = cancel.cancelled() => break (false, "Cancelled".to_string()),
+                    event = events.recv() => event,
+                };
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => {
+                        break (false, "Lodestone is shutting down".to_string())
+                    }
+                };
+                let instance_event_inner = match event.event_inner {
+                    EventInner::InstanceEvent(InstanceEvent {
+                        instance_uuid,
+                        instance_event_inner,
+                        ..
+                    }) if instance_uuid == __self.uuid => instance_event_inner,
+                    _ => continue,
+                };
+                match instance_event_inner {
+                    InstanceEventInner::InstanceOutput { message, .. } => {
+                        if done_pattern.is_match(&message).unwrap_or(false) {
+                            break (true, "World pregenerated".to_string());
+                        }
+                        let percent = match parse_percent(&progress_pattern, &message) {
+                            Some(percent) => percent.clamp(0.0, 100.0),
+                            None => continue,
+                        };
+                        let delta = match __self.pregen.lock().await.as_mut() {
+                            Some(task) => {
+                                let delta = percent - task.info.percent;
+                                task.info.percent = percent;
+                                delta
+                            }
+                            None => continue,
+                        };
+                        __self
+                            .event_broadcaster
+                            .send(Event::new_progression_event_update(
+                                &event_id,
+                                parse_system_msg(&message).unwrap_or(message),
+                                delta,
+                            ));
+                    }
+                    InstanceEventInner::PlayerChange { player_list, .. } => {
+                        __self.throttle_pregen(player_list.len()).await;
+                    }
+                    InstanceEventInner::StateTransition {
+                        to: State::Stopping | State::Stopped | State::Error,
+                    } => break (false, "The server stopped".to_string()),
+                    _ => {}
+                }
+            };
+            let mut pregen = __self.pregen.lock().await;
+            if matches!(pregen.as_ref(), Some(task) if task.info.job_id == info.job_id) {
+                pregen.take();
+            }
+            drop(pregen);
+            __self
+                .event_broadcaster
+                .send(Event::new_progression_event_end(
+                    event_id,
+                    success,
+                    Some(message),
+                    None,
+                ));
+        });
+
+        if !config.keep_running_with_players {
+            let players_online = self.players_manager.lock().await.count();
+            self.throttle_pregen(players_online as usize).await;
+        }
+        Ok(self.pregen_info().await.unwrap_or(info))
+    }
+
+    /// Pauses the task while players are online and resumes it once they all left
+    async fn throttle_pregen(&self, players_online: usize) {
+        let mut pregen = self.pregen.lock().await;
+        let task = match pregen.as_mut() {
+            Some(task) if !task.info.keep_running_with_players => task,
+            _ => return,
+        };
+        let commands = match task.info.status {
+            PregenStatus::Running if players_online > 0 => {
+                task.info.status = PregenStatus::WaitingForPlayersToLeave;
+                task.commands.pause.clone()
+            }
+            PregenStatus::WaitingForPlayersToLeave if players_online == 0 => {
+                task.info.status = PregenStatus::Running;
+                task.commands.resume.clone()
+            }
+            _ => return,
+        };
+        drop(pregen);
+        if let Err(e) = self.send_commands(&commands).await {
+            error!("Failed to pause or resume world pregeneration: {}", e);
+        }
+    }
+
+    pub async fn set_pregen_paused(&self, paused: bool) -> Result<(), Error> {
+        let players_online = self.players_manager.lock().await.count();
+        let mut pregen = self.pregen.lock().await;
+        let task = pregen.as_mut().ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("The world is not being pregenerated"),
+        })?;
+        let commands = match (task.info.status, paused) {
+            (PregenStatus::Running, true) => task.commands.pause.clone(),
+            (PregenStatus::WaitingForPlayersToLeave, true) => Vec::new(),
+            (PregenStatus::Paused, false)
+                if players_online > 0 && !task.info.keep_running_with_players =>
+            {
+                task.info.status = PregenStatus::WaitingForPlayersToLeave;
+                return Ok(());
+            }
+            (PregenStatus::Paused, false) => task.commands.resume.clone(),
+            _ => return Ok(()),
+        };
+        task.info.status = if paused {
+            PregenStatus::Paused
+        } else {
+            PregenStatus::Running
+        };
+        drop(pregen);
+        self.send_commands(&commands).await
+    }
+
+    pub async fn cancel_pregen(&self) -> Result<(), Error> {
+        let task = self.pregen.lock().await.take().ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("The world is not being pregenerated"),
+        })?;
+        task.cancel.cancel();
+        self.send_commands(&task.commands.cancel).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunky_commands() {
+        let commands = PregenCommands::default();
+        let progress_pattern = compile_pattern(&commands.progress_pattern).unwrap();
+        assert_eq!(
+            parse_percent(
+                &progress_pattern,
+                "[12:00:00 INFO]: [Chunky] Task running for world. Processed: 1234 chunks (5.67%), ETA: 0:12:34, Rate: 123.4 cps, Current: 12, 34"
+            ),
+            Some(5.67)
+        );
+        let config = PregenConfig {
+            radius: 1000,
+            center_x: 0,
+            center_z: -16,
+            world: None,
+            keep_running_with_players: false,
+            commands: None,
+        };
+        assert_eq!(
+            fill_commands(&config, &commands),
+            vec!["chunky center 0 -16", "chunky radius 1000", "chunky start"]
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! The server process, spawned either with plain pipes or inside a pseudo terminal.
+//!
+//! Some servers and wrapper scripts only print prompts or colored output, or flush
+//! line by line, when attached to a TTY. PTY mode gives them one; stdout and stderr are then
+//! merged into a single stream, which is how a terminal would show them anyway.
+
+use std::{
+    io::{Read, Write},
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream, ReadBuf},
+    process::{Child, ChildStdin, Command},
+};
+
+use crate::error::Error;
+
+pub(super) enum ServerProcess {
+    Pipe(Child),
+    Pty {
+        child: Box<dyn portable_pty::Child + Send + Sync>,
+        // dropping the master closes the terminal, so it lives as long as the child
+        _master: Box<dyn MasterPty + Send>,
+    },
+}
+
+impl ServerProcess {
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            ServerProcess::Pipe(child) => child.id(),
+            ServerProcess::Pty { child, .. } => child.process_id(),
+        }
+    }
+
+    pub async fn kill(&mut self) -> Result<(), Error> {
+        match self {
+            ServerProcess::Pipe(child) => child.kill().await.context("Failed to kill process")?,
+            // reaped by the output task once the terminal closes
+            ServerProcess::Pty { child, .. } => child.kill().context("Failed to kill process")?,
+        }
+        Ok(())
+    }
+
+    /// Waits for the process in the background, so it doesn't linger as a zombie
+    ///
+    /// The server may have closed its output without exiting, so this doesn't block on it
+    pub fn reap(self) {
+        match self {
+            // tokio already reaps dropped children
+            ServerProcess::Pipe(_) => {}
+            ServerProcess::Pty { mut child, .. } => {
+                tokio::task::spawn_blocking(move || {
+                    let _ = child.wait();
+                });
+            }
+        }
+    }
+}
+
+pub(super) enum ServerStdin {
+    Pipe(ChildStdin),
+    /// Fed to the terminal by a dedicated thread, see [`spawn_pty`]
+    Pty(DuplexStream),
+}
+
+impl ServerStdin {
+    pub async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            ServerStdin::Pipe(stdin) => stdin.write_all(buf).await,
+            ServerStdin::Pty(stdin) => stdin.write_all(buf).await,
+        }
+    }
+}
+
+/// A reader that never yields, standing in for stderr when it is merged into stdout
+pub(super) struct PendingReader;
+
+impl AsyncRead for PendingReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Pending
+    }
+}
+
+pub(super) struct SpawnedPty {
+    pub process: ServerProcess,
+    pub stdin: ServerStdin,
+    pub output: DuplexStream,
+}
+
+/// Runs `command` inside a pseudo terminal
+///
+/// The terminal is read and written on dedicated threads since portable-pty only offers
+/// blocking io, a server that stops reading its input then can't block the runtime
+pub(super) fn spawn_pty(command: &Command) -> Result<SpawnedPty, Error> {
+    let command = command.as_std();
+    let mut builder = CommandBuilder::new(command.get_program());
+    builder.args(command.get_args());
+    if let Some(dir) = command.get_current_dir() {
+        builder.cwd(dir);
+    }
+    for (key, value) in command.get_envs() {
+        if let Some(value) = value {
+            builder.env(key, value);
+        }
+    }
+    let pair = native_pty_system()
+        .openpty(PtySize {
+            rows: 24,
+            cols: 200,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .map_err(|e| eyre!("Failed to open pty: {e}"))?;
+    let child = pair
+        .slave
+        .spawn_command(builder)
+        .map_err(|e| eyre!("Failed to spawn process in pty: {e}"))?;
+    drop(pair.slave);
+    let mut reader = pair
+        .master
+        .try_clone_reader()
+        .map_err(|e| eyre!("Failed to read from pty: {e}"))?;
+    let mut writer = pair
+        .master
+        .take_writer()
+        .map_err(|e| eyre!("Failed to write to pty: {e}"))?;
+
+    let (mut output_tx, output_rx) = tokio::io::duplex(64 * 1024);
+    let handle = tokio::runtime::Handle::current();
+    let input_handle = handle.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0_u8; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                // the read errors out instead of returning 0 once the child exits on some platforms
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if handle.block_on(output_tx.write_all(&buf[..n])).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    let (input_tx, mut input_rx) = tokio::io::duplex(64 * 1024);
+    std::thread::spawn(move || {
+        let mut buf = [0_u8; 4096];
+        loop {
+            match input_handle.block_on(input_rx.read(&mut buf)) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if writer
+                        .write_all(&buf[..n])
+                        .and_then(|_| writer.flush())
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    Ok(SpawnedPty {
+        process: ServerProcess::Pty {
+            child,
+            _master: pair.master,
+        },
+        stdin: ServerStdin::Pty(input_tx),
+        output: output_rx,
+    })
+}
@@ -0,0 +1,413 @@
+//! Asking a running server about itself over the network, rather than relying on its console.
+//!
+//! Java servers answer the Query protocol (GameSpy 4) when `enable-query` is on, with the full
+//! player list and, on Bukkit derived servers, the plugins. Bedrock clients see the server
+//! through Geyser, which answers RakNet's unconnected ping when crossplay is enabled. The
+//! players Query reports are used to correct the list built from console output, which misses
+//! joins and leaves on some setups.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tracing::debug;
+use ts_rs::TS;
+
+use crate::{
+    prelude::GameInstance,
+    traits::t_server::{State, TServer},
+    AppState,
+};
+
+use super::{player::MinecraftPlayer, MinecraftInstance};
+
+const QUERY_INTERVAL: Duration = Duration::from_secs(15);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const QUERY_MAGIC: [u8; 2] = [0xFE, 0xFD];
+const RAKNET_MAGIC: [u8; 16] = [
+    0x00, 0xFF, 0xFF, 0x00, 0xFE, 0xFE, 0xFE, 0xFE, 0xFD, 0xFD, 0xFD, 0xFD, 0x12, 0x34, 0x56, 0x78,
+];
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct QueryStatus {
+    pub motd: String,
+    pub version: String,
+    /// Like `Paper on 1.20.1`, only Bukkit derived servers say
+    pub software: Option<String>,
+    pub plugins: Vec<String>,
+    pub map: String,
+    pub players_online: u32,
+    pub max_players: u32,
+    pub players: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct BedrockStatus {
+    pub motd: String,
+    pub version: String,
+    pub protocol: u32,
+    pub players_online: u32,
+    pub max_players: u32,
+    pub level_name: Option<String>,
+    pub game_mode: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ServerStatus {
+    /// When the server was last asked, in seconds since the epoch
+    pub checked_at: i64,
+    /// `None` when Query is disabled or the server didn't answer
+    pub query: Option<QueryStatus>,
+    /// `None` without crossplay, or when Geyser didn't answer
+    pub bedrock: Option<BedrockStatus>,
+}
+
+fn query_packet(kind: u8, session_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(7 + payload.len());
+    packet.extend_from_slice(&QUERY_MAGIC);
+    packet.push(kind);
+    packet.extend_from_slice(&session_id.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// The rest of a response of `kind` to the session, without its header
+fn query_body(response: &[u8], kind: u8, session_id: u32) -> Option<&[u8]> {
+    if response.len() < 5 || response[0] != kind || response[1..5] != session_id.to_be_bytes() {
+        return None;
+    }
+    Some(&response[5..])
+}
+
+/// The challenge token from a handshake response, to send back with the stat request
+fn parse_challenge(response: &[u8], session_id: u32) -> Option<[u8; 4]> {
+    let body = query_body(response, 0x09, session_id)?;
+    let token = body.split(|byte| *byte == 0).next()?;
+    let token: i32 = std::str::from_utf8(token).ok()?.trim().parse().ok()?;
+    Some(token.to_be_bytes())
+}
+
+fn parse_plugins(plugins: &str) -> (Option<String>, Vec<String>) {
+    if plugins.trim().is_empty() {
+        return (None, Vec::new());
+    }
+    match plugins.split_once(": ") {
+        Some((software, list)) => (
+            Some(software.trim().to_string()),
+            list.split("; ")
+                .map(|plugin| plugin.trim().to_string())
+                .filter(|plugin| !plugin.is_empty())
+                .collect(),
+        ),
+        None => (Some(plugins.trim().to_string()), Vec::new()),
+    }
+}
+
+fn parse_full_stat(response: &[u8], session_id: u32) -> Option<QueryStatus> {
+    let body = query_body(response, 0x00, session_id)?;
+    // "splitnum\0\x80\0", then key and value pairs up to an empty key
+    let mut fields = body.get(11..)?.split(|byte| *byte == 0);
+    let mut status = QueryStatus::default();
+    loop {
+        let key = fields.next()?;
+        if key.is_empty() {
+            break;
+        }
+        let value = String::from_utf8_lossy(fields.next()?).to_string();
+        match key {
+            b"hostname" => status.motd = value,
+            b"version" => status.version = value,
+            b"plugins" => (status.software, status.plugins) = parse_plugins(&value),
+            b"map" => status.map = value,
+            b"numplayers" => status.players_online = value.parse().ok()?,
+            b"maxplayers" => status.max_players = value.parse().ok()?,
+            _ => {}
+        }
+    }
+    // "\x01player_\0\0", then a name per field up to an empty one
+    if fields.next()? != b"\x01player_" {
+        return None;
+    }
+    fields.next()?;
+    status.players = fields
+        .take_while(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .collect();
+    Some(status)
+}
+
+fn parse_unconnected_pong(response: &[u8]) -> Option<BedrockStatus> {
+    // id, the ping's time and the server's guid come before the magic
+    if response.first() != Some(&0x1C) || response.get(17..33)? != RAKNET_MAGIC {
+        return None;
+    }
+    let len = u16::from_be_bytes([*response.get(33)?, *response.get(34)?]) as usize;
+    let id = String::from_utf8_lossy(response.get(35..35 + len)?).to_string();
+    // MCPE;motd;protocol;version;online;max;server id;level name;game mode;...
+    let fields: Vec<&str> = id.split(';').collect();
+    if fields.len() < 6 {
+        return None;
+    }
+    let optional = |index: usize| {
+        fields
+            .get(index)
+            .filter(|field| !field.is_empty())
+            .map(|field| field.to_string())
+    };
+    Some(BedrockStatus {
+        motd: fields[1].to_string(),
+        protocol: fields[2].parse().ok()?,
+        version: fields[3].to_string(),
+        players_online: fields[4].parse().ok()?,
+        max_players: fields[5].parse().ok()?,
+        level_name: optional(7),
+        game_mode: optional(8),
+    })
+}
+
+async fn exchange(socket: &UdpSocket, request: &[u8]) -> Option<Vec<u8>> {
+    socket.send(request).await.ok()?;
+    let mut buf = vec![0u8; 65536];
+    let len = tokio::time::timeout(QUERY_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .ok()?
+        .ok()?;
+    buf.truncate(len);
+    Some(buf)
+}
+
+async fn connect(addr: SocketAddr) -> Option<UdpSocket> {
+    let bind = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind).await.ok()?;
+    socket.connect(addr).await.ok()?;
+    Some(socket)
+}
+
+pub async fn query(addr: SocketAddr) -> Option<QueryStatus> {
+    let socket = connect(addr).await?;
+    // only the low 4 bits of each byte are kept by servers
+    let session_id = rand::random::<u32>() & 0x0F0F_0F0F;
+    let handshake = exchange(&socket, &query_packet(0x09, session_id, &[])).await?;
+    let challenge = parse_challenge(&handshake, session_id)?;
+    let mut payload = challenge.to_vec();
+    // the padding asks for the full stat rather than the basic one
+    payload.extend_from_slice(&[0; 4]);
+    let stat = exchange(&socket, &query_packet(0x00, session_id, &payload)).await?;
+    parse_full_stat(&stat, session_id)
+}
+
+pub async fn bedrock_ping(addr: SocketAddr) -> Option<BedrockStatus> {
+    let socket = connect(addr).await?;
+    let mut ping = vec![0x01];
+    ping.extend_from_slice(&chrono::Utc::now().timestamp_millis().to_be_bytes());
+    ping.extend_from_slice(&RAKNET_MAGIC);
+    ping.extend_from_slice(&rand::random::<u64>().to_be_bytes());
+    parse_unconnected_pong(&exchange(&socket, &ping).await?)
+}
+
+impl MinecraftInstance {
+    /// The address the server listens on, the loopback one unless it is bound to a single IP
+    async fn local_addr(&self, port: u32) -> Option<SocketAddr> {
+        let ip = self
+            .configurable_manifest
+            .lock()
+            .await
+            .get_unique_setting_key("server-ip")
+            .and_then(|setting| setting.get_value())
+            .and_then(|value| value.try_as_string().ok())
+            .filter(|ip| !ip.trim().is_empty())
+            .cloned()
+            .unwrap_or_else(|| "127.0.0.1".to_string());
+        let ip = ip.parse().ok()?;
+        Some(SocketAddr::new(ip, port.try_into().ok()?))
+    }
+
+    async fn query_port(&self) -> Option<u32> {
+        let manifest = self.configurable_manifest.lock().await;
+        let enabled = manifest
+            .get_unique_setting_key("enable-query")
+            .and_then(|setting| setting.get_value())
+            .and_then(|value| value.try_as_boolean().ok())
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+        manifest
+            .get_unique_setting_key("query.port")
+            .and_then(|setting| setting.get_value())
+            .and_then(|value| value.try_as_unsigned_integer().ok())
+            .or_else(|| {
+                manifest
+                    .get_unique_setting_key("server-port")
+                    .and_then(|setting| setting.get_value())
+                    .and_then(|value| value.try_as_unsigned_integer().ok())
+            })
+    }
+
+    /// The last answers the server gave, asking it first if it never has been
+    pub async fn server_status(&self) -> ServerStatus {
+        let cached = self.server_status.lock().await.clone();
+        match cached {
+            Some(status) => status,
+            None => self.refresh_server_status().await,
+        }
+    }
+
+    pub async fn refresh_server_status(&self) -> ServerStatus {
+        let query_addr = match self.query_port().await {
+            Some(port) => self.local_addr(port).await,
+            None => None,
+        };
+        let bedrock_port = self.config.lock().await.bedrock_port;
+        let bedrock_addr = match bedrock_port {
+            Some(port) => self.local_addr(port).await,
+            None => None,
+        };
+        let (query, bedrock) = tokio::join!(
+            async {
+                match query_addr {
+                    Some(addr) => query(addr).await,
+                    None => None,
+                }
+            },
+            async {
+                match bedrock_addr {
+                    Some(addr) => bedrock_ping(addr).await,
+                    None => None,
+                }
+            }
+        );
+        if let Some(query) = &query {
+            self.sync_players(&query.players).await;
+        }
+        let status = ServerStatus {
+            checked_at: chrono::Utc::now().timestamp(),
+            query,
+            bedrock,
+        };
+        *self.server_status.lock().await = Some(status.clone());
+        status
+    }
+
+    /// Brings the player list in line with what the server reported
+    async fn sync_players(&self, online: &[String]) {
+        let instance_name = self.config.lock().await.name.clone();
+        let mut players_manager = self.players_manager.lock().await;
+        let gone: Vec<String> = players_manager
+            .as_ref()
+            .iter()
+            .filter(|player| !online.contains(&player.name))
+            .map(|player| player.name.clone())
+            .collect();
+        for name in gone {
+            debug!("{name} was missed leaving {instance_name}");
+            players_manager.remove_by_name(name, instance_name.clone());
+        }
+        for name in online {
+            if !players_manager
+                .as_ref()
+                .iter()
+                .any(|player| &player.name == name)
+            {
+                debug!("{name} was missed joining {instance_name}");
+                players_manager.add_player(
+                    MinecraftPlayer::new(name.clone(), None),
+                    instance_name.clone(),
+                );
+            }
+        }
+    }
+}
+
+/// Asks running Minecraft instances about themselves until the core shuts down
+pub async fn run_status_queries(state: AppState) {
+    let mut interval = tokio::time::interval(QUERY_INTERVAL);
+    loop {
+        interval.tick().await;
+        let instances: Vec<MinecraftInstance> = state
+            .instances
+            .lock()
+            .await
+            .values()
+            .filter_map(|instance| match instance {
+                GameInstance::MinecraftInstance(minecraft) => Some(minecraft.clone()),
+                _ => None,
+            })
+            .collect();
+        for instance in instances {
+            if instance.state().await != State::Running {
+                // a stale status would say a stopped server still has players
+                *instance.server_status.lock().await = None;
+                continue;
+            }
+            instance.refresh_server_status().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_full_stat() {
+        let session_id = 0x0102_0304;
+        assert_eq!(
+            parse_challenge(b"\x09\x01\x02\x03\x049513307\0", session_id),
+            Some(9513307i32.to_be_bytes())
+        );
+        let mut response = vec![0x00, 0x01, 0x02, 0x03, 0x04];
+        response.extend_from_slice(b"splitnum\0\x80\0");
+        response.extend_from_slice(b"hostname\0A Minecraft Server\0gametype\0SMP\0");
+        response.extend_from_slice(b"version\x001.20.1\0");
+        response.extend_from_slice(b"plugins\0Paper on 1.20.1: WorldEdit 7.2; LuckPerms 5.4\0");
+        response.extend_from_slice(b"map\0world\0numplayers\x002\0maxplayers\x0020\0\0");
+        response.extend_from_slice(b"\x01player_\0\0Steve\0Alex\0\0");
+        assert_eq!(
+            parse_full_stat(&response, session_id),
+            Some(QueryStatus {
+                motd: "A Minecraft Server".to_string(),
+                version: "1.20.1".to_string(),
+                software: Some("Paper on 1.20.1".to_string()),
+                plugins: vec!["WorldEdit 7.2".to_string(), "LuckPerms 5.4".to_string()],
+                map: "world".to_string(),
+                players_online: 2,
+                max_players: 20,
+                players: vec!["Steve".to_string(), "Alex".to_string()],
+            })
+        );
+        assert_eq!(parse_full_stat(&response, 0x0504_0302), None);
+    }
+
+    #[test]
+    fn test_parse_unconnected_pong() {
+        let id = "MCPE;Geyser;594;1.20.10;3;20;1234;Lodestone;Survival;1;19132;19133;";
+        let mut response = vec![0x1C];
+        response.extend_from_slice(&[0; 16]);
+        response.extend_from_slice(&RAKNET_MAGIC);
+        response.extend_from_slice(&(id.len() as u16).to_be_bytes());
+        response.extend_from_slice(id.as_bytes());
+        assert_eq!(
+            parse_unconnected_pong(&response),
+            Some(BedrockStatus {
+                motd: "Geyser".to_string(),
+                version: "1.20.10".to_string(),
+                protocol: 594,
+                players_online: 3,
+                max_players: 20,
+                level_name: Some("Lodestone".to_string()),
+                game_mode: Some("Survival".to_string()),
+            })
+        );
+        response[18] = 0;
+        assert_eq!(parse_unconnected_pong(&response), None);
+    }
+}
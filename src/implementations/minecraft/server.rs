@@ -1,34 +1,44 @@
-use std::path::PathBuf;
 use std::process::Stdio;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use color_eyre::eyre::{eyre, Context};
 use sysinfo::{Pid, PidExt, ProcessExt, SystemExt};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Command;
 
+use crate::console_parser::{ConsoleLine, LogLevel};
 use crate::error::{Error, ErrorKind};
 use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
 use crate::implementations::minecraft::line_parser::{
-    parse_player_joined, parse_player_left, parse_player_msg, parse_server_started,
-    parse_system_msg, PlayerMessage,
+    parse_player_msg, parse_system_msg, PlayerMessage,
 };
 use crate::implementations::minecraft::player::MinecraftPlayer;
 use crate::implementations::minecraft::util::name_to_uuid;
+use crate::isolation;
+use crate::log_parser::LogEvent;
 use crate::macro_executor::SpawnResult;
+use crate::process_priority;
+use crate::process_tree::ProcessTree;
+use crate::sandbox::{self, SandboxLevel};
 use crate::traits::t_configurable::TConfigurable;
 use crate::traits::t_macro::TaskEntry;
-use crate::traits::t_server::{MonitorReport, State, StateAction, TServer};
+use crate::traits::t_server::{
+    DiskSample, MonitorReport, State, StateAction, StateTransition, TServer,
+};
 
 use crate::types::Snowflake;
 use crate::util::{dont_spawn_terminal, list_dir};
 
+use super::gc_log::gc_log_args;
+use super::process::{spawn_pty, PendingReader, ServerProcess, ServerStdin, SpawnedPty};
 use super::r#macro::{resolve_macro_invocation, MinecraftMainWorkerGenerator};
+use super::world_upgrade::FORCE_UPGRADE_ARG;
 use super::{Flavour, ForgeBuildVersion, MinecraftInstance};
-use tracing::{error, info, warn};
+use tracing::{error, info, instrument, warn};
 
 #[async_trait::async_trait]
 impl TServer for MinecraftInstance {
+    #[instrument(skip_all, fields(instance_uuid = %self.uuid))]
     async fn start(&mut self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
         self.state.lock().await.try_transition(
@@ -125,24 +135,29 @@ impl TServer for MinecraftInstance {
             );
         }
 
-        let jre = if let Some(jre) = &config.java_cmd {
-            PathBuf::from(jre)
+        let gc_log_args = if config.gc_logging {
+            match self.prepare_gc_log_dir().await {
+                Ok(()) => gc_log_args(config.jre_major_version),
+                Err(e) => {
+                    warn!(
+                        "[{}] Failed to create the GC log directory, not logging GC: {e}",
+                        config.name
+                    );
+                    Vec::new()
+                }
+            }
         } else {
-            self.path_to_runtimes
-                .join("java")
-                .join(format!("jre{}", config.jre_major_version))
-                .join(if std::env::consts::OS == "macos" {
-                    "Contents/Home/bin"
-                } else {
-                    "bin"
-                })
-                .join("java")
+            Vec::new()
         };
 
+        let jre = self.java_binary(&config);
+        let sandbox_policy = self.sandbox_policy(&config, &jre).await;
+
         let mut server_start_command = Command::new(&jre);
         let server_start_command = server_start_command
             .arg(format!("-Xmx{}M", config.max_ram))
             .arg(format!("-Xms{}M", config.min_ram))
+            .args(&gc_log_args)
             .args(
                 &config
                     .cmd_args
@@ -229,42 +244,113 @@ impl TServer for MinecraftInstance {
         let server_start_command = server_start_command
             .arg("nogui")
             .current_dir(&self.path_to_instance);
+        if self.world_upgrade.lock().await.is_some() {
+            server_start_command.arg(FORCE_UPGRADE_ARG);
+        }
 
-        match dont_spawn_terminal(server_start_command)
-            .stdout(Stdio::piped())
-            .stdin(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-        {
-            Ok(mut proc) => {
-                let stdin = proc.stdin.take().ok_or_else(|| {
-                    error!(
-                        "[{}] Failed to take stdin during startup",
-                        config.name.clone()
-                    );
-                    eyre!("Failed to take stdin during startup")
-                })?;
+        let instance_user = isolation::prepare(&self.uuid, &self.path_to_instance).await;
+        let mut setpriv_command;
+        let server_start_command = match &instance_user {
+            Ok(Some(user)) if config.use_pty => {
+                setpriv_command = isolation::wrap_with_setpriv(
+                    server_start_command,
+                    user,
+                    &self.path_to_instance,
+                );
+                &mut setpriv_command
+            }
+            Ok(Some(user)) => isolation::run_as(server_start_command, user, &self.path_to_instance),
+            _ => server_start_command,
+        };
+
+        let sandboxed = match instance_user {
+            Err(e) => Err(e),
+            Ok(_) if config.sandbox == SandboxLevel::Off => Ok(()),
+            Ok(_) if config.use_pty => Err(eyre!(
+                "Servers attached to a pseudo terminal can't be sandboxed, turn one of them off"
+            )
+            .into()),
+            Ok(_) => sandbox::apply(server_start_command, &sandbox_policy),
+        };
+
+        let spawn_result = if let Err(e) = sandboxed {
+            Err(e)
+        } else if config.use_pty {
+            spawn_pty(server_start_command).map(
+                |SpawnedPty {
+                     process,
+                     stdin,
+                     output,
+                 }| {
+                    (
+                        process,
+                        stdin,
+                        Box::new(output) as Box<dyn AsyncRead + Send + Unpin>,
+                        Box::new(PendingReader) as Box<dyn AsyncRead + Send + Unpin>,
+                    )
+                },
+            )
+        } else {
+            dont_spawn_terminal(server_start_command)
+                .stdout(Stdio::piped())
+                .stdin(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .context("Failed to spawn server process")
+                .map_err(Error::from)
+                .and_then(|mut proc| {
+                    let stdin = proc.stdin.take().ok_or_else(|| {
+                        error!(
+                            "[{}] Failed to take stdin during startup",
+                            config.name.clone()
+                        );
+                        eyre!("Failed to take stdin during startup")
+                    })?;
+                    let stdout = proc.stdout.take().ok_or_else(|| {
+                        error!(
+                            "[{}] Failed to take stdout during startup",
+                            config.name.clone()
+                        );
+                        eyre!("Failed to take stdout during startup")
+                    })?;
+                    let stderr = proc.stderr.take().ok_or_else(|| {
+                        error!(
+                            "[{}] Failed to take stderr during startup",
+                            config.name.clone()
+                        );
+                        eyre!("Failed to take stderr during startup")
+                    })?;
+                    Ok((
+                        ServerProcess::Pipe(proc),
+                        ServerStdin::Pipe(stdin),
+                        Box::new(stdout) as Box<dyn AsyncRead + Send + Unpin>,
+                        Box::new(stderr) as Box<dyn AsyncRead + Send + Unpin>,
+                    ))
+                })
+        };
+
+        match spawn_result {
+            Ok((proc, stdin, stdout, stderr)) => {
                 self.stdin.lock().await.replace(stdin);
-                let stdout = proc.stdout.take().ok_or_else(|| {
-                    error!(
-                        "[{}] Failed to take stdout during startup",
-                        config.name.clone()
-                    );
-                    eyre!("Failed to take stdout during startup")
-                })?;
-                let stderr = proc.stderr.take().ok_or_else(|| {
-                    error!(
-                        "[{}] Failed to take stderr during startup",
-                        config.name.clone()
-                    );
-                    eyre!("Failed to take stderr during startup")
-                })?;
+                if let Some(pid) = proc.id() {
+                    if let Err(e) = self.write_process_record(pid).await {
+                        warn!("[{}] Failed to record server process: {}", config.name, e);
+                    }
+                    if let Err(e) = process_priority::apply(pid, config.priority) {
+                        warn!(
+                            "[{}] Failed to set process priority, running at normal priority: {}",
+                            config.name, e
+                        );
+                    }
+                    *self.process_tree.lock().await = Some(ProcessTree::new(pid));
+                }
                 *self.process.lock().await = Some(proc);
                 tokio::task::spawn({
                     let event_broadcaster = self.event_broadcaster.clone();
                     let uuid = self.uuid.clone();
                     let name = config.name.clone();
                     let players_manager = self.players_manager.clone();
+                    let line_parser = self.line_parser(&config);
                     let mut __self = self.clone();
                     async move {
                         let mut did_start = false;
@@ -304,7 +390,12 @@ impl TServer for MinecraftInstance {
 
                             if let Ok(line) = line_res {
                                 if let Some(line) = line {
-                                    let line = String::from_utf8_lossy(&line).to_string();
+                                    let console_line =
+                                        ConsoleLine::parse(&String::from_utf8_lossy(&line));
+                                    // escape codes would otherwise trip up the line parsers below
+                                    let line = console_line.message.clone();
+                                    let logged_as_error =
+                                        console_line.level == Some(LogLevel::Error);
                                     if !is_stdout {
                                         // info!("[{}] {}", name, line);
                                         warn!("[{}] {}", name, line);
@@ -312,10 +403,7 @@ impl TServer for MinecraftInstance {
                                     event_broadcaster.send(Event {
                                         event_inner: EventInner::InstanceEvent(InstanceEvent {
                                             instance_uuid: uuid.clone(),
-                                            instance_event_inner:
-                                                InstanceEventInner::InstanceOutput {
-                                                    message: line.clone(),
-                                                },
+                                            instance_event_inner: console_line.into(),
                                             instance_name: name.clone(),
                                         }),
                                         details: "".to_string(),
@@ -323,7 +411,8 @@ impl TServer for MinecraftInstance {
                                         caused_by: CausedBy::System,
                                     });
 
-                                    if parse_server_started(&line) && !did_start {
+                                    let log_event = line_parser.parse(&line);
+                                    if log_event == Some(LogEvent::Ready) && !did_start {
                                         did_start = true;
                                         self.state
                                             .lock()
@@ -350,67 +439,45 @@ impl TServer for MinecraftInstance {
                                             )
                                             .unwrap();
 
-                                        if let (Some(true), Some(rcon_psw), Some(rcon_port)) = {
-                                            let lock = self.configurable_manifest.lock().await;
-
-                                            let a = lock
-                                                .get_unique_setting_key("enable-rcon")
-                                                .and_then(|v| {
-                                                    v.get_value().map(|v| v.try_as_boolean().ok())
-                                                })
-                                                .flatten();
-
-                                            let b = lock
-                                                .get_unique_setting_key("rcon.password")
-                                                .and_then(|v| {
-                                                    v.get_value().map(|v| v.try_as_string().ok())
-                                                })
-                                                .flatten()
-                                                .cloned();
-
-                                            let c = lock
-                                                .get_unique_setting_key("rcon.port")
-                                                .and_then(|v| {
-                                                    v.get_value()
-                                                        .map(|v| v.try_as_unsigned_integer().ok())
-                                                })
-                                                .flatten();
-                                            (a, b, c)
-                                        } {
-                                            let max_retry = 3;
-                                            for i in 0..max_retry {
-                                                let rcon =
-                                                <rcon::Connection<tokio::net::TcpStream>>::builder(
-                                                )
-                                                .enable_minecraft_quirks(true)
-                                                .connect(
-                                                    &format!("localhost:{}", rcon_port),
-                                                    &rcon_psw,
-                                                )
+                                        self.connect_rcon().await;
+                                    }
+                                    match log_event {
+                                        Some(LogEvent::PlayerJoined(player_name)) => {
+                                            players_manager.lock().await.add_player(
+                                                MinecraftPlayer {
+                                                    name: player_name.clone(),
+                                                    uuid: name_to_uuid(&player_name).await,
+                                                },
+                                                self.name().await,
+                                            );
+                                        }
+                                        Some(LogEvent::PlayerLeft(player_name)) => {
+                                            players_manager
+                                                .lock()
                                                 .await
-                                                .map_err(|e| {
-                                                    warn!(
-                                                    "Failed to connect to RCON: {}, retry {}/{}",
-                                                    e, i, max_retry
-                                                );
-                                                    e
-                                                });
-                                                if let Ok(rcon) = rcon {
-                                                    info!("Connected to RCON");
-                                                    self.rcon_conn.lock().await.replace(rcon);
-                                                    break;
-                                                }
-                                                tokio::time::sleep(Duration::from_secs(
-                                                    2_u64.pow(i),
-                                                ))
-                                                .await;
-                                            }
-                                        } else {
-                                            warn!("RCON is not enabled or misconfigured, skipping");
-                                            self.rcon_conn.lock().await.take();
+                                                .remove_by_name(&player_name, self.name().await);
                                         }
+                                        // lines logged at error level already count as errors
+                                        Some(LogEvent::Error(message)) if !logged_as_error => {
+                                            event_broadcaster.send(Event {
+                                                event_inner: EventInner::InstanceEvent(
+                                                    InstanceEvent {
+                                                        instance_uuid: uuid.clone(),
+                                                        instance_event_inner:
+                                                            InstanceEventInner::InstanceError {
+                                                                message,
+                                                            },
+                                                        instance_name: name.clone(),
+                                                    },
+                                                ),
+                                                details: "".to_string(),
+                                                snowflake: Snowflake::default(),
+                                                caused_by: CausedBy::System,
+                                            });
+                                        }
+                                        _ => {}
                                     }
-                                    if let Some(system_msg) = parse_system_msg(&line) {
+                                    if parse_system_msg(&line).is_some() {
                                         let _ = event_broadcaster.send(Event {
                                             event_inner: EventInner::InstanceEvent(InstanceEvent {
                                                 instance_uuid: uuid.clone(),
@@ -424,23 +491,6 @@ impl TServer for MinecraftInstance {
                                             snowflake: Snowflake::default(),
                                             caused_by: CausedBy::System,
                                         });
-                                        if let Some(player_name) = parse_player_joined(&system_msg)
-                                        {
-                                            players_manager.lock().await.add_player(
-                                                MinecraftPlayer {
-                                                    name: player_name.clone(),
-                                                    uuid: name_to_uuid(&player_name).await,
-                                                },
-                                                self.name().await,
-                                            );
-                                        } else if let Some(player_name) =
-                                            parse_player_left(&system_msg)
-                                        {
-                                            players_manager
-                                                .lock()
-                                                .await
-                                                .remove_by_name(&player_name, self.name().await);
-                                        }
                                     } else if let Some(PlayerMessage { player, message }) =
                                         parse_player_msg(&line)
                                     {
@@ -465,6 +515,11 @@ impl TServer for MinecraftInstance {
                             }
                         }
                         info!("Instance {} process shutdown", name);
+                        self.reap_process_tree().await;
+                        if let Some(process) = self.process.lock().await.take() {
+                            process.reap();
+                        }
+                        self.remove_process_record().await;
                         self.state
                             .lock()
                             .await
@@ -542,11 +597,11 @@ impl TServer for MinecraftInstance {
                         }),
                     )
                     .unwrap();
-                Err(e).context("Failed to start server")?;
-                unreachable!();
+                Err(e)
             }
         }
     }
+    #[instrument(skip_all, fields(instance_uuid = %self.uuid))]
     async fn stop(&mut self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
 
@@ -567,21 +622,26 @@ impl TServer for MinecraftInstance {
         )?;
         let name = config.name.clone();
         let _uuid = self.uuid.clone();
-        self.stdin
-            .lock()
-            .await
-            .as_mut()
-            .ok_or_else(|| {
-                error!("[{}] Failed to stop instance: stdin not available", name);
-                eyre!("Failed to stop instance: stdin not available")
-            })?
-            .write_all(b"stop\n")
-            .await
-            .context("Failed to write to stdin")
-            .map_err(|e| {
-                error!("[{}] Failed to stop instance: {}", name, e);
-                e
-            })?;
+        let adopted_pid = *self.adopted_pid.lock().await;
+        if let Some(pid) = adopted_pid {
+            self.stop_adopted_process(pid).await?;
+        } else {
+            self.stdin
+                .lock()
+                .await
+                .as_mut()
+                .ok_or_else(|| {
+                    error!("[{}] Failed to stop instance: stdin not available", name);
+                    eyre!("Failed to stop instance: stdin not available")
+                })?
+                .write_all(b"stop\n")
+                .await
+                .context("Failed to write to stdin")
+                .map_err(|e| {
+                    error!("[{}] Failed to stop instance: {}", name, e);
+                    e
+                })?;
+        }
         self.rcon_conn.lock().await.take();
         let mut rx = self.event_broadcaster.subscribe();
         let instance_uuid = self.uuid.clone();
@@ -605,6 +665,7 @@ impl TServer for MinecraftInstance {
         }
     }
 
+    #[instrument(skip_all, fields(instance_uuid = %self.uuid))]
     async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
         if block {
             self.stop(caused_by.clone(), block).await?;
@@ -624,6 +685,7 @@ impl TServer for MinecraftInstance {
         }
     }
 
+    #[instrument(skip_all, fields(instance_uuid = %self.uuid))]
     async fn kill(&mut self, _cause_by: CausedBy) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
 
@@ -631,6 +693,14 @@ impl TServer for MinecraftInstance {
             warn!("[{}] Instance is already stopped", config.name.clone());
             return Err(eyre!("Instance is already stopped").into());
         }
+        // children first, killing a wrapper script before them would leave them running
+        if let Some(tree) = self.process_tree.lock().await.as_ref() {
+            tree.kill_descendants(&mut self.system.lock().await);
+        }
+        let adopted_pid = *self.adopted_pid.lock().await;
+        if let Some(pid) = adopted_pid {
+            return self.kill_adopted_process(pid).await;
+        }
         self.process
             .lock()
             .await
@@ -644,7 +714,6 @@ impl TServer for MinecraftInstance {
             })?
             .kill()
             .await
-            .context("Failed to kill process")
             .map_err(|e| {
                 error!("[{}] Failed to kill instance: {}", config.name.clone(), e);
                 e
@@ -660,6 +729,9 @@ impl TServer for MinecraftInstance {
         let config = self.config.lock().await.clone();
         if self.state().await == State::Stopped {
             Err(eyre!("Instance is stopped").into())
+        } else if self.adopted_pid.lock().await.is_some() {
+            // the adopted process' stdin belonged to the previous core
+            self.send_rcon(command).await.map(|_| ())
         } else {
             match self.stdin.lock().await.as_mut() {
                 Some(stdin) => match {
@@ -704,30 +776,84 @@ impl TServer for MinecraftInstance {
             }
         }
     }
+
+    async fn console_history(&self, lines: usize) -> Vec<Event> {
+        self.console_buffer.last(lines).await
+    }
+
     async fn monitor(&self) -> MonitorReport {
+        let process_tree = self.process_tree.lock().await;
         let mut sys = self.system.lock().await;
         sys.refresh_memory();
-        if let Some(pid) = self.process.lock().await.as_ref().and_then(|p| p.id()) {
-            sys.refresh_process(Pid::from_u32(pid));
-            let proc = (*sys).process(Pid::from_u32(pid));
-            if let Some(proc) = proc {
-                let cpu_usage =
-                    sys.process(Pid::from_u32(pid)).unwrap().cpu_usage() / sys.cpus().len() as f32;
-
-                let memory_usage = proc.memory();
-                let disk_usage = proc.disk_usage();
-                let start_time = proc.start_time();
-                MonitorReport {
-                    memory_usage: Some(memory_usage),
-                    disk_usage: Some(disk_usage.into()),
-                    cpu_usage: Some(cpu_usage),
-                    start_time: Some(start_time),
-                }
-            } else {
-                MonitorReport::default()
+        let pid = match self.server_pid().await {
+            Some(pid) => pid,
+            None => return MonitorReport::default(),
+        };
+        // usage is for the whole tree, forge and wrapper scripts run the server in children
+        let pids = match process_tree.as_ref() {
+            Some(tree) if tree.root() == pid => tree.pids(&mut sys),
+            _ => {
+                sys.refresh_process(Pid::from_u32(pid));
+                vec![Pid::from_u32(pid)]
             }
-        } else {
-            MonitorReport::default()
+        };
+        drop(process_tree);
+        let proc = match sys.process(Pid::from_u32(pid)) {
+            Some(proc) => proc,
+            None => return MonitorReport::default(),
+        };
+        let start_time = proc.start_time();
+        let mut disk_usage = proc.disk_usage();
+        let mut cpu_usage = proc.cpu_usage();
+        let mut memory_usage = proc.memory();
+        for child in pids
+            .iter()
+            .filter(|child| child.as_u32() != pid)
+            .filter_map(|child| sys.process(*child))
+        {
+            let child_disk_usage = child.disk_usage();
+            disk_usage.total_read_bytes += child_disk_usage.total_read_bytes;
+            disk_usage.read_bytes += child_disk_usage.read_bytes;
+            disk_usage.total_written_bytes += child_disk_usage.total_written_bytes;
+            disk_usage.written_bytes += child_disk_usage.written_bytes;
+            cpu_usage += child.cpu_usage();
+            memory_usage += child.memory();
+        }
+        let cpu_usage = cpu_usage / sys.cpus().len() as f32;
+
+        // reports are asked for from several places, so rates come from the
+        // running totals rather than from what was read since the last refresh
+        let sample = DiskSample {
+            at: Instant::now(),
+            total_read_bytes: disk_usage.total_read_bytes,
+            total_written_bytes: disk_usage.total_written_bytes,
+        };
+        let rates = self
+            .last_disk_sample
+            .lock()
+            .await
+            .replace(sample)
+            .and_then(|previous| sample.rates_since(&previous));
+        MonitorReport {
+            memory_usage: Some(memory_usage),
+            disk_usage: Some(disk_usage.into()),
+            cpu_usage: Some(cpu_usage),
+            start_time: Some(start_time),
+            disk_read_rate: rates.map(|(read, _)| read),
+            disk_write_rate: rates.map(|(_, written)| written),
+            open_files: count_open_files(pid),
         }
     }
 }
+
+#[cfg(target_os = "linux")]
+fn count_open_files(pid: u32) -> Option<u64> {
+    std::fs::read_dir(format!("/proc/{pid}/fd"))
+        .ok()
+        .map(|entries| entries.count() as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn count_open_files(_pid: u32) -> Option<u64> {
+    None
+}
@@ -0,0 +1,463 @@
+//! Checks installed mods and plugins against a Minecraft version before upgrading to it.
+//!
+//! Fabric mods declare the Minecraft versions they work with in `fabric.mod.json`,
+//! Forge mods in `META-INF/mods.toml` and plugins the oldest API they need in `plugin.yml`.
+//! Addons that declare nothing are reported as unknown rather than blocking the upgrade.
+
+use std::cmp::Ordering;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ts_rs::TS;
+
+use crate::error::Error;
+use crate::traits::t_configurable::TConfigurable;
+
+use super::MinecraftInstance;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum AddonKind {
+    Mod,
+    Plugin,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum Compatibility {
+    Compatible,
+    /// The addon requires Minecraft versions the target version isn't part of
+    Incompatible {
+        requirement: String,
+    },
+    /// The addon doesn't say which versions it works with, or says it in a way we can't read
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct AddonCompatibility {
+    /// Relative to the instance's directory
+    pub file: String,
+    pub kind: AddonKind,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub compatibility: Compatibility,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct UpgradeReport {
+    pub current_version: String,
+    pub target_version: String,
+    pub addons: Vec<AddonCompatibility>,
+    /// How many addons are incompatible with the target version
+    pub blockers: u32,
+    /// Sent back to confirm the upgrade, it changes whenever the report would
+    pub report_id: String,
+}
+
+/// `1.19.2` as `[1, 19, 2]`, pre-releases and snapshots aren't understood
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .split(['-', '+'])
+        .next()?
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+fn compare_versions(a: &[u64], b: &[u64]) -> Ordering {
+    for i in 0..a.len().max(b.len()) {
+        match a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0)) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+    Ordering::Equal
+}
+
+/// A single Fabric version comparator, like `>=1.19`, `~1.19.2` or `1.20.x`
+fn fabric_comparator_matches(comparator: &str, version: &[u64]) -> Option<bool> {
+    if comparator == "*" {
+        return Some(true);
+    }
+    let (op, bound) = [">=", "<=", ">", "<", "=", "~", "^"]
+        .iter()
+        .find_map(|op| comparator.strip_prefix(op).map(|bound| (*op, bound)))
+        .unwrap_or(("", comparator));
+    let wildcard = bound
+        .split('.')
+        .position(|part| matches!(part, "x" | "X" | "*"));
+    let bound_str = match wildcard {
+        Some(position) => bound
+            .split('.')
+            .take(position)
+            .collect::<Vec<_>>()
+            .join("."),
+        None => bound.to_string(),
+    };
+    let bound = if bound_str.is_empty() {
+        Vec::new()
+    } else {
+        parse_version(&bound_str)?
+    };
+    if wildcard.is_some() || op == "~" || op == "^" {
+        // everything sharing the fixed part, and at least the bound for ~ and ^
+        let fixed = match op {
+            "~" if wildcard.is_none() => bound.len().min(2),
+            "^" if wildcard.is_none() => 1,
+            _ => bound.len(),
+        };
+        let prefix_matches = version.len() >= fixed && version[..fixed] == bound[..fixed];
+        return Some(prefix_matches && compare_versions(version, &bound) != Ordering::Less);
+    }
+    let ordering = compare_versions(version, &bound);
+    Some(match op {
+        ">=" => ordering != Ordering::Less,
+        "<=" => ordering != Ordering::Greater,
+        ">" => ordering == Ordering::Greater,
+        "<" => ordering == Ordering::Less,
+        _ => ordering == Ordering::Equal,
+    })
+}
+
+/// A Fabric version predicate, every space separated comparator has to match
+fn fabric_predicate_matches(predicate: &str, version: &[u64]) -> Option<bool> {
+    let mut matches = true;
+    for comparator in predicate.split_whitespace() {
+        matches &= fabric_comparator_matches(comparator, version)?;
+    }
+    Some(matches)
+}
+
+/// A Maven version range as used by Forge, like `[1.19,1.20)` or `[1.18.2]`
+fn maven_range_matches(range: &str, version: &[u64]) -> Option<bool> {
+    let range = range.trim();
+    if !range.starts_with(['[', '(']) {
+        // a bare version is only a recommendation
+        return Some(true);
+    }
+    let mut rest = range;
+    while !rest.is_empty() {
+        let end = rest.find([']', ')'])?;
+        let (inclusive_start, inclusive_end) = (rest.starts_with('['), &rest[end..=end] == "]");
+        let inner = &rest[1..end];
+        let (lower, upper) = match inner.split_once(',') {
+            Some((lower, upper)) => (lower.trim(), upper.trim()),
+            None => (inner.trim(), inner.trim()),
+        };
+        let above_lower = lower.is_empty()
+            || match compare_versions(version, &parse_version(lower)?) {
+                Ordering::Greater => true,
+                Ordering::Equal => inclusive_start,
+                Ordering::Less => false,
+            };
+        let below_upper = upper.is_empty()
+            || match compare_versions(version, &parse_version(upper)?) {
+                Ordering::Less => true,
+                Ordering::Equal => inclusive_end,
+                Ordering::Greater => false,
+            };
+        if above_lower && below_upper {
+            return Some(true);
+        }
+        rest = rest[end + 1..].trim_start_matches([',', ' ']);
+    }
+    Some(false)
+}
+
+fn compatibility(requirement: String, matches: Option<bool>) -> Compatibility {
+    match matches {
+        Some(true) => Compatibility::Compatible,
+        Some(false) => Compatibility::Incompatible { requirement },
+        None => Compatibility::Unknown,
+    }
+}
+
+#[derive(Deserialize)]
+struct FabricModJson {
+    id: Option<String>,
+    name: Option<String>,
+    version: Option<String>,
+    #[serde(default)]
+    depends: serde_json::Map<String, serde_json::Value>,
+}
+
+fn check_fabric_mod(content: &str, version: Option<&[u64]>) -> AddonCompatibility {
+    let manifest: Option<FabricModJson> = serde_json::from_str(content).ok();
+    let requirement = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.depends.get("minecraft"));
+    let compatibility = match (requirement, version) {
+        (Some(requirement), Some(version)) => {
+            // an array means any of the predicates
+            let predicates: Option<Vec<&str>> = match requirement {
+                serde_json::Value::String(predicate) => Some(vec![predicate.as_str()]),
+                serde_json::Value::Array(predicates) => predicates
+                    .iter()
+                    .map(|predicate| predicate.as_str())
+                    .collect(),
+                _ => None,
+            };
+            let matches = predicates.and_then(|predicates| {
+                predicates
+                    .iter()
+                    .map(|predicate| fabric_predicate_matches(predicate, version))
+                    .collect::<Option<Vec<bool>>>()
+                    .map(|matches| matches.into_iter().any(|matches| matches))
+            });
+            let requirement = match requirement {
+                serde_json::Value::String(predicate) => predicate.clone(),
+                other => other.to_string(),
+            };
+            compatibility(requirement, matches)
+        }
+        _ => Compatibility::Unknown,
+    };
+    let manifest = manifest.unwrap_or(FabricModJson {
+        id: None,
+        name: None,
+        version: None,
+        depends: Default::default(),
+    });
+    AddonCompatibility {
+        file: String::new(),
+        kind: AddonKind::Mod,
+        id: manifest.id,
+        name: manifest.name,
+        version: manifest.version,
+        compatibility,
+    }
+}
+
+fn check_forge_mod(content: &str, version: Option<&[u64]>) -> AddonCompatibility {
+    let manifest: Option<toml::Value> = content.parse().ok();
+    let first_mod = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get("mods")?.as_array()?.first().cloned());
+    let field = |key: &str| {
+        first_mod
+            .as_ref()
+            .and_then(|first_mod| first_mod.get(key)?.as_str())
+            .map(|value| value.to_string())
+    };
+    let id = field("modId");
+    let requirement = manifest
+        .as_ref()
+        .and_then(|manifest| {
+            manifest
+                .get("dependencies")?
+                .get(id.as_deref()?)?
+                .as_array()?
+                .iter()
+                .find(|dependency| {
+                    dependency.get("modId").and_then(|id| id.as_str()) == Some("minecraft")
+                })?
+                .get("versionRange")?
+                .as_str()
+        })
+        .map(|requirement| requirement.to_string());
+    let compatibility = match (requirement, version) {
+        (Some(requirement), Some(version)) => {
+            let matches = maven_range_matches(&requirement, version);
+            compatibility(requirement, matches)
+        }
+        _ => Compatibility::Unknown,
+    };
+    AddonCompatibility {
+        file: String::new(),
+        kind: AddonKind::Mod,
+        name: field("displayName"),
+        // usually `${file.jarVersion}`, filled in by Forge at runtime
+        version: field("version").filter(|version| !version.starts_with("${")),
+        id,
+        compatibility,
+    }
+}
+
+/// A top level scalar of `plugin.yml`, read as written since YAML would turn `1.20` into `1.2`
+fn plugin_yml_value(content: &str, key: &str) -> Option<String> {
+    let value = content
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))?;
+    let value = value.split(" #").next()?.trim().trim_matches(['\'', '"']);
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn check_plugin(content: &str, version: Option<&[u64]>) -> AddonCompatibility {
+    let name = plugin_yml_value(content, "name");
+    let api_version = plugin_yml_value(content, "api-version");
+    // plugins run on any version from the API version they were built against onwards
+    let compatibility = match (api_version, version) {
+        (Some(api_version), Some(version)) => {
+            let matches = parse_version(&api_version)
+                .map(|api_version| compare_versions(version, &api_version) != Ordering::Less);
+            compatibility(format!(">={}", api_version), matches)
+        }
+        _ => Compatibility::Unknown,
+    };
+    AddonCompatibility {
+        file: String::new(),
+        kind: AddonKind::Plugin,
+        id: name.clone(),
+        name,
+        version: plugin_yml_value(content, "version"),
+        compatibility,
+    }
+}
+
+fn read_zip_entry(archive: &mut zip::ZipArchive<std::fs::File>, name: &str) -> Option<String> {
+    let mut entry = archive.by_name(name).ok()?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+fn check_jar(path: &Path, kind: AddonKind, version: Option<&[u64]>) -> AddonCompatibility {
+    let unknown = AddonCompatibility {
+        file: String::new(),
+        kind,
+        id: None,
+        name: None,
+        version: None,
+        compatibility: Compatibility::Unknown,
+    };
+    let mut archive = match std::fs::File::open(path)
+        .ok()
+        .and_then(|file| zip::ZipArchive::new(file).ok())
+    {
+        Some(archive) => archive,
+        None => return unknown,
+    };
+    match kind {
+        AddonKind::Mod => {
+            if let Some(content) = read_zip_entry(&mut archive, "fabric.mod.json") {
+                check_fabric_mod(&content, version)
+            } else if let Some(content) = read_zip_entry(&mut archive, "META-INF/mods.toml") {
+                check_forge_mod(&content, version)
+            } else {
+                unknown
+            }
+        }
+        AddonKind::Plugin => {
+            match read_zip_entry(&mut archive, "paper-plugin.yml")
+                .or_else(|| read_zip_entry(&mut archive, "plugin.yml"))
+            {
+                Some(content) => check_plugin(&content, version),
+                None => unknown,
+            }
+        }
+    }
+}
+
+fn check_addons(path_to_instance: &Path, target_version: &str) -> Vec<AddonCompatibility> {
+    let version = parse_version(target_version);
+    let mut ret = Vec::new();
+    for (dir, kind) in [("mods", AddonKind::Mod), ("plugins", AddonKind::Plugin)] {
+        let mut jars: Vec<PathBuf> = match std::fs::read_dir(path_to_instance.join(dir)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && path.extension().map_or(false, |ext| ext == "jar"))
+                .collect(),
+            Err(_) => continue,
+        };
+        jars.sort();
+        for jar in jars {
+            let mut addon = check_jar(&jar, kind, version.as_deref());
+            addon.file = jar
+                .strip_prefix(path_to_instance)
+                .unwrap_or(&jar)
+                .display()
+                .to_string();
+            ret.push(addon);
+        }
+    }
+    ret
+}
+
+impl MinecraftInstance {
+    /// Which installed mods and plugins are known to work with `target_version`
+    pub async fn upgrade_report(&self, target_version: &str) -> Result<UpgradeReport, Error> {
+        let path_to_instance = self.path_to_instance.clone();
+        let target = target_version.to_string();
+        let addons = tokio::task::spawn_blocking(move || check_addons(&path_to_instance, &target))
+            .await
+            .context("Compatibility check panicked")?;
+        let blockers = addons
+            .iter()
+            .filter(|addon| matches!(addon.compatibility, Compatibility::Incompatible { .. }))
+            .count() as u32;
+        let current_version = self.version().await;
+        let report_id = hex::encode(Sha256::digest(
+            serde_json::to_vec(&(&current_version, target_version, &addons))
+                .context("Failed to serialize compatibility report")?,
+        ));
+        Ok(UpgradeReport {
+            current_version,
+            target_version: target_version.to_string(),
+            addons,
+            blockers,
+            report_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fabric_predicates() {
+        let version = parse_version("1.19.2").unwrap();
+        assert_eq!(fabric_predicate_matches(">=1.19", &version), Some(true));
+        assert_eq!(
+            fabric_predicate_matches(">=1.19 <1.19.2", &version),
+            Some(false)
+        );
+        assert_eq!(fabric_predicate_matches("~1.19", &version), Some(true));
+        assert_eq!(fabric_predicate_matches("~1.20", &version), Some(false));
+        assert_eq!(fabric_predicate_matches("1.19.x", &version), Some(true));
+        assert_eq!(fabric_predicate_matches("1.18.x", &version), Some(false));
+        assert_eq!(fabric_predicate_matches("*", &version), Some(true));
+        assert_eq!(fabric_predicate_matches("1.19.2", &version), Some(true));
+        assert_eq!(fabric_predicate_matches(">=23w13a", &version), None);
+    }
+
+    #[test]
+    fn test_maven_ranges() {
+        let version = parse_version("1.19.2").unwrap();
+        assert_eq!(maven_range_matches("[1.19,1.20)", &version), Some(true));
+        assert_eq!(maven_range_matches("[1.19.3,)", &version), Some(false));
+        assert_eq!(maven_range_matches("[1.18.2]", &version), Some(false));
+        assert_eq!(
+            maven_range_matches("[1.18.2],[1.19.2]", &version),
+            Some(true)
+        );
+        assert_eq!(maven_range_matches("(,1.19.2)", &version), Some(false));
+    }
+
+    #[test]
+    fn test_plugin_api_version() {
+        let version = parse_version("1.20.1").unwrap();
+        let plugin = check_plugin(
+            "name: Test\nversion: 1.10\napi-version: 1.13 # oldest supported\n",
+            Some(&version),
+        );
+        assert_eq!(plugin.compatibility, Compatibility::Compatible);
+        assert_eq!(plugin.version.as_deref(), Some("1.10"));
+        let plugin = check_plugin(
+            "name: Test\napi-version: 1.20\n",
+            Some(&parse_version("1.19.4").unwrap()),
+        );
+        assert!(matches!(
+            plugin.compatibility,
+            Compatibility::Incompatible { .. }
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+//! Upgrade runs, which start the server with `--forceUpgrade` so every chunk of the world is
+//! converted to the server's version up front instead of as players come across it.
+//!
+//! Converting a large world can take an hour, so the run has no startup timeout and isn't
+//! retried or rolled back by [`crate::startup_watchdog`]. Progress the server prints is reported
+//! as a progression event, which ends once the server is ready. Later starts are normal ones.
+
+use color_eyre::eyre::eyre;
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::SubscriptionFilter;
+use crate::events::{
+    CausedBy, Event, EventInner, EventType, InstanceEvent, InstanceEventInner,
+    ProgressionStartValue,
+};
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_server::{State, TServer};
+use crate::types::Snowflake;
+
+use super::MinecraftInstance;
+
+pub(super) const FORCE_UPGRADE_ARG: &str = "--forceUpgrade";
+
+lazy_static! {
+    /// `45% completed (1234 / 2741 chunks)...`
+    static ref PROGRESS: Regex =
+        Regex::new(r"(?P<percent>\d+)% completed \((?P<done>\d+) / (?P<total>\d+) chunks\)")
+            .unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Progress {
+    percent: f64,
+    done: u64,
+    total: u64,
+}
+
+fn parse_progress(line: &str) -> Option<Progress> {
+    let captures = PROGRESS.captures(line).ok()??;
+    Some(Progress {
+        percent: captures.name("percent")?.as_str().parse().ok()?,
+        done: captures.name("done")?.as_str().parse().ok()?,
+        total: captures.name("total")?.as_str().parse().ok()?,
+    })
+}
+
+impl MinecraftInstance {
+    /// Starts the server for an upgrade run, returning the id of the job following it
+    pub async fn start_world_upgrade(&mut self, caused_by: CausedBy) -> Result<Snowflake, Error> {
+        if self.state().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Stop the server before upgrading its world"),
+            });
+        }
+        let mut world_upgrade = self.world_upgrade.lock().await;
+        if world_upgrade.is_some() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The world is already being upgraded"),
+            });
+        }
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!("Upgrading the world of {}", self.name().await),
+            Some(100.0),
+            Some(ProgressionStartValue::WorldUpgrade {
+                instance_uuid: self.uuid.clone(),
+            }),
+            caused_by.clone(),
+        );
+        let job_id = event_id.snowflake();
+        *world_upgrade = Some(job_id);
+        drop(world_upgrade);
+
+        let mut events = self
+            .event_broadcaster
+            .subscribe_filtered(SubscriptionFilter {
+                event_types: Some(vec![EventType::InstanceEvent]),
+                instance_uuids: Some(vec![self.uuid.clone()]),
+                ..Default::default()
+            });
+        self.event_broadcaster.send(progression_start_event);
+        if let Err(e) = self.start(caused_by, false).await {
+            self.world_upgrade.lock().await.take();
+            self.event_broadcaster
+                .send(Event::new_progression_event_end(
+                    event_id,
+                    false,
+                    Some(&format!("Failed to start the server: {e}")),
+                    None,
+                ));
+            return Err(e);
+        }
+
+        let __self = self.clone();
+        tokio::spawn(async move {
+            let mut percent = 0.0;
+            let (success, message) = loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => {
+                        break (false, "Lodestone is shutting down".to_string())
+                    }
+                };
+                let instance_event_inner = match event.event_inner {
+                    EventInner::InstanceEvent(InstanceEvent {
+                        instance_event_inner,
+                        ..
+                    }) => instance_event_inner,
+                    _ => continue,
+                };
+                match instance_event_inner {
+                    InstanceEventInner::InstanceOutput { message, .. } => {
+                        let progress = match parse_progress(&message) {
+                            Some(progress) => progress,
+                            None => continue,
+                        };
+                        let delta = progress.percent - percent;
+                        percent = progress.percent;
+                        __self
+                            .event_broadcaster
+                            .send(Event::new_progression_event_update(
+                                &event_id,
+                                format!("Upgraded {} of {} chunks", progress.done, progress.total),
+                                delta,
+                            ));
+                    }
+                    InstanceEventInner::StateTransition { to: State::Running } => {
+                        break (true, "World upgraded".to_string())
+                    }
+                    InstanceEventInner::StateTransition {
+                        to: State::Stopping | State::Stopped | State::Error,
+                    } => {
+                        break (
+                            false,
+                            "The server stopped before the upgrade finished".to_string(),
+                        )
+                    }
+                    _ => {}
+                }
+            };
+            __self.world_upgrade.lock().await.take();
+            __self
+                .event_broadcaster
+                .send(Event::new_progression_event_end(
+                    event_id,
+                    success,
+                    Some(&message),
+                    None,
+                ));
+        });
+        Ok(job_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_progress() {
+        assert_eq!(
+            parse_progress(
+                "[12:00:00] [Server thread/INFO]: 45% completed (1234 / 2741 chunks)..."
+            ),
+            Some(Progress {
+                percent: 45.0,
+                done: 1234,
+                total: 2741,
+            })
+        );
+        assert_eq!(
+            parse_progress("[12:00:00] [Server thread/INFO]: Forcing world upgrade!"),
+            None
+        );
+    }
+}
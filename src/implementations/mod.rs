@@ -1,2 +1,4 @@
+#[cfg(feature = "fake-instance")]
+pub mod fake;
 pub mod generic;
 pub mod minecraft;
@@ -0,0 +1,390 @@
+//! Setting up Minecraft instances in the background, in a way that survives the core going down.
+//!
+//! Before anything is written to disk, the intent to create the instance is recorded in the
+//! database, and the record is removed once the instance is ready or cleaned up after failing.
+//! A record still there on startup means the core died mid-creation. The half-created directory
+//! is then wiped and the setup runs again from the start, unless it was already retried or the
+//! user who asked for it is gone, in which case the directory is removed. Either way an event
+//! says what happened.
+
+use std::path::PathBuf;
+
+use color_eyre::eyre::Context;
+use sqlx::{sqlite::SqlitePool, Row};
+use tracing::{error, info, warn};
+
+use crate::{
+    auth::{instance_access::InstanceAccess, user_id::UserId},
+    error::Error,
+    events::{
+        CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, ProgressionEndValue,
+        ProgressionStartValue,
+    },
+    i18n::{LocalizedMessage, MessageId},
+    implementations::minecraft::{self, SetupConfig},
+    storage::StorageLocation,
+    traits::{t_configurable::GameType, TInstance},
+    types::{DotLodestoneConfig, InstanceUuid, Snowflake},
+    AppState,
+};
+
+/// Interrupted creations are resumed this many times before giving up on them
+const MAX_ATTEMPTS: i64 = 2;
+
+#[derive(Clone, Debug)]
+pub struct PendingCreation {
+    pub instance_uuid: InstanceUuid,
+    pub setup_path: PathBuf,
+    pub setup_config: SetupConfig,
+    pub dot_lodestone_config: DotLodestoneConfig,
+    pub requester_id: UserId,
+    pub attempts: i64,
+}
+
+pub async fn record_creation(pool: &SqlitePool, creation: &PendingCreation) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO InstanceCreations (instance_uuid, setup_path, setup_config, \
+         dot_lodestone_config, requester_id, started_at, attempts) VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&creation.instance_uuid)
+    .bind(creation.setup_path.to_string_lossy().into_owned())
+    .bind(serde_json::to_string(&creation.setup_config).context("Failed to serialize setup")?)
+    .bind(
+        serde_json::to_string(&creation.dot_lodestone_config)
+            .context("Failed to serialize .lodestone_config")?,
+    )
+    .bind(&creation.requester_id)
+    .bind(chrono::Utc::now().timestamp())
+    .bind(creation.attempts)
+    .execute(pool)
+    .await
+    .context("Failed to record instance creation")?;
+    Ok(())
+}
+
+pub async fn forget_creation(pool: &SqlitePool, uuid: &InstanceUuid) {
+    if let Err(e) = sqlx::query("DELETE FROM InstanceCreations WHERE instance_uuid = ?")
+        .bind(uuid)
+        .execute(pool)
+        .await
+    {
+        warn!("Failed to clear the creation record of instance {uuid}: {e}");
+    }
+}
+
+async fn count_attempt(pool: &SqlitePool, uuid: &InstanceUuid) -> Result<(), Error> {
+    sqlx::query("UPDATE InstanceCreations SET attempts = attempts + 1 WHERE instance_uuid = ?")
+        .bind(uuid)
+        .execute(pool)
+        .await
+        .context("Failed to record instance creation")?;
+    Ok(())
+}
+
+/// Creations that never finished, unreadable records are dropped
+pub async fn pending_creations(pool: &SqlitePool) -> Result<Vec<PendingCreation>, Error> {
+    let rows = sqlx::query("SELECT * FROM InstanceCreations")
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch instance creations")?;
+    let mut pending = Vec::new();
+    for row in rows {
+        let parsed = (|| -> Result<PendingCreation, Error> {
+            let setup_config: String = row.try_get("setup_config").context("Invalid record")?;
+            let dot_lodestone_config: String = row
+                .try_get("dot_lodestone_config")
+                .context("Invalid record")?;
+            Ok(PendingCreation {
+                instance_uuid: row.try_get("instance_uuid").context("Invalid record")?,
+                setup_path: PathBuf::from(
+                    row.try_get::<String, _>("setup_path")
+                        .context("Invalid record")?,
+                ),
+                setup_config: serde_json::from_str(&setup_config).context("Invalid record")?,
+                dot_lodestone_config: serde_json::from_str(&dot_lodestone_config)
+                    .context("Invalid record")?,
+                requester_id: row.try_get("requester_id").context("Invalid record")?,
+                attempts: row.try_get("attempts").context("Invalid record")?,
+            })
+        })();
+        match parsed {
+            Ok(creation) => pending.push(creation),
+            Err(e) => {
+                warn!("Dropping unreadable instance creation record: {e}");
+                if let Ok(uuid) = row.try_get::<InstanceUuid, _>("instance_uuid") {
+                    forget_creation(pool, &uuid).await;
+                }
+            }
+        }
+    }
+    Ok(pending)
+}
+
+/// Picks a uuid and directory for a new instance, records the creation and writes the
+/// instance's `.lodestone_config`, after which [`create_minecraft_instance`] can run the setup
+pub async fn prepare_creation(
+    state: &AppState,
+    setup_config: SetupConfig,
+    game_type: GameType,
+    location: &StorageLocation,
+    requester_id: UserId,
+) -> Result<PendingCreation, Error> {
+    let mut instance_uuid = InstanceUuid::default();
+    for uuid in state.instances.lock().await.keys() {
+        if let Some(uuid) = uuid.as_ref().get(0..8) {
+            if uuid == &instance_uuid.no_prefix()[0..8] {
+                instance_uuid = InstanceUuid::default();
+            }
+        }
+    }
+    let setup_path = location.path.join(format!(
+        "{}-{}",
+        setup_config.name,
+        &instance_uuid.no_prefix()[0..8]
+    ));
+    let dot_lodestone_config = DotLodestoneConfig::new(instance_uuid.clone(), game_type)
+        .with_storage_location(&location.id);
+    let creation = PendingCreation {
+        instance_uuid: instance_uuid.clone(),
+        setup_path: setup_path.clone(),
+        setup_config,
+        dot_lodestone_config: dot_lodestone_config.clone(),
+        requester_id,
+        attempts: 1,
+    };
+    // without the record the creation can't be recovered, but it can still go ahead
+    if let Err(e) = record_creation(&state.sqlite_pool, &creation).await {
+        warn!(
+            "Instance {instance_uuid} won't be recovered if the core stops while it is set up: {e}"
+        );
+    }
+
+    let written = async {
+        tokio::fs::create_dir_all(&setup_path)
+            .await
+            .context("Failed to create instance directory")?;
+
+        // write dot lodestone config
+
+        tokio::fs::write(
+            setup_path.join(".lodestone_config"),
+            serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+        )
+        .await
+        .context("Failed to write .lodestone_config file")
+    }
+    .await;
+    if let Err(e) = written {
+        forget_creation(&state.sqlite_pool, &instance_uuid).await;
+        return Err(e.into());
+    }
+    Ok(creation)
+}
+
+/// Runs the setup, registering the instance once it succeeds and removing its directory if it
+/// doesn't
+pub async fn create_minecraft_instance(
+    state: AppState,
+    creation: PendingCreation,
+    caused_by: CausedBy,
+) {
+    let PendingCreation {
+        instance_uuid: uuid,
+        setup_path,
+        setup_config,
+        dot_lodestone_config,
+        requester_id,
+        ..
+    } = creation;
+    let event_broadcaster = state.event_broadcaster.clone();
+    let instance_name = setup_config.name.clone();
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        format!("Setting up Minecraft server {instance_name}"),
+        Some(10.0),
+        Some(ProgressionStartValue::InstanceCreation {
+            instance_uuid: uuid.clone(),
+            instance_name: instance_name.clone(),
+            port: setup_config.port,
+            flavour: setup_config.flavour.to_string(),
+            game_type: "minecraft".to_string(),
+        }),
+        caused_by,
+    );
+    event_broadcaster.send(
+        progression_start_event.with_localized_message(
+            LocalizedMessage::new(MessageId::InstanceCreationStarted)
+                .with_param("game", "Minecraft")
+                .with_param("instance_name", &instance_name),
+        ),
+    );
+    let minecraft_instance = match minecraft::MinecraftInstance::new(
+        setup_config.clone(),
+        dot_lodestone_config,
+        setup_path.clone(),
+        &event_id,
+        state.event_broadcaster.clone(),
+        state.macro_executor.clone(),
+    )
+    .await
+    {
+        Ok(v) => {
+            event_broadcaster.send(
+                Event::new_progression_event_end(
+                    event_id,
+                    true,
+                    Some("Instance created successfully"),
+                    Some(ProgressionEndValue::InstanceCreation(
+                        v.get_instance_info().await,
+                    )),
+                )
+                .with_localized_message(LocalizedMessage::new(
+                    MessageId::InstanceCreationSucceeded,
+                )),
+            );
+            v
+        }
+        Err(e) => {
+            event_broadcaster.send(
+                Event::new_progression_event_end(
+                    event_id,
+                    false,
+                    Some(&format!("Instance creation failed: {e}")),
+                    None,
+                )
+                .with_localized_message(
+                    LocalizedMessage::new(MessageId::InstanceCreationFailed)
+                        .with_param("error", &e),
+                ),
+            );
+            if let Err(e) = crate::util::fs::remove_dir_all(&setup_path).await {
+                error!("Failed to remove directory after instance creation failed: {e}");
+            }
+            forget_creation(&state.sqlite_pool, &uuid).await;
+            return;
+        }
+    };
+    state.port_manager.lock().await.add_port(setup_config.port);
+    // the permissions as they are now, they may have changed while the instance was set up
+    let requester = state.users_manager.read().await.get_user(&requester_id);
+    if let Some(requester) = requester {
+        let mut perm = requester.permissions;
+        perm.can_start_instance.insert(uuid.clone());
+        perm.can_stop_instance.insert(uuid.clone());
+        perm.can_view_instance.insert(uuid.clone());
+        perm.can_read_instance_file.insert(uuid.clone());
+        perm.can_write_instance_file.insert(uuid.clone());
+        // ignore errors since we don't care if the permissions update fails
+        let _ = state
+            .users_manager
+            .write()
+            .await
+            .update_permissions(&requester.uid, perm, CausedBy::System)
+            .await
+            .map_err(|e| {
+                error!("Failed to update permissions: {:?}", e);
+                e
+            });
+    }
+    let mut instances = state.instances.lock().await;
+    if let Err(e) = state
+        .global_settings
+        .lock()
+        .await
+        .set_instance_access(uuid.clone(), Some(InstanceAccess::new(requester_id)))
+        .await
+    {
+        error!("Failed to record the owner of instance {uuid}: {e}");
+    }
+    instances.insert(uuid.clone(), minecraft_instance.into());
+    drop(instances);
+    forget_creation(&state.sqlite_pool, &uuid).await;
+}
+
+/// Wipes what an interrupted creation left behind, leaving only `.lodestone_config`
+async fn reset_directory(creation: &PendingCreation) -> Result<(), Error> {
+    if tokio::fs::metadata(&creation.setup_path).await.is_ok() {
+        crate::util::fs::remove_dir_all(&creation.setup_path).await?;
+    }
+    tokio::fs::create_dir_all(&creation.setup_path)
+        .await
+        .context("Failed to create instance directory")?;
+    tokio::fs::write(
+        creation.setup_path.join(".lodestone_config"),
+        serde_json::to_string_pretty(&creation.dot_lodestone_config)
+            .context("Failed to serialize .lodestone_config")?,
+    )
+    .await
+    .context("Failed to write .lodestone_config file")?;
+    Ok(())
+}
+
+fn report(state: &AppState, creation: &PendingCreation, message: String) {
+    state.event_broadcaster.send(Event {
+        event_inner: EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid: creation.instance_uuid.clone(),
+            instance_name: creation.setup_config.name.clone(),
+            instance_event_inner: InstanceEventInner::InstanceWarning {
+                message: message.clone(),
+            },
+        }),
+        details: message,
+        snowflake: Snowflake::default(),
+        caused_by: CausedBy::System,
+    });
+}
+
+/// Resumes or cleans up the creations the core was in the middle of when it went down
+pub async fn recover_creations(state: AppState, pending: Vec<PendingCreation>) {
+    for creation in pending {
+        let name = creation.setup_config.name.clone();
+        let requester = state
+            .users_manager
+            .read()
+            .await
+            .get_user(&creation.requester_id);
+        let resumable = creation.attempts < MAX_ATTEMPTS && requester.is_some();
+        if resumable {
+            let reset = match reset_directory(&creation).await {
+                Ok(_) => count_attempt(&state.sqlite_pool, &creation.instance_uuid).await,
+                Err(e) => Err(e),
+            };
+            match (reset, requester) {
+                (Ok(_), Some(requester)) => {
+                    info!("Resuming the interrupted creation of instance {name}");
+                    report(
+                        &state,
+                        &creation,
+                        format!("Setting up {name} was interrupted, it is starting over"),
+                    );
+                    let caused_by = CausedBy::User {
+                        user_id: requester.uid,
+                        user_name: requester.username,
+                        impersonated_by: requester.impersonated_by,
+                    };
+                    tokio::spawn(create_minecraft_instance(
+                        state.clone(),
+                        creation,
+                        caused_by,
+                    ));
+                    continue;
+                }
+                (Err(e), _) => {
+                    warn!("Failed to resume the creation of instance {name}: {e}");
+                }
+                _ => {}
+            }
+        }
+        info!("Cleaning up the interrupted creation of instance {name}");
+        if let Err(e) = crate::util::fs::remove_dir_all(&creation.setup_path).await {
+            if tokio::fs::metadata(&creation.setup_path).await.is_ok() {
+                error!("Failed to remove the directory of instance {name}: {e}");
+            }
+        }
+        forget_creation(&state.sqlite_pool, &creation.instance_uuid).await;
+        report(
+            &state,
+            &creation,
+            format!("Setting up {name} was interrupted and could not be resumed, its files were removed"),
+        );
+    }
+}
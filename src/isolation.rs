@@ -0,0 +1,256 @@
+//! Running every instance as its own unprivileged system user, on Linux.
+//!
+//! When turned on, each instance gets a system user named after its uuid. Before the server
+//! starts, its directory is handed over to that user and closed to everyone else, and the
+//! process drops to that user, so a compromised server or a malicious plugin can't read the
+//! files of other instances or of the core.
+//!
+//! The core has to run as root, or with the capabilities to create users, change the owner of
+//! files and switch users. The java runtimes under the lodestone path must stay reachable by
+//! other users, which they aren't if the lodestone path is in root's home directory.
+//!
+//! The switch is kept in the global settings, and mirrored here since instances start their
+//! process without access to them.
+
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+use crate::{
+    error::{Error, ErrorKind},
+    types::InstanceUuid,
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// CAP_CHOWN, CAP_DAC_OVERRIDE, CAP_FOWNER, CAP_SETGID and CAP_SETUID
+const REQUIRED_CAPABILITIES: u64 = (1 << 0) | (1 << 1) | (1 << 3) | (1 << 6) | (1 << 7);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Whether the effective capabilities in a `/proc/<pid>/status` file are enough, root has them
+/// all
+fn has_capabilities(status: &str) -> bool {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .map_or(false, |caps| {
+            caps & REQUIRED_CAPABILITIES == REQUIRED_CAPABILITIES
+        })
+}
+
+/// Whether instances can be isolated on this machine
+pub fn is_supported() -> bool {
+    cfg!(target_os = "linux")
+        && std::fs::read_to_string("/proc/self/status")
+            .map_or(false, |status| has_capabilities(&status))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstanceUser {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// The part of the uuid used is the one instance directories are named with, which is kept
+/// unique when instances are created
+pub fn user_name(uuid: &InstanceUuid) -> String {
+    let uuid = uuid.no_prefix();
+    format!("lodestone-{}", &uuid[..uuid.len().min(8)])
+}
+
+/// Uid and gid of `name` in the contents of `/etc/passwd`
+fn find_in_passwd(passwd: &str, name: &str) -> Option<(u32, u32)> {
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        let _password = fields.next()?;
+        Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+    })
+}
+
+async fn lookup_user(name: &str) -> Result<Option<InstanceUser>, Error> {
+    let passwd = tokio::fs::read_to_string("/etc/passwd")
+        .await
+        .context("Failed to read /etc/passwd")?;
+    Ok(
+        find_in_passwd(&passwd, name).map(|(uid, gid)| InstanceUser {
+            name: name.to_string(),
+            uid,
+            gid,
+        }),
+    )
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<(), Error> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .context(format!("Failed to run {program}"))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The user of the instance, created if it doesn't exist yet
+async fn ensure_user(uuid: &InstanceUuid) -> Result<InstanceUser, Error> {
+    let name = user_name(uuid);
+    if let Some(user) = lookup_user(&name).await? {
+        return Ok(user);
+    }
+    info!("Creating system user {name} for instance {uuid}");
+    run(
+        "useradd",
+        &[
+            "--system",
+            "--user-group",
+            "--no-create-home",
+            "--home-dir",
+            "/nonexistent",
+            "--shell",
+            "/usr/sbin/nologin",
+            &name,
+        ],
+    )
+    .await?;
+    lookup_user(&name)
+        .await?
+        .ok_or_else(|| eyre!("User {name} was created but can't be found").into())
+}
+
+/// Gives the instance directory to its user and closes it to everyone else.
+///
+/// Done before every start, since files the core writes into the directory belong to root
+async fn hand_over(path: &Path, user: &InstanceUser) -> Result<(), Error> {
+    run(
+        "chown",
+        &[
+            "-R",
+            &format!("{}:{}", user.uid, user.gid),
+            &path.to_string_lossy(),
+        ],
+    )
+    .await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700))
+            .await
+            .context(format!("Failed to restrict access to {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// The user the instance's server should run as, `None` unless isolation is on
+pub async fn prepare(uuid: &InstanceUuid, path: &Path) -> Result<Option<InstanceUser>, Error> {
+    if !is_enabled() {
+        return Ok(None);
+    }
+    if !is_supported() {
+        return Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "Instance isolation is on, but the core is not running as root or lacks the capabilities it needs"
+            ),
+        });
+    }
+    let user = ensure_user(uuid).await?;
+    hand_over(path, &user).await?;
+    Ok(Some(user))
+}
+
+/// Makes `command` run as `user`, with the instance directory as its home
+pub fn run_as<'a>(command: &'a mut Command, user: &InstanceUser, path: &Path) -> &'a mut Command {
+    #[cfg(unix)]
+    command.uid(user.uid).gid(user.gid);
+    command.env("HOME", path).env("USER", &user.name)
+}
+
+/// The same as `command`, but switching to `user` through `setpriv` first. For spawning in a
+/// pseudo terminal, which can't be told to switch users itself
+pub fn wrap_with_setpriv(command: &Command, user: &InstanceUser, path: &Path) -> Command {
+    let command = command.as_std();
+    let mut wrapped = Command::new("setpriv");
+    wrapped
+        .arg(format!("--reuid={}", user.uid))
+        .arg(format!("--regid={}", user.gid))
+        .arg("--clear-groups")
+        .arg("--")
+        .arg(command.get_program())
+        .args(command.get_args())
+        .env("HOME", path)
+        .env("USER", &user.name);
+    if let Some(dir) = command.get_current_dir() {
+        wrapped.current_dir(dir);
+    }
+    wrapped
+}
+
+/// Deletes the user of a removed instance, if it has one
+pub async fn remove_user(uuid: &InstanceUuid) {
+    if !cfg!(target_os = "linux") {
+        return;
+    }
+    let name = user_name(uuid);
+    match lookup_user(&name).await {
+        Ok(Some(_)) => {
+            if let Err(e) = run("userdel", &[&name]).await {
+                warn!("Failed to delete system user {name}: {e}");
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to look up system user {name}: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_capabilities() {
+        assert!(has_capabilities(
+            "Name:\tlodestone\nCapEff:\t000001ffffffffff\n"
+        ));
+        assert!(!has_capabilities(
+            "Name:\tlodestone\nCapEff:\t0000000000000000\n"
+        ));
+        // CAP_SETUID missing
+        assert!(!has_capabilities("CapEff:\t000000000000004b\n"));
+        assert!(has_capabilities("CapEff:\t00000000000000cb\n"));
+        assert!(!has_capabilities("Name:\tlodestone\n"));
+    }
+
+    #[test]
+    fn test_find_in_passwd() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+                      lodestone-1a2b3c4d:x:998:997::/nonexistent:/usr/sbin/nologin\n";
+        assert_eq!(find_in_passwd(passwd, "root"), Some((0, 0)));
+        assert_eq!(
+            find_in_passwd(passwd, "lodestone-1a2b3c4d"),
+            Some((998, 997))
+        );
+        assert_eq!(find_in_passwd(passwd, "lodestone"), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Long running operations, as seen through the progression events they emit.
+//!
+//! The id of a job is the id of its progression, and jobs are kept on disk
+//! so users can find out how an operation ended after a restart
+
+use std::{collections::HashMap, path::PathBuf};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{info_span, Span};
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    events::{
+        CausedBy, Event, EventInner, ProgressionEndValue, ProgressionEventInner,
+        ProgressionStartValue,
+    },
+    i18n::LocalizedMessage,
+    types::{InstanceUuid, Snowflake},
+};
+
+/// How many finished jobs are remembered
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    /// The core stopped while the job was running
+    Interrupted,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct Job {
+    pub id: Snowflake,
+    pub name: String,
+    pub localized_name: Option<LocalizedMessage>,
+    pub caused_by: CausedBy,
+    /// The instance the job is about, when it is known
+    pub instance_uuid: Option<InstanceUuid>,
+    pub status: JobStatus,
+    pub progress: f64,
+    /// `None` when the amount of work isn't known up front
+    pub total: Option<f64>,
+    pub progress_message: Option<String>,
+    /// Outcome once the job is over
+    pub message: Option<String>,
+    pub localized_message: Option<LocalizedMessage>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+fn start_instance_uuid(inner: &ProgressionStartValue) -> &InstanceUuid {
+    match inner {
+        ProgressionStartValue::InstanceCreation { instance_uuid, .. }
+        | ProgressionStartValue::InstanceDelete { instance_uuid }
+        | ProgressionStartValue::WorldPregeneration { instance_uuid }
+        | ProgressionStartValue::WorldUpgrade { instance_uuid }
+        | ProgressionStartValue::InstanceUpgrade { instance_uuid, .. } => instance_uuid,
+    }
+}
+
+fn end_instance_uuid(inner: &ProgressionEndValue) -> &InstanceUuid {
+    match inner {
+        ProgressionEndValue::InstanceCreation(info) => &info.uuid,
+        ProgressionEndValue::InstanceDelete { instance_uuid }
+        | ProgressionEndValue::FSOperationCompleted { instance_uuid, .. }
+        | ProgressionEndValue::SnapshotRestored { instance_uuid, .. } => instance_uuid,
+        ProgressionEndValue::SnapshotCreated(snapshot)
+        | ProgressionEndValue::SnapshotVerified(snapshot) => &snapshot.instance_uuid,
+    }
+}
+
+pub struct JobsManager {
+    jobs: HashMap<Snowflake, Job>,
+    path_to_jobs: PathBuf,
+    /// Open for as long as each job runs, see [`crate::otel`]
+    spans: HashMap<Snowflake, Span>,
+}
+
+impl JobsManager {
+    /// Jobs that were still running when the core stopped are marked as interrupted
+    pub async fn load(path_to_jobs: PathBuf) -> Result<Self, Error> {
+        let jobs: Vec<Job> = match tokio::fs::read(&path_to_jobs).await {
+            Ok(content) => {
+                serde_json::from_slice(&content).context("Failed to deserialize job json")?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(e)
+                    .context(format!(
+                        "Failed to read job file : {}",
+                        path_to_jobs.display()
+                    ))
+                    .map_err(Into::into)
+            }
+        };
+        let jobs = jobs
+            .into_iter()
+            .map(|mut job| {
+                if job.status == JobStatus::Running {
+                    job.status = JobStatus::Interrupted;
+                }
+                (job.id, job)
+            })
+            .collect();
+        Ok(Self {
+            jobs,
+            path_to_jobs,
+            spans: HashMap::new(),
+        })
+    }
+
+    pub async fn write_to_file(&self) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(&self.path_to_jobs)
+            .await
+            .context(format!(
+                "Failed to open/create json file {}",
+                &self.path_to_jobs.display()
+            ))?;
+        file.write_all(
+            serde_json::to_string(&self.list())
+                .context("Failed to serialize job json")?
+                .as_bytes(),
+        )
+        .await
+        .context("Failed to write to job json")?;
+        Ok(())
+    }
+
+    /// Newest first
+    pub fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.jobs.values().cloned().collect();
+        jobs.sort_by(|a, b| b.id.cmp(&a.id));
+        jobs
+    }
+
+    /// Jobs still running, along with the instance they are about
+    pub fn running(&self) -> impl Iterator<Item = &Job> {
+        self.jobs
+            .values()
+            .filter(|job| job.status == JobStatus::Running)
+    }
+
+    pub fn get(&self, id: &Snowflake) -> Option<Job> {
+        self.jobs.get(id).cloned()
+    }
+
+    /// Updates the job behind a progression event,
+    /// returning whether it started or finished and should be saved
+    pub fn handle_event(&mut self, event: &Event, now: i64) -> bool {
+        let progression_event = match &event.event_inner {
+            EventInner::ProgressionEvent(progression_event) => progression_event,
+            _ => return false,
+        };
+        let id = progression_event.event_id();
+        match progression_event.progression_event_inner() {
+            ProgressionEventInner::ProgressionStart {
+                progression_name,
+                total,
+                inner,
+                localized_name,
+            } => {
+                let instance_uuid = inner.as_ref().map(start_instance_uuid).cloned();
+                self.spans.insert(
+                    id,
+                    info_span!(
+                        parent: None,
+                        "job",
+                        otel.name = %progression_name,
+                        otel.status_code = tracing::field::Empty,
+                        job.id = %id.to_string(),
+                        instance_uuid = ?instance_uuid,
+                    ),
+                );
+                self.jobs.insert(
+                    id,
+                    Job {
+                        id,
+                        name: progression_name.clone(),
+                        localized_name: localized_name.clone(),
+                        caused_by: event.caused_by.clone(),
+                        instance_uuid,
+                        status: JobStatus::Running,
+                        progress: 0.0,
+                        total: *total,
+                        progress_message: None,
+                        message: None,
+                        localized_message: None,
+                        started_at: now,
+                        finished_at: None,
+                    },
+                );
+                true
+            }
+            ProgressionEventInner::ProgressionUpdate {
+                progress_message,
+                progress,
+            } => {
+                if let Some(job) = self.jobs.get_mut(&id) {
+                    job.progress += progress;
+                    job.progress_message = Some(progress_message.clone());
+                }
+                false
+            }
+            ProgressionEventInner::ProgressionEnd {
+                success,
+                message,
+                inner,
+                localized_message,
+            } => {
+                let job = match self.jobs.get_mut(&id) {
+                    Some(job) => job,
+                    None => return false,
+                };
+                job.status = if *success {
+                    JobStatus::Succeeded
+                } else {
+                    JobStatus::Failed
+                };
+                if let Some(total) = job.total.filter(|_| *success) {
+                    job.progress = total;
+                }
+                if let Some(span) = self.spans.remove(&id) {
+                    span.record("otel.status_code", if *success { "OK" } else { "ERROR" });
+                }
+                job.message = message.clone();
+                job.localized_message = localized_message.clone();
+                job.finished_at = Some(now);
+                if job.instance_uuid.is_none() {
+                    job.instance_uuid = inner.as_ref().map(end_instance_uuid).cloned();
+                }
+                self.prune();
+                true
+            }
+        }
+    }
+
+    fn prune(&mut self) {
+        let mut finished: Vec<Snowflake> = self
+            .jobs
+            .values()
+            .filter(|job| job.status != JobStatus::Running)
+            .map(|job| job.id)
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        for id in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+            self.jobs.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_event() {
+        let mut manager = JobsManager {
+            jobs: HashMap::new(),
+            path_to_jobs: PathBuf::new(),
+            spans: HashMap::new(),
+        };
+        let (start, event_id) =
+            Event::new_progression_event_start("Test", Some(10.0), None, CausedBy::System);
+        assert!(manager.handle_event(&start, 1));
+        let id = manager.list()[0].id;
+        assert!(!manager.handle_event(
+            &Event::new_progression_event_update(&event_id, "Halfway", 5.0),
+            2
+        ));
+        assert_eq!(manager.get(&id).unwrap().progress, 5.0);
+        assert!(manager.handle_event(
+            &Event::new_progression_event_end(event_id, false, Some("Oops"), None),
+            3
+        ));
+        let job = manager.get(&id).unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.finished_at, Some(3));
+        assert_eq!(job.message.as_deref(), Some("Oops"));
+    }
+}
@@ -3,46 +3,88 @@
 use crate::event_broadcaster::EventBroadcaster;
 use crate::migration::migrate;
 use crate::prelude::{
-    init_paths, lodestone_path, path_to_global_settings, path_to_stores, path_to_users, VERSION,
+    init_paths, lodestone_path, path_to_global_settings, path_to_invites, path_to_jobs,
+    path_to_stores, path_to_users, VERSION,
 };
 use crate::traits::t_configurable::GameType;
 use crate::traits::t_server::State;
 use crate::{
-    db::write::write_event_to_db_task,
+    db::{migrate::run_migrations, write::write_event_to_db_task},
     global_settings::GlobalSettingsData,
     handlers::{
-        checks::get_checks_routes, core_info::get_core_info_routes, events::get_events_routes,
-        gateway::get_gateway_routes, global_fs::get_global_fs_routes,
-        global_settings::get_global_settings_routes, instance::*,
-        instance_config::get_instance_config_routes, instance_fs::get_instance_fs_routes,
-        instance_macro::get_instance_macro_routes, instance_players::get_instance_players_routes,
+        checks::get_checks_routes, command_role::get_command_role_routes,
+        core_backup::get_core_backup_routes, core_info::get_core_info_routes,
+        events::get_events_routes, extension::get_extension_routes, gateway::get_gateway_routes,
+        global_fs::get_global_fs_routes, global_settings::get_global_settings_routes,
+        i18n::get_i18n_routes, instance::*, instance_access::get_instance_access_routes,
+        instance_action::get_instance_action_routes, instance_alerts::get_instance_alerts_routes,
+        instance_announcements::get_instance_announcements_routes,
+        instance_archive::get_instance_archive_routes, instance_config::get_instance_config_routes,
+        instance_crossplay::get_instance_crossplay_routes,
+        instance_diagnostics::get_instance_diagnostics_routes, instance_fs::get_instance_fs_routes,
+        instance_fs_tail::get_instance_fs_tail_routes, instance_import::get_instance_import_routes,
+        instance_log_patterns::get_instance_log_patterns_routes,
+        instance_luckperms::get_instance_luckperms_routes,
+        instance_macro::get_instance_macro_routes, instance_map::get_instance_map_routes,
+        instance_moderation::get_instance_moderation_routes, instance_nbt::get_instance_nbt_routes,
+        instance_network::get_instance_network_routes,
+        instance_player_reputation::get_instance_player_reputation_routes,
+        instance_players::get_instance_players_routes, instance_pregen::get_instance_pregen_routes,
+        instance_recommendations::get_instance_recommendations_routes,
+        instance_resource_pack::get_instance_resource_pack_routes,
         instance_server::get_instance_server_routes,
-        instance_setup_configs::get_instance_setup_config_routes, monitor::get_monitor_routes,
-        setup::get_setup_route, system::get_system_routes, users::get_user_routes,
+        instance_setup_configs::get_instance_setup_config_routes,
+        instance_snapshot::get_instance_snapshot_routes,
+        instance_upgrade::get_instance_upgrade_routes, instance_web::get_instance_web_routes,
+        instance_webdav::get_instance_webdav_routes, invite::get_invite_routes,
+        jobs::get_jobs_routes, monitor::get_monitor_routes,
+        password_reset::get_password_reset_routes, probes::get_probe_routes,
+        quota::get_quota_routes, reconcile::get_reconcile_routes, setup::get_setup_route,
+        share_link::get_share_link_routes, storage::get_storage_routes,
+        summary::get_summary_routes, system::get_system_routes, users::get_user_routes,
+        whitelist_group::get_whitelist_group_routes,
     },
     util::rand_alphanumeric,
 };
 
-use auth::user::UsersManager;
+use alerts::AlertsManager;
+use archive::ArchiveManager;
+use auth::{invite::InvitesManager, password_reset::PasswordResetManager, user::UsersManager};
 use axum::Router;
+use jobs::JobsManager;
+use share_link::ShareLinks;
 
 use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
 use color_eyre::eyre::Context;
 use color_eyre::Report;
+use core_config::CoreConfig;
 use error::Error;
 use events::{CausedBy, Event};
+use extension::ExtensionManager;
 use futures::Future;
 use global_settings::GlobalSettings;
+use health::HealthTracker;
 use implementations::{generic, minecraft};
 use macro_executor::MacroExecutor;
+use network_filter::NetworkFilterManager;
 use port_manager::PortManager;
 use prelude::GameInstance;
+use probes::{Component, Probes};
+use rate_limiter::RateLimiter;
+use recommendations::MetricsHistory;
 use reqwest::{header, Method};
 use ringbuffer::{AllocRingBuffer, RingBufferWrite};
+use service::ShutdownPolicy;
+use startup_watchdog::StartupWatchdog;
+use summary::SummaryCache;
+use web_proxy::WebSessions;
 
 use semver::Version;
-use sqlx::{sqlite::SqliteConnectOptions, Pool};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
+    Pool,
+};
 use std::{
     collections::{HashMap, HashSet},
     net::SocketAddr,
@@ -57,38 +99,99 @@ use tokio::{
     sync::{broadcast::error::RecvError, Mutex, RwLock},
 };
 use tower_http::{
+    compression::CompressionLayer,
     cors::{Any, CorsLayer},
     trace::TraceLayer,
 };
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, warn, Span};
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter};
 use traits::{t_configurable::TConfigurable, t_server::MonitorReport, t_server::TServer};
 use types::{DotLodestoneConfig, InstanceUuid};
 use uuid::Uuid;
+mod alerts;
+mod announcement;
+mod archive;
 pub mod auth;
+mod chat_moderation;
+mod command_filter;
+mod connection_info;
+mod console_buffer;
+mod console_parser;
+mod core_backup;
+mod core_config;
 pub mod db;
 mod deno_ops;
+mod disk_space;
 pub mod error;
 mod event_broadcaster;
 mod events;
+mod extension;
+mod file_tail;
+mod global_macro;
 pub mod global_settings;
 mod handlers;
+mod health;
+mod hex_view;
+pub mod i18n;
 pub mod implementations;
+mod instance_creation;
+mod isolation;
+mod jobs;
+pub mod local_admin;
+mod log_parser;
+mod log_shipping;
 pub mod macro_executor;
+mod macro_history;
+mod mail;
+mod maintenance;
 mod migration;
+mod network_filter;
+mod otel;
 mod output_types;
+mod player_reputation;
 mod port_manager;
+mod power_schedule;
 pub mod prelude;
+mod probes;
+mod process_priority;
+mod process_tree;
+mod provisioning;
+mod quota;
+mod rate_limiter;
+mod recommendations;
+mod reconcile;
+mod remote_backup;
+mod resource_pack;
+mod restart_schedule;
+mod sandbox;
+pub mod service;
+mod settings_history;
+mod share_link;
+mod snapshot;
+mod startup_watchdog;
+mod storage;
+mod summary;
 pub mod tauri_export;
+mod telemetry;
+mod text_encoding;
 mod traits;
 pub mod types;
 pub mod util;
+mod wasm_macro;
+mod web_proxy;
+mod whitelist_group;
+mod world_map;
 
 #[derive(Clone)]
 pub struct AppState {
     instances: Arc<Mutex<HashMap<InstanceUuid, GameInstance>>>,
     users_manager: Arc<RwLock<UsersManager>>,
+    invites_manager: Arc<Mutex<InvitesManager>>,
+    password_resets: Arc<Mutex<PasswordResetManager>>,
+    jobs: Arc<Mutex<JobsManager>>,
+    archives: Arc<Mutex<ArchiveManager>>,
+    share_links: Arc<Mutex<ShareLinks>>,
     events_buffer: Arc<Mutex<AllocRingBuffer<Event>>>,
     console_out_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<Event>>>>,
     monitor_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<MonitorReport>>>>,
@@ -102,6 +205,16 @@ pub struct AppState {
     download_urls: Arc<Mutex<HashMap<String, PathBuf>>>,
     macro_executor: MacroExecutor,
     sqlite_pool: sqlx::SqlitePool,
+    rate_limiter: RateLimiter,
+    alerts: Arc<Mutex<AlertsManager>>,
+    health: Arc<Mutex<HealthTracker>>,
+    metrics_history: Arc<Mutex<MetricsHistory>>,
+    summary_cache: Arc<Mutex<SummaryCache>>,
+    startup_watchdog: Arc<Mutex<StartupWatchdog>>,
+    network_filters: Arc<Mutex<NetworkFilterManager>>,
+    web_sessions: Arc<Mutex<WebSessions>>,
+    extensions: Arc<Mutex<ExtensionManager>>,
+    probes: Arc<Mutex<Probes>>,
 }
 async fn restore_instances(
     instances_path: &Path,
@@ -121,6 +234,10 @@ async fn restore_instances(
                 continue;
             }
         };
+        // archived instances are kept as files next to the instance directories
+        if !path.is_dir() {
+            continue;
+        }
         let dot_lodestone_config_file = match std::fs::File::open(path.join(".lodestone_config")) {
             Ok(v) => v,
             Err(e) => {
@@ -165,6 +282,11 @@ fn setup_tracing() -> tracing_appender::non_blocking::WorkerGuard {
         tracing_appender::rolling::hourly(lodestone_path().join("log"), "lodestone_core.log");
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
 
+    let (otel_layer, otel_error) = match otel::layer() {
+        Ok(layer) => (layer, None),
+        Err(e) => (None, Some(e)),
+    };
+
     // set up a subscriber that logs formatted tracing events to stdout without colors without setting it as the default
 
     #[cfg(debug_assertions)]
@@ -196,6 +318,7 @@ fn setup_tracing() -> tracing_appender::non_blocking::WorkerGuard {
             .with_writer(non_blocking);
 
         tracing_subscriber::registry()
+            .with(otel_layer)
             .with(fmt_layer_stdout)
             .with(fmt_layer_file)
             .with(EnvFilter::from("lodestone_core=debug"))
@@ -235,11 +358,16 @@ fn setup_tracing() -> tracing_appender::non_blocking::WorkerGuard {
 
         tracing_subscriber::registry()
             // .with(ErrorLayer::default())
+            .with(otel_layer)
             .with(fmt_layer_stdout)
             .with(fmt_layer_file)
             .init();
     }
 
+    if let Some(e) = otel_error {
+        error!("Failed to set up OpenTelemetry, spans won't be exported: {e}");
+    }
+
     _guard
 }
 
@@ -322,6 +450,24 @@ pub struct Args {
     pub is_desktop: bool,
     #[arg(short, long)]
     pub lodestone_path: Option<PathBuf>,
+    /// Read the configuration from this file instead of `lodestone.toml` in the lodestone
+    /// directory. Falls back to the `LODESTONE_CONFIG` environment variable.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Use a fixed first time setup key instead of a randomly generated one,
+    /// so headless installs can complete the owner setup without reading the logs.
+    /// Falls back to the `LODESTONE_SETUP_KEY` environment variable.
+    #[arg(long)]
+    pub setup_key: Option<String>,
+    /// Write the process id to this file while the core is running
+    #[arg(long)]
+    pub pid_file: Option<PathBuf>,
+    /// What to do with running instances when the core is shut down
+    #[arg(long, value_enum, default_value_t = ShutdownPolicy::Stop)]
+    pub shutdown_policy: ShutdownPolicy,
+    /// Run under the Windows service control manager
+    #[arg(long, default_value = "false")]
+    pub service: bool,
 }
 
 pub async fn run(
@@ -334,26 +480,32 @@ pub async fn run(
     let _ = color_eyre::install().map_err(|e| {
         error!("Failed to install color_eyre: {}", e);
     });
-    let lodestone_path_ = if let Some(path) = args.lodestone_path {
-        path
-    } else {
-        PathBuf::from(match std::env::var("LODESTONE_PATH") {
-            Ok(v) => v,
-            Err(_) => home::home_dir()
+    // tracing isn't set up before the lodestone directory is known
+    let (core_config, lodestone_path_) =
+        match CoreConfig::resolve(args.config, args.lodestone_path, || {
+            home::home_dir()
                 .unwrap_or_else(|| {
                     std::env::current_dir().expect("what kinda os are you running lodestone on???")
                 })
                 .join(".lodestone")
-                .to_str()
-                .unwrap()
-                .to_string(),
-        })
-    };
+        }) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{e}, exiting");
+                std::process::exit(1);
+            }
+        };
     init_paths(lodestone_path_);
     let lodestone_path = lodestone_path();
     info!("Lodestone path: {}", lodestone_path.display());
     std::env::set_current_dir(lodestone_path).unwrap();
     let guard = setup_tracing();
+    let pid_file = args.pid_file.as_deref().and_then(|path| {
+        service::PidFile::create(path)
+            .map_err(|e| error!("Failed to create pid file: {}", e))
+            .ok()
+    });
+    let shutdown_policy = args.shutdown_policy;
     if args.is_desktop {
         info!("Lodestone Core running in Tauri");
     }
@@ -375,6 +527,16 @@ pub async fn run(
 
     users_manager.load_users().await.unwrap();
 
+    let invites_manager = InvitesManager::load(path_to_invites().clone())
+        .await
+        .unwrap();
+
+    let jobs = JobsManager::load(path_to_jobs().clone()).await.unwrap();
+
+    let archives = ArchiveManager::load().await.unwrap();
+
+    let share_links = ShareLinks::load().await.unwrap();
+
     let mut global_settings = GlobalSettings::new(
         path_to_global_settings().clone(),
         tx.clone(),
@@ -382,9 +544,27 @@ pub async fn run(
     );
 
     global_settings.load_from_file().await.unwrap();
+    if let Err(e) = core_config::apply_settings(&mut global_settings, &core_config).await {
+        error!("Failed to apply the configuration: {e}, exiting");
+        std::process::exit(1);
+    }
+
+    users_manager.set_password_hashing(global_settings.password_hashing());
+    isolation::set_enabled(global_settings.instance_isolation());
+
+    if let Some(owner) = &core_config.owner {
+        if let Err(e) = core_config::bootstrap_owner(&mut users_manager, owner).await {
+            error!("Failed to create the owner: {e}, exiting");
+            std::process::exit(1);
+        }
+    }
 
     let first_time_setup_key = if !users_manager.as_ref().iter().any(|(_, user)| user.is_owner) {
-        let key = rand_alphanumeric(16);
+        let key = args
+            .setup_key
+            .or_else(|| std::env::var("LODESTONE_SETUP_KEY").ok())
+            .filter(|key| !key.is_empty())
+            .unwrap_or_else(|| rand_alphanumeric(16));
         // log the first time setup key in green so it's easy to find
         info!(
             "First time setup key: {}",
@@ -399,6 +579,24 @@ pub async fn run(
     } else {
         None
     };
+    let path_to_db = path_to_stores().join("data.db");
+    let sqlite_pool = Pool::connect_with(
+        SqliteConnectOptions::from_str(&format!("sqlite://{}", path_to_db.display()))
+            .unwrap()
+            .create_if_missing(true)
+            // lets events be read while a batch is being written
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(Duration::from_secs(5)),
+    )
+    .await
+    .unwrap();
+    let mut probes = Probes::default();
+    let migrations = run_migrations(&sqlite_pool, &path_to_db).await;
+    if let Err(e) = &migrations {
+        error!("{e}. Events will not be saved until this is fixed");
+    }
+    probes.finished(Component::Migrations, migrations.map_err(|e| e.to_string()));
     let macro_executor = MacroExecutor::new(tx.clone());
     let mut instances = restore_instances(&path_to_instances, tx.clone(), macro_executor.clone())
         .await
@@ -409,6 +607,27 @@ pub async fn run(
             );
         })
         .unwrap();
+    for location in global_settings.storage_locations() {
+        match restore_instances(&location.path, tx.clone(), macro_executor.clone()).await {
+            Ok(restored) => instances.extend(restored),
+            Err(e) => error!(
+                "Failed to restore instances in storage location {}: {}",
+                location.name, e
+            ),
+        }
+    }
+    // instances the core went down in the middle of creating are set up again or removed later
+    let pending_creations = instance_creation::pending_creations(&sqlite_pool)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Failed to look for interrupted instance creations: {e}");
+            Vec::new()
+        });
+    instances.retain(|uuid, _| {
+        !pending_creations
+            .iter()
+            .any(|creation| creation.instance_uuid == *uuid)
+    });
     for (_, instance) in instances.iter_mut() {
         if instance.auto_start().await {
             info!("Auto starting instance {}", instance.name().await);
@@ -424,10 +643,18 @@ pub async fn run(
     let mut allocated_ports = HashSet::new();
     for (_, instance) in instances.iter() {
         allocated_ports.insert(instance.port().await);
+        if let Some(bedrock_port) = instance.bedrock_port().await {
+            allocated_ports.insert(bedrock_port);
+        }
     }
     let shared_state = AppState {
         instances: Arc::new(Mutex::new(instances)),
         users_manager: Arc::new(RwLock::new(users_manager)),
+        invites_manager: Arc::new(Mutex::new(invites_manager)),
+        password_resets: Arc::new(Mutex::new(PasswordResetManager::default())),
+        jobs: Arc::new(Mutex::new(jobs)),
+        archives: Arc::new(Mutex::new(archives)),
+        share_links: Arc::new(Mutex::new(share_links)),
         events_buffer: Arc::new(Mutex::new(AllocRingBuffer::with_capacity(512))),
         console_out_buffer: Arc::new(Mutex::new(HashMap::new())),
         monitor_buffer: Arc::new(Mutex::new(HashMap::new())),
@@ -440,16 +667,17 @@ pub async fn run(
         download_urls: Arc::new(Mutex::new(HashMap::new())),
         global_settings: Arc::new(Mutex::new(global_settings)),
         macro_executor,
-        sqlite_pool: Pool::connect_with(
-            SqliteConnectOptions::from_str(&format!(
-                "sqlite://{}/data.db",
-                path_to_stores().display()
-            ))
-            .unwrap()
-            .create_if_missing(true),
-        )
-        .await
-        .unwrap(),
+        rate_limiter: RateLimiter::new(),
+        alerts: Arc::new(Mutex::new(AlertsManager::default())),
+        health: Arc::new(Mutex::new(HealthTracker::default())),
+        metrics_history: Arc::new(Mutex::new(MetricsHistory::default())),
+        summary_cache: Arc::new(Mutex::new(SummaryCache::default())),
+        startup_watchdog: Arc::new(Mutex::new(StartupWatchdog::default())),
+        network_filters: Arc::new(Mutex::new(NetworkFilterManager::default())),
+        web_sessions: Arc::new(Mutex::new(WebSessions::default())),
+        extensions: Arc::new(Mutex::new(ExtensionManager::default())),
+        probes: Arc::new(Mutex::new(probes)),
+        sqlite_pool,
     };
 
     let event_buffer_task = {
@@ -486,7 +714,35 @@ pub async fn run(
         }
     };
 
-    let write_to_db_task = write_event_to_db_task(tx.subscribe(), shared_state.sqlite_pool.clone());
+    tokio::spawn({
+        let jobs = shared_state.jobs.clone();
+        let mut event_receiver = tx.subscribe();
+        async move {
+            loop {
+                let event = match event_receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => {
+                        warn!("Job tracker lagged");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let mut jobs = jobs.lock().await;
+                if jobs.handle_event(&event, chrono::Utc::now().timestamp()) {
+                    if let Err(e) = jobs.write_to_file().await {
+                        warn!("Failed to save jobs: {e}");
+                    }
+                }
+            }
+        }
+    });
+
+    let db_shutdown = tokio_util::sync::CancellationToken::new();
+    let mut write_to_db_task = tokio::spawn(write_event_to_db_task(
+        tx.subscribe(),
+        shared_state.sqlite_pool.clone(),
+        db_shutdown.clone(),
+    ));
 
     let monitor_report_task = {
         let monitor_buffer = shared_state.monitor_buffer.clone();
@@ -509,8 +765,16 @@ pub async fn run(
     };
 
     let tls_config_result = RustlsConfig::from_pem_file(
-        lodestone_path.join("tls").join("cert.pem"),
-        lodestone_path.join("tls").join("key.pem"),
+        core_config
+            .listener
+            .tls_cert
+            .clone()
+            .unwrap_or_else(|| lodestone_path.join("tls").join("cert.pem")),
+        core_config
+            .listener
+            .tls_key
+            .clone()
+            .unwrap_or_else(|| lodestone_path.join("tls").join("key.pem")),
     )
     .await;
 
@@ -528,44 +792,186 @@ pub async fn run(
                         Method::OPTIONS,
                     ])
                     .allow_headers([header::ORIGIN, header::CONTENT_TYPE, header::AUTHORIZATION]) // Note I can't find X-Auth-Token but it was in the original rocket version, hope it's fine
+                    .expose_headers(maintenance::exposed_headers())
                     .allow_origin(Any);
 
-                let trace = TraceLayer::new_for_http();
+                let trace = TraceLayer::new_for_http()
+                    .make_span_with(|request: &axum::http::Request<_>| otel::request_span(request))
+                    .on_response(
+                        |response: &axum::http::Response<_>, latency: Duration, span: &Span| {
+                            otel::record_response(response, latency, span)
+                        },
+                    );
+
+                let compression = CompressionLayer::new().gzip(true).br(true);
 
                 let api_routes = Router::new()
                     .merge(get_events_routes(shared_state.clone()))
                     .merge(get_instance_setup_config_routes(shared_state.clone()))
                     .merge(get_instance_server_routes(shared_state.clone()))
                     .merge(get_instance_config_routes(shared_state.clone()))
+                    .merge(get_instance_crossplay_routes(shared_state.clone()))
+                    .merge(get_instance_diagnostics_routes(shared_state.clone()))
                     .merge(get_instance_players_routes(shared_state.clone()))
+                    .merge(get_instance_pregen_routes(shared_state.clone()))
                     .merge(get_instance_routes(shared_state.clone()))
                     .merge(get_system_routes(shared_state.clone()))
                     .merge(get_checks_routes(shared_state.clone()))
                     .merge(get_user_routes(shared_state.clone()))
                     .merge(get_core_info_routes(shared_state.clone()))
+                    .merge(get_core_backup_routes(shared_state.clone()))
                     .merge(get_setup_route(shared_state.clone()))
                     .merge(get_monitor_routes(shared_state.clone()))
                     .merge(get_instance_macro_routes(shared_state.clone()))
-                    .merge(get_instance_fs_routes(shared_state.clone()))
-                    .merge(get_global_fs_routes(shared_state.clone()))
+                    .merge(get_instance_snapshot_routes(shared_state.clone()))
+                    .merge(get_instance_upgrade_routes(shared_state.clone()))
+                    .merge(get_instance_import_routes(shared_state.clone()))
+                    .merge(get_instance_network_routes(shared_state.clone()))
+                    .merge(get_instance_web_routes(shared_state.clone()))
+                    .merge(get_instance_webdav_routes(shared_state.clone()))
+                    .merge(get_instance_nbt_routes(shared_state.clone()))
+                    .merge(get_instance_map_routes(shared_state.clone()))
+                    .merge(get_instance_recommendations_routes(shared_state.clone()))
+                    .merge(get_instance_resource_pack_routes(shared_state.clone()))
+                    .merge(get_instance_alerts_routes(shared_state.clone()))
+                    .merge(get_instance_action_routes(shared_state.clone()))
+                    .merge(get_instance_announcements_routes(shared_state.clone()))
+                    .merge(get_instance_luckperms_routes(shared_state.clone()))
+                    .merge(get_instance_log_patterns_routes(shared_state.clone()))
+                    .merge(get_instance_moderation_routes(shared_state.clone()))
+                    .merge(get_instance_player_reputation_routes(shared_state.clone()))
+                    .merge(get_instance_archive_routes(shared_state.clone()))
+                    .merge(get_quota_routes(shared_state.clone()))
+                    .merge(get_reconcile_routes(shared_state.clone()))
+                    .merge(get_instance_access_routes(shared_state.clone()))
+                    .merge(get_jobs_routes(shared_state.clone()))
+                    .merge(get_storage_routes(shared_state.clone()))
+                    .merge(get_whitelist_group_routes(shared_state.clone()))
+                    .merge(get_command_role_routes(shared_state.clone()))
+                    .merge(get_summary_routes(shared_state.clone()))
+                    .merge(get_share_link_routes(shared_state.clone()))
+                    .merge(get_invite_routes(shared_state.clone()).route_layer(
+                        axum::middleware::from_fn_with_state(
+                            shared_state.clone(),
+                            rate_limiter::rate_limit,
+                        ),
+                    ))
+                    .merge(get_password_reset_routes(shared_state.clone()).route_layer(
+                        axum::middleware::from_fn_with_state(
+                            shared_state.clone(),
+                            rate_limiter::rate_limit,
+                        ),
+                    ))
+                    .merge(get_instance_fs_routes(shared_state.clone()).route_layer(
+                        axum::middleware::from_fn_with_state(
+                            shared_state.clone(),
+                            rate_limiter::rate_limit,
+                        ),
+                    ))
+                    .merge(
+                        get_instance_fs_tail_routes(shared_state.clone()).route_layer(
+                            axum::middleware::from_fn_with_state(
+                                shared_state.clone(),
+                                rate_limiter::rate_limit,
+                            ),
+                        ),
+                    )
+                    .merge(get_global_fs_routes(shared_state.clone()).route_layer(
+                        axum::middleware::from_fn_with_state(
+                            shared_state.clone(),
+                            rate_limiter::rate_limit,
+                        ),
+                    ))
                     .merge(get_global_settings_routes(shared_state.clone()))
                     .merge(get_gateway_routes(shared_state.clone()))
+                    .merge(get_i18n_routes(shared_state.clone()))
+                    .merge(get_extension_routes(shared_state.clone()))
+                    .layer(axum::middleware::from_fn_with_state(
+                        shared_state.clone(),
+                        maintenance::reject_during_maintenance,
+                    ))
+                    .layer(axum::middleware::from_fn_with_state(
+                        shared_state.clone(),
+                        i18n::negotiate_locale,
+                    ))
+                    .layer(compression)
                     .layer(cors)
                     .layer(trace);
-                let app = Router::new().nest("/api/v1", api_routes);
-                #[allow(unused_variables, unused_mut)]
-                let mut port = 16_662_u16;
+                let app = Router::new()
+                    .nest("/api/v1", api_routes)
+                    .merge(get_probe_routes(shared_state.clone()));
+                tokio::spawn({
+                    let shared_state = shared_state.clone();
+                    async move {
+                        let mut interval = tokio::time::interval(Duration::from_secs(600));
+                        loop {
+                            interval.tick().await;
+                            let config = shared_state.global_settings.lock().await.rate_limit();
+                            shared_state.rate_limiter.prune(&config);
+                        }
+                    }
+                });
+                tokio::spawn(alerts::run_alerts(shared_state.clone()));
+                tokio::spawn(announcement::run_announcements(shared_state.clone()));
+                tokio::spawn(chat_moderation::run_chat_moderation(shared_state.clone()));
+                tokio::spawn(player_reputation::run_watched_players(shared_state.clone()));
+                tokio::spawn(health::run_health(shared_state.clone()));
+                tokio::spawn(recommendations::run_metrics_history(shared_state.clone()));
+                tokio::spawn(summary::run_summary_cache(shared_state.clone()));
+                tokio::spawn(startup_watchdog::run_startup_watchdog(shared_state.clone()));
+                tokio::spawn({
+                    let shared_state = shared_state.clone();
+                    async move {
+                        instance_creation::recover_creations(
+                            shared_state.clone(),
+                            pending_creations,
+                        )
+                        .await;
+                        shared_state
+                            .probes
+                            .lock()
+                            .await
+                            .finished(Component::InstanceCreations, Ok(()));
+                        // after recovery, so interrupted creations aren't provisioned twice
+                        provisioning::run_provisioning(shared_state).await;
+                    }
+                });
+                tokio::spawn(reconcile::check_on_startup(shared_state.clone()));
+                tokio::spawn(world_map::run_world_maps(shared_state.clone()));
+                tokio::spawn(telemetry::run_telemetry(shared_state.clone()));
+                tokio::spawn(power_schedule::run_power_schedules(shared_state.clone()));
+                tokio::spawn(restart_schedule::run_restart_schedules(
+                    shared_state.clone(),
+                ));
+                tokio::spawn(network_filter::start_network_filters(shared_state.clone()));
+                tokio::spawn(whitelist_group::run_whitelist_sync(shared_state.clone()));
+                tokio::spawn(minecraft::query::run_status_queries(shared_state.clone()));
+                tokio::spawn({
+                    let shared_state = shared_state.clone();
+                    async move {
+                        extension::load_extensions(shared_state.clone()).await;
+                        shared_state
+                            .probes
+                            .lock()
+                            .await
+                            .finished(Component::Extensions, Ok(()));
+                    }
+                });
+                tokio::spawn(macro_history::run_macro_history(shared_state.clone()));
+                tokio::spawn(log_shipping::run_log_shipping(shared_state.clone()));
+                tokio::spawn(probes::run_heartbeat(shared_state.clone()));
+                #[allow(unused_mut)]
+                let mut addr = core_config.listen_address();
                 #[cfg(not(debug_assertions))]
-                if port_scanner::scan_port(port) {
-                    error!("Port {port} is already in use, exiting");
+                if port_scanner::scan_port(addr.port()) {
+                    error!("Port {} is already in use, exiting", addr.port());
                     std::process::exit(1);
                 }
                 #[cfg(debug_assertions)]
-                while port_scanner::scan_port(port) {
-                    debug!("Port {port} is already in use, trying next port");
-                    port += 1;
+                while port_scanner::scan_port(addr.port()) {
+                    debug!("Port {} is already in use, trying next port", addr.port());
+                    addr.set_port(addr.port() + 1);
                 }
-                let addr = SocketAddr::from(([0, 0, 0, 0], port));
                 let axum_server_handle = axum_server::Handle::new();
                 tokio::spawn({
                     let axum_server_handle = axum_server_handle.clone();
@@ -577,7 +983,7 @@ pub async fn run(
                                 info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
                                 axum_server::bind_rustls(addr, config)
                                     .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
+                                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                                     .await
                             }
                             Err(e) => {
@@ -586,36 +992,34 @@ pub async fn run(
                                 info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
                                 axum_server::bind(addr)
                                     .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
+                                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                                     .await
                             }
                         }
                         .unwrap();
                     }
                 });
+                service::sd_notify("READY=1");
                 select! {
-                    _ = write_to_db_task => info!("Write to db task exited"),
+                    _ = &mut write_to_db_task => info!("Write to db task exited"),
                     _ = event_buffer_task => info!("Event buffer task exited"),
                     _ = monitor_report_task => info!("Monitor report task exited"),
-                    _ = tokio::signal::ctrl_c() => info!("Ctrl+C received"),
+                    _ = service::shutdown_signal() => {},
                 }
+                service::sd_notify("STOPPING=1");
                 info!("Shutting down web server");
                 axum_server_handle.shutdown();
-                info!("Signalling all instances to stop");
-                // cleanup
-                let mut instances = shared_state.instances.lock().await;
-                for (_, instance) in instances.iter_mut() {
-                    if instance.state().await == State::Stopped {
-                        continue;
-                    }
-                    if let Err(e) = instance.stop(CausedBy::System, false).await {
-                        error!(
-                            "Failed to stop instance {} : {}. Instance may need manual cleanup",
-                            instance.uuid().await,
-                            e
-                        );
-                    }
+                let _pid_file = pid_file;
+                service::stop_instances(&shared_state, shutdown_policy).await;
+                service::drain_jobs(&shared_state).await;
+                info!("Flushing events to db");
+                db_shutdown.cancel();
+                if !write_to_db_task.is_finished() {
+                    let _ = write_to_db_task.await;
                 }
+                shared_state.sqlite_pool.close().await;
+                otel::shutdown().await;
+                info!("Shutdown complete");
             }
         },
         shared_state,
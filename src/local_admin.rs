@@ -0,0 +1,143 @@
+//! Administration straight from the lodestone directory, for the local mode of `lodestone-cli`.
+//!
+//! Meant for when the core is stopped, like on a host whose API can't be reached. A running core
+//! keeps users and instances in memory and would write over changes made here.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+
+use crate::{
+    auth::{
+        permission::UserPermission,
+        user::{User, UsersManager},
+        user_id::UserId,
+    },
+    core_config::CoreConfig,
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::CausedBy,
+    global_settings::GlobalSettingsData,
+    prelude::{init_paths, path_to_global_settings, path_to_instances, path_to_users},
+    snapshot,
+    traits::t_configurable::GameType,
+    types::{DotLodestoneConfig, InstanceUuid, Snowflake},
+};
+
+pub struct LocalInstance {
+    pub uuid: InstanceUuid,
+    pub game_type: GameType,
+    pub path: PathBuf,
+}
+
+/// Finds the lodestone directory the way the core does, returns where it is
+pub fn init(lodestone_path: Option<PathBuf>) -> Result<PathBuf, Error> {
+    let (_, lodestone_path) = CoreConfig::resolve(None, lodestone_path, || {
+        home::home_dir()
+            .unwrap_or_else(|| std::env::current_dir().unwrap_or_default())
+            .join(".lodestone")
+    })?;
+    if !lodestone_path.is_dir() {
+        return Err(eyre!("No lodestone directory at {}", lodestone_path.display()).into());
+    }
+    init_paths(lodestone_path.clone());
+    Ok(lodestone_path)
+}
+
+async fn global_settings() -> Result<GlobalSettingsData, Error> {
+    match tokio::fs::read(path_to_global_settings()).await {
+        Ok(content) if !content.is_empty() => Ok(serde_json::from_slice(&content)
+            .context("Failed to deserialize global settings json")?),
+        Ok(_) => Ok(GlobalSettingsData::default()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(GlobalSettingsData::default()),
+        Err(e) => Err(e)
+            .context("Failed to read global settings")
+            .map_err(Into::into),
+    }
+}
+
+/// Instances of every storage location, including ones the core would fail to restore
+pub async fn list_instances() -> Result<Vec<LocalInstance>, Error> {
+    let mut dirs = vec![path_to_instances().clone()];
+    dirs.extend(
+        global_settings()
+            .await?
+            .storage_locations
+            .into_iter()
+            .map(|location| location.path),
+    );
+    let mut ret = Vec::new();
+    for dir in dirs {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e)
+                    .context(format!("Failed to read {}", dir.display()))
+                    .map_err(Into::into)
+            }
+        };
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context(format!("Failed to read {}", dir.display()))?
+        {
+            let path = entry.path();
+            let config: DotLodestoneConfig =
+                match tokio::fs::read(path.join(".lodestone_config")).await {
+                    Ok(content) => match serde_json::from_slice(&content) {
+                        Ok(config) => config,
+                        Err(_) => continue,
+                    },
+                    // archives and stray files
+                    Err(_) => continue,
+                };
+            ret.push(LocalInstance {
+                uuid: config.uuid().clone(),
+                game_type: config.game_type().clone(),
+                path,
+            });
+        }
+    }
+    Ok(ret)
+}
+
+/// A user without any permission, like the ones created from the dashboard
+pub async fn create_user(username: String, password: String) -> Result<UserId, Error> {
+    let (event_broadcaster, _rx) = EventBroadcaster::new(1);
+    let mut users_manager =
+        UsersManager::new(event_broadcaster, HashMap::new(), path_to_users().clone());
+    users_manager.load_users().await?;
+    let user = User::new(
+        username,
+        password,
+        false,
+        false,
+        UserPermission::default(),
+        &global_settings().await?.password_hashing,
+    );
+    let uid = user.uid.clone();
+    users_manager.add_user(user, CausedBy::System).await?;
+    Ok(uid)
+}
+
+/// Snapshots the instance's files as they are, the instance should not be running
+pub async fn create_snapshot(
+    uuid: &InstanceUuid,
+    name: String,
+    description: Option<String>,
+) -> Result<Snowflake, Error> {
+    let instance = list_instances()
+        .await?
+        .into_iter()
+        .find(|instance| &instance.uuid == uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("No instance {uuid} in this lodestone directory"),
+        })?;
+    Ok(
+        snapshot::create_snapshot(instance.path, instance.uuid, name, description)
+            .await?
+            .id,
+    )
+}
@@ -0,0 +1,223 @@
+//! Recognizing what happens on a server from the lines it prints.
+//!
+//! Every game implementation registers regular expressions for the lines it knows, see
+//! [`builtin_patterns`]. Servers whose logs look different, like proxies or lesser known
+//! brands, can be given patterns of their own per instance, which are tried before the built-in
+//! ones. Patterns for players joining and leaving capture the player's name in a group named
+//! `player`, patterns for errors may capture the error in a group named `message`.
+
+use color_eyre::eyre::eyre;
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    implementations::minecraft::line_parser,
+    traits::t_configurable::Game,
+};
+
+pub const MAX_CUSTOM_PATTERNS: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum LogPatternKind {
+    PlayerJoined,
+    PlayerLeft,
+    /// The server finished starting and accepts players
+    Ready,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct LogPattern {
+    pub kind: LogPatternKind,
+    pub regex: String,
+}
+
+impl LogPattern {
+    pub fn new(kind: LogPatternKind, regex: &str) -> Self {
+        Self {
+            kind,
+            regex: regex.to_string(),
+        }
+    }
+
+    fn compile(&self) -> Result<Regex, Error> {
+        let regex = Regex::new(&self.regex).map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid pattern {}: {e}", self.regex),
+        })?;
+        let needs_player = matches!(
+            self.kind,
+            LogPatternKind::PlayerJoined | LogPatternKind::PlayerLeft
+        );
+        if needs_player && !regex.capture_names().any(|name| name == Some("player")) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Pattern {} must capture the player's name in a group named player, like (?P<player>\\S+)",
+                    self.regex
+                ),
+            });
+        }
+        Ok(regex)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogEvent {
+    PlayerJoined(String),
+    PlayerLeft(String),
+    Ready,
+    Error(String),
+}
+
+/// The patterns a game recognizes out of the box
+pub fn builtin_patterns(game: &Game) -> Vec<LogPattern> {
+    match game {
+        Game::MinecraftJava { .. } => line_parser::log_patterns(),
+        Game::MinecraftBedrock => bedrock_patterns(),
+        Game::Generic { .. } => Vec::new(),
+    }
+}
+
+/// Bedrock Dedicated Server, which has no implementation of its own yet but can be run as a
+/// generic instance
+fn bedrock_patterns() -> Vec<LogPattern> {
+    vec![
+        LogPattern::new(
+            LogPatternKind::PlayerJoined,
+            r"Player connected: (?P<player>[^,]+), xuid",
+        ),
+        LogPattern::new(
+            LogPatternKind::PlayerLeft,
+            r"Player disconnected: (?P<player>[^,]+), xuid",
+        ),
+        LogPattern::new(LogPatternKind::Ready, r"\] Server started\.$"),
+        LogPattern::new(LogPatternKind::Error, r"\bERROR\] (?P<message>.+)"),
+    ]
+}
+
+/// Checks custom patterns before they are saved
+pub fn validate(patterns: &[LogPattern]) -> Result<(), Error> {
+    if patterns.len() > MAX_CUSTOM_PATTERNS {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("An instance can have at most {MAX_CUSTOM_PATTERNS} custom patterns"),
+        });
+    }
+    for pattern in patterns {
+        pattern.compile()?;
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct LineParser {
+    patterns: Vec<(LogPatternKind, Regex)>,
+}
+
+impl LineParser {
+    /// A parser trying `custom` first, then what `game` registered. Patterns that don't
+    /// compile are left out
+    pub fn new(game: &Game, custom: &[LogPattern]) -> Self {
+        Self::compile(custom.iter().cloned().chain(builtin_patterns(game)))
+    }
+
+    /// A parser with only the given patterns
+    pub fn custom(patterns: &[LogPattern]) -> Self {
+        Self::compile(patterns.iter().cloned())
+    }
+
+    fn compile(patterns: impl Iterator<Item = LogPattern>) -> Self {
+        let patterns = patterns
+            .filter_map(|pattern| match pattern.compile() {
+                Ok(regex) => Some((pattern.kind, regex)),
+                Err(e) => {
+                    warn!("Skipping log pattern: {e}");
+                    None
+                }
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    pub fn has_ready_pattern(&self) -> bool {
+        self.patterns
+            .iter()
+            .any(|(kind, _)| *kind == LogPatternKind::Ready)
+    }
+
+    /// What the first matching pattern says happened
+    pub fn parse(&self, line: &str) -> Option<LogEvent> {
+        self.patterns.iter().find_map(|(kind, regex)| {
+            let captures = regex.captures(line).ok()??;
+            match kind {
+                LogPatternKind::PlayerJoined => Some(LogEvent::PlayerJoined(
+                    captures.name("player")?.as_str().to_string(),
+                )),
+                LogPatternKind::PlayerLeft => Some(LogEvent::PlayerLeft(
+                    captures.name("player")?.as_str().to_string(),
+                )),
+                LogPatternKind::Ready => Some(LogEvent::Ready),
+                LogPatternKind::Error => Some(LogEvent::Error(
+                    captures
+                        .name("message")
+                        .map_or(line, |message| message.as_str())
+                        .to_string(),
+                )),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::t_configurable::MinecraftVariant;
+
+    #[test]
+    fn test_custom_patterns_come_first() {
+        let game = Game::MinecraftJava {
+            variant: MinecraftVariant::Paper,
+        };
+        let parser = LineParser::new(&game, &[]);
+        assert_eq!(
+            parser.parse("[12:00:00 INFO]: Steve joined the game"),
+            Some(LogEvent::PlayerJoined("Steve".to_string()))
+        );
+        assert_eq!(
+            parser.parse("[12:00:00 INFO]: <Alex> Steve joined the game"),
+            None
+        );
+
+        let custom = [LogPattern::new(
+            LogPatternKind::PlayerJoined,
+            r"\[connected player\] (?P<player>\S+) \(.+\) has connected",
+        )];
+        let parser = LineParser::new(&game, &custom);
+        assert_eq!(
+            parser
+                .parse("[12:00:00 INFO]: [connected player] Steve (/127.0.0.1:5123) has connected"),
+            Some(LogEvent::PlayerJoined("Steve".to_string()))
+        );
+        assert_eq!(
+            parser.parse("[12:00:00 INFO]: Done (3.2s)! For help, type \"help\""),
+            Some(LogEvent::Ready)
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[LogPattern::new(LogPatternKind::Ready, r"Listening on")]).is_ok());
+        assert!(validate(&[LogPattern::new(LogPatternKind::Ready, r"(unclosed")]).is_err());
+        assert!(validate(&[LogPattern::new(
+            LogPatternKind::PlayerLeft,
+            r"(\S+) disconnected"
+        )])
+        .is_err());
+    }
+}
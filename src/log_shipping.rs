@@ -0,0 +1,606 @@
+//! Shipping console output and events of instances to a central log system.
+//!
+//! Lines can go to a syslog server (RFC 5424 over UDP or TCP), Loki or Elasticsearch. Each
+//! instance uses its own destination if it has one and the core-wide one otherwise. Lines are
+//! sent in batches and kept in a bounded buffer while the destination can't be reached, retrying
+//! with a growing delay, so the oldest lines are the first to go if it stays down for long.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{Event, EventInner, EventLevel, InstanceEventInner},
+    remote_backup::SECRET_PLACEHOLDER,
+    types::InstanceUuid,
+    AppState,
+};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the resolved settings of an instance are used before being looked up again
+const SETTINGS_REFRESH: Duration = Duration::from_secs(10);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_BATCH: usize = 500;
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+pub const MAX_BUFFER_LINES: u32 = 100_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum SyslogTransport {
+    Udp,
+    Tcp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum LogDestination {
+    Syslog {
+        /// `host:port`
+        address: String,
+        transport: SyslogTransport,
+    },
+    Loki {
+        /// e.g. `http://loki:3100`, lines are pushed to `/loki/api/v1/push` under it
+        url: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    Elasticsearch {
+        /// e.g. `https://elastic:9200`, lines are sent to `/_bulk` under it
+        url: String,
+        index: String,
+        api_key: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct LogShippingSettings {
+    pub destination: LogDestination,
+    #[serde(default = "default_true")]
+    pub console: bool,
+    /// Events of the instance other than its console, as JSON
+    #[serde(default)]
+    pub events: bool,
+    /// Lines kept while the destination can't be reached
+    #[serde(default = "default_buffer_lines")]
+    pub buffer_lines: u32,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_buffer_lines() -> u32 {
+    10_000
+}
+
+fn validate_url(url: &str) -> Result<(), Error> {
+    let url = url::Url::parse(url).map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid log shipping URL: {e}"),
+    })?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Log shipping URL must be http or https"),
+        });
+    }
+    Ok(())
+}
+
+impl LogShippingSettings {
+    /// A copy that is safe to hand out to clients
+    pub fn redacted(&self) -> Self {
+        let mut ret = self.clone();
+        match &mut ret.destination {
+            LogDestination::Loki {
+                password: Some(password),
+                ..
+            } => *password = SECRET_PLACEHOLDER.to_string(),
+            LogDestination::Elasticsearch {
+                api_key: Some(api_key),
+                ..
+            } => *api_key = SECRET_PLACEHOLDER.to_string(),
+            _ => {}
+        }
+        ret
+    }
+
+    /// Fills in credentials a client sent back as [`SECRET_PLACEHOLDER`]
+    pub fn keep_secrets_from(&mut self, old: &LogShippingSettings) {
+        match (&mut self.destination, &old.destination) {
+            (
+                LogDestination::Loki { password, .. },
+                LogDestination::Loki {
+                    password: old_password,
+                    ..
+                },
+            ) if password.as_deref() == Some(SECRET_PLACEHOLDER) => {
+                *password = old_password.clone()
+            }
+            (
+                LogDestination::Elasticsearch { api_key, .. },
+                LogDestination::Elasticsearch {
+                    api_key: old_api_key,
+                    ..
+                },
+            ) if api_key.as_deref() == Some(SECRET_PLACEHOLDER) => *api_key = old_api_key.clone(),
+            _ => {}
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.buffer_lines == 0 || self.buffer_lines > MAX_BUFFER_LINES {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Log shipping buffer must be between 1 and {MAX_BUFFER_LINES} lines"),
+            });
+        }
+        if !self.console && !self.events {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Log shipping must send console output, events or both"),
+            });
+        }
+        match &self.destination {
+            LogDestination::Syslog { address, .. } => {
+                let valid = address.rsplit_once(':').map_or(false, |(host, port)| {
+                    !host.is_empty() && port.parse::<u16>().is_ok()
+                });
+                if !valid {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Syslog address must be host:port"),
+                    });
+                }
+            }
+            LogDestination::Loki { url, .. } => validate_url(url)?,
+            LogDestination::Elasticsearch { url, index, .. } => {
+                validate_url(url)?;
+                if index.is_empty() || index.chars().any(|c| c.is_ascii_uppercase()) {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Elasticsearch index must be a non-empty lowercase name"),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum LineKind {
+    Console,
+    Event,
+}
+
+impl LineKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LineKind::Console => "console",
+            LineKind::Event => "event",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LogLine {
+    time_millis: i64,
+    instance_uuid: InstanceUuid,
+    instance_name: String,
+    kind: LineKind,
+    level: EventLevel,
+    message: String,
+}
+
+impl LogLine {
+    fn from_event(event: &Event) -> Option<LogLine> {
+        let instance_event = match &event.event_inner {
+            EventInner::InstanceEvent(instance_event) => instance_event,
+            _ => return None,
+        };
+        let (kind, message) = match &instance_event.instance_event_inner {
+            InstanceEventInner::InstanceOutput { message, .. }
+            | InstanceEventInner::SystemMessage { message } => (LineKind::Console, message.clone()),
+            InstanceEventInner::PlayerMessage {
+                player,
+                player_message,
+            } => (LineKind::Console, format!("<{player}> {player_message}")),
+            inner => (LineKind::Event, serde_json::to_string(inner).ok()?),
+        };
+        Some(LogLine {
+            time_millis: event.snowflake.timestamp_millis(),
+            instance_uuid: instance_event.instance_uuid.clone(),
+            instance_name: instance_event.instance_name.clone(),
+            kind,
+            level: event.level(),
+            message,
+        })
+    }
+
+    fn rfc3339(&self) -> String {
+        chrono::DateTime::<chrono::Utc>::from_utc(
+            chrono::NaiveDateTime::from_timestamp_millis(self.time_millis).unwrap_or_default(),
+            chrono::Utc,
+        )
+        .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+    }
+
+    fn level_str(&self) -> &'static str {
+        match self.level {
+            EventLevel::Info => "info",
+            EventLevel::Warning => "warning",
+            EventLevel::Error => "error",
+        }
+    }
+}
+
+/// An RFC 5424 message, from the user facility
+fn syslog_message(line: &LogLine, hostname: &str) -> String {
+    let severity = match line.level {
+        EventLevel::Error => 3,
+        EventLevel::Warning => 4,
+        EventLevel::Info => 6,
+    };
+    format!(
+        "<{}>1 {} {} lodestone - {} - [{}] {}",
+        8 + severity,
+        line.rfc3339(),
+        hostname,
+        line.kind.as_str(),
+        line.instance_name,
+        line.message
+    )
+}
+
+/// One stream for each kind and level of line
+fn loki_body(lines: &[LogLine]) -> serde_json::Value {
+    let mut streams: Vec<(
+        (LineKind, &'static str),
+        serde_json::Value,
+        Vec<serde_json::Value>,
+    )> = Vec::new();
+    for line in lines {
+        let key = (line.kind, line.level_str());
+        let value = json!([(line.time_millis * 1_000_000).to_string(), line.message]);
+        match streams.iter_mut().find(|(k, ..)| *k == key) {
+            Some((_, _, values)) => values.push(value),
+            None => streams.push((
+                key,
+                json!({
+                    "job": "lodestone",
+                    "instance": line.instance_name,
+                    "instance_uuid": line.instance_uuid,
+                    "kind": line.kind.as_str(),
+                    "level": line.level_str(),
+                }),
+                vec![value],
+            )),
+        }
+    }
+    json!({
+        "streams": streams
+            .into_iter()
+            .map(|(_, stream, values)| json!({ "stream": stream, "values": values }))
+            .collect::<Vec<_>>()
+    })
+}
+
+fn elasticsearch_body(lines: &[LogLine], index: &str) -> String {
+    let mut body = String::new();
+    for line in lines {
+        body.push_str(&json!({ "index": { "_index": index } }).to_string());
+        body.push('\n');
+        body.push_str(
+            &json!({
+                "@timestamp": line.rfc3339(),
+                "instance_uuid": line.instance_uuid,
+                "instance_name": line.instance_name,
+                "kind": line.kind.as_str(),
+                "level": line.level_str(),
+                "message": line.message,
+            })
+            .to_string(),
+        );
+        body.push('\n');
+    }
+    body
+}
+
+async fn send_syslog(
+    address: &str,
+    transport: SyslogTransport,
+    lines: &[LogLine],
+) -> Result<(), Error> {
+    let hostname = whoami::hostname();
+    match transport {
+        SyslogTransport::Udp => {
+            let socket = tokio::net::UdpSocket::bind("0.0.0.0:0")
+                .await
+                .context("Failed to open a UDP socket")?;
+            for line in lines {
+                socket
+                    .send_to(syslog_message(line, &hostname).as_bytes(), address)
+                    .await
+                    .context(format!("Failed to send to syslog at {address}"))?;
+            }
+        }
+        SyslogTransport::Tcp => {
+            let mut stream = tokio::net::TcpStream::connect(address)
+                .await
+                .context(format!("Failed to connect to syslog at {address}"))?;
+            // octet counting, so messages may hold newlines
+            let mut frames = String::new();
+            for line in lines {
+                let message = syslog_message(line, &hostname);
+                frames.push_str(&format!("{} {}", message.len(), message));
+            }
+            stream
+                .write_all(frames.as_bytes())
+                .await
+                .context(format!("Failed to send to syslog at {address}"))?;
+            stream.shutdown().await.ok();
+        }
+    }
+    Ok(())
+}
+
+async fn send(destination: &LogDestination, lines: &[LogLine]) -> Result<(), Error> {
+    let client = reqwest::Client::new();
+    match destination {
+        LogDestination::Syslog { address, transport } => {
+            tokio::time::timeout(SEND_TIMEOUT, send_syslog(address, *transport, lines))
+                .await
+                .map_err(|_| eyre!("Timed out sending to syslog at {address}"))??;
+        }
+        LogDestination::Loki {
+            url,
+            username,
+            password,
+        } => {
+            let mut request = client
+                .post(format!("{}/loki/api/v1/push", url.trim_end_matches('/')))
+                .timeout(SEND_TIMEOUT)
+                .json(&loki_body(lines));
+            if let Some(username) = username {
+                request = request.basic_auth(username, password.as_ref());
+            }
+            request
+                .send()
+                .await
+                .context("Failed to send logs to Loki")?
+                .error_for_status()
+                .context("Loki refused the logs")?;
+        }
+        LogDestination::Elasticsearch {
+            url,
+            index,
+            api_key,
+        } => {
+            let mut request = client
+                .post(format!("{}/_bulk", url.trim_end_matches('/')))
+                .timeout(SEND_TIMEOUT)
+                .header("Content-Type", "application/x-ndjson")
+                .body(elasticsearch_body(lines, index));
+            if let Some(api_key) = api_key {
+                request = request.header("Authorization", format!("ApiKey {api_key}"));
+            }
+            let response: serde_json::Value = request
+                .send()
+                .await
+                .context("Failed to send logs to Elasticsearch")?
+                .error_for_status()
+                .context("Elasticsearch refused the logs")?
+                .json()
+                .await
+                .context("Invalid response from Elasticsearch")?;
+            if response["errors"].as_bool() == Some(true) {
+                return Err(eyre!("Elasticsearch failed to index some of the logs").into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Buffers the lines of one instance and sends them, until the sender is dropped
+async fn ship(uuid: InstanceUuid, settings: LogShippingSettings, mut rx: mpsc::Receiver<LogLine>) {
+    let mut buffer: VecDeque<LogLine> = VecDeque::new();
+    let mut dropped = 0_usize;
+    let mut retry_delay = MIN_RETRY_DELAY;
+    let mut retry_at = Instant::now();
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    let mut closed = false;
+    while !closed || !buffer.is_empty() {
+        tokio::select! {
+            line = rx.recv(), if !closed => match line {
+                Some(line) => {
+                    buffer.push_back(line);
+                    if buffer.len() > settings.buffer_lines as usize {
+                        buffer.pop_front();
+                        dropped += 1;
+                    }
+                }
+                None => closed = true,
+            },
+            _ = interval.tick() => {
+                if buffer.is_empty() || Instant::now() < retry_at {
+                    continue;
+                }
+                let batch: Vec<LogLine> = buffer.iter().take(MAX_BATCH).cloned().collect();
+                match send(&settings.destination, &batch).await {
+                    Ok(_) => {
+                        buffer.drain(..batch.len());
+                        retry_delay = MIN_RETRY_DELAY;
+                        if dropped > 0 {
+                            warn!("Dropped {dropped} log lines of {uuid} while shipping was failing");
+                            dropped = 0;
+                        }
+                    }
+                    Err(e) => {
+                        if retry_delay == MIN_RETRY_DELAY {
+                            warn!("Failed to ship logs of {uuid}, retrying: {e}");
+                        }
+                        // the destination was taken away, whatever is left goes with it
+                        if closed {
+                            break;
+                        }
+                        retry_at = Instant::now() + retry_delay;
+                        retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Shipper {
+    settings: LogShippingSettings,
+    tx: mpsc::Sender<LogLine>,
+}
+
+/// Hands the console output and events of every instance to its shipper
+pub async fn run_log_shipping(state: AppState) {
+    let mut rx = state.event_broadcaster.subscribe();
+    let mut resolved: HashMap<InstanceUuid, Option<LogShippingSettings>> = HashMap::new();
+    let mut resolved_at = Instant::now();
+    let mut shippers: HashMap<InstanceUuid, Shipper> = HashMap::new();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        let line = match LogLine::from_event(&event) {
+            Some(line) => line,
+            None => continue,
+        };
+        if resolved_at.elapsed() >= SETTINGS_REFRESH {
+            resolved.clear();
+            resolved_at = Instant::now();
+        }
+        let settings = match resolved.get(&line.instance_uuid) {
+            Some(settings) => settings.clone(),
+            None => {
+                let settings = state
+                    .global_settings
+                    .lock()
+                    .await
+                    .log_shipping(&line.instance_uuid);
+                resolved.insert(line.instance_uuid.clone(), settings.clone());
+                settings
+            }
+        };
+        let settings = match settings {
+            Some(settings) => settings,
+            None => {
+                shippers.remove(&line.instance_uuid);
+                continue;
+            }
+        };
+        let wanted = match line.kind {
+            LineKind::Console => settings.console,
+            LineKind::Event => settings.events,
+        };
+        if shippers
+            .get(&line.instance_uuid)
+            .map_or(true, |shipper| shipper.settings != settings)
+        {
+            let (tx, rx) = mpsc::channel(settings.buffer_lines as usize);
+            tokio::spawn(ship(line.instance_uuid.clone(), settings.clone(), rx));
+            shippers.insert(line.instance_uuid.clone(), Shipper { settings, tx });
+        }
+        if wanted {
+            if let Some(shipper) = shippers.get(&line.instance_uuid) {
+                // the shipper is behind by a whole buffer, the line is dropped
+                let _ = shipper.tx.try_send(line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(kind: LineKind, level: EventLevel, message: &str) -> LogLine {
+        LogLine {
+            time_millis: 1_700_000_000_123,
+            instance_uuid: InstanceUuid::from("INSTANCE_1".to_string()),
+            instance_name: "survival".to_string(),
+            kind,
+            level,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_syslog_message() {
+        assert_eq!(
+            syslog_message(
+                &line(LineKind::Console, EventLevel::Warning, "Can't keep up!"),
+                "host"
+            ),
+            "<12>1 2023-11-14T22:13:20.123Z host lodestone - console - [survival] Can't keep up!"
+        );
+    }
+
+    #[test]
+    fn test_loki_body() {
+        let body = loki_body(&[
+            line(LineKind::Console, EventLevel::Info, "a"),
+            line(LineKind::Event, EventLevel::Info, "b"),
+            line(LineKind::Console, EventLevel::Info, "c"),
+        ]);
+        let streams = body["streams"].as_array().unwrap();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0]["stream"]["kind"], "console");
+        assert_eq!(streams[0]["values"][1][1], "c");
+        assert_eq!(streams[0]["values"][0][0], "1700000000123000000");
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut settings = LogShippingSettings {
+            destination: LogDestination::Syslog {
+                address: "logs.example.com:514".to_string(),
+                transport: SyslogTransport::Udp,
+            },
+            console: true,
+            events: false,
+            buffer_lines: default_buffer_lines(),
+        };
+        assert!(settings.validate().is_ok());
+        settings.destination = LogDestination::Syslog {
+            address: "logs.example.com".to_string(),
+            transport: SyslogTransport::Udp,
+        };
+        assert!(settings.validate().is_err());
+        settings.destination = LogDestination::Elasticsearch {
+            url: "https://elastic:9200".to_string(),
+            index: "Lodestone".to_string(),
+            api_key: None,
+        };
+        assert!(settings.validate().is_err());
+        settings.destination = LogDestination::Loki {
+            url: "http://loki:3100".to_string(),
+            username: None,
+            password: None,
+        };
+        assert!(settings.validate().is_ok());
+        settings.console = false;
+        assert!(settings.validate().is_err());
+    }
+}
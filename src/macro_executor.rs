@@ -1,6 +1,9 @@
 use std::{
-    fmt::{Debug, Display},
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::Debug,
     path::PathBuf,
+    rc::Rc,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
@@ -12,11 +15,10 @@ use color_eyre::eyre::Context;
 use dashmap::DashMap;
 use deno_runtime::permissions::Permissions;
 use futures_util::Future;
-use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::{runtime::Builder, sync::mpsc, task::LocalSet};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, log::warn};
-use ts_rs::TS;
 
 use crate::{
     deno_ops::events::register_all_event_ops,
@@ -25,6 +27,7 @@ use crate::{
     events::{CausedBy, EventInner, MacroEvent, MacroEventInner},
     traits::t_macro::ExitStatus,
     types::InstanceUuid,
+    wasm_macro::{WasmLimits, WasmMacro, WasmMacroHost},
 };
 
 use color_eyre::eyre::eyre;
@@ -42,7 +45,7 @@ use deno_core::ModuleSpecifier;
 use deno_core::ModuleType;
 use deno_core::ResolutionKind;
 use deno_core::{anyhow, error::generic_error};
-use deno_core::{resolve_import, ModuleCode};
+use deno_core::{op, resolve_import, ModuleCode, OpState};
 
 use futures::FutureExt;
 
@@ -53,34 +56,7 @@ pub struct TypescriptModuleLoader {
     http: reqwest::Client,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, TS)]
-#[serde(transparent)]
-#[ts(export)]
-pub struct MacroPID(pub usize); // todo remove pub
-
-impl From<MacroPID> for usize {
-    fn from(uid: MacroPID) -> Self {
-        uid.0
-    }
-}
-
-impl From<&MacroPID> for usize {
-    fn from(uid: &MacroPID) -> Self {
-        uid.0
-    }
-}
-
-impl AsRef<usize> for MacroPID {
-    fn as_ref(&self) -> &usize {
-        &self.0
-    }
-}
-
-impl Display for MacroPID {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "MacroPID({})", self.0)
-    }
-}
+pub use lodestone_types::MacroPID;
 
 impl Default for TypescriptModuleLoader {
     fn default() -> Self {
@@ -193,9 +169,52 @@ impl ModuleLoader for TypescriptModuleLoader {
     }
 }
 
+/// Lines a macro printed, only the last ones are kept
+const MAX_OUTPUT_LINES: usize = 1000;
+
+/// Makes `console` also hand what is printed to the core
+const CAPTURE_OUTPUT_JS: &str = r#"
+((ops) => {
+    for (const level of ["log", "info", "warn", "error", "debug"]) {
+        const original = globalThis.console[level];
+        globalThis.console[level] = (...args) => {
+            original(...args);
+            ops.capture_output(
+                args.map((arg) => typeof arg === "string" ? arg : Deno.inspect(arg)).join(" "),
+            );
+        };
+    }
+})(Deno.core.ops);
+"#;
+
+/// What a macro printed, shared with the thread running it
+#[derive(Clone, Debug, Default)]
+pub struct MacroOutput(Arc<std::sync::Mutex<VecDeque<String>>>);
+
+impl MacroOutput {
+    pub fn push(&self, line: String) {
+        let mut lines = self.0.lock().unwrap();
+        if lines.len() == MAX_OUTPUT_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[op]
+fn capture_output(state: Rc<RefCell<OpState>>, line: String) {
+    state.borrow().borrow::<MacroOutput>().push(line);
+}
+
 #[derive(Clone, Debug)]
 pub struct MacroExecutor {
     macro_process_table: Arc<DashMap<MacroPID, deno_core::v8::IsolateHandle>>,
+    wasm_process_table: Arc<DashMap<MacroPID, CancellationToken>>,
+    output_table: Arc<DashMap<MacroPID, MacroOutput>>,
     exit_status_table: Arc<DashMap<MacroPID, ExitStatus>>,
     channel_table:
         Arc<DashMap<MacroPID, (mpsc::UnboundedSender<Value>, mpsc::UnboundedSender<Value>)>>,
@@ -237,6 +256,8 @@ impl MacroExecutor {
 
         MacroExecutor {
             macro_process_table: process_table,
+            wasm_process_table: Arc::new(DashMap::new()),
+            output_table: Arc::new(DashMap::new()),
             event_broadcaster,
             channel_table: Arc::new(DashMap::new()),
             exit_status_table,
@@ -280,6 +301,8 @@ impl MacroExecutor {
             &std::env::current_dir().context("Failed to get current directory")?,
         )
         .context("Failed to resolve path")?;
+        let output = MacroOutput::default();
+        self.output_table.insert(pid, output.clone());
         let rt = Builder::new_current_thread().enable_all().build().unwrap();
         std::thread::spawn({
             let process_table = self.macro_process_table.clone();
@@ -289,6 +312,15 @@ impl MacroExecutor {
                 local.spawn_local(async move {
                     let mut worker_option = worker_options_generator.generate();
                     register_all_event_ops(&mut worker_option, event_broadcaster.clone());
+                    worker_option.extensions.push(
+                        deno_core::Extension::builder("macro_output")
+                            .ops(vec![capture_output::decl()])
+                            .state(move |state| {
+                                state.put(output);
+                            })
+                            .force_op_registration()
+                            .build(),
+                    );
                     worker_option.bootstrap.args = args;
 
                     let mut main_worker = deno_runtime::worker::MainWorker::from_options(
@@ -299,6 +331,13 @@ impl MacroExecutor {
                         worker_option,
                     );
 
+                    if let Err(e) = main_worker.execute_script(
+                        "[lodestone:capture_output]",
+                        ModuleCode::Static(CAPTURE_OUTPUT_JS),
+                    ) {
+                        warn!("Failed to capture the output of macro {pid}: {e}");
+                    }
+
                     let isolate_handle = main_worker.js_runtime.v8_isolate().thread_safe_handle();
 
                     process_table.insert(pid, isolate_handle);
@@ -458,8 +497,58 @@ impl MacroExecutor {
         })
     }
 
+    /// Runs a WebAssembly macro, see [`crate::wasm_macro`]
+    pub async fn spawn_wasm(
+        &self,
+        path_to_module: PathBuf,
+        args: Vec<String>,
+        _caused_by: CausedBy,
+        host: Arc<dyn WasmMacroHost>,
+        limits: WasmLimits,
+        instance_uuid: Option<InstanceUuid>,
+    ) -> Result<MacroPID, Error> {
+        let bytes = tokio::fs::read(&path_to_module)
+            .await
+            .context(format!("Failed to read {}", path_to_module.display()))?;
+        let wasm_macro = WasmMacro::new(&bytes, limits)?;
+        let pid = MacroPID(self.next_process_id.fetch_add(1, Ordering::SeqCst));
+        let stop = CancellationToken::new();
+        self.wasm_process_table.insert(pid, stop.clone());
+        let output = MacroOutput::default();
+        self.output_table.insert(pid, output.clone());
+        self.event_broadcaster.send(
+            MacroEvent {
+                macro_pid: pid,
+                macro_event_inner: MacroEventInner::Started,
+                instance_uuid: instance_uuid.clone(),
+            }
+            .into(),
+        );
+        tokio::spawn({
+            let wasm_process_table = self.wasm_process_table.clone();
+            let event_broadcaster = self.event_broadcaster.clone();
+            async move {
+                let exit_status = wasm_macro.run(args, host, output, stop).await;
+                wasm_process_table.remove(&pid);
+                event_broadcaster.send(
+                    MacroEvent {
+                        macro_pid: pid,
+                        macro_event_inner: MacroEventInner::Stopped { exit_status },
+                        instance_uuid,
+                    }
+                    .into(),
+                );
+            }
+        });
+        Ok(pid)
+    }
+
     /// abort a macro execution
     pub fn abort_macro(&self, pid: MacroPID) -> Result<(), Error> {
+        if let Some((_, stop)) = self.wasm_process_table.remove(&pid) {
+            stop.cancel();
+            return Ok(());
+        }
         self.macro_process_table
             .get(&pid)
             .ok_or_else(|| Error {
@@ -525,6 +614,14 @@ impl MacroExecutor {
     pub async fn get_macro_status(&self, pid: MacroPID) -> Option<ExitStatus> {
         self.exit_status_table.get(&pid).map(|v| v.clone())
     }
+
+    /// The last lines the macro printed, which are no longer kept once taken
+    pub fn take_output(&self, pid: MacroPID) -> Vec<String> {
+        self.output_table
+            .remove(&pid)
+            .map(|(_, output)| output.lines())
+            .unwrap_or_default()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,241 @@
+//! Runs of macros started from the API, kept in the database once they are over.
+//!
+//! The in-memory task history only covers macros run since the core started. Here every run is
+//! kept with who started it, its arguments, how it ended and the last lines it printed, so
+//! automation that failed overnight can still be looked into. Only the latest runs of each
+//! instance are kept.
+
+use color_eyre::eyre::Context;
+use serde::Serialize;
+use sqlx::{sqlite::SqlitePool, Row};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    events::{CausedBy, EventInner, MacroEvent, MacroEventInner},
+    macro_executor::MacroPID,
+    traits::t_macro::{ExitStatus, TaskEntry},
+    types::InstanceUuid,
+    AppState,
+};
+
+const MAX_RUNS_PER_INSTANCE: i64 = 500;
+pub const DEFAULT_LIMIT: u32 = 50;
+pub const MAX_LIMIT: u32 = 500;
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct MacroRun {
+    pub id: i64,
+    pub instance_uuid: InstanceUuid,
+    pub macro_pid: MacroPID,
+    pub macro_name: String,
+    pub args: Vec<String>,
+    pub caused_by: CausedBy,
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+    /// `None` while the macro runs
+    pub exit_status: Option<ExitStatus>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct MacroRunOutput {
+    pub run: MacroRun,
+    /// The last lines the macro printed, empty while it runs
+    pub output: Vec<String>,
+}
+
+fn run_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<MacroRun, Error> {
+    let args: String = row.try_get("args").context("Failed to read macro run")?;
+    let caused_by: String = row
+        .try_get("caused_by")
+        .context("Failed to read macro run")?;
+    let exit_status: Option<String> = row
+        .try_get("exit_status")
+        .context("Failed to read macro run")?;
+    Ok(MacroRun {
+        id: row.try_get("id").context("Failed to read macro run")?,
+        instance_uuid: row
+            .try_get("instance_uuid")
+            .context("Failed to read macro run")?,
+        macro_pid: MacroPID(
+            row.try_get::<i64, _>("macro_pid")
+                .context("Failed to read macro run")? as usize,
+        ),
+        macro_name: row
+            .try_get("macro_name")
+            .context("Failed to read macro run")?,
+        args: serde_json::from_str(&args).unwrap_or_default(),
+        caused_by: serde_json::from_str(&caused_by).unwrap_or(CausedBy::Unknown),
+        started_at: row
+            .try_get("started_at")
+            .context("Failed to read macro run")?,
+        ended_at: row
+            .try_get("ended_at")
+            .context("Failed to read macro run")?,
+        exit_status: exit_status.and_then(|exit_status| serde_json::from_str(&exit_status).ok()),
+    })
+}
+
+/// Records a run that was just started, finishing it right away if it is already over
+pub async fn record_start(
+    state: &AppState,
+    instance_uuid: &InstanceUuid,
+    task: &TaskEntry,
+    args: &[String],
+    caused_by: &CausedBy,
+) -> Result<(), Error> {
+    let pool = &state.sqlite_pool;
+    sqlx::query(
+        "INSERT INTO MacroRuns (instance_uuid, macro_pid, macro_name, args, caused_by, \
+         started_at) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(instance_uuid)
+    .bind(task.pid.0 as i64)
+    .bind(&task.name)
+    .bind(serde_json::to_string(args).context("Failed to serialize macro arguments")?)
+    .bind(serde_json::to_string(caused_by).context("Failed to serialize cause")?)
+    .bind(task.creation_time)
+    .execute(pool)
+    .await
+    .context("Failed to record macro run")?;
+    sqlx::query(
+        "DELETE FROM MacroRuns WHERE instance_uuid = ? AND id NOT IN \
+         (SELECT id FROM MacroRuns WHERE instance_uuid = ? ORDER BY id DESC LIMIT ?)",
+    )
+    .bind(instance_uuid)
+    .bind(instance_uuid)
+    .bind(MAX_RUNS_PER_INSTANCE)
+    .execute(pool)
+    .await
+    .context("Failed to prune macro runs")?;
+    // the macro may have stopped before the run was recorded
+    if let Some(exit_status) = state.macro_executor.get_macro_status(task.pid).await {
+        let output = state.macro_executor.take_output(task.pid);
+        finish(pool, instance_uuid, task.pid, &exit_status, &output).await?;
+    }
+    Ok(())
+}
+
+async fn finish(
+    pool: &SqlitePool,
+    instance_uuid: &InstanceUuid,
+    pid: MacroPID,
+    exit_status: &ExitStatus,
+    output: &[String],
+) -> Result<(), Error> {
+    sqlx::query(
+        "UPDATE MacroRuns SET ended_at = ?, exit_status = ?, output = ? \
+         WHERE instance_uuid = ? AND macro_pid = ? AND ended_at IS NULL",
+    )
+    .bind(exit_status.time())
+    .bind(serde_json::to_string(exit_status).context("Failed to serialize exit status")?)
+    .bind(serde_json::to_string(output).context("Failed to serialize macro output")?)
+    .bind(instance_uuid)
+    .bind(pid.0 as i64)
+    .execute(pool)
+    .await
+    .context("Failed to record the end of a macro run")?;
+    Ok(())
+}
+
+/// The latest runs of the instance first, without their output
+pub async fn list_runs(
+    pool: &SqlitePool,
+    instance_uuid: &InstanceUuid,
+    before: Option<i64>,
+    limit: u32,
+) -> Result<Vec<MacroRun>, Error> {
+    sqlx::query(
+        "SELECT id, instance_uuid, macro_pid, macro_name, args, caused_by, started_at, \
+         ended_at, exit_status FROM MacroRuns WHERE instance_uuid = ? AND id < ? \
+         ORDER BY id DESC LIMIT ?",
+    )
+    .bind(instance_uuid)
+    .bind(before.unwrap_or(i64::MAX))
+    .bind(limit.min(MAX_LIMIT))
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch macro runs")?
+    .iter()
+    .map(run_from_row)
+    .collect()
+}
+
+pub async fn get_run_output(
+    pool: &SqlitePool,
+    instance_uuid: &InstanceUuid,
+    id: i64,
+) -> Result<Option<MacroRunOutput>, Error> {
+    let row = match sqlx::query("SELECT * FROM MacroRuns WHERE instance_uuid = ? AND id = ?")
+        .bind(instance_uuid)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to fetch macro run")?
+    {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let output: String = row.try_get("output").context("Failed to read macro run")?;
+    Ok(Some(MacroRunOutput {
+        run: run_from_row(&row)?,
+        output: serde_json::from_str(&output).unwrap_or_default(),
+    }))
+}
+
+/// Runs the core went down in the middle of never got an end
+async fn interrupt_unfinished(pool: &SqlitePool) -> Result<(), Error> {
+    let now = chrono::Utc::now().timestamp();
+    let exit_status = ExitStatus::Error {
+        time: now,
+        error_msg: "The core stopped while the macro was running".to_string(),
+    };
+    sqlx::query("UPDATE MacroRuns SET ended_at = ?, exit_status = ? WHERE ended_at IS NULL")
+        .bind(now)
+        .bind(serde_json::to_string(&exit_status).context("Failed to serialize exit status")?)
+        .execute(pool)
+        .await
+        .context("Failed to close interrupted macro runs")?;
+    Ok(())
+}
+
+/// Records how runs end, with what they printed
+pub async fn run_macro_history(state: AppState) {
+    if let Err(e) = interrupt_unfinished(&state.sqlite_pool).await {
+        warn!("{e}");
+    }
+    let mut rx = state.event_broadcaster.subscribe();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        if let EventInner::MacroEvent(MacroEvent {
+            instance_uuid,
+            macro_pid,
+            macro_event_inner: MacroEventInner::Stopped { exit_status },
+        }) = event.event_inner
+        {
+            // taken either way, output of macros that aren't recorded isn't kept
+            let output = state.macro_executor.take_output(macro_pid);
+            if let Some(instance_uuid) = instance_uuid {
+                if let Err(e) = finish(
+                    &state.sqlite_pool,
+                    &instance_uuid,
+                    macro_pid,
+                    &exit_status,
+                    &output,
+                )
+                .await
+                {
+                    warn!("{e}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Outgoing email, only available once the owner has configured an SMTP server.
+
+use color_eyre::eyre::{eyre, Context};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, Address, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    remote_backup::SECRET_PLACEHOLDER,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum SmtpSecurity {
+    /// Plain text, only for servers on the same machine or network
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// e.g. `Lodestone <lodestone@example.com>`
+    pub from: String,
+}
+
+impl SmtpConfig {
+    pub fn redacted(&self) -> Self {
+        let mut ret = self.clone();
+        if ret.password.is_some() {
+            ret.password = Some(SECRET_PLACEHOLDER.to_string());
+        }
+        ret
+    }
+
+    /// Fills in a password the client sent back as [`SECRET_PLACEHOLDER`]
+    pub fn keep_secrets_from(&mut self, old: &SmtpConfig) {
+        if self.password.as_deref() == Some(SECRET_PLACEHOLDER) {
+            self.password = old.password.clone();
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        self.from.parse::<Mailbox>().map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid sender address: {e}"),
+        })?;
+        Ok(())
+    }
+}
+
+pub fn validate_address(address: &str) -> Result<(), Error> {
+    address.parse::<Address>().map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid email address: {e}"),
+    })?;
+    Ok(())
+}
+
+pub async fn send_mail(
+    config: &SmtpConfig,
+    to: &str,
+    subject: &str,
+    body: String,
+) -> Result<(), Error> {
+    let message = Message::builder()
+        .from(
+            config
+                .from
+                .parse::<Mailbox>()
+                .context("Invalid sender address")?,
+        )
+        .to(to.parse::<Mailbox>().context("Invalid recipient address")?)
+        .subject(subject)
+        .body(body)
+        .context("Failed to build email")?;
+    let mut transport = match config.security {
+        SmtpSecurity::None => {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(config.host.as_str())
+        }
+        SmtpSecurity::StartTls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .context("Failed to set up SMTP connection")?
+        }
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+            .context("Failed to set up SMTP connection")?,
+    }
+    .port(config.port);
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    transport
+        .build()
+        .send(message)
+        .await
+        .context("Failed to send email")?;
+    Ok(())
+}
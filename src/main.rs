@@ -6,5 +6,16 @@ use lodestone_core::Args;
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    #[cfg(windows)]
+    if args.service {
+        if let Err(e) =
+            tokio::task::spawn_blocking(move || lodestone_core::service::windows::run(args))
+                .await
+                .unwrap()
+        {
+            eprintln!("Failed to start as a Windows service: {e}");
+        }
+        return;
+    }
     lodestone_core::run(args).await.0.await;
 }
@@ -0,0 +1,172 @@
+//! Maintenance mode, during which only owners and admins can change anything through the API.
+//! Reads keep working for everyone.
+
+use axum::{
+    http::{header, HeaderName, HeaderValue, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use color_eyre::{eyre::eyre, Report};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    AppState,
+};
+
+/// Unix timestamp of when the current or upcoming maintenance started or starts
+const MAINTENANCE_START_HEADER: &str = "x-lodestone-maintenance-start";
+/// Unix timestamp of when it ends, left out if it lasts until turned off
+const MAINTENANCE_END_HEADER: &str = "x-lodestone-maintenance-end";
+const MAINTENANCE_MESSAGE_HEADER: &str = "x-lodestone-maintenance-message";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct MaintenanceWindow {
+    pub start: i64,
+    /// `None` to stay in maintenance until turned off
+    pub end: Option<i64>,
+    /// Shown to users while the maintenance is upcoming or ongoing
+    pub message: Option<String>,
+}
+
+impl MaintenanceWindow {
+    pub fn is_active(&self, now: i64) -> bool {
+        self.start <= now && !self.is_over(now)
+    }
+
+    pub fn is_over(&self, now: i64) -> bool {
+        self.end.map_or(false, |end| end <= now)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if matches!(self.end, Some(end) if end <= self.start) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Maintenance must end after it starts"),
+            });
+        }
+        if let Some(message) = &self.message {
+            // it is sent back as a header
+            if message.chars().any(char::is_control) {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Maintenance message can't contain control characters"),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_headers(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+        headers.insert(
+            HeaderName::from_static(MAINTENANCE_START_HEADER),
+            HeaderValue::from(self.start),
+        );
+        if let Some(end) = self.end {
+            headers.insert(
+                HeaderName::from_static(MAINTENANCE_END_HEADER),
+                HeaderValue::from(end),
+            );
+        }
+        if let Some(message) = self
+            .message
+            .as_ref()
+            .and_then(|message| HeaderValue::from_bytes(message.as_bytes()).ok())
+        {
+            headers.insert(HeaderName::from_static(MAINTENANCE_MESSAGE_HEADER), message);
+        }
+    }
+}
+
+/// Headers browser clients need to be allowed to read to show a banner
+pub fn exposed_headers() -> [HeaderName; 4] {
+    [
+        HeaderName::from_static(MAINTENANCE_START_HEADER),
+        HeaderName::from_static(MAINTENANCE_END_HEADER),
+        HeaderName::from_static(MAINTENANCE_MESSAGE_HEADER),
+        header::RETRY_AFTER,
+    ]
+}
+
+fn is_read_only(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Rejects mutating requests from everyone but owners and admins while in maintenance,
+/// and tells clients about upcoming or ongoing maintenance through response headers
+pub async fn reject_during_maintenance<B>(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    let maintenance = state.global_settings.lock().await.maintenance();
+    let window = match maintenance {
+        Some(window) if !window.is_over(now) => window,
+        _ => return next.run(request).await,
+    };
+    // admins need to be able to log in to do the maintenance
+    let allowed = !window.is_active(now)
+        || is_read_only(request.method())
+        || request.uri().path().ends_with("/user/login")
+        || match request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            Some(token) => state
+                .users_manager
+                .read()
+                .await
+                .try_auth(token)
+                .map_or(false, |user| user.is_owner || user.is_admin),
+            None => false,
+        };
+    let mut response = if allowed {
+        next.run(request).await
+    } else {
+        let mut source = Report::msg(LocalizedMessage::new(MessageId::MaintenanceMode));
+        if let Some(message) = &window.message {
+            source = source.wrap_err(message.clone());
+        }
+        let mut response = Error {
+            kind: ErrorKind::ServiceUnavailable,
+            source,
+        }
+        .into_response();
+        if let Some(end) = window.end {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from((end - now).max(1)));
+        }
+        response
+    };
+    window.insert_headers(&mut response);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_window() {
+        let mut window = MaintenanceWindow {
+            start: 100,
+            end: Some(200),
+            message: None,
+        };
+        assert!(!window.is_active(99));
+        assert!(window.is_active(100));
+        assert!(!window.is_active(200));
+        assert!(window.is_over(200));
+        window.end = None;
+        assert!(window.is_active(i64::MAX));
+        assert!(!window.is_over(i64::MAX));
+    }
+}
@@ -4,7 +4,15 @@ use color_eyre::eyre::Context;
 use serde_json::{json, Value};
 use tracing::error;
 
-use crate::{error::Error, implementations::minecraft::RestoreConfig};
+use crate::{
+    console_buffer::DEFAULT_CONSOLE_BUFFER_LINES,
+    error::Error,
+    implementations::minecraft::RestoreConfig,
+    process_priority::ProcessPriority,
+    sandbox::SandboxLevel,
+    startup_watchdog::StartupTimeoutAction,
+    wasm_macro::{DEFAULT_WASM_MACRO_MEMORY, DEFAULT_WASM_MACRO_TIMEOUT},
+};
 
 use super::RestoreConfigV042;
 
@@ -25,6 +33,19 @@ impl From<RestoreConfigV042> for RestoreConfig {
             jre_major_version: config.jre_major_version,
             has_started: config.has_started,
             java_cmd: None,
+            use_pty: false,
+            config_files: Vec::new(),
+            bedrock_port: None,
+            console_buffer_lines: DEFAULT_CONSOLE_BUFFER_LINES,
+            gc_logging: false,
+            sandbox: SandboxLevel::Off,
+            priority: ProcessPriority::Normal,
+            log_patterns: Vec::new(),
+            startup_timeout: 0,
+            on_startup_timeout: StartupTimeoutAction::Leave,
+            wasm_macro_memory: DEFAULT_WASM_MACRO_MEMORY,
+            wasm_macro_timeout: DEFAULT_WASM_MACRO_TIMEOUT,
+            disabled_global_macros: Vec::new(),
         }
     }
 }
@@ -0,0 +1,664 @@
+//! Per-instance proxy in front of the game port, filtering connections by IP and counting them.
+//!
+//! With the proxy enabled, players connect to its `listen_port` instead of the instance's own
+//! port, and it only forwards connections the allow and deny lists let through. The instance's
+//! port should then no longer be reachable from outside, which can be ensured by binding the
+//! server to `127.0.0.1`. As every forwarded connection comes from the core, the server itself no
+//! longer sees the address of its players.
+//!
+//! The instance's port is looked up for every new connection, and a new listen port or address
+//! is bound before the old one is let go, so either can be changed without restarting the server.
+//! Connections made through the old one are drained, they carry on for a while and are closed if
+//! still open after that. UDP can be
+//! forwarded too, for Bedrock and Geyser, with a session per client that ends once it goes quiet.
+//! With `wake_on_connect`, a connection to a stopped instance starts it. The client that woke it
+//! is turned away, Minecraft doesn't wait for a server to come up, and can join once it has.
+//! Connections are only handed over once the instance is Running, which it is when the server
+//! says it is ready, not as soon as its port is open.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Context};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    power_schedule::local_now,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+    AppState,
+};
+
+const MAX_RULES: usize = 256;
+const MAX_BLOCKED_ATTEMPTS: usize = 100;
+const MAX_UDP_SESSIONS: usize = 1024;
+const UDP_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+/// How long connections through a port the proxy no longer listens on are kept open
+pub const DEFAULT_DRAIN: Duration = Duration::from_secs(5 * 60);
+pub const MAX_DRAIN: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct NetworkFilterSettings {
+    pub enabled: bool,
+    /// The port the proxy takes connections on
+    pub listen_port: u32,
+    /// The address to listen on, all of them when not set
+    #[serde(default)]
+    pub listen_address: Option<String>,
+    /// Addresses or CIDRs, like `192.168.1.0/24`. When not empty, only these are let through
+    pub allow: Vec<String>,
+    /// Addresses or CIDRs always turned away, even when also allowed
+    pub deny: Vec<String>,
+    /// Also forward UDP on `listen_port`, to the instance's port
+    #[serde(default)]
+    pub udp: bool,
+    /// Start the instance when someone connects while it is stopped, unless its power schedule
+    /// says it shouldn't run
+    #[serde(default)]
+    pub wake_on_connect: bool,
+}
+
+/// Counted since the core started
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ProxyMetrics {
+    pub active_connections: u32,
+    pub total_connections: u64,
+    pub blocked_connections: u64,
+    pub udp_sessions: u32,
+    /// From players to the instance, TCP connections are counted once they close
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub last_connection_at: Option<i64>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct BlockedAttempt {
+    pub ip: String,
+    pub time: i64,
+}
+
+fn parse_rule(rule: &str) -> Option<IpNet> {
+    let rule = rule.trim();
+    IpNet::from_str(rule)
+        .ok()
+        .or_else(|| IpAddr::from_str(rule).ok().map(IpNet::from))
+}
+
+/// IPv4 clients of a dual stack listener show up as IPv4-mapped IPv6 addresses
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Rules {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl Rules {
+    fn allows(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+impl NetworkFilterSettings {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.allow.len() + self.deny.len() > MAX_RULES {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("At most {MAX_RULES} addresses can be listed"),
+            });
+        }
+        if self.listen_port == 0 || self.listen_port > u16::MAX as u32 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid listen port {}", self.listen_port),
+            });
+        }
+        if let Some(address) = &self.listen_address {
+            if IpAddr::from_str(address.trim()).is_err() {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid listen address {address}"),
+                });
+            }
+        }
+        if let Some(rule) = self
+            .allow
+            .iter()
+            .chain(self.deny.iter())
+            .find(|rule| parse_rule(rule).is_none())
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{rule} is neither an address nor a CIDR"),
+            });
+        }
+        Ok(())
+    }
+
+    fn listen_addr(&self) -> SocketAddr {
+        let ip = self
+            .listen_address
+            .as_deref()
+            .and_then(|address| IpAddr::from_str(address.trim()).ok())
+            .unwrap_or_else(|| IpAddr::from([0, 0, 0, 0]));
+        SocketAddr::new(ip, self.listen_port as u16)
+    }
+
+    fn rules(&self) -> Rules {
+        Rules {
+            allow: self.allow.iter().filter_map(|r| parse_rule(r)).collect(),
+            deny: self.deny.iter().filter_map(|r| parse_rule(r)).collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    rules: Rules,
+    wake_on_connect: bool,
+    waking: bool,
+    blocked: VecDeque<BlockedAttempt>,
+    metrics: ProxyMetrics,
+}
+
+impl Shared {
+    /// Whether a new connection or UDP session from `ip` is let through, counting it either way
+    fn admit(&mut self, ip: IpAddr) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        if !self.rules.allows(ip) {
+            if self.blocked.len() >= MAX_BLOCKED_ATTEMPTS {
+                self.blocked.pop_front();
+            }
+            self.blocked.push_back(BlockedAttempt {
+                ip: canonical(ip).to_string(),
+                time: now,
+            });
+            self.metrics.blocked_connections += 1;
+            return false;
+        }
+        self.metrics.total_connections += 1;
+        self.metrics.last_connection_at = Some(now);
+        true
+    }
+}
+
+/// What is kept of a proxy once it is stopped, so it can still be looked at
+#[derive(Default)]
+struct History {
+    blocked: VecDeque<BlockedAttempt>,
+    metrics: ProxyMetrics,
+}
+
+struct Proxy {
+    listen_addr: SocketAddr,
+    udp: bool,
+    shared: Arc<SyncMutex<Shared>>,
+    tasks: Vec<JoinHandle<()>>,
+    /// Set to close the connections made through the proxy
+    closing: watch::Sender<bool>,
+}
+
+#[derive(Default)]
+pub struct NetworkFilterManager {
+    proxies: HashMap<InstanceUuid, Proxy>,
+    history: HashMap<InstanceUuid, History>,
+}
+
+impl NetworkFilterManager {
+    /// Starts, updates or stops the proxy of an instance to match `settings`
+    pub async fn apply(
+        &mut self,
+        state: &AppState,
+        uuid: &InstanceUuid,
+        settings: Option<&NetworkFilterSettings>,
+    ) -> Result<(), Error> {
+        self.apply_draining(state, uuid, settings, DEFAULT_DRAIN)
+            .await
+    }
+
+    /// Like [`NetworkFilterManager::apply`], with how long connections through a port or address
+    /// that is let go are kept open
+    pub async fn apply_draining(
+        &mut self,
+        state: &AppState,
+        uuid: &InstanceUuid,
+        settings: Option<&NetworkFilterSettings>,
+        drain: Duration,
+    ) -> Result<(), Error> {
+        let settings = settings.filter(|settings| settings.enabled);
+        let settings = match settings {
+            Some(settings) => settings,
+            None => {
+                self.stop(state, uuid, drain).await;
+                return Ok(());
+            }
+        };
+        let listen_addr = settings.listen_addr();
+        if let Some(proxy) = self.proxies.get(uuid) {
+            if listen_addr == proxy.listen_addr && settings.udp == proxy.udp {
+                let mut shared = proxy.shared.lock().unwrap();
+                shared.rules = settings.rules();
+                shared.wake_on_connect = settings.wake_on_connect;
+                return Ok(());
+            }
+        }
+        // bound before the old proxy is stopped, so a failure leaves it running, unless it holds
+        // the port already
+        if self.proxies.get(uuid).map_or(false, |proxy| {
+            proxy.listen_addr.port() == listen_addr.port()
+        }) {
+            self.stop(state, uuid, drain).await;
+        }
+        let listener = TcpListener::bind(listen_addr).await.context(format!(
+            "Failed to listen on port {} for the network filter",
+            settings.listen_port
+        ))?;
+        let socket = if settings.udp {
+            Some(UdpSocket::bind(listen_addr).await.context(format!(
+                "Failed to listen on UDP port {} for the network filter",
+                settings.listen_port
+            ))?)
+        } else {
+            None
+        };
+        let history = match self.proxies.remove(uuid) {
+            Some(proxy) => release(state, proxy, drain).await,
+            None => self.history.remove(uuid).unwrap_or_default(),
+        };
+        let shared = Arc::new(SyncMutex::new(Shared {
+            rules: settings.rules(),
+            wake_on_connect: settings.wake_on_connect,
+            waking: false,
+            blocked: history.blocked,
+            metrics: ProxyMetrics {
+                active_connections: 0,
+                udp_sessions: 0,
+                ..history.metrics
+            },
+        }));
+        let (closing, closed) = watch::channel(false);
+        let mut tasks = vec![tokio::spawn(run_proxy(
+            state.clone(),
+            uuid.clone(),
+            listener,
+            shared.clone(),
+            closed.clone(),
+        ))];
+        if let Some(socket) = socket {
+            tasks.push(tokio::spawn(run_udp_proxy(
+                state.clone(),
+                uuid.clone(),
+                socket,
+                shared.clone(),
+                closed,
+            )));
+        }
+        state
+            .port_manager
+            .lock()
+            .await
+            .add_port(settings.listen_port);
+        info!("Filtering connections to instance {uuid} on {listen_addr}");
+        self.proxies.insert(
+            uuid.clone(),
+            Proxy {
+                listen_addr,
+                udp: settings.udp,
+                shared,
+                tasks,
+                closing,
+            },
+        );
+        Ok(())
+    }
+
+    async fn stop(&mut self, state: &AppState, uuid: &InstanceUuid, drain: Duration) {
+        if let Some(proxy) = self.proxies.remove(uuid) {
+            let history = release(state, proxy, drain).await;
+            self.history.insert(uuid.clone(), history);
+        }
+    }
+
+    /// Stops the proxy and drops what is known of the instance, for when it is deleted
+    pub async fn forget(&mut self, state: &AppState, uuid: &InstanceUuid) {
+        self.stop(state, uuid, Duration::ZERO).await;
+        self.history.remove(uuid);
+    }
+
+    /// Most recent first
+    pub fn blocked_attempts(&self, uuid: &InstanceUuid) -> Vec<BlockedAttempt> {
+        let blocked = match self.proxies.get(uuid) {
+            Some(proxy) => proxy.shared.lock().unwrap().blocked.clone(),
+            None => self
+                .history
+                .get(uuid)
+                .map(|history| history.blocked.clone())
+                .unwrap_or_default(),
+        };
+        blocked.into_iter().rev().collect()
+    }
+
+    pub fn metrics(&self, uuid: &InstanceUuid) -> ProxyMetrics {
+        match self.proxies.get(uuid) {
+            Some(proxy) => proxy.shared.lock().unwrap().metrics.clone(),
+            None => self
+                .history
+                .get(uuid)
+                .map(|history| history.metrics.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Stops taking connections, the ones already forwarded are closed after `drain`
+async fn release(state: &AppState, proxy: Proxy, drain: Duration) -> History {
+    for task in proxy.tasks {
+        task.abort();
+    }
+    state
+        .port_manager
+        .lock()
+        .await
+        .deallocate(proxy.listen_addr.port() as u32);
+    let closing = proxy.closing;
+    tokio::spawn(async move {
+        tokio::time::sleep(drain).await;
+        let _ = closing.send(true);
+    });
+    let mut shared = proxy.shared.lock().unwrap();
+    History {
+        blocked: std::mem::take(&mut shared.blocked),
+        metrics: shared.metrics.clone(),
+    }
+}
+
+/// Resolves once the connections of a proxy are to be closed
+async fn until_closed(closed: &mut watch::Receiver<bool>) {
+    while !*closed.borrow() {
+        if closed.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+enum Upstream {
+    Port(u16),
+    /// Being woken up, the connection is turned away
+    NotReady,
+    /// The instance was removed
+    Gone,
+}
+
+/// Where to forward a new connection to. Starts the instance first if it should be woken up
+async fn upstream(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    shared: &Arc<SyncMutex<Shared>>,
+) -> Upstream {
+    let instance = match state.instances.lock().await.get(uuid).cloned() {
+        Some(instance) => instance,
+        None => return Upstream::Gone,
+    };
+    let port = instance.port().await as u16;
+    let wake_on_connect = shared.lock().unwrap().wake_on_connect;
+    if !wake_on_connect {
+        return Upstream::Port(port);
+    }
+    match instance.state().await {
+        State::Stopped => {}
+        // the port may already be open while the world is still loading
+        State::Starting => return Upstream::NotReady,
+        _ => return Upstream::Port(port),
+    }
+    {
+        let mut shared = shared.lock().unwrap();
+        if shared.waking {
+            return Upstream::NotReady;
+        }
+        shared.waking = true;
+    }
+    let schedule = state
+        .global_settings
+        .lock()
+        .await
+        .instance_power_schedule(uuid);
+    if schedule.map_or(true, |schedule| schedule.allows_start(local_now())) {
+        let shared = shared.clone();
+        let mut instance = instance;
+        tokio::spawn(async move {
+            info!(
+                "Someone connected to stopped instance {}, starting it",
+                instance.name().await
+            );
+            if let Err(e) = instance.start(CausedBy::System, true).await {
+                warn!("Failed to wake up instance {}: {e}", instance.name().await);
+            }
+            shared.lock().unwrap().waking = false;
+        });
+    } else {
+        shared.lock().unwrap().waking = false;
+    }
+    Upstream::NotReady
+}
+
+async fn run_proxy(
+    state: AppState,
+    uuid: InstanceUuid,
+    listener: TcpListener,
+    shared: Arc<SyncMutex<Shared>>,
+    closed: watch::Receiver<bool>,
+) {
+    loop {
+        let (inbound, peer) = match listener.accept().await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Network filter of instance {uuid} failed to accept a connection: {e}");
+                continue;
+            }
+        };
+        if !shared.lock().unwrap().admit(peer.ip()) {
+            continue;
+        }
+        // looked up every time, the instance's port can change while the proxy runs
+        let port = match upstream(&state, &uuid, &shared).await {
+            Upstream::Port(port) => port,
+            Upstream::NotReady => continue,
+            Upstream::Gone => break,
+        };
+        let shared = shared.clone();
+        let mut closed = closed.clone();
+        tokio::spawn(async move {
+            let mut inbound = inbound;
+            let mut outbound =
+                match TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], port))).await {
+                    Ok(outbound) => outbound,
+                    // the instance is most likely not running
+                    Err(_) => return,
+                };
+            shared.lock().unwrap().metrics.active_connections += 1;
+            let copied = tokio::select! {
+                copied = tokio::io::copy_bidirectional(&mut inbound, &mut outbound) => copied.ok(),
+                _ = until_closed(&mut closed) => None,
+            };
+            let mut shared = shared.lock().unwrap();
+            shared.metrics.active_connections -= 1;
+            if let Some((bytes_in, bytes_out)) = copied {
+                shared.metrics.bytes_in += bytes_in;
+                shared.metrics.bytes_out += bytes_out;
+            }
+        });
+    }
+}
+
+struct UdpSession {
+    socket: Arc<UdpSocket>,
+    last_seen: Instant,
+    task: JoinHandle<()>,
+}
+
+/// Each client gets its own socket towards the instance, so replies can be told apart
+async fn run_udp_proxy(
+    state: AppState,
+    uuid: InstanceUuid,
+    socket: UdpSocket,
+    shared: Arc<SyncMutex<Shared>>,
+    closed: watch::Receiver<bool>,
+) {
+    let socket = Arc::new(socket);
+    let mut sessions: HashMap<SocketAddr, UdpSession> = HashMap::new();
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(v) => v,
+            // like when an earlier reply couldn't be delivered on Windows
+            Err(_) => continue,
+        };
+        let now = Instant::now();
+        if !sessions.contains_key(&peer) {
+            sessions.retain(|_, session| {
+                let alive = now.duration_since(session.last_seen) < UDP_SESSION_TIMEOUT;
+                if !alive {
+                    session.task.abort();
+                }
+                alive
+            });
+            if sessions.len() >= MAX_UDP_SESSIONS || !shared.lock().unwrap().admit(peer.ip()) {
+                continue;
+            }
+            let port = match upstream(&state, &uuid, &shared).await {
+                Upstream::Port(port) => port,
+                Upstream::NotReady => continue,
+                Upstream::Gone => break,
+            };
+            let upstream = match UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    warn!("Network filter of instance {uuid} failed to open a UDP socket: {e}");
+                    continue;
+                }
+            };
+            if upstream
+                .connect(SocketAddr::from(([127, 0, 0, 1], port)))
+                .await
+                .is_err()
+            {
+                continue;
+            }
+            let upstream = Arc::new(upstream);
+            // replies keep going out through the old socket while draining, until the client
+            // has gone quiet
+            let task = tokio::spawn({
+                let (upstream, socket, shared) = (upstream.clone(), socket.clone(), shared.clone());
+                let mut closed = closed.clone();
+                async move {
+                    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+                    loop {
+                        let received = tokio::select! {
+                            received = tokio::time::timeout(UDP_SESSION_TIMEOUT, upstream.recv(&mut buf)) => received,
+                            _ = until_closed(&mut closed) => break,
+                        };
+                        let len = match received {
+                            Ok(Ok(len)) => len,
+                            _ => break,
+                        };
+                        if socket.send_to(&buf[..len], peer).await.is_ok() {
+                            shared.lock().unwrap().metrics.bytes_out += len as u64;
+                        }
+                    }
+                }
+            });
+            sessions.insert(
+                peer,
+                UdpSession {
+                    socket: upstream,
+                    last_seen: now,
+                    task,
+                },
+            );
+        }
+        let session = match sessions.get_mut(&peer) {
+            Some(session) => session,
+            None => continue,
+        };
+        session.last_seen = now;
+        if session.socket.send(&buf[..len]).await.is_ok() {
+            shared.lock().unwrap().metrics.bytes_in += len as u64;
+        }
+        shared.lock().unwrap().metrics.udp_sessions = sessions.len() as u32;
+    }
+    for session in sessions.into_values() {
+        session.task.abort();
+    }
+}
+
+/// Starts the proxies of instances with filtering enabled, when the core starts
+pub async fn start_network_filters(state: AppState) {
+    let all_settings = state.global_settings.lock().await.all_network_filters();
+    let mut manager = state.network_filters.lock().await;
+    for (uuid, settings) in all_settings {
+        if let Err(e) = manager.apply(&state, &uuid, Some(&settings)).await {
+            warn!("Failed to start the network filter of instance {uuid}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let settings = NetworkFilterSettings {
+            enabled: true,
+            listen_port: 25566,
+            listen_address: None,
+            allow: vec!["192.168.1.0/24".to_string(), "10.0.0.7".to_string()],
+            deny: vec!["192.168.1.13".to_string()],
+            udp: false,
+            wake_on_connect: false,
+        };
+        assert!(settings.validate().is_ok());
+        let rules = settings.rules();
+        let ip = |ip: &str| IpAddr::from_str(ip).unwrap();
+        assert!(rules.allows(ip("192.168.1.20")));
+        assert!(rules.allows(ip("::ffff:10.0.0.7")));
+        assert!(!rules.allows(ip("192.168.1.13")));
+        assert!(!rules.allows(ip("8.8.8.8")));
+
+        let deny_only = Rules {
+            allow: Vec::new(),
+            deny: vec![parse_rule("8.8.8.0/24").unwrap()],
+        };
+        assert!(deny_only.allows(ip("1.1.1.1")));
+        assert!(!deny_only.allows(ip("8.8.8.8")));
+
+        let mut invalid = settings;
+        invalid.deny.push("not an address".to_string());
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! OpenTelemetry traces of the core, to find where slow API calls and stuck jobs spend their time.
+//!
+//! Off unless `OTEL_EXPORTER_OTLP_ENDPOINT` is set, spans are then exported there over OTLP/gRPC
+//! under the service name in `OTEL_SERVICE_NAME`, `lodestone_core` by default. API requests get a
+//! span named after their route, and instance lifecycle operations and jobs get their own, along
+//! with whatever else is instrumented in the core.
+
+use std::time::Duration;
+
+use axum::extract::MatchedPath;
+use axum::http::{Request, Response};
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::KeyValue;
+use tracing::{debug, info_span, Span, Subscriber};
+use tracing_subscriber::{registry::LookupSpan, EnvFilter, Layer};
+
+use crate::prelude::VERSION;
+
+const ENDPOINT_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+const SERVICE_NAME_VAR: &str = "OTEL_SERVICE_NAME";
+
+/// The layer exporting spans, `None` if no endpoint is configured
+pub fn layer<S>(
+) -> Result<Option<Box<dyn Layer<S> + Send + Sync>>, opentelemetry::trace::TraceError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if std::env::var(ENDPOINT_VAR).map_or(true, |endpoint| endpoint.is_empty()) {
+        return Ok(None);
+    }
+    let service_name =
+        std::env::var(SERVICE_NAME_VAR).unwrap_or_else(|_| "lodestone_core".to_string());
+    // the exporter reads the endpoint and headers from the standard environment variables
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .with_trace_config(trace::config().with_resource(Resource::new(vec![
+            KeyValue::new("service.name", service_name),
+            KeyValue::new("service.version", VERSION.with(|v| v.to_string())),
+        ])))
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    Ok(Some(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(EnvFilter::from("lodestone_core=info"))
+            .boxed(),
+    ))
+}
+
+/// Sends the spans that are still buffered
+pub async fn shutdown() {
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+}
+
+/// The span of an API request, named after the route it matched
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    info_span!(
+        "request",
+        otel.name = %format!("{} {}", request.method(), route),
+        otel.kind = "server",
+        otel.status_code = tracing::field::Empty,
+        http.method = %request.method(),
+        http.route = %route,
+        http.status_code = tracing::field::Empty,
+    )
+}
+
+pub fn record_response<B>(response: &Response<B>, latency: Duration, span: &Span) {
+    let status = response.status();
+    span.record("http.status_code", status.as_u16());
+    if status.is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+    debug!(latency = ?latency, status = status.as_u16(), "finished processing request");
+}
@@ -2,10 +2,12 @@ use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::{
+    console_parser::LogLevel,
     events::{
-        CausedBy, Event, EventInner, EventLevel, InstanceEventInner, MacroEventInner,
+        CausedBy, Event, EventInner, EventLevel, EventType, InstanceEventInner, MacroEventInner,
         ProgressionEventInner,
     },
+    health::HealthStatus,
     types::Snowflake,
 };
 
@@ -19,12 +21,33 @@ pub struct ClientEvent {
     pub caused_by: CausedBy,
 }
 
-impl From<&Event> for ClientEvent {
-    fn from(event: &Event) -> Self {
-        let level = match &event.event_inner {
+impl Event {
+    pub fn level(&self) -> EventLevel {
+        match &self.event_inner {
             EventInner::InstanceEvent(i) => match i.instance_event_inner {
-                InstanceEventInner::InstanceError { .. } => EventLevel::Error,
-                InstanceEventInner::InstanceWarning { .. } => EventLevel::Warning,
+                InstanceEventInner::InstanceError { .. }
+                | InstanceEventInner::InstanceOutput {
+                    level: Some(LogLevel::Error),
+                    ..
+                }
+                | InstanceEventInner::HealthChanged {
+                    health: HealthStatus::Unhealthy,
+                    ..
+                } => EventLevel::Error,
+                InstanceEventInner::InstanceWarning { .. }
+                | InstanceEventInner::InstanceOutput {
+                    level: Some(LogLevel::Warn),
+                    ..
+                }
+                | InstanceEventInner::AlertTriggered { .. }
+                | InstanceEventInner::SettingsRolledBack { .. }
+                | InstanceEventInner::CommandBlocked { .. }
+                | InstanceEventInner::ChatModerated { .. }
+                | InstanceEventInner::WatchedPlayerJoined { .. }
+                | InstanceEventInner::HealthChanged {
+                    health: HealthStatus::Degraded,
+                    ..
+                } => EventLevel::Warning,
                 _ => EventLevel::Info,
             },
             EventInner::UserEvent(_) => EventLevel::Info,
@@ -51,12 +74,17 @@ impl From<&Event> for ClientEvent {
                 }
             },
             EventInner::FSEvent(_) => EventLevel::Info,
-        };
+        }
+    }
+}
+
+impl From<&Event> for ClientEvent {
+    fn from(event: &Event) -> Self {
         ClientEvent {
             event_inner: event.event_inner.clone(),
             details: event.details.clone(),
             snowflake: event.snowflake,
-            level,
+            level: event.level(),
             caused_by: event.caused_by.clone(),
         }
     }
@@ -73,3 +101,69 @@ impl AsRef<ClientEvent> for ClientEvent {
         self
     }
 }
+
+/// Columns of [`ClientEvent::to_csv_row`]
+pub const CLIENT_EVENT_CSV_HEADER: &str =
+    "snowflake,timestamp,level,event_type,instance_uuid,caused_by,details,event\n";
+
+/// Quotes a field if it would otherwise break the row
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl ClientEvent {
+    /// One line of csv, with the whole event as json in the last column
+    pub fn to_csv_row(&self) -> String {
+        let event_type: EventType = (&self.event_inner).into();
+        let instance_uuid = match &self.event_inner {
+            EventInner::InstanceEvent(i) => i.instance_uuid.to_string(),
+            _ => "".to_string(),
+        };
+        let caused_by = match &self.caused_by {
+            CausedBy::User {
+                user_name,
+                impersonated_by: Some(impersonator),
+                ..
+            } => format!("{user_name} (impersonated by {impersonator})"),
+            CausedBy::User { user_name, .. } => user_name.clone(),
+            CausedBy::Instance { instance_uuid } => instance_uuid.to_string(),
+            CausedBy::Macro { .. } => "Macro".to_string(),
+            CausedBy::System => "System".to_string(),
+            CausedBy::Unknown => "Unknown".to_string(),
+        };
+        let fields = [
+            self.snowflake.to_string(),
+            self.snowflake.timestamp_millis().to_string(),
+            format!("{:?}", self.level),
+            format!("{:?}", event_type),
+            instance_uuid,
+            caused_by,
+            self.details.clone(),
+            serde_json::to_string(&self.event_inner).unwrap_or_default(),
+        ];
+        let mut row = fields
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<_>>()
+            .join(",");
+        row.push('\n');
+        row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
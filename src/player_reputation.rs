@@ -0,0 +1,410 @@
+//! Notes and flags on players, shared by everyone moderating any of the core's instances.
+//!
+//! Players are keyed by the id their instance reports, a UUID for Java players and an XUID for
+//! Bedrock ones, so what is known about a player follows them from server to server. Notes are
+//! kept in the database next to the events. When a watched player joins an instance a
+//! [`InstanceEventInner::WatchedPlayerJoined`] event is sent out, so staff online see it.
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqlitePool, Row};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    auth::user_id::UserId,
+    error::{Error, ErrorKind},
+    event_broadcaster::SubscriptionFilter,
+    events::{CausedBy, Event, EventInner, EventType, InstanceEvent, InstanceEventInner},
+    traits::t_player::TPlayer,
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+const MAX_NOTE_LEN: usize = 4000;
+const MAX_FLAGS: usize = 16;
+const MAX_FLAG_LEN: usize = 32;
+const MAX_ID_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PlayerReputation {
+    pub player_id: String,
+    /// The name the player last had when something was noted about them
+    pub player_name: String,
+    pub watched: bool,
+    /// Short labels, like `griefer` or `trusted`
+    pub flags: Vec<String>,
+    pub note_count: u32,
+    pub updated_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PlayerNote {
+    pub id: i64,
+    pub player_id: String,
+    pub body: String,
+    pub author_id: UserId,
+    pub author_name: String,
+    /// Where the note was written from
+    pub instance_uuid: Option<InstanceUuid>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct PlayerReputationUpdate {
+    pub player_name: String,
+    pub watched: bool,
+    pub flags: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct NewPlayerNote {
+    /// The player's current name, kept so they can be recognized by it
+    pub player_name: String,
+    pub body: String,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct PlayerNoteEdit {
+    pub body: String,
+}
+
+fn bad_request(message: String) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!(message),
+    }
+}
+
+pub fn validate_player(player_id: &str, player_name: &str) -> Result<(), Error> {
+    for (what, value) in [("id", player_id), ("name", player_name)] {
+        if value.trim().is_empty()
+            || value.len() > MAX_ID_LEN
+            || value.chars().any(|c| c.is_control())
+        {
+            return Err(bad_request(format!("Invalid player {what} {value:?}")));
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_note(body: &str) -> Result<(), Error> {
+    if body.trim().is_empty() {
+        return Err(bad_request("A note cannot be empty".to_string()));
+    }
+    if body.chars().count() > MAX_NOTE_LEN {
+        return Err(bad_request(format!(
+            "Notes can be at most {MAX_NOTE_LEN} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// Trims the flags and drops duplicates, ignoring case
+fn normalize_flags(flags: Vec<String>) -> Result<Vec<String>, Error> {
+    let mut normalized: Vec<String> = Vec::new();
+    for flag in flags {
+        let flag = flag.trim().to_string();
+        if flag.is_empty()
+            || flag.chars().count() > MAX_FLAG_LEN
+            || flag.chars().any(|c| c.is_control())
+        {
+            return Err(bad_request(format!("Invalid flag {flag:?}")));
+        }
+        if !normalized
+            .iter()
+            .any(|existing| existing.eq_ignore_ascii_case(&flag))
+        {
+            normalized.push(flag);
+        }
+    }
+    if normalized.len() > MAX_FLAGS {
+        return Err(bad_request(format!(
+            "A player can have at most {MAX_FLAGS} flags"
+        )));
+    }
+    Ok(normalized)
+}
+
+fn note_not_found() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Note not found"),
+    }
+}
+
+const SELECT_REPUTATION: &str = "SELECT player_id, player_name, watched, flags, updated_at, \
+     (SELECT COUNT(*) FROM PlayerNotes WHERE PlayerNotes.player_id = PlayerReputation.player_id) \
+     AS note_count FROM PlayerReputation";
+
+fn reputation_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<PlayerReputation, Error> {
+    let flags: String = row
+        .try_get("flags")
+        .context("Failed to read player flags")?;
+    Ok(PlayerReputation {
+        player_id: row.try_get("player_id").context("Failed to read player")?,
+        player_name: row
+            .try_get("player_name")
+            .context("Failed to read player")?,
+        watched: row.try_get("watched").context("Failed to read player")?,
+        flags: serde_json::from_str(&flags).unwrap_or_default(),
+        note_count: row
+            .try_get::<i64, _>("note_count")
+            .context("Failed to read player")? as u32,
+        updated_at: row.try_get("updated_at").context("Failed to read player")?,
+    })
+}
+
+fn note_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<PlayerNote, Error> {
+    Ok(PlayerNote {
+        id: row.try_get("id").context("Failed to read note")?,
+        player_id: row.try_get("player_id").context("Failed to read note")?,
+        body: row.try_get("body").context("Failed to read note")?,
+        author_id: row.try_get("author_id").context("Failed to read note")?,
+        author_name: row.try_get("author_name").context("Failed to read note")?,
+        instance_uuid: row.try_get("instance_id").context("Failed to read note")?,
+        created_at: row.try_get("created_at").context("Failed to read note")?,
+        updated_at: row.try_get("updated_at").context("Failed to read note")?,
+    })
+}
+
+pub async fn get_reputation(
+    pool: &SqlitePool,
+    player_id: &str,
+) -> Result<Option<PlayerReputation>, Error> {
+    sqlx::query(&format!("{SELECT_REPUTATION} WHERE player_id = ?"))
+        .bind(player_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to fetch player reputation")?
+        .map(|row| reputation_from_row(&row))
+        .transpose()
+}
+
+/// Watched players and those with flags, most recently updated first
+pub async fn list_marked(pool: &SqlitePool) -> Result<Vec<PlayerReputation>, Error> {
+    sqlx::query(&format!(
+        "{SELECT_REPUTATION} WHERE watched OR flags != '[]' ORDER BY updated_at DESC"
+    ))
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch player reputations")?
+    .iter()
+    .map(reputation_from_row)
+    .collect()
+}
+
+pub async fn set_reputation(
+    pool: &SqlitePool,
+    player_id: &str,
+    update: PlayerReputationUpdate,
+) -> Result<PlayerReputation, Error> {
+    validate_player(player_id, &update.player_name)?;
+    let flags = normalize_flags(update.flags)?;
+    sqlx::query(
+        "INSERT INTO PlayerReputation (player_id, player_name, watched, flags, updated_at) \
+         VALUES (?, ?, ?, ?, ?) ON CONFLICT(player_id) DO UPDATE SET \
+         player_name = excluded.player_name, watched = excluded.watched, \
+         flags = excluded.flags, updated_at = excluded.updated_at",
+    )
+    .bind(player_id)
+    .bind(update.player_name.trim())
+    .bind(update.watched)
+    .bind(serde_json::to_string(&flags).context("Failed to serialize player flags")?)
+    .bind(chrono::Utc::now().timestamp())
+    .execute(pool)
+    .await
+    .context("Failed to save player reputation")?;
+    get_reputation(pool, player_id).await?.ok_or_else(|| Error {
+        kind: ErrorKind::Internal,
+        source: eyre!("Player reputation was not saved"),
+    })
+}
+
+/// Oldest first
+pub async fn list_notes(pool: &SqlitePool, player_id: &str) -> Result<Vec<PlayerNote>, Error> {
+    sqlx::query("SELECT * FROM PlayerNotes WHERE player_id = ? ORDER BY id")
+        .bind(player_id)
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch player notes")?
+        .iter()
+        .map(note_from_row)
+        .collect()
+}
+
+pub async fn get_note(
+    pool: &SqlitePool,
+    player_id: &str,
+    note_id: i64,
+) -> Result<PlayerNote, Error> {
+    sqlx::query("SELECT * FROM PlayerNotes WHERE id = ? AND player_id = ?")
+        .bind(note_id)
+        .bind(player_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to fetch player note")?
+        .map(|row| note_from_row(&row))
+        .transpose()?
+        .ok_or_else(note_not_found)
+}
+
+pub async fn add_note(
+    pool: &SqlitePool,
+    player_id: &str,
+    note: NewPlayerNote,
+    author_id: &UserId,
+    author_name: &str,
+    instance_uuid: &InstanceUuid,
+) -> Result<PlayerNote, Error> {
+    validate_player(player_id, &note.player_name)?;
+    validate_note(&note.body)?;
+    let now = chrono::Utc::now().timestamp();
+    let mut transaction = pool.begin().await.context("Failed to start transaction")?;
+    // the player gets a row of their own, so their name is known
+    sqlx::query(
+        "INSERT INTO PlayerReputation (player_id, player_name, updated_at) VALUES (?, ?, ?) \
+         ON CONFLICT(player_id) DO UPDATE SET player_name = excluded.player_name",
+    )
+    .bind(player_id)
+    .bind(note.player_name.trim())
+    .bind(now)
+    .execute(&mut transaction)
+    .await
+    .context("Failed to save player")?;
+    let id = sqlx::query(
+        "INSERT INTO PlayerNotes (player_id, body, author_id, author_name, instance_id, \
+         created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(player_id)
+    .bind(note.body.trim())
+    .bind(author_id)
+    .bind(author_name)
+    .bind(instance_uuid)
+    .bind(now)
+    .bind(now)
+    .execute(&mut transaction)
+    .await
+    .context("Failed to save player note")?
+    .last_insert_rowid();
+    transaction
+        .commit()
+        .await
+        .context("Failed to save player note")?;
+    get_note(pool, player_id, id).await
+}
+
+pub async fn edit_note(
+    pool: &SqlitePool,
+    player_id: &str,
+    note_id: i64,
+    body: &str,
+) -> Result<PlayerNote, Error> {
+    validate_note(body)?;
+    let result = sqlx::query(
+        "UPDATE PlayerNotes SET body = ?, updated_at = ? WHERE id = ? AND player_id = ?",
+    )
+    .bind(body.trim())
+    .bind(chrono::Utc::now().timestamp())
+    .bind(note_id)
+    .bind(player_id)
+    .execute(pool)
+    .await
+    .context("Failed to save player note")?;
+    if result.rows_affected() == 0 {
+        return Err(note_not_found());
+    }
+    get_note(pool, player_id, note_id).await
+}
+
+pub async fn delete_note(pool: &SqlitePool, player_id: &str, note_id: i64) -> Result<(), Error> {
+    let result = sqlx::query("DELETE FROM PlayerNotes WHERE id = ? AND player_id = ?")
+        .bind(note_id)
+        .bind(player_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete player note")?;
+    if result.rows_affected() == 0 {
+        return Err(note_not_found());
+    }
+    Ok(())
+}
+
+/// Tells staff when a watched player joins any instance, until the core shuts down
+pub async fn run_watched_players(state: AppState) {
+    let mut events = state
+        .event_broadcaster
+        .subscribe_filtered(SubscriptionFilter {
+            event_types: Some(vec![EventType::InstanceEvent]),
+            ..Default::default()
+        });
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        let (uuid, instance_name, joined) = match event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner: InstanceEventInner::PlayerChange { players_joined, .. },
+            }) => (instance_uuid, instance_name, players_joined),
+            _ => continue,
+        };
+        for player in joined {
+            let reputation = match get_reputation(&state.sqlite_pool, &player.get_id()).await {
+                Ok(Some(reputation)) if reputation.watched => reputation,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("Failed to look up player {}: {e}", player.get_name());
+                    continue;
+                }
+            };
+            state.event_broadcaster.send(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: uuid.clone(),
+                    instance_name: instance_name.clone(),
+                    instance_event_inner: InstanceEventInner::WatchedPlayerJoined {
+                        player_id: reputation.player_id,
+                        player_name: player.get_name(),
+                        flags: reputation.flags,
+                        note_count: reputation.note_count,
+                    },
+                }),
+                details: format!("Watched player {} joined", player.get_name()),
+                snowflake: Snowflake::default(),
+                caused_by: CausedBy::System,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_flags() {
+        assert_eq!(
+            normalize_flags(vec![
+                " griefer ".to_string(),
+                "Griefer".to_string(),
+                "alt account".to_string()
+            ])
+            .unwrap(),
+            vec!["griefer".to_string(), "alt account".to_string()]
+        );
+        assert!(normalize_flags(vec!["".to_string()]).is_err());
+        assert!(normalize_flags(vec!["x".repeat(MAX_FLAG_LEN + 1)]).is_err());
+        assert!(validate_player("069a79f4-44e9-4726-a5be-fca90e38aaf5", "Notch").is_ok());
+        assert!(validate_player("Notch", "Notch\n").is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Windows of time an instance is allowed to run in, like weekday evenings on a shared machine.
+//!
+//! The instance is started when a window opens, players are warned ahead of it closing, and it is
+//! stopped once it closes. Starting it by hand outside of a window is refused unless the request
+//! overrides the schedule. Times are in the core's local time.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+    AppState,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_WINDOWS: usize = 32;
+const MAX_WARNING_MINUTES: u32 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum Day {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl From<Weekday> for Day {
+    fn from(weekday: Weekday) -> Self {
+        match weekday {
+            Weekday::Mon => Day::Monday,
+            Weekday::Tue => Day::Tuesday,
+            Weekday::Wed => Day::Wednesday,
+            Weekday::Thu => Day::Thursday,
+            Weekday::Fri => Day::Friday,
+            Weekday::Sat => Day::Saturday,
+            Weekday::Sun => Day::Sunday,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PowerWindow {
+    /// The days the window opens on
+    pub days: Vec<Day>,
+    /// `HH:MM`
+    pub start: String,
+    /// `HH:MM`, a window ending before it starts runs past midnight into the next day
+    pub end: String,
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+impl PowerWindow {
+    fn contains(&self, at: NaiveDateTime) -> bool {
+        let (start, end) = match (parse_time(&self.start), parse_time(&self.end)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return false,
+        };
+        let time = at.time();
+        let opens_on = |weekday: Weekday| self.days.contains(&weekday.into());
+        if start < end {
+            opens_on(at.weekday()) && start <= time && time < end
+        } else {
+            (opens_on(at.weekday()) && start <= time)
+                || (opens_on(at.weekday().pred()) && time < end)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PowerSchedule {
+    /// Nothing is started, stopped or refused while disabled
+    pub enabled: bool,
+    pub windows: Vec<PowerWindow>,
+    /// How long before a window closes players are told the server is stopping, 0 to not warn
+    pub warning_minutes: u32,
+}
+
+impl PowerSchedule {
+    pub fn validate(&self) -> Result<(), Error> {
+        let bad_request = |message: String| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(message),
+        };
+        if self.windows.is_empty() || self.windows.len() > MAX_WINDOWS {
+            return Err(bad_request(format!(
+                "A power schedule needs between 1 and {MAX_WINDOWS} windows"
+            )));
+        }
+        if self.warning_minutes > MAX_WARNING_MINUTES {
+            return Err(bad_request(format!(
+                "The warning can be at most {MAX_WARNING_MINUTES} minutes before a window closes"
+            )));
+        }
+        for window in &self.windows {
+            if window.days.is_empty() {
+                return Err(bad_request("A window needs at least one day".to_string()));
+            }
+            match (parse_time(&window.start), parse_time(&window.end)) {
+                (Some(start), Some(end)) if start != end => {}
+                (Some(_), Some(_)) => {
+                    return Err(bad_request(
+                        "A window can't start and end at the same time".to_string(),
+                    ))
+                }
+                _ => {
+                    return Err(bad_request(format!(
+                        "Invalid window {}-{}, times must be HH:MM",
+                        window.start, window.end
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_open(&self, at: NaiveDateTime) -> bool {
+        self.windows.iter().any(|window| window.contains(at))
+    }
+
+    /// Whether the instance may be started by hand at `at`
+    pub fn allows_start(&self, at: NaiveDateTime) -> bool {
+        !self.enabled || self.is_open(at)
+    }
+
+    fn closes_within(&self, at: NaiveDateTime, minutes: u32) -> bool {
+        self.is_open(at) && !self.is_open(at + chrono::Duration::minutes(minutes as i64))
+    }
+}
+
+pub fn local_now() -> NaiveDateTime {
+    chrono::Local::now().naive_local()
+}
+
+/// What was last seen of an instance's schedule
+#[derive(Clone, Copy)]
+struct Tracked {
+    open: bool,
+    warned: bool,
+}
+
+/// Opens and closes the windows of every instance's schedule until the core shuts down
+pub async fn run_power_schedules(state: AppState) {
+    let mut tracked: HashMap<InstanceUuid, Tracked> = HashMap::new();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let schedules = state.global_settings.lock().await.all_power_schedules();
+        tracked.retain(|uuid, _| schedules.contains_key(uuid));
+        let now = local_now();
+        for (uuid, schedule) in schedules {
+            if !schedule.enabled {
+                tracked.remove(&uuid);
+                continue;
+            }
+            let open = schedule.is_open(now);
+            // nothing is done about the window the core came up in, it may have been left on purpose
+            let last = match tracked.insert(
+                uuid.clone(),
+                Tracked {
+                    open,
+                    warned: false,
+                },
+            ) {
+                Some(last) => last,
+                None => continue,
+            };
+            let instance = state.instances.lock().await.get(&uuid).cloned();
+            let mut instance = match instance {
+                Some(instance) => instance,
+                None => continue,
+            };
+            let name = instance.name().await;
+            let instance_state = instance.state().await;
+            if open && !last.open {
+                if instance_state == State::Stopped {
+                    info!("Power window of instance {name} opened, starting it");
+                    if let Err(e) = instance.start(CausedBy::System, false).await {
+                        warn!("Failed to start instance {name} for its power window: {e}");
+                    }
+                }
+            } else if !open && last.open {
+                if matches!(instance_state, State::Starting | State::Running) {
+                    info!("Power window of instance {name} closed, stopping it");
+                    if let Err(e) = instance.stop(CausedBy::System, false).await {
+                        warn!("Failed to stop instance {name} at the end of its power window: {e}");
+                    }
+                }
+            } else if open {
+                let mut warned = last.warned;
+                if !warned
+                    && schedule.warning_minutes > 0
+                    && schedule.closes_within(now, schedule.warning_minutes)
+                {
+                    warned = true;
+                    if instance_state == State::Running {
+                        let _ = instance
+                            .send_command(
+                                &format!(
+                                    "say This server stops in {} minute(s), as scheduled",
+                                    schedule.warning_minutes
+                                ),
+                                CausedBy::System,
+                            )
+                            .await;
+                    }
+                }
+                tracked.insert(uuid, Tracked { open, warned });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{date} {time}"), "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_power_windows() {
+        let schedule = PowerSchedule {
+            enabled: true,
+            windows: vec![
+                PowerWindow {
+                    days: vec![Day::Monday, Day::Friday],
+                    start: "16:00".to_string(),
+                    end: "23:00".to_string(),
+                },
+                PowerWindow {
+                    days: vec![Day::Saturday],
+                    start: "22:00".to_string(),
+                    end: "02:00".to_string(),
+                },
+            ],
+            warning_minutes: 5,
+        };
+        assert!(schedule.validate().is_ok());
+        // 2023-05-01 is a Monday
+        assert!(schedule.is_open(at("2023-05-01", "16:00")));
+        assert!(!schedule.is_open(at("2023-05-01", "23:00")));
+        assert!(!schedule.is_open(at("2023-05-02", "17:00")));
+        assert!(schedule.closes_within(at("2023-05-01", "22:56"), 5));
+        assert!(!schedule.closes_within(at("2023-05-01", "22:54"), 5));
+        // past midnight into Sunday
+        assert!(schedule.is_open(at("2023-05-07", "01:30")));
+        assert!(!schedule.is_open(at("2023-05-06", "01:30")));
+        assert!(!schedule.allows_start(at("2023-05-03", "12:00")));
+    }
+}
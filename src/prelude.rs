@@ -1,4 +1,3 @@
-use lazy_static::lazy_static;
 use std::path::PathBuf;
 
 use once_cell::sync::OnceCell;
@@ -40,6 +39,42 @@ pub fn path_to_users() -> &'static PathBuf {
     PATH_TO_USERS.get().unwrap()
 }
 
+static PATH_TO_INVITES: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn path_to_invites() -> &'static PathBuf {
+    PATH_TO_INVITES.get().unwrap()
+}
+
+static PATH_TO_JOBS: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn path_to_jobs() -> &'static PathBuf {
+    PATH_TO_JOBS.get().unwrap()
+}
+
+static PATH_TO_SNAPSHOTS: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn path_to_snapshots() -> &'static PathBuf {
+    PATH_TO_SNAPSHOTS.get().unwrap()
+}
+
+static PATH_TO_MAPS: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn path_to_maps() -> &'static PathBuf {
+    PATH_TO_MAPS.get().unwrap()
+}
+
+static PATH_TO_ARCHIVES: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn path_to_archives() -> &'static PathBuf {
+    PATH_TO_ARCHIVES.get().unwrap()
+}
+
+static PATH_TO_GLOBAL_MACROS: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn path_to_global_macros() -> &'static PathBuf {
+    PATH_TO_GLOBAL_MACROS.get().unwrap()
+}
+
 static PATH_TO_TMP: OnceCell<PathBuf> = OnceCell::new();
 
 pub fn path_to_tmp() -> &'static PathBuf {
@@ -56,12 +91,22 @@ pub fn init_paths(lodestone_path: PathBuf) {
     let path_to_stores = lodestone_path.join("stores");
     let path_to_global_settings = lodestone_path.join("global_settings.json");
     let path_to_users = lodestone_path.join("stores").join("users.json");
+    let path_to_invites = lodestone_path.join("stores").join("invites.json");
+    let path_to_jobs = lodestone_path.join("stores").join("jobs.json");
     let path_to_tmp = lodestone_path.join("tmp");
+    let path_to_snapshots = lodestone_path.join("snapshots");
+    let path_to_maps = lodestone_path.join("maps");
+    let path_to_archives = lodestone_path.join("archives");
+    let path_to_global_macros = lodestone_path.join("macros");
 
     std::fs::create_dir_all(&path_to_instances).unwrap();
     std::fs::create_dir_all(&path_to_binaries).unwrap();
     std::fs::create_dir_all(&path_to_stores).unwrap();
     std::fs::create_dir_all(&path_to_tmp).unwrap();
+    std::fs::create_dir_all(&path_to_snapshots).unwrap();
+    std::fs::create_dir_all(&path_to_maps).unwrap();
+    std::fs::create_dir_all(&path_to_archives).unwrap();
+    std::fs::create_dir_all(&path_to_global_macros).unwrap();
     // std::fs::File::create(&path_to_global_settings).unwrap();
     // std::fs::File::create(&path_to_users).unwrap();
     // std::fs::File::create(&path_to_tmp).unwrap();
@@ -72,7 +117,13 @@ pub fn init_paths(lodestone_path: PathBuf) {
     let _ = PATH_TO_STORES.set(path_to_stores);
     let _ = PATH_TO_GLOBAL_SETTINGS.set(path_to_global_settings);
     let _ = PATH_TO_USERS.set(path_to_users);
+    let _ = PATH_TO_INVITES.set(path_to_invites);
+    let _ = PATH_TO_JOBS.set(path_to_jobs);
     let _ = PATH_TO_TMP.set(path_to_tmp);
+    let _ = PATH_TO_SNAPSHOTS.set(path_to_snapshots);
+    let _ = PATH_TO_MAPS.set(path_to_maps);
+    let _ = PATH_TO_ARCHIVES.set(path_to_archives);
+    let _ = PATH_TO_GLOBAL_MACROS.set(path_to_global_macros);
 }
 
 thread_local! {
@@ -85,19 +136,12 @@ thread_local! {
     };
 
     pub static LODESTONE_EPOCH_SEC: i64 = 1667530800;
-    pub static LODESTONE_EPOCH_MIL: i64 = 1667530800000;
-}
-
-lazy_static! {
-    pub static ref SNOWFLAKE_GENERATOR: std::sync::Mutex<snowflake::SnowflakeIdGenerator> =
-        std::sync::Mutex::new(snowflake::SnowflakeIdGenerator::with_epoch(
-            1,
-            1,
-            std::time::UNIX_EPOCH + std::time::Duration::from_millis(1667530800000)
-        ));
+    pub static LODESTONE_EPOCH_MIL: i64 = lodestone_types::LODESTONE_EPOCH_MIL;
 }
 
 use crate::generic::GenericInstance;
+#[cfg(feature = "fake-instance")]
+use crate::implementations::fake::FakeInstance;
 use crate::minecraft::MinecraftInstance;
 #[enum_dispatch::enum_dispatch(
     TInstance,
@@ -106,10 +150,13 @@ use crate::minecraft::MinecraftInstance;
     TPlayerManagement,
     TResourceManagement,
     TServer,
+    TAction,
     TManifest
 )]
 #[derive(Clone)]
 pub enum GameInstance {
     MinecraftInstance,
     GenericInstance,
+    #[cfg(feature = "fake-instance")]
+    FakeInstance,
 }
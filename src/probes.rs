@@ -0,0 +1,208 @@
+//! Liveness and readiness of the core, for container orchestrators and headless deployments.
+//!
+//! The core is live as long as the Tokio runtime keeps getting to its tasks and the database
+//! answers, a failing liveness check means the core should be restarted. It is ready once startup
+//! is over: migrations were applied, interrupted instance creations were dealt with and
+//! extensions were loaded. Both checks report every component they looked at.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+use ts_rs::TS;
+
+use crate::AppState;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How late the heartbeat can be before the runtime is considered stuck
+const STALL_THRESHOLD: Duration = Duration::from_secs(10);
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum Component {
+    EventLoop,
+    Database,
+    Migrations,
+    InstanceCreations,
+    Extensions,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum ComponentStatus {
+    Ok,
+    /// Still starting up
+    Pending,
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct ComponentReport {
+    pub component: Component,
+    pub status: ComponentStatus,
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct ProbeReport {
+    /// Whether every component is ok
+    pub ok: bool,
+    pub components: Vec<ComponentReport>,
+}
+
+impl ProbeReport {
+    fn new(components: Vec<ComponentReport>) -> Self {
+        Self {
+            ok: components
+                .iter()
+                .all(|report| report.status == ComponentStatus::Ok),
+            components,
+        }
+    }
+}
+
+pub struct Probes {
+    startup: BTreeMap<Component, ComponentReport>,
+    last_heartbeat: Instant,
+    lag: Duration,
+}
+
+impl Default for Probes {
+    fn default() -> Self {
+        Self {
+            startup: [
+                Component::Migrations,
+                Component::InstanceCreations,
+                Component::Extensions,
+            ]
+            .into_iter()
+            .map(|component| {
+                (
+                    component,
+                    ComponentReport {
+                        component,
+                        status: ComponentStatus::Pending,
+                        message: None,
+                    },
+                )
+            })
+            .collect(),
+            last_heartbeat: Instant::now(),
+            lag: Duration::ZERO,
+        }
+    }
+}
+
+impl Probes {
+    /// Marks a step of startup as done, failed steps keep the core from being ready
+    pub fn finished(&mut self, component: Component, result: Result<(), String>) {
+        let (status, message) = match result {
+            Ok(()) => (ComponentStatus::Ok, None),
+            Err(e) => (ComponentStatus::Failed, Some(e)),
+        };
+        self.startup.insert(
+            component,
+            ComponentReport {
+                component,
+                status,
+                message,
+            },
+        );
+    }
+
+    fn event_loop(&self, now: Instant) -> ComponentReport {
+        let since = now.saturating_duration_since(self.last_heartbeat);
+        if since > STALL_THRESHOLD {
+            ComponentReport {
+                component: Component::EventLoop,
+                status: ComponentStatus::Failed,
+                message: Some(format!("No heartbeat for {}s", since.as_secs())),
+            }
+        } else {
+            ComponentReport {
+                component: Component::EventLoop,
+                status: ComponentStatus::Ok,
+                message: Some(format!("Heartbeat {}ms late", self.lag.as_millis())),
+            }
+        }
+    }
+}
+
+async fn database(pool: &SqlitePool) -> ComponentReport {
+    let (status, message) =
+        match tokio::time::timeout(DATABASE_TIMEOUT, sqlx::query("SELECT 1").execute(pool)).await {
+            Ok(Ok(_)) => (ComponentStatus::Ok, None),
+            Ok(Err(e)) => (ComponentStatus::Failed, Some(e.to_string())),
+            Err(_) => (
+                ComponentStatus::Failed,
+                Some(format!("No answer within {}s", DATABASE_TIMEOUT.as_secs())),
+            ),
+        };
+    ComponentReport {
+        component: Component::Database,
+        status,
+        message,
+    }
+}
+
+pub async fn liveness(state: &AppState) -> ProbeReport {
+    let event_loop = state.probes.lock().await.event_loop(Instant::now());
+    ProbeReport::new(vec![event_loop, database(&state.sqlite_pool).await])
+}
+
+pub async fn readiness(state: &AppState) -> ProbeReport {
+    let mut components = liveness(state).await.components;
+    components.extend(state.probes.lock().await.startup.values().cloned());
+    ProbeReport::new(components)
+}
+
+/// Measures how late the runtime wakes tasks up
+pub async fn run_heartbeat(state: AppState) {
+    loop {
+        let start = Instant::now();
+        tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+        let lag = start.elapsed().saturating_sub(HEARTBEAT_INTERVAL);
+        let mut probes = state.probes.lock().await;
+        probes.last_heartbeat = Instant::now();
+        probes.lag = lag;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probes() {
+        let mut probes = Probes::default();
+        let report = ProbeReport::new(probes.startup.values().cloned().collect());
+        assert!(!report.ok);
+
+        probes.finished(Component::Migrations, Ok(()));
+        probes.finished(Component::InstanceCreations, Ok(()));
+        probes.finished(Component::Extensions, Err("Failed to read".to_string()));
+        let report = ProbeReport::new(probes.startup.values().cloned().collect());
+        assert!(!report.ok);
+        assert_eq!(
+            report.components[2].message.as_deref(),
+            Some("Failed to read")
+        );
+        probes.finished(Component::Extensions, Ok(()));
+        assert!(ProbeReport::new(probes.startup.values().cloned().collect()).ok);
+
+        let now = probes.last_heartbeat;
+        assert_eq!(probes.event_loop(now).status, ComponentStatus::Ok);
+        assert_eq!(
+            probes
+                .event_loop(now + STALL_THRESHOLD + Duration::from_secs(1))
+                .status,
+            ComponentStatus::Failed
+        );
+    }
+}
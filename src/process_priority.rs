@@ -0,0 +1,240 @@
+//! Scheduling priority of a server process, so a background or archival server on the same
+//! machine doesn't take CPU time and disk bandwidth from the one players are on.
+//!
+//! One level sets the nice value and the IO priority on Linux, the nice value on other unixes,
+//! and the priority class on Windows. It is applied right after the server is spawned, before
+//! the JVM starts its own threads, which inherit it. Raising the priority above normal needs
+//! root or CAP_SYS_NICE on Linux and administrator rights on Windows; when that fails the
+//! server runs at normal priority and a warning is logged.
+
+use std::str::FromStr;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorKind};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessPriority {
+    Low,
+    BelowNormal,
+    #[default]
+    Normal,
+    AboveNormal,
+    High,
+}
+
+impl ToString for ProcessPriority {
+    fn to_string(&self) -> String {
+        match self {
+            ProcessPriority::Low => "low",
+            ProcessPriority::BelowNormal => "below_normal",
+            ProcessPriority::Normal => "normal",
+            ProcessPriority::AboveNormal => "above_normal",
+            ProcessPriority::High => "high",
+        }
+        .to_string()
+    }
+}
+
+impl FromStr for ProcessPriority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(ProcessPriority::Low),
+            "below_normal" => Ok(ProcessPriority::BelowNormal),
+            "normal" => Ok(ProcessPriority::Normal),
+            "above_normal" => Ok(ProcessPriority::AboveNormal),
+            "high" => Ok(ProcessPriority::High),
+            _ => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid priority. The only valid priorities are: low, below_normal, normal, above_normal, high"),
+            }),
+        }
+    }
+}
+
+impl ProcessPriority {
+    /// Lower is scheduled first
+    pub fn nice(&self) -> i32 {
+        match self {
+            ProcessPriority::Low => 15,
+            ProcessPriority::BelowNormal => 5,
+            ProcessPriority::Normal => 0,
+            ProcessPriority::AboveNormal => -5,
+            ProcessPriority::High => -10,
+        }
+    }
+
+    /// Level within the best-effort class, 0 to 7, lower is served first. 4 is what processes
+    /// get by default
+    pub fn io_level(&self) -> u32 {
+        match self {
+            ProcessPriority::Low => 7,
+            ProcessPriority::BelowNormal => 6,
+            ProcessPriority::Normal => 4,
+            ProcessPriority::AboveNormal => 2,
+            ProcessPriority::High => 0,
+        }
+    }
+}
+
+/// Applies `priority` to the process `pid` and its threads. Does nothing for
+/// [`ProcessPriority::Normal`], which is what the process starts with
+pub fn apply(pid: u32, priority: ProcessPriority) -> Result<(), Error> {
+    if priority == ProcessPriority::Normal {
+        return Ok(());
+    }
+    imp::apply(pid, priority)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use color_eyre::eyre::eyre;
+
+    use super::ProcessPriority;
+    use crate::error::Error;
+
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_BE: u32 = 2;
+    const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+    /// Linux schedules threads, not processes, so every thread is set
+    fn threads(pid: u32) -> Vec<libc::pid_t> {
+        let mut threads: Vec<libc::pid_t> = std::fs::read_dir(format!("/proc/{pid}/task"))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if threads.is_empty() {
+            threads.push(pid as libc::pid_t);
+        }
+        threads
+    }
+
+    pub fn apply(pid: u32, priority: ProcessPriority) -> Result<(), Error> {
+        let ioprio = (IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | priority.io_level();
+        for tid in threads(pid) {
+            // SAFETY: plain syscalls on a thread id, no memory is shared
+            unsafe {
+                if libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, priority.nice()) != 0 {
+                    return Err(eyre!(
+                        "Failed to set the nice value of process {pid}: {}",
+                        std::io::Error::last_os_error()
+                    )
+                    .into());
+                }
+                if libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    tid,
+                    ioprio as libc::c_int,
+                ) != 0
+                {
+                    return Err(eyre!(
+                        "Failed to set the IO priority of process {pid}: {}",
+                        std::io::Error::last_os_error()
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+mod imp {
+    use color_eyre::eyre::eyre;
+
+    use super::ProcessPriority;
+    use crate::error::Error;
+
+    pub fn apply(pid: u32, priority: ProcessPriority) -> Result<(), Error> {
+        // SAFETY: a plain syscall on a process id
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, pid as libc::id_t, priority.nice()) } != 0
+        {
+            return Err(eyre!(
+                "Failed to set the nice value of process {pid}: {}",
+                std::io::Error::last_os_error()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use color_eyre::eyre::eyre;
+    use windows_sys::Win32::{
+        Foundation::CloseHandle,
+        System::Threading::{
+            OpenProcess, SetPriorityClass, ABOVE_NORMAL_PRIORITY_CLASS,
+            BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS,
+            NORMAL_PRIORITY_CLASS, PROCESS_SET_INFORMATION,
+        },
+    };
+
+    use super::ProcessPriority;
+    use crate::error::Error;
+
+    pub fn apply(pid: u32, priority: ProcessPriority) -> Result<(), Error> {
+        let class = match priority {
+            ProcessPriority::Low => IDLE_PRIORITY_CLASS,
+            ProcessPriority::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+            ProcessPriority::Normal => NORMAL_PRIORITY_CLASS,
+            ProcessPriority::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+            ProcessPriority::High => HIGH_PRIORITY_CLASS,
+        };
+        unsafe {
+            let process = OpenProcess(PROCESS_SET_INFORMATION, 0, pid);
+            if process == 0 {
+                return Err(eyre!(
+                    "Failed to open process {pid}: {}",
+                    std::io::Error::last_os_error()
+                )
+                .into());
+            }
+            let set = SetPriorityClass(process, class);
+            let error = std::io::Error::last_os_error();
+            CloseHandle(process);
+            if set == 0 {
+                return Err(
+                    eyre!("Failed to set the priority class of process {pid}: {error}").into(),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_round_trip() {
+        for priority in [
+            ProcessPriority::Low,
+            ProcessPriority::BelowNormal,
+            ProcessPriority::Normal,
+            ProcessPriority::AboveNormal,
+            ProcessPriority::High,
+        ] {
+            assert_eq!(
+                priority.to_string().parse::<ProcessPriority>().unwrap(),
+                priority
+            );
+            assert_eq!(
+                serde_json::to_string(&priority).unwrap(),
+                format!("\"{}\"", priority.to_string())
+            );
+        }
+        assert!("realtime".parse::<ProcessPriority>().is_err());
+    }
+}
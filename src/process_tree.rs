@@ -0,0 +1,189 @@
+//! A server process together with every process it started.
+//!
+//! Wrapper scripts like `run.bat` and some modded servers run the game in child processes,
+//! which are left behind when only the process Lodestone spawned is stopped or killed. On
+//! Windows the server is put in a Job Object right after it is spawned: whatever it starts
+//! joins the job too and stays in it even once its parent has exited, so the job always knows
+//! the whole tree. Elsewhere the tree is found by following parent pids.
+//!
+//! The job is not set to kill its processes when closed, a server is allowed to outlive the
+//! core that started it.
+
+use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
+#[cfg(windows)]
+use tracing::warn;
+
+pub struct ProcessTree {
+    root: u32,
+    #[cfg(windows)]
+    job: Option<job::Job>,
+}
+
+impl ProcessTree {
+    pub fn new(root: u32) -> Self {
+        Self {
+            root,
+            #[cfg(windows)]
+            job: match job::Job::assign(root) {
+                Ok(job) => Some(job),
+                Err(e) => {
+                    warn!("Failed to put process {root} in a job object, its children will not be tracked: {e}");
+                    None
+                }
+            },
+        }
+    }
+
+    pub fn root(&self) -> u32 {
+        self.root
+    }
+
+    /// The processes of the tree that are still alive, refreshed in `sys`, the root first
+    pub fn pids(&self, sys: &mut System) -> Vec<Pid> {
+        #[cfg(windows)]
+        if let Some(job) = &self.job {
+            let mut pids: Vec<Pid> = job
+                .pids()
+                .into_iter()
+                .map(Pid::from_u32)
+                .filter(|pid| sys.refresh_process(*pid))
+                .collect();
+            pids.sort_by_key(|pid| pid.as_u32() != self.root);
+            return pids;
+        }
+        sys.refresh_processes();
+        descendants(sys, Pid::from_u32(self.root))
+    }
+
+    /// Kills everything the root started, the root itself is left to whoever spawned it
+    pub fn kill_descendants(&self, sys: &mut System) {
+        for pid in self.pids(sys) {
+            if pid.as_u32() == self.root {
+                continue;
+            }
+            if let Some(process) = sys.process(pid) {
+                process.kill();
+            }
+        }
+    }
+}
+
+/// `root` and the processes below it, if it is alive
+fn descendants(sys: &System, root: Pid) -> Vec<Pid> {
+    if sys.process(root).is_none() {
+        return Vec::new();
+    }
+    let mut tree = vec![root];
+    let mut i = 0;
+    while i < tree.len() {
+        let parent = tree[i];
+        let children: Vec<Pid> = sys
+            .processes()
+            .iter()
+            .filter(|(pid, process)| process.parent() == Some(parent) && !tree.contains(pid))
+            .map(|(pid, _)| *pid)
+            .collect();
+        tree.extend(children);
+        i += 1;
+    }
+    tree
+}
+
+#[cfg(windows)]
+mod job {
+    use std::{ffi::c_void, mem::size_of};
+
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        System::{
+            JobObjects::{
+                AssignProcessToJobObject, CreateJobObjectW, JobObjectBasicProcessIdList,
+                QueryInformationJobObject,
+            },
+            Threading::{OpenProcess, PROCESS_SET_QUOTA, PROCESS_TERMINATE},
+        },
+    };
+
+    /// Processes past this many are left out of [`Job::pids`]
+    const MAX_PROCESSES: usize = 256;
+
+    /// Laid out like `JOBOBJECT_BASIC_PROCESS_ID_LIST`, with room for more than one pid
+    #[repr(C)]
+    struct ProcessIdList {
+        _assigned: u32,
+        listed: u32,
+        pids: [usize; MAX_PROCESSES],
+    }
+
+    pub struct Job(HANDLE);
+
+    // a job handle can be used from any thread
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    impl Job {
+        pub fn assign(pid: u32) -> std::io::Result<Self> {
+            unsafe {
+                let handle = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+                if handle == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let job = Job(handle);
+                let process = OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, 0, pid);
+                if process == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let assigned = AssignProcessToJobObject(handle, process);
+                CloseHandle(process);
+                if assigned == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(job)
+            }
+        }
+
+        pub fn pids(&self) -> Vec<u32> {
+            let mut list = ProcessIdList {
+                _assigned: 0,
+                listed: 0,
+                pids: [0; MAX_PROCESSES],
+            };
+            let ok = unsafe {
+                QueryInformationJobObject(
+                    self.0,
+                    JobObjectBasicProcessIdList,
+                    &mut list as *mut ProcessIdList as *mut c_void,
+                    size_of::<ProcessIdList>() as u32,
+                    std::ptr::null_mut(),
+                )
+            };
+            if ok == 0 {
+                return Vec::new();
+            }
+            let listed = (list.listed as usize).min(MAX_PROCESSES);
+            list.pids[..listed].iter().map(|pid| *pid as u32).collect()
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_starts_at_root() {
+        let mut sys = System::new();
+        let tree = ProcessTree::new(std::process::id());
+        let pids = tree.pids(&mut sys);
+        assert_eq!(pids.first(), Some(&Pid::from_u32(std::process::id())));
+        assert!(ProcessTree::new(u32::MAX).pids(&mut sys).is_empty());
+    }
+}
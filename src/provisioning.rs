@@ -0,0 +1,509 @@
+//! Instances declared in `provisioning.toml`, for setups that are reproduced from files.
+//!
+//! On startup every declared instance that doesn't exist yet is created and given its settings,
+//! owned by the owner. Instances are matched by name. Declared instances that exist but no longer
+//! look like their declaration are left alone, the differences are logged and listed by
+//! `/instances/provisioning`:
+//!
+//! ```toml
+//! [[instance]]
+//! name = "survival"
+//! game_type = "MinecraftPaper"
+//! version = "1.20.1"
+//! port = 25565
+//! max_ram = 4096
+//!
+//! [instance.settings]
+//! difficulty = "hard"
+//! max-players = 20
+//! ```
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use ts_rs::TS;
+
+use crate::{
+    disk_space,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    handlers::instance_setup_configs::HandlerGameType,
+    implementations::minecraft::{FlavourKind, SetupConfig},
+    instance_creation,
+    prelude::lodestone_path,
+    traits::t_configurable::{
+        manifest::ConfigurableManifest, Game, MinecraftVariant, TConfigurable,
+    },
+    types::InstanceUuid,
+    AppState,
+};
+
+pub const PROVISIONING_FILE: &str = "provisioning.toml";
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProvisioningFile {
+    #[serde(default, rename = "instance")]
+    pub instances: Vec<DeclaredInstance>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DeclaredInstance {
+    pub name: String,
+    pub game_type: HandlerGameType,
+    pub version: String,
+    pub port: u32,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub min_ram: Option<u32>,
+    #[serde(default)]
+    pub max_ram: Option<u32>,
+    #[serde(default)]
+    pub cmd_args: Vec<String>,
+    #[serde(default)]
+    pub auto_start: Option<bool>,
+    #[serde(default)]
+    pub restart_on_crash: Option<bool>,
+    /// Id of the storage location to create the instance in, the default one if not set
+    #[serde(default)]
+    pub storage: Option<String>,
+    /// By setting id, like `difficulty` or `max-players`
+    #[serde(default)]
+    pub settings: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct Drift {
+    /// `game_type`, `version`, `port` or the id of a setting
+    pub field: String,
+    pub declared: String,
+    /// `None` if the instance has no such setting
+    pub actual: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct InstanceDrift {
+    pub instance_uuid: InstanceUuid,
+    pub name: String,
+    pub drift: Vec<Drift>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct ProvisioningReport {
+    /// Declared instances that don't exist
+    pub missing: Vec<String>,
+    /// Declared instances still being set up
+    pub pending: Vec<String>,
+    /// Declared instances whose name is shared by several instances, they are left alone
+    pub ambiguous: Vec<String>,
+    pub drifted: Vec<InstanceDrift>,
+}
+
+fn invalid(message: String) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!(message),
+    }
+}
+
+impl ProvisioningFile {
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let file: Self = toml::from_str(content)
+            .map_err(|e| invalid(format!("Invalid {PROVISIONING_FILE}: {e}")))?;
+        let mut names = HashSet::new();
+        for instance in &file.instances {
+            if instance.name.is_empty() {
+                return Err(invalid("Declared instances need a name".to_string()));
+            }
+            if !names.insert(instance.name.as_str()) {
+                return Err(invalid(format!(
+                    "Instance {} is declared more than once",
+                    instance.name
+                )));
+            }
+            if instance.port == 0 || instance.port > u16::MAX as u32 {
+                return Err(invalid(format!(
+                    "Instance {} has an invalid port",
+                    instance.name
+                )));
+            }
+            FlavourKind::try_from(instance.game_type).map_err(|_| {
+                invalid(format!(
+                    "Instance {} can't be provisioned, only Minecraft Java servers can",
+                    instance.name
+                ))
+            })?;
+            for (setting_id, value) in &instance.settings {
+                setting_value(value).map_err(|e| {
+                    invalid(format!(
+                        "Setting {setting_id} of instance {}: {e}",
+                        instance.name
+                    ))
+                })?;
+            }
+        }
+        Ok(file)
+    }
+
+    /// `None` if there is no provisioning file
+    pub async fn load() -> Result<Option<Self>, Error> {
+        match tokio::fs::read_to_string(path_to_provisioning()).await {
+            Ok(content) => Self::parse(&content).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("Failed to read {PROVISIONING_FILE}: {e}"),
+            }),
+        }
+    }
+}
+
+impl DeclaredInstance {
+    fn flavour(&self) -> String {
+        FlavourKind::try_from(self.game_type)
+            .map(|flavour| flavour.to_string())
+            .unwrap_or_default()
+    }
+
+    fn setup_config(&self) -> Result<SetupConfig, Error> {
+        Ok(SetupConfig {
+            name: self.name.clone(),
+            version: self.version.clone(),
+            flavour: FlavourKind::try_from(self.game_type)?.into(),
+            port: self.port,
+            cmd_args: self.cmd_args.clone(),
+            description: self.description.clone(),
+            min_ram: self.min_ram,
+            max_ram: self.max_ram,
+            auto_start: self.auto_start,
+            restart_on_crash: self.restart_on_crash,
+            backup_period: None,
+        })
+    }
+}
+
+fn path_to_provisioning() -> PathBuf {
+    lodestone_path().join(PROVISIONING_FILE)
+}
+
+/// Settings are written as TOML values, but compared and parsed in their string form
+fn setting_value(value: &toml::Value) -> Result<String, Error> {
+    match value {
+        toml::Value::String(value) => Ok(value.clone()),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(invalid(
+            "Settings must be strings, numbers or booleans".to_string(),
+        )),
+    }
+}
+
+fn flavour_of(game: &Game) -> String {
+    match game {
+        Game::MinecraftJava { variant } => match variant {
+            MinecraftVariant::Vanilla => "vanilla".to_string(),
+            MinecraftVariant::Forge => "forge".to_string(),
+            MinecraftVariant::Fabric => "fabric".to_string(),
+            MinecraftVariant::Paper => "paper".to_string(),
+            MinecraftVariant::Spigot => "spigot".to_string(),
+            MinecraftVariant::Other { name } => name.to_lowercase(),
+        },
+        Game::MinecraftBedrock => "bedrock".to_string(),
+        Game::Generic { .. } => "generic".to_string(),
+    }
+}
+
+/// The section the setting is in, and its current value
+fn find_setting(
+    manifest: &ConfigurableManifest,
+    setting_id: &str,
+) -> Option<(String, Option<String>)> {
+    manifest
+        .get_all_sections()
+        .into_iter()
+        .find_map(|(section_id, section)| {
+            section.get_setting(setting_id).map(|setting| {
+                (
+                    section_id,
+                    setting.get_value().map(|value| value.to_string()),
+                )
+            })
+        })
+}
+
+fn compare(declared: &DeclaredInstance, actual: &BTreeMap<&str, Option<String>>) -> Vec<Drift> {
+    let mut expected = vec![
+        ("game_type".to_string(), declared.flavour()),
+        ("version".to_string(), declared.version.clone()),
+        ("port".to_string(), declared.port.to_string()),
+    ];
+    for (setting_id, value) in &declared.settings {
+        if let Ok(value) = setting_value(value) {
+            expected.push((setting_id.clone(), value));
+        }
+    }
+    expected
+        .into_iter()
+        .filter_map(|(field, declared)| {
+            let actual = actual.get(field.as_str()).cloned().flatten();
+            (actual.as_ref() != Some(&declared)).then_some(Drift {
+                field,
+                declared,
+                actual,
+            })
+        })
+        .collect()
+}
+
+/// Compares the declared instances with the instances there are
+pub async fn report(
+    state: &AppState,
+    file: &ProvisioningFile,
+) -> Result<ProvisioningReport, Error> {
+    let pending: HashSet<String> = instance_creation::pending_creations(&state.sqlite_pool)
+        .await?
+        .into_iter()
+        .map(|creation| creation.setup_config.name)
+        .collect();
+    let mut report = ProvisioningReport::default();
+    let mut instances = state.instances.lock().await;
+    for declared in &file.instances {
+        let mut matching = Vec::new();
+        for (uuid, instance) in instances.iter() {
+            if instance.name().await == declared.name {
+                matching.push(uuid.clone());
+            }
+        }
+        let uuid = match matching.as_slice() {
+            [] if pending.contains(&declared.name) => {
+                report.pending.push(declared.name.clone());
+                continue;
+            }
+            [] => {
+                report.missing.push(declared.name.clone());
+                continue;
+            }
+            [uuid] => uuid.clone(),
+            _ => {
+                report.ambiguous.push(declared.name.clone());
+                continue;
+            }
+        };
+        let instance = match instances.get_mut(&uuid) {
+            Some(instance) => instance,
+            None => continue,
+        };
+        let manifest = instance.configurable_manifest().await;
+        let mut actual = BTreeMap::new();
+        actual.insert("game_type", Some(flavour_of(&instance.game_type().await)));
+        actual.insert("version", Some(instance.version().await));
+        actual.insert("port", Some(instance.port().await.to_string()));
+        for setting_id in declared.settings.keys() {
+            actual.insert(
+                setting_id.as_str(),
+                find_setting(&manifest, setting_id).and_then(|(_, value)| value),
+            );
+        }
+        let drift = compare(declared, &actual);
+        if !drift.is_empty() {
+            report.drifted.push(InstanceDrift {
+                instance_uuid: uuid,
+                name: declared.name.clone(),
+                drift,
+            });
+        }
+    }
+    Ok(report)
+}
+
+async fn apply_settings(state: &AppState, uuid: &InstanceUuid, declared: &DeclaredInstance) {
+    let mut instances = state.instances.lock().await;
+    let instance = match instances.get_mut(uuid) {
+        Some(instance) => instance,
+        // the setup failed, which was already reported
+        None => return,
+    };
+    let manifest = instance.configurable_manifest().await;
+    for (setting_id, value) in &declared.settings {
+        let applied = async {
+            let (section_id, _) = find_setting(&manifest, setting_id)
+                .ok_or_else(|| invalid("The instance has no such setting".to_string()))?;
+            let value_type = manifest
+                .get_setting(&section_id, setting_id)
+                .map(|setting| setting.get_value_type().clone())
+                .ok_or_else(|| invalid("The instance has no such setting".to_string()))?;
+            let value = value_type.parse_value(&setting_value(value)?)?;
+            instance
+                .update_configurable(&section_id, setting_id, value)
+                .await
+        }
+        .await;
+        if let Err(e) = applied {
+            warn!(
+                "Failed to set {setting_id} of provisioned instance {}: {e}",
+                declared.name
+            );
+        }
+    }
+}
+
+async fn provision(state: &AppState, declared: &DeclaredInstance) -> Result<(), Error> {
+    let owner = state
+        .users_manager
+        .read()
+        .await
+        .as_ref()
+        .values()
+        .find(|user| user.is_owner)
+        .cloned()
+        .ok_or_else(|| {
+            invalid("Instances can only be provisioned once there is an owner".to_string())
+        })?;
+    let (disk_space, location) = {
+        let global_settings = state.global_settings.lock().await;
+        (
+            global_settings.disk_space(),
+            global_settings.storage_location(declared.storage.as_deref())?,
+        )
+    };
+    disk_space::ensure_space(&disk_space, &location.path, 0, None, None).await?;
+    let creation = instance_creation::prepare_creation(
+        state,
+        declared.setup_config()?,
+        declared.game_type.into(),
+        &location,
+        owner.uid,
+    )
+    .await?;
+    let uuid = creation.instance_uuid.clone();
+    info!("Provisioning instance {}", declared.name);
+    instance_creation::create_minecraft_instance(state.clone(), creation, CausedBy::System).await;
+    apply_settings(state, &uuid, declared).await;
+    Ok(())
+}
+
+/// Creates the declared instances that are missing and logs how the others drifted
+pub async fn run_provisioning(state: AppState) {
+    let file = match ProvisioningFile::load().await {
+        Ok(Some(file)) => file,
+        Ok(None) => return,
+        Err(e) => {
+            error!("{e}, no instances were provisioned");
+            return;
+        }
+    };
+    let report = match report(&state, &file).await {
+        Ok(report) => report,
+        Err(e) => {
+            error!("Failed to compare the declared instances: {e}");
+            return;
+        }
+    };
+    for declared in &file.instances {
+        if report.missing.contains(&declared.name) {
+            if let Err(e) = provision(&state, declared).await {
+                error!("Failed to provision instance {}: {e}", declared.name);
+            }
+        }
+    }
+    for name in &report.ambiguous {
+        warn!(
+            "Several instances are named {name}, none of them is checked against its declaration"
+        );
+    }
+    for instance in &report.drifted {
+        for drift in &instance.drift {
+            warn!(
+                "Instance {} has {} {}, {} declares {}",
+                instance.name,
+                drift.field,
+                drift.actual.as_deref().unwrap_or("unset"),
+                PROVISIONING_FILE,
+                drift.declared
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provisioning_file() {
+        let file = ProvisioningFile::parse(
+            r#"
+            [[instance]]
+            name = "survival"
+            game_type = "MinecraftPaper"
+            version = "1.20.1"
+            port = 25565
+
+            [instance.settings]
+            difficulty = "hard"
+            max-players = 20
+            "#,
+        )
+        .unwrap();
+        let declared = &file.instances[0];
+        assert_eq!(declared.flavour(), "paper");
+
+        let mut actual = BTreeMap::new();
+        actual.insert("game_type", Some("paper".to_string()));
+        actual.insert("version", Some("1.20.1".to_string()));
+        actual.insert("port", Some("25565".to_string()));
+        actual.insert("difficulty", Some("hard".to_string()));
+        actual.insert("max-players", Some("20".to_string()));
+        assert!(compare(declared, &actual).is_empty());
+
+        actual.insert("version", Some("1.19.4".to_string()));
+        actual.insert("max-players", None);
+        assert_eq!(
+            compare(declared, &actual),
+            vec![
+                Drift {
+                    field: "version".to_string(),
+                    declared: "1.20.1".to_string(),
+                    actual: Some("1.19.4".to_string()),
+                },
+                Drift {
+                    field: "max-players".to_string(),
+                    declared: "20".to_string(),
+                    actual: None,
+                },
+            ]
+        );
+
+        let duplicated = r#"
+            [[instance]]
+            name = "survival"
+            game_type = "MinecraftJavaVanilla"
+            version = "1.20.1"
+            port = 25565
+
+            [[instance]]
+            name = "survival"
+            game_type = "MinecraftJavaVanilla"
+            version = "1.20.1"
+            port = 25566
+            "#;
+        assert!(ProvisioningFile::parse(duplicated).is_err());
+        let bedrock = r#"
+            [[instance]]
+            name = "bedrock"
+            game_type = "MinecraftBedrock"
+            version = "1.20.1"
+            port = 19132
+            "#;
+        assert!(ProvisioningFile::parse(bedrock).is_err());
+    }
+}
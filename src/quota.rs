@@ -0,0 +1,245 @@
+//! Limits on what a single user may use, for cores shared between several people.
+//!
+//! Instances count against their owner, normally whoever created them.
+//! A user's own quota takes precedence over the one of their role, and the owner is never limited.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::{instance_access::InstanceAccess, user::User, user_id::UserId},
+    disk_space::dir_size,
+    error::{Error, ErrorKind},
+    i18n::{LocalizedMessage, MessageId},
+    prelude::GameInstance,
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    util::format_byte,
+    AppState,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum QuotaRole {
+    Admin,
+    User,
+}
+
+/// `None` means unlimited
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct UserQuota {
+    pub max_instances: Option<u32>,
+    /// In bytes, summed over the directories of the user's instances
+    pub max_disk_usage: Option<u64>,
+    /// In MiB, summed over the maximum RAM of the user's instances
+    pub max_ram: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct QuotaSettings {
+    /// Applies to admins without a quota of their own
+    pub admin: UserQuota,
+    /// Applies to everyone else without a quota of their own
+    pub user: UserQuota,
+    pub users: HashMap<UserId, UserQuota>,
+}
+
+impl QuotaSettings {
+    /// `None` if the user is not limited at all
+    pub fn quota_of(&self, user: &User) -> Option<UserQuota> {
+        if user.is_owner {
+            return None;
+        }
+        Some(
+            self.users
+                .get(&user.uid)
+                .copied()
+                .unwrap_or(if user.is_admin { self.admin } else { self.user }),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct QuotaUsage {
+    pub instances: u32,
+    pub disk_usage: u64,
+    pub ram: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct UserQuotaReport {
+    pub uid: UserId,
+    pub username: String,
+    pub quota: Option<UserQuota>,
+    pub usage: QuotaUsage,
+}
+
+/// Maximum RAM of an instance in MiB, 0 for instances without such a setting
+pub async fn max_ram_of(instance: &mut GameInstance) -> u32 {
+    instance
+        .configurable_manifest()
+        .await
+        .get_unique_setting_key("max_ram")
+        .and_then(|setting| setting.get_value())
+        .and_then(|value| value.try_as_unsigned_integer().ok())
+        .unwrap_or(0)
+}
+
+pub async fn usage_of(
+    access: &HashMap<InstanceUuid, InstanceAccess>,
+    instances: &mut HashMap<InstanceUuid, GameInstance>,
+    uid: &UserId,
+) -> QuotaUsage {
+    let mut usage = QuotaUsage::default();
+    for (uuid, instance) in instances.iter_mut() {
+        if access
+            .get(uuid)
+            .map_or(false, |access| access.is_owned_by(uid))
+        {
+            usage.instances += 1;
+            usage.disk_usage += dir_size(instance.path().await).await;
+            usage.ram += max_ram_of(instance).await;
+        }
+    }
+    usage
+}
+
+/// Checks that `additional` fits in `quota` on top of `usage`.
+///
+/// Only what grows is checked, so a user over their quota can still scale things down
+pub fn check_quota(
+    quota: &UserQuota,
+    usage: &QuotaUsage,
+    additional: &QuotaUsage,
+) -> Result<(), Error> {
+    if let Some(max_instances) = quota.max_instances {
+        if additional.instances > 0 && usage.instances + additional.instances > max_instances {
+            return Err(Error::localized(
+                ErrorKind::PermissionDenied,
+                LocalizedMessage::new(MessageId::InstanceQuotaExceeded)
+                    .with_param("limit", max_instances),
+            ));
+        }
+    }
+    if let Some(max_disk_usage) = quota.max_disk_usage {
+        // a new instance needs at least some room
+        if (additional.instances > 0 || additional.disk_usage > 0)
+            && usage.disk_usage + additional.disk_usage >= max_disk_usage
+        {
+            return Err(Error::localized(
+                ErrorKind::PermissionDenied,
+                LocalizedMessage::new(MessageId::DiskQuotaExceeded)
+                    .with_param("limit", format_byte(max_disk_usage)),
+            ));
+        }
+    }
+    if let Some(max_ram) = quota.max_ram {
+        if additional.ram > 0 && usage.ram + additional.ram > max_ram {
+            return Err(Error::localized(
+                ErrorKind::PermissionDenied,
+                LocalizedMessage::new(MessageId::RamQuotaExceeded)
+                    .with_param("limit", format!("{max_ram} MiB")),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `user` may create another instance with `ram` MiB of maximum RAM
+pub async fn check_new_instance(state: &AppState, user: &User, ram: u32) -> Result<(), Error> {
+    let quota = match state.global_settings.lock().await.quotas().quota_of(user) {
+        Some(quota) => quota,
+        None => return Ok(()),
+    };
+    let access = state.all_instance_access().await;
+    let usage = usage_of(&access, &mut *state.instances.lock().await, &user.uid).await;
+    check_quota(
+        &quota,
+        &usage,
+        &QuotaUsage {
+            instances: 1,
+            disk_usage: 0,
+            ram,
+        },
+    )
+}
+
+/// Whether the maximum RAM of an instance may be changed to `ram` MiB, judged by the quota of its owner
+pub async fn check_ram_change(
+    state: &AppState,
+    instances: &mut HashMap<InstanceUuid, GameInstance>,
+    uuid: &InstanceUuid,
+    ram: u32,
+) -> Result<(), Error> {
+    let access = state.all_instance_access().await;
+    let owner = match access.get(uuid).and_then(|access| access.owner.as_ref()) {
+        Some(uid) => match state.users_manager.read().await.get_user(uid) {
+            Some(owner) => owner,
+            None => return Ok(()),
+        },
+        None => return Ok(()),
+    };
+    let quota = match state.global_settings.lock().await.quotas().quota_of(&owner) {
+        Some(quota) => quota,
+        None => return Ok(()),
+    };
+    let current = match instances.get_mut(uuid) {
+        Some(instance) => max_ram_of(instance).await,
+        None => return Ok(()),
+    };
+    let usage = usage_of(&access, instances, &owner.uid).await;
+    check_quota(
+        &quota,
+        &usage,
+        &QuotaUsage {
+            ram: ram.saturating_sub(current),
+            ..Default::default()
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_quota() {
+        let quota = UserQuota {
+            max_instances: Some(2),
+            max_disk_usage: Some(1000),
+            max_ram: Some(4096),
+        };
+        let usage = QuotaUsage {
+            instances: 1,
+            disk_usage: 500,
+            ram: 2048,
+        };
+        let new_instance = |ram| QuotaUsage {
+            instances: 1,
+            disk_usage: 0,
+            ram,
+        };
+        assert!(check_quota(&quota, &usage, &new_instance(2048)).is_ok());
+        assert!(check_quota(&quota, &usage, &new_instance(4096)).is_err());
+        let full = QuotaUsage {
+            instances: 2,
+            ..usage
+        };
+        assert!(check_quota(&quota, &full, &new_instance(0)).is_err());
+        let out_of_disk = QuotaUsage {
+            disk_usage: 1000,
+            ..usage
+        };
+        assert!(check_quota(&quota, &out_of_disk, &new_instance(0)).is_err());
+        // lowering RAM is always allowed, even past the quota
+        let over_ram = QuotaUsage { ram: 8192, ..usage };
+        assert!(check_quota(&quota, &over_ram, &QuotaUsage::default()).is_ok());
+        assert!(check_quota(&UserQuota::default(), &over_ram, &new_instance(8192)).is_ok());
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use ts_rs::TS;
+
+use crate::{
+    error::ErrorKind,
+    i18n::{LocalizedMessage, MessageId, RenderedMessage},
+    AppState,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Sustained number of requests a single user or ip may make per minute
+    pub requests_per_minute: u32,
+    /// How many requests may be made in a burst before throttling kicks in
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            requests_per_minute: 120,
+            burst: 30,
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, now: Instant) -> Self {
+        Self {
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token out of the bucket,
+    /// or returns how long the caller has to wait until one is available
+    fn try_take(&mut self, config: &RateLimitConfig, now: Instant) -> Result<(), Duration> {
+        let capacity = config.burst.max(1) as f64;
+        let refill_per_sec = config.requests_per_minute.max(1) as f64 / 60.0;
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * refill_per_sec).min(capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / refill_per_sec,
+            ))
+        }
+    }
+}
+
+/// Per-user and per-ip token bucket rate limiter
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    buckets: Arc<DashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&self, key: &str, config: &RateLimitConfig) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.buckets
+            .entry(key.to_owned())
+            .or_insert_with(|| TokenBucket::new(config.burst.max(1) as f64, now))
+            .try_take(config, now)
+    }
+
+    /// Drops buckets that have been idle long enough to be full again
+    pub fn prune(&self, config: &RateLimitConfig) {
+        let now = Instant::now();
+        let refill_time = Duration::from_secs_f64(
+            config.burst.max(1) as f64 * 60.0 / config.requests_per_minute.max(1) as f64,
+        );
+        self.buckets
+            .retain(|_, bucket| now.saturating_duration_since(bucket.last_refill) < refill_time);
+    }
+}
+
+/// Middleware for expensive routes. Every request counts against the peer ip, and requests with a
+/// valid bearer token also against their user, so made up tokens can't get around the limit.
+pub async fn rate_limit<B>(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let config = state.global_settings.lock().await.rate_limit();
+    if !config.enabled {
+        return next.run(request).await;
+    }
+    let ip_key = match connect_info {
+        Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "unknown".to_string(),
+    };
+    let mut checked = state.rate_limiter.check(&ip_key, &config);
+    if checked.is_ok() {
+        let requester = match request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            Some(token) => state.users_manager.read().await.try_auth(token),
+            None => None,
+        };
+        if let Some(requester) = requester {
+            checked = state
+                .rate_limiter
+                .check(&format!("user:{}", requester.uid), &config);
+        }
+    }
+    match checked {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let message = LocalizedMessage::new(MessageId::TooManyRequests);
+            let mut response = (
+                StatusCode::TOO_MANY_REQUESTS,
+                json!({
+                    "kind": ErrorKind::TooManyRequests,
+                    "causes": [message.to_string()],
+                    "message": RenderedMessage::from(&message),
+                })
+                .to_string(),
+            )
+                .into_response();
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from((retry_after.as_secs_f64().ceil() as u64).max(1)),
+            );
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let config = RateLimitConfig {
+            enabled: true,
+            requests_per_minute: 60,
+            burst: 2,
+        };
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(config.burst as f64, start);
+        assert!(bucket.try_take(&config, start).is_ok());
+        assert!(bucket.try_take(&config, start).is_ok());
+        let wait = bucket.try_take(&config, start).unwrap_err();
+        assert_eq!(wait.as_secs(), 1);
+        // one token per second is refilled
+        assert!(bucket
+            .try_take(&config, start + Duration::from_secs(1))
+            .is_ok());
+        assert!(bucket
+            .try_take(&config, start + Duration::from_secs(1))
+            .is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! Settings suggestions drawn from how an instance has been running.
+//!
+//! Running instances are sampled every minute, memory against their maximum RAM, CPU, player
+//! count and the last TPS reading, and a few days of samples are kept in memory. Suggestions
+//! are only made once enough of them have been seen, and only from patterns that hold for a
+//! good share of the time rather than from a single spike.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    prelude::GameInstance,
+    quota,
+    traits::{
+        t_configurable::TConfigurable,
+        t_player::TPlayerManagement,
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+    AppState,
+};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+const HISTORY_SECS: i64 = 3 * 24 * 60 * 60;
+/// An hour of running time before anything is suggested
+const MIN_SAMPLES: usize = 60;
+/// Share of samples a pattern has to show up in to count as consistent
+const CONSISTENT_SHARE: f64 = 0.5;
+const HIGH_MEMORY_SHARE: f64 = 0.95;
+const LOW_MEMORY_SHARE: f64 = 0.5;
+/// Lowering max RAM is only suggested above this, smaller servers don't gain much from it
+const MIN_MAX_RAM_TO_LOWER_MB: u32 = 4096;
+const LOW_TPS: f64 = 18.0;
+const MIN_TPS_READINGS: usize = 10;
+const LOW_TPS_SHARE: f64 = 0.2;
+const HIGH_CPU_PERCENT: f32 = 90.0;
+const MIN_VIEW_DISTANCE: u32 = 6;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Sample {
+    time: i64,
+    memory_mb: f64,
+    max_ram_mb: u32,
+    cpu_percent: Option<f32>,
+    players: u32,
+    tps: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum RecommendationKind {
+    RaiseMaxRam,
+    LowerMaxRam,
+    LowerViewDistance,
+    LowTps,
+    HighCpu,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct Recommendation {
+    pub kind: RecommendationKind,
+    /// Key of the setting to change, if there is one to change
+    pub setting: Option<String>,
+    pub current: Option<String>,
+    pub suggested: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct Recommendations {
+    /// Minutes of running time the suggestions are based on, since the core started
+    pub samples: u32,
+    /// Time of the oldest sample, in seconds since the epoch
+    pub since: Option<i64>,
+    pub recommendations: Vec<Recommendation>,
+}
+
+#[derive(Default)]
+pub struct MetricsHistory {
+    instances: HashMap<InstanceUuid, VecDeque<Sample>>,
+}
+
+impl MetricsHistory {
+    fn push(&mut self, uuid: InstanceUuid, sample: Sample) {
+        let samples = self.instances.entry(uuid).or_default();
+        while samples
+            .front()
+            .map_or(false, |oldest| sample.time - oldest.time > HISTORY_SECS)
+        {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    pub fn forget(&mut self, uuid: &InstanceUuid) {
+        self.instances.remove(uuid);
+    }
+
+    pub fn recommendations(
+        &self,
+        uuid: &InstanceUuid,
+        view_distance: Option<u32>,
+    ) -> Recommendations {
+        let samples: Vec<Sample> = self
+            .instances
+            .get(uuid)
+            .map(|samples| samples.iter().cloned().collect())
+            .unwrap_or_default();
+        Recommendations {
+            samples: samples.len() as u32,
+            since: samples.first().map(|sample| sample.time),
+            recommendations: analyze(&samples, view_distance),
+        }
+    }
+}
+
+fn share(samples: &[Sample], f: impl Fn(&Sample) -> bool) -> f64 {
+    samples.iter().filter(|sample| f(sample)).count() as f64 / samples.len() as f64
+}
+
+/// Rounds megabytes up to whole gigabytes
+fn round_up_gb(mb: f64) -> u32 {
+    ((mb / 1024.0).ceil() as u32).max(1) * 1024
+}
+
+fn analyze(samples: &[Sample], view_distance: Option<u32>) -> Vec<Recommendation> {
+    let mut ret = Vec::new();
+    if samples.len() < MIN_SAMPLES {
+        return ret;
+    }
+    // max RAM may have been changed along the way, only the current one matters
+    let max_ram_mb = samples.last().map(|sample| sample.max_ram_mb).unwrap_or(0);
+    let current: Vec<Sample> = samples
+        .iter()
+        .filter(|sample| sample.max_ram_mb == max_ram_mb)
+        .cloned()
+        .collect();
+    if max_ram_mb > 0 && current.len() >= MIN_SAMPLES {
+        let high = share(&current, |sample| {
+            sample.memory_mb >= max_ram_mb as f64 * HIGH_MEMORY_SHARE
+        });
+        let mut memory: Vec<f64> = current.iter().map(|sample| sample.memory_mb).collect();
+        memory.sort_by(|a, b| a.total_cmp(b));
+        let p95 = memory[(memory.len() - 1) * 95 / 100];
+        if high >= CONSISTENT_SHARE {
+            let suggested = round_up_gb(max_ram_mb as f64 * 1.5);
+            ret.push(Recommendation {
+                kind: RecommendationKind::RaiseMaxRam,
+                setting: Some("max_ram".to_string()),
+                current: Some(max_ram_mb.to_string()),
+                suggested: Some(suggested.to_string()),
+                reason: format!(
+                    "Memory was {:.0}% used or more {:.0}% of the time, raise max RAM to {} GB",
+                    HIGH_MEMORY_SHARE * 100.0,
+                    high * 100.0,
+                    suggested / 1024
+                ),
+            });
+        } else if max_ram_mb >= MIN_MAX_RAM_TO_LOWER_MB
+            && p95 < max_ram_mb as f64 * LOW_MEMORY_SHARE
+        {
+            let suggested = round_up_gb(p95 * 1.5).max(MIN_MAX_RAM_TO_LOWER_MB / 2);
+            if suggested < max_ram_mb {
+                ret.push(Recommendation {
+                    kind: RecommendationKind::LowerMaxRam,
+                    setting: Some("max_ram".to_string()),
+                    current: Some(max_ram_mb.to_string()),
+                    suggested: Some(suggested.to_string()),
+                    reason: format!(
+                        "Memory stayed under {:.0} MB 95% of the time, max RAM could be lowered to {} GB",
+                        p95,
+                        suggested / 1024
+                    ),
+                });
+            }
+        }
+    }
+
+    let readings: Vec<&Sample> = samples
+        .iter()
+        .filter(|sample| sample.tps.is_some())
+        .collect();
+    let mut low_tps_players: Vec<u32> = readings
+        .iter()
+        .filter(|sample| sample.tps.map_or(false, |tps| tps < LOW_TPS))
+        .map(|sample| sample.players)
+        .collect();
+    if readings.len() >= MIN_TPS_READINGS
+        && low_tps_players.len() as f64 / readings.len() as f64 >= LOW_TPS_SHARE
+    {
+        low_tps_players.sort_unstable();
+        let players = low_tps_players[low_tps_players.len() / 2];
+        ret.push(match view_distance {
+            Some(view_distance) if view_distance > MIN_VIEW_DISTANCE => {
+                let suggested = (view_distance - 2).max(MIN_VIEW_DISTANCE);
+                Recommendation {
+                    kind: RecommendationKind::LowerViewDistance,
+                    setting: Some("view-distance".to_string()),
+                    current: Some(view_distance.to_string()),
+                    suggested: Some(suggested.to_string()),
+                    reason: format!(
+                        "TPS drops below {LOW_TPS} at around {players} players, view-distance could be lowered to {suggested}"
+                    ),
+                }
+            }
+            _ => Recommendation {
+                kind: RecommendationKind::LowTps,
+                setting: None,
+                current: None,
+                suggested: None,
+                reason: format!(
+                    "TPS drops below {LOW_TPS} at around {players} players, look for laggy farms or plugins"
+                ),
+            },
+        });
+    }
+
+    let cpu: Vec<Sample> = samples
+        .iter()
+        .filter(|sample| sample.cpu_percent.is_some())
+        .cloned()
+        .collect();
+    if cpu.len() >= MIN_SAMPLES {
+        let high = share(&cpu, |sample| {
+            sample
+                .cpu_percent
+                .map_or(false, |cpu| cpu >= HIGH_CPU_PERCENT)
+        });
+        if high >= CONSISTENT_SHARE {
+            ret.push(Recommendation {
+                kind: RecommendationKind::HighCpu,
+                setting: None,
+                current: None,
+                suggested: None,
+                reason: format!(
+                    "CPU was {HIGH_CPU_PERCENT}% used or more {:.0}% of the time, the machine may be too small for this server",
+                    high * 100.0
+                ),
+            });
+        }
+    }
+    ret
+}
+
+/// Samples running instances until the core shuts down
+pub async fn run_metrics_history(state: AppState) {
+    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let instances: Vec<(InstanceUuid, GameInstance)> = state
+            .instances
+            .lock()
+            .await
+            .iter()
+            .map(|(uuid, instance)| (uuid.clone(), instance.clone()))
+            .collect();
+        for (uuid, mut instance) in instances {
+            if instance.state().await != State::Running {
+                continue;
+            }
+            let report = instance.monitor().await;
+            let memory = match report.memory_usage {
+                Some(memory) => memory,
+                None => continue,
+            };
+            let now = chrono::Utc::now().timestamp();
+            let sample = Sample {
+                time: now,
+                memory_mb: memory as f64 / (1024.0 * 1024.0),
+                max_ram_mb: quota::max_ram_of(&mut instance).await,
+                cpu_percent: report.cpu_usage,
+                players: instance.get_player_count().await.unwrap_or(0),
+                tps: state.health.lock().await.tps(&uuid, now),
+            };
+            state.metrics_history.lock().await.push(uuid, sample);
+        }
+    }
+}
+
+/// The view distance of Minecraft instances, to suggest lowering
+pub async fn view_distance(instance: &mut GameInstance) -> Option<u32> {
+    instance
+        .configurable_manifest()
+        .await
+        .get_unique_setting_key("view-distance")
+        .and_then(|setting| setting.get_value())
+        .and_then(|value| value.try_as_unsigned_integer().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(memory_mb: f64, players: u32, tps: Option<f64>) -> Sample {
+        Sample {
+            time: 0,
+            memory_mb,
+            max_ram_mb: 4096,
+            cpu_percent: Some(20.0),
+            players,
+            tps,
+        }
+    }
+
+    #[test]
+    fn test_analyze() {
+        let few = vec![sample(4000.0, 0, None); MIN_SAMPLES - 1];
+        assert!(analyze(&few, Some(10)).is_empty());
+
+        let full = vec![sample(4000.0, 0, None); MIN_SAMPLES];
+        let ret = analyze(&full, Some(10));
+        assert_eq!(ret.len(), 1);
+        assert_eq!(ret[0].kind, RecommendationKind::RaiseMaxRam);
+        assert_eq!(ret[0].suggested.as_deref(), Some("6144"));
+
+        let idle = vec![sample(800.0, 0, None); MIN_SAMPLES];
+        let ret = analyze(&idle, Some(10));
+        assert_eq!(ret[0].kind, RecommendationKind::LowerMaxRam);
+        assert_eq!(ret[0].suggested.as_deref(), Some("2048"));
+
+        let mut laggy = vec![sample(2500.0, 4, Some(20.0)); MIN_SAMPLES];
+        laggy.extend(vec![sample(2500.0, 15, Some(14.0)); 20]);
+        let ret = analyze(&laggy, Some(12));
+        assert_eq!(ret.len(), 1);
+        assert_eq!(ret[0].kind, RecommendationKind::LowerViewDistance);
+        assert_eq!(ret[0].suggested.as_deref(), Some("10"));
+        assert!(ret[0].reason.contains("15 players"));
+        assert_eq!(
+            analyze(&laggy, Some(MIN_VIEW_DISTANCE))[0].kind,
+            RecommendationKind::LowTps
+        );
+    }
+
+    #[test]
+    fn test_history_window() {
+        let mut history = MetricsHistory::default();
+        let uuid = InstanceUuid::default();
+        for time in [0, 60, HISTORY_SECS + 30] {
+            history.push(
+                uuid.clone(),
+                Sample {
+                    time,
+                    ..sample(1000.0, 0, None)
+                },
+            );
+        }
+        let recommendations = history.recommendations(&uuid, None);
+        assert_eq!(recommendations.samples, 2);
+        assert_eq!(recommendations.since, Some(60));
+    }
+}
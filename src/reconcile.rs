@@ -0,0 +1,241 @@
+//! Finding instance directories and registrations that no longer line up.
+//!
+//! An instance is its directory in a storage location, the `.lodestone_config` in it and what
+//! is configured for it in the global settings. Files moved by hand or the core going down at
+//! the wrong time can leave a directory nothing is loaded from, an orphan, or settings for an
+//! instance that has no directory anymore, a ghost. Both are found on startup and listed by
+//! `/instances/reconcile`, where owners can adopt or remove them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    instance_creation,
+    storage::StorageLocation,
+    traits::t_configurable::TConfigurable,
+    types::{DotLodestoneConfig, InstanceUuid},
+    AppState,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum OrphanReason {
+    /// Has a valid `.lodestone_config`, but the instance failed to load
+    NotLoaded,
+    /// Its `.lodestone_config` has the uuid of an instance loaded from another directory
+    DuplicateUuid,
+    /// `.lodestone_config` is missing or can't be read
+    MissingConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct OrphanDirectory {
+    #[ts(type = "string")]
+    pub path: PathBuf,
+    /// Id of the storage location the directory is in
+    pub storage_location: String,
+    pub reason: OrphanReason,
+    /// From `.lodestone_config`, if it could be read
+    pub instance_uuid: Option<InstanceUuid>,
+    /// Whether it holds a Minecraft server that can be loaded
+    pub adoptable: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct GhostInstance {
+    pub instance_uuid: InstanceUuid,
+    /// Whether the instance is still loaded even though its directory is gone
+    pub loaded: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, TS)]
+#[ts(export)]
+pub struct ReconcileReport {
+    pub orphans: Vec<OrphanDirectory>,
+    pub ghosts: Vec<GhostInstance>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum ReconcileAction {
+    /// Loads the directory as an instance owned by whoever adopts it
+    AdoptOrphan {
+        #[ts(type = "string")]
+        path: PathBuf,
+    },
+    /// Deletes the directory
+    RemoveOrphan {
+        #[ts(type = "string")]
+        path: PathBuf,
+    },
+    /// Drops everything configured for the instance
+    RemoveGhost { instance_uuid: InstanceUuid },
+}
+
+/// Why the directory at `path` is an orphan, `None` if the instance in it is loaded from it
+fn classify(
+    path: &Path,
+    config: Option<&DotLodestoneConfig>,
+    loaded: &HashMap<InstanceUuid, PathBuf>,
+) -> Option<OrphanReason> {
+    match config {
+        None => Some(OrphanReason::MissingConfig),
+        Some(config) => match loaded.get(config.uuid()) {
+            Some(loaded_path) if loaded_path == path => None,
+            Some(_) => Some(OrphanReason::DuplicateUuid),
+            None => Some(OrphanReason::NotLoaded),
+        },
+    }
+}
+
+async fn read_config(path: &Path) -> Option<DotLodestoneConfig> {
+    let bytes = tokio::fs::read(path.join(".lodestone_config")).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+async fn scan_location(
+    location: &StorageLocation,
+    loaded: &HashMap<InstanceUuid, PathBuf>,
+    skipped: &HashSet<PathBuf>,
+    orphans: &mut Vec<OrphanDirectory>,
+) {
+    let mut entries = match tokio::fs::read_dir(&location.path).await {
+        Ok(v) => v,
+        Err(e) => {
+            warn!(
+                "Failed to read storage location {} while reconciling instances: {e}",
+                location.name
+            );
+            return;
+        }
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        // archives are files, and directories being moved in are staged under a dot name
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if hidden || skipped.contains(&path) || !path.is_dir() {
+            continue;
+        }
+        let config = read_config(&path).await;
+        if let Some(reason) = classify(&path, config.as_ref(), loaded) {
+            orphans.push(OrphanDirectory {
+                adoptable: path.join(".lodestone_minecraft_config.json").is_file(),
+                storage_location: location.id.clone(),
+                reason,
+                instance_uuid: config.map(|config| config.uuid().clone()),
+                path,
+            });
+        }
+    }
+}
+
+/// Compares the storage locations, the loaded instances and the global settings
+pub async fn report(state: &AppState) -> Result<ReconcileReport, Error> {
+    let mut loaded = HashMap::new();
+    for (uuid, instance) in state.instances.lock().await.iter() {
+        loaded.insert(uuid.clone(), instance.path().await);
+    }
+    let (locations, referenced) = {
+        let global_settings = state.global_settings.lock().await;
+        let mut locations = vec![StorageLocation::default_location()];
+        locations.extend(global_settings.storage_locations().iter().cloned());
+        (locations, global_settings.referenced_instances())
+    };
+    // instances still being set up are neither
+    let pending = instance_creation::pending_creations(&state.sqlite_pool).await?;
+    let skipped: HashSet<PathBuf> = pending
+        .iter()
+        .map(|creation| creation.setup_path.clone())
+        .collect();
+
+    let mut report = ReconcileReport::default();
+    for location in &locations {
+        scan_location(location, &loaded, &skipped, &mut report.orphans).await;
+    }
+    for (uuid, path) in &loaded {
+        if tokio::fs::metadata(path).await.is_err() {
+            report.ghosts.push(GhostInstance {
+                instance_uuid: uuid.clone(),
+                loaded: true,
+            });
+        }
+    }
+    for uuid in referenced {
+        let being_created = pending
+            .iter()
+            .any(|creation| creation.instance_uuid == uuid);
+        if !loaded.contains_key(&uuid) && !being_created {
+            report.ghosts.push(GhostInstance {
+                instance_uuid: uuid,
+                loaded: false,
+            });
+        }
+    }
+    report.orphans.sort_by(|a, b| a.path.cmp(&b.path));
+    report
+        .ghosts
+        .sort_by(|a, b| a.instance_uuid.as_ref().cmp(b.instance_uuid.as_ref()));
+    Ok(report)
+}
+
+/// Logs what is out of place, so it doesn't go unnoticed until someone looks at the report
+pub async fn check_on_startup(state: AppState) {
+    let report = match report(&state).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to reconcile instances: {e}");
+            return;
+        }
+    };
+    for orphan in &report.orphans {
+        warn!(
+            "{} is not loaded as an instance ({:?}), adopt or remove it from the reconcile report",
+            orphan.path.display(),
+            orphan.reason
+        );
+    }
+    for ghost in &report.ghosts {
+        warn!(
+            "Instance {} has settings but no directory, remove it from the reconcile report",
+            ghost.instance_uuid
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::t_configurable::GameType;
+
+    #[test]
+    fn test_classify() {
+        let config = DotLodestoneConfig::new(InstanceUuid::default(), GameType::MinecraftJava);
+        let path = PathBuf::from("/instances/survival");
+        let mut loaded = HashMap::new();
+
+        assert_eq!(
+            classify(&path, None, &loaded),
+            Some(OrphanReason::MissingConfig)
+        );
+        assert_eq!(
+            classify(&path, Some(&config), &loaded),
+            Some(OrphanReason::NotLoaded)
+        );
+        loaded.insert(config.uuid().clone(), path.clone());
+        assert_eq!(classify(&path, Some(&config), &loaded), None);
+        assert_eq!(
+            classify(Path::new("/instances/copy"), Some(&config), &loaded),
+            Some(OrphanReason::DuplicateUuid)
+        );
+    }
+}
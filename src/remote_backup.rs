@@ -0,0 +1,832 @@
+//! Off-site copies of snapshots.
+//!
+//! A snapshot is packed into a tarball and uploaded to S3-compatible storage or a WebDAV server.
+//! Every instance gets a folder on the remote holding its archives and an `index.json` describing them,
+//! so listing and pruning backups works the same way regardless of what the backend can list.
+
+use std::{
+    path::Path,
+    pin::Pin,
+    task::{ready, Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use futures::{Future, StreamExt};
+use s3::{creds::Credentials, Bucket, Region};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWriteExt, ReadBuf},
+    time::{Instant, Sleep},
+};
+use tokio_util::io::ReaderStream;
+use tracing::{instrument, warn};
+use ts_rs::TS;
+
+use crate::{
+    disk_space::{self, DiskSpaceConfig},
+    error::{Error, ErrorKind},
+    prelude::{path_to_snapshots, path_to_tmp},
+    snapshot::{self, Manifest, SnapshotInfo, VerificationStatus},
+    types::{InstanceUuid, Snowflake},
+};
+
+/// Sent to clients in place of credentials, and understood as "unchanged" when sent back
+pub const SECRET_PLACEHOLDER: &str = "********";
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum RemoteStorage {
+    S3 {
+        /// e.g. `https://s3.us-east-1.amazonaws.com` or the address of a MinIO server
+        endpoint: String,
+        region: String,
+        bucket: String,
+        access_key: String,
+        secret_key: String,
+        /// Address the bucket as `<endpoint>/<bucket>` instead of `<bucket>.<endpoint>`,
+        /// needed by most self hosted implementations
+        #[serde(default)]
+        path_style: bool,
+    },
+    WebDav {
+        url: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct RetentionPolicy {
+    /// Number of most recent backups to keep
+    pub keep_last: Option<u32>,
+    /// Backups older than this are removed
+    pub max_age_days: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct RemoteBackupSettings {
+    pub storage: RemoteStorage,
+    /// Folder on the remote that backups are stored under
+    #[serde(default)]
+    pub path_prefix: String,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Upload speed cap in KiB/s
+    pub bandwidth_limit: Option<u32>,
+    /// Upload every snapshot as soon as it is created
+    #[serde(default)]
+    pub upload_snapshots: bool,
+    /// Read the archive back and check it against the snapshot's manifest before uploading it
+    #[serde(default)]
+    pub verify_archives: bool,
+}
+
+impl RemoteBackupSettings {
+    /// A copy that is safe to hand out to clients
+    pub fn redacted(&self) -> Self {
+        let mut ret = self.clone();
+        match &mut ret.storage {
+            RemoteStorage::S3 { secret_key, .. } => *secret_key = SECRET_PLACEHOLDER.to_string(),
+            RemoteStorage::WebDav { password, .. } => {
+                if password.is_some() {
+                    *password = Some(SECRET_PLACEHOLDER.to_string())
+                }
+            }
+        }
+        ret
+    }
+
+    /// Fills in credentials a client sent back as [`SECRET_PLACEHOLDER`]
+    pub fn keep_secrets_from(&mut self, old: &RemoteBackupSettings) {
+        match (&mut self.storage, &old.storage) {
+            (
+                RemoteStorage::S3 { secret_key, .. },
+                RemoteStorage::S3 {
+                    secret_key: old_secret_key,
+                    ..
+                },
+            ) if secret_key.as_str() == SECRET_PLACEHOLDER => *secret_key = old_secret_key.clone(),
+            (
+                RemoteStorage::WebDav { password, .. },
+                RemoteStorage::WebDav {
+                    password: old_password,
+                    ..
+                },
+            ) if password.as_deref() == Some(SECRET_PLACEHOLDER) => {
+                *password = old_password.clone()
+            }
+            _ => {}
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.bandwidth_limit == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Bandwidth limit must be greater than 0"),
+            });
+        }
+        if self.retention.keep_last == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Retention must keep at least one backup"),
+            });
+        }
+        Backend::new(&self.storage).map(|_| ())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct RemoteBackupInfo {
+    pub snapshot: SnapshotInfo,
+    /// Size of the compressed archive on the remote
+    pub archive_size: u64,
+    pub upload_time: i64,
+    /// Outcome of the test extraction of the archive, if it was done
+    #[serde(default)]
+    pub verification: VerificationStatus,
+}
+
+enum Backend {
+    S3(Bucket),
+    WebDav {
+        client: reqwest::Client,
+        url: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+}
+
+impl Backend {
+    fn new(storage: &RemoteStorage) -> Result<Self, Error> {
+        match storage {
+            RemoteStorage::S3 {
+                endpoint,
+                region,
+                bucket,
+                access_key,
+                secret_key,
+                path_style,
+            } => {
+                let credentials =
+                    Credentials::new(Some(access_key), Some(secret_key), None, None, None)
+                        .map_err(|e| Error {
+                            kind: ErrorKind::BadRequest,
+                            source: eyre!("Invalid S3 credentials: {e}"),
+                        })?;
+                let bucket = Bucket::new(
+                    bucket,
+                    Region::Custom {
+                        region: region.clone(),
+                        endpoint: endpoint.clone(),
+                    },
+                    credentials,
+                )
+                .map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid S3 bucket: {e}"),
+                })?;
+                Ok(Backend::S3(if *path_style {
+                    bucket.with_path_style()
+                } else {
+                    bucket
+                }))
+            }
+            RemoteStorage::WebDav {
+                url,
+                username,
+                password,
+            } => {
+                url::Url::parse(url).map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid WebDAV url: {e}"),
+                })?;
+                Ok(Backend::WebDav {
+                    client: reqwest::Client::new(),
+                    url: url.trim_end_matches('/').to_string(),
+                    username: username.clone(),
+                    password: password.clone(),
+                })
+            }
+        }
+    }
+
+    fn webdav_request(
+        client: &reqwest::Client,
+        method: reqwest::Method,
+        url: String,
+        username: &Option<String>,
+        password: &Option<String>,
+    ) -> reqwest::RequestBuilder {
+        let request = client.request(method, url);
+        match username {
+            Some(username) => request.basic_auth(username, password.as_ref()),
+            None => request,
+        }
+    }
+
+    async fn put(
+        &self,
+        key: &str,
+        mut reader: impl AsyncRead + Send + Sync + Unpin + 'static,
+        size: u64,
+    ) -> Result<(), Error> {
+        match self {
+            Backend::S3(bucket) => {
+                let status = bucket
+                    .put_object_stream(&mut reader, key)
+                    .await
+                    .context("Failed to upload to S3")?;
+                if !(200..300).contains(&status) {
+                    return Err(eyre!("S3 upload failed with status {status}").into());
+                }
+            }
+            Backend::WebDav {
+                client,
+                url,
+                username,
+                password,
+            } => {
+                // collections have to exist before anything can be put in them
+                let mut collection = url.clone();
+                for segment in key
+                    .split('/')
+                    .rev()
+                    .skip(1)
+                    .collect::<Vec<_>>()
+                    .iter()
+                    .rev()
+                {
+                    collection = format!("{collection}/{segment}");
+                    // fails with 405 if the collection already exists
+                    let _ = Self::webdav_request(
+                        client,
+                        reqwest::Method::from_bytes(b"MKCOL").unwrap(),
+                        format!("{collection}/"),
+                        username,
+                        password,
+                    )
+                    .send()
+                    .await;
+                }
+                Self::webdav_request(
+                    client,
+                    reqwest::Method::PUT,
+                    format!("{url}/{key}"),
+                    username,
+                    password,
+                )
+                .header(reqwest::header::CONTENT_LENGTH, size)
+                .body(reqwest::Body::wrap_stream(ReaderStream::new(reader)))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .context("Failed to upload to WebDAV server")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `None` if there is nothing stored under `key`
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            Backend::S3(bucket) => {
+                let response = bucket
+                    .get_object(key)
+                    .await
+                    .context("Failed to download from S3")?;
+                match response.status_code() {
+                    404 => Ok(None),
+                    200..=299 => Ok(Some(response.bytes().to_vec())),
+                    status => Err(eyre!("S3 download failed with status {status}").into()),
+                }
+            }
+            Backend::WebDav {
+                client,
+                url,
+                username,
+                password,
+            } => {
+                let response = Self::webdav_request(
+                    client,
+                    reqwest::Method::GET,
+                    format!("{url}/{key}"),
+                    username,
+                    password,
+                )
+                .send()
+                .await
+                .context("Failed to download from WebDAV server")?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                Ok(Some(
+                    response
+                        .error_for_status()
+                        .context("Failed to download from WebDAV server")?
+                        .bytes()
+                        .await
+                        .context("Failed to download from WebDAV server")?
+                        .to_vec(),
+                ))
+            }
+        }
+    }
+
+    async fn get_to_file(&self, key: &str, dest: &Path) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(dest)
+            .await
+            .context(format!("Failed to create file {}", dest.display()))?;
+        match self {
+            Backend::S3(bucket) => {
+                let status = bucket
+                    .get_object_to_writer(key, &mut file)
+                    .await
+                    .context("Failed to download from S3")?;
+                if !(200..300).contains(&status) {
+                    return Err(eyre!("S3 download failed with status {status}").into());
+                }
+            }
+            Backend::WebDav {
+                client,
+                url,
+                username,
+                password,
+            } => {
+                let mut stream = Self::webdav_request(
+                    client,
+                    reqwest::Method::GET,
+                    format!("{url}/{key}"),
+                    username,
+                    password,
+                )
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .context("Failed to download from WebDAV server")?
+                .bytes_stream();
+                while let Some(chunk) = stream.next().await {
+                    file.write_all(&chunk.context("Failed to download from WebDAV server")?)
+                        .await
+                        .context(format!("Failed to write to file {}", dest.display()))?;
+                }
+            }
+        }
+        file.flush()
+            .await
+            .context(format!("Failed to write to file {}", dest.display()))?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match self {
+            Backend::S3(bucket) => {
+                let response = bucket
+                    .delete_object(key)
+                    .await
+                    .context("Failed to delete from S3")?;
+                match response.status_code() {
+                    200..=299 | 404 => Ok(()),
+                    status => Err(eyre!("S3 delete failed with status {status}").into()),
+                }
+            }
+            Backend::WebDav {
+                client,
+                url,
+                username,
+                password,
+            } => {
+                let response = Self::webdav_request(
+                    client,
+                    reqwest::Method::DELETE,
+                    format!("{url}/{key}"),
+                    username,
+                    password,
+                )
+                .send()
+                .await
+                .context("Failed to delete from WebDAV server")?;
+                if response.status() != reqwest::StatusCode::NOT_FOUND {
+                    response
+                        .error_for_status()
+                        .context("Failed to delete from WebDAV server")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Caps how fast the inner reader can be read from
+struct Throttled<R> {
+    inner: R,
+    bytes_per_second: u64,
+    start: Instant,
+    read: u64,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> Throttled<R> {
+    fn new(inner: R, bytes_per_second: u64) -> Self {
+        Self {
+            inner,
+            bytes_per_second,
+            start: Instant::now(),
+            read: 0,
+            delay: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Throttled<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        if let Some(delay) = this.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            this.delay = None;
+        }
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.read += (buf.filled().len() - filled) as u64;
+        let due =
+            this.start + Duration::from_secs_f64(this.read as f64 / this.bytes_per_second as f64);
+        if due > Instant::now() {
+            this.delay = Some(Box::pin(tokio::time::sleep_until(due)));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn instance_dir(settings: &RemoteBackupSettings, instance_uuid: &InstanceUuid) -> String {
+    let prefix = settings.path_prefix.trim_matches('/');
+    if prefix.is_empty() {
+        instance_uuid.no_prefix()
+    } else {
+        format!("{prefix}/{}", instance_uuid.no_prefix())
+    }
+}
+
+fn archive_key(
+    settings: &RemoteBackupSettings,
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> String {
+    format!(
+        "{}/{}.tar.gz",
+        instance_dir(settings, instance_uuid),
+        id.to_string()
+    )
+}
+
+fn manifest_key(
+    settings: &RemoteBackupSettings,
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> String {
+    format!(
+        "{}/{}.manifest.json",
+        instance_dir(settings, instance_uuid),
+        id.to_string()
+    )
+}
+
+/// Deletes the archive and manifest of a backup
+async fn delete_backup_files(
+    backend: &Backend,
+    settings: &RemoteBackupSettings,
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> Result<(), Error> {
+    backend
+        .delete(&archive_key(settings, instance_uuid, id))
+        .await?;
+    backend
+        .delete(&manifest_key(settings, instance_uuid, id))
+        .await
+}
+
+async fn read_index(
+    backend: &Backend,
+    settings: &RemoteBackupSettings,
+    instance_uuid: &InstanceUuid,
+) -> Result<Vec<RemoteBackupInfo>, Error> {
+    match backend
+        .get(&format!(
+            "{}/{INDEX_FILE}",
+            instance_dir(settings, instance_uuid)
+        ))
+        .await?
+    {
+        Some(content) => {
+            Ok(serde_json::from_slice(&content).context("Failed to parse remote backup index")?)
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+async fn write_index(
+    backend: &Backend,
+    settings: &RemoteBackupSettings,
+    instance_uuid: &InstanceUuid,
+    index: &[RemoteBackupInfo],
+) -> Result<(), Error> {
+    let content =
+        serde_json::to_vec_pretty(index).context("Failed to serialize remote backup index")?;
+    let size = content.len() as u64;
+    backend
+        .put(
+            &format!("{}/{INDEX_FILE}", instance_dir(settings, instance_uuid)),
+            std::io::Cursor::new(content),
+            size,
+        )
+        .await
+}
+
+/// Removes the backups that fall outside of `policy` from `index`, returning them
+fn apply_retention(
+    index: &mut Vec<RemoteBackupInfo>,
+    policy: &RetentionPolicy,
+    now: i64,
+) -> Vec<RemoteBackupInfo> {
+    index.sort_by_key(|backup| backup.snapshot.id);
+    let mut removed = Vec::new();
+    if let Some(max_age_days) = policy.max_age_days {
+        let cutoff = now - max_age_days as i64 * 24 * 60 * 60;
+        let (old, kept): (Vec<_>, Vec<_>) = index
+            .drain(..)
+            .partition(|backup| backup.snapshot.creation_time < cutoff);
+        removed.extend(old);
+        *index = kept;
+    }
+    if let Some(keep_last) = policy.keep_last {
+        let excess = index.len().saturating_sub(keep_last as usize);
+        removed.extend(index.drain(..excess));
+    }
+    removed
+}
+
+/// Backups of an instance on the remote, oldest first
+pub async fn list_remote_backups(
+    settings: &RemoteBackupSettings,
+    instance_uuid: &InstanceUuid,
+) -> Result<Vec<RemoteBackupInfo>, Error> {
+    let backend = Backend::new(&settings.storage)?;
+    let mut index = read_index(&backend, settings, instance_uuid).await?;
+    index.sort_by_key(|backup| backup.snapshot.id);
+    Ok(index)
+}
+
+/// Uploads a local snapshot, then prunes the remote according to the retention policy
+#[instrument(skip_all, fields(instance_uuid = %instance_uuid, snapshot_id = %id.to_string()))]
+pub async fn upload_snapshot(
+    settings: &RemoteBackupSettings,
+    disk_space: &DiskSpaceConfig,
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> Result<RemoteBackupInfo, Error> {
+    let snapshot = snapshot::get_snapshot(instance_uuid, id).await?;
+    let backend = Backend::new(&settings.storage)?;
+    // the archive is compressed, it will not be larger than the snapshot
+    disk_space::ensure_space(
+        disk_space,
+        path_to_tmp(),
+        snapshot.size,
+        Some(instance_uuid),
+        Some(id),
+    )
+    .await?;
+
+    crate::util::fs::create_dir_all(path_to_tmp()).await?;
+    let archive = tempfile::NamedTempFile::new_in(path_to_tmp())
+        .context("Failed to create temporary file")?
+        .into_temp_path();
+    snapshot::export_snapshot(instance_uuid, id, archive.to_path_buf()).await?;
+    let manifest = snapshot::read_manifest(instance_uuid, id).await?;
+    let verification = match &manifest {
+        Some(manifest) if settings.verify_archives => {
+            let problems = snapshot::check_archive(archive.to_path_buf(), manifest.clone()).await?;
+            if !problems.is_empty() {
+                return Err(eyre!(
+                    "Archive of the snapshot is broken, not uploading it: {}",
+                    problems.join(", ")
+                )
+                .into());
+            }
+            VerificationStatus::Verified {
+                time: chrono::Utc::now().timestamp(),
+            }
+        }
+        _ => VerificationStatus::Unverified,
+    };
+    let file = tokio::fs::File::open(&archive)
+        .await
+        .context("Failed to open snapshot archive")?;
+    let archive_size = file
+        .metadata()
+        .await
+        .context("Failed to read snapshot archive metadata")?
+        .len();
+    let key = archive_key(settings, instance_uuid, id);
+    match settings.bandwidth_limit {
+        Some(limit) => {
+            backend
+                .put(
+                    &key,
+                    Throttled::new(file, limit as u64 * 1024),
+                    archive_size,
+                )
+                .await?
+        }
+        None => backend.put(&key, file, archive_size).await?,
+    }
+    if let Some(manifest) = &manifest {
+        let content =
+            serde_json::to_vec(manifest).context("Failed to serialize snapshot manifest")?;
+        let size = content.len() as u64;
+        backend
+            .put(
+                &manifest_key(settings, instance_uuid, id),
+                std::io::Cursor::new(content),
+                size,
+            )
+            .await?;
+    }
+
+    let info = RemoteBackupInfo {
+        snapshot,
+        archive_size,
+        upload_time: chrono::Utc::now().timestamp(),
+        verification,
+    };
+    let mut index = read_index(&backend, settings, instance_uuid).await?;
+    index.retain(|backup| backup.snapshot.id != id);
+    index.push(info.clone());
+    let pruned = apply_retention(&mut index, &settings.retention, info.upload_time);
+    write_index(&backend, settings, instance_uuid, &index).await?;
+    for backup in pruned {
+        if let Err(e) =
+            delete_backup_files(&backend, settings, instance_uuid, backup.snapshot.id).await
+        {
+            warn!(
+                "Failed to delete remote backup {} past retention: {e}",
+                backup.snapshot.id.to_string()
+            );
+        }
+    }
+    Ok(info)
+}
+
+/// Downloads a remote backup into a local snapshot, which can then be restored like any other.
+/// The snapshot is checked against the manifest uploaded with it
+#[instrument(skip_all, fields(instance_uuid = %instance_uuid, snapshot_id = %id.to_string()))]
+pub async fn download_backup(
+    settings: &RemoteBackupSettings,
+    disk_space: &DiskSpaceConfig,
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> Result<SnapshotInfo, Error> {
+    let backend = Backend::new(&settings.storage)?;
+    let backup = read_index(&backend, settings, instance_uuid)
+        .await?
+        .into_iter()
+        .find(|backup| backup.snapshot.id == id)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Remote backup not found"),
+        })?;
+    // room for both the archive and the snapshot extracted from it
+    disk_space::ensure_space(
+        disk_space,
+        path_to_snapshots(),
+        backup.archive_size + backup.snapshot.size,
+        Some(instance_uuid),
+        None,
+    )
+    .await?;
+    crate::util::fs::create_dir_all(path_to_tmp()).await?;
+    let archive = tempfile::NamedTempFile::new_in(path_to_tmp())
+        .context("Failed to create temporary file")?
+        .into_temp_path();
+    backend
+        .get_to_file(&archive_key(settings, instance_uuid, id), &archive)
+        .await?;
+    let manifest: Option<Manifest> = match backend
+        .get(&manifest_key(settings, instance_uuid, id))
+        .await?
+    {
+        Some(content) => {
+            Some(serde_json::from_slice(&content).context("Failed to parse snapshot manifest")?)
+        }
+        None => None,
+    };
+    snapshot::import_snapshot(backup.snapshot, archive.to_path_buf(), manifest).await
+}
+
+pub async fn delete_remote_backup(
+    settings: &RemoteBackupSettings,
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> Result<(), Error> {
+    let backend = Backend::new(&settings.storage)?;
+    let mut index = read_index(&backend, settings, instance_uuid).await?;
+    let len = index.len();
+    index.retain(|backup| backup.snapshot.id != id);
+    if index.len() == len {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Remote backup not found"),
+        });
+    }
+    write_index(&backend, settings, instance_uuid, &index).await?;
+    delete_backup_files(&backend, settings, instance_uuid, id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backup(name: &str, creation_time: i64) -> RemoteBackupInfo {
+        RemoteBackupInfo {
+            snapshot: SnapshotInfo {
+                id: Snowflake::new(),
+                instance_uuid: InstanceUuid::default(),
+                name: name.to_string(),
+                description: None,
+                creation_time,
+                size: 0,
+                bytes_copied: 0,
+                verification: VerificationStatus::Unverified,
+            },
+            archive_size: 0,
+            upload_time: creation_time,
+            verification: VerificationStatus::Unverified,
+        }
+    }
+
+    #[test]
+    fn test_apply_retention() {
+        let day = 24 * 60 * 60;
+        let now = 100 * day;
+        // snowflakes are increasing, so the order of creation is the order of the ids
+        let (first, second, third, fourth) = (
+            backup("1", now - 10 * day),
+            backup("2", now - 5 * day),
+            backup("3", now - day),
+            backup("4", now),
+        );
+        let mut index = vec![third, first, fourth, second];
+        let removed = apply_retention(
+            &mut index,
+            &RetentionPolicy {
+                keep_last: Some(2),
+                max_age_days: Some(7),
+            },
+            now,
+        );
+        let ids = |backups: &[RemoteBackupInfo]| {
+            backups
+                .iter()
+                .map(|backup| backup.snapshot.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&index), vec!["3", "4"]);
+        assert_eq!(ids(&removed), vec!["1", "2"]);
+
+        let mut index = vec![backup("1", 0), backup("2", 0)];
+        assert!(apply_retention(&mut index, &RetentionPolicy::default(), now).is_empty());
+        assert_eq!(index.len(), 2);
+    }
+
+    #[test]
+    fn test_keep_secrets() {
+        let old = RemoteBackupSettings {
+            storage: RemoteStorage::S3 {
+                endpoint: "https://s3.example.com".to_string(),
+                region: "us-east-1".to_string(),
+                bucket: "backups".to_string(),
+                access_key: "access".to_string(),
+                secret_key: "secret".to_string(),
+                path_style: false,
+            },
+            path_prefix: String::new(),
+            retention: RetentionPolicy::default(),
+            bandwidth_limit: None,
+            upload_snapshots: false,
+            verify_archives: false,
+        };
+        let mut new = old.redacted();
+        assert_ne!(new, old);
+        new.keep_secrets_from(&old);
+        assert_eq!(new, old);
+    }
+}
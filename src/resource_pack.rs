@@ -0,0 +1,172 @@
+//! Resource packs hosted by the core itself, so a server's pack doesn't need a file host.
+//!
+//! An instance has at most one pack, served without sign in at `/resource_packs/:id`, since
+//! Minecraft clients can't authenticate. The id is random and long enough not to be guessed, and
+//! stays the same when the pack is replaced, so the URL in `server.properties` only changes if
+//! the core's address does. Clients tell packs apart by their SHA-1, which is written next to it.
+
+use std::path::PathBuf;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tokio::io::AsyncWriteExt;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    prelude::path_to_stores,
+    util::rand_alphanumeric,
+};
+
+/// What Minecraft accepts since 1.18, older versions only take 100 MB
+pub const MAX_PACK_SIZE: u64 = 250 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ResourcePack {
+    pub id: String,
+    /// The name the pack was uploaded with
+    pub file_name: String,
+    pub size: u64,
+    /// Hex encoded, like `resource-pack-sha1` wants it
+    pub sha1: String,
+    /// Written to `resource-pack`
+    pub url: String,
+    pub uploaded_at: i64,
+}
+
+impl ResourcePack {
+    pub fn new_id() -> String {
+        rand_alphanumeric(32)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        path_to_pack(&self.id)
+    }
+}
+
+fn path_to_packs() -> PathBuf {
+    path_to_stores().join("resource_packs")
+}
+
+/// Check the id with [`is_valid_id`] first when it comes from a request
+pub fn path_to_pack(id: &str) -> PathBuf {
+    path_to_packs().join(format!("{id}.zip"))
+}
+
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() == 32 && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// The URL players download the pack from, `base_url` being where the core is reachable
+pub fn pack_url(base_url: &str, id: &str) -> Result<String, Error> {
+    let base_url = base_url.trim().trim_end_matches('/');
+    if !(base_url.starts_with("http://") || base_url.starts_with("https://"))
+        || base_url
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The core's address must be an http or https URL"),
+        });
+    }
+    Ok(format!("{base_url}/resource_packs/{id}"))
+}
+
+/// Writes a pack as it is uploaded, hashing it along the way
+pub struct PackWriter {
+    file: tokio::fs::File,
+    path: PathBuf,
+    hasher: Sha1,
+    size: u64,
+}
+
+impl PackWriter {
+    /// Starts writing next to the pack it replaces, which is kept until [`PackWriter::finish`]
+    pub async fn create(id: &str) -> Result<Self, Error> {
+        crate::util::fs::create_dir_all(path_to_packs()).await?;
+        let path = path_to_packs().join(format!("{id}.zip.part"));
+        let file = tokio::fs::File::create(&path)
+            .await
+            .context(format!("Failed to create {}", path.display()))?;
+        Ok(Self {
+            file,
+            path,
+            hasher: Sha1::new(),
+            size: 0,
+        })
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.size += chunk.len() as u64;
+        if self.size > MAX_PACK_SIZE {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Resource packs can be at most {} MB",
+                    MAX_PACK_SIZE / 1024 / 1024
+                ),
+            });
+        }
+        self.hasher.update(chunk);
+        self.file
+            .write_all(chunk)
+            .await
+            .context("Failed to write resource pack")?;
+        Ok(())
+    }
+
+    /// Moves the pack in place, returns its size and SHA-1
+    pub async fn finish(mut self, id: &str) -> Result<(u64, String), Error> {
+        if self.size == 0 {
+            self.discard().await;
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("No resource pack was uploaded"),
+            });
+        }
+        self.file
+            .flush()
+            .await
+            .context("Failed to write resource pack")?;
+        tokio::fs::rename(&self.path, path_to_pack(id))
+            .await
+            .context("Failed to move resource pack in place")?;
+        Ok((self.size, hex::encode(self.hasher.finalize())))
+    }
+
+    pub async fn discard(self) {
+        drop(self.file);
+        let _ = tokio::fs::remove_file(&self.path).await;
+    }
+}
+
+pub async fn remove_pack(id: &str) -> Result<(), Error> {
+    match tokio::fs::remove_file(path_to_pack(id)).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e)
+            .context("Failed to remove resource pack")
+            .map_err(Into::into),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_url() {
+        let id = ResourcePack::new_id();
+        assert!(is_valid_id(&id));
+        assert!(!is_valid_id("../global_settings"));
+        assert_eq!(
+            pack_url("https://core.example.com:16662/", &id).unwrap(),
+            format!("https://core.example.com:16662/resource_packs/{id}")
+        );
+        assert!(pack_url("core.example.com", &id).is_err());
+        assert!(pack_url("https://core.example.com\nmotd=hi", &id).is_err());
+    }
+}
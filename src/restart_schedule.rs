@@ -0,0 +1,346 @@
+//! Restarts that keep long running servers healthy, mostly modded ones that leak memory.
+//!
+//! An instance can be restarted at fixed times of day, and when its memory has stayed above a
+//! threshold for a while with few enough players online that a restart bothers nobody much.
+//! Players are warned ahead of either, and the restart stops the server gracefully. Times are in
+//! the core's local time, like [`crate::power_schedule`].
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, NaiveTime};
+use color_eyre::eyre::eyre;
+use ringbuffer::RingBufferExt;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    power_schedule::local_now,
+    traits::{
+        t_configurable::TConfigurable,
+        t_player::TPlayerManagement,
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+    AppState,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_DAILY_RESTARTS: usize = 24;
+const MAX_WARNING_MINUTES: u32 = 60;
+const MAX_HIGH_MEMORY_MINUTES: u32 = 24 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct MemoryRestart {
+    pub threshold_mb: u64,
+    /// How long memory has to stay above the threshold
+    pub for_minutes: u32,
+    /// Only restarts with fewer players than this online
+    pub below_players: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct RestartSchedule {
+    pub enabled: bool,
+    /// `HH:MM`, every day
+    pub daily_at: Vec<String>,
+    pub memory: Option<MemoryRestart>,
+    /// How long before a restart players are told about it, 0 to not warn
+    pub warning_minutes: u32,
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+impl RestartSchedule {
+    pub fn validate(&self) -> Result<(), Error> {
+        let bad_request = |message: String| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(message),
+        };
+        if self.daily_at.is_empty() && self.memory.is_none() {
+            return Err(bad_request(
+                "A restart schedule needs a time of day or a memory threshold".to_string(),
+            ));
+        }
+        if self.daily_at.len() > MAX_DAILY_RESTARTS {
+            return Err(bad_request(format!(
+                "At most {MAX_DAILY_RESTARTS} restarts can be scheduled a day"
+            )));
+        }
+        if let Some(time) = self.daily_at.iter().find(|time| parse_time(time).is_none()) {
+            return Err(bad_request(format!(
+                "Invalid restart time {time}, times must be HH:MM"
+            )));
+        }
+        if self.warning_minutes > MAX_WARNING_MINUTES {
+            return Err(bad_request(format!(
+                "The warning can be at most {MAX_WARNING_MINUTES} minutes before a restart"
+            )));
+        }
+        if let Some(memory) = &self.memory {
+            if memory.threshold_mb == 0 {
+                return Err(bad_request(
+                    "The memory threshold must be above 0".to_string(),
+                ));
+            }
+            if memory.for_minutes > MAX_HIGH_MEMORY_MINUTES {
+                return Err(bad_request(format!(
+                    "Memory can be required to stay high for at most {MAX_HIGH_MEMORY_MINUTES} minutes"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn warning(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.warning_minutes as i64)
+    }
+
+    /// The scheduled restarts in `(after, until]`, on the days around them
+    fn daily_between(
+        &self,
+        after: NaiveDateTime,
+        until: NaiveDateTime,
+    ) -> impl Iterator<Item = NaiveDateTime> + '_ {
+        let mut days = vec![after.date(), until.date()];
+        // a warning before midnight is for a restart the next day
+        days.extend(until.date().succ_opt());
+        days.dedup();
+        self.daily_at
+            .iter()
+            .filter_map(|time| parse_time(time))
+            .flat_map(move |time| days.clone().into_iter().map(move |day| day.and_time(time)))
+            .filter(move |at| after < *at && *at <= until)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RestartReason {
+    Scheduled,
+    HighMemory,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Step {
+    Warn(RestartReason),
+    Restart(RestartReason),
+}
+
+/// What was last seen of an instance
+#[derive(Default)]
+struct Tracked {
+    last_check: Option<NaiveDateTime>,
+    high_memory_since: Option<NaiveDateTime>,
+    /// A restart players have been warned about
+    pending: Option<(NaiveDateTime, RestartReason)>,
+}
+
+impl Tracked {
+    /// The instance isn't running, so there is nothing to restart
+    fn idle(&mut self, now: NaiveDateTime) {
+        *self = Self {
+            last_check: Some(now),
+            ..Default::default()
+        };
+    }
+
+    fn check(
+        &mut self,
+        schedule: &RestartSchedule,
+        now: NaiveDateTime,
+        memory_mb: Option<u64>,
+        players: u32,
+    ) -> Option<Step> {
+        let last_check = self.last_check.replace(now);
+        if let Some((at, reason)) = self.pending {
+            if now < at {
+                return None;
+            }
+            self.pending = None;
+            self.high_memory_since = None;
+            return Some(Step::Restart(reason));
+        }
+        if let Some(last_check) = last_check {
+            if schedule.daily_between(last_check, now).next().is_some() {
+                return Some(Step::Restart(RestartReason::Scheduled));
+            }
+            if schedule.warning_minutes > 0 {
+                let warned = schedule
+                    .daily_between(last_check + schedule.warning(), now + schedule.warning())
+                    .next();
+                if let Some(at) = warned {
+                    self.pending = Some((at, RestartReason::Scheduled));
+                    return Some(Step::Warn(RestartReason::Scheduled));
+                }
+            }
+        }
+        let memory = schedule.memory.as_ref()?;
+        match memory_mb {
+            Some(memory_mb) if memory_mb > memory.threshold_mb => {
+                let since = *self.high_memory_since.get_or_insert(now);
+                if now - since < chrono::Duration::minutes(memory.for_minutes as i64)
+                    || players >= memory.below_players
+                {
+                    return None;
+                }
+                if schedule.warning_minutes > 0 {
+                    self.pending = Some((now + schedule.warning(), RestartReason::HighMemory));
+                    Some(Step::Warn(RestartReason::HighMemory))
+                } else {
+                    self.high_memory_since = None;
+                    Some(Step::Restart(RestartReason::HighMemory))
+                }
+            }
+            _ => {
+                self.high_memory_since = None;
+                None
+            }
+        }
+    }
+}
+
+/// Follows every instance's restart schedule until the core shuts down
+pub async fn run_restart_schedules(state: AppState) {
+    const MB: u64 = 1024 * 1024;
+    let mut tracked: HashMap<InstanceUuid, Tracked> = HashMap::new();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let schedules = state.global_settings.lock().await.all_restart_schedules();
+        tracked.retain(|uuid, _| schedules.get(uuid).map_or(false, |s| s.enabled));
+        let now = local_now();
+        for (uuid, schedule) in schedules {
+            if !schedule.enabled {
+                continue;
+            }
+            let instance = state.instances.lock().await.get(&uuid).cloned();
+            let mut instance = match instance {
+                Some(instance) => instance,
+                None => continue,
+            };
+            let seen = tracked.entry(uuid.clone()).or_default();
+            if instance.state().await != State::Running {
+                seen.idle(now);
+                continue;
+            }
+            let memory_mb = state
+                .monitor_buffer
+                .lock()
+                .await
+                .get(&uuid)
+                .and_then(|reports| reports.back()?.memory_usage)
+                .map(|memory| memory / MB);
+            let players = instance.get_player_count().await.unwrap_or(0);
+            let name = instance.name().await;
+            let because = |reason| match reason {
+                RestartReason::Scheduled => "as scheduled",
+                RestartReason::HighMemory => "to free up memory",
+            };
+            match seen.check(&schedule, now, memory_mb, players) {
+                Some(Step::Warn(reason)) => {
+                    let _ = instance
+                        .send_command(
+                            &format!(
+                                "say This server restarts in {} minute(s), {}",
+                                schedule.warning_minutes,
+                                because(reason)
+                            ),
+                            CausedBy::System,
+                        )
+                        .await;
+                }
+                Some(Step::Restart(reason)) => {
+                    info!("Restarting instance {name} {}", because(reason));
+                    // restarting waits for the server to stop, which shouldn't hold up the others
+                    tokio::spawn(async move {
+                        if let Err(e) = instance.restart(CausedBy::System, false).await {
+                            warn!("Failed to restart instance {name}: {e}");
+                        }
+                    });
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("2023-05-01 {time}"), "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_daily_restart_with_warning() {
+        let schedule = RestartSchedule {
+            enabled: true,
+            daily_at: vec!["04:00".to_string()],
+            memory: None,
+            warning_minutes: 5,
+        };
+        assert!(schedule.validate().is_ok());
+        let mut tracked = Tracked::default();
+        assert_eq!(tracked.check(&schedule, at("03:54:40"), None, 0), None);
+        assert_eq!(
+            tracked.check(&schedule, at("03:55:10"), None, 0),
+            Some(Step::Warn(RestartReason::Scheduled))
+        );
+        assert_eq!(tracked.check(&schedule, at("03:59:40"), None, 0), None);
+        assert_eq!(
+            tracked.check(&schedule, at("04:00:10"), None, 0),
+            Some(Step::Restart(RestartReason::Scheduled))
+        );
+        assert_eq!(tracked.check(&schedule, at("04:00:40"), None, 0), None);
+    }
+
+    #[test]
+    fn test_high_memory_restart() {
+        let schedule = RestartSchedule {
+            enabled: true,
+            daily_at: Vec::new(),
+            memory: Some(MemoryRestart {
+                threshold_mb: 4096,
+                for_minutes: 10,
+                below_players: 2,
+            }),
+            warning_minutes: 0,
+        };
+        assert!(schedule.validate().is_ok());
+        let mut tracked = Tracked::default();
+        assert_eq!(
+            tracked.check(&schedule, at("12:00:00"), Some(5000), 0),
+            None
+        );
+        // dropping below the threshold starts the wait over
+        assert_eq!(
+            tracked.check(&schedule, at("12:05:00"), Some(3000), 0),
+            None
+        );
+        assert_eq!(
+            tracked.check(&schedule, at("12:06:00"), Some(5000), 0),
+            None
+        );
+        assert_eq!(
+            tracked.check(&schedule, at("12:15:00"), Some(5000), 0),
+            None
+        );
+        // too many players online
+        assert_eq!(
+            tracked.check(&schedule, at("12:16:00"), Some(5000), 2),
+            None
+        );
+        assert_eq!(
+            tracked.check(&schedule, at("12:16:30"), Some(5000), 1),
+            Some(Step::Restart(RestartReason::HighMemory))
+        );
+    }
+}
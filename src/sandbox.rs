@@ -0,0 +1,244 @@
+//! Confining a spawned server on Linux, so a compromised server or a malicious plugin can't
+//! reach past its own instance.
+//!
+//! Landlock limits the filesystem to the instance directory, the java runtime and read-only
+//! system directories. In strict mode the server can additionally only listen on its own
+//! ports, and a seccomp filter blocks syscalls a game server has no use for, like `ptrace` or
+//! loading kernel modules. Both are applied in the child between fork and exec, and hold for
+//! everything it starts. Kernels without Landlock, or too old for some of its rules, enforce
+//! what they support.
+//!
+//! Servers attached to a pseudo terminal can't be sandboxed, portable-pty gives no way to run
+//! code in the child before exec.
+
+use std::{path::PathBuf, str::FromStr};
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::error::{Error, ErrorKind};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxLevel {
+    #[default]
+    Off,
+    /// Only the filesystem is restricted
+    Filesystem,
+    /// The filesystem, listening ports and dangerous syscalls are restricted
+    Strict,
+}
+
+impl ToString for SandboxLevel {
+    fn to_string(&self) -> String {
+        match self {
+            SandboxLevel::Off => "off",
+            SandboxLevel::Filesystem => "filesystem",
+            SandboxLevel::Strict => "strict",
+        }
+        .to_string()
+    }
+}
+
+impl FromStr for SandboxLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(SandboxLevel::Off),
+            "filesystem" => Ok(SandboxLevel::Filesystem),
+            "strict" => Ok(SandboxLevel::Strict),
+            _ => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Invalid sandbox level. The only valid levels are: off, filesystem, strict"
+                ),
+            }),
+        }
+    }
+}
+
+pub struct SandboxPolicy {
+    pub level: SandboxLevel,
+    /// Fully accessible
+    pub instance_dir: PathBuf,
+    /// Readable and executable, where the java runtime is
+    pub runtime_dirs: Vec<PathBuf>,
+    /// TCP ports the server can listen on in strict mode
+    pub ports: Vec<u16>,
+}
+
+/// Sets `command` up to sandbox the process it spawns
+pub fn apply(command: &mut Command, policy: &SandboxPolicy) -> Result<(), Error> {
+    if policy.level == SandboxLevel::Off {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        linux::apply(command, policy)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = command;
+        Err(eyre!("Sandboxing servers is only supported on Linux").into())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use color_eyre::eyre::eyre;
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, AccessNet, NetPort, Ruleset, RulesetAttr,
+        RulesetCreated, RulesetCreatedAttr, RulesetError, ABI,
+    };
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+    use tokio::process::Command;
+
+    use super::{SandboxLevel, SandboxPolicy};
+    use crate::error::Error;
+
+    /// Shared libraries, certificates, time zones, and what java reads about the machine
+    const READ_ONLY_DIRS: &[&str] = &[
+        "/usr", "/lib", "/lib32", "/lib64", "/bin", "/sbin", "/etc", "/opt", "/proc", "/sys",
+    ];
+
+    /// Java keeps performance data and unpacks native libraries in `/tmp`
+    const WRITABLE_DIRS: &[&str] = &["/tmp", "/dev/shm"];
+
+    /// `/dev/null`, `/dev/urandom` and the like
+    const DEVICE_DIRS: &[&str] = &["/dev"];
+
+    const BLOCKED_SYSCALLS: &[i64] = &[
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_reboot,
+    ];
+
+    fn existing(dirs: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+        dirs.into_iter().filter(|dir| dir.exists()).collect()
+    }
+
+    fn ruleset(policy: &SandboxPolicy) -> Result<RulesetCreated, RulesetError> {
+        let abi = ABI::V4;
+        let strict = policy.level == SandboxLevel::Strict;
+        let mut ruleset = Ruleset::default().handle_access(AccessFs::from_all(abi))?;
+        if strict {
+            ruleset = ruleset.handle_access(AccessNet::BindTcp)?;
+        }
+        let read_only = existing(
+            READ_ONLY_DIRS
+                .iter()
+                .map(PathBuf::from)
+                .chain(policy.runtime_dirs.iter().cloned()),
+        );
+        let writable = existing(
+            WRITABLE_DIRS
+                .iter()
+                .map(PathBuf::from)
+                .chain(std::iter::once(policy.instance_dir.clone())),
+        );
+        let devices = existing(DEVICE_DIRS.iter().map(PathBuf::from));
+        let mut ruleset = ruleset
+            .create()?
+            .add_rules(path_beneath_rules(read_only, AccessFs::from_read(abi)))?
+            .add_rules(path_beneath_rules(writable, AccessFs::from_all(abi)))?
+            .add_rules(path_beneath_rules(
+                devices,
+                AccessFs::from_read(abi) | AccessFs::WriteFile,
+            ))?;
+        if strict {
+            for port in &policy.ports {
+                ruleset = ruleset.add_rule(NetPort::new(*port, AccessNet::BindTcp))?;
+            }
+        }
+        Ok(ruleset)
+    }
+
+    fn seccomp_program() -> Result<BpfProgram, Error> {
+        let rules = BLOCKED_SYSCALLS
+            .iter()
+            .map(|syscall| (*syscall, Vec::new()))
+            .collect::<BTreeMap<_, _>>();
+        let arch = std::env::consts::ARCH
+            .try_into()
+            .map_err(|e| eyre!("Unsupported architecture for seccomp: {e:?}"))?;
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::EPERM as u32),
+            arch,
+        )
+        .map_err(|e| eyre!("Failed to build seccomp filter: {e}"))?;
+        filter
+            .try_into()
+            .map_err(|e| eyre!("Failed to compile seccomp filter: {e}").into())
+    }
+
+    pub fn apply(command: &mut Command, policy: &SandboxPolicy) -> Result<(), Error> {
+        // everything that allocates or opens files is done here, the child only installs it
+        let mut ruleset =
+            Some(ruleset(policy).map_err(|e| eyre!("Failed to set up Landlock rules: {e}"))?);
+        let program = match policy.level {
+            SandboxLevel::Strict => Some(seccomp_program()?),
+            _ => None,
+        };
+        // SAFETY: the closure only makes syscalls restricting the child process
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(ruleset) = ruleset.take() {
+                    ruleset
+                        .restrict_self()
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                }
+                if let Some(program) = &program {
+                    seccompiler::apply_filter(program)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandbox_level_round_trip() {
+        for level in [
+            SandboxLevel::Off,
+            SandboxLevel::Filesystem,
+            SandboxLevel::Strict,
+        ] {
+            assert_eq!(level.to_string().parse::<SandboxLevel>().unwrap(), level);
+            assert_eq!(
+                serde_json::to_string(&level).unwrap(),
+                format!("\"{}\"", level.to_string())
+            );
+        }
+        assert!("paranoid".parse::<SandboxLevel>().is_err());
+    }
+}
@@ -0,0 +1,292 @@
+//! Integration with service managers (systemd, Windows Service Control Manager)
+//! so the core can be run headless and supervised.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use color_eyre::eyre::Context;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    events::CausedBy,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    AppState,
+};
+
+/// How long a single instance gets to stop gracefully before it is killed
+const INSTANCE_STOP_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long snapshots, uploads, restores and other jobs get to finish on shutdown
+const JOB_DRAIN_TIMEOUT: Duration = Duration::from_secs(300);
+const JOB_DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What to do with running instances when the core shuts down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum, TS)]
+#[ts(export)]
+pub enum ShutdownPolicy {
+    /// Gracefully stop every running instance before exiting
+    #[default]
+    Stop,
+    /// Exit without touching running instances
+    LeaveRunning,
+}
+
+static SHUTDOWN_REQUESTED: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Ask the core to shut down, as if it received SIGTERM
+pub fn request_shutdown() {
+    SHUTDOWN_REQUESTED.notify_one();
+}
+
+/// Resolves once the core should shut down:
+/// on Ctrl+C, SIGTERM (unix), or a call to [`request_shutdown`]
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Ctrl+C received"),
+        _ = terminate => info!("SIGTERM received"),
+        _ = SHUTDOWN_REQUESTED.notified() => info!("Shutdown requested"),
+    }
+}
+
+/// Stops every running instance whose shutdown policy (falling back to `default_policy`) says so,
+/// waiting for them in parallel and killing those that do not stop within [`INSTANCE_STOP_TIMEOUT`]
+pub(crate) async fn stop_instances(state: &AppState, default_policy: ShutdownPolicy) {
+    let instances = state.instances.lock().await.clone();
+    let global_settings = state.global_settings.lock().await;
+    let mut to_stop = Vec::new();
+    for (uuid, instance) in instances {
+        if instance.state().await == State::Stopped {
+            continue;
+        }
+        match global_settings
+            .instance_shutdown_policy(&uuid)
+            .unwrap_or(default_policy)
+        {
+            ShutdownPolicy::Stop => to_stop.push(instance),
+            ShutdownPolicy::LeaveRunning => {
+                info!("Leaving instance {} running", instance.name().await)
+            }
+        }
+    }
+    drop(global_settings);
+    info!("Signalling {} instance(s) to stop", to_stop.len());
+    futures::future::join_all(to_stop.into_iter().map(|mut instance| async move {
+        let uuid = instance.uuid().await;
+        match tokio::time::timeout(INSTANCE_STOP_TIMEOUT, instance.stop(CausedBy::System, true))
+            .await
+        {
+            Ok(Ok(())) => info!("Instance {} stopped", uuid),
+            Ok(Err(e)) => error!(
+                "Failed to stop instance {} : {}. Instance may need manual cleanup",
+                uuid, e
+            ),
+            Err(_) => {
+                warn!("Instance {} did not stop in time, killing it", uuid);
+                if let Err(e) = instance.kill(CausedBy::System).await {
+                    error!(
+                        "Failed to kill instance {} : {}. Instance may need manual cleanup",
+                        uuid, e
+                    );
+                }
+            }
+        }
+    }))
+    .await;
+}
+
+/// Waits for running jobs, see [`crate::jobs`], to finish so their files and progression events
+/// are complete before the event db is closed. Jobs still running after [`JOB_DRAIN_TIMEOUT`] are
+/// left to be marked as interrupted on the next start.
+pub(crate) async fn drain_jobs(state: &AppState) {
+    if state.jobs.lock().await.running().next().is_some() {
+        // so systemd doesn't kill the core while it waits
+        sd_notify(&format!(
+            "EXTEND_TIMEOUT_USEC={}",
+            JOB_DRAIN_TIMEOUT.as_micros()
+        ));
+    }
+    let drained = tokio::time::timeout(JOB_DRAIN_TIMEOUT, async {
+        let mut last_running = 0;
+        loop {
+            let running = state.jobs.lock().await.running().count();
+            if running == 0 {
+                break;
+            }
+            if running != last_running {
+                info!("Waiting for {running} job(s) to finish");
+                last_running = running;
+            }
+            tokio::time::sleep(JOB_DRAIN_POLL_INTERVAL).await;
+        }
+    })
+    .await;
+    if drained.is_err() {
+        for job in state.jobs.lock().await.running() {
+            warn!("Job \"{}\" did not finish before shutdown", job.name);
+        }
+    }
+}
+
+/// Writes the current process id to `path`, removed again by [`PidFile::drop`]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        std::fs::write(path, std::process::id().to_string())
+            .context(format!("Failed to write pid file at {}", path.display()))?;
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove pid file at {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Sends a state update to systemd if the core was started with `Type=notify`
+///
+/// Does nothing when `NOTIFY_SOCKET` is not set, or on non-linux platforms
+pub fn sd_notify(state: &str) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let socket_path = match std::env::var("NOTIFY_SOCKET") {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        let addr = if let Some(abstract_name) = socket_path.strip_prefix('@') {
+            SocketAddr::from_abstract_name(abstract_name.as_bytes())
+        } else {
+            SocketAddr::from_pathname(&socket_path)
+        };
+        let result = addr.and_then(|addr| {
+            let socket = UnixDatagram::unbound()?;
+            socket.send_to_addr(state.as_bytes(), &addr)
+        });
+        if let Err(e) = result {
+            warn!("Failed to notify systemd: {e}");
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = state;
+}
+
+#[cfg(windows)]
+pub mod windows {
+    //! Lets the core be registered with `sc.exe create lodestone binPath= "... --service"`
+    use std::{ffi::OsString, time::Duration};
+
+    use tracing::error;
+    use windows_service::{
+        define_windows_service,
+        service::{
+            ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+            ServiceType,
+        },
+        service_control_handler::{self, ServiceControlHandlerResult},
+        service_dispatcher,
+    };
+
+    use crate::Args;
+
+    const SERVICE_NAME: &str = "lodestone_core";
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Blocks until the service control manager stops the service
+    pub fn run(args: Args) -> Result<(), windows_service::Error> {
+        // the dispatcher calls into `service_main` on its own thread,
+        // so the args are handed over through a global
+        *ARGS.lock().unwrap() = Some(args);
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+    }
+
+    static ARGS: std::sync::Mutex<Option<Args>> = std::sync::Mutex::new(None);
+
+    fn service_main(_arguments: Vec<OsString>) {
+        let status_handle =
+            match service_control_handler::register(SERVICE_NAME, |control| match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    super::request_shutdown();
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            }) {
+                Ok(handle) => handle,
+                Err(e) => {
+                    error!("Failed to register service control handler: {e}");
+                    return;
+                }
+            };
+        let set_state = |state: ServiceState, controls_accepted: ServiceControlAccept| {
+            if let Err(e) = status_handle.set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted,
+                exit_code: ServiceExitCode::Win32(0),
+                checkpoint: 0,
+                wait_hint: Duration::from_secs(30),
+                process_id: None,
+            }) {
+                error!("Failed to update service status: {e}");
+            }
+        };
+        set_state(ServiceState::StartPending, ServiceControlAccept::empty());
+        let args = match ARGS.lock().unwrap().take() {
+            Some(args) => args,
+            None => return,
+        };
+        let runtime = match tokio::runtime::Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                error!("Failed to start tokio runtime: {e}");
+                set_state(ServiceState::Stopped, ServiceControlAccept::empty());
+                return;
+            }
+        };
+        runtime.block_on(async {
+            let (core_fut, _app_state, _guard) = crate::run(args).await;
+            set_state(
+                ServiceState::Running,
+                ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            );
+            core_fut.await;
+        });
+        set_state(ServiceState::Stopped, ServiceControlAccept::empty());
+    }
+}
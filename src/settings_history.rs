@@ -0,0 +1,270 @@
+//! History of the changes made to an instance's configurable settings.
+//!
+//! Every change is recorded with who made it, when, and the value before and after, in a file
+//! kept in the instance directory. The settings as they were at any point in time are pieced
+//! back together from those records, which is what diffs and rollbacks work from. Values of
+//! secret settings are never written down, so they can't be rolled back either.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    events::CausedBy,
+    traits::t_configurable::manifest::{ConfigurableManifest, ConfigurableValue},
+    types::Snowflake,
+};
+
+const HISTORY_FILE: &str = ".lodestone_settings_history.json";
+/// Oldest changes are dropped past this
+const MAX_CHANGES: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct SettingChangeRecord {
+    pub id: Snowflake,
+    pub at: i64,
+    pub caused_by: CausedBy,
+    pub section_id: String,
+    pub setting_id: String,
+    pub name: String,
+    /// Left out for secret settings
+    pub old_value: Option<ConfigurableValue>,
+    /// Left out for secret settings
+    pub new_value: Option<ConfigurableValue>,
+    pub is_secret: bool,
+}
+
+impl SettingChangeRecord {
+    /// The change about to be made to `manifest`, `None` if the setting doesn't exist
+    pub fn new(
+        manifest: &ConfigurableManifest,
+        section_id: &str,
+        setting_id: &str,
+        new_value: &ConfigurableValue,
+        caused_by: CausedBy,
+    ) -> Option<Self> {
+        let setting = manifest.get_setting(section_id, setting_id)?;
+        let is_secret = setting.is_secret();
+        Some(Self {
+            id: Snowflake::default(),
+            at: chrono::Utc::now().timestamp(),
+            caused_by,
+            section_id: section_id.to_string(),
+            setting_id: setting_id.to_string(),
+            name: setting.get_name().clone(),
+            old_value: setting.get_value().filter(|_| !is_secret).cloned(),
+            new_value: Some(new_value.clone()).filter(|_| !is_secret),
+            is_secret,
+        })
+    }
+
+    fn changes_value(&self) -> bool {
+        self.is_secret || self.old_value != self.new_value
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct SettingHistoryDiff {
+    pub section_id: String,
+    pub setting_id: String,
+    pub name: String,
+    /// Left out for secret settings
+    pub from: Option<ConfigurableValue>,
+    /// Left out for secret settings
+    pub to: Option<ConfigurableValue>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotSetting {
+    pub name: String,
+    pub value: Option<ConfigurableValue>,
+    pub is_secret: bool,
+}
+
+/// Settings by section and setting id, only those with recorded changes
+pub type SettingsSnapshot = BTreeMap<(String, String), SnapshotSetting>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SettingsHistory {
+    /// Oldest first
+    changes: Vec<SettingChangeRecord>,
+}
+
+impl SettingsHistory {
+    pub async fn load(instance_path: &Path) -> Self {
+        let path = instance_path.join(HISTORY_FILE);
+        let content = match tokio::fs::read(&path).await {
+            Ok(content) => content,
+            Err(_) => return Self::default(),
+        };
+        serde_json::from_slice(&content).unwrap_or_else(|e| {
+            warn!(
+                "Ignoring unreadable settings history {}: {e}",
+                path.display()
+            );
+            Self::default()
+        })
+    }
+
+    pub async fn save(&self, instance_path: &Path) -> Result<(), Error> {
+        tokio::fs::write(
+            instance_path.join(HISTORY_FILE),
+            serde_json::to_vec(self).context("Failed to serialize settings history")?,
+        )
+        .await
+        .context("Failed to write settings history")?;
+        Ok(())
+    }
+
+    /// Changes that leave the value as it was are not kept
+    pub fn push(&mut self, change: SettingChangeRecord) {
+        if !change.changes_value() {
+            return;
+        }
+        self.changes.push(change);
+        if self.changes.len() > MAX_CHANGES {
+            self.changes.drain(..self.changes.len() - MAX_CHANGES);
+        }
+    }
+
+    pub fn changes(&self) -> &[SettingChangeRecord] {
+        &self.changes
+    }
+
+    /// The settings as they were right after `at`
+    pub fn snapshot_at(&self, at: i64) -> SettingsSnapshot {
+        let mut snapshot = SettingsSnapshot::new();
+        for change in &self.changes {
+            let key = (change.section_id.clone(), change.setting_id.clone());
+            let setting = |value: &Option<ConfigurableValue>| SnapshotSetting {
+                name: change.name.clone(),
+                value: value.clone(),
+                is_secret: change.is_secret,
+            };
+            if change.at <= at {
+                snapshot.insert(key, setting(&change.new_value));
+            } else {
+                // what it was before its first change after `at`
+                snapshot
+                    .entry(key)
+                    .or_insert_with(|| setting(&change.old_value));
+            }
+        }
+        snapshot
+    }
+
+    /// The settings that differ between `from` and `to`, secret ones if they were changed at all
+    pub fn diff(&self, from: i64, to: i64) -> Vec<SettingHistoryDiff> {
+        let (from, to) = (from.min(to), from.max(to));
+        let (before, after) = (self.snapshot_at(from), self.snapshot_at(to));
+        after
+            .into_iter()
+            .filter_map(|(key, setting)| {
+                let changed = if setting.is_secret {
+                    self.changes.iter().any(|change| {
+                        change.at > from
+                            && change.at <= to
+                            && change.section_id == key.0
+                            && change.setting_id == key.1
+                    })
+                } else {
+                    before.get(&key).map(|before| &before.value) != Some(&setting.value)
+                };
+                changed.then(|| SettingHistoryDiff {
+                    from: before.get(&key).and_then(|before| before.value.clone()),
+                    section_id: key.0,
+                    setting_id: key.1,
+                    name: setting.name,
+                    to: setting.value,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(
+        at: i64,
+        setting_id: &str,
+        old_value: Option<u32>,
+        new_value: Option<u32>,
+    ) -> SettingChangeRecord {
+        SettingChangeRecord {
+            id: Snowflake::default(),
+            at,
+            caused_by: CausedBy::System,
+            section_id: "section".to_string(),
+            setting_id: setting_id.to_string(),
+            name: setting_id.to_string(),
+            old_value: old_value.map(ConfigurableValue::UnsignedInteger),
+            new_value: new_value.map(ConfigurableValue::UnsignedInteger),
+            is_secret: false,
+        }
+    }
+
+    fn value(snapshot: &SettingsSnapshot, setting_id: &str) -> Option<ConfigurableValue> {
+        snapshot[&("section".to_string(), setting_id.to_string())]
+            .value
+            .clone()
+    }
+
+    #[test]
+    fn test_snapshot_and_diff() {
+        let mut history = SettingsHistory::default();
+        history.push(change(10, "max_ram", Some(1024), Some(2048)));
+        history.push(change(20, "port", Some(25565), Some(25566)));
+        history.push(change(30, "max_ram", Some(2048), Some(4096)));
+        // no-op changes aren't recorded
+        history.push(change(40, "port", Some(25566), Some(25566)));
+        assert_eq!(history.changes().len(), 3);
+
+        let start = history.snapshot_at(0);
+        assert_eq!(
+            value(&start, "max_ram"),
+            Some(ConfigurableValue::UnsignedInteger(1024))
+        );
+        assert_eq!(
+            value(&start, "port"),
+            Some(ConfigurableValue::UnsignedInteger(25565))
+        );
+        let middle = history.snapshot_at(25);
+        assert_eq!(
+            value(&middle, "max_ram"),
+            Some(ConfigurableValue::UnsignedInteger(2048))
+        );
+        assert_eq!(
+            value(&middle, "port"),
+            Some(ConfigurableValue::UnsignedInteger(25566))
+        );
+
+        let diff = history.diff(25, 0);
+        assert_eq!(diff.len(), 2);
+        assert_eq!(diff[0].setting_id, "max_ram");
+        assert_eq!(diff[0].from, Some(ConfigurableValue::UnsignedInteger(1024)));
+        assert_eq!(diff[0].to, Some(ConfigurableValue::UnsignedInteger(2048)));
+        assert_eq!(history.diff(20, 25), vec![]);
+        assert_eq!(history.diff(20, 30)[0].setting_id, "max_ram");
+    }
+
+    #[test]
+    fn test_secret_changes_show_up_without_values() {
+        let mut history = SettingsHistory::default();
+        let mut secret = change(10, "rcon_password", None, None);
+        secret.is_secret = true;
+        history.push(secret);
+        assert_eq!(history.diff(0, 5), vec![]);
+        let diff = history.diff(0, 10);
+        assert_eq!(diff.len(), 1);
+        assert_eq!((diff[0].from.clone(), diff[0].to.clone()), (None, None));
+    }
+}
@@ -0,0 +1,304 @@
+//! Expiring links to a single instance file, for sharing a crash report or a world with someone
+//! who has no account.
+//!
+//! The link carries a token signed with a secret only the core knows, naming the link it was
+//! made for. The link itself, kept in the stores, says which file it gives access to, so a token
+//! can't be turned into access to anything else, and deleting the link revokes it early. A link
+//! stops working as well once its creator could no longer read the instance's files themselves.
+//!
+//! Snapshots are shared as a gzipped tarball, packed when the link is made and kept next to the
+//! links until it expires or is revoked.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use color_eyre::eyre::Context;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use ts_rs::TS;
+
+use crate::{
+    auth::{user::User, user_id::UserId},
+    error::Error,
+    prelude::path_to_stores,
+    snapshot::SnapshotInfo,
+    types::{InstanceUuid, Snowflake},
+    util::rand_alphanumeric,
+};
+
+const SHARE_LINKS_FILE: &str = "share_links.json";
+const SHARED_ARCHIVES_DIR: &str = "share_links";
+pub const DEFAULT_TTL_SECS: u32 = 24 * 60 * 60;
+pub const MAX_TTL_SECS: u32 = 7 * 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ShareLink {
+    pub id: String,
+    pub instance_uuid: InstanceUuid,
+    /// Relative to the instance's directory, or the archive's file name for a snapshot
+    pub path: String,
+    /// Set on links to a snapshot archive
+    #[serde(default)]
+    pub snapshot: Option<Snowflake>,
+    pub created_by: UserId,
+    pub created_by_name: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub downloads: u32,
+}
+
+impl ShareLink {
+    pub fn new(instance_uuid: InstanceUuid, path: String, creator: &User, ttl_secs: u32) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        Self {
+            id: rand_alphanumeric(16),
+            instance_uuid,
+            path,
+            snapshot: None,
+            created_by: creator.uid.clone(),
+            created_by_name: creator.username.clone(),
+            created_at: now,
+            expires_at: now + ttl_secs as i64,
+            downloads: 0,
+        }
+    }
+
+    pub fn new_snapshot(snapshot: &SnapshotInfo, creator: &User, ttl_secs: u32) -> Self {
+        let mut link = Self::new(
+            snapshot.instance_uuid.clone(),
+            String::new(),
+            creator,
+            ttl_secs,
+        );
+        link.path = format!("snapshot_{}_{}.tar.gz", snapshot.id, link.id);
+        link.snapshot = Some(snapshot.id);
+        link
+    }
+
+    /// Where the archive of a snapshot link is kept
+    pub fn archive_path(&self) -> Option<PathBuf> {
+        self.snapshot
+            .map(|_| path_to_stores().join(SHARED_ARCHIVES_DIR).join(&self.path))
+    }
+}
+
+/// Best effort, a leftover archive only takes up space
+async fn remove_archive(link: &ShareLink) {
+    if let Some(path) = link.archive_path() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShareClaim {
+    link: String,
+    exp: usize,
+}
+
+fn sign(secret: &str, link: &ShareLink) -> Result<String, Error> {
+    Ok(jsonwebtoken::encode(
+        &Header::new(Algorithm::HS512),
+        &ShareClaim {
+            link: link.id.clone(),
+            exp: link.expires_at.max(0) as usize,
+        },
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .context("Failed to sign share link")?)
+}
+
+/// The id of the link `token` was signed for, if it is valid and hasn't expired
+fn verify(secret: &str, token: &str) -> Option<String> {
+    let mut validation = Validation::new(Algorithm::HS512);
+    validation.leeway = 0;
+    jsonwebtoken::decode::<ShareClaim>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .ok()
+    .map(|data| data.claims.link)
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShareLinksData {
+    secret: String,
+    links: HashMap<String, ShareLink>,
+}
+
+pub struct ShareLinks {
+    data: ShareLinksData,
+    path_to_links: PathBuf,
+}
+
+impl ShareLinks {
+    pub async fn load() -> Result<Self, Error> {
+        let path_to_links = path_to_stores().join(SHARE_LINKS_FILE);
+        let data = match tokio::fs::read(&path_to_links).await {
+            Ok(content) => serde_json::from_slice(&content)
+                .context("Failed to deserialize share links json")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ShareLinksData {
+                secret: rand_alphanumeric(64),
+                links: HashMap::new(),
+            },
+            Err(e) => {
+                return Err(e)
+                    .context(format!(
+                        "Failed to read share links file : {}",
+                        path_to_links.display()
+                    ))
+                    .map_err(Into::into)
+            }
+        };
+        Ok(Self {
+            data,
+            path_to_links,
+        })
+    }
+
+    async fn write_to_file(&self) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(&self.path_to_links)
+            .await
+            .context(format!(
+                "Failed to open/create json file {}",
+                &self.path_to_links.display()
+            ))?;
+        file.write_all(
+            serde_json::to_string(&self.data)
+                .context("Failed to serialize share links json")?
+                .as_bytes(),
+        )
+        .await
+        .context("Failed to write to share links json")?;
+        Ok(())
+    }
+
+    /// Returns the token to download the file with
+    pub async fn create(&mut self, link: ShareLink) -> Result<String, Error> {
+        let token = sign(&self.data.secret, &link)?;
+        let now = chrono::Utc::now().timestamp();
+        let old_links = self.data.links.clone();
+        self.data.links.retain(|_, link| link.expires_at > now);
+        self.data.links.insert(link.id.clone(), link);
+        if let Err(e) = self.write_to_file().await {
+            self.data.links = old_links;
+            return Err(e);
+        }
+        for expired in old_links.values().filter(|link| link.expires_at <= now) {
+            remove_archive(expired).await;
+        }
+        Ok(token)
+    }
+
+    /// The link behind `token`, if it is still valid
+    pub fn resolve(&self, token: &str) -> Option<ShareLink> {
+        let id = verify(&self.data.secret, token)?;
+        self.data
+            .links
+            .get(&id)
+            .filter(|link| link.expires_at > chrono::Utc::now().timestamp())
+            .cloned()
+    }
+
+    /// Counting downloads isn't worth failing one over, so the count is only kept in memory if
+    /// it can't be written
+    pub async fn record_download(&mut self, id: &str) {
+        if let Some(link) = self.data.links.get_mut(id) {
+            link.downloads += 1;
+            let _ = self.write_to_file().await;
+        }
+    }
+
+    /// Unexpired links to files of the instance, newest first
+    pub fn list(&self, uuid: &InstanceUuid) -> Vec<ShareLink> {
+        let now = chrono::Utc::now().timestamp();
+        let mut links: Vec<_> = self
+            .data
+            .links
+            .values()
+            .filter(|link| &link.instance_uuid == uuid && link.expires_at > now)
+            .cloned()
+            .collect();
+        links.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        links
+    }
+
+    pub fn get(&self, uuid: &InstanceUuid, id: &str) -> Option<ShareLink> {
+        self.data
+            .links
+            .get(id)
+            .filter(|link| &link.instance_uuid == uuid)
+            .cloned()
+    }
+
+    pub async fn revoke(
+        &mut self,
+        uuid: &InstanceUuid,
+        id: &str,
+    ) -> Result<Option<ShareLink>, Error> {
+        if !self
+            .data
+            .links
+            .get(id)
+            .map_or(false, |link| &link.instance_uuid == uuid)
+        {
+            return Ok(None);
+        }
+        let link = self.data.links.remove(id);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(link) = link {
+                self.data.links.insert(id.to_string(), link);
+            }
+            return Err(e);
+        }
+        if let Some(link) = &link {
+            remove_archive(link).await;
+        }
+        Ok(link)
+    }
+
+    pub async fn forget_instance(&mut self, uuid: &InstanceUuid) -> Result<(), Error> {
+        let (forgotten, kept) = std::mem::take(&mut self.data.links)
+            .into_iter()
+            .partition(|(_, link)| &link.instance_uuid == uuid);
+        self.data.links = kept;
+        let forgotten: HashMap<String, ShareLink> = forgotten;
+        for link in forgotten.values() {
+            remove_archive(link).await;
+        }
+        self.write_to_file().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(expires_at: i64) -> ShareLink {
+        ShareLink {
+            id: "link".to_string(),
+            instance_uuid: InstanceUuid::default(),
+            path: "crash-reports/crash.txt".to_string(),
+            snapshot: None,
+            created_by: UserId::default(),
+            created_by_name: "owner".to_string(),
+            created_at: 0,
+            expires_at,
+            downloads: 0,
+        }
+    }
+
+    #[test]
+    fn test_share_token() {
+        let in_an_hour = chrono::Utc::now().timestamp() + 3600;
+        let token = sign("secret", &link(in_an_hour)).unwrap();
+        assert_eq!(verify("secret", &token), Some("link".to_string()));
+        assert_eq!(verify("another secret", &token), None);
+        assert_eq!(verify("secret", &format!("{token}x")), None);
+
+        let expired = sign("secret", &link(chrono::Utc::now().timestamp() - 1)).unwrap();
+        assert_eq!(verify("secret", &expired), None);
+    }
+}
@@ -0,0 +1,569 @@
+//! Named, on-demand snapshots of an instance's directory.
+//!
+//! Snapshots live under `<lodestone>/snapshots/<instance uuid>/<snapshot id>`, away from the instance's
+//! own files, and are only ever created, restored or deleted explicitly by a user.
+//! Files are cloned with reflinks where the filesystem supports it (btrfs, xfs, APFS, ReFS),
+//! so a snapshot of a large world costs next to no space until the world changes,
+//! and copied otherwise.
+//!
+//! The hash of every file is recorded in a manifest while the instance is read, and the snapshot
+//! is checked against it right after being made, so a broken snapshot is flagged long before it is needed.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+use ts_rs::TS;
+use walkdir::WalkDir;
+
+use crate::{
+    error::{Error, ErrorKind},
+    prelude::path_to_snapshots,
+    types::{InstanceUuid, Snowflake},
+};
+
+const SNAPSHOT_INFO_FILE: &str = "snapshot.json";
+const MANIFEST_FILE: &str = "manifest.json";
+const SNAPSHOT_DATA_DIR: &str = "data";
+/// Files that describe a running process rather than the instance itself
+const EXCLUDED_FILES: [&str; 1] = [".lodestone_process.json"];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default, TS)]
+#[ts(export)]
+#[serde(tag = "status")]
+pub enum VerificationStatus {
+    /// Not checked yet, or made before manifests were recorded
+    #[default]
+    Unverified,
+    Verified {
+        time: i64,
+    },
+    Corrupt {
+        time: i64,
+        problems: Vec<String>,
+    },
+}
+
+impl VerificationStatus {
+    fn from_problems(problems: Vec<String>) -> Self {
+        let time = chrono::Utc::now().timestamp();
+        if problems.is_empty() {
+            VerificationStatus::Verified { time }
+        } else {
+            VerificationStatus::Corrupt { time, problems }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct FileHash {
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Hashes of the files in a snapshot, keyed by their `/` separated path relative to the instance
+pub type Manifest = BTreeMap<String, FileHash>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct SnapshotInfo {
+    pub id: Snowflake,
+    pub instance_uuid: InstanceUuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub creation_time: i64,
+    /// Total size of the files in the snapshot
+    pub size: u64,
+    /// Bytes that had to be physically copied, 0 if every file could be cloned
+    pub bytes_copied: u64,
+    #[serde(default)]
+    pub verification: VerificationStatus,
+}
+
+fn instance_snapshots_dir(instance_uuid: &InstanceUuid) -> PathBuf {
+    path_to_snapshots().join(instance_uuid.no_prefix())
+}
+
+fn snapshot_dir(instance_uuid: &InstanceUuid, id: Snowflake) -> PathBuf {
+    instance_snapshots_dir(instance_uuid).join(id.to_string())
+}
+
+fn manifest_key(relative: &Path) -> String {
+    relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn hash_reader(mut reader: impl Read) -> std::io::Result<FileHash> {
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut reader, &mut hasher)?;
+    Ok(FileHash {
+        size,
+        sha256: hex::encode(hasher.finalize()),
+    })
+}
+
+pub fn hash_file(path: &Path) -> Result<FileHash, Error> {
+    Ok(std::fs::File::open(path)
+        .and_then(hash_reader)
+        .context(format!("Failed to hash {}", path.display()))?)
+}
+
+/// Compares the hashes found against the manifest, describing every mismatch
+fn compare_to_manifest(found: &Manifest, manifest: &Manifest) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (key, hash) in found {
+        match manifest.get(key) {
+            None => problems.push(format!("Unexpected file {key}")),
+            Some(expected) if expected != hash => {
+                problems.push(format!("{key} does not match its recorded hash"))
+            }
+            Some(_) => {}
+        }
+    }
+    let found_keys: HashSet<&String> = found.keys().collect();
+    for key in manifest.keys() {
+        if !found_keys.contains(key) {
+            problems.push(format!("Missing file {key}"));
+        }
+    }
+    problems
+}
+
+/// Hashes every file under `dir`, keyed by their `/` separated path relative to it.
+/// Links are not followed
+pub fn hash_dir(dir: &Path) -> Result<Manifest, Error> {
+    let mut found = Manifest::new();
+    for entry in WalkDir::new(dir).follow_links(false) {
+        let entry = entry.context("Failed to walk directory")?;
+        if entry.file_type().is_file() {
+            let relative = entry
+                .path()
+                .strip_prefix(dir)
+                .context("Failed to compute relative path")?;
+            found.insert(manifest_key(relative), hash_file(entry.path())?);
+        }
+    }
+    Ok(found)
+}
+
+fn check_dir(dir: &Path, manifest: &Manifest) -> Result<Vec<String>, Error> {
+    Ok(compare_to_manifest(&hash_dir(dir)?, manifest))
+}
+
+/// Reads a gzipped tarball made by [`export_snapshot`] without writing anything to disk,
+/// checking its content against the manifest
+pub async fn check_archive(archive: PathBuf, manifest: Manifest) -> Result<Vec<String>, Error> {
+    tokio::task::spawn_blocking(move || -> Result<Vec<String>, Error> {
+        let file = std::fs::File::open(&archive)
+            .context(format!("Failed to open archive {}", archive.display()))?;
+        let mut archive = tar::Archive::new(GzDecoder::new(file));
+        let mut found = Manifest::new();
+        for entry in archive.entries().context("Failed to read archive")? {
+            let entry = entry.context("Failed to read archive")?;
+            if entry.header().entry_type().is_file() {
+                let key = manifest_key(&entry.path().context("Failed to read archive")?);
+                found.insert(key, hash_reader(entry).context("Failed to read archive")?);
+            }
+        }
+        Ok(compare_to_manifest(&found, &manifest))
+    })
+    .await
+    .context("Archive check task panicked")?
+}
+
+/// Clones every file under `from` into `to`, falling back to a copy per file,
+/// recording the hash of each copy in `manifest` if given.
+/// Returns the total size and the number of bytes that had to be copied
+fn clone_dir(
+    from: &Path,
+    to: &Path,
+    mut manifest: Option<&mut Manifest>,
+) -> Result<(u64, u64), Error> {
+    let mut size = 0;
+    let mut bytes_copied = 0;
+    for entry in WalkDir::new(from).follow_links(false) {
+        let entry = entry.context("Failed to walk directory")?;
+        let relative = entry
+            .path()
+            .strip_prefix(from)
+            .context("Failed to compute relative path")?;
+        if entry.depth() == 1
+            && EXCLUDED_FILES
+                .iter()
+                .any(|excluded| relative == Path::new(excluded))
+        {
+            continue;
+        }
+        let dest = to.join(relative);
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            std::fs::create_dir_all(&dest)
+                .context(format!("Failed to create directory {}", dest.display()))?;
+        } else if file_type.is_file() {
+            match reflink_copy::reflink_or_copy(entry.path(), &dest).context(format!(
+                "Failed to copy {} to {}",
+                entry.path().display(),
+                dest.display()
+            ))? {
+                // cloned, no data was copied
+                None => {
+                    size += entry
+                        .metadata()
+                        .map(|metadata| metadata.len())
+                        .unwrap_or_default()
+                }
+                Some(copied) => {
+                    size += copied;
+                    bytes_copied += copied;
+                }
+            }
+            // the source may change while the server runs, the copy is what the snapshot holds
+            if let Some(manifest) = manifest.as_deref_mut() {
+                manifest.insert(manifest_key(relative), hash_file(&dest)?);
+            }
+        }
+        // symlinks are skipped, they could point outside of the instance
+    }
+    Ok((size, bytes_copied))
+}
+
+pub async fn read_manifest(
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> Result<Option<Manifest>, Error> {
+    match tokio::fs::read(snapshot_dir(instance_uuid, id).join(MANIFEST_FILE)).await {
+        Ok(content) => Ok(Some(
+            serde_json::from_slice(&content).context("Failed to parse snapshot manifest")?,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(eyre!(e).wrap_err("Failed to read snapshot manifest").into()),
+    }
+}
+
+async fn write_manifest(
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+    manifest: &Manifest,
+) -> Result<(), Error> {
+    tokio::fs::write(
+        snapshot_dir(instance_uuid, id).join(MANIFEST_FILE),
+        serde_json::to_vec(manifest).context("Failed to serialize snapshot manifest")?,
+    )
+    .await
+    .context("Failed to write snapshot manifest")?;
+    Ok(())
+}
+
+async fn write_info(info: &SnapshotInfo) -> Result<(), Error> {
+    tokio::fs::write(
+        snapshot_dir(&info.instance_uuid, info.id).join(SNAPSHOT_INFO_FILE),
+        serde_json::to_string_pretty(info).context("Failed to serialize snapshot info")?,
+    )
+    .await
+    .context("Failed to write snapshot info")?;
+    Ok(())
+}
+
+#[instrument(skip_all, fields(instance_uuid = %instance_uuid))]
+pub async fn create_snapshot(
+    instance_path: PathBuf,
+    instance_uuid: InstanceUuid,
+    name: String,
+    description: Option<String>,
+) -> Result<SnapshotInfo, Error> {
+    let id = Snowflake::new();
+    let dir = snapshot_dir(&instance_uuid, id);
+    let data_dir = dir.join(SNAPSHOT_DATA_DIR);
+    tokio::fs::create_dir_all(&data_dir)
+        .await
+        .context("Failed to create snapshot directory")?;
+    let (size, bytes_copied, manifest, verification) =
+        match tokio::task::spawn_blocking(move || -> Result<_, Error> {
+            let mut manifest = Manifest::new();
+            let (size, bytes_copied) = clone_dir(&instance_path, &data_dir, Some(&mut manifest))?;
+            // hashed from the copies themselves, checking them again right away can't find anything
+            let verification = VerificationStatus::from_problems(Vec::new());
+            Ok((size, bytes_copied, manifest, verification))
+        })
+        .await
+        .context("Snapshot task panicked")?
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = crate::util::fs::remove_dir_all(&dir).await;
+                return Err(e);
+            }
+        };
+    let info = SnapshotInfo {
+        id,
+        instance_uuid,
+        name,
+        description,
+        creation_time: chrono::Utc::now().timestamp(),
+        size,
+        bytes_copied,
+        verification,
+    };
+    let write_result = match write_manifest(&info.instance_uuid, id, &manifest).await {
+        Ok(()) => write_info(&info).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = write_result {
+        let _ = crate::util::fs::remove_dir_all(&dir).await;
+        return Err(e);
+    }
+    Ok(info)
+}
+
+/// All snapshots of an instance, oldest first
+pub async fn list_snapshots(instance_uuid: &InstanceUuid) -> Result<Vec<SnapshotInfo>, Error> {
+    read_snapshots_in(&instance_snapshots_dir(instance_uuid)).await
+}
+
+async fn read_snapshots_in(dir: &Path) -> Result<Vec<SnapshotInfo>, Error> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut snapshots = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .context("Failed to read snapshots directory")?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context("Failed to read snapshots directory")?
+    {
+        // a snapshot without its info file is either being created or was interrupted
+        if let Ok(content) = tokio::fs::read(entry.path().join(SNAPSHOT_INFO_FILE)).await {
+            match serde_json::from_slice::<SnapshotInfo>(&content) {
+                Ok(info) => snapshots.push(info),
+                Err(e) => tracing::warn!(
+                    "Ignoring snapshot at {} with invalid info: {e}",
+                    entry.path().display()
+                ),
+            }
+        }
+    }
+    snapshots.sort_by_key(|snapshot| snapshot.id);
+    Ok(snapshots)
+}
+
+pub async fn get_snapshot(
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> Result<SnapshotInfo, Error> {
+    let content = tokio::fs::read(snapshot_dir(instance_uuid, id).join(SNAPSHOT_INFO_FILE))
+        .await
+        .map_err(|_| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Snapshot not found"),
+        })?;
+    Ok(serde_json::from_slice(&content).context("Failed to parse snapshot info")?)
+}
+
+/// Replaces the content of `instance_path` with the snapshot.
+///
+/// The snapshot is first cloned next to the instance and then swapped in,
+/// so a failure half way through leaves the instance untouched.
+/// The instance must not be running
+#[instrument(skip_all, fields(instance_uuid = %instance_uuid, snapshot_id = %id.to_string()))]
+pub async fn restore_snapshot(
+    instance_path: PathBuf,
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> Result<(), Error> {
+    get_snapshot(instance_uuid, id).await?;
+    let data_dir = snapshot_dir(instance_uuid, id).join(SNAPSHOT_DATA_DIR);
+    // next to the instance, which may not be in the default storage location
+    let parent_dir = instance_path
+        .parent()
+        .ok_or_else(|| eyre!("Instance path {} has no parent", instance_path.display()))?;
+    let staging_dir = parent_dir.join(format!(".restoring-{}", id.to_string()));
+    let old_dir = parent_dir.join(format!(".replaced-{}", id.to_string()));
+    tokio::task::spawn_blocking({
+        let staging_dir = staging_dir.clone();
+        move || clone_dir(&data_dir, &staging_dir, None)
+    })
+    .await
+    .context("Restore task panicked")?
+    .map_err(|e| {
+        let _ = std::fs::remove_dir_all(&staging_dir);
+        e
+    })?;
+    crate::util::fs::rename(&instance_path, &old_dir).await?;
+    if let Err(e) = crate::util::fs::rename(&staging_dir, &instance_path).await {
+        // put the original files back
+        let _ = crate::util::fs::rename(&old_dir, &instance_path).await;
+        let _ = crate::util::fs::remove_dir_all(&staging_dir).await;
+        return Err(e);
+    }
+    crate::util::fs::remove_dir_all(&old_dir).await?;
+    Ok(())
+}
+
+/// Checks the files of a snapshot against its manifest again, recording the outcome
+pub async fn verify_snapshot(
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+) -> Result<SnapshotInfo, Error> {
+    let mut info = get_snapshot(instance_uuid, id).await?;
+    let manifest = read_manifest(instance_uuid, id)
+        .await?
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Snapshot has no manifest to verify against"),
+        })?;
+    let data_dir = snapshot_dir(instance_uuid, id).join(SNAPSHOT_DATA_DIR);
+    let problems = tokio::task::spawn_blocking(move || check_dir(&data_dir, &manifest))
+        .await
+        .context("Verification task panicked")??;
+    info.verification = VerificationStatus::from_problems(problems);
+    write_info(&info).await?;
+    Ok(info)
+}
+
+/// Packs the files of a snapshot into a gzipped tarball at `dest`
+pub async fn export_snapshot(
+    instance_uuid: &InstanceUuid,
+    id: Snowflake,
+    dest: PathBuf,
+) -> Result<(), Error> {
+    get_snapshot(instance_uuid, id).await?;
+    let data_dir = snapshot_dir(instance_uuid, id).join(SNAPSHOT_DATA_DIR);
+    tokio::task::spawn_blocking(move || -> Result<(), Error> {
+        let file = std::fs::File::create(&dest)
+            .context(format!("Failed to create archive {}", dest.display()))?;
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        builder.follow_symlinks(false);
+        builder
+            .append_dir_all(".", &data_dir)
+            .context("Failed to archive snapshot")?;
+        builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .context("Failed to archive snapshot")?;
+        Ok(())
+    })
+    .await
+    .context("Archive task panicked")?
+}
+
+/// Recreates a snapshot from an archive made by [`export_snapshot`],
+/// verifying it if the manifest of the original snapshot is given
+pub async fn import_snapshot(
+    mut info: SnapshotInfo,
+    archive: PathBuf,
+    manifest: Option<Manifest>,
+) -> Result<SnapshotInfo, Error> {
+    if get_snapshot(&info.instance_uuid, info.id).await.is_ok() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Snapshot already exists locally"),
+        });
+    }
+    let dir = snapshot_dir(&info.instance_uuid, info.id);
+    let data_dir = dir.join(SNAPSHOT_DATA_DIR);
+    crate::util::fs::create_dir_all(&data_dir).await?;
+    let result = tokio::task::spawn_blocking({
+        let manifest = manifest.clone();
+        move || -> Result<VerificationStatus, Error> {
+            let file = std::fs::File::open(&archive)
+                .context(format!("Failed to open archive {}", archive.display()))?;
+            // entries escaping the destination are skipped by unpack
+            tar::Archive::new(GzDecoder::new(file))
+                .unpack(&data_dir)
+                .context("Failed to extract snapshot")?;
+            Ok(match manifest {
+                Some(manifest) => {
+                    VerificationStatus::from_problems(check_dir(&data_dir, &manifest)?)
+                }
+                None => VerificationStatus::Unverified,
+            })
+        }
+    })
+    .await
+    .context("Extract task panicked")?;
+    let write_result = match result {
+        Ok(verification) => {
+            info.verification = verification;
+            match &manifest {
+                Some(manifest) => write_manifest(&info.instance_uuid, info.id, manifest).await,
+                None => Ok(()),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = match write_result {
+        Ok(()) => write_info(&info).await,
+        Err(e) => Err(e),
+    } {
+        let _ = crate::util::fs::remove_dir_all(&dir).await;
+        return Err(e);
+    }
+    Ok(info)
+}
+
+pub async fn delete_snapshot(instance_uuid: &InstanceUuid, id: Snowflake) -> Result<(), Error> {
+    get_snapshot(instance_uuid, id).await?;
+    crate::util::fs::remove_dir_all(snapshot_dir(instance_uuid, id)).await
+}
+
+/// Removes every snapshot of an instance, used when the instance itself is deleted
+pub async fn delete_all_snapshots(instance_uuid: &InstanceUuid) -> Result<(), Error> {
+    let dir = instance_snapshots_dir(instance_uuid);
+    if dir.exists() {
+        crate::util::fs::remove_dir_all(dir).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clone_and_check_dir() {
+        let temp_dir = tempdir::TempDir::new("test_snapshot").unwrap();
+        let from = temp_dir.path().join("from");
+        let to = temp_dir.path().join("to");
+        std::fs::create_dir_all(from.join("world")).unwrap();
+        std::fs::write(from.join("server.properties"), "motd=hi").unwrap();
+        std::fs::write(from.join("world/level.dat"), [1, 2, 3]).unwrap();
+        std::fs::write(from.join(".lodestone_process.json"), "{}").unwrap();
+
+        let mut manifest = Manifest::new();
+        let (size, _) = clone_dir(&from, &to, Some(&mut manifest)).unwrap();
+        assert_eq!(size, 10);
+        assert_eq!(
+            manifest.keys().collect::<Vec<_>>(),
+            vec!["server.properties", "world/level.dat"]
+        );
+        assert!(check_dir(&to, &manifest).unwrap().is_empty());
+
+        std::fs::write(to.join("world/level.dat"), [3, 2, 1]).unwrap();
+        std::fs::remove_file(to.join("server.properties")).unwrap();
+        std::fs::write(to.join("extra"), "").unwrap();
+        assert_eq!(
+            check_dir(&to, &manifest).unwrap(),
+            vec![
+                "Unexpected file extra".to_string(),
+                "world/level.dat does not match its recorded hash".to_string(),
+                "Missing file server.properties".to_string(),
+            ]
+        );
+    }
+}
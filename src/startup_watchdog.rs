@@ -0,0 +1,589 @@
+//! Rolls back settings changes that keep an instance from starting.
+//!
+//! Whenever an instance gets to running, its settings files are saved as the last known good
+//! configuration. If a later start fails while the settings differ from those, they are put
+//! back, the instance is started once more and a [`InstanceEventInner::SettingsRolledBack`]
+//! event lists what was reverted. A retry that fails too is left alone, since the settings
+//! were evidently not the problem.
+//!
+//! Instances can also have a startup timeout, for servers that hang on a world upgrade or a
+//! broken mod instead of exiting. A start taking longer is reported with an
+//! [`InstanceEventInner::InstanceError`] holding the last lines of output, and the server is
+//! either left running for inspection or killed, which then counts as a failed start: settings
+//! are rolled back if they changed, and otherwise the instance is just started once more.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::SubscriptionFilter,
+    events::{CausedBy, Event, EventInner, EventType, InstanceEvent, InstanceEventInner},
+    handlers::instance_snapshot::reload_minecraft_instance,
+    prelude::GameInstance,
+    traits::{t_configurable::TConfigurable, t_server::State, t_server::TServer},
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+/// Kept in the instance directory, so it moves along with the instance
+const LAST_GOOD_FILE: &str = ".lodestone_last_good_config.json";
+const MINECRAFT_CONFIG: &str = ".lodestone_minecraft_config.json";
+const SERVER_PROPERTIES: &str = "server.properties";
+const WATCHED_FILES: [&str; 2] = [MINECRAFT_CONFIG, SERVER_PROPERTIES];
+/// How often starting instances are checked against their timeout
+const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// Lines of output put in the timeout error
+const LOG_TAIL_LINES: usize = 30;
+
+/// What happens to a server that takes longer than its startup timeout
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupTimeoutAction {
+    /// Keep it running so it can be looked into
+    #[default]
+    Leave,
+    /// Kill it and start it once more
+    Retry,
+}
+
+impl ToString for StartupTimeoutAction {
+    fn to_string(&self) -> String {
+        match self {
+            StartupTimeoutAction::Leave => "leave",
+            StartupTimeoutAction::Retry => "retry",
+        }
+        .to_string()
+    }
+}
+
+impl FromStr for StartupTimeoutAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "leave" => Ok(StartupTimeoutAction::Leave),
+            "retry" => Ok(StartupTimeoutAction::Retry),
+            _ => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid action. The only valid actions are: leave, retry"),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct SettingRevert {
+    pub file: String,
+    pub key: String,
+    /// `None` if the setting wasn't there
+    pub failed_value: Option<String>,
+    pub restored_value: Option<String>,
+}
+
+/// Contents of the watched files, missing files left out
+type ConfigFiles = BTreeMap<String, String>;
+
+fn properties(content: &str) -> BTreeMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once(['=', ':'])?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+fn json_fields(content: &str) -> BTreeMap<String, String> {
+    match serde_json::from_str::<serde_json::Value>(content) {
+        Ok(serde_json::Value::Object(fields)) => fields
+            .into_iter()
+            .map(|(key, value)| (key, value.to_string()))
+            .collect(),
+        _ => BTreeMap::from([(String::new(), content.to_string())]),
+    }
+}
+
+/// The settings that differ between a failed and a good configuration
+pub fn diff(failed: &ConfigFiles, good: &ConfigFiles) -> Vec<SettingRevert> {
+    let mut ret = Vec::new();
+    for file in WATCHED_FILES {
+        let (failed, good) = (failed.get(file), good.get(file));
+        if failed == good {
+            continue;
+        }
+        let parse = |content: Option<&String>| {
+            content.map_or_else(BTreeMap::new, |content| {
+                if file == SERVER_PROPERTIES {
+                    properties(content)
+                } else {
+                    json_fields(content)
+                }
+            })
+        };
+        let (failed, good) = (parse(failed), parse(good));
+        let keys: HashSet<&String> = failed.keys().chain(good.keys()).collect();
+        let mut keys: Vec<_> = keys.into_iter().collect();
+        keys.sort();
+        for key in keys {
+            if failed.get(key) != good.get(key) {
+                ret.push(SettingRevert {
+                    file: file.to_string(),
+                    key: key.clone(),
+                    failed_value: failed.get(key).cloned(),
+                    restored_value: good.get(key).cloned(),
+                });
+            }
+        }
+    }
+    ret
+}
+
+async fn read_config_files(path: &Path) -> ConfigFiles {
+    let mut files = ConfigFiles::new();
+    for file in WATCHED_FILES {
+        if let Ok(content) = tokio::fs::read_to_string(path.join(file)).await {
+            files.insert(file.to_string(), content);
+        }
+    }
+    files
+}
+
+async fn read_last_good(path: &Path) -> Option<ConfigFiles> {
+    let content = tokio::fs::read(path.join(LAST_GOOD_FILE)).await.ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+async fn write_last_good(path: &Path, files: &ConfigFiles) -> Result<(), Error> {
+    tokio::fs::write(
+        path.join(LAST_GOOD_FILE),
+        serde_json::to_vec(files).context("Failed to serialize last good config")?,
+    )
+    .await
+    .context("Failed to write last good config")?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Watch {
+    last_state: Option<State>,
+    /// Set while a start that mustn't be retried is going on, like the one after a rollback
+    retrying: bool,
+    starting_since: Option<Instant>,
+    /// Set once the current start took longer than the timeout
+    timed_out: bool,
+    /// Set when the server was killed for it, so it gets started again
+    killed_on_timeout: bool,
+}
+
+#[derive(Default)]
+pub struct StartupWatchdog {
+    instances: HashMap<InstanceUuid, Watch>,
+}
+
+enum Action {
+    SaveLastGood,
+    Rollback { killed_on_timeout: bool },
+}
+
+impl StartupWatchdog {
+    fn observe(&mut self, uuid: &InstanceUuid, to: State, now: Instant) -> Option<Action> {
+        let watch = self.instances.entry(uuid.clone()).or_default();
+        let from = watch.last_state.replace(to);
+        if to != State::Starting {
+            watch.starting_since = None;
+        } else if from != Some(State::Starting) {
+            watch.starting_since = Some(now);
+            watch.timed_out = false;
+        }
+        match (from, to) {
+            (_, State::Running) => {
+                watch.retrying = false;
+                watch.killed_on_timeout = false;
+                Some(Action::SaveLastGood)
+            }
+            (Some(State::Starting), State::Stopped | State::Error) => {
+                let killed_on_timeout = std::mem::take(&mut watch.killed_on_timeout);
+                if std::mem::take(&mut watch.retrying) {
+                    None
+                } else {
+                    Some(Action::Rollback { killed_on_timeout })
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Instances starting for longer than `timeout` allows and not reported yet
+    fn overdue(
+        &self,
+        now: Instant,
+        timeout: impl Fn(&InstanceUuid) -> Option<Duration>,
+    ) -> Vec<InstanceUuid> {
+        self.instances
+            .iter()
+            .filter(
+                |(uuid, watch)| match (watch.starting_since, timeout(uuid)) {
+                    (Some(since), Some(timeout)) => !watch.timed_out && now - since >= timeout,
+                    _ => false,
+                },
+            )
+            .map(|(uuid, _)| uuid.clone())
+            .collect()
+    }
+
+    /// Marks the current start as timed out. Whether the server should be killed, which it
+    /// isn't when it is already being started again
+    fn time_out(&mut self, uuid: &InstanceUuid, action: StartupTimeoutAction) -> bool {
+        let watch = self.instances.entry(uuid.clone()).or_default();
+        watch.timed_out = true;
+        watch.killed_on_timeout = action == StartupTimeoutAction::Retry && !watch.retrying;
+        watch.killed_on_timeout
+    }
+
+    fn retrying(&mut self, uuid: &InstanceUuid) {
+        self.instances.entry(uuid.clone()).or_default().retrying = true;
+    }
+
+    /// The next start of the instance is neither rolled back nor retried if it fails, for one
+    /// off runs like a world upgrade
+    pub fn leave_next_start(&mut self, uuid: &InstanceUuid) {
+        self.retrying(uuid);
+    }
+
+    pub fn forget(&mut self, uuid: &InstanceUuid) {
+        self.instances.remove(uuid);
+    }
+}
+
+async fn instance_path(state: &AppState, uuid: &InstanceUuid) -> Option<PathBuf> {
+    match state.instances.lock().await.get(uuid)? {
+        GameInstance::MinecraftInstance(instance) => Some(instance.path().await),
+        _ => None,
+    }
+}
+
+/// Whether anything was rolled back, in which case the instance is started again
+async fn rollback(state: &AppState, uuid: &InstanceUuid, name: &str) -> Result<bool, Error> {
+    let path = match instance_path(state, uuid).await {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let good = match read_last_good(&path).await {
+        Some(good) => good,
+        None => return Ok(false),
+    };
+    let failed = read_config_files(&path).await;
+    let reverted = diff(&failed, &good);
+    if reverted.is_empty() {
+        return Ok(false);
+    }
+    info!("Start of {name} failed after a settings change, rolling it back");
+    for file in WATCHED_FILES {
+        if let Some(content) = good.get(file) {
+            tokio::fs::write(path.join(file), content)
+                .await
+                .context(format!("Failed to restore {file}"))?;
+        }
+    }
+    // the running instance keeps its settings in memory, so it is loaded again from the files
+    let instance: GameInstance = reload_minecraft_instance(path, state).await?.into();
+    state
+        .instances
+        .lock()
+        .await
+        .insert(uuid.clone(), instance.clone());
+    state.event_broadcaster.send(Event {
+        event_inner: EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid: uuid.clone(),
+            instance_name: name.to_string(),
+            instance_event_inner: InstanceEventInner::SettingsRolledBack { reverted },
+        }),
+        details: "Settings rolled back to the last ones the instance started with".to_string(),
+        snowflake: Snowflake::default(),
+        caused_by: CausedBy::System,
+    });
+    state.startup_watchdog.lock().await.retrying(uuid);
+    let mut instance = instance;
+    instance.start(CausedBy::System, false).await?;
+    Ok(true)
+}
+
+/// Starts an instance again after it was killed for taking too long to start
+async fn retry_start(state: &AppState, uuid: &InstanceUuid, name: &str) -> Result<(), Error> {
+    let mut instance = match state.instances.lock().await.get(uuid).cloned() {
+        Some(instance) => instance,
+        None => return Ok(()),
+    };
+    info!("Starting {name} again after it timed out");
+    state.startup_watchdog.lock().await.retrying(uuid);
+    instance.start(CausedBy::System, false).await
+}
+
+async fn startup_timeout(instance: &GameInstance) -> Option<(Duration, StartupTimeoutAction)> {
+    match instance {
+        GameInstance::MinecraftInstance(instance) => instance.startup_timeout().await,
+        _ => None,
+    }
+}
+
+/// The output lines among `events`
+fn output_lines(events: &[Event]) -> Vec<&str> {
+    events
+        .iter()
+        .filter_map(|event| match &event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_event_inner: InstanceEventInner::InstanceOutput { message, .. },
+                ..
+            }) => Some(message.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+async fn check_startup_timeouts(state: &AppState) {
+    let instances: HashMap<InstanceUuid, GameInstance> = state.instances.lock().await.clone();
+    let mut timeouts = HashMap::new();
+    for (uuid, instance) in &instances {
+        if let Some(timeout) = startup_timeout(instance).await {
+            timeouts.insert(uuid.clone(), timeout);
+        }
+    }
+    let overdue = state
+        .startup_watchdog
+        .lock()
+        .await
+        .overdue(Instant::now(), |uuid| {
+            timeouts.get(uuid).map(|(timeout, _)| *timeout)
+        });
+    for uuid in overdue {
+        let (mut instance, (timeout, action)) = match (instances.get(&uuid), timeouts.get(&uuid)) {
+            (Some(instance), Some(timeout)) => (instance.clone(), *timeout),
+            _ => continue,
+        };
+        if instance.state().await != State::Starting {
+            continue;
+        }
+        let kill = state.startup_watchdog.lock().await.time_out(&uuid, action);
+        let name = instance.name().await;
+        let tail = output_lines(&instance.console_history(LOG_TAIL_LINES).await).join("\n");
+        warn!(
+            "{name} did not finish starting within {} minutes:\n{tail}",
+            timeout.as_secs() / 60
+        );
+        state.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: uuid.clone(),
+                instance_name: name.clone(),
+                instance_event_inner: InstanceEventInner::InstanceError {
+                    message: format!(
+                        "Server did not finish starting within {} minutes, {}. Last lines of output:\n{tail}",
+                        timeout.as_secs() / 60,
+                        if kill {
+                            "killing it to start it again"
+                        } else {
+                            "leaving it running for inspection"
+                        }
+                    ),
+                },
+            }),
+            details: "Startup timed out".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        });
+        if kill {
+            if let Err(e) = instance.kill(CausedBy::System).await {
+                error!("Failed to kill {name} after it timed out: {e}");
+            }
+        }
+    }
+}
+
+/// Follows instance state changes until the core shuts down
+pub async fn run_startup_watchdog(state: AppState) {
+    let mut events = state
+        .event_broadcaster
+        .subscribe_filtered(SubscriptionFilter {
+            event_types: Some(vec![EventType::InstanceEvent]),
+            ..Default::default()
+        });
+    let mut interval = tokio::time::interval(TIMEOUT_CHECK_INTERVAL);
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            _ = interval.tick() => {
+                check_startup_timeouts(&state).await;
+                continue;
+            }
+        };
+        let (uuid, name, to) = match &event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner: InstanceEventInner::StateTransition { to },
+            }) => (instance_uuid.clone(), instance_name.clone(), *to),
+            _ => continue,
+        };
+        let action = state
+            .startup_watchdog
+            .lock()
+            .await
+            .observe(&uuid, to, Instant::now());
+        match action {
+            Some(Action::SaveLastGood) => {
+                if let Some(path) = instance_path(&state, &uuid).await {
+                    let files = read_config_files(&path).await;
+                    if let Err(e) = write_last_good(&path, &files).await {
+                        warn!("Failed to save the last good config of {name}: {e}");
+                    }
+                }
+            }
+            Some(Action::Rollback { killed_on_timeout }) => {
+                // the instance is restarted from here, so the event loop isn't held up
+                let state = state.clone();
+                tokio::spawn(async move {
+                    match rollback(&state, &uuid, &name).await {
+                        Ok(false) if killed_on_timeout => {
+                            if let Err(e) = retry_start(&state, &uuid, &name).await {
+                                error!("Failed to start {name} again: {e}");
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => error!("Failed to roll back the settings of {name}: {e}"),
+                    }
+                });
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let good = ConfigFiles::from([
+            (
+                MINECRAFT_CONFIG.to_string(),
+                r#"{"max_ram":2048,"cmd_args":[]}"#.to_string(),
+            ),
+            (
+                SERVER_PROPERTIES.to_string(),
+                "#comment\nview-distance=10\nmotd=Hi\n".to_string(),
+            ),
+        ]);
+        let mut failed = good.clone();
+        failed.insert(
+            MINECRAFT_CONFIG.to_string(),
+            r#"{"max_ram":2048,"cmd_args":["-XX:+Bad"]}"#.to_string(),
+        );
+        failed.insert(
+            SERVER_PROPERTIES.to_string(),
+            "view-distance=64\nmotd=Hi\nextra=1\n".to_string(),
+        );
+        assert_eq!(
+            diff(&failed, &good),
+            vec![
+                SettingRevert {
+                    file: MINECRAFT_CONFIG.to_string(),
+                    key: "cmd_args".to_string(),
+                    failed_value: Some(r#"["-XX:+Bad"]"#.to_string()),
+                    restored_value: Some("[]".to_string()),
+                },
+                SettingRevert {
+                    file: SERVER_PROPERTIES.to_string(),
+                    key: "extra".to_string(),
+                    failed_value: Some("1".to_string()),
+                    restored_value: None,
+                },
+                SettingRevert {
+                    file: SERVER_PROPERTIES.to_string(),
+                    key: "view-distance".to_string(),
+                    failed_value: Some("64".to_string()),
+                    restored_value: Some("10".to_string()),
+                },
+            ]
+        );
+        assert!(diff(&good, &good).is_empty());
+    }
+
+    #[test]
+    fn test_retry_only_once() {
+        let mut watchdog = StartupWatchdog::default();
+        let uuid = InstanceUuid::default();
+        let now = Instant::now();
+        assert!(watchdog.observe(&uuid, State::Starting, now).is_none());
+        assert!(matches!(
+            watchdog.observe(&uuid, State::Stopped, now),
+            Some(Action::Rollback {
+                killed_on_timeout: false
+            })
+        ));
+        watchdog.retrying(&uuid);
+        watchdog.observe(&uuid, State::Starting, now);
+        assert!(watchdog.observe(&uuid, State::Error, now).is_none());
+        watchdog.observe(&uuid, State::Starting, now);
+        assert!(matches!(
+            watchdog.observe(&uuid, State::Running, now),
+            Some(Action::SaveLastGood)
+        ));
+    }
+
+    #[test]
+    fn test_startup_timeout() {
+        let mut watchdog = StartupWatchdog::default();
+        let uuid = InstanceUuid::default();
+        let start = Instant::now();
+        let timeout = |_: &InstanceUuid| Some(Duration::from_secs(600));
+        watchdog.observe(&uuid, State::Starting, start);
+        assert!(watchdog
+            .overdue(start + Duration::from_secs(599), timeout)
+            .is_empty());
+        let late = start + Duration::from_secs(600);
+        assert_eq!(watchdog.overdue(late, timeout), vec![uuid.clone()]);
+        assert!(watchdog.time_out(&uuid, StartupTimeoutAction::Retry));
+        // reported once per start
+        assert!(watchdog.overdue(late, timeout).is_empty());
+        assert!(matches!(
+            watchdog.observe(&uuid, State::Stopped, late),
+            Some(Action::Rollback {
+                killed_on_timeout: true
+            })
+        ));
+
+        // the retry hangs too and is left alone
+        watchdog.retrying(&uuid);
+        watchdog.observe(&uuid, State::Starting, late);
+        let later = late + Duration::from_secs(600);
+        assert_eq!(watchdog.overdue(later, timeout), vec![uuid.clone()]);
+        assert!(!watchdog.time_out(&uuid, StartupTimeoutAction::Retry));
+    }
+
+    #[test]
+    fn test_startup_timeout_action_round_trip() {
+        for action in [StartupTimeoutAction::Leave, StartupTimeoutAction::Retry] {
+            assert_eq!(
+                action.to_string().parse::<StartupTimeoutAction>().unwrap(),
+                action
+            );
+        }
+        assert!("kill".parse::<StartupTimeoutAction>().is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Storage locations instances can be kept in, like an SSD pool for busy servers and an HDD pool
+//! for the rest.
+//!
+//! The instances directory under the lodestone path is always there as the default location,
+//! the others are configured in the global settings. An instance records the location it was
+//! placed in in its `.lodestone_config`.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use walkdir::WalkDir;
+
+use crate::{
+    error::{Error, ErrorKind},
+    prelude::path_to_instances,
+};
+
+pub const DEFAULT_STORAGE_LOCATION: &str = "default";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct StorageLocation {
+    pub id: String,
+    pub name: String,
+    /// Directory the instances are created in
+    #[ts(type = "string")]
+    pub path: PathBuf,
+}
+
+impl StorageLocation {
+    pub fn default_location() -> Self {
+        Self {
+            id: DEFAULT_STORAGE_LOCATION.to_string(),
+            name: "Default".to_string(),
+            path: path_to_instances().clone(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.id.is_empty()
+            || !self
+                .id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Storage location id can only contain letters, digits, '-' and '_', got '{}'",
+                    self.id
+                ),
+            });
+        }
+        if self.id == DEFAULT_STORAGE_LOCATION {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("'{}' is reserved", DEFAULT_STORAGE_LOCATION),
+            });
+        }
+        if !self.path.is_absolute() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Path of storage location {} must be absolute", self.name),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the instance at `instance_path` is kept here
+    pub fn holds(&self, instance_path: &Path) -> bool {
+        instance_path.parent() == Some(self.path.as_path())
+    }
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct StorageLocationInfo {
+    pub location: StorageLocation,
+    /// `None` if the disk the location is on can't be found
+    pub available_space: Option<u64>,
+    pub instance_count: u32,
+}
+
+/// Checks a new set of configured locations, which must not include the default one
+pub fn validate_locations(locations: &[StorageLocation]) -> Result<(), Error> {
+    for (i, location) in locations.iter().enumerate() {
+        location.validate()?;
+        if locations[..i].iter().any(|other| other.id == location.id) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Duplicate storage location id '{}'", location.id),
+            });
+        }
+        if location.path == *path_to_instances()
+            || locations[..i]
+                .iter()
+                .any(|other| other.path == location.path)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "{} is already used by another storage location",
+                    location.path.display()
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The location `id` refers to, the default one if `None`
+pub fn resolve(configured: &[StorageLocation], id: Option<&str>) -> Result<StorageLocation, Error> {
+    match id {
+        None | Some(DEFAULT_STORAGE_LOCATION) => Ok(StorageLocation::default_location()),
+        Some(id) => configured
+            .iter()
+            .find(|location| location.id == id)
+            .cloned()
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Unknown storage location '{}'", id),
+            }),
+    }
+}
+
+/// Moves the instance directory at `from` into `to`, keeping its name.
+///
+/// A plain rename is tried first. Across disks the files are copied to a staging directory
+/// next to the destination, so a failure half way through leaves the original untouched.
+/// Returns the new path of the instance, which must not be running
+pub async fn move_instance_dir(from: &Path, to: &Path) -> Result<PathBuf, Error> {
+    let dir_name = from
+        .file_name()
+        .ok_or_else(|| eyre!("Instance path {} has no name", from.display()))?;
+    let dest = to.join(dir_name);
+    if dest.exists() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} already exists", dest.display()),
+        });
+    }
+    crate::util::fs::create_dir_all(to).await?;
+    if tokio::fs::rename(from, &dest).await.is_ok() {
+        return Ok(dest);
+    }
+    let staging_dir = to.join(format!(".moving-{}", dir_name.to_string_lossy()));
+    tokio::task::spawn_blocking({
+        let from = from.to_owned();
+        let staging_dir = staging_dir.clone();
+        move || copy_dir(&from, &staging_dir)
+    })
+    .await
+    .context("Move task panicked")?
+    .map_err(|e| {
+        let _ = std::fs::remove_dir_all(&staging_dir);
+        e
+    })?;
+    if let Err(e) = crate::util::fs::rename(&staging_dir, &dest).await {
+        let _ = crate::util::fs::remove_dir_all(&staging_dir).await;
+        return Err(e);
+    }
+    crate::util::fs::remove_dir_all(from).await?;
+    Ok(dest)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    for entry in WalkDir::new(from).follow_links(false) {
+        let entry = entry.context("Failed to walk directory")?;
+        let dest = to.join(
+            entry
+                .path()
+                .strip_prefix(from)
+                .context("Failed to compute relative path")?,
+        );
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            std::fs::create_dir_all(&dest)
+                .context(format!("Failed to create directory {}", dest.display()))?;
+        } else if file_type.is_file() {
+            std::fs::copy(entry.path(), &dest).context(format!(
+                "Failed to copy {} to {}",
+                entry.path().display(),
+                dest.display()
+            ))?;
+        } else if file_type.is_symlink() {
+            let target = std::fs::read_link(entry.path())
+                .context(format!("Failed to read link {}", entry.path().display()))?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&target, &dest)
+                .context(format!("Failed to create link {}", dest.display()))?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(&target, &dest)
+                .context(format!("Failed to create link {}", dest.display()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(id: &str, path: &str) -> StorageLocation {
+        StorageLocation {
+            id: id.to_string(),
+            name: id.to_string(),
+            path: PathBuf::from(path),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(location("ssd-pool", "/mnt/ssd").validate().is_ok());
+        assert!(location("", "/mnt/ssd").validate().is_err());
+        assert!(location("ssd pool", "/mnt/ssd").validate().is_err());
+        assert!(location(DEFAULT_STORAGE_LOCATION, "/mnt/ssd")
+            .validate()
+            .is_err());
+        assert!(location("ssd", "relative/path").validate().is_err());
+    }
+
+    #[test]
+    fn test_holds() {
+        let ssd = location("ssd", "/mnt/ssd");
+        assert!(ssd.holds(Path::new("/mnt/ssd/Survival-1234abcd")));
+        assert!(!ssd.holds(Path::new("/mnt/ssd/nested/Survival-1234abcd")));
+        assert!(!ssd.holds(Path::new("/mnt/hdd/Survival-1234abcd")));
+    }
+
+    #[tokio::test]
+    async fn test_move_instance_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let from = temp.path().join("a").join("Survival-1234abcd");
+        std::fs::create_dir_all(from.join("world")).unwrap();
+        std::fs::write(from.join("world").join("level.dat"), b"level").unwrap();
+        let to = temp.path().join("b");
+
+        let dest = move_instance_dir(&from, &to).await.unwrap();
+        assert_eq!(dest, to.join("Survival-1234abcd"));
+        assert!(!from.exists());
+        assert_eq!(
+            std::fs::read(dest.join("world").join("level.dat")).unwrap(),
+            b"level"
+        );
+        // refuses to overwrite
+        std::fs::create_dir_all(&from).unwrap();
+        assert!(move_instance_dir(&from, &to).await.is_err());
+    }
+}
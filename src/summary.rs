@@ -0,0 +1,228 @@
+//! Counts for the home screen, kept up to date from instance events.
+//!
+//! Asking every instance for its state and players means waiting on each of their locks, which
+//! a busy instance can hold for a while. Instead the state and player count of each instance
+//! are followed through the events they send, after asking each one once when the core starts.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use ts_rs::TS;
+
+use crate::{
+    event_broadcaster::SubscriptionFilter,
+    events::{Event, EventInner, EventType, InstanceEvent, InstanceEventInner},
+    traits::{t_player::TPlayerManagement, t_server::State, t_server::TServer},
+    types::InstanceUuid,
+    AppState,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct InstanceCounts {
+    pub total: u32,
+    pub starting: u32,
+    pub running: u32,
+    pub stopping: u32,
+    pub stopped: u32,
+    pub error: u32,
+}
+
+impl InstanceCounts {
+    fn add(&mut self, state: State) {
+        self.total += 1;
+        match state {
+            State::Starting => self.starting += 1,
+            State::Running => self.running += 1,
+            State::Stopping => self.stopping += 1,
+            State::Stopped => self.stopped += 1,
+            State::Error => self.error += 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct HostUsage {
+    /// Percent of all cores
+    pub cpu_percent: f32,
+    pub memory_total: u64,
+    pub memory_used: u64,
+    /// Memory used by instances, from their last monitor report
+    pub instances_memory: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct Summary {
+    pub instances: InstanceCounts,
+    pub players_online: u32,
+    pub host: HostUsage,
+    pub active_jobs: u32,
+    /// Alerts firing right now, the closest thing to unread notifications
+    pub firing_alerts: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CachedInstance {
+    state: State,
+    players: u32,
+}
+
+impl Default for CachedInstance {
+    fn default() -> Self {
+        Self {
+            state: State::Stopped,
+            players: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SummaryCache {
+    instances: HashMap<InstanceUuid, CachedInstance>,
+}
+
+impl SummaryCache {
+    fn observe(&mut self, event: &Event) {
+        if let EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid,
+            instance_event_inner,
+            ..
+        }) = &event.event_inner
+        {
+            let cached = self.instances.entry(instance_uuid.clone()).or_default();
+            match instance_event_inner {
+                InstanceEventInner::StateTransition { to } => {
+                    cached.state = *to;
+                    // players aren't told to have left when the server stops
+                    if matches!(to, State::Stopped | State::Error) {
+                        cached.players = 0;
+                    }
+                }
+                InstanceEventInner::PlayerChange { player_list, .. } => {
+                    cached.players = player_list.len() as u32;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Counts over `uuids`, instances never heard of counted as stopped
+    pub fn counts<'a>(
+        &self,
+        uuids: impl IntoIterator<Item = &'a InstanceUuid>,
+    ) -> (InstanceCounts, u32) {
+        let mut counts = InstanceCounts::default();
+        let mut players = 0;
+        for uuid in uuids {
+            let cached = self.instances.get(uuid).copied().unwrap_or_default();
+            counts.add(cached.state);
+            players += cached.players;
+        }
+        (counts, players)
+    }
+
+    pub fn forget(&mut self, uuid: &InstanceUuid) {
+        self.instances.remove(uuid);
+    }
+}
+
+/// Follows instance events until the core shuts down
+pub async fn run_summary_cache(state: AppState) {
+    let mut events = state
+        .event_broadcaster
+        .subscribe_filtered(SubscriptionFilter {
+            event_types: Some(vec![EventType::InstanceEvent]),
+            ..Default::default()
+        });
+    let instances: Vec<_> = state
+        .instances
+        .lock()
+        .await
+        .iter()
+        .map(|(uuid, instance)| (uuid.clone(), instance.clone()))
+        .collect();
+    for (uuid, instance) in instances {
+        let cached = CachedInstance {
+            state: instance.state().await,
+            players: instance.get_player_count().await.unwrap_or(0),
+        };
+        state
+            .summary_cache
+            .lock()
+            .await
+            .instances
+            .entry(uuid)
+            .or_insert(cached);
+    }
+    loop {
+        match events.recv().await {
+            Ok(event) => state.summary_cache.lock().await.observe(&event),
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::events::CausedBy;
+    use crate::traits::t_player::Player;
+    use crate::types::Snowflake;
+
+    fn event(uuid: &InstanceUuid, inner: InstanceEventInner) -> Event {
+        Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: uuid.clone(),
+                instance_name: "Survival".to_string(),
+                instance_event_inner: inner,
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        }
+    }
+
+    #[test]
+    fn test_summary_cache() {
+        let mut cache = SummaryCache::default();
+        let (a, b) = (InstanceUuid::default(), InstanceUuid::default());
+        cache.observe(&event(
+            &a,
+            InstanceEventInner::StateTransition { to: State::Running },
+        ));
+        cache.observe(&event(
+            &a,
+            InstanceEventInner::PlayerChange {
+                player_list: HashSet::<Player>::new(),
+                players_joined: HashSet::new(),
+                players_left: HashSet::new(),
+            },
+        ));
+        let (counts, players) = cache.counts([&a, &b]);
+        assert_eq!((counts.total, counts.running, counts.stopped), (2, 1, 1));
+        assert_eq!(players, 0);
+
+        cache.instances.get_mut(&a).unwrap().players = 3;
+        cache.observe(&event(
+            &a,
+            InstanceEventInner::StateTransition { to: State::Stopped },
+        ));
+        assert_eq!(
+            cache.counts([&a]),
+            (
+                InstanceCounts {
+                    total: 1,
+                    stopped: 1,
+                    ..Default::default()
+                },
+                0
+            )
+        );
+    }
+}
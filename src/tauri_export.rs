@@ -39,13 +39,17 @@ pub async fn setup_owner_account(
             source: eyre!("Owner account already present"),
         });
     }
-    let user = User::new(username, password, true, false, UserPermission::default());
-    app_state
-        .users_manager
-        .write()
-        .await
-        .add_user(user, CausedBy::System)
-        .await?;
+    let mut users_manager = app_state.users_manager.write().await;
+    let user = User::new(
+        username,
+        password,
+        true,
+        false,
+        UserPermission::default(),
+        &users_manager.password_hashing(),
+    );
+    users_manager.add_user(user, CausedBy::System).await?;
+    drop(users_manager);
     app_state.first_time_setup_key.lock().await.take();
     Ok(())
 }
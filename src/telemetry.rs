@@ -0,0 +1,182 @@
+//! Opt-in anonymous usage statistics.
+//!
+//! Nothing is sent unless an owner enables telemetry and sets an endpoint. A report only holds
+//! aggregate counts and the core's version and platform, along with a random installation id
+//! that isn't derived from anything about the machine. `GET /global_settings/telemetry/preview`
+//! returns exactly what would be sent.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    prelude::VERSION,
+    traits::t_configurable::{Game, TConfigurable},
+    util::rand_alphanumeric,
+    AppState,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_INTERVAL_HOURS: u32 = 30 * 24;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct TelemetrySettings {
+    pub enabled: bool,
+    /// Where reports are POSTed to, nothing is sent without one
+    pub endpoint: Option<String>,
+    pub interval_hours: u32,
+    /// Random, only used to tell reports of different cores apart. Can't be changed by clients
+    pub installation_id: String,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: None,
+            interval_hours: 24,
+            installation_id: rand_alphanumeric(32),
+        }
+    }
+}
+
+impl TelemetrySettings {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.interval_hours == 0 || self.interval_hours > MAX_INTERVAL_HOURS {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Telemetry interval must be between 1 and {MAX_INTERVAL_HOURS} hours"
+                ),
+            });
+        }
+        if let Some(endpoint) = &self.endpoint {
+            let url = url::Url::parse(endpoint).map_err(|e| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid telemetry endpoint: {e}"),
+            })?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Telemetry endpoint must be http or https"),
+                });
+            }
+        }
+        if self.enabled && self.endpoint.is_none() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Telemetry needs an endpoint to be enabled"),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct TelemetryReport {
+    pub installation_id: String,
+    pub core_version: String,
+    pub os: String,
+    pub arch: String,
+    pub instance_count: u32,
+    /// Instances of each game type, like `MinecraftJava/Fabric`
+    pub game_types: BTreeMap<String, u32>,
+}
+
+fn game_label(game: &Game) -> String {
+    match game {
+        Game::MinecraftJava { variant } => format!("MinecraftJava/{variant:?}"),
+        Game::MinecraftBedrock => "MinecraftBedrock".to_string(),
+        // the display name is up to whoever wrote the instance
+        Game::Generic { game_name, .. } => format!("Generic/{game_name:?}"),
+    }
+}
+
+pub async fn build_report(state: &AppState) -> TelemetryReport {
+    let installation_id = state
+        .global_settings
+        .lock()
+        .await
+        .telemetry()
+        .installation_id;
+    let mut game_types = BTreeMap::new();
+    let instances = state.instances.lock().await;
+    for instance in instances.values() {
+        *game_types
+            .entry(game_label(&instance.game_type().await))
+            .or_insert(0) += 1;
+    }
+    TelemetryReport {
+        installation_id,
+        core_version: VERSION.with(|v| v.to_string()),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        instance_count: instances.len() as u32,
+        game_types,
+    }
+}
+
+async fn send_report(endpoint: &str, report: &TelemetryReport) -> Result<(), Error> {
+    reqwest::Client::new()
+        .post(endpoint)
+        .timeout(SEND_TIMEOUT)
+        .json(report)
+        .send()
+        .await
+        .context("Failed to send telemetry")?
+        .error_for_status()
+        .context("Telemetry endpoint returned an error")?;
+    Ok(())
+}
+
+/// Sends a report every configured interval while telemetry is enabled
+pub async fn run_telemetry(state: AppState) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut last_sent: Option<Instant> = None;
+    loop {
+        interval.tick().await;
+        let settings = state.global_settings.lock().await.telemetry();
+        let endpoint = match (settings.enabled, settings.endpoint) {
+            (true, Some(endpoint)) => endpoint,
+            _ => continue,
+        };
+        let due = last_sent.map_or(true, |last_sent| {
+            last_sent.elapsed() >= Duration::from_secs(settings.interval_hours as u64 * 60 * 60)
+        });
+        if !due {
+            continue;
+        }
+        let report = build_report(&state).await;
+        match send_report(&endpoint, &report).await {
+            Ok(_) => last_sent = Some(Instant::now()),
+            Err(e) => warn!("Failed to send telemetry: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut settings = TelemetrySettings::default();
+        assert!(settings.validate().is_ok());
+        settings.enabled = true;
+        assert!(settings.validate().is_err());
+        settings.endpoint = Some("ftp://example.com".to_string());
+        assert!(settings.validate().is_err());
+        settings.endpoint = Some("https://example.com/report".to_string());
+        assert!(settings.validate().is_ok());
+        settings.interval_hours = 0;
+        assert!(settings.validate().is_err());
+    }
+}
@@ -0,0 +1,107 @@
+//! Text files that aren't UTF-8, like legacy configs saved as Latin-1 or UTF-16,
+//! converted to UTF-8 for editing and back when saved.
+
+use chardetng::EncodingDetector;
+use color_eyre::eyre::eyre;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+use crate::error::{Error, ErrorKind};
+
+/// Tells the client which encoding a file was read as, to send back when saving it
+pub const FILE_ENCODING_HEADER: &str = "x-lodestone-file-encoding";
+
+/// Decodes the content of a text file, going by its BOM if it has one, then trying UTF-8,
+/// then guessing from the content
+pub fn decode(bytes: &[u8]) -> Result<(String, &'static Encoding), Error> {
+    let (encoding, content) = match Encoding::for_bom(bytes) {
+        Some((encoding, bom_length)) => (encoding, &bytes[bom_length..]),
+        None => {
+            if let Ok(text) = std::str::from_utf8(bytes) {
+                return Ok((text.to_string(), UTF_8));
+            }
+            // text without a BOM wouldn't have any in the encodings that can be guessed
+            if bytes.contains(&0) {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("File is not a text file"),
+                });
+            }
+            let mut detector = EncodingDetector::new();
+            detector.feed(bytes, true);
+            (detector.guess(None, true), bytes)
+        }
+    };
+    let (text, had_errors) = encoding.decode_without_bom_handling(content);
+    // replacement characters would end up in the file when saving it back
+    if had_errors {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("File is not valid {}", encoding.name()),
+        });
+    }
+    Ok((text.into_owned(), encoding))
+}
+
+/// Encodes text to write to a file, `label` being an encoding name like `windows-1252` or `utf-16le`.
+/// UTF-16 gets a BOM so the file can be told apart when read again
+pub fn encode(text: &str, label: &str) -> Result<Vec<u8>, Error> {
+    let encoding = Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Unknown encoding {}", label),
+    })?;
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let mut ret = Vec::with_capacity(2 + text.len() * 2);
+        for unit in std::iter::once(0xFEFF).chain(text.encode_utf16()) {
+            if encoding == UTF_16LE {
+                ret.extend_from_slice(&unit.to_le_bytes());
+            } else {
+                ret.extend_from_slice(&unit.to_be_bytes());
+            }
+        }
+        return Ok(ret);
+    }
+    let (bytes, _, had_errors) = encoding.encode(text);
+    if had_errors {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Text has characters that can't be written as {}",
+                encoding.name()
+            ),
+        });
+    }
+    Ok(bytes.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let (text, encoding) = decode("motd=héllo".as_bytes()).unwrap();
+        assert_eq!((text.as_str(), encoding), ("motd=héllo", UTF_8));
+
+        let latin1 = b"motd=Willkommen auf unserem Server, viel Spa\xdf beim Spielen!\nlevel-name=Gr\xfcnwald\n";
+        let (text, encoding) = decode(latin1).unwrap();
+        assert_eq!(encoding.name(), "windows-1252");
+        assert!(text.contains("Grünwald"));
+
+        let utf16 = encode("level-name=world", "utf-16le").unwrap();
+        let (text, encoding) = decode(&utf16).unwrap();
+        assert_eq!((text.as_str(), encoding), ("level-name=world", UTF_16LE));
+
+        assert!(decode(&[0x7f, 0x45, 0x4c, 0x46, 0x02, 0x00, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("Grünwald", "latin1").unwrap(), b"Gr\xfcnwald");
+        assert_eq!(
+            encode("ab", "utf-16be").unwrap(),
+            [0xfe, 0xff, 0, b'a', 0, b'b']
+        );
+        assert!(encode("雪", "windows-1252").is_err());
+        assert!(encode("text", "not-an-encoding").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::{
+        t_configurable::manifest::{ConfigurableValue, SettingManifest},
+        GameInstance,
+    },
+    types::InstanceUuid,
+};
+
+/// What a user needs to be allowed to do to run an action, on top of viewing the instance
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum ActionPermission {
+    StartInstance,
+    StopInstance,
+    WriteConsole,
+    AccessSetting,
+    WriteResource,
+    WriteInstanceFile,
+}
+
+impl ActionPermission {
+    pub fn user_action(self, uuid: InstanceUuid) -> UserAction {
+        match self {
+            ActionPermission::StartInstance => UserAction::StartInstance(uuid),
+            ActionPermission::StopInstance => UserAction::StopInstance(uuid),
+            ActionPermission::WriteConsole => UserAction::WriteConsole(uuid),
+            ActionPermission::AccessSetting => UserAction::AccessSetting(uuid),
+            ActionPermission::WriteResource => UserAction::WriteResource(uuid),
+            ActionPermission::WriteInstanceFile => UserAction::WriteInstanceFile(uuid),
+        }
+    }
+}
+
+/// A game specific operation an implementation offers, like reloading plugins
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct InstanceAction {
+    pub name: String,
+    pub description: String,
+    /// The arguments the action is run with are checked against them
+    pub parameters: Vec<SettingManifest>,
+    pub permission: ActionPermission,
+}
+
+/// Checks the arguments of an action against its parameters, by id.
+///
+/// Arguments that are left out take the parameter's default, optional parameters without one
+/// are left out as well.
+pub fn parse_action_args(
+    parameters: &[SettingManifest],
+    mut args: HashMap<String, String>,
+) -> Result<HashMap<String, ConfigurableValue>, Error> {
+    let mut parsed = HashMap::new();
+    for parameter in parameters {
+        let value = match args.remove(parameter.get_identifier()) {
+            Some(arg) => parameter
+                .get_value_type()
+                .parse_value(&arg)
+                .map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid argument {}: {}", parameter.get_name(), e.source),
+                })?,
+            None => match parameter.get_default_value() {
+                Some(default) => default.clone(),
+                None if parameter.is_required() => {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Missing argument {}", parameter.get_name()),
+                    })
+                }
+                None => continue,
+            },
+        };
+        parsed.insert(parameter.get_identifier().clone(), value);
+    }
+    if let Some(unknown) = args.keys().next() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The action takes no argument {}", unknown),
+        });
+    }
+    Ok(parsed)
+}
+
+#[async_trait]
+#[enum_dispatch::enum_dispatch]
+pub trait TAction {
+    /// The actions the instance offers, which may depend on its flavour
+    async fn list_actions(&self) -> Vec<InstanceAction> {
+        Vec::new()
+    }
+    /// The console command the action sends, if it sends one, so it can be checked against the
+    /// requester's command roles before the action is run
+    async fn action_command(
+        &self,
+        _name: &str,
+        _args: &HashMap<String, ConfigurableValue>,
+    ) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+    /// Runs an action with arguments already checked against its parameters
+    async fn run_action(
+        &mut self,
+        name: &str,
+        _args: HashMap<String, ConfigurableValue>,
+        _caused_by: CausedBy,
+    ) -> Result<serde_json::Value, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance has no action {}", name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::t_configurable::manifest::ConfigurableValueType;
+
+    #[test]
+    fn test_parse_action_args() {
+        let parameters = vec![
+            SettingManifest::new_required_unset(
+                "message".to_string(),
+                "Message".to_string(),
+                String::new(),
+                ConfigurableValueType::String { regex: None },
+                false,
+                true,
+            ),
+            SettingManifest::new_optional_value(
+                "delay".to_string(),
+                "Delay".to_string(),
+                String::new(),
+                None,
+                ConfigurableValueType::UnsignedInteger {
+                    min: None,
+                    max: None,
+                },
+                Some(ConfigurableValue::UnsignedInteger(5)),
+                false,
+                true,
+            ),
+        ];
+        let args = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        let parsed = parse_action_args(&parameters, args(&[("message", "hi")])).unwrap();
+        assert_eq!(
+            parsed.get("message"),
+            Some(&ConfigurableValue::String("hi".to_string()))
+        );
+        assert_eq!(
+            parsed.get("delay"),
+            Some(&ConfigurableValue::UnsignedInteger(5))
+        );
+        assert!(parse_action_args(&parameters, args(&[])).is_err());
+        assert!(
+            parse_action_args(&parameters, args(&[("message", "hi"), ("delay", "soon")])).is_err()
+        );
+        assert!(
+            parse_action_args(&parameters, args(&[("message", "hi"), ("volume", "11")])).is_err()
+        );
+    }
+}
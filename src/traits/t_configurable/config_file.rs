@@ -0,0 +1,442 @@
+//! Adapters exposing YAML, TOML and JSON config files as setting sections,
+//! the way server.properties is exposed for Minecraft instances.
+//!
+//! Every scalar in the file becomes a setting, keyed by its dotted path (`database.port`).
+//! Lists, nulls and anything else that doesn't fit a [`ConfigurableValue`] is left as is
+//! when the file is written back, as are values that weren't changed.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::manifest::{ConfigurableValue, SectionManifest, SettingManifest};
+use crate::error::{Error, ErrorKind};
+use crate::util::scoped_join_win_safe;
+
+/// Prefix of the id of sections backed by a config file, followed by the file's relative path
+pub const CONFIG_FILE_SECTION_PREFIX: &str = "config_file:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+/// A parsed config file, whatever its format
+trait ConfigNode: Sized {
+    /// `None` for values that can't be edited as a single setting
+    fn to_configurable(&self) -> Option<ConfigurableValue>;
+    fn from_configurable(value: &ConfigurableValue) -> Self;
+    /// Entries of a table or mapping, empty for anything else
+    fn children(&self) -> Vec<(String, &Self)>;
+    fn children_mut(&mut self) -> Vec<(String, &mut Self)>;
+}
+
+fn integer_to_configurable(value: i64) -> Option<ConfigurableValue> {
+    if let Ok(value) = i32::try_from(value) {
+        Some(ConfigurableValue::Integer(value))
+    } else {
+        u32::try_from(value)
+            .ok()
+            .map(ConfigurableValue::UnsignedInteger)
+    }
+}
+
+impl ConfigNode for serde_json::Value {
+    fn to_configurable(&self) -> Option<ConfigurableValue> {
+        match self {
+            serde_json::Value::String(value) => Some(ConfigurableValue::String(value.clone())),
+            serde_json::Value::Bool(value) => Some(ConfigurableValue::Boolean(*value)),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(value) => integer_to_configurable(value),
+                None if number.is_f64() => number
+                    .as_f64()
+                    .map(|value| ConfigurableValue::Float(value as f32)),
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn from_configurable(value: &ConfigurableValue) -> Self {
+        match value {
+            ConfigurableValue::String(value) | ConfigurableValue::Enum(value) => {
+                value.clone().into()
+            }
+            ConfigurableValue::Integer(value) => (*value).into(),
+            ConfigurableValue::UnsignedInteger(value) => (*value).into(),
+            ConfigurableValue::Float(value) => (*value as f64).into(),
+            ConfigurableValue::Boolean(value) => (*value).into(),
+        }
+    }
+
+    fn children(&self) -> Vec<(String, &Self)> {
+        match self {
+            serde_json::Value::Object(map) => map
+                .iter()
+                .map(|(key, value)| (key.clone(), value))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn children_mut(&mut self) -> Vec<(String, &mut Self)> {
+        match self {
+            serde_json::Value::Object(map) => map
+                .iter_mut()
+                .map(|(key, value)| (key.clone(), value))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn yaml_key(key: &serde_yaml::Value) -> Option<String> {
+    match key {
+        serde_yaml::Value::String(key) => Some(key.clone()),
+        serde_yaml::Value::Number(key) => Some(key.to_string()),
+        serde_yaml::Value::Bool(key) => Some(key.to_string()),
+        _ => None,
+    }
+}
+
+impl ConfigNode for serde_yaml::Value {
+    fn to_configurable(&self) -> Option<ConfigurableValue> {
+        match self {
+            serde_yaml::Value::String(value) => Some(ConfigurableValue::String(value.clone())),
+            serde_yaml::Value::Bool(value) => Some(ConfigurableValue::Boolean(*value)),
+            serde_yaml::Value::Number(number) => match number.as_i64() {
+                Some(value) => integer_to_configurable(value),
+                None if number.is_f64() => number
+                    .as_f64()
+                    .map(|value| ConfigurableValue::Float(value as f32)),
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn from_configurable(value: &ConfigurableValue) -> Self {
+        match value {
+            ConfigurableValue::String(value) | ConfigurableValue::Enum(value) => {
+                serde_yaml::Value::String(value.clone())
+            }
+            ConfigurableValue::Integer(value) => {
+                serde_yaml::Value::Number(i64::from(*value).into())
+            }
+            ConfigurableValue::UnsignedInteger(value) => {
+                serde_yaml::Value::Number(u64::from(*value).into())
+            }
+            ConfigurableValue::Float(value) => serde_yaml::Value::Number((*value as f64).into()),
+            ConfigurableValue::Boolean(value) => serde_yaml::Value::Bool(*value),
+        }
+    }
+
+    fn children(&self) -> Vec<(String, &Self)> {
+        match self {
+            serde_yaml::Value::Mapping(map) => map
+                .iter()
+                .filter_map(|(key, value)| yaml_key(key).map(|key| (key, value)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn children_mut(&mut self) -> Vec<(String, &mut Self)> {
+        match self {
+            serde_yaml::Value::Mapping(map) => map
+                .iter_mut()
+                .filter_map(|(key, value)| yaml_key(key).map(|key| (key, value)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl ConfigNode for toml::Value {
+    fn to_configurable(&self) -> Option<ConfigurableValue> {
+        match self {
+            toml::Value::String(value) => Some(ConfigurableValue::String(value.clone())),
+            toml::Value::Boolean(value) => Some(ConfigurableValue::Boolean(*value)),
+            toml::Value::Integer(value) => integer_to_configurable(*value),
+            toml::Value::Float(value) => Some(ConfigurableValue::Float(*value as f32)),
+            _ => None,
+        }
+    }
+
+    fn from_configurable(value: &ConfigurableValue) -> Self {
+        match value {
+            ConfigurableValue::String(value) | ConfigurableValue::Enum(value) => {
+                toml::Value::String(value.clone())
+            }
+            ConfigurableValue::Integer(value) => toml::Value::Integer(i64::from(*value)),
+            ConfigurableValue::UnsignedInteger(value) => toml::Value::Integer(i64::from(*value)),
+            ConfigurableValue::Float(value) => toml::Value::Float(*value as f64),
+            ConfigurableValue::Boolean(value) => toml::Value::Boolean(*value),
+        }
+    }
+
+    fn children(&self) -> Vec<(String, &Self)> {
+        match self {
+            toml::Value::Table(table) => table
+                .iter()
+                .map(|(key, value)| (key.clone(), value))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn children_mut(&mut self) -> Vec<(String, &mut Self)> {
+        match self {
+            toml::Value::Table(table) => table
+                .iter_mut()
+                .map(|(key, value)| (key.clone(), value))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn child_id(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn looks_secret(key: &str) -> bool {
+    let key = key.to_lowercase();
+    ["password", "secret", "token"]
+        .iter()
+        .any(|word| key.contains(word))
+}
+
+fn collect_settings<N: ConfigNode>(
+    node: &N,
+    prefix: &str,
+    settings: &mut IndexMap<String, SettingManifest>,
+) {
+    for (key, child) in node.children() {
+        let setting_id = child_id(prefix, &key);
+        match child.to_configurable() {
+            Some(value) => {
+                if settings.contains_key(&setting_id) {
+                    continue;
+                }
+                settings.insert(
+                    setting_id.clone(),
+                    SettingManifest::new_required_value(
+                        setting_id.clone(),
+                        key.clone(),
+                        setting_id,
+                        value,
+                        None,
+                        looks_secret(&key),
+                        true,
+                    ),
+                );
+            }
+            None => collect_settings(child, &setting_id, settings),
+        }
+    }
+}
+
+fn apply_settings<N: ConfigNode>(node: &mut N, prefix: &str, section: &SectionManifest) {
+    for (key, child) in node.children_mut() {
+        let setting_id = child_id(prefix, &key);
+        match child.to_configurable() {
+            Some(old_value) => {
+                if let Some(new_value) = section
+                    .get_setting(&setting_id)
+                    .and_then(|setting| setting.get_value())
+                {
+                    // rewriting untouched values could lose precision or formatting
+                    if *new_value != old_value {
+                        *child = N::from_configurable(new_value);
+                    }
+                }
+            }
+            None => apply_settings(child, &setting_id, section),
+        }
+    }
+}
+
+fn parse_error(format: ConfigFormat, e: impl std::fmt::Display) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Failed to parse {:?} config: {}", format, e),
+    }
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "yml" | "yaml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Every editable value in `content`, keyed by its dotted path
+    pub fn parse_settings(
+        &self,
+        content: &str,
+    ) -> Result<IndexMap<String, SettingManifest>, Error> {
+        let mut settings = IndexMap::new();
+        match self {
+            ConfigFormat::Yaml => collect_settings(
+                &serde_yaml::from_str::<serde_yaml::Value>(content)
+                    .map_err(|e| parse_error(*self, e))?,
+                "",
+                &mut settings,
+            ),
+            ConfigFormat::Toml => collect_settings(
+                &content
+                    .parse::<toml::Value>()
+                    .map_err(|e| parse_error(*self, e))?,
+                "",
+                &mut settings,
+            ),
+            ConfigFormat::Json => collect_settings(
+                &serde_json::from_str::<serde_json::Value>(content)
+                    .map_err(|e| parse_error(*self, e))?,
+                "",
+                &mut settings,
+            ),
+        }
+        Ok(settings)
+    }
+
+    /// `content` with the values from `section` written in
+    pub fn apply_section(&self, content: &str, section: &SectionManifest) -> Result<String, Error> {
+        Ok(match self {
+            ConfigFormat::Yaml => {
+                let mut root: serde_yaml::Value =
+                    serde_yaml::from_str(content).map_err(|e| parse_error(*self, e))?;
+                apply_settings(&mut root, "", section);
+                serde_yaml::to_string(&root).context("Failed to serialize YAML config")?
+            }
+            ConfigFormat::Toml => {
+                let mut root: toml::Value = content.parse().map_err(|e| parse_error(*self, e))?;
+                apply_settings(&mut root, "", section);
+                toml::to_string_pretty(&root).context("Failed to serialize TOML config")?
+            }
+            ConfigFormat::Json => {
+                let mut root: serde_json::Value =
+                    serde_json::from_str(content).map_err(|e| parse_error(*self, e))?;
+                apply_settings(&mut root, "", section);
+                serde_json::to_string_pretty(&root).context("Failed to serialize JSON config")?
+            }
+        })
+    }
+}
+
+/// A config file inside an instance, edited through its own setting section
+#[derive(Debug, Clone)]
+pub struct ConfigFileAdapter {
+    /// Relative to the instance's directory
+    relative_path: String,
+    path: PathBuf,
+    format: ConfigFormat,
+}
+
+impl ConfigFileAdapter {
+    pub fn new(path_to_instance: &Path, relative_path: &str) -> Result<Self, Error> {
+        let path = scoped_join_win_safe(path_to_instance, relative_path)?;
+        let format = ConfigFormat::from_path(&path).ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} is not a YAML, TOML or JSON file", relative_path),
+        })?;
+        Ok(Self {
+            relative_path: relative_path.to_string(),
+            path,
+            format,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn section_id(&self) -> String {
+        format!("{}{}", CONFIG_FILE_SECTION_PREFIX, self.relative_path)
+    }
+
+    pub async fn read_section(&self) -> Result<SectionManifest, Error> {
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .context(format!(
+                "Failed to read config file {}",
+                self.path.display()
+            ))?;
+        Ok(SectionManifest::new(
+            self.section_id(),
+            self.relative_path.clone(),
+            format!("Settings in {}", self.relative_path),
+            self.format.parse_settings(&content)?,
+        ))
+    }
+
+    /// Writes the values of `section` back into the file, keeping everything else in it
+    pub async fn write_section(&self, section: &SectionManifest) -> Result<(), Error> {
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .context(format!(
+                "Failed to read config file {}",
+                self.path.display()
+            ))?;
+        tokio::fs::write(&self.path, self.format.apply_section(&content, section)?)
+            .await
+            .context(format!(
+                "Failed to write config file {}",
+                self.path.display()
+            ))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let content = "server:\n  port: 25577\n  motd: hello\nforwarding-secret: abc\nservers: [lobby, survival]\n";
+        let settings = ConfigFormat::Yaml.parse_settings(content).unwrap();
+        assert_eq!(
+            settings.keys().collect::<Vec<_>>(),
+            vec!["server.port", "server.motd", "forwarding-secret"]
+        );
+        assert!(settings["forwarding-secret"].is_secret());
+        let mut section = SectionManifest::new(
+            "test".to_string(),
+            "test".to_string(),
+            "test".to_string(),
+            settings,
+        );
+        section
+            .update_setting("server.port", ConfigurableValue::Integer(25565))
+            .unwrap();
+        let written = ConfigFormat::Yaml.apply_section(content, &section).unwrap();
+        let reparsed = ConfigFormat::Yaml.parse_settings(&written).unwrap();
+        assert_eq!(
+            reparsed["server.port"].get_value(),
+            Some(&ConfigurableValue::Integer(25565))
+        );
+        assert!(written.contains("survival"));
+    }
+
+    #[test]
+    fn test_toml_keeps_unsupported_values() {
+        let content = "[database]\nhost = \"localhost\"\ncreated = 1979-05-27T07:32:00Z\n";
+        let settings = ConfigFormat::Toml.parse_settings(content).unwrap();
+        assert_eq!(settings.keys().collect::<Vec<_>>(), vec!["database.host"]);
+    }
+}
@@ -179,6 +179,33 @@ impl ConfigurableValueType {
     }
 }
 
+impl ConfigurableValueType {
+    /// Parses a value of this type from its string form, as the values of `ConfigurableValue`
+    /// are printed
+    pub fn parse_value(&self, value: &str) -> Result<ConfigurableValue, Error> {
+        let parsed = match self {
+            ConfigurableValueType::String { .. } => {
+                Some(ConfigurableValue::String(value.to_string()))
+            }
+            ConfigurableValueType::Integer { .. } => {
+                value.parse().ok().map(ConfigurableValue::Integer)
+            }
+            ConfigurableValueType::UnsignedInteger { .. } => {
+                value.parse().ok().map(ConfigurableValue::UnsignedInteger)
+            }
+            ConfigurableValueType::Float { .. } => value.parse().ok().map(ConfigurableValue::Float),
+            ConfigurableValueType::Boolean => value.parse().ok().map(ConfigurableValue::Boolean),
+            ConfigurableValueType::Enum { .. } => Some(ConfigurableValue::Enum(value.to_string())),
+        };
+        let parsed = parsed.ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Expected {}, found {}", self.to_string(), value),
+        })?;
+        self.type_check(&parsed)?;
+        Ok(parsed)
+    }
+}
+
 impl ToString for ConfigurableValue {
     fn to_string(&self) -> String {
         match self {
@@ -300,6 +327,21 @@ impl SettingManifest {
     pub fn get_identifier(&self) -> &String {
         &self.setting_id
     }
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+    pub fn is_secret(&self) -> bool {
+        self.is_secret
+    }
+    pub fn is_required(&self) -> bool {
+        self.is_required
+    }
+    pub fn get_value_type(&self) -> &ConfigurableValueType {
+        &self.value_type
+    }
+    pub fn get_default_value(&self) -> Option<&ConfigurableValue> {
+        self.default_value.as_ref()
+    }
     /// # WARNING
     /// Will infer the type of the value from the value itself
     ///
@@ -397,6 +439,28 @@ impl SettingManifest {
         }
     }
 
+    /// A required setting that has no value yet, it must be given one before it is used
+    pub fn new_required_unset(
+        setting_id: String,
+        name: String,
+        description: String,
+        value_type: ConfigurableValueType,
+        is_secret: bool,
+        is_mutable: bool,
+    ) -> Self {
+        Self {
+            setting_id,
+            name,
+            description,
+            value: None,
+            value_type,
+            default_value: None,
+            is_secret,
+            is_required: true,
+            is_mutable,
+        }
+    }
+
     fn set_value_type_safe(&mut self, value: ConfigurableValue) -> Result<(), Error> {
         self.value_type
             .type_check(&value)
@@ -658,6 +722,12 @@ impl ConfigurableManifest {
         self.setting_sections.clone()
     }
 
+    /// Adds `section`, replacing any section with the same id
+    pub fn insert_section(&mut self, section: SectionManifest) {
+        self.setting_sections
+            .insert(section.section_id.clone(), section);
+    }
+
     pub fn set_setting_value(
         &mut self,
         section_id: &str,
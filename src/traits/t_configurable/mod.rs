@@ -1,58 +1,29 @@
+pub mod config_file;
 pub mod manifest;
+pub mod preview;
+pub mod propagation;
 pub use std::path::PathBuf;
 
 use async_trait::async_trait;
 use color_eyre::eyre::eyre;
-use enum_kinds::EnumKind;
 pub use serde::{Deserialize, Serialize};
 pub use serde_json;
-use ts_rs::TS;
 
 use self::manifest::ConfigurableManifest;
 use self::manifest::ConfigurableValue;
+use self::preview::{diff_manifests, SettingChange, SettingsPreview};
 use crate::error::Error;
 use crate::error::ErrorKind;
 use crate::implementations::minecraft::Flavour;
+#[cfg(feature = "fake-instance")]
+use crate::traits::FakeInstance;
 use crate::traits::GameInstance;
 use crate::traits::GenericInstance;
 use crate::traits::MinecraftInstance;
 
 use crate::types::InstanceUuid;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
-#[serde(tag = "type")]
-#[ts(export)]
-pub enum MinecraftVariant {
-    Vanilla,
-    Forge,
-    Fabric,
-    Paper,
-    Spigot,
-    Other { name: String },
-}
-
-/// The type of game this instance is
-/// 
-/// Meant to be consumed by frontend to display the correct icon
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, EnumKind)]
-#[enum_kind(GameType, derive(Serialize, Deserialize, TS))]
-#[serde(tag = "type")]
-#[ts(export)]
-pub enum Game {
-    MinecraftJava {
-        variant: MinecraftVariant,
-    },
-    MinecraftBedrock,
-    Generic {
-        game_name: GameType,       //used for identifying the "game" ("Minecraft")
-        game_display_name: String, //displaying to the user what on earth this is ("MinecraftGlowstone")
-    },
-}
-
-#[test]
-fn export_game_type() {
-    let _ = GameType::export();
-}
+pub use lodestone_types::{Game, GameType, MinecraftVariant};
 
 impl From<Flavour> for Game {
     fn from(value: Flavour) -> Self {
@@ -88,6 +59,10 @@ pub trait TConfigurable {
     async fn port(&self) -> u32;
     async fn creation_time(&self) -> i64;
     async fn path(&self) -> PathBuf;
+    /// Port Bedrock Edition players can join on, if the instance supports crossplay
+    async fn bedrock_port(&self) -> Option<u32> {
+        None
+    }
     /// does start when lodestone starts
     async fn auto_start(&self) -> bool;
     async fn restart_on_crash(&self) -> bool;
@@ -126,6 +101,23 @@ pub trait TConfigurable {
         })
     }
 
+    /// YAML, TOML or JSON files, relative to the instance's directory,
+    /// to expose as setting sections
+    async fn set_config_files(&mut self, _config_files: Vec<String>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support editing config files"),
+        })
+    }
+
+    /// How many console lines are kept in memory for the console view
+    async fn set_console_buffer_lines(&mut self, _lines: u32) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support resizing its console buffer"),
+        })
+    }
+
     async fn configurable_manifest(&mut self) -> ConfigurableManifest;
 
     async fn update_configurable(
@@ -134,4 +126,25 @@ pub trait TConfigurable {
         setting_id: &str,
         value: ConfigurableValue,
     ) -> Result<(), Error>;
+
+    /// What applying `changes` in order would do, without applying anything
+    ///
+    /// Assumes every change needs a restart unless the instance knows better
+    async fn preview_configurable(
+        &mut self,
+        changes: Vec<SettingChange>,
+    ) -> Result<SettingsPreview, Error> {
+        let current = self.configurable_manifest().await;
+        let mut updated = current.clone();
+        for change in changes {
+            updated.update_setting_value(&change.section_id, &change.setting_id, change.value)?;
+        }
+        let settings = diff_manifests(&current, &updated, |_, _| true);
+        Ok(SettingsPreview {
+            requires_restart: !settings.is_empty(),
+            settings,
+            files: Vec::new(),
+            launch_args: None,
+        })
+    }
 }
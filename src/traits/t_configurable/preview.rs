@@ -0,0 +1,127 @@
+//! Dry runs of setting changes, so users can see what a batch of changes
+//! would do before applying it.
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::manifest::{ConfigurableManifest, ConfigurableValue};
+
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
+pub struct SettingChange {
+    pub section_id: String,
+    pub setting_id: String,
+    pub value: ConfigurableValue,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, TS)]
+#[ts(export)]
+pub struct SettingDiff {
+    pub section_id: String,
+    pub setting_id: String,
+    pub name: String,
+    /// Left out for secret settings
+    pub old_value: Option<ConfigurableValue>,
+    /// Left out for secret settings
+    pub new_value: Option<ConfigurableValue>,
+    pub requires_restart: bool,
+}
+
+/// Lines that would go away and lines that would appear, in order
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct LineDiff {
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+impl LineDiff {
+    pub fn between(old: &[String], new: &[String]) -> Self {
+        Self {
+            removed: old
+                .iter()
+                .filter(|line| !new.contains(line))
+                .cloned()
+                .collect(),
+            added: new
+                .iter()
+                .filter(|line| !old.contains(line))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct FileDiff {
+    /// Relative to the instance's directory
+    pub path: String,
+    pub diff: LineDiff,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct SettingsPreview {
+    /// Only the settings whose value would actually change
+    pub settings: Vec<SettingDiff>,
+    pub files: Vec<FileDiff>,
+    /// Changes to the arguments the instance is launched with, if the instance has any
+    pub launch_args: Option<LineDiff>,
+    /// Whether any of the changes only takes effect after a restart
+    pub requires_restart: bool,
+}
+
+/// Every setting whose value differs between `old` and `new`
+///
+/// `requires_restart` decides, given a section and setting id, whether the change needs a restart
+pub fn diff_manifests(
+    old: &ConfigurableManifest,
+    new: &ConfigurableManifest,
+    requires_restart: impl Fn(&str, &str) -> bool,
+) -> Vec<SettingDiff> {
+    let mut ret = Vec::new();
+    for (section_id, section) in new.get_all_sections() {
+        for (setting_id, setting) in section.all_settings() {
+            let old_value = old
+                .get_setting(&section_id, setting_id)
+                .and_then(|setting| setting.get_value());
+            if old_value == setting.get_value() {
+                continue;
+            }
+            let (old_value, new_value) = if setting.is_secret() {
+                (None, None)
+            } else {
+                (old_value.cloned(), setting.get_value().cloned())
+            };
+            ret.push(SettingDiff {
+                requires_restart: requires_restart(&section_id, setting_id),
+                section_id: section_id.clone(),
+                setting_id: setting_id.clone(),
+                name: setting.get_name().clone(),
+                old_value,
+                new_value,
+            });
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_diff() {
+        let old = vec!["a=1".to_string(), "b=2".to_string(), "c=3".to_string()];
+        let new = vec!["a=1".to_string(), "b=4".to_string(), "c=3".to_string()];
+        let diff = LineDiff::between(&old, &new);
+        assert_eq!(diff.removed, vec!["b=2".to_string()]);
+        assert_eq!(diff.added, vec!["b=4".to_string()]);
+        assert!(LineDiff::between(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+//! Copying setting values from one instance to others, for users running several similar
+//! servers.
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{manifest::ConfigurableManifest, preview::SettingChange, preview::SettingsPreview};
+use crate::error::{Error, ErrorKind};
+use crate::types::InstanceUuid;
+
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
+pub struct SettingKey {
+    pub section_id: String,
+    pub setting_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
+pub struct SettingsPropagation {
+    pub settings: Vec<SettingKey>,
+    pub targets: Vec<InstanceUuid>,
+    /// Only preview the changes on each target
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct PropagationOutcome {
+    pub instance_uuid: InstanceUuid,
+    /// What changes, or would change on a dry run
+    pub preview: Option<SettingsPreview>,
+    /// Why the settings couldn't be applied to this target, the other targets are still done
+    pub error: Option<String>,
+}
+
+/// The changes that give the chosen settings the values they have in `source`
+pub fn changes_from(
+    source: &ConfigurableManifest,
+    settings: &[SettingKey],
+) -> Result<Vec<SettingChange>, Error> {
+    settings
+        .iter()
+        .map(|key| {
+            let setting = source
+                .get_setting(&key.section_id, &key.setting_id)
+                .ok_or_else(|| Error {
+                    kind: ErrorKind::NotFound,
+                    source: eyre!(
+                        "The source instance has no setting {}/{}",
+                        key.section_id,
+                        key.setting_id
+                    ),
+                })?;
+            let value = setting.get_value().cloned().ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Setting {}/{} has no value on the source instance",
+                    key.section_id,
+                    key.setting_id
+                ),
+            })?;
+            Ok(SettingChange {
+                section_id: key.section_id.clone(),
+                setting_id: key.setting_id.clone(),
+                value,
+            })
+        })
+        .collect()
+}
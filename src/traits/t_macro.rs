@@ -7,7 +7,7 @@ use crate::{
     error::{Error, ErrorKind},
     events::CausedBy,
     macro_executor::MacroPID,
-    traits::GameInstance,
+    traits::{t_configurable::manifest::SettingManifest, GameInstance},
 };
 
 use serde::Deserialize;
@@ -20,6 +20,12 @@ pub struct MacroEntry {
     pub last_run: Option<i64>,
     // relative path to instance root
     pub path: PathBuf,
+    /// The parameters the macro declares, the arguments it is run with are checked against them
+    #[serde(default)]
+    pub parameters: Vec<SettingManifest>,
+    /// Set for macros of the core's macros directory, whether this instance runs them
+    #[serde(default)]
+    pub global_enabled: Option<bool>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, TS)]
@@ -62,6 +68,54 @@ impl ExitStatus {
     }
 }
 
+/// Checks the arguments of a macro against the parameters it declares, in order.
+///
+/// Arguments that are left out or empty take the parameter's default, and optional parameters
+/// without one are passed as empty strings so every argument keeps its position. Macros that
+/// declare no parameters get their arguments as they are.
+pub fn parse_macro_args(
+    parameters: &[SettingManifest],
+    args: Vec<String>,
+) -> Result<Vec<String>, Error> {
+    if parameters.is_empty() {
+        return Ok(args);
+    }
+    if args.len() > parameters.len() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "The macro takes {} arguments, {} were given",
+                parameters.len(),
+                args.len()
+            ),
+        });
+    }
+    parameters
+        .iter()
+        .enumerate()
+        .map(
+            |(i, parameter)| match args.get(i).filter(|arg| !arg.is_empty()) {
+                Some(arg) => parameter
+                    .get_value_type()
+                    .parse_value(arg)
+                    .map(|value| value.to_string())
+                    .map_err(|e| Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Invalid argument {}: {}", parameter.get_name(), e.source),
+                    }),
+                None => match parameter.get_default_value() {
+                    Some(default) => Ok(default.to_string()),
+                    None if parameter.is_required() => Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Missing argument {}", parameter.get_name()),
+                    }),
+                    None => Ok(String::new()),
+                },
+            },
+        )
+        .collect()
+}
+
 #[async_trait]
 #[enum_dispatch::enum_dispatch]
 pub trait TMacro {
@@ -87,4 +141,77 @@ pub trait TMacro {
             source: eyre!("This instance does not support killing macro"),
         })
     }
+    async fn set_global_macro_enabled(&mut self, _name: &str, _enabled: bool) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support global macros"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::t_configurable::manifest::{ConfigurableValue, ConfigurableValueType};
+
+    #[test]
+    fn test_parse_macro_args() {
+        let parameters = vec![
+            SettingManifest::new_required_unset(
+                "message".to_string(),
+                "Message".to_string(),
+                String::new(),
+                ConfigurableValueType::String { regex: None },
+                false,
+                true,
+            ),
+            SettingManifest::new_value_with_type(
+                "times".to_string(),
+                "Times".to_string(),
+                String::new(),
+                Some(ConfigurableValue::UnsignedInteger(1)),
+                ConfigurableValueType::UnsignedInteger {
+                    min: Some(1),
+                    max: Some(10),
+                },
+                Some(ConfigurableValue::UnsignedInteger(1)),
+                false,
+                true,
+            ),
+            SettingManifest::new_optional_value(
+                "loud".to_string(),
+                "Loud".to_string(),
+                String::new(),
+                None,
+                ConfigurableValueType::Boolean,
+                None,
+                false,
+                true,
+            ),
+        ];
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            parse_macro_args(&parameters, args(&["hi", "3", "true"])).unwrap(),
+            args(&["hi", "3", "true"])
+        );
+        assert_eq!(
+            parse_macro_args(&parameters, args(&["hi"])).unwrap(),
+            args(&["hi", "1", ""])
+        );
+        assert_eq!(
+            parse_macro_args(&parameters, args(&["hi", "", "false"])).unwrap(),
+            args(&["hi", "1", "false"])
+        );
+        assert!(parse_macro_args(&parameters, args(&[])).is_err());
+        assert!(parse_macro_args(&parameters, args(&["hi", "11"])).is_err());
+        assert!(parse_macro_args(&parameters, args(&["hi", "two"])).is_err());
+        assert!(parse_macro_args(&parameters, args(&["hi", "1", "yes"])).is_err());
+        assert!(parse_macro_args(&parameters, args(&["hi", "1", "true", "extra"])).is_err());
+
+        assert_eq!(
+            parse_macro_args(&[], args(&["anything", "goes"])).unwrap(),
+            args(&["anything", "goes"])
+        );
+    }
 }
@@ -1,39 +1,25 @@
 use async_trait::async_trait;
 use color_eyre::eyre::eyre;
-use serde::{Deserialize, Serialize};
-use ts_rs::TS;
 
 use crate::error::{Error, ErrorKind};
-use crate::implementations::generic::player::GenericPlayer;
-use crate::minecraft::player::MinecraftPlayer;
 use crate::traits::GameInstance;
-#[enum_dispatch::enum_dispatch]
 pub trait TPlayer {
     fn get_id(&self) -> String;
     fn get_name(&self) -> String;
 }
 
-#[enum_dispatch::enum_dispatch(TPlayer)]
-#[derive(Serialize, Deserialize, Debug, Eq, TS, Clone)]
-#[serde(tag = "type")]
-#[ts(export)]
-pub enum Player {
-    MinecraftPlayer,
-    GenericPlayer,
-}
+pub use lodestone_types::Player;
 
-impl PartialEq for Player {
-    fn eq(&self, other: &Self) -> bool {
-        self.get_id() == other.get_id()
+impl TPlayer for Player {
+    fn get_id(&self) -> String {
+        self.id()
     }
-}
-use std::collections::HashSet;
-use std::hash::{Hash, Hasher};
-impl Hash for Player {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.get_id().hash(state);
+
+    fn get_name(&self) -> String {
+        self.name().to_string()
     }
 }
+use std::collections::HashSet;
 
 #[async_trait]
 #[enum_dispatch::enum_dispatch]
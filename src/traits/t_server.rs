@@ -1,22 +1,15 @@
+use std::time::Instant;
+
 use async_trait::async_trait;
 use color_eyre::eyre::eyre;
 use serde::{Deserialize, Serialize};
 
 use ts_rs::TS;
 
-use crate::events::CausedBy;
+use crate::events::{CausedBy, Event};
 use crate::Error;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, Copy)]
-#[serde(rename = "InstanceState")]
-#[ts(export)]
-pub enum State {
-    Starting,
-    Running,
-    Stopping,
-    Stopped,
-    Error,
-}
+pub use lodestone_types::State;
 
 pub enum StateAction {
     UserStart,
@@ -44,6 +37,33 @@ impl From<sysinfo::DiskUsage> for DiskUsage {
         }
     }
 }
+
+/// Cumulative disk IO of a process at some point, turned into rates between two samples
+#[derive(Debug, Clone, Copy)]
+pub struct DiskSample {
+    pub at: Instant,
+    pub total_read_bytes: u64,
+    pub total_written_bytes: u64,
+}
+
+impl DiskSample {
+    /// Bytes read and written per second since `previous`
+    pub fn rates_since(&self, previous: &DiskSample) -> Option<(f64, f64)> {
+        let elapsed = self.at.checked_duration_since(previous.at)?.as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        Some((
+            self.total_read_bytes
+                .saturating_sub(previous.total_read_bytes) as f64
+                / elapsed,
+            self.total_written_bytes
+                .saturating_sub(previous.total_written_bytes) as f64
+                / elapsed,
+        ))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, TS, Default)]
 #[serde(rename = "PerformanceReport")]
 #[ts(export)]
@@ -52,22 +72,33 @@ pub struct MonitorReport {
     pub disk_usage: Option<DiskUsage>,
     pub cpu_usage: Option<f32>,
     pub start_time: Option<u64>,
+    /// Bytes per second, averaged since the previous report
+    #[serde(default)]
+    pub disk_read_rate: Option<f64>,
+    #[serde(default)]
+    pub disk_write_rate: Option<f64>,
+    /// Only known on Linux
+    #[serde(default)]
+    pub open_files: Option<u64>,
 }
 
-impl ToString for State {
-    fn to_string(&self) -> String {
-        match self {
-            State::Starting => "Starting".to_string(),
-            State::Running => "Running".to_string(),
-            State::Stopping => "Stopping".to_string(),
-            State::Stopped => "Stopped".to_string(),
-            State::Error => "Error".to_string(),
-        }
-    }
+/// The transitions an instance's state can go through
+pub trait StateTransition {
+    fn try_new_state(
+        &self,
+        action: StateAction,
+        on_transit: Option<&dyn Fn(State)>,
+    ) -> Result<State, Error>;
+
+    fn try_transition(
+        &mut self,
+        action: StateAction,
+        on_transit: Option<&dyn Fn(State)>,
+    ) -> Result<(), Error>;
 }
 
-impl State {
-    pub fn try_new_state(
+impl StateTransition for State {
+    fn try_new_state(
         &self,
         action: StateAction,
         on_transit: Option<&dyn Fn(State)>,
@@ -104,7 +135,7 @@ impl State {
         Ok(state)
     }
 
-    pub fn try_transition(
+    fn try_transition(
         &mut self,
         action: StateAction,
         on_transit: Option<&dyn Fn(State)>,
@@ -127,4 +158,6 @@ pub trait TServer {
     async fn state(&self) -> State;
     async fn send_command(&self, command: &str, caused_by: CausedBy) -> Result<(), Error>;
     async fn monitor(&self) -> MonitorReport;
+    /// Up to `lines` of the most recent console events, oldest first
+    async fn console_history(&self, lines: usize) -> Vec<Event>;
 }
@@ -1,24 +1,11 @@
-use std::fmt::Display;
-
 use crate::migration::DotLodestoneConfigV043;
+use crate::storage::DEFAULT_STORAGE_LOCATION;
 use crate::traits::t_configurable::GameType;
-use crate::{
-    implementations::minecraft::Flavour, migration::RestoreConfigV042, prelude::SNOWFLAKE_GENERATOR,
-};
+use crate::{implementations::minecraft::Flavour, migration::RestoreConfigV042};
 use serde::{Deserialize, Serialize};
-use serde_aux::prelude::*;
 use ts_rs::TS;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, TS, Copy)]
-#[ts(export)]
-#[serde(into = "String")]
-#[derive(sqlx::Type)]
-#[sqlx(transparent)]
-pub struct Snowflake(
-    #[serde(deserialize_with = "deserialize_number_from_string")]
-    #[ts(type = "string")]
-    i64,
-);
+pub use lodestone_types::{InstanceUuid, Snowflake};
 
 #[derive(Deserialize, Clone, Debug, TS)]
 #[ts(export)]
@@ -27,97 +14,6 @@ pub struct TimeRange {
     pub end: i64,
 }
 
-impl From<Snowflake> for String {
-    fn from(snowflake: Snowflake) -> Self {
-        snowflake.to_string()
-    }
-}
-
-#[test]
-fn test_snowflake() {
-    let snowflake1 = Snowflake::new();
-    // serializing
-    let snowflake_str = serde_json::to_string(&snowflake1).unwrap();
-    println!("{}", snowflake_str);
-    // deserializing
-    let snowflake2: Snowflake = serde_json::from_str(&snowflake_str).unwrap();
-    assert_eq!(snowflake1, snowflake2);
-}
-
-impl Default for Snowflake {
-    fn default() -> Self {
-        Self(get_snowflake())
-    }
-}
-
-impl Snowflake {
-    pub fn new() -> Self {
-        Self(get_snowflake())
-    }
-}
-
-impl ToString for Snowflake {
-    fn to_string(&self) -> String {
-        self.0.to_string()
-    }
-}
-
-fn get_snowflake() -> i64 {
-    SNOWFLAKE_GENERATOR.lock().unwrap().real_time_generate()
-}
-
-#[derive(Debug, Clone, Eq, Serialize, Deserialize, TS)]
-#[serde(transparent)]
-#[ts(export)]
-#[derive(sqlx::Type)]
-#[sqlx(transparent)]
-pub struct InstanceUuid(String);
-
-impl InstanceUuid {
-    pub fn no_prefix(&self) -> String {
-        self.0.replace("INSTANCE_", "")
-    }
-}
-
-impl From<String> for InstanceUuid {
-    fn from(uuid: String) -> Self {
-        Self(uuid)
-    }
-}
-// new type idiom
-
-impl Default for InstanceUuid {
-    fn default() -> Self {
-        Self(format!("INSTANCE_{}", uuid::Uuid::new_v4()))
-    }
-}
-
-impl AsRef<str> for InstanceUuid {
-    fn as_ref(&self) -> &str {
-        &self.0
-    }
-}
-
-// implement partial eq for all types that can be converted to stri/ng
-impl<T: AsRef<str>> PartialEq<T> for InstanceUuid {
-    fn eq(&self, other: &T) -> bool {
-        self.0 == other.as_ref()
-    }
-}
-
-use std::hash::Hash;
-impl Hash for InstanceUuid {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.0.hash(state);
-    }
-}
-
-impl Display for InstanceUuid {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
-
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LodestoneMetadata {
     pub semver: semver::Version,
@@ -130,6 +26,9 @@ pub struct DotLodestoneConfig {
     game_type: GameType,
     uuid: InstanceUuid,
     creation_time: i64,
+    /// Id of the storage location the instance is kept in, the default one if `None`
+    #[serde(default)]
+    storage_location: Option<String>,
 }
 
 impl From<RestoreConfigV042> for DotLodestoneConfig {
@@ -145,6 +44,7 @@ impl From<RestoreConfigV042> for DotLodestoneConfig {
             game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            storage_location: None,
         }
     }
 }
@@ -155,6 +55,7 @@ impl From<DotLodestoneConfigV043> for DotLodestoneConfig {
             game_type: config.game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            storage_location: None,
         }
     }
 }
@@ -165,9 +66,15 @@ impl DotLodestoneConfig {
             game_type,
             uuid,
             creation_time: chrono::Utc::now().timestamp(),
+            storage_location: None,
         }
     }
 
+    pub fn with_storage_location(mut self, storage_location: &str) -> Self {
+        self.set_storage_location(storage_location);
+        self
+    }
+
     pub fn uuid(&self) -> &InstanceUuid {
         &self.uuid
     }
@@ -178,15 +85,43 @@ impl DotLodestoneConfig {
     pub fn game_type(&self) -> &GameType {
         &self.game_type
     }
+
+    pub fn storage_location(&self) -> &str {
+        self.storage_location
+            .as_deref()
+            .unwrap_or(DEFAULT_STORAGE_LOCATION)
+    }
+
+    pub fn set_storage_location(&mut self, storage_location: &str) {
+        self.storage_location =
+            (storage_location != DEFAULT_STORAGE_LOCATION).then(|| storage_location.to_string());
+    }
 }
 
+/// The types shared with the clients can't be marked `#[ts(export)]` from here, so their
+/// bindings are written along with the core's own
 #[test]
-fn test_instance_uuid() {
-    let uuid1 = InstanceUuid::default();
-    // serializing
-    let uuid_str = serde_json::to_string(&uuid1).unwrap();
-    println!("{}", uuid_str);
-    // deserializing
-    let uuid2: InstanceUuid = serde_json::from_str(&uuid_str).unwrap();
-    assert_eq!(uuid1, uuid2);
+fn export_bindings_shared_types() {
+    use lodestone_types::*;
+    CausedBy::export().unwrap();
+    CoreInfo::export().unwrap();
+    EventLevel::export().unwrap();
+    Game::export().unwrap();
+    GameType::export().unwrap();
+    GenericPlayer::export().unwrap();
+    HealthStatus::export().unwrap();
+    InstanceInfo::export().unwrap();
+    InstanceUuid::export().unwrap();
+    JwtToken::export().unwrap();
+    Locale::export().unwrap();
+    LoginReply::export().unwrap();
+    MacroPID::export().unwrap();
+    MinecraftPlayer::export().unwrap();
+    MinecraftVariant::export().unwrap();
+    Player::export().unwrap();
+    PublicUser::export().unwrap();
+    Snowflake::export().unwrap();
+    State::export().unwrap();
+    UserId::export().unwrap();
+    UserPermission::export().unwrap();
 }
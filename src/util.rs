@@ -6,6 +6,7 @@ use std::io::{Read, Write};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
 
 use futures_util::StreamExt;
@@ -627,6 +628,22 @@ pub fn format_byte(mut bytes: u64) -> String {
     format!("{:.1} {}", bytes, unit)
 }
 
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts `body` as JSON to a user configured webhook, treating error statuses as failures
+pub async fn post_webhook(url: &str, body: &serde_json::Value) -> Result<(), Error> {
+    Client::new()
+        .post(url)
+        .timeout(WEBHOOK_TIMEOUT)
+        .json(body)
+        .send()
+        .await
+        .context("Failed to call webhook")?
+        .error_for_status()
+        .context("Webhook returned an error")?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::prelude::init_paths;
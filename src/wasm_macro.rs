@@ -0,0 +1,420 @@
+//! WebAssembly macros, so macros can be written in any language that compiles to WebAssembly.
+//!
+//! A macro named `backup` can be a `backup.wasm` next to the TypeScript ones. Unlike those, it
+//! runs with a memory limit and is stopped once it runs for longer than its time limit, both set
+//! per instance. It may use WASI to read its arguments and print, but gets no files or network.
+//! The instance is controlled through the functions imported from the `lodestone` module, the
+//! same ones TypeScript macros have as ops:
+//!
+//! - `send_stdin(ptr, len) -> i32` sends a command to the server, returning 0 or -1 on error
+//! - `send_rcon(ptr, len) -> i64` sends a command over RCON, returning its response
+//! - `on_event(ptr, len) -> i64` waits for an event, like `playerMessage`, returning it as JSON
+//!
+//! What the macro prints is kept with its run, like the console output of TypeScript macros.
+//!
+//! Strings are UTF-8, passed as a pointer and length into the macro's exported `memory`. Those
+//! returned by the core are written to memory the macro hands out through its exported
+//! `lodestone_alloc(len) -> ptr`, and returned as `ptr << 32 | len`, or 0 on error.
+//!
+//! The exported `_start` is run, or `run` for modules not built for WASI.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+use tokio_util::sync::CancellationToken;
+use wasi_common::pipe::WritePipe;
+use wasmtime::{
+    Caller, Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedFunc,
+};
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+
+use crate::error::{Error, ErrorKind};
+use crate::macro_executor::MacroOutput;
+use crate::traits::t_macro::ExitStatus;
+
+/// In megabytes
+pub const DEFAULT_WASM_MACRO_MEMORY: u32 = 64;
+/// In minutes
+pub const DEFAULT_WASM_MACRO_TIMEOUT: u32 = 60;
+/// How often a running macro yields, so it can't hold up the thread it runs on
+const YIELD_INTERVAL: Duration = Duration::from_millis(10);
+/// Output without a line break is split into lines of at most this many bytes
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// What a WebAssembly macro can do to its instance
+#[async_trait]
+pub trait WasmMacroHost: Send + Sync {
+    async fn send_stdin(&self, cmd: &str) -> Result<(), Error>;
+    async fn send_rcon(&self, cmd: &str) -> Result<String, Error>;
+    /// The next event of the given kind, as JSON
+    async fn on_event(&self, event: &str) -> Result<Option<String>, Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// Of the macro's linear memory
+    pub max_memory_bytes: usize,
+    /// `None` to let the macro run until it returns
+    pub timeout: Option<Duration>,
+}
+
+struct WasmState {
+    wasi: WasiCtx,
+    limits: StoreLimits,
+    host: Arc<dyn WasmMacroHost>,
+}
+
+/// Splits what the macro writes to stdout or stderr into lines
+struct OutputWriter {
+    output: MacroOutput,
+    partial: Vec<u8>,
+}
+
+impl OutputWriter {
+    fn new(output: MacroOutput) -> Self {
+        Self {
+            output,
+            partial: Vec::new(),
+        }
+    }
+
+    fn push_line(&mut self) {
+        let line = std::mem::take(&mut self.partial);
+        self.output
+            .push(String::from_utf8_lossy(&line).trim_end().to_string());
+    }
+}
+
+impl std::io::Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for byte in buf {
+            if *byte == b'\n' {
+                self.push_line();
+            } else {
+                self.partial.push(*byte);
+                if self.partial.len() >= MAX_LINE_BYTES {
+                    self.push_line();
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Packs a string the core wrote into the macro's memory into a return value
+fn pack(ptr: u32, len: u32) -> i64 {
+    ((ptr as i64) << 32) | len as i64
+}
+
+fn read_string(
+    caller: &mut Caller<'_, WasmState>,
+    ptr: i32,
+    len: i32,
+) -> Result<String, wasmtime::Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("The macro doesn't export its memory"))?;
+    // bounds checked before copying, so the macro can't make the core allocate what it likes
+    let start = ptr as u32 as usize;
+    let bytes = start
+        .checked_add(len as u32 as usize)
+        .and_then(|end| memory.data(&*caller).get(start..end))
+        .ok_or_else(|| wasmtime::Error::msg("The string is outside of the macro's memory"))?;
+    Ok(std::str::from_utf8(bytes)?.to_string())
+}
+
+async fn write_string(
+    caller: &mut Caller<'_, WasmState>,
+    content: &str,
+) -> Result<i64, wasmtime::Error> {
+    let alloc: TypedFunc<i32, i32> = caller
+        .get_export("lodestone_alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| wasmtime::Error::msg("The macro doesn't export lodestone_alloc"))?
+        .typed(&*caller)?;
+    let len = content.len() as i32;
+    let ptr = alloc.call_async(&mut *caller, len).await?;
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("The macro doesn't export its memory"))?;
+    memory.write(&mut *caller, ptr as u32 as usize, content.as_bytes())?;
+    Ok(pack(ptr as u32, len as u32))
+}
+
+fn link_host_functions(linker: &mut Linker<WasmState>) -> Result<(), wasmtime::Error> {
+    linker.func_wrap2_async(
+        "lodestone",
+        "send_stdin",
+        |mut caller: Caller<'_, WasmState>, ptr: i32, len: i32| {
+            Box::new(async move {
+                let cmd = read_string(&mut caller, ptr, len)?;
+                let host = caller.data().host.clone();
+                Ok(match host.send_stdin(&cmd).await {
+                    Ok(()) => 0,
+                    Err(_) => -1,
+                })
+            })
+        },
+    )?;
+    linker.func_wrap2_async(
+        "lodestone",
+        "send_rcon",
+        |mut caller: Caller<'_, WasmState>, ptr: i32, len: i32| {
+            Box::new(async move {
+                let cmd = read_string(&mut caller, ptr, len)?;
+                let host = caller.data().host.clone();
+                match host.send_rcon(&cmd).await {
+                    Ok(response) => write_string(&mut caller, &response).await,
+                    Err(_) => Ok(0),
+                }
+            })
+        },
+    )?;
+    linker.func_wrap2_async(
+        "lodestone",
+        "on_event",
+        |mut caller: Caller<'_, WasmState>, ptr: i32, len: i32| {
+            Box::new(async move {
+                let event = read_string(&mut caller, ptr, len)?;
+                let host = caller.data().host.clone();
+                match host.on_event(&event).await {
+                    Ok(Some(event)) => write_string(&mut caller, &event).await,
+                    Ok(None) | Err(_) => Ok(0),
+                }
+            })
+        },
+    )?;
+    Ok(())
+}
+
+/// A compiled macro, ready to run once
+pub struct WasmMacro {
+    engine: Engine,
+    module: Module,
+    limits: WasmLimits,
+}
+
+impl WasmMacro {
+    pub fn new(bytes: &[u8], limits: WasmLimits) -> Result<Self, Error> {
+        let mut config = Config::new();
+        config.async_support(true).epoch_interruption(true);
+        let engine = Engine::new(&config).map_err(|e| eyre!("Failed to create engine: {e}"))?;
+        let module = Module::new(&engine, bytes).map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid WebAssembly module: {e}"),
+        })?;
+        Ok(Self {
+            engine,
+            module,
+            limits,
+        })
+    }
+
+    async fn call(
+        &self,
+        args: Vec<String>,
+        host: Arc<dyn WasmMacroHost>,
+        output: MacroOutput,
+    ) -> Result<(), wasmtime::Error> {
+        let mut linker = Linker::new(&self.engine);
+        wasmtime_wasi::add_to_linker(&mut linker, |state: &mut WasmState| &mut state.wasi)?;
+        link_host_functions(&mut linker)?;
+        let wasi = WasiCtxBuilder::new()
+            .stdout(Box::new(WritePipe::new(OutputWriter::new(output.clone()))))
+            .stderr(Box::new(WritePipe::new(OutputWriter::new(output))))
+            .args(&args)?
+            .build();
+        let mut store = Store::new(
+            &self.engine,
+            WasmState {
+                wasi,
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(self.limits.max_memory_bytes)
+                    .instances(1)
+                    .build(),
+                host,
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.epoch_deadline_async_yield_and_update(1);
+        let instance = linker.instantiate_async(&mut store, &self.module).await?;
+        let entry = match instance.get_typed_func::<(), ()>(&mut store, "_start") {
+            Ok(entry) => entry,
+            Err(_) => instance.get_typed_func::<(), ()>(&mut store, "run")?,
+        };
+        match entry.call_async(&mut store, ()).await {
+            Ok(()) => Ok(()),
+            Err(e) => match e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+                Some(wasmtime_wasi::I32Exit(0)) => Ok(()),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Runs the macro until it returns, runs out of time or `stop` is cancelled
+    pub async fn run(
+        self,
+        args: Vec<String>,
+        host: Arc<dyn WasmMacroHost>,
+        output: MacroOutput,
+        stop: CancellationToken,
+    ) -> ExitStatus {
+        let ticker = tokio::spawn({
+            let engine = self.engine.clone();
+            async move {
+                let mut interval = tokio::time::interval(YIELD_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    engine.increment_epoch();
+                }
+            }
+        });
+        let timeout = async {
+            match self.limits.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        let time = || chrono::Utc::now().timestamp();
+        let exit_status = tokio::select! {
+            result = self.call(args, host, output) => match result {
+                Ok(()) => ExitStatus::Success { time: time() },
+                Err(e) => ExitStatus::Error {
+                    time: time(),
+                    error_msg: format!("{e:#}"),
+                },
+            },
+            _ = timeout => ExitStatus::Error {
+                time: time(),
+                error_msg: format!(
+                    "Stopped after running for longer than its limit of {} seconds",
+                    self.limits.timeout.unwrap_or_default().as_secs()
+                ),
+            },
+            _ = stop.cancelled() => ExitStatus::Killed { time: time() },
+        };
+        ticker.abort();
+        exit_status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeHost {
+        commands: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl WasmMacroHost for FakeHost {
+        async fn send_stdin(&self, cmd: &str) -> Result<(), Error> {
+            self.commands.lock().unwrap().push(cmd.to_string());
+            Ok(())
+        }
+        async fn send_rcon(&self, cmd: &str) -> Result<String, Error> {
+            Ok(format!("ran {cmd}"))
+        }
+        async fn on_event(&self, _event: &str) -> Result<Option<String>, Error> {
+            Ok(None)
+        }
+    }
+
+    const LIMITS: WasmLimits = WasmLimits {
+        max_memory_bytes: 1 << 20,
+        timeout: Some(Duration::from_millis(500)),
+    };
+
+    #[test]
+    fn test_pack() {
+        assert_eq!(pack(0x10, 3), 0x10_0000_0003);
+        assert_eq!(pack(u32::MAX, u32::MAX), -1);
+    }
+
+    #[tokio::test]
+    async fn test_wasm_macro() {
+        let host = Arc::new(FakeHost::default());
+        let wasm_macro = WasmMacro::new(
+            br#"(module
+                (import "lodestone" "send_stdin" (func $send_stdin (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "say hi")
+                (func (export "run") (drop (call $send_stdin (i32.const 0) (i32.const 6)))))"#,
+            LIMITS,
+        )
+        .unwrap();
+        let exit_status = wasm_macro
+            .run(
+                Vec::new(),
+                host.clone(),
+                MacroOutput::default(),
+                CancellationToken::new(),
+            )
+            .await;
+        assert!(exit_status.is_success());
+        assert_eq!(*host.commands.lock().unwrap(), vec!["say hi"]);
+
+        let looping =
+            WasmMacro::new(br#"(module (func (export "run") (loop (br 0))))"#, LIMITS).unwrap();
+        assert!(matches!(
+            looping
+                .run(
+                    Vec::new(),
+                    host.clone(),
+                    MacroOutput::default(),
+                    CancellationToken::new()
+                )
+                .await,
+            ExitStatus::Error { .. }
+        ));
+
+        let greedy = WasmMacro::new(
+            br#"(module (memory 1) (func (export "run")
+                (if (i32.eq (memory.grow (i32.const 32)) (i32.const -1)) (then unreachable))))"#,
+            LIMITS,
+        )
+        .unwrap();
+        assert!(matches!(
+            greedy
+                .run(
+                    Vec::new(),
+                    host.clone(),
+                    MacroOutput::default(),
+                    CancellationToken::new()
+                )
+                .await,
+            ExitStatus::Error { .. }
+        ));
+
+        let out_of_bounds = WasmMacro::new(
+            br#"(module
+                (import "lodestone" "send_stdin" (func $send_stdin (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "run") (drop (call $send_stdin (i32.const 0) (i32.const -1)))))"#,
+            LIMITS,
+        )
+        .unwrap();
+        assert!(matches!(
+            out_of_bounds
+                .run(
+                    Vec::new(),
+                    host,
+                    MacroOutput::default(),
+                    CancellationToken::new()
+                )
+                .await,
+            ExitStatus::Error { .. }
+        ));
+    }
+}
@@ -0,0 +1,273 @@
+//! Forwarding `/instance/:uuid/web/*` to a local HTTP port of the instance, for plugins like
+//! Dynmap or BlueMap that serve their own pages.
+//!
+//! Requests need a bearer token like any other, but browsers can't add one to the pages and
+//! assets they load. Instead a short-lived session is created through the API and opened once as
+//! `?session=<key>`, after which it is kept in a cookie scoped to the instance's `web/` path.
+//! WebSockets aren't forwarded.
+//!
+//! Only owners and admins can choose the port, since whatever listens on it gets requests with
+//! any method and body from users who can view the instance. The proxied pages show what players
+//! control, like their names, chat and markers, and are served from the core's own origin, so
+//! every response is sandboxed into an opaque origin where its scripts can't reach the dashboard's
+//! storage or act with its token.
+
+use std::collections::HashMap;
+
+use axum::{
+    body::{Bytes, StreamBody},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::{eyre, Context};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::user_id::UserId,
+    error::{Error, ErrorKind},
+    types::InstanceUuid,
+    util::rand_alphanumeric,
+};
+
+pub const SESSION_COOKIE: &str = "lodestone_web_session";
+pub const SESSION_QUERY: &str = "session";
+const SESSION_TTL_SECS: i64 = 12 * 60 * 60;
+
+/// Lets the pages run their scripts and forms, but not as the dashboard's origin
+const SANDBOX_POLICY: &str = "sandbox allow-scripts allow-forms allow-popups";
+
+/// Not passed on, they are about the connection to us rather than the request
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+];
+
+/// Redirects are passed back to the browser as they are
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap_or_default()
+});
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct WebProxySettings {
+    /// The local port the instance serves its pages on
+    pub port: u32,
+}
+
+impl WebProxySettings {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.port == 0 || self.port > u16::MAX as u32 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid port {}", self.port),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct WebSessionInfo {
+    pub session: String,
+    pub expires_at: i64,
+}
+
+struct WebSession {
+    uid: UserId,
+    instance_uuid: InstanceUuid,
+    expires_at: i64,
+}
+
+#[derive(Default)]
+pub struct WebSessions {
+    sessions: HashMap<String, WebSession>,
+}
+
+impl WebSessions {
+    pub fn create(&mut self, uid: UserId, instance_uuid: InstanceUuid) -> WebSessionInfo {
+        let now = chrono::Utc::now().timestamp();
+        self.sessions.retain(|_, session| session.expires_at > now);
+        let session = rand_alphanumeric(32);
+        let expires_at = now + SESSION_TTL_SECS;
+        self.sessions.insert(
+            session.clone(),
+            WebSession {
+                uid,
+                instance_uuid,
+                expires_at,
+            },
+        );
+        WebSessionInfo {
+            session,
+            expires_at,
+        }
+    }
+
+    /// The user the session was made for, if it is still valid for the instance
+    pub fn resolve(&self, session: &str, instance_uuid: &InstanceUuid) -> Option<UserId> {
+        self.sessions
+            .get(session)
+            .filter(|session| {
+                session.instance_uuid == *instance_uuid
+                    && session.expires_at > chrono::Utc::now().timestamp()
+            })
+            .map(|session| session.uid.clone())
+    }
+}
+
+/// The value of our cookie, and the `Cookie` header without it
+pub fn take_session_cookie(headers: &HeaderMap) -> (Option<String>, Option<String>) {
+    let mut session = None;
+    let mut rest = Vec::new();
+    for cookie in headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(str::trim)
+        .filter(|cookie| !cookie.is_empty())
+    {
+        match cookie.split_once('=') {
+            Some((name, value)) if name == SESSION_COOKIE => session = Some(value.to_string()),
+            _ => rest.push(cookie),
+        }
+    }
+    (session, (!rest.is_empty()).then(|| rest.join("; ")))
+}
+
+/// The session in the query, and the query without it
+pub fn take_session_query(query: Option<&str>) -> (Option<String>, Option<String>) {
+    let mut session = None;
+    let mut rest = Vec::new();
+    for pair in query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+    {
+        match pair.split_once('=') {
+            Some((SESSION_QUERY, value)) => session = Some(value.to_string()),
+            _ => rest.push(pair),
+        }
+    }
+    (session, (!rest.is_empty()).then(|| rest.join("&")))
+}
+
+pub fn session_cookie(session: &str, path: &str) -> String {
+    format!(
+        "{SESSION_COOKIE}={session}; Path={path}; Max-Age={SESSION_TTL_SECS}; HttpOnly; SameSite=Lax"
+    )
+}
+
+/// Sends the request on to `port` and streams back whatever it answers
+pub async fn forward(
+    port: u32,
+    method: Method,
+    path: &str,
+    query: Option<&str>,
+    mut headers: HeaderMap,
+    cookies: Option<String>,
+    body: Bytes,
+) -> Result<Response, Error> {
+    let mut url = format!("http://127.0.0.1:{port}/{}", path.trim_start_matches('/'));
+    if let Some(query) = query {
+        url.push('?');
+        url.push_str(query);
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(name);
+    }
+    headers.remove(header::AUTHORIZATION);
+    headers.remove(header::COOKIE);
+    if let Some(cookies) = cookies.and_then(|cookies| HeaderValue::from_str(&cookies).ok()) {
+        headers.insert(header::COOKIE, cookies);
+    }
+    let upstream = CLIENT
+        .request(method, url)
+        .headers(headers)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| Error {
+            kind: ErrorKind::ServiceUnavailable,
+            source: eyre!("The instance's web server on port {port} didn't answer: {e}"),
+        })?;
+    let status = upstream.status();
+    let mut upstream_headers = upstream.headers().clone();
+    for name in HOP_BY_HOP_HEADERS {
+        upstream_headers.remove(name);
+    }
+    let mut response = StreamBody::new(upstream.bytes_stream()).into_response();
+    *response.status_mut() = status;
+    response.headers_mut().extend(upstream_headers);
+    // added to any policy of the upstream, browsers enforce all of them
+    response.headers_mut().append(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(SANDBOX_POLICY),
+    );
+    Ok(response)
+}
+
+/// Sends the browser to `location`, with the session kept in a cookie from then on
+pub fn redirect_with_session(location: &str, cookie: &str) -> Result<Response, Error> {
+    let mut response = StatusCode::SEE_OTHER.into_response();
+    response.headers_mut().insert(
+        header::LOCATION,
+        HeaderValue::from_str(location).context("Invalid redirect location")?,
+    );
+    response.headers_mut().insert(
+        header::SET_COOKIE,
+        HeaderValue::from_str(cookie).context("Invalid session cookie")?,
+    );
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_session() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; lodestone_web_session=abc; lang=en"),
+        );
+        assert_eq!(
+            take_session_cookie(&headers),
+            (
+                Some("abc".to_string()),
+                Some("theme=dark; lang=en".to_string())
+            )
+        );
+        assert_eq!(
+            take_session_query(Some("session=abc&zoom=3")),
+            (Some("abc".to_string()), Some("zoom=3".to_string()))
+        );
+        assert_eq!(take_session_query(None), (None, None));
+    }
+
+    #[test]
+    fn test_web_sessions() {
+        let mut sessions = WebSessions::default();
+        let uuid = InstanceUuid::from("INSTANCE_a".to_string());
+        let info = sessions.create(UserId::from("user".to_string()), uuid.clone());
+        assert!(sessions.resolve(&info.session, &uuid).is_some());
+        assert!(sessions
+            .resolve(&info.session, &InstanceUuid::from("INSTANCE_b".to_string()))
+            .is_none());
+        assert!(sessions.resolve("nope", &uuid).is_none());
+    }
+}
@@ -0,0 +1,353 @@
+//! Whitelists shared by several instances, for networks of servers with a single membership.
+//!
+//! A group is a list of players and the instances it applies to. An instance's `whitelist.json`,
+//! or `allowlist.json` on Bedrock, is managed by its groups and holds exactly their players, so
+//! players added to it by hand are removed on the next sync. An instance taken out of every
+//! group keeps its last whitelist. Running servers are told to reload it right away. Instances
+//! are also checked every few minutes, which catches files edited while the core wasn't looking.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::{
+        t_configurable::{Game, TConfigurable},
+        t_server::{State, TServer},
+    },
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+pub const MAX_GROUPS: usize = 32;
+pub const MAX_PLAYERS_PER_GROUP: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct WhitelistPlayer {
+    pub name: String,
+    /// `None` when Mojang doesn't know the name, like for Bedrock players, who are then only
+    /// added to Bedrock allowlists
+    pub uuid: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct NewWhitelistGroup {
+    pub name: String,
+    /// Player names
+    pub players: Vec<String>,
+    pub instances: Vec<InstanceUuid>,
+}
+
+impl NewWhitelistGroup {
+    pub fn validate(&self) -> Result<(), Error> {
+        let bad_request = |message: String| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(message),
+        };
+        if self.name.trim().is_empty() {
+            return Err(bad_request(
+                "Whitelist group name cannot be empty".to_string(),
+            ));
+        }
+        if self.players.len() > MAX_PLAYERS_PER_GROUP {
+            return Err(bad_request(format!(
+                "A whitelist group can have at most {MAX_PLAYERS_PER_GROUP} players"
+            )));
+        }
+        if let Some(name) = self.players.iter().find(|name| !is_valid_name(name)) {
+            return Err(bad_request(format!("Invalid player name {name}")));
+        }
+        Ok(())
+    }
+}
+
+/// Java names are 3 to 16 letters, digits and underscores, Bedrock gamertags also have spaces
+fn is_valid_name(name: &str) -> bool {
+    (3..=16).contains(&name.chars().count())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ' ')
+        && name.trim() == name
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct WhitelistGroup {
+    pub id: Snowflake,
+    pub name: String,
+    pub players: Vec<WhitelistPlayer>,
+    pub instances: Vec<InstanceUuid>,
+}
+
+/// Mojang's ids come without dashes, which the whitelist needs
+fn dashed_uuid(id: &str) -> Option<String> {
+    if id.len() != 32 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &id[0..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..32]
+    ))
+}
+
+/// Looks up the names that aren't in `known` already, names are matched ignoring case
+pub async fn resolve_players(names: &[String], known: &[WhitelistPlayer]) -> Vec<WhitelistPlayer> {
+    let mut players: Vec<WhitelistPlayer> = Vec::new();
+    for name in names {
+        if players
+            .iter()
+            .any(|player| player.name.eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+        let player = match known
+            .iter()
+            .find(|player| player.name.eq_ignore_ascii_case(name))
+        {
+            Some(player) => player.clone(),
+            None => WhitelistPlayer {
+                name: name.clone(),
+                uuid: crate::minecraft::util::name_to_uuid(name)
+                    .await
+                    .and_then(|id| dashed_uuid(&id)),
+            },
+        };
+        players.push(player);
+    }
+    players
+}
+
+/// Every player of the groups the instance is in, by lowercased name
+fn members(groups: &[WhitelistGroup], uuid: &InstanceUuid) -> BTreeMap<String, WhitelistPlayer> {
+    groups
+        .iter()
+        .filter(|group| group.instances.contains(uuid))
+        .flat_map(|group| group.players.iter())
+        .map(|player| (player.name.to_lowercase(), player.clone()))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edition {
+    Java,
+    Bedrock,
+}
+
+impl Edition {
+    fn of(game: &Game) -> Option<Self> {
+        match game {
+            Game::MinecraftJava { .. } => Some(Self::Java),
+            Game::MinecraftBedrock => Some(Self::Bedrock),
+            Game::Generic { .. } => None,
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Java => "whitelist.json",
+            Self::Bedrock => "allowlist.json",
+        }
+    }
+
+    fn reload_command(self) -> &'static str {
+        match self {
+            Self::Java => "whitelist reload",
+            Self::Bedrock => "allowlist reload",
+        }
+    }
+
+    fn file_content(self, members: &BTreeMap<String, WhitelistPlayer>) -> serde_json::Value {
+        let entries: Vec<serde_json::Value> = members
+            .values()
+            .filter_map(|player| match self {
+                Self::Java => player
+                    .uuid
+                    .as_ref()
+                    .map(|uuid| json!({ "uuid": uuid, "name": player.name })),
+                Self::Bedrock => Some(json!({ "ignoresPlayerLimit": false, "name": player.name })),
+            })
+            .collect();
+        serde_json::Value::Array(entries)
+    }
+}
+
+/// The lowercased names in a whitelist file
+fn listed_names(content: &[u8]) -> Option<Vec<String>> {
+    let entries: Vec<serde_json::Value> = serde_json::from_slice(content).ok()?;
+    let mut names: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| Some(entry.get("name")?.as_str()?.to_lowercase()))
+        .collect();
+    names.sort();
+    Some(names)
+}
+
+/// Rewrites the instance's whitelist if it doesn't hold exactly its groups' players, returns
+/// whether it had to
+async fn sync_instance(
+    state: &AppState,
+    groups: &[WhitelistGroup],
+    uuid: &InstanceUuid,
+) -> Result<bool, Error> {
+    if !groups.iter().any(|group| group.instances.contains(uuid)) {
+        return Ok(false);
+    }
+    let instance = match state.instances.lock().await.get(uuid).cloned() {
+        Some(instance) => instance,
+        None => return Ok(false),
+    };
+    let edition = match Edition::of(&instance.game_type().await) {
+        Some(edition) => edition,
+        None => return Ok(false),
+    };
+    let members = members(groups, uuid);
+    let content = edition.file_content(&members);
+    let path: PathBuf = instance.path().await.join(edition.file_name());
+    let wanted = listed_names(content.to_string().as_bytes());
+    if tokio::fs::read(&path)
+        .await
+        .ok()
+        .map_or(false, |existing| listed_names(&existing) == wanted)
+    {
+        return Ok(false);
+    }
+    tokio::fs::write(
+        &path,
+        serde_json::to_string_pretty(&content).context("Failed to serialize whitelist")?,
+    )
+    .await
+    .context(format!("Failed to write {}", path.display()))?;
+    if instance.state().await == State::Running {
+        if let Err(e) = instance
+            .send_command(edition.reload_command(), CausedBy::System)
+            .await
+        {
+            warn!(
+                "Failed to reload the whitelist of {}: {e}",
+                instance.name().await
+            );
+        }
+    }
+    Ok(true)
+}
+
+/// Syncs `instances`, typically the ones a group applied to before and after a change
+pub async fn sync_instances(state: &AppState, instances: &[InstanceUuid]) {
+    let groups = state
+        .global_settings
+        .lock()
+        .await
+        .whitelist_groups()
+        .to_vec();
+    for uuid in instances {
+        if let Err(e) = sync_instance(state, &groups, uuid).await {
+            warn!("Failed to sync whitelist of instance {uuid}: {e}");
+        }
+    }
+}
+
+/// Keeps the whitelists of grouped instances in sync until the core shuts down
+pub async fn run_whitelist_sync(state: AppState) {
+    let mut interval = tokio::time::interval(SYNC_INTERVAL);
+    loop {
+        interval.tick().await;
+        let groups = state
+            .global_settings
+            .lock()
+            .await
+            .whitelist_groups()
+            .to_vec();
+        let mut instances: Vec<InstanceUuid> = groups
+            .iter()
+            .flat_map(|group| group.instances.iter().cloned())
+            .collect();
+        let mut seen = HashSet::new();
+        instances.retain(|uuid| seen.insert(uuid.clone()));
+        for uuid in instances {
+            match sync_instance(&state, &groups, &uuid).await {
+                Ok(true) => info!("Whitelist of instance {uuid} was out of sync, rewrote it"),
+                Ok(false) => {}
+                Err(e) => warn!("Failed to sync whitelist of instance {uuid}: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(name: &str, uuid: Option<&str>) -> WhitelistPlayer {
+        WhitelistPlayer {
+            name: name.to_string(),
+            uuid: uuid.map(|uuid| uuid.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_whitelist_content() {
+        let lobby = InstanceUuid::default();
+        let groups = vec![
+            WhitelistGroup {
+                id: Snowflake::new(),
+                name: "staff".to_string(),
+                players: vec![
+                    player("Steve", Some("8667ba71-b85a-4004-af54-457a9734eed7")),
+                    player("Bedrock Guy", None),
+                ],
+                instances: vec![lobby.clone()],
+            },
+            WhitelistGroup {
+                id: Snowflake::new(),
+                name: "members".to_string(),
+                players: vec![player(
+                    "steve",
+                    Some("8667ba71-b85a-4004-af54-457a9734eed7"),
+                )],
+                instances: vec![lobby.clone()],
+            },
+        ];
+        let members = members(&groups, &lobby);
+        assert_eq!(members.len(), 2);
+        assert_eq!(
+            Edition::Java.file_content(&members),
+            json!([{ "uuid": "8667ba71-b85a-4004-af54-457a9734eed7", "name": "steve" }])
+        );
+        assert_eq!(
+            listed_names(
+                Edition::Bedrock
+                    .file_content(&members)
+                    .to_string()
+                    .as_bytes()
+            ),
+            Some(vec!["bedrock guy".to_string(), "steve".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_dashed_uuid() {
+        assert_eq!(
+            dashed_uuid("8667ba71b85a4004af54457a9734eed7").as_deref(),
+            Some("8667ba71-b85a-4004-af54-457a9734eed7")
+        );
+        assert_eq!(dashed_uuid("not a uuid"), None);
+        assert!(is_valid_name("Bedrock Guy"));
+        assert!(!is_valid_name("ab"));
+        assert!(!is_valid_name("Steve\""));
+    }
+}
@@ -0,0 +1,437 @@
+//! A top-down overview of the main world, rendered from its region files into a PNG.
+//!
+//! Each pixel is the topmost block of a column, colored by a small table of common blocks and
+//! shaded by height like the in-game map. Large worlds are sampled instead of read in full, so
+//! the image never exceeds [`MAX_SIZE`] pixels a side. Maps of running instances are re-rendered
+//! every [`RENDER_INTERVAL`] and kept under the lodestone path.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use indexmap::IndexMap;
+use tracing::warn;
+
+use crate::{
+    error::{Error, ErrorKind},
+    implementations::minecraft::{
+        nbt::{NbtFile, NbtTag},
+        MinecraftInstance,
+    },
+    prelude::{path_to_maps, GameInstance},
+    traits::{t_configurable::TConfigurable, t_server::State, t_server::TServer},
+    types::InstanceUuid,
+    AppState,
+};
+
+pub const RENDER_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Width and height limit of the image, in pixels
+pub const MAX_SIZE: i64 = 1024;
+const REGION_SIZE: i64 = 512;
+const SECTOR_SIZE: usize = 4096;
+
+pub fn map_path(uuid: &InstanceUuid) -> PathBuf {
+    path_to_maps().join(format!("{uuid}.png"))
+}
+
+/// `(x, z)` of a region file named `r.<x>.<z>.mca`
+fn region_coords(path: &Path) -> Option<(i64, i64)> {
+    let name = path.file_name()?.to_str()?;
+    let mut parts = name.strip_prefix("r.")?.strip_suffix(".mca")?.split('.');
+    let x = parts.next()?.parse().ok()?;
+    let z = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((x, z))
+}
+
+/// The chunk at `index` (`x + z * 32`) of a region file, `None` if it isn't generated or is
+/// stored in a way that isn't supported (LZ4, or in a separate file for huge chunks)
+fn read_chunk(region: &[u8], index: usize) -> Option<NbtTag> {
+    let location = region.get(index * 4..index * 4 + 4)?;
+    let offset = u32::from_be_bytes([0, location[0], location[1], location[2]]) as usize;
+    if offset == 0 {
+        return None;
+    }
+    let start = offset * SECTOR_SIZE;
+    let header = region.get(start..start + 5)?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    if !matches!(header[4], 1..=3) {
+        return None;
+    }
+    let data = region.get(start + 5..start + 4 + length)?;
+    NbtFile::parse(data).ok().map(|file| file.root)
+}
+
+/// Value `index` of `count` values of `bits` bits packed in `longs`.
+///
+/// Since 1.16 values don't span two longs, before that they did
+fn unpack(longs: &[i64], bits: usize, count: usize, index: usize) -> Option<u64> {
+    let mask = (1u64 << bits) - 1;
+    let per_long = 64 / bits;
+    if longs.len() == (count + per_long - 1) / per_long {
+        let long = *longs.get(index / per_long)? as u64;
+        return Some((long >> ((index % per_long) * bits)) & mask);
+    }
+    let bit = index * bits;
+    let low = (*longs.get(bit / 64)? as u64) >> (bit % 64);
+    let value = if bit % 64 + bits > 64 {
+        low | ((*longs.get(bit / 64 + 1)? as u64) << (64 - bit % 64))
+    } else {
+        low
+    };
+    Some(value & mask)
+}
+
+/// What is needed to find the topmost block of each column of a chunk, from 1.13 on
+struct ChunkView<'a> {
+    min_y: i32,
+    heightmap: &'a [i64],
+    sections: &'a [NbtTag],
+    /// Before 1.18 everything was under `Level`, with different names
+    legacy: bool,
+}
+
+impl<'a> ChunkView<'a> {
+    fn new(chunk: &'a NbtTag) -> Option<Self> {
+        let root = match chunk {
+            NbtTag::Compound(root) => root,
+            _ => return None,
+        };
+        let (root, legacy) = match root.get("Level") {
+            Some(NbtTag::Compound(level)) => (level, true),
+            _ => (root, false),
+        };
+        let heightmap = match root.get("Heightmaps") {
+            Some(NbtTag::Compound(heightmaps)) => match heightmaps.get("WORLD_SURFACE") {
+                Some(NbtTag::LongArray(heightmap)) => heightmap.as_slice(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let sections = match root.get(if legacy { "Sections" } else { "sections" }) {
+            Some(NbtTag::List(sections)) => sections.as_slice(),
+            _ => return None,
+        };
+        let min_y = match root.get("yPos") {
+            Some(NbtTag::Int(y)) => y * 16,
+            _ => 0,
+        };
+        Some(Self {
+            min_y,
+            heightmap,
+            sections,
+            legacy,
+        })
+    }
+
+    fn section(&self, section_y: i32) -> Option<&'a IndexMap<String, NbtTag>> {
+        self.sections.iter().find_map(|section| match section {
+            NbtTag::Compound(section)
+                if section.get("Y") == Some(&NbtTag::Byte(section_y as i8)) =>
+            {
+                Some(section)
+            }
+            _ => None,
+        })
+    }
+
+    /// Name and height of the topmost block at `x`, `z` within the chunk
+    fn surface(&self, x: usize, z: usize) -> Option<(&'a str, i32)> {
+        let height = unpack(self.heightmap, 9, 256, z * 16 + x)? as i32;
+        if height == 0 {
+            return None;
+        }
+        let y = self.min_y + height - 1;
+        let section = self.section(y.div_euclid(16))?;
+        let (palette, data) = if self.legacy {
+            (section.get("Palette"), section.get("BlockStates"))
+        } else {
+            match section.get("block_states") {
+                Some(NbtTag::Compound(states)) => (states.get("palette"), states.get("data")),
+                _ => return None,
+            }
+        };
+        let palette = match palette {
+            Some(NbtTag::List(palette)) => palette,
+            _ => return None,
+        };
+        let index = match data {
+            Some(NbtTag::LongArray(data)) => {
+                let bits = (usize::BITS - (palette.len().max(2) - 1).leading_zeros()).max(4);
+                let block = (y.rem_euclid(16) as usize) * 256 + z * 16 + x;
+                unpack(data, bits as usize, 4096, block)? as usize
+            }
+            // a single block type fills the section
+            _ => 0,
+        };
+        match palette.get(index)? {
+            NbtTag::Compound(state) => match state.get("Name") {
+                Some(NbtTag::String(name)) => Some((name.as_str(), y)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Color of a block on the map, by its id like `minecraft:grass_block`
+fn block_color(name: &str) -> [u8; 3] {
+    let name = name.strip_prefix("minecraft:").unwrap_or(name);
+    match name {
+        "grass_block" | "moss_block" => return [98, 148, 60],
+        "sand" | "sandstone" | "birch_planks" => return [219, 207, 163],
+        "red_sand" | "terracotta" => return [169, 88, 33],
+        "gravel" => return [134, 126, 123],
+        "dirt" | "coarse_dirt" | "rooted_dirt" | "dirt_path" | "farmland" => return [134, 96, 67],
+        "podzol" | "mud" => return [90, 63, 28],
+        "mycelium" => return [111, 99, 105],
+        "clay" => return [160, 166, 179],
+        "lava" => return [207, 92, 15],
+        "bedrock" => return [80, 80, 80],
+        "netherrack" => return [111, 54, 52],
+        "end_stone" => return [219, 222, 158],
+        "obsidian" => return [21, 18, 30],
+        _ => {}
+    }
+    let rules: [(&str, [u8; 3]); 14] = [
+        ("water", [51, 89, 196]),
+        ("kelp", [51, 89, 196]),
+        ("seagrass", [51, 89, 196]),
+        ("ice", [160, 188, 240]),
+        ("snow", [245, 250, 250]),
+        ("leaves", [52, 94, 36]),
+        ("grass", [98, 148, 60]),
+        ("fern", [98, 148, 60]),
+        ("log", [102, 81, 51]),
+        ("wood", [102, 81, 51]),
+        ("planks", [162, 130, 78]),
+        ("deepslate", [80, 80, 85]),
+        ("stone", [125, 125, 125]),
+        ("andesite", [125, 125, 125]),
+    ];
+    rules
+        .iter()
+        .find(|(part, _)| name.contains(part))
+        .map(|(_, color)| *color)
+        .unwrap_or([140, 140, 140])
+}
+
+/// Renders the region files in `region_dir` into a PNG
+pub fn render(region_dir: &Path) -> Result<Vec<u8>, Error> {
+    let regions: Vec<(PathBuf, (i64, i64))> = std::fs::read_dir(region_dir)
+        .context(format!("Failed to read {}", region_dir.display()))?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let coords = region_coords(&path)?;
+            Some((path, coords))
+        })
+        .collect();
+    if regions.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("The world has not been generated yet"),
+        });
+    }
+    let (min_x, max_x, min_z, max_z) = regions.iter().fold(
+        (i64::MAX, i64::MIN, i64::MAX, i64::MIN),
+        |(min_x, max_x, min_z, max_z), (_, (x, z))| {
+            (min_x.min(*x), max_x.max(*x), min_z.min(*z), max_z.max(*z))
+        },
+    );
+    let (origin_x, origin_z) = (min_x * REGION_SIZE, min_z * REGION_SIZE);
+    let (width, height) = (
+        (max_x - min_x + 1) * REGION_SIZE,
+        (max_z - min_z + 1) * REGION_SIZE,
+    );
+    let mut scale = 1;
+    while width / scale > MAX_SIZE || height / scale > MAX_SIZE {
+        scale *= 2;
+    }
+    let (image_width, image_height) = ((width + scale - 1) / scale, (height + scale - 1) / scale);
+    let mut pixels: Vec<Option<([u8; 3], i32)>> = vec![None; (image_width * image_height) as usize];
+
+    for (path, (region_x, region_z)) in regions {
+        let region = match std::fs::read(&path) {
+            Ok(region) => region,
+            Err(e) => {
+                warn!("Failed to read region file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        for index in 0..1024 {
+            let chunk_x = region_x * REGION_SIZE + (index % 32) as i64 * 16;
+            let chunk_z = region_z * REGION_SIZE + (index / 32) as i64 * 16;
+            // only every `scale`th column is drawn, other chunks aren't even decompressed
+            let sampled = |origin: i64, start: i64| {
+                (0..16).filter(move |i| (start + i - origin) % scale == 0)
+            };
+            if sampled(origin_x, chunk_x).next().is_none()
+                || sampled(origin_z, chunk_z).next().is_none()
+            {
+                continue;
+            }
+            let chunk = match read_chunk(&region, index) {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+            let view = match ChunkView::new(&chunk) {
+                Some(view) => view,
+                None => continue,
+            };
+            for z in sampled(origin_z, chunk_z) {
+                for x in sampled(origin_x, chunk_x) {
+                    if let Some((name, y)) = view.surface(x as usize, z as usize) {
+                        let pixel_x = (chunk_x + x - origin_x) / scale;
+                        let pixel_z = (chunk_z + z - origin_z) / scale;
+                        pixels[(pixel_z * image_width + pixel_x) as usize] =
+                            Some((block_color(name), y));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut data = Vec::with_capacity(pixels.len() * 4);
+    for (i, pixel) in pixels.iter().enumerate() {
+        match pixel {
+            Some((color, y)) => {
+                // lit from the north, like maps in game
+                let north = i
+                    .checked_sub(image_width as usize)
+                    .and_then(|north| pixels[north]);
+                let shade = match north {
+                    Some((_, north_y)) if north_y < *y => 1.1,
+                    Some((_, north_y)) if north_y > *y => 0.85,
+                    _ => 1.0,
+                };
+                data.extend(color.iter().map(|c| (*c as f32 * shade).min(255.0) as u8));
+                data.push(255);
+            }
+            None => data.extend_from_slice(&[0, 0, 0, 0]),
+        }
+    }
+    let mut ret = Vec::new();
+    let mut encoder = png::Encoder::new(&mut ret, image_width as u32, image_height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(&data)?;
+            writer.finish()
+        })
+        .context("Failed to encode map")?;
+    Ok(ret)
+}
+
+/// Renders the map of an instance and saves it to [`map_path`]
+pub async fn render_instance(instance: &MinecraftInstance) -> Result<PathBuf, Error> {
+    let region_dir = instance.world_path().await?.join("region");
+    let png = tokio::task::spawn_blocking(move || render(&region_dir))
+        .await
+        .context("Render task panicked")??;
+    let path = map_path(&instance.uuid().await);
+    crate::util::fs::write_all(&path, png).await?;
+    Ok(path)
+}
+
+/// Keeps the maps of running Minecraft instances up to date until the core shuts down
+pub async fn run_world_maps(state: AppState) {
+    let mut interval = tokio::time::interval(RENDER_INTERVAL);
+    loop {
+        interval.tick().await;
+        let instances: Vec<MinecraftInstance> = state
+            .instances
+            .lock()
+            .await
+            .values()
+            .filter_map(|instance| match instance {
+                GameInstance::MinecraftInstance(minecraft) => Some(minecraft.clone()),
+                _ => None,
+            })
+            .collect();
+        for instance in instances {
+            // a stopped world doesn't change, unless it has never been rendered
+            if instance.state().await != State::Running && map_path(&instance.uuid().await).exists()
+            {
+                continue;
+            }
+            if let Err(e) = render_instance(&instance).await {
+                if !matches!(e.kind, ErrorKind::NotFound) {
+                    warn!("Failed to render map of {}: {}", instance.name().await, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_coords() {
+        assert_eq!(region_coords(Path::new("region/r.-1.2.mca")), Some((-1, 2)));
+        assert_eq!(region_coords(Path::new("region/r.0.0.mcr")), None);
+        assert_eq!(region_coords(Path::new("region/r.0.0.0.mca")), None);
+    }
+
+    #[test]
+    fn test_unpack() {
+        // three 9 bit values per long would span longs without padding
+        let values: Vec<u64> = (0..256).map(|i| (i * 7) % 384).collect();
+        let mut padded = vec![0i64; 37];
+        for (i, v) in values.iter().enumerate() {
+            padded[i / 7] |= (v << ((i % 7) * 9)) as i64;
+        }
+        let mut spanning = vec![0i64; 36];
+        for (i, v) in values.iter().enumerate() {
+            let bit = i * 9;
+            spanning[bit / 64] |= (v << (bit % 64)) as i64;
+            if bit % 64 + 9 > 64 {
+                spanning[bit / 64 + 1] |= (v >> (64 - bit % 64)) as i64;
+            }
+        }
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(unpack(&padded, 9, 256, i), Some(*v));
+            assert_eq!(unpack(&spanning, 9, 256, i), Some(*v));
+        }
+    }
+
+    #[test]
+    fn test_surface() {
+        let mut heightmap = vec![0i64; 37];
+        // column 0, 0 is filled up to y = 70 in a world starting at -64
+        heightmap[0] = 70 + 64 + 1;
+        let section = NbtTag::Compound(IndexMap::from([
+            ("Y".to_string(), NbtTag::Byte(4)),
+            (
+                "block_states".to_string(),
+                NbtTag::Compound(IndexMap::from([(
+                    "palette".to_string(),
+                    NbtTag::List(vec![NbtTag::Compound(IndexMap::from([(
+                        "Name".to_string(),
+                        NbtTag::String("minecraft:grass_block".to_string()),
+                    )]))]),
+                )])),
+            ),
+        ]));
+        let chunk = NbtTag::Compound(IndexMap::from([
+            ("yPos".to_string(), NbtTag::Int(-4)),
+            (
+                "Heightmaps".to_string(),
+                NbtTag::Compound(IndexMap::from([(
+                    "WORLD_SURFACE".to_string(),
+                    NbtTag::LongArray(heightmap),
+                )])),
+            ),
+            ("sections".to_string(), NbtTag::List(vec![section])),
+        ]));
+        let view = ChunkView::new(&chunk).unwrap();
+        assert_eq!(view.surface(0, 0), Some(("minecraft:grass_block", 70)));
+        assert_eq!(view.surface(1, 0), None);
+        assert_eq!(block_color("minecraft:oak_leaves"), [52, 94, 36]);
+    }
+}